@@ -0,0 +1,99 @@
+//! Benchmarks for the hot paths of the avro decoder and encoder, with the schema registry mocked.
+//! Run with `cargo bench --features avro --bench avro`.
+use apache_avro::types::Value;
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use mockito::{Mock, Server, ServerGuard};
+use tokio::runtime::{Builder, Runtime};
+
+use schema_registry_converter::async_impl::avro::{AvroDecoder, AvroEncoder};
+use schema_registry_converter::async_impl::schema_registry::SrSettings;
+use schema_registry_converter::schema_registry_common::SubjectNameStrategy;
+use test_utils::Heartbeat;
+
+const SCHEMA: &str = r#"{\"type\":\"record\",\"name\":\"Heartbeat\",\"namespace\":\"nl.openweb.data\",\"fields\":[{\"name\":\"beat\",\"type\":\"long\"}]}"#;
+const HEARTBEAT_3: &[u8] = &[0, 0, 0, 0, 3, 6];
+
+/// The mocks are removed when dropped, so they are returned together with the server.
+fn mock_registry() -> (ServerGuard, Vec<Mock>) {
+    let mut server = Server::new();
+    let by_subject = server
+        .mock("GET", "/subjects/heartbeat-value/versions/latest")
+        .with_status(200)
+        .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+        .with_body(format!(
+            r#"{{"subject":"heartbeat-value","version":1,"id":3,"schema":"{}"}}"#,
+            SCHEMA
+        ))
+        .create();
+    let by_id = server
+        .mock("GET", "/schemas/ids/3?deleted=true")
+        .with_status(200)
+        .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+        .with_body(format!(r#"{{"schema":"{}"}}"#, SCHEMA))
+        .create();
+    (server, vec![by_subject, by_id])
+}
+
+fn runtime() -> Runtime {
+    Builder::new_current_thread().enable_all().build().unwrap()
+}
+
+fn decode(c: &mut Criterion) {
+    let runtime = runtime();
+    let (server, _mocks) = mock_registry();
+    let sr_settings = SrSettings::new(server.url());
+    let decoder = AvroDecoder::new(sr_settings.clone());
+    let mut group = c.benchmark_group("avro_decode");
+
+    group.bench_function("cold_cache", |b| {
+        b.iter(|| {
+            let decoder = AvroDecoder::new(sr_settings.clone());
+            runtime.block_on(decoder.decode(Some(HEARTBEAT_3))).unwrap()
+        })
+    });
+    group.bench_function("warm_cache", |b| {
+        b.iter(|| runtime.block_on(decoder.decode(Some(HEARTBEAT_3))).unwrap())
+    });
+
+    let payloads = vec![Some(HEARTBEAT_3); 100];
+    group.throughput(Throughput::Elements(payloads.len() as u64));
+    group.bench_function("batch_of_100", |b| {
+        b.iter(|| runtime.block_on(decoder.decode_batch(&payloads)))
+    });
+    group.finish();
+}
+
+fn encode(c: &mut Criterion) {
+    let runtime = runtime();
+    let (server, _mocks) = mock_registry();
+    let encoder = AvroEncoder::new(SrSettings::new(server.url()));
+    let strategy = SubjectNameStrategy::TopicNameStrategy(String::from("heartbeat"), false);
+    let mut group = c.benchmark_group("avro_encode");
+    group.throughput(Throughput::Elements(1));
+
+    group.bench_function("encode", |b| {
+        b.iter(|| {
+            let values = vec![("beat", Value::Long(3))];
+            runtime.block_on(encoder.encode(values, &strategy)).unwrap()
+        })
+    });
+    group.bench_function("encode_struct", |b| {
+        b.iter(|| {
+            runtime
+                .block_on(encoder.encode_struct(Heartbeat { beat: 3 }, &strategy))
+                .unwrap()
+        })
+    });
+    group.bench_function("encode_struct_into", |b| {
+        let mut buf = Vec::new();
+        b.iter(|| {
+            runtime
+                .block_on(encoder.encode_struct_into(Heartbeat { beat: 3 }, &strategy, &mut buf))
+                .unwrap()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, decode, encode);
+criterion_main!(benches);
@@ -0,0 +1,84 @@
+//! Benchmarks for the hot paths of the json decoder and encoder, with the schema registry mocked.
+//! Run with `cargo bench --features json --bench json`.
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use mockito::{Mock, Server, ServerGuard};
+use serde_json::json;
+use tokio::runtime::{Builder, Runtime};
+
+use schema_registry_converter::async_impl::json::{JsonDecoder, JsonEncoder};
+use schema_registry_converter::async_impl::schema_registry::SrSettings;
+use schema_registry_converter::schema_registry_common::SubjectNameStrategy;
+use test_utils::{get_json_body, json_result_java_bytes, json_result_schema};
+
+/// The mocks are removed when dropped, so they are returned together with the server.
+fn mock_registry() -> (ServerGuard, Vec<Mock>) {
+    let mut server = Server::new();
+    let by_subject = server
+        .mock("GET", "/subjects/testresult-value/versions/latest")
+        .with_status(200)
+        .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+        .with_body(get_json_body(json_result_schema(), 10))
+        .create();
+    let by_id = server
+        .mock("GET", "/schemas/ids/10?deleted=true")
+        .with_status(200)
+        .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+        .with_body(get_json_body(json_result_schema(), 10))
+        .create();
+    (server, vec![by_subject, by_id])
+}
+
+fn runtime() -> Runtime {
+    Builder::new_current_thread().enable_all().build().unwrap()
+}
+
+fn decode(c: &mut Criterion) {
+    let runtime = runtime();
+    let (server, _mocks) = mock_registry();
+    let sr_settings = SrSettings::new(server.url());
+    let decoder = JsonDecoder::new(sr_settings.clone());
+    let mut group = c.benchmark_group("json_decode");
+
+    group.bench_function("cold_cache", |b| {
+        b.iter(|| {
+            let decoder = JsonDecoder::new(sr_settings.clone());
+            runtime
+                .block_on(decoder.decode(Some(json_result_java_bytes())))
+                .unwrap()
+        })
+    });
+    group.bench_function("warm_cache", |b| {
+        b.iter(|| {
+            runtime
+                .block_on(decoder.decode(Some(json_result_java_bytes())))
+                .unwrap()
+        })
+    });
+    group.finish();
+}
+
+fn encode(c: &mut Criterion) {
+    let runtime = runtime();
+    let (server, _mocks) = mock_registry();
+    let encoder = JsonEncoder::new(SrSettings::new(server.url()));
+    let strategy = SubjectNameStrategy::TopicNameStrategy(String::from("testresult"), false);
+    let value = json!({"down": "string", "up": "STRING"});
+    let mut group = c.benchmark_group("json_encode");
+    group.throughput(Throughput::Elements(1));
+
+    group.bench_function("encode", |b| {
+        b.iter(|| runtime.block_on(encoder.encode(&value, &strategy)).unwrap())
+    });
+    group.bench_function("encode_into", |b| {
+        let mut buf = Vec::new();
+        b.iter(|| {
+            runtime
+                .block_on(encoder.encode_into(&value, &strategy, &mut buf))
+                .unwrap()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, decode, encode);
+criterion_main!(benches);
@@ -0,0 +1,115 @@
+//! Benchmarks for the hot paths of the protobuf decoders and encoder, with the schema registry
+//! mocked. Run with `cargo bench --features proto_decoder,proto_descriptor,proto_raw --bench proto`.
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use mockito::{Mock, Server, ServerGuard};
+use tokio::runtime::{Builder, Runtime};
+
+use schema_registry_converter::async_impl::proto_decoder::ProtoDecoder;
+use schema_registry_converter::async_impl::proto_raw::{ProtoRawDecoder, ProtoRawEncoder};
+use schema_registry_converter::async_impl::schema_registry::SrSettings;
+use schema_registry_converter::schema_registry_common::SubjectNameStrategy;
+use test_utils::{
+    get_proto_body, get_proto_hb_101, get_proto_hb_101_only_data, get_proto_hb_descriptor_set,
+    get_proto_hb_schema,
+};
+
+/// The mocks are removed when dropped, so they are returned together with the server.
+fn mock_registry() -> (ServerGuard, Vec<Mock>) {
+    let mut server = Server::new();
+    let by_subject = server
+        .mock("GET", "/subjects/nl.openweb.data.Heartbeat/versions/latest")
+        .with_status(200)
+        .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+        .with_body(get_proto_body(get_proto_hb_schema(), 7))
+        .create();
+    let by_id = server
+        .mock("GET", "/schemas/ids/7?deleted=true")
+        .with_status(200)
+        .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+        .with_body(get_proto_body(get_proto_hb_schema(), 7))
+        .create();
+    (server, vec![by_subject, by_id])
+}
+
+fn runtime() -> Runtime {
+    Builder::new_current_thread().enable_all().build().unwrap()
+}
+
+fn decode(c: &mut Criterion) {
+    let runtime = runtime();
+    let (server, _mocks) = mock_registry();
+    let sr_settings = SrSettings::new(server.url());
+    let decoder = ProtoDecoder::new(sr_settings.clone());
+    let raw_decoder = ProtoRawDecoder::new(sr_settings.clone());
+    let mut group = c.benchmark_group("proto_decode");
+
+    group.bench_function("context_parse", |b| {
+        b.iter(|| {
+            let decoder = ProtoDecoder::new(sr_settings.clone());
+            decoder
+                .add_schema_override(7, get_proto_hb_descriptor_set())
+                .unwrap()
+        })
+    });
+    group.bench_function("cold_cache", |b| {
+        b.iter(|| {
+            let decoder = ProtoDecoder::new(sr_settings.clone());
+            runtime
+                .block_on(decoder.decode(Some(get_proto_hb_101())))
+                .unwrap()
+        })
+    });
+    group.bench_function("warm_cache", |b| {
+        b.iter(|| {
+            runtime
+                .block_on(decoder.decode(Some(get_proto_hb_101())))
+                .unwrap()
+        })
+    });
+    group.bench_function("raw_warm_cache", |b| {
+        b.iter(|| {
+            runtime
+                .block_on(raw_decoder.decode(Some(get_proto_hb_101())))
+                .unwrap()
+        })
+    });
+
+    let payloads = vec![Some(get_proto_hb_101()); 100];
+    group.throughput(Throughput::Elements(payloads.len() as u64));
+    group.bench_function("batch_of_100", |b| {
+        b.iter(|| runtime.block_on(decoder.decode_batch(&payloads)))
+    });
+    group.finish();
+}
+
+fn encode(c: &mut Criterion) {
+    let runtime = runtime();
+    let (server, _mocks) = mock_registry();
+    let encoder = ProtoRawEncoder::new(SrSettings::new(server.url()));
+    let strategy =
+        SubjectNameStrategy::RecordNameStrategy(String::from("nl.openweb.data.Heartbeat"));
+    let full_name = "nl.openweb.data.Heartbeat";
+    let mut group = c.benchmark_group("proto_encode");
+    group.throughput(Throughput::Elements(1));
+
+    group.bench_function("encode", |b| {
+        b.iter(|| {
+            runtime
+                .block_on(encoder.encode(get_proto_hb_101_only_data(), full_name, &strategy))
+                .unwrap()
+        })
+    });
+    group.bench_function("encode_into", |b| {
+        let mut buf = Vec::new();
+        b.iter(|| {
+            let bytes = get_proto_hb_101_only_data();
+            runtime
+                .block_on(encoder.encode_into(bytes, full_name, &strategy, &mut buf))
+                .unwrap()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, decode, encode);
+criterion_main!(benches);
@@ -0,0 +1,180 @@
+//! Derive macro's for the schema_registry_converter crate, use them with the `derive` feature of
+//! that crate instead of depending on this crate directly.
+//!
+//! `SrAvroSerialize` implements the `schema_registry_converter::avro_derive::SrAvroSerialize`
+//! trait for a struct with named fields. The .avsc file is included at compile time, with a path
+//! relative to the root of the crate using the macro.
+//!
+//! ```ignore
+//! #[derive(SrAvroSerialize)]
+//! #[sr_avro(schema = "schemas/heartbeat.avsc", topic = "heartbeat")]
+//! struct Heartbeat {
+//!     beat: i64,
+//!     #[sr_avro(rename = "source")]
+//!     origin: String,
+//!     #[sr_avro(skip)]
+//!     received: u64,
+//! }
+//! ```
+//!
+//! The attributes on the struct are:
+//! * `schema`, the path to the .avsc file, this one is mandatory.
+//! * `topic`, the topic to use for the subject.
+//! * `key`, when the struct is used as the key, only used together with `topic`.
+//! * `record_name`, to add the full name of the record to the subject, or to use only the full
+//!   name when there is no topic.
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Error, Fields, LitStr};
+
+#[proc_macro_derive(SrAvroSerialize, attributes(sr_avro))]
+pub fn derive_sr_avro_serialize(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(&input) {
+        Ok(v) => v.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+#[derive(Default)]
+struct ContainerAttributes {
+    schema: Option<LitStr>,
+    topic: Option<LitStr>,
+    key: bool,
+    record_name: bool,
+}
+
+#[derive(Default)]
+struct FieldAttributes {
+    rename: Option<LitStr>,
+    skip: bool,
+}
+
+fn container_attributes(input: &DeriveInput) -> Result<ContainerAttributes, Error> {
+    let mut attributes = ContainerAttributes::default();
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("sr_avro")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("schema") {
+                attributes.schema = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("topic") {
+                attributes.topic = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("key") {
+                attributes.key = true;
+            } else if meta.path.is_ident("record_name") {
+                attributes.record_name = true;
+            } else {
+                return Err(meta.error("unsupported sr_avro attribute"));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(attributes)
+}
+
+fn field_attributes(field: &syn::Field) -> Result<FieldAttributes, Error> {
+    let mut attributes = FieldAttributes::default();
+    for attr in field.attrs.iter().filter(|a| a.path().is_ident("sr_avro")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename") {
+                attributes.rename = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("skip") {
+                attributes.skip = true;
+            } else {
+                return Err(meta.error("unsupported sr_avro field attribute"));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(attributes)
+}
+
+fn expand(input: &DeriveInput) -> Result<TokenStream2, Error> {
+    let fields = match &input.data {
+        Data::Struct(s) => match &s.fields {
+            Fields::Named(f) => &f.named,
+            _ => {
+                return Err(Error::new_spanned(
+                    &input.ident,
+                    "SrAvroSerialize can only be derived for structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(Error::new_spanned(
+                &input.ident,
+                "SrAvroSerialize can only be derived for structs",
+            ))
+        }
+    };
+    let attributes = container_attributes(input)?;
+    let schema = match attributes.schema {
+        Some(v) => v,
+        None => {
+            return Err(Error::new_spanned(
+                &input.ident,
+                "the path to the .avsc file is needed, using #[sr_avro(schema = \"...\")]",
+            ))
+        }
+    };
+    if attributes.topic.is_none() && !attributes.record_name {
+        return Err(Error::new_spanned(
+            &input.ident,
+            "either a topic or record_name is needed to get the subject",
+        ));
+    }
+    let topic = match attributes.topic {
+        Some(t) => quote!(::std::option::Option::Some(#t)),
+        None => quote!(::std::option::Option::None),
+    };
+    let key = attributes.key;
+    let record_name = attributes.record_name;
+
+    let mut values = Vec::with_capacity(fields.len());
+    for field in fields {
+        let field_attributes = field_attributes(field)?;
+        if field_attributes.skip {
+            continue;
+        }
+        let ident = field.ident.as_ref().expect("named fields have an ident");
+        let name = match field_attributes.rename {
+            Some(v) => v,
+            None => LitStr::new(&ident.to_string(), ident.span()),
+        };
+        values.push(quote! {
+            (#name, ::schema_registry_converter::avro_derive::to_field_value(&self.#ident)?)
+        });
+    }
+
+    let ident = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::schema_registry_converter::avro_derive::SrAvroSerialize
+            for #ident #type_generics #where_clause
+        {
+            const AVRO_SCHEMA: &'static str =
+                include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/", #schema));
+
+            fn subject_name_strategy() -> ::std::result::Result<
+                ::schema_registry_converter::schema_registry_common::SubjectNameStrategy,
+                ::schema_registry_converter::error::SRCError,
+            > {
+                ::schema_registry_converter::avro_derive::get_subject_name_strategy(
+                    Self::AVRO_SCHEMA,
+                    #topic,
+                    #key,
+                    #record_name,
+                )
+            }
+
+            fn to_avro_values(
+                &self,
+            ) -> ::std::result::Result<
+                ::std::vec::Vec<(&'static str, ::schema_registry_converter::avro_derive::Value)>,
+                ::schema_registry_converter::error::SRCError,
+            > {
+                ::std::result::Result::Ok(::std::vec![#(#values),*])
+            }
+        }
+    })
+}
@@ -0,0 +1,1116 @@
+//! Maps decoded values, together with their schema, to arrow arrays, so messages can be collected
+//! into a `RecordBatch`, for example to write them as parquet. An [ArrowBatcher] is created from an
+//! avro schema, a json schema or a protobuf message, and collects the values pushed to it into
+//! record batches of the configured size.
+//!
+//! Avro records, json objects and protobuf messages become structs, arrays and repeated fields
+//! become lists, and maps become maps with string keys. Enums become strings. The logical types of
+//! avro map to the arrow types with the same meaning, and a protobuf Timestamp becomes a timestamp
+//! in nanoseconds. The other well known protobuf types, like Struct and Any, become their json as a
+//! string. Fields that are missing, or not set in protobuf, become null.
+use std::sync::Arc;
+
+#[cfg(feature = "avro")]
+use apache_avro::types::Value as AvroValue;
+#[cfg(feature = "avro")]
+use apache_avro::Schema as AvroSchema;
+use arrow_array::types::{
+    Date32Type, Decimal128Type, Int32Type, Int64Type, Time32MillisecondType, Time64MicrosecondType,
+    TimestampMicrosecondType, TimestampMillisecondType, TimestampNanosecondType, UInt32Type,
+    UInt64Type,
+};
+use arrow_array::{
+    ArrayRef, ArrowPrimitiveType, BinaryArray, BooleanArray, Float32Array, Float64Array, ListArray,
+    MapArray, NullArray, PrimitiveArray, RecordBatch, RecordBatchOptions, StringArray, StructArray,
+};
+use arrow_buffer::{NullBuffer, OffsetBuffer};
+use arrow_schema::{DataType, Field, FieldRef, Fields, Schema, SchemaRef, TimeUnit};
+#[cfg(feature = "proto_decoder")]
+use protofish::context::{Context, MessageField, MessageInfo, Multiplicity, ValueType};
+#[cfg(feature = "proto_decoder")]
+use protofish::decode::{MessageValue, PackedArray, Value as ProtoValue};
+use serde_json::Value as JsonValue;
+
+#[cfg(feature = "avro")]
+use crate::avro_logical::{collect_names, resolve_ref, Names};
+use crate::error::SRCError;
+#[cfg(feature = "proto_decoder")]
+use crate::proto_decoder_common::{
+    field_value, int_field, is_map_entry, proto_value_to_json, to_json, wrapper_default,
+};
+
+/// A decoded value independent of the schema type, checked against the arrow type when it's pushed
+/// so building the arrays can't fail on the values.
+#[derive(Debug, PartialEq)]
+enum Datum {
+    Null,
+    Bool(bool),
+    Int(i128),
+    Float(f64),
+    Str(String),
+    Bytes(Vec<u8>),
+    List(Vec<Datum>),
+    Map(Vec<(String, Datum)>),
+    Struct(Vec<(String, Datum)>),
+}
+
+static NULL: Datum = Datum::Null;
+
+/// Collects decoded values into record batches. A batch is returned from the push which reaches the
+/// batch size, and [ArrowBatcher::flush] returns the remaining values, for example when the consumer
+/// commits its offsets. A value that doesn't match the schema is rejected when it's pushed, so it
+/// can't fail the whole batch.
+/// ```
+/// use schema_registry_converter::arrow::ArrowBatcher;
+/// use serde_json::json;
+///
+/// let schema = json!({"type": "object", "properties": {"beat": {"type": "integer"}}});
+/// let mut batcher = ArrowBatcher::for_json(&schema, 2).unwrap();
+/// assert!(batcher.push_json(&json!({"beat": 3})).unwrap().is_none());
+/// let batch = batcher.push_json(&json!({"beat": 4})).unwrap().unwrap();
+/// assert_eq!(batch.num_rows(), 2);
+/// ```
+#[derive(Debug)]
+pub struct ArrowBatcher {
+    schema: SchemaRef,
+    row_type: DataType,
+    batch_size: usize,
+    rows: Vec<Datum>,
+}
+
+impl ArrowBatcher {
+    /// Creates a batcher for an arrow schema, returning a batch each time batch_size values are
+    /// pushed.
+    pub fn new(schema: SchemaRef, batch_size: usize) -> ArrowBatcher {
+        ArrowBatcher {
+            row_type: DataType::Struct(schema.fields().clone()),
+            schema,
+            batch_size: batch_size.max(1),
+            rows: Vec::new(),
+        }
+    }
+
+    /// Creates a batcher for values of the avro schema, which needs to be a record.
+    #[cfg(feature = "avro")]
+    pub fn for_avro(schema: &AvroSchema, batch_size: usize) -> Result<ArrowBatcher, SRCError> {
+        Ok(ArrowBatcher::new(
+            Arc::new(avro_to_arrow_schema(schema)?),
+            batch_size,
+        ))
+    }
+
+    /// Creates a batcher for values of the json schema, which needs to be an object with properties.
+    pub fn for_json(schema: &JsonValue, batch_size: usize) -> Result<ArrowBatcher, SRCError> {
+        Ok(ArrowBatcher::new(
+            Arc::new(json_to_arrow_schema(schema)?),
+            batch_size,
+        ))
+    }
+
+    /// Creates a batcher for the protobuf message with the full name in the context.
+    #[cfg(feature = "proto_decoder")]
+    pub fn for_proto(
+        context: &Context,
+        full_name: &str,
+        batch_size: usize,
+    ) -> Result<ArrowBatcher, SRCError> {
+        Ok(ArrowBatcher::new(
+            Arc::new(proto_to_arrow_schema(context, full_name)?),
+            batch_size,
+        ))
+    }
+
+    /// The arrow schema of the batches.
+    pub fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    /// The number of values pushed since the last batch.
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Adds a decoded avro value, returning a batch when the batch size is reached.
+    #[cfg(feature = "avro")]
+    pub fn push_avro(&mut self, value: &AvroValue) -> Result<Option<RecordBatch>, SRCError> {
+        let datum = avro_datum(value)?;
+        self.push(datum)
+    }
+
+    /// Adds a json value, returning a batch when the batch size is reached.
+    pub fn push_json(&mut self, value: &JsonValue) -> Result<Option<RecordBatch>, SRCError> {
+        self.push(json_datum(value))
+    }
+
+    /// Adds a decoded protobuf message, returning a batch when the batch size is reached.
+    #[cfg(feature = "proto_decoder")]
+    pub fn push_proto(
+        &mut self,
+        value: &MessageValue,
+        context: &Context,
+    ) -> Result<Option<RecordBatch>, SRCError> {
+        let info = context.resolve_message(value.msg_ref);
+        self.push(proto_message_datum(info, value, context))
+    }
+
+    /// Returns a batch with the values pushed since the last batch, or None when there are none.
+    pub fn flush(&mut self) -> Result<Option<RecordBatch>, SRCError> {
+        if self.rows.is_empty() {
+            return Ok(None);
+        }
+        let rows = std::mem::take(&mut self.rows);
+        let rows: Vec<&Datum> = rows.iter().collect();
+        let columns = match &self.row_type {
+            DataType::Struct(fields) => to_columns(fields, &rows)?,
+            _ => Vec::new(),
+        };
+        let options = RecordBatchOptions::new().with_row_count(Some(rows.len()));
+        match RecordBatch::try_new_with_options(self.schema.clone(), columns, &options) {
+            Ok(batch) => Ok(Some(batch)),
+            Err(e) => Err(arrow_error(e)),
+        }
+    }
+
+    fn push(&mut self, datum: Datum) -> Result<Option<RecordBatch>, SRCError> {
+        check(&datum, &self.row_type, false, "$")?;
+        self.rows.push(datum);
+        if self.rows.len() >= self.batch_size {
+            self.flush()
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// Maps an avro record schema to an arrow schema, with a field for each field of the record.
+#[cfg(feature = "avro")]
+pub fn avro_to_arrow_schema(schema: &AvroSchema) -> Result<Schema, SRCError> {
+    let mut names = Names::new();
+    collect_names(schema, &mut names);
+    match avro_type(schema, &names, &mut Vec::new())? {
+        (DataType::Struct(fields), _) => Ok(Schema::new(fields)),
+        _ => Err(SRCError::non_retryable_without_cause(
+            "Only an avro record can be mapped to an arrow schema",
+        )),
+    }
+}
+
+/// Maps a json schema of an object to an arrow schema, with a field for each property.
+pub fn json_to_arrow_schema(schema: &JsonValue) -> Result<Schema, SRCError> {
+    match json_type(schema, "$")? {
+        (DataType::Struct(fields), _) => Ok(Schema::new(fields)),
+        _ => Err(SRCError::non_retryable_without_cause(
+            "Only a json object with properties can be mapped to an arrow schema",
+        )),
+    }
+}
+
+/// Maps the protobuf message with the full name to an arrow schema, with a field for each field of
+/// the message.
+#[cfg(feature = "proto_decoder")]
+pub fn proto_to_arrow_schema(context: &Context, full_name: &str) -> Result<Schema, SRCError> {
+    let info = match context.get_message(full_name) {
+        Some(v) => v,
+        None => {
+            return Err(SRCError::non_retryable_without_cause(&format!(
+                "Could not find message {} in the context",
+                full_name
+            )))
+        }
+    };
+    Ok(Schema::new(proto_fields(info, context, &mut Vec::new())?))
+}
+
+fn unsupported(what: &str) -> SRCError {
+    SRCError::non_retryable_without_cause(&format!("Could not map {} to an arrow type", what))
+}
+
+fn arrow_error(e: arrow_schema::ArrowError) -> SRCError {
+    SRCError::non_retryable_with_cause(e, "Could not create arrow array")
+}
+
+fn map_type(value_type: DataType, nullable: bool) -> DataType {
+    let entries = Fields::from(vec![
+        Field::new("keys", DataType::Utf8, false),
+        Field::new("values", value_type, nullable),
+    ]);
+    DataType::Map(
+        Arc::new(Field::new("entries", DataType::Struct(entries), false)),
+        false,
+    )
+}
+
+#[cfg(feature = "avro")]
+fn avro_type(
+    schema: &AvroSchema,
+    names: &Names,
+    records: &mut Vec<String>,
+) -> Result<(DataType, bool), SRCError> {
+    let data_type = match resolve_ref(schema, names) {
+        AvroSchema::Null => return Ok((DataType::Null, true)),
+        AvroSchema::Boolean => DataType::Boolean,
+        AvroSchema::Int => DataType::Int32,
+        AvroSchema::Long => DataType::Int64,
+        AvroSchema::Float => DataType::Float32,
+        AvroSchema::Double => DataType::Float64,
+        AvroSchema::Bytes | AvroSchema::Fixed(_) => DataType::Binary,
+        AvroSchema::String | AvroSchema::Uuid | AvroSchema::Enum(_) => DataType::Utf8,
+        AvroSchema::Date => DataType::Date32,
+        AvroSchema::TimeMillis => DataType::Time32(TimeUnit::Millisecond),
+        AvroSchema::TimeMicros => DataType::Time64(TimeUnit::Microsecond),
+        AvroSchema::TimestampMillis => {
+            DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into()))
+        }
+        AvroSchema::TimestampMicros => {
+            DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into()))
+        }
+        AvroSchema::LocalTimestampMillis => DataType::Timestamp(TimeUnit::Millisecond, None),
+        AvroSchema::LocalTimestampMicros => DataType::Timestamp(TimeUnit::Microsecond, None),
+        AvroSchema::Decimal(d) if d.precision <= 38 => {
+            DataType::Decimal128(d.precision as u8, d.scale as i8)
+        }
+        AvroSchema::Array(items) => {
+            let (item_type, nullable) = avro_type(items, names, records)?;
+            DataType::new_list(item_type, nullable)
+        }
+        AvroSchema::Map(values) => {
+            let (value_type, nullable) = avro_type(values, names, records)?;
+            map_type(value_type, nullable)
+        }
+        AvroSchema::Union(u) => {
+            let variants: Vec<&AvroSchema> = u
+                .variants()
+                .iter()
+                .filter(|s| !matches!(s, AvroSchema::Null))
+                .collect();
+            if variants.len() != 1 {
+                return Err(unsupported(
+                    "an avro union with more than one type besides null",
+                ));
+            }
+            let (data_type, nullable) = avro_type(variants[0], names, records)?;
+            return Ok((data_type, nullable || u.variants().len() > 1));
+        }
+        AvroSchema::Record(r) => {
+            let name = r.name.fullname(None);
+            if records.contains(&name) {
+                return Err(unsupported(&format!("recursive avro record {}", name)));
+            }
+            records.push(name);
+            let mut fields = Vec::with_capacity(r.fields.len());
+            for field in &r.fields {
+                let (data_type, nullable) = avro_type(&field.schema, names, records)?;
+                fields.push(Field::new(field.name.clone(), data_type, nullable));
+            }
+            records.pop();
+            DataType::Struct(Fields::from(fields))
+        }
+        other => return Err(unsupported(&format!("avro schema {:?}", other))),
+    };
+    Ok((data_type, false))
+}
+
+#[cfg(feature = "avro")]
+fn avro_datum(value: &AvroValue) -> Result<Datum, SRCError> {
+    Ok(match value {
+        AvroValue::Null => Datum::Null,
+        AvroValue::Boolean(b) => Datum::Bool(*b),
+        AvroValue::Int(i) | AvroValue::Date(i) | AvroValue::TimeMillis(i) => {
+            Datum::Int(i128::from(*i))
+        }
+        AvroValue::Long(i)
+        | AvroValue::TimeMicros(i)
+        | AvroValue::TimestampMillis(i)
+        | AvroValue::TimestampMicros(i)
+        | AvroValue::LocalTimestampMillis(i)
+        | AvroValue::LocalTimestampMicros(i) => Datum::Int(i128::from(*i)),
+        AvroValue::Float(f) => Datum::Float(f64::from(*f)),
+        AvroValue::Double(f) => Datum::Float(*f),
+        AvroValue::Bytes(b) | AvroValue::Fixed(_, b) => Datum::Bytes(b.clone()),
+        AvroValue::String(s) | AvroValue::Enum(_, s) => Datum::Str(s.clone()),
+        AvroValue::Uuid(u) => Datum::Str(u.to_string()),
+        AvroValue::Decimal(d) => match Vec::<u8>::try_from(d).ok().and_then(|b| unscaled(&b)) {
+            Some(i) => Datum::Int(i),
+            None => return Err(unsupported("an avro decimal of more than 16 bytes")),
+        },
+        AvroValue::Union(_, v) => avro_datum(v)?,
+        AvroValue::Array(items) => Datum::List(
+            items
+                .iter()
+                .map(avro_datum)
+                .collect::<Result<Vec<Datum>, SRCError>>()?,
+        ),
+        AvroValue::Map(entries) => Datum::Map(
+            entries
+                .iter()
+                .map(|(k, v)| Ok((k.clone(), avro_datum(v)?)))
+                .collect::<Result<Vec<(String, Datum)>, SRCError>>()?,
+        ),
+        AvroValue::Record(fields) => Datum::Struct(
+            fields
+                .iter()
+                .map(|(k, v)| Ok((k.clone(), avro_datum(v)?)))
+                .collect::<Result<Vec<(String, Datum)>, SRCError>>()?,
+        ),
+        other => return Err(unsupported(&format!("avro value {:?}", other))),
+    })
+}
+
+/// The bytes of an avro decimal are the unscaled value, as big endian two's complement.
+#[cfg(feature = "avro")]
+fn unscaled(bytes: &[u8]) -> Option<i128> {
+    if bytes.len() > 16 {
+        return None;
+    }
+    let negative = bytes.first().map(|b| b & 0x80 != 0).unwrap_or(false);
+    let mut buffer = if negative { [0xff; 16] } else { [0; 16] };
+    buffer[16 - bytes.len()..].copy_from_slice(bytes);
+    Some(i128::from_be_bytes(buffer))
+}
+
+fn json_type(schema: &JsonValue, path: &str) -> Result<(DataType, bool), SRCError> {
+    let (type_name, nullable) = match schema.get("type") {
+        Some(JsonValue::String(t)) => (t.as_str(), t == "null"),
+        Some(JsonValue::Array(types)) => {
+            let not_null: Vec<&str> = types
+                .iter()
+                .filter_map(|t| t.as_str())
+                .filter(|t| *t != "null")
+                .collect();
+            if not_null.len() != 1 {
+                return Err(unsupported(&format!(
+                    "json schema at {} with multiple types",
+                    path
+                )));
+            }
+            (not_null[0], not_null.len() < types.len())
+        }
+        _ => {
+            return Err(unsupported(&format!(
+                "json schema at {} without a type",
+                path
+            )))
+        }
+    };
+    let data_type = match type_name {
+        "null" => DataType::Null,
+        "boolean" => DataType::Boolean,
+        "integer" => DataType::Int64,
+        "number" => DataType::Float64,
+        "string" => DataType::Utf8,
+        "array" => match schema.get("items") {
+            Some(items) => {
+                let (item_type, nullable) = json_type(items, &format!("{}[]", path))?;
+                DataType::new_list(item_type, nullable)
+            }
+            None => {
+                return Err(unsupported(&format!(
+                    "json array at {} without items",
+                    path
+                )))
+            }
+        },
+        "object" => match (schema.get("properties"), schema.get("additionalProperties")) {
+            (Some(JsonValue::Object(properties)), _) => {
+                let required: Vec<&str> = match schema.get("required") {
+                    Some(JsonValue::Array(r)) => r.iter().filter_map(|n| n.as_str()).collect(),
+                    _ => Vec::new(),
+                };
+                let mut fields = Vec::with_capacity(properties.len());
+                for (name, property) in properties {
+                    let (data_type, nullable) = json_type(property, &format!("{}.{}", path, name))?;
+                    let nullable = nullable || !required.contains(&name.as_str());
+                    fields.push(Field::new(name.clone(), data_type, nullable));
+                }
+                DataType::Struct(Fields::from(fields))
+            }
+            (_, Some(values @ JsonValue::Object(_))) => {
+                let (value_type, nullable) = json_type(values, &format!("{}.*", path))?;
+                map_type(value_type, nullable)
+            }
+            _ => {
+                return Err(unsupported(&format!(
+                    "json object at {} without properties",
+                    path
+                )))
+            }
+        },
+        other => return Err(unsupported(&format!("json type {} at {}", other, path))),
+    };
+    Ok((data_type, nullable))
+}
+
+fn json_datum(value: &JsonValue) -> Datum {
+    match value {
+        JsonValue::Null => Datum::Null,
+        JsonValue::Bool(b) => Datum::Bool(*b),
+        JsonValue::Number(n) => match (n.as_i64(), n.as_u64()) {
+            (Some(i), _) => Datum::Int(i128::from(i)),
+            (_, Some(u)) => Datum::Int(i128::from(u)),
+            _ => Datum::Float(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        JsonValue::String(s) => Datum::Str(s.clone()),
+        JsonValue::Array(items) => Datum::List(items.iter().map(json_datum).collect()),
+        JsonValue::Object(entries) => Datum::Struct(
+            entries
+                .iter()
+                .map(|(k, v)| (k.clone(), json_datum(v)))
+                .collect(),
+        ),
+    }
+}
+
+#[cfg(feature = "proto_decoder")]
+fn proto_fields(
+    info: &MessageInfo,
+    context: &Context,
+    messages: &mut Vec<String>,
+) -> Result<Fields, SRCError> {
+    if messages.contains(&info.full_name) {
+        return Err(unsupported(&format!(
+            "recursive protobuf message {}",
+            info.full_name
+        )));
+    }
+    messages.push(info.full_name.clone());
+    let mut fields = Vec::new();
+    for field in info.iter_fields() {
+        let data_type = proto_field_type(field, context, messages)?;
+        let repeated = matches!(
+            field.multiplicity,
+            Multiplicity::Repeated | Multiplicity::RepeatedPacked
+        );
+        fields.push(Field::new(field.name.clone(), data_type, !repeated));
+    }
+    messages.pop();
+    Ok(Fields::from(fields))
+}
+
+#[cfg(feature = "proto_decoder")]
+fn proto_field_type(
+    field: &MessageField,
+    context: &Context,
+    messages: &mut Vec<String>,
+) -> Result<DataType, SRCError> {
+    match field.multiplicity {
+        Multiplicity::Repeated | Multiplicity::RepeatedPacked => {
+            if let (true, ValueType::Message(entry)) =
+                (is_map_entry(field, context), &field.field_type)
+            {
+                return match context.resolve_message(*entry).get_field(2) {
+                    Some(value) => Ok(map_type(
+                        proto_value_type(&value.field_type, context, messages)?,
+                        true,
+                    )),
+                    None => Err(unsupported(&format!("protobuf map field {}", field.name))),
+                };
+            }
+            Ok(DataType::new_list(
+                proto_value_type(&field.field_type, context, messages)?,
+                false,
+            ))
+        }
+        _ => proto_value_type(&field.field_type, context, messages),
+    }
+}
+
+#[cfg(feature = "proto_decoder")]
+fn proto_value_type(
+    value_type: &ValueType,
+    context: &Context,
+    messages: &mut Vec<String>,
+) -> Result<DataType, SRCError> {
+    Ok(match value_type {
+        ValueType::Double => DataType::Float64,
+        ValueType::Float => DataType::Float32,
+        ValueType::Int32 | ValueType::SInt32 | ValueType::SFixed32 => DataType::Int32,
+        ValueType::Int64 | ValueType::SInt64 | ValueType::SFixed64 => DataType::Int64,
+        ValueType::UInt32 | ValueType::Fixed32 => DataType::UInt32,
+        ValueType::UInt64 | ValueType::Fixed64 => DataType::UInt64,
+        ValueType::Bool => DataType::Boolean,
+        ValueType::String | ValueType::Enum(_) => DataType::Utf8,
+        ValueType::Bytes => DataType::Binary,
+        ValueType::Message(msg_ref) => {
+            let info = context.resolve_message(*msg_ref);
+            match info.full_name.as_str() {
+                "google.protobuf.Timestamp" => {
+                    DataType::Timestamp(TimeUnit::Nanosecond, Some("UTC".into()))
+                }
+                "google.protobuf.DoubleValue" => DataType::Float64,
+                "google.protobuf.FloatValue" => DataType::Float32,
+                "google.protobuf.Int64Value" => DataType::Int64,
+                "google.protobuf.UInt64Value" => DataType::UInt64,
+                "google.protobuf.Int32Value" => DataType::Int32,
+                "google.protobuf.UInt32Value" => DataType::UInt32,
+                "google.protobuf.BoolValue" => DataType::Boolean,
+                "google.protobuf.StringValue" => DataType::Utf8,
+                "google.protobuf.BytesValue" => DataType::Binary,
+                "google.protobuf.Duration"
+                | "google.protobuf.Struct"
+                | "google.protobuf.Value"
+                | "google.protobuf.ListValue"
+                | "google.protobuf.FieldMask"
+                | "google.protobuf.Any" => DataType::Utf8,
+                _ => DataType::Struct(proto_fields(info, context, messages)?),
+            }
+        }
+        other => return Err(unsupported(&format!("protobuf type {:?}", other))),
+    })
+}
+
+#[cfg(feature = "proto_decoder")]
+fn proto_message_datum(info: &MessageInfo, value: &MessageValue, context: &Context) -> Datum {
+    match info.full_name.as_str() {
+        "google.protobuf.Timestamp" => Datum::Int(
+            i128::from(int_field(value, 1)) * 1_000_000_000 + i128::from(int_field(value, 2)),
+        ),
+        "google.protobuf.DoubleValue"
+        | "google.protobuf.FloatValue"
+        | "google.protobuf.Int64Value"
+        | "google.protobuf.UInt64Value"
+        | "google.protobuf.Int32Value"
+        | "google.protobuf.UInt32Value"
+        | "google.protobuf.BoolValue"
+        | "google.protobuf.StringValue"
+        | "google.protobuf.BytesValue" => match field_value(value, 1) {
+            Some(v) => proto_datum(v, context),
+            None => json_datum(&wrapper_default(&info.full_name)),
+        },
+        "google.protobuf.Duration"
+        | "google.protobuf.Struct"
+        | "google.protobuf.Value"
+        | "google.protobuf.ListValue"
+        | "google.protobuf.FieldMask"
+        | "google.protobuf.Any" => match proto_value_to_json(value, context) {
+            JsonValue::String(s) => Datum::Str(s),
+            json => Datum::Str(json.to_string()),
+        },
+        _ => Datum::Struct(
+            info.iter_fields()
+                .map(|field| (field.name.clone(), proto_field_datum(field, value, context)))
+                .collect(),
+        ),
+    }
+}
+
+#[cfg(feature = "proto_decoder")]
+fn proto_field_datum(field: &MessageField, value: &MessageValue, context: &Context) -> Datum {
+    let values = value
+        .fields
+        .iter()
+        .filter(|f| f.number == field.number)
+        .map(|f| &f.value);
+    match field.multiplicity {
+        Multiplicity::Repeated | Multiplicity::RepeatedPacked => {
+            let mut items = Vec::new();
+            for v in values {
+                match v {
+                    ProtoValue::Packed(p) => items.extend(packed_datums(p)),
+                    v => items.push(proto_datum(v, context)),
+                }
+            }
+            if is_map_entry(field, context) {
+                Datum::Map(items.into_iter().filter_map(map_entry).collect())
+            } else {
+                Datum::List(items)
+            }
+        }
+        _ => match values.last() {
+            Some(v) => proto_datum(v, context),
+            None => Datum::Null,
+        },
+    }
+}
+
+/// Map keys can be any integral or string type in protobuf, in arrow they are always strings.
+#[cfg(feature = "proto_decoder")]
+fn map_entry(entry: Datum) -> Option<(String, Datum)> {
+    let mut fields = match entry {
+        Datum::Struct(fields) => fields,
+        _ => return None,
+    };
+    let value = match fields.pop() {
+        Some((name, value)) if name == "value" => value,
+        _ => return None,
+    };
+    let key = match fields.pop() {
+        Some((_, Datum::Str(s))) => s,
+        Some((_, Datum::Int(i))) => i.to_string(),
+        Some((_, Datum::Bool(b))) => b.to_string(),
+        _ => String::new(),
+    };
+    Some((key, value))
+}
+
+#[cfg(feature = "proto_decoder")]
+fn proto_datum(value: &ProtoValue, context: &Context) -> Datum {
+    match value {
+        ProtoValue::Double(v) => Datum::Float(*v),
+        ProtoValue::Float(v) => Datum::Float(f64::from(*v)),
+        ProtoValue::Int32(v) | ProtoValue::SInt32(v) | ProtoValue::SFixed32(v) => {
+            Datum::Int(i128::from(*v))
+        }
+        ProtoValue::Int64(v) | ProtoValue::SInt64(v) | ProtoValue::SFixed64(v) => {
+            Datum::Int(i128::from(*v))
+        }
+        ProtoValue::UInt32(v) | ProtoValue::Fixed32(v) => Datum::Int(i128::from(*v)),
+        ProtoValue::UInt64(v) | ProtoValue::Fixed64(v) => Datum::Int(i128::from(*v)),
+        ProtoValue::Bool(v) => Datum::Bool(*v),
+        ProtoValue::String(v) => Datum::Str(v.clone()),
+        ProtoValue::Bytes(v) => Datum::Bytes(v.to_vec()),
+        ProtoValue::Enum(_) => match to_json(value, context) {
+            JsonValue::String(s) => Datum::Str(s),
+            json => Datum::Str(json.to_string()),
+        },
+        ProtoValue::Message(m) => {
+            proto_message_datum(context.resolve_message(m.msg_ref), m, context)
+        }
+        ProtoValue::Packed(p) => Datum::List(packed_datums(p)),
+        _ => Datum::Null,
+    }
+}
+
+#[cfg(feature = "proto_decoder")]
+fn packed_datums(packed: &PackedArray) -> Vec<Datum> {
+    match packed {
+        PackedArray::Double(v) => v.iter().map(|f| Datum::Float(*f)).collect(),
+        PackedArray::Float(v) => v.iter().map(|f| Datum::Float(f64::from(*f))).collect(),
+        PackedArray::Int32(v) | PackedArray::SInt32(v) | PackedArray::SFixed32(v) => {
+            v.iter().map(|i| Datum::Int(i128::from(*i))).collect()
+        }
+        PackedArray::Int64(v) | PackedArray::SInt64(v) | PackedArray::SFixed64(v) => {
+            v.iter().map(|i| Datum::Int(i128::from(*i))).collect()
+        }
+        PackedArray::UInt32(v) | PackedArray::Fixed32(v) => {
+            v.iter().map(|i| Datum::Int(i128::from(*i))).collect()
+        }
+        PackedArray::UInt64(v) | PackedArray::Fixed64(v) => {
+            v.iter().map(|i| Datum::Int(i128::from(*i))).collect()
+        }
+        PackedArray::Bool(v) => v.iter().map(|b| Datum::Bool(*b)).collect(),
+    }
+}
+
+fn lookup<'d>(fields: &'d [(String, Datum)], name: &str) -> &'d Datum {
+    match fields.iter().rev().find(|(n, _)| n == name) {
+        Some((_, d)) => d,
+        None => &NULL,
+    }
+}
+
+/// Checks the value can be put in an array of the data type, with the path of the value in the
+/// error when it can't.
+fn check(datum: &Datum, data_type: &DataType, nullable: bool, path: &str) -> Result<(), SRCError> {
+    let valid = match (data_type, datum) {
+        (DataType::Null, _) => true,
+        (_, Datum::Null) => nullable,
+        (DataType::Boolean, Datum::Bool(_)) => true,
+        (DataType::Int32 | DataType::Date32 | DataType::Time32(_), Datum::Int(i)) => {
+            i32::try_from(*i).is_ok()
+        }
+        (DataType::Int64 | DataType::Time64(_) | DataType::Timestamp(_, _), Datum::Int(i)) => {
+            i64::try_from(*i).is_ok()
+        }
+        (DataType::UInt32, Datum::Int(i)) => u32::try_from(*i).is_ok(),
+        (DataType::UInt64, Datum::Int(i)) => u64::try_from(*i).is_ok(),
+        (DataType::Decimal128(_, _), Datum::Int(_)) => true,
+        (DataType::Float32 | DataType::Float64, Datum::Int(_) | Datum::Float(_)) => true,
+        (DataType::Utf8, Datum::Str(_)) => true,
+        (DataType::Binary, Datum::Bytes(_) | Datum::Str(_)) => true,
+        (DataType::List(field), Datum::List(items)) => {
+            for (i, item) in items.iter().enumerate() {
+                let item_path = format!("{}[{}]", path, i);
+                check(item, field.data_type(), field.is_nullable(), &item_path)?;
+            }
+            true
+        }
+        (DataType::Map(field, _), Datum::Map(entries) | Datum::Struct(entries)) => {
+            let value_field = map_value_field(field)?;
+            for (key, value) in entries {
+                let value_path = format!("{}.{}", path, key);
+                check(
+                    value,
+                    value_field.data_type(),
+                    value_field.is_nullable(),
+                    &value_path,
+                )?;
+            }
+            true
+        }
+        (DataType::Struct(fields), Datum::Struct(values)) => {
+            for field in fields {
+                let field_path = format!("{}.{}", path, field.name());
+                check(
+                    lookup(values, field.name()),
+                    field.data_type(),
+                    field.is_nullable(),
+                    &field_path,
+                )?;
+            }
+            true
+        }
+        _ => false,
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err(SRCError::non_retryable_without_cause(&format!(
+            "Value at {} can't be converted to arrow type {}",
+            path, data_type
+        )))
+    }
+}
+
+fn map_value_field(field: &FieldRef) -> Result<&FieldRef, SRCError> {
+    match field.data_type() {
+        DataType::Struct(fields) if fields.len() == 2 => Ok(&fields[1]),
+        other => Err(unsupported(&format!("map entries of type {}", other))),
+    }
+}
+
+fn nulls(values: &[&Datum]) -> Option<NullBuffer> {
+    if values.iter().any(|d| matches!(d, Datum::Null)) {
+        Some(NullBuffer::from(
+            values
+                .iter()
+                .map(|d| !matches!(d, Datum::Null))
+                .collect::<Vec<bool>>(),
+        ))
+    } else {
+        None
+    }
+}
+
+fn to_array(data_type: &DataType, values: &[&Datum]) -> Result<ArrayRef, SRCError> {
+    Ok(match data_type {
+        DataType::Null => Arc::new(NullArray::new(values.len())),
+        DataType::Boolean => Arc::new(
+            values
+                .iter()
+                .map(|d| match d {
+                    Datum::Bool(b) => Some(*b),
+                    _ => None,
+                })
+                .collect::<BooleanArray>(),
+        ),
+        DataType::Int32 => primitive::<Int32Type>(data_type, values),
+        DataType::Int64 => primitive::<Int64Type>(data_type, values),
+        DataType::UInt32 => primitive::<UInt32Type>(data_type, values),
+        DataType::UInt64 => primitive::<UInt64Type>(data_type, values),
+        DataType::Date32 => primitive::<Date32Type>(data_type, values),
+        DataType::Time32(TimeUnit::Millisecond) => {
+            primitive::<Time32MillisecondType>(data_type, values)
+        }
+        DataType::Time64(TimeUnit::Microsecond) => {
+            primitive::<Time64MicrosecondType>(data_type, values)
+        }
+        DataType::Timestamp(TimeUnit::Millisecond, _) => {
+            primitive::<TimestampMillisecondType>(data_type, values)
+        }
+        DataType::Timestamp(TimeUnit::Microsecond, _) => {
+            primitive::<TimestampMicrosecondType>(data_type, values)
+        }
+        DataType::Timestamp(TimeUnit::Nanosecond, _) => {
+            primitive::<TimestampNanosecondType>(data_type, values)
+        }
+        DataType::Decimal128(_, _) => primitive::<Decimal128Type>(data_type, values),
+        DataType::Float32 => Arc::new(
+            values
+                .iter()
+                .map(|d| float(d).map(|f| f as f32))
+                .collect::<Float32Array>(),
+        ),
+        DataType::Float64 => Arc::new(values.iter().map(|d| float(d)).collect::<Float64Array>()),
+        DataType::Utf8 => Arc::new(
+            values
+                .iter()
+                .map(|d| match d {
+                    Datum::Str(s) => Some(s.as_str()),
+                    _ => None,
+                })
+                .collect::<StringArray>(),
+        ),
+        DataType::Binary => Arc::new(
+            values
+                .iter()
+                .map(|d| match d {
+                    Datum::Bytes(b) => Some(b.as_slice()),
+                    Datum::Str(s) => Some(s.as_bytes()),
+                    _ => None,
+                })
+                .collect::<BinaryArray>(),
+        ),
+        DataType::List(field) => to_list(field, values)?,
+        DataType::Map(field, sorted) => to_map(field, *sorted, values)?,
+        DataType::Struct(fields) => {
+            let nulls = nulls(values);
+            let array = if fields.is_empty() {
+                StructArray::new_empty_fields(values.len(), nulls)
+            } else {
+                match StructArray::try_new(fields.clone(), to_columns(fields, values)?, nulls) {
+                    Ok(v) => v,
+                    Err(e) => return Err(arrow_error(e)),
+                }
+            };
+            Arc::new(array)
+        }
+        other => return Err(unsupported(&format!("arrow type {}", other))),
+    })
+}
+
+fn primitive<T: ArrowPrimitiveType>(data_type: &DataType, values: &[&Datum]) -> ArrayRef
+where
+    T::Native: TryFrom<i128>,
+{
+    let array: PrimitiveArray<T> = values
+        .iter()
+        .map(|d| match d {
+            Datum::Int(i) => T::Native::try_from(*i).ok(),
+            _ => None,
+        })
+        .collect();
+    Arc::new(array.with_data_type(data_type.clone()))
+}
+
+fn float(datum: &Datum) -> Option<f64> {
+    match datum {
+        Datum::Float(f) => Some(*f),
+        Datum::Int(i) => Some(*i as f64),
+        _ => None,
+    }
+}
+
+fn to_columns(fields: &Fields, values: &[&Datum]) -> Result<Vec<ArrayRef>, SRCError> {
+    fields
+        .iter()
+        .map(|field| {
+            let column: Vec<&Datum> = values
+                .iter()
+                .map(|d| match d {
+                    Datum::Struct(v) => lookup(v, field.name()),
+                    _ => &NULL,
+                })
+                .collect();
+            to_array(field.data_type(), &column)
+        })
+        .collect()
+}
+
+fn to_list(field: &FieldRef, values: &[&Datum]) -> Result<ArrayRef, SRCError> {
+    let mut lengths = Vec::with_capacity(values.len());
+    let mut items = Vec::new();
+    for d in values {
+        match d {
+            Datum::List(l) => {
+                lengths.push(l.len());
+                items.extend(l.iter());
+            }
+            _ => lengths.push(0),
+        }
+    }
+    let child = to_array(field.data_type(), &items)?;
+    match ListArray::try_new(
+        field.clone(),
+        OffsetBuffer::from_lengths(lengths),
+        child,
+        nulls(values),
+    ) {
+        Ok(v) => Ok(Arc::new(v)),
+        Err(e) => Err(arrow_error(e)),
+    }
+}
+
+fn to_map(field: &FieldRef, sorted: bool, values: &[&Datum]) -> Result<ArrayRef, SRCError> {
+    let entry_fields = match field.data_type() {
+        DataType::Struct(fields) if fields.len() == 2 => fields,
+        other => return Err(unsupported(&format!("map entries of type {}", other))),
+    };
+    let mut lengths = Vec::with_capacity(values.len());
+    let mut keys = Vec::new();
+    let mut entry_values = Vec::new();
+    for d in values {
+        match d {
+            Datum::Map(entries) | Datum::Struct(entries) => {
+                lengths.push(entries.len());
+                for (key, value) in entries {
+                    keys.push(key.as_str());
+                    entry_values.push(value);
+                }
+            }
+            _ => lengths.push(0),
+        }
+    }
+    let columns = vec![
+        Arc::new(StringArray::from(keys)) as ArrayRef,
+        to_array(entry_fields[1].data_type(), &entry_values)?,
+    ];
+    let entries = match StructArray::try_new(entry_fields.clone(), columns, None) {
+        Ok(v) => v,
+        Err(e) => return Err(arrow_error(e)),
+    };
+    match MapArray::try_new(
+        field.clone(),
+        OffsetBuffer::from_lengths(lengths),
+        entries,
+        nulls(values),
+        sorted,
+    ) {
+        Ok(v) => Ok(Arc::new(v)),
+        Err(e) => Err(arrow_error(e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::{Float64Type, Int64Type};
+    use arrow_array::Array;
+    use arrow_schema::DataType;
+    use serde_json::json;
+
+    use crate::arrow::{json_to_arrow_schema, ArrowBatcher};
+
+    #[cfg(feature = "avro")]
+    #[test]
+    fn avro_values_to_batches() {
+        use apache_avro::types::Value;
+        use apache_avro::Schema;
+        use std::collections::HashMap;
+
+        let schema = Schema::parse_str(
+            r#"{"type":"record","name":"Reading","fields":[
+                {"name":"id","type":"long"},
+                {"name":"tags","type":{"type":"array","items":"string"}},
+                {"name":"score","type":["null","double"]},
+                {"name":"counts","type":{"type":"map","values":"int"}}
+            ]}"#,
+        )
+        .unwrap();
+        let mut batcher = ArrowBatcher::for_avro(&schema, 2).unwrap();
+        let value = |id: i64, score: Value| {
+            Value::Record(vec![
+                (String::from("id"), Value::Long(id)),
+                (
+                    String::from("tags"),
+                    Value::Array(vec![Value::String(String::from("a"))]),
+                ),
+                (String::from("score"), score),
+                (
+                    String::from("counts"),
+                    Value::Map(HashMap::from([(String::from("x"), Value::Int(1))])),
+                ),
+            ])
+        };
+
+        assert!(batcher
+            .push_avro(&value(1, Value::Union(0, Box::new(Value::Null))))
+            .unwrap()
+            .is_none());
+        let batch = batcher
+            .push_avro(&value(2, Value::Union(1, Box::new(Value::Double(0.5)))))
+            .unwrap()
+            .unwrap();
+        assert_eq!(batch.num_rows(), 2);
+        assert_eq!(
+            batch.column(0).as_primitive::<Int64Type>().values(),
+            &[1, 2]
+        );
+        let scores = batch.column(2).as_primitive::<Float64Type>();
+        assert!(scores.is_null(0));
+        assert_eq!(scores.value(1), 0.5);
+        assert_eq!(batch.column(1).as_list::<i32>().value(1).len(), 1);
+        assert!(matches!(batch.column(3).data_type(), DataType::Map(_, _)));
+
+        let error = batcher
+            .push_avro(&Value::Record(vec![(String::from("id"), Value::Null)]))
+            .unwrap_err();
+        assert_eq!(
+            error.error,
+            "Value at $.id can't be converted to arrow type Int64"
+        );
+        assert!(batcher.is_empty());
+        assert!(batcher.flush().unwrap().is_none());
+    }
+
+    #[test]
+    fn json_values_to_batches() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "name": {"type": "string"},
+                "amount": {"type": "number"},
+                "items": {"type": "array", "items": {"type": "integer"}}
+            },
+            "required": ["name"]
+        });
+        let arrow_schema = json_to_arrow_schema(&schema).unwrap();
+        assert!(!arrow_schema.field_with_name("name").unwrap().is_nullable());
+        assert!(arrow_schema
+            .field_with_name("amount")
+            .unwrap()
+            .is_nullable());
+
+        let mut batcher = ArrowBatcher::for_json(&schema, 10).unwrap();
+        batcher
+            .push_json(&json!({"name": "a", "amount": 3, "items": [1, 2]}))
+            .unwrap();
+        batcher.push_json(&json!({"name": "b"})).unwrap();
+        assert!(batcher.push_json(&json!({"amount": 1.5})).is_err());
+
+        let batch = batcher.flush().unwrap().unwrap();
+        assert_eq!(batch.num_rows(), 2);
+        let amounts = batch
+            .column_by_name("amount")
+            .unwrap()
+            .as_primitive::<Float64Type>();
+        assert_eq!(amounts.value(0), 3.0);
+        assert!(amounts.is_null(1));
+        assert_eq!(
+            batch
+                .column_by_name("name")
+                .unwrap()
+                .as_string::<i32>()
+                .value(1),
+            "b"
+        );
+    }
+
+    #[cfg(feature = "proto_decoder")]
+    #[test]
+    fn proto_values_to_batches() {
+        use protofish::context::Context;
+
+        let context = Context::parse([r#"syntax = "proto3";
+            package nl.openweb.data;
+            message Person {
+              string name = 1;
+              repeated int32 numbers = 2;
+              Color color = 3;
+              bytes raw = 5;
+              enum Color { RED = 0; GREEN = 1; }
+            }"#])
+        .unwrap();
+        let info = context.get_message("nl.openweb.data.Person").unwrap();
+        let value = info.decode(
+            &[10, 3, 66, 111, 98, 18, 2, 1, 2, 24, 1, 42, 2, 1, 2],
+            &context,
+        );
+
+        let mut batcher = ArrowBatcher::for_proto(&context, "nl.openweb.data.Person", 1).unwrap();
+        let batch = batcher.push_proto(&value, &context).unwrap().unwrap();
+
+        assert_eq!(batch.num_rows(), 1);
+        let name = batch.column_by_name("name").unwrap().as_string::<i32>();
+        assert_eq!(name.value(0), "Bob");
+        let numbers = batch.column_by_name("numbers").unwrap().as_list::<i32>();
+        assert_eq!(numbers.value(0).len(), 2);
+        let color = batch.column_by_name("color").unwrap().as_string::<i32>();
+        assert_eq!(color.value(0), "GREEN");
+        let raw = batch.column_by_name("raw").unwrap().as_binary::<i32>();
+        assert_eq!(raw.value(0), &[1, 2]);
+    }
+}
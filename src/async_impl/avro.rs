@@ -22,6 +22,7 @@
 //!
 //! [avro-rs]: https://crates.io/crates/avro-rs
 
+use std::collections::HashSet;
 use std::io::Cursor;
 use std::sync::Arc;
 
@@ -29,22 +30,42 @@ use apache_avro::types::Value;
 use apache_avro::{from_avro_datum, Schema};
 use dashmap::mapref::entry::Entry;
 use dashmap::DashMap;
-use futures::future::{BoxFuture, Shared};
+use futures::future::{join_all, try_join, Shared};
+use futures::lock::Mutex as AsyncMutex;
 use futures::FutureExt;
+use serde::de::DeserializeOwned;
 use serde::ser::Serialize;
 use serde_json::value;
 
 use crate::async_impl::schema_registry::{
-    get_referenced_schema, get_schema_by_id_and_type, get_schema_by_subject, SrSettings,
+    get_all_schemas, get_allowed_schema, get_migration, get_referenced_schema,
+    get_schema_by_subject, lookup_schema, SrSettings,
 };
+use crate::async_impl::{SrFuture, SrFutureExt};
 use crate::avro_common::{
-    get_name, item_to_bytes, replace_reference, values_to_bytes, AvroSchema, DecodeResult,
-    DecodeResultWithSchema,
+    apply_field_encryption, check_record_name, contains_definition, decode_datum,
+    decode_with_reader_schema, decompress, from_avro_value, get_fingerprint, get_name,
+    get_single_object_bytes, item_to_bytes, item_to_value, migrate_value,
+    missing_fingerprint_error, parse_local_schema, replace_reference, values_to_bytes,
+    values_to_bytes_with_schema, values_to_encrypted_record, write_bytes, AvroCompression,
+    AvroFraming, AvroSchema, DecodeResult, DecodeResultWithSchema, FingerprintLookups, LocalSchema,
+    LocalSchemaProvider, ReaderSchema, UnknownFieldPolicy,
 };
+#[cfg(feature = "derive")]
+use crate::avro_derive::SrAvroSerialize;
+use crate::avro_logical::{to_serde_value, AvroCodecConfig};
 use crate::error::SRCError;
+use crate::field_encryption::{Direction, FieldEncryptor};
+use crate::migration::{Migration, MigrationConfig};
 use crate::schema_registry_common::{
-    get_bytes_result, BytesResult, RegisteredReference, RegisteredSchema, SchemaType,
-    SubjectNameStrategy,
+    check_policy, check_remaining_bytes, to_wire_format_error, BytesResult, DecodeMetadata,
+    DecodeResultWithMetadata, DecodedRecord, DecoderOptions, DeserializationErrorPolicy,
+    IdStrategy, RegisteredReference, RegisteredSchema, SchemaPolicy, SchemaType, SchemaUsageKind,
+    SlowPathWarnings, Strictness, SubjectNameStrategy,
+};
+use crate::telemetry::{
+    decode_start, record_cache_lookup, record_decode_error, warn_batch_fetch_failed,
+    warn_registry_fetch, warn_slow_decode, CachedSchemas,
 };
 
 /// A decoder used to transform bytes to a Value object
@@ -59,6 +80,8 @@ use crate::schema_registry_common::{
 /// both. But only using the SubjectNameStrategy::TopicNameStrategy it has to be made explicit
 /// whether it's actual used as key or value.
 ///
+/// Cloning the decoder is cheap, and the clones share the cache.
+///
 /// ```
 /// use apache_avro::types::Value;
 /// use mockito::Server;
@@ -81,14 +104,28 @@ use crate::schema_registry_common::{
 /// # Ok(())
 /// # }
 /// ```
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct AvroDecoder<'a> {
     sr_settings: SrSettings,
-    direct_cache: DashMap<u32, Arc<AvroSchema>>,
-    cache: DashMap<u32, SharedFutureSchema<'a>>,
+    direct_cache: Arc<DashMap<u32, Arc<AvroSchema>>>,
+    cache: Arc<DashMap<u32, SharedFutureSchema<'a>>>,
+    fingerprints: Arc<DashMap<u64, Arc<AvroSchema>>>,
+    fingerprint_lookups: Arc<FingerprintLookups<AsyncMutex<()>>>,
+    codec_config: AvroCodecConfig,
+    compression: AvroCompression,
+    id_strategy: IdStrategy,
+    strictness: Strictness,
+    error_policy: DeserializationErrorPolicy,
+    field_encryptor: Option<Arc<dyn FieldEncryptor>>,
+    migration: Option<MigrationConfig>,
+    migrations: Arc<DashMap<u32, Arc<Migration>>>,
+    reader_schema: Option<ReaderSchema>,
+    slow_path: SlowPathWarnings,
+    policy: Option<Arc<dyn SchemaPolicy>>,
+    cached_schemas: Arc<CachedSchemas>,
 }
 
-type SharedFutureSchema<'a> = Shared<BoxFuture<'a, Result<Arc<AvroSchema>, SRCError>>>;
+type SharedFutureSchema<'a> = Shared<SrFuture<'a, Result<Arc<AvroSchema>, SRCError>>>;
 
 impl<'a> AvroDecoder<'a> {
     /// Creates a new decoder which will use the supplied url to fetch the schema's since the schema
@@ -99,10 +136,105 @@ impl<'a> AvroDecoder<'a> {
     pub fn new(sr_settings: SrSettings) -> AvroDecoder<'a> {
         AvroDecoder {
             sr_settings,
-            direct_cache: DashMap::new(),
-            cache: DashMap::new(),
+            direct_cache: Arc::new(DashMap::new()),
+            cache: Arc::new(DashMap::new()),
+            fingerprints: Arc::new(DashMap::new()),
+            fingerprint_lookups: Arc::new(FingerprintLookups::new(AsyncMutex::new(()))),
+            codec_config: AvroCodecConfig::default(),
+            compression: AvroCompression::default(),
+            id_strategy: IdStrategy::default(),
+            strictness: Strictness::Lenient,
+            error_policy: DeserializationErrorPolicy::default(),
+            field_encryptor: None,
+            migration: None,
+            migrations: Arc::new(DashMap::new()),
+            reader_schema: None,
+            slow_path: SlowPathWarnings::default(),
+            policy: None,
+            cached_schemas: Arc::new(CachedSchemas::new("avro")),
         }
     }
+    /// Sets how logical types are converted by decode_as.
+    pub fn with_codec_config(mut self, codec_config: AvroCodecConfig) -> AvroDecoder<'a> {
+        self.codec_config = codec_config;
+        self
+    }
+    /// Sets the compression of the avro bytes, which needs to be the same as used by the encoder.
+    pub fn with_compression(mut self, compression: AvroCompression) -> AvroDecoder<'a> {
+        self.compression = compression;
+        self
+    }
+    /// Sets how the schema id is read, which needs to be the same as used by the encoder. With
+    /// the id in a header, use decode_with_headers.
+    pub fn with_id_strategy(mut self, id_strategy: IdStrategy) -> AvroDecoder<'a> {
+        self.id_strategy = id_strategy;
+        self
+    }
+    /// Sets how payloads that don't follow the wire format are handled. Payloads without the magic
+    /// byte and id always give an error with the [WireFormatError](crate::error::WireFormatError).
+    /// When strict, bytes left after decoding the data give an error with it too.
+    pub fn with_strictness(mut self, strictness: Strictness) -> AvroDecoder<'a> {
+        self.strictness = strictness;
+        self
+    }
+    /// Sets what decode_or_skip does with payloads that can't be decoded.
+    pub fn with_error_policy(
+        mut self,
+        error_policy: DeserializationErrorPolicy,
+    ) -> AvroDecoder<'a> {
+        self.error_policy = error_policy;
+        self
+    }
+    /// Sets the encryptor used to decrypt the fields tagged by an ENCRYPT rule of the schema.
+    pub fn with_field_encryptor(
+        mut self,
+        field_encryptor: Arc<dyn FieldEncryptor>,
+    ) -> AvroDecoder<'a> {
+        self.field_encryptor = Some(field_encryptor);
+        self
+    }
+    /// Reads the values as the latest version of the subject with the metadata of the migration,
+    /// applying the migration rules of the versions in between when a value was written with
+    /// another version. Used by decode, decode_with_schema, decode_with_metadata and decode_as.
+    pub fn with_migration(mut self, migration: MigrationConfig) -> AvroDecoder<'a> {
+        self.migration = Some(migration);
+        self
+    }
+    /// Reads the values as the reader schema, filling the fields missing in the schema the value was
+    /// written with, with the default of the reader schema. The policy sets what happens with fields
+    /// only in the writer schema. When a migration is also set, the migrated values are read as the
+    /// reader schema. The schema of the results is the reader schema, with the id and subject of the
+    /// writer schema. Used by decode, decode_with_schema, decode_with_metadata and decode_as.
+    pub fn with_reader_schema(
+        mut self,
+        reader_schema: Schema,
+        unknown_fields: UnknownFieldPolicy,
+    ) -> AvroDecoder<'a> {
+        self.reader_schema = Some(ReaderSchema::new(reader_schema, unknown_fields));
+        self
+    }
+    /// Sets which slow paths of decode are logged as a warning, like a decode taking longer than a
+    /// threshold, or a decode needing to fetch the schema.
+    pub fn with_slow_path_warnings(mut self, slow_path: SlowPathWarnings) -> AvroDecoder<'a> {
+        self.slow_path = slow_path;
+        self
+    }
+    /// Sets the policy restricting which schema's are fetched and decoded. Payloads with a schema
+    /// id that's not allowed give an error with a `PolicyViolation`, which is not cached, so a
+    /// change of the policy is applied directly.
+    pub fn with_policy(mut self, policy: Arc<dyn SchemaPolicy>) -> AvroDecoder<'a> {
+        self.policy = Some(policy);
+        self
+    }
+    /// Sets the options shared by all the decoders, replacing the id strategy, strictness, slow
+    /// path warnings and policy set before.
+    pub fn with_options(mut self, options: DecoderOptions) -> AvroDecoder<'a> {
+        self.id_strategy = options.id_strategy;
+        self.strictness = options.strictness;
+        self.slow_path = options.slow_path;
+        self.policy = options.policy;
+        self
+    }
     /// Remove al the errors from the cache, you might need to/want to run this when a recoverable
     /// error is met. Errors are also cashed to prevent trying to get schema's that either don't
     /// exist or can't be parsed.
@@ -152,6 +284,33 @@ impl<'a> AvroDecoder<'a> {
             None => true,
         });
     }
+    /// Removes the migrations to the latest version of the subject of the migration from the cache,
+    /// so a new latest version with the metadata is picked up on the next decode. Long-lived
+    /// consumers can call this when they are signaled a new version was registered, like with
+    /// invalidate_subject of the encoder. The schema's for the id's don't change, so they are kept.
+    pub fn invalidate_subject(&self, subject: &str) {
+        if matches!(&self.migration, Some(config) if config.subject == subject) {
+            self.migrations.clear();
+        }
+    }
+    /// Decodes the bytes, applying the error policy when they can't be decoded. None is returned
+    /// when the bytes are skipped.
+    pub async fn decode_or_skip(
+        &self,
+        bytes: Option<&[u8]>,
+    ) -> Result<Option<DecodeResult>, SRCError> {
+        let result = self.decode(bytes).await;
+        self.error_policy.apply(bytes, result)
+    }
+    /// Decodes both the key and the payload of a record, concurrently, the same way as decode.
+    pub async fn decode_message(
+        &self,
+        key: Option<&[u8]>,
+        payload: Option<&[u8]>,
+    ) -> Result<DecodedRecord<DecodeResult>, SRCError> {
+        let (key, value) = try_join(self.decode(key), self.decode(payload)).await?;
+        Ok(DecodedRecord { key, value })
+    }
     /// Decodes bytes into a value.
     /// The choice to use Option<&[u8]> as type us made so it plays nice with the BorrowedMessage
     /// struct from rdkafka, for example if we have m: &'a BorrowedMessage and decoder: &'a
@@ -173,33 +332,87 @@ impl<'a> AvroDecoder<'a> {
     /// }
     /// ```
     pub async fn decode(&self, bytes: Option<&[u8]>) -> Result<DecodeResult, SRCError> {
-        match get_bytes_result(bytes) {
+        self.decode_with_headers(bytes, &[]).await
+    }
+    /// Decodes bytes, using the headers of the record to get the schema id when the id strategy
+    /// has the id in a header.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "decode", level = "debug", skip_all, err)
+    )]
+    pub async fn decode_with_headers(
+        &self,
+        bytes: Option<&[u8]>,
+        headers: &[(&str, &[u8])],
+    ) -> Result<DecodeResult, SRCError> {
+        let result = match self.id_strategy.get_bytes_result(bytes, headers) {
             BytesResult::Null => Ok(DecodeResult {
                 name: None,
                 value: Value::Null,
             }),
-            BytesResult::Valid(id, bytes) => self.deserialize(id, &bytes).await,
-            BytesResult::Invalid(bytes) => Err(SRCError::non_retryable_without_cause(&format!(
-                "Invalid bytes {:?}",
-                bytes
-            ))),
+            BytesResult::Valid(id, bytes) => {
+                let start = decode_start(&self.slow_path);
+                let result = self.deserialize(id, &bytes).await;
+                warn_slow_decode(&self.slow_path, start, id, || {
+                    self.direct_cache.get(&id).and_then(|s| s.subject.clone())
+                });
+                result
+            }
+            BytesResult::Invalid(bytes) => Err(SRCError::from(to_wire_format_error(&bytes))),
+        };
+        if result.is_err() {
+            record_decode_error("avro");
         }
+        result
+    }
+    /// Decodes bytes using the avro single object encoding, instead of the Confluent wire format.
+    /// The schema is found by the fingerprint in the bytes. When the fingerprint is not known yet,
+    /// all the schema's are fetched from the schema registry to find it.
+    pub async fn decode_single_object(
+        &self,
+        bytes: Option<&[u8]>,
+    ) -> Result<DecodeResult, SRCError> {
+        let bytes = match bytes {
+            None => {
+                return Ok(DecodeResult {
+                    name: None,
+                    value: Value::Null,
+                })
+            }
+            Some(v) => v,
+        };
+        let (fingerprint, data) = get_single_object_bytes(bytes)?;
+        let schema = self.get_schema_by_fingerprint(fingerprint).await?;
+        let bytes = decompress(data, self.compression)?;
+        decode_datum(&schema, &bytes, self.field_encryptor.as_deref())
     }
     /// The actual deserialization trying to get the id from the bytes to retrieve the schema, and
     /// using a reader transforms the bytes to a value.
     async fn deserialize(&self, id: u32, bytes: &[u8]) -> Result<DecodeResult, SRCError> {
-        let schema = self.get_schema(id).await?;
-        let mut reader = Cursor::new(bytes);
-        match from_avro_datum(&schema.parsed, &mut reader, None) {
-            Ok(v) => Ok(DecodeResult {
-                name: get_name(&schema.parsed),
-                value: v,
-            }),
-            Err(e) => Err(SRCError::non_retryable_with_cause(
-                e,
-                "Could not transform bytes using schema",
-            )),
+        let result = self.deserialize_with_schema(id, bytes).await?;
+        Ok(DecodeResult {
+            name: result.name,
+            value: result.value,
+        })
+    }
+    /// Decodes multiple payloads, for example all the records from one poll. The schema's for the
+    /// distinct id's are fetched concurrently first, so each schema is only fetched once, after
+    /// which the payloads are decoded concurrently. The results have the same order as the payloads.
+    /// Failing to get a schema is logged as a warning, and gives an error for the payloads with its
+    /// id. Everything runs concurrently on the task calling this, not in parallel, so for large
+    /// batches that take a lot of cpu to decode, spread them over multiple tasks.
+    pub async fn decode_batch(
+        &self,
+        payloads: &[Option<&[u8]>],
+    ) -> Vec<Result<DecodeResult, SRCError>> {
+        let ids = self.id_strategy.get_schema_ids(payloads);
+        let fetched = join_all(ids.iter().map(|id| self.get_schema(*id))).await;
+        for (id, result) in ids.into_iter().zip(fetched) {
+            if let Err(err) = result {
+                warn_batch_fetch_failed(id, &err);
+            }
         }
+        join_all(payloads.iter().map(|p| self.decode(*p))).await
     }
     /// Decodes bytes into a DecodeResultWithSchema.
     /// The choice to use Option<&[u8]> as type us made so it plays nice with the BorrowedMessage
@@ -210,16 +423,75 @@ impl<'a> AvroDecoder<'a> {
         &self,
         bytes: Option<&[u8]>,
     ) -> Result<Option<DecodeResultWithSchema>, SRCError> {
-        match get_bytes_result(bytes) {
+        match self.id_strategy.get_bytes_result(bytes, &[]) {
             BytesResult::Null => Ok(None),
             BytesResult::Valid(id, bytes) => match self.deserialize_with_schema(id, &bytes).await {
                 Ok(v) => Ok(Some(v)),
                 Err(e) => Err(e),
             },
-            BytesResult::Invalid(bytes) => Err(SRCError::non_retryable_without_cause(&format!(
-                "Invalid bytes {:?}",
-                bytes
-            ))),
+            BytesResult::Invalid(bytes) => Err(SRCError::from(to_wire_format_error(&bytes))),
+        }
+    }
+    /// Decodes bytes into a value, together with the id of the schema and the full name of the
+    /// record, so messages can be routed by type without another lookup.
+    pub async fn decode_with_metadata(
+        &self,
+        bytes: Option<&[u8]>,
+    ) -> Result<Option<DecodeResultWithMetadata<Value>>, SRCError> {
+        Ok(self
+            .decode_with_schema(bytes)
+            .await?
+            .map(|r| DecodeResultWithMetadata {
+                metadata: DecodeMetadata {
+                    schema_id: r.schema.id,
+                    full_name: r.name.map(|n| n.fullname(None)),
+                },
+                value: r.value,
+            }))
+    }
+    /// Decodes bytes into a value, resolving the schema the bytes were written with, from the
+    /// schema registry, to the supplied reader schema. Fields not in the reader schema are skipped,
+    /// and fields only in the reader schema get their default value, so the value can be projected
+    /// onto a struct that only knows the reader schema. An error is returned when the schema's are
+    /// not compatible.
+    pub async fn decode_with_reader_schema(
+        &self,
+        bytes: Option<&[u8]>,
+        reader_schema: &Schema,
+    ) -> Result<DecodeResult, SRCError> {
+        match self.id_strategy.get_bytes_result(bytes, &[]) {
+            BytesResult::Null => Ok(DecodeResult {
+                name: None,
+                value: Value::Null,
+            }),
+            BytesResult::Valid(id, bytes) => {
+                let bytes = decompress(&bytes, self.compression)?;
+                decode_with_reader_schema(
+                    &*self.get_schema(id).await?,
+                    &bytes,
+                    reader_schema,
+                    self.field_encryptor.as_deref(),
+                )
+            }
+            BytesResult::Invalid(bytes) => Err(SRCError::from(to_wire_format_error(&bytes))),
+        }
+    }
+    /// Decodes bytes directly into any struct implementing Deserialize, like the structs generated
+    /// from avro schema's. Fixed values are deserialized as bytes, and by default dates and times as
+    /// the number from the logical type. Set an [AvroCodecConfig] with `with_codec_config` to
+    /// deserialize decimals, uuid's, dates and times from strings, into rust_decimal, uuid and
+    /// chrono types.
+    pub async fn decode_as<T: DeserializeOwned>(
+        &self,
+        bytes: Option<&[u8]>,
+    ) -> Result<Option<T>, SRCError> {
+        match self.decode_with_schema(bytes).await? {
+            None => Ok(None),
+            Some(result) => {
+                let value =
+                    to_serde_value(result.value, &result.schema.parsed, &self.codec_config)?;
+                Ok(Some(from_avro_value(&value)?))
+            }
         }
     }
     /// The actual deserialization trying to get the id from the bytes to retrieve the schema, and
@@ -229,32 +501,137 @@ impl<'a> AvroDecoder<'a> {
         id: u32,
         bytes: &[u8],
     ) -> Result<DecodeResultWithSchema, SRCError> {
+        let bytes: &[u8] = &decompress(bytes, self.compression)?;
         let schema = self.get_schema(id).await?;
         let mut reader = Cursor::new(bytes);
-        match from_avro_datum(&schema.parsed, &mut reader, None) {
-            Ok(value) => Ok(DecodeResultWithSchema {
-                name: get_name(&schema.parsed),
-                value,
-                schema,
-            }),
-            Err(e) => Err(SRCError::non_retryable_with_cause(
-                e,
-                "Could not transform bytes using schema",
-            )),
+        let value = match from_avro_datum(&schema.parsed, &mut reader, None) {
+            Ok(value) => {
+                check_remaining_bytes(bytes.len() - reader.position() as usize, self.strictness)?;
+                self.decrypt(&schema, value)?
+            }
+            Err(e) => {
+                return Err(SRCError::non_retryable_with_cause(
+                    e,
+                    "Could not transform bytes using schema",
+                ))
+            }
+        };
+        let writer_schema = schema.clone();
+        let (schema, value) = self.migrate(id, schema, value).await?;
+        let (schema, value) = match &self.reader_schema {
+            Some(reader_schema) => reader_schema.read(&schema, value)?,
+            None => (schema, value),
+        };
+        Ok(DecodeResultWithSchema {
+            name: get_name(&schema.parsed),
+            value,
+            schema,
+            writer_schema,
+        })
+    }
+
+    /// Gives the schema to read the value as, with the value migrated to it when a migration is
+    /// set.
+    async fn migrate(
+        &self,
+        id: u32,
+        schema: Arc<AvroSchema>,
+        value: Value,
+    ) -> Result<(Arc<AvroSchema>, Value), SRCError> {
+        let config = match &self.migration {
+            Some(v) => v,
+            None => return Ok((schema, value)),
+        };
+        let cached = self.migrations.get(&id).map(|m| m.value().clone());
+        let migration = match cached {
+            Some(v) => v,
+            None => {
+                let migration = Arc::new(get_migration(&self.sr_settings, config, id).await?);
+                self.migrations.insert(id, migration.clone());
+                migration
+            }
+        };
+        if migration.reader_id == id {
+            return Ok((schema, value));
         }
+        let reader_schema = self.get_schema(migration.reader_id).await?;
+        let value = migrate_value(value, &reader_schema, &migration.rules, &*config.executor)?;
+        Ok((reader_schema, value))
+    }
+
+    fn decrypt(&self, schema: &AvroSchema, value: Value) -> Result<Value, SRCError> {
+        apply_field_encryption(
+            schema,
+            self.field_encryptor.as_deref(),
+            value,
+            Direction::Read,
+        )
     }
 
     async fn get_schema(&self, id: u32) -> Result<Arc<AvroSchema>, SRCError> {
+        check_policy(self.policy.as_deref(), id)?;
         match self.direct_cache.get(&id) {
             None => {
+                record_cache_lookup(id, false);
+                warn_registry_fetch(&self.slow_path, id);
                 let result = self.get_schema_by_shared_future(id).await;
-                if result.is_ok() && !self.direct_cache.contains_key(&id) {
-                    self.direct_cache.insert(id, result.clone().unwrap());
-                    self.cache.remove(&id);
+                if let Ok(schema) = &result {
+                    if let Entry::Vacant(e) = self.direct_cache.entry(id) {
+                        e.insert(schema.clone());
+                        self.cache.remove(&id);
+                        self.cached_schemas.added();
+                        self.sr_settings
+                            .record_usage(SchemaUsageKind::Decoded, id, None);
+                    }
                 };
+                if matches!(&result, Err(e) if e.is_throttled()) {
+                    self.cache.remove(&id);
+                }
                 result
             }
-            Some(result) => Ok(result.value().clone()),
+            Some(result) => {
+                record_cache_lookup(id, true);
+                Ok(result.value().clone())
+            }
+        }
+    }
+
+    async fn get_schema_by_fingerprint(
+        &self,
+        fingerprint: u64,
+    ) -> Result<Arc<AvroSchema>, SRCError> {
+        if let Some(schema) = self.fingerprints.get(&fingerprint) {
+            return Ok(schema.value().clone());
+        }
+        if self.fingerprint_lookups.is_missing(fingerprint) {
+            return Err(missing_fingerprint_error(fingerprint));
+        }
+        // Only one lookup of all the schema's at a time, the others wait for it and use its result.
+        let _lookup = self.fingerprint_lookups.lock.lock().await;
+        if let Some(schema) = self.fingerprints.get(&fingerprint) {
+            return Ok(schema.value().clone());
+        }
+        if self.fingerprint_lookups.is_missing(fingerprint) {
+            return Err(missing_fingerprint_error(fingerprint));
+        }
+        let mut ids = HashSet::new();
+        for registered_schema in get_all_schemas(&self.sr_settings).await? {
+            if registered_schema.schema_type != SchemaType::Avro
+                || !ids.insert(registered_schema.id)
+            {
+                continue;
+            }
+            if let Ok(schema) = to_avro_schema(&self.sr_settings, registered_schema).await {
+                self.fingerprints
+                    .insert(get_fingerprint(&schema.parsed), schema);
+            }
+        }
+        match self.fingerprints.get(&fingerprint) {
+            Some(schema) => Ok(schema.value().clone()),
+            None => {
+                self.fingerprint_lookups.set_missing(fingerprint);
+                Err(missing_fingerprint_error(fingerprint))
+            }
         }
     }
 
@@ -263,15 +640,18 @@ impl<'a> AvroDecoder<'a> {
             Entry::Occupied(e) => e.get().clone(),
             Entry::Vacant(e) => {
                 let sr_settings = self.sr_settings.clone();
+                let policy = self.policy.clone();
                 let v = async move {
-                    match get_schema_by_id_and_type(id, &sr_settings, SchemaType::Avro).await {
+                    match get_allowed_schema(id, &sr_settings, SchemaType::Avro, policy.as_deref())
+                        .await
+                    {
                         Ok(registered_schema) => {
                             to_avro_schema(&sr_settings, registered_schema).await
                         }
                         Err(e) => Err(e.into_cache()),
                     }
                 }
-                .boxed()
+                .sr_boxed()
                 .shared();
                 e.insert(v).value().clone()
             }
@@ -333,6 +713,12 @@ pub struct AvroEncoder<'a> {
     sr_settings: SrSettings,
     direct_cache: DashMap<String, Arc<AvroSchema>>,
     cache: DashMap<String, SharedFutureSchema<'a>>,
+    codec_config: AvroCodecConfig,
+    framing: AvroFraming,
+    id_strategy: IdStrategy,
+    compression: AvroCompression,
+    local_schemas: LocalSchemaProvider,
+    field_encryptor: Option<Arc<dyn FieldEncryptor>>,
 }
 
 impl<'a> AvroEncoder<'a> {
@@ -378,8 +764,53 @@ impl<'a> AvroEncoder<'a> {
             sr_settings,
             direct_cache: DashMap::new(),
             cache: DashMap::new(),
+            codec_config: AvroCodecConfig::default(),
+            framing: AvroFraming::default(),
+            id_strategy: IdStrategy::default(),
+            compression: AvroCompression::default(),
+            local_schemas: LocalSchemaProvider::default(),
+            field_encryptor: None,
         }
     }
+    /// Sets how logical types are converted by encode_struct.
+    pub fn with_codec_config(mut self, codec_config: AvroCodecConfig) -> AvroEncoder<'a> {
+        self.codec_config = codec_config;
+        self
+    }
+    /// Sets the framing of the encoded bytes, by default the Confluent wire format is used. With
+    /// the single object encoding the bytes can be decoded without the schema id, for example by
+    /// systems not using the schema registry.
+    pub fn with_framing(mut self, framing: AvroFraming) -> AvroEncoder<'a> {
+        self.framing = framing;
+        self
+    }
+    /// Sets how the schema id is added with the Confluent framing, by default as 4 bytes. With the
+    /// id in a header, only the avro bytes are returned, and the id can be found with
+    /// get_schema_and_id.
+    pub fn with_id_strategy(mut self, id_strategy: IdStrategy) -> AvroEncoder<'a> {
+        self.id_strategy = id_strategy;
+        self
+    }
+    /// Sets the compression of the avro bytes, for large values. Consumers need to use the same
+    /// compression to decode the bytes.
+    pub fn with_compression(mut self, compression: AvroCompression) -> AvroEncoder<'a> {
+        self.compression = compression;
+        self
+    }
+    /// Sets the schema's available locally, which are used instead of the schema from the schema
+    /// registry when the subject matches.
+    pub fn with_local_schemas(mut self, local_schemas: LocalSchemaProvider) -> AvroEncoder<'a> {
+        self.local_schemas = local_schemas;
+        self
+    }
+    /// Sets the encryptor used to encrypt the fields tagged by an ENCRYPT rule of the schema.
+    pub fn with_field_encryptor(
+        mut self,
+        field_encryptor: Arc<dyn FieldEncryptor>,
+    ) -> AvroEncoder<'a> {
+        self.field_encryptor = Some(field_encryptor);
+        self
+    }
     /// Remove al the errors from the cache, you might need to/want to run this when a recoverable
     /// error is met. Errors are also cashed to prevent trying to get schema's that either don't
     /// exist or can't be parsed.
@@ -429,6 +860,13 @@ impl<'a> AvroEncoder<'a> {
             None => true,
         });
     }
+    /// Removes the schema of the subject from the cache, so the latest version is fetched again on
+    /// the next encode. Long-lived producers can call this when they are signaled a new version was
+    /// registered, to pick it up without a restart.
+    pub fn invalidate_subject(&self, subject: &str) {
+        self.direct_cache.remove(subject);
+        self.cache.remove(subject);
+    }
     /// Encodes a vector of values to bytes. The correct values of the 'keys' depend on the schema
     /// being fetched at runtime, or the one supplied with the SubjectNameStrategy.
     ///
@@ -461,19 +899,25 @@ impl<'a> AvroEncoder<'a> {
     pub async fn encode<'k>(
         &self,
         values: Vec<(&'k str, Value)>,
-        subject_name_strategy: SubjectNameStrategy,
+        subject_name_strategy: impl Into<SubjectNameStrategy>,
     ) -> Result<Vec<u8>, SRCError> {
+        let subject_name_strategy = subject_name_strategy.into();
         let key = subject_name_strategy.get_subject()?;
-        let schema = self
-            .get_schema_and_id_by_shared_future(key, subject_name_strategy)
-            .clone()
-            .await?;
-        values_to_bytes(&schema, values)
+        let schema = self.get_schema_and_id(&key, subject_name_strategy).await?;
+        values_to_bytes(
+            &schema,
+            values,
+            self.field_encryptor.as_deref(),
+            self.framing,
+            &self.id_strategy,
+            self.compression,
+        )
     }
 
     /// Encodes a struct or a primitive value to bytes. The schema used for the encoding will be
     /// retrieved from the schema registry, or it will use the one supplied with the
-    /// SubjectNameStrategy.
+    /// SubjectNameStrategy. The fields of the struct are matched to the schema by name, and fields
+    /// missing from the struct get the default from the schema.
     ///
     /// The function get_supplied_schema might be used to easily provide the schema in the correct
     /// form.
@@ -523,13 +967,136 @@ impl<'a> AvroEncoder<'a> {
     pub async fn encode_struct(
         &self,
         item: impl Serialize,
-        subject_name_strategy: &SubjectNameStrategy,
+        subject_name_strategy: impl Into<SubjectNameStrategy>,
     ) -> Result<Vec<u8>, SRCError> {
+        let subject_name_strategy = subject_name_strategy.into();
+        let key = subject_name_strategy.get_subject()?;
+        let schema = self.get_schema_and_id(&key, subject_name_strategy).await?;
+        item_to_bytes(
+            &schema,
+            item,
+            &self.codec_config,
+            self.field_encryptor.as_deref(),
+            self.framing,
+            &self.id_strategy,
+            self.compression,
+        )
+    }
+
+    /// Encodes multiple structs for the same subject, getting the schema only once, which saves the
+    /// cache lookups when producing a batch of records. Stops at, and returns, the first error.
+    pub async fn encode_many<T: Serialize>(
+        &self,
+        items: &[T],
+        subject_name_strategy: impl Into<SubjectNameStrategy>,
+    ) -> Result<Vec<Vec<u8>>, SRCError> {
+        let subject_name_strategy = subject_name_strategy.into();
+        let key = subject_name_strategy.get_subject()?;
+        let schema = self.get_schema_and_id(&key, subject_name_strategy).await?;
+        items
+            .iter()
+            .map(|item| {
+                item_to_bytes(
+                    &schema,
+                    item,
+                    &self.codec_config,
+                    self.field_encryptor.as_deref(),
+                    self.framing,
+                    &self.id_strategy,
+                    self.compression,
+                )
+            })
+            .collect()
+    }
+
+    /// Like `encode`, but writing the bytes to the buffer, which is cleared first. This way the
+    /// buffer, and its allocation, can be reused for multiple records.
+    pub async fn encode_into<'k>(
+        &self,
+        values: Vec<(&'k str, Value)>,
+        subject_name_strategy: impl Into<SubjectNameStrategy>,
+        buf: &mut Vec<u8>,
+    ) -> Result<(), SRCError> {
+        let subject_name_strategy = subject_name_strategy.into();
+        let key = subject_name_strategy.get_subject()?;
+        let schema = self.get_schema_and_id(&key, subject_name_strategy).await?;
+        let record = values_to_encrypted_record(&schema, values, self.field_encryptor.as_deref())?;
+        write_bytes(
+            &schema.parsed,
+            schema.id,
+            record,
+            self.framing,
+            &self.id_strategy,
+            self.compression,
+            buf,
+        )
+    }
+
+    /// Like `encode_struct`, but writing the bytes to the buffer, which is cleared first. This way
+    /// the buffer, and its allocation, can be reused for multiple records.
+    pub async fn encode_struct_into(
+        &self,
+        item: impl Serialize,
+        subject_name_strategy: impl Into<SubjectNameStrategy>,
+        buf: &mut Vec<u8>,
+    ) -> Result<(), SRCError> {
+        let subject_name_strategy = subject_name_strategy.into();
         let key = subject_name_strategy.get_subject()?;
-        let schema = self
-            .get_schema_and_id(&key, subject_name_strategy.clone())
-            .await?;
-        item_to_bytes(&schema, item)
+        let schema = self.get_schema_and_id(&key, subject_name_strategy).await?;
+        let value = item_to_value(
+            &schema,
+            item,
+            &self.codec_config,
+            self.field_encryptor.as_deref(),
+        )?;
+        write_bytes(
+            &schema.parsed,
+            schema.id,
+            value,
+            self.framing,
+            &self.id_strategy,
+            self.compression,
+            buf,
+        )
+    }
+
+    /// Encodes a struct deriving `SrAvroSerialize`, using the schema from the .avsc file and the
+    /// subject set with the attributes of the struct.
+    #[cfg(feature = "derive")]
+    pub async fn encode_derived<T: SrAvroSerialize>(&self, item: &T) -> Result<Vec<u8>, SRCError> {
+        let values = item.to_avro_values()?;
+        self.encode(values, T::subject_name_strategy()?).await
+    }
+
+    /// Encodes a vector of values to bytes using a schema known locally, together with the id it's
+    /// registered with, for example from an .avsc file embedded at build time. The schema registry
+    /// is not called, so it's up to the caller to make sure the id is correct.
+    /// ```
+    /// use apache_avro::types::Value;
+    /// use apache_avro::Schema;
+    /// use schema_registry_converter::async_impl::avro::AvroEncoder;
+    /// use schema_registry_converter::async_impl::schema_registry::SrSettings;
+    ///
+    /// let schema = Schema::parse_str(r#"{"type":"record","name":"Heartbeat","namespace":"nl.openweb.data","fields":[{"name":"beat","type":"long"}]}"#).unwrap();
+    /// let encoder = AvroEncoder::new(SrSettings::new(String::from("http://127.0.0.1:1234")));
+    /// let bytes = encoder.encode_with_local_schema(vec![("beat", Value::Long(3))], &schema, 3);
+    ///
+    /// assert_eq!(bytes, Ok(vec![0, 0, 0, 0, 3, 6]));
+    /// ```
+    pub fn encode_with_local_schema(
+        &self,
+        values: Vec<(&str, Value)>,
+        schema: &Schema,
+        id: u32,
+    ) -> Result<Vec<u8>, SRCError> {
+        values_to_bytes_with_schema(
+            schema,
+            id,
+            values,
+            self.framing,
+            &self.id_strategy,
+            self.compression,
+        )
     }
 
     pub async fn get_schema_and_id(
@@ -542,11 +1109,20 @@ impl<'a> AvroEncoder<'a> {
                 let result = self
                     .get_schema_and_id_by_shared_future(key.to_string(), subject_name_strategy)
                     .await;
-                if result.is_ok() && !self.direct_cache.contains_key(key) {
-                    self.direct_cache
-                        .insert(key.to_string(), result.clone().unwrap());
-                    self.cache.remove(key);
+                if let Ok(schema) = &result {
+                    if let Entry::Vacant(e) = self.direct_cache.entry(key.to_string()) {
+                        e.insert(schema.clone());
+                        self.cache.remove(key);
+                        self.sr_settings.record_usage(
+                            SchemaUsageKind::Encoded,
+                            schema.id,
+                            Some(key),
+                        );
+                    }
                 };
+                if matches!(&result, Err(e) if e.is_throttled()) {
+                    self.cache.remove(key);
+                }
                 result
             }
             Some(result) => Ok(result.value().clone()),
@@ -562,15 +1138,27 @@ impl<'a> AvroEncoder<'a> {
             Entry::Occupied(e) => e.get().clone(),
             Entry::Vacant(e) => {
                 let sr_settings = self.sr_settings.clone();
+                let subject = e.key().clone();
+                let local_schema = self.local_schemas.get(&subject);
                 let v = async move {
-                    match get_schema_by_subject(&sr_settings, &subject_name_strategy).await {
-                        Ok(registered_schema) => {
-                            to_avro_schema(&sr_settings, registered_schema).await
+                    match local_schema {
+                        Some(LocalSchema::WithId(avro_schema)) => Ok(avro_schema),
+                        Some(LocalSchema::WithoutId(raw)) => {
+                            lookup_local_schema(&sr_settings, &subject, raw).await
                         }
-                        Err(e) => Err(e.into_cache()),
+                        None => match get_schema_by_subject(&sr_settings, &subject_name_strategy)
+                            .await
+                        {
+                            Ok(registered_schema) => {
+                                to_avro_schema(&sr_settings, registered_schema)
+                                    .await
+                                    .and_then(|s| check_record_name(s, &subject_name_strategy))
+                            }
+                            Err(e) => Err(e.into_cache()),
+                        },
                     }
                 }
-                .boxed()
+                .sr_boxed()
                 .shared();
                 e.insert(v).value().clone()
             }
@@ -578,6 +1166,25 @@ impl<'a> AvroEncoder<'a> {
     }
 }
 
+async fn lookup_local_schema(
+    sr_settings: &SrSettings,
+    subject: &str,
+    raw: String,
+) -> Result<Arc<AvroSchema>, SRCError> {
+    let parsed = parse_local_schema(&raw)?;
+    match lookup_schema(sr_settings, subject, &SchemaType::Avro, &raw).await {
+        Ok(registered_schema) => Ok(Arc::new(AvroSchema {
+            id: registered_schema.id,
+            raw,
+            parsed,
+            rule_set: registered_schema.rule_set,
+            metadata: registered_schema.metadata,
+            subject: Some(String::from(subject)),
+        })),
+        Err(e) => Err(e.into_cache()),
+    }
+}
+
 async fn to_avro_schema(
     sr_settings: &SrSettings,
     registered_schema: RegisteredSchema,
@@ -593,7 +1200,8 @@ async fn to_avro_schema(
     }
     let main_schema = match serde_json::from_str(&registered_schema.schema) {
         Ok(v) => {
-            match add_references(sr_settings, v, registered_schema.references.as_slice()).await {
+            let sr_settings = sr_settings.counting_references();
+            match add_references(&sr_settings, v, registered_schema.references.as_slice()).await {
                 Ok(u) => u,
                 Err(e) => return Err(e),
             }
@@ -610,6 +1218,9 @@ async fn to_avro_schema(
             id: registered_schema.id,
             raw: registered_schema.schema,
             parsed,
+            rule_set: registered_schema.rule_set,
+            metadata: registered_schema.metadata,
+            subject: registered_schema.subject,
         })),
         Err(e) => Err(SRCError::non_retryable_with_cause(
             e,
@@ -625,12 +1236,16 @@ fn add_references<'a>(
     sr_settings: &'a SrSettings,
     json_value: value::Value,
     references: &'a [RegisteredReference],
-) -> BoxFuture<'a, Result<value::Value, SRCError>> {
+) -> SrFuture<'a, Result<value::Value, SRCError>> {
     async move {
         let mut new_value = json_value;
         for r in references.iter() {
+            if contains_definition(&new_value, &r.name) {
+                continue;
+            }
             let registered_schema = match get_referenced_schema(sr_settings, r).await {
                 Ok(v) => v,
+                Err(e) if e.is_throttled() => return Err(e),
                 Err(e) => {
                     return Err(SRCError::non_retryable_with_cause(
                         e,
@@ -656,16 +1271,20 @@ fn add_references<'a>(
         }
         Ok(new_value)
     }
-    .boxed()
+    .sr_boxed()
 }
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
+
     use apache_avro::from_value;
     use mockito::Server;
 
     use crate::avro_common::get_supplied_schema;
-    use crate::schema_registry_common::SuppliedSchema;
+    use crate::error::{PolicyViolation, WireFormatError};
+    use crate::migration::RuleExecutor;
+    use crate::schema_registry_common::{Rule, SchemaIdPolicy, SchemaUsage, SuppliedSchema};
 
     use super::*;
     use test_utils::Heartbeat;
@@ -675,7 +1294,7 @@ mod tests {
         let sr_settings = SrSettings::new(String::from("http://127.0.0.1:1234"));
         let decoder = AvroDecoder::new(sr_settings);
         assert_eq!(
-            "AvroDecoder { sr_settings: SrSettings { urls: [\"http://127.0.0.1:1234\"], client: Client { accepts: Accepts, proxies: [Proxy(System({}), None)], referer: true, default_headers: {\"accept\": \"*/*\"} }, authorization: None }, direct_cache: {}, cache: {} }"
+            "AvroDecoder { sr_settings: SrSettings { urls: [\"http://127.0.0.1:1234\"], client: Client { accepts: Accepts, proxies: [Proxy(System({}), None)], referer: true, default_headers: {\"accept\": \"*/*\"} }, authorization: None, dialect: Confluent, fetch_deleted: true, normalize: false, headers: {}, usage_listener: None, fallback: None, limits: SchemaLimits { max_schema_bytes: None, max_references: None, max_total_references: None, max_depth: None }, references_fetched: None }, direct_cache: {}, cache: {}, fingerprints: {}, fingerprint_lookups: FingerprintLookups { missing: {} }, codec_config: AvroCodecConfig { decimal_as_string: false, uuid_as_string: false, temporal_as_string: false }, compression: None, id_strategy: Confluent, strictness: Lenient, error_policy: FailFast, field_encryptor: None, migration: None, migrations: {}, reader_schema: None, slow_path: SlowPathWarnings { decode_threshold: None, registry_fetch: false }, policy: None, cached_schemas: CachedSchemas { schema_type: \"avro\", count: 0 } }"
                 .to_owned(),
             format!("{:?}", decoder)
         )
@@ -711,134 +1330,382 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_decoder_with_name() {
+    async fn test_decoder_policy() {
         let mut server = Server::new_async().await;
         let _m = server.mock("GET", "/schemas/ids/1?deleted=true")
             .with_status(200)
             .with_header("content-type", "application/vnd.schemaregistry.v1+json")
             .with_body(r#"{"schema":"{\"type\":\"record\",\"name\":\"Heartbeat\",\"namespace\":\"nl.openweb.data\",\"fields\":[{\"name\":\"beat\",\"type\":\"long\"}]}"}"#)
+            .expect(0)
             .create();
 
-        let sr_settings = SrSettings::new(server.url());
-        let decoder = AvroDecoder::new(sr_settings);
-        let heartbeat = decoder.decode(Some(&[0, 0, 0, 0, 1, 6])).await;
-        let item = match heartbeat {
-            Ok(r) => {
-                let name = r.name.unwrap();
-                assert_eq!(name.name.as_str(), "Heartbeat");
-                assert_eq!(name.namespace.unwrap().as_str(), "nl.openweb.data");
-                from_value::<Heartbeat>(&r.value).unwrap()
-            }
-            _ => panic!(),
-        };
-        assert_eq!(item.beat, 3i64);
-    }
-
-    #[tokio::test]
-    async fn test_decoder_no_bytes() {
-        let sr_settings = SrSettings::new(String::from("http://127.0.0.1:1234"));
-        let decoder = AvroDecoder::new(sr_settings);
-        let heartbeat = decoder.decode(None).await.unwrap().value;
+        let decoder = AvroDecoder::new(SrSettings::new(server.url()))
+            .with_policy(Arc::new(SchemaIdPolicy::Deny(HashSet::from([1]))));
+        let error = decoder.decode(Some(&[0, 0, 0, 0, 1, 6])).await.unwrap_err();
 
-        assert_eq!(heartbeat, Value::Null)
+        _m.assert();
+        assert_eq!(error.policy_violation(), Some(&PolicyViolation { id: 1 }));
+        assert!(!error.cached);
     }
 
     #[tokio::test]
-    async fn test_decoder_with_name_no_bytes() {
+    async fn test_decode_or_skip() {
         let sr_settings = SrSettings::new(String::from("http://127.0.0.1:1234"));
-        let decoder = AvroDecoder::new(sr_settings);
-        let heartbeat = decoder.decode(None).await.unwrap();
+        let decoder = AvroDecoder::new(sr_settings.clone());
+        assert!(decoder.decode_or_skip(Some(&[1, 2, 3])).await.is_err());
 
-        assert_eq!(
-            heartbeat,
-            DecodeResult {
-                name: None,
-                value: Value::Null
-            }
-        )
+        let decoder =
+            AvroDecoder::new(sr_settings).with_error_policy(DeserializationErrorPolicy::SkipAndLog);
+        let result = decoder.decode_or_skip(Some(&[1, 2, 3])).await.unwrap();
+        assert!(result.is_none());
     }
 
     #[tokio::test]
-    async fn test_decoder_magic_byte_not_present() {
-        let sr_settings = SrSettings::new(String::from("http://127.0.0.1:1234"));
-        let decoder = AvroDecoder::new(sr_settings);
-        let result = decoder.decode(Some(&[1, 0, 0, 0, 1, 6])).await.unwrap_err();
-
-        assert_eq!(
-            result,
-            SRCError::non_retryable_without_cause("Invalid bytes [1, 0, 0, 0, 1, 6]")
-        )
-    }
+    async fn test_decode_batch() {
+        let mut server = Server::new_async().await;
+        let _m = server.mock("GET", "/schemas/ids/1?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"schema":"{\"type\":\"record\",\"name\":\"Heartbeat\",\"namespace\":\"nl.openweb.data\",\"fields\":[{\"name\":\"beat\",\"type\":\"long\"}]}"}"#)
+            .expect(1)
+            .create();
 
-    #[tokio::test]
-    async fn test_decoder_with_name_magic_byte_not_present() {
-        let sr_settings = SrSettings::new(String::from("http://127.0.0.1:1234"));
+        let sr_settings = SrSettings::new(server.url());
         let decoder = AvroDecoder::new(sr_settings);
-        let result = decoder.decode(Some(&[1, 0, 0, 0, 1, 6])).await.unwrap_err();
+        let payloads: [Option<&[u8]>; 3] = [
+            Some(&[0, 0, 0, 0, 1, 6]),
+            Some(&[0, 0, 0, 0, 1, 8]),
+            Some(&[1, 0, 0, 0, 1, 6]),
+        ];
+        let results = decoder.decode_batch(&payloads).await;
 
+        _m.assert();
+        assert_eq!(results.len(), 3);
         assert_eq!(
-            result,
-            SRCError::non_retryable_without_cause("Invalid bytes [1, 0, 0, 0, 1, 6]")
-        )
-    }
-
-    #[tokio::test]
-    async fn test_decoder_not_enough_bytes() {
-        let sr_settings = SrSettings::new(String::from("http://127.0.0.1:1234"));
-        let decoder = AvroDecoder::new(sr_settings);
-        let result = decoder.decode(Some(&[0, 0, 0, 0])).await.unwrap_err();
-
+            results[0].as_ref().unwrap().value,
+            Value::Record(vec![("beat".to_string(), Value::Long(3))])
+        );
         assert_eq!(
-            result,
-            SRCError::non_retryable_without_cause("Invalid bytes [0, 0, 0, 0]")
-        )
+            results[1].as_ref().unwrap().value,
+            Value::Record(vec![("beat".to_string(), Value::Long(4))])
+        );
+        assert!(results[2].is_err())
     }
 
     #[tokio::test]
-    async fn test_decoder_wrong_data() {
+    async fn test_decode_batch_failing_schema() {
         let mut server = Server::new_async().await;
         let _m = server.mock("GET", "/schemas/ids/1?deleted=true")
             .with_status(200)
             .with_header("content-type", "application/vnd.schemaregistry.v1+json")
             .with_body(r#"{"schema":"{\"type\":\"record\",\"name\":\"Heartbeat\",\"namespace\":\"nl.openweb.data\",\"fields\":[{\"name\":\"beat\",\"type\":\"long\"}]}"}"#)
+            .expect(1)
+            .create();
+        let _n = server
+            .mock("GET", "/schemas/ids/2?deleted=true")
+            .with_status(404)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"error_code":40403,"message":"Schema not found"}"#)
+            .expect(1)
             .create();
 
         let sr_settings = SrSettings::new(server.url());
         let decoder = AvroDecoder::new(sr_settings);
-        let error = decoder.decode(Some(&[0, 0, 0, 0, 1])).await.unwrap_err();
+        let payloads: [Option<&[u8]>; 3] = [
+            Some(&[0, 0, 0, 0, 2, 6]),
+            Some(&[0, 0, 0, 0, 1, 8]),
+            Some(&[0, 0, 0, 0, 2, 8]),
+        ];
+        let results = decoder.decode_batch(&payloads).await;
 
-        assert_eq!(error.error, "Could not transform bytes using schema")
+        _m.assert();
+        _n.assert();
+        assert!(results[0].is_err());
+        assert_eq!(
+            results[1].as_ref().unwrap().value,
+            Value::Record(vec![("beat".to_string(), Value::Long(4))])
+        );
+        assert!(results[2].is_err())
     }
 
     #[tokio::test]
-    async fn test_decoder_with_name_wrong_data() {
+    async fn test_long_and_header_id() {
         let mut server = Server::new_async().await;
         let _m = server.mock("GET", "/schemas/ids/1?deleted=true")
             .with_status(200)
             .with_header("content-type", "application/vnd.schemaregistry.v1+json")
             .with_body(r#"{"schema":"{\"type\":\"record\",\"name\":\"Heartbeat\",\"namespace\":\"nl.openweb.data\",\"fields\":[{\"name\":\"beat\",\"type\":\"long\"}]}"}"#)
+            .expect(1)
             .create();
 
         let sr_settings = SrSettings::new(server.url());
-        let decoder = AvroDecoder::new(sr_settings);
-        let error = decoder.decode(Some(&[0, 0, 0, 0, 1])).await.unwrap_err();
+        let schema = Schema::parse_str(r#"{"type":"record","name":"Heartbeat","namespace":"nl.openweb.data","fields":[{"name":"beat","type":"long"}]}"#).unwrap();
+        let encoder = AvroEncoder::new(sr_settings.clone()).with_id_strategy(IdStrategy::Long);
+        let bytes = encoder
+            .encode_with_local_schema(vec![("beat", Value::Long(3))], &schema, 1)
+            .unwrap();
+        assert_eq!(bytes, vec![0, 0, 0, 0, 0, 0, 0, 0, 1, 6]);
 
-        assert_eq!(error.error, "Could not transform bytes using schema")
+        let decoder = AvroDecoder::new(sr_settings).with_id_strategy(IdStrategy::Long);
+        let result = decoder.decode(Some(&bytes)).await.unwrap();
+        assert_eq!(
+            result.value,
+            Value::Record(vec![("beat".to_string(), Value::Long(3))])
+        );
+
+        let extractor = |headers: &[(&str, &[u8])]| {
+            headers
+                .iter()
+                .find(|(k, _)| *k == "schema_id")
+                .and_then(|(_, v)| std::str::from_utf8(v).ok()?.parse::<u32>().ok())
+        };
+        let decoder = decoder.with_id_strategy(IdStrategy::Header(Arc::new(extractor)));
+        let id: &[u8] = b"1";
+        let result = decoder
+            .decode_with_headers(Some(&[6]), &[("schema_id", id)])
+            .await
+            .unwrap();
+        assert_eq!(
+            result.value,
+            Value::Record(vec![("beat".to_string(), Value::Long(3))])
+        );
+        assert!(decoder.decode(Some(&[6])).await.is_err());
+        _m.assert();
     }
 
     #[tokio::test]
-    async fn test_decoder_no_json_response() {
+    async fn test_decoder_with_name() {
         let mut server = Server::new_async().await;
         let _m = server.mock("GET", "/schemas/ids/1?deleted=true")
             .with_status(200)
             .with_header("content-type", "application/vnd.schemaregistry.v1+json")
-            .with_body(r#"{"type\":\"record\",\"name\":\"Heartbeat\",\"namespace\":\"nl.openweb.data\",\"fields\":[{\"name\":\"beat\",\"type\":\"long\"}]}"}"#)
+            .with_body(r#"{"schema":"{\"type\":\"record\",\"name\":\"Heartbeat\",\"namespace\":\"nl.openweb.data\",\"fields\":[{\"name\":\"beat\",\"type\":\"long\"}]}"}"#)
             .create();
 
         let sr_settings = SrSettings::new(server.url());
         let decoder = AvroDecoder::new(sr_settings);
-        let heartbeat = decoder.decode(Some(&[0, 0, 0, 0, 1, 6])).await.unwrap_err();
-
+        let heartbeat = decoder.decode(Some(&[0, 0, 0, 0, 1, 6])).await;
+        let item = match heartbeat {
+            Ok(r) => {
+                let name = r.name.unwrap();
+                assert_eq!(name.name.as_str(), "Heartbeat");
+                assert_eq!(name.namespace.unwrap().as_str(), "nl.openweb.data");
+                from_value::<Heartbeat>(&r.value).unwrap()
+            }
+            _ => panic!(),
+        };
+        assert_eq!(item.beat, 3i64);
+    }
+
+    #[tokio::test]
+    async fn test_decode_with_metadata() {
+        let mut server = Server::new_async().await;
+        let _m = server.mock("GET", "/schemas/ids/1?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"schema":"{\"type\":\"record\",\"name\":\"Heartbeat\",\"namespace\":\"nl.openweb.data\",\"fields\":[{\"name\":\"beat\",\"type\":\"long\"}]}"}"#)
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let decoder = AvroDecoder::new(sr_settings);
+        let result = decoder
+            .decode_with_metadata(Some(&[0, 0, 0, 0, 1, 6]))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(result.metadata.schema_id, 1);
+        assert_eq!(
+            result.metadata.full_name,
+            Some(String::from("nl.openweb.data.Heartbeat"))
+        );
+        assert_eq!(from_value::<Heartbeat>(&result.value).unwrap().beat, 3i64);
+        assert!(decoder.decode_with_metadata(None).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_decode_with_reader_schema() {
+        let mut server = Server::new_async().await;
+        let _m = server.mock("GET", "/schemas/ids/1?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"schema":"{\"type\":\"record\",\"name\":\"Heartbeat\",\"namespace\":\"nl.openweb.data\",\"fields\":[{\"name\":\"beat\",\"type\":\"long\"}]}"}"#)
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let decoder = AvroDecoder::new(sr_settings);
+        let reader_schema = Schema::parse_str(r#"{"type":"record","name":"Heartbeat","namespace":"nl.openweb.data","fields":[{"name":"beat","type":"long"},{"name":"source","type":"string","default":"unknown"}]}"#).unwrap();
+        let result = decoder
+            .decode_with_reader_schema(Some(&[0, 0, 0, 0, 1, 6]), &reader_schema)
+            .await
+            .unwrap();
+
+        assert_eq!(result.name.unwrap().name.as_str(), "Heartbeat");
+        assert_eq!(
+            result.value,
+            Value::Record(vec![
+                (String::from("beat"), Value::Long(3)),
+                (
+                    String::from("source"),
+                    Value::String(String::from("unknown"))
+                ),
+            ])
+        );
+
+        let incompatible = Schema::parse_str(r#"{"type":"record","name":"Heartbeat","namespace":"nl.openweb.data","fields":[{"name":"source","type":"string"}]}"#).unwrap();
+        let error = decoder
+            .decode_with_reader_schema(Some(&[0, 0, 0, 0, 1, 6]), &incompatible)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.error,
+            "Could not transform bytes using writer and reader schema"
+        )
+    }
+
+    #[tokio::test]
+    async fn test_decode_as() {
+        let mut server = Server::new_async().await;
+        let _m = server.mock("GET", "/schemas/ids/1?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"schema":"{\"type\":\"record\",\"name\":\"Heartbeat\",\"namespace\":\"nl.openweb.data\",\"fields\":[{\"name\":\"beat\",\"type\":\"long\"}]}"}"#)
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let decoder = AvroDecoder::new(sr_settings);
+        let heartbeat = decoder
+            .decode_as::<Heartbeat>(Some(&[0, 0, 0, 0, 1, 6]))
+            .await
+            .unwrap();
+
+        assert_eq!(heartbeat.unwrap().beat, 3i64);
+        assert!(decoder
+            .decode_as::<Heartbeat>(None)
+            .await
+            .unwrap()
+            .is_none())
+    }
+
+    #[tokio::test]
+    async fn test_decoder_no_bytes() {
+        let sr_settings = SrSettings::new(String::from("http://127.0.0.1:1234"));
+        let decoder = AvroDecoder::new(sr_settings);
+        let heartbeat = decoder.decode(None).await.unwrap().value;
+
+        assert_eq!(heartbeat, Value::Null)
+    }
+
+    #[tokio::test]
+    async fn test_decoder_with_name_no_bytes() {
+        let sr_settings = SrSettings::new(String::from("http://127.0.0.1:1234"));
+        let decoder = AvroDecoder::new(sr_settings);
+        let heartbeat = decoder.decode(None).await.unwrap();
+
+        assert_eq!(
+            heartbeat,
+            DecodeResult {
+                name: None,
+                value: Value::Null
+            }
+        )
+    }
+
+    #[tokio::test]
+    async fn test_decoder_magic_byte_not_present() {
+        let sr_settings = SrSettings::new(String::from("http://127.0.0.1:1234"));
+        let decoder = AvroDecoder::new(sr_settings);
+        let result = decoder.decode(Some(&[1, 0, 0, 0, 1, 6])).await.unwrap_err();
+
+        assert_eq!(result, SRCError::from(WireFormatError::InvalidMagicByte(1)))
+    }
+
+    #[tokio::test]
+    async fn test_decoder_with_name_magic_byte_not_present() {
+        let sr_settings = SrSettings::new(String::from("http://127.0.0.1:1234"));
+        let decoder = AvroDecoder::new(sr_settings);
+        let result = decoder.decode(Some(&[1, 0, 0, 0, 1, 6])).await.unwrap_err();
+
+        assert_eq!(result, SRCError::from(WireFormatError::InvalidMagicByte(1)))
+    }
+
+    #[tokio::test]
+    async fn test_decoder_not_enough_bytes() {
+        let sr_settings = SrSettings::new(String::from("http://127.0.0.1:1234"));
+        let decoder = AvroDecoder::new(sr_settings);
+        let result = decoder.decode(Some(&[0, 0, 0, 0])).await.unwrap_err();
+
+        assert_eq!(result, SRCError::from(WireFormatError::TooShort(4)))
+    }
+
+    #[tokio::test]
+    async fn test_decoder_strict_trailing_bytes() {
+        let mut server = Server::new_async().await;
+        let _m = server.mock("GET", "/schemas/ids/1?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"schema":"{\"type\":\"record\",\"name\":\"Heartbeat\",\"namespace\":\"nl.openweb.data\",\"fields\":[{\"name\":\"beat\",\"type\":\"long\"}]}"}"#)
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let lenient = AvroDecoder::new(sr_settings);
+        let strict = lenient.clone().with_strictness(Strictness::Strict);
+        let bytes: &[u8] = &[0, 0, 0, 0, 1, 6, 7];
+
+        assert!(lenient.decode(Some(bytes)).await.is_ok());
+        let error = strict.decode(Some(bytes)).await.unwrap_err();
+        assert_eq!(
+            error.wire_format(),
+            Some(&WireFormatError::TrailingBytes(1))
+        );
+        assert!(strict.decode(Some(&bytes[..6])).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_decoder_wrong_data() {
+        let mut server = Server::new_async().await;
+        let _m = server.mock("GET", "/schemas/ids/1?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"schema":"{\"type\":\"record\",\"name\":\"Heartbeat\",\"namespace\":\"nl.openweb.data\",\"fields\":[{\"name\":\"beat\",\"type\":\"long\"}]}"}"#)
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let decoder = AvroDecoder::new(sr_settings);
+        let error = decoder.decode(Some(&[0, 0, 0, 0, 1])).await.unwrap_err();
+
+        assert_eq!(error.error, "Could not transform bytes using schema")
+    }
+
+    #[tokio::test]
+    async fn test_decoder_with_name_wrong_data() {
+        let mut server = Server::new_async().await;
+        let _m = server.mock("GET", "/schemas/ids/1?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"schema":"{\"type\":\"record\",\"name\":\"Heartbeat\",\"namespace\":\"nl.openweb.data\",\"fields\":[{\"name\":\"beat\",\"type\":\"long\"}]}"}"#)
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let decoder = AvroDecoder::new(sr_settings);
+        let error = decoder.decode(Some(&[0, 0, 0, 0, 1])).await.unwrap_err();
+
+        assert_eq!(error.error, "Could not transform bytes using schema")
+    }
+
+    #[tokio::test]
+    async fn test_decoder_no_json_response() {
+        let mut server = Server::new_async().await;
+        let _m = server.mock("GET", "/schemas/ids/1?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"type\":\"record\",\"name\":\"Heartbeat\",\"namespace\":\"nl.openweb.data\",\"fields\":[{\"name\":\"beat\",\"type\":\"long\"}]}"}"#)
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let decoder = AvroDecoder::new(sr_settings);
+        let heartbeat = decoder.decode(Some(&[0, 0, 0, 0, 1, 6])).await.unwrap_err();
+
         assert_eq!(
             heartbeat,
             SRCError::new(
@@ -1004,12 +1871,41 @@ mod tests {
         )
     }
 
+    #[tokio::test]
+    async fn test_decoder_does_not_cache_throttling() {
+        let mut server = Server::new_async().await;
+        let sr_settings = SrSettings::new(server.url());
+        let decoder = AvroDecoder::new(sr_settings);
+        let bytes = [0, 0, 0, 0, 2, 6];
+
+        let _m = server
+            .mock("GET", "/schemas/ids/2?deleted=true")
+            .with_status(429)
+            .with_header("retry-after", "1")
+            .create();
+        let err = decoder.decode(Some(&bytes)).await.unwrap_err();
+        assert!(err.is_throttled());
+        assert!(!err.cached);
+
+        let _m = server.mock("GET", "/schemas/ids/2?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"schema":"{\"type\":\"record\",\"name\":\"Heartbeat\",\"namespace\":\"nl.openweb.data\",\"fields\":[{\"name\":\"beat\",\"type\":\"long\"}]}"}"#)
+            .create();
+
+        let heartbeat = decoder.decode(Some(&bytes)).await.unwrap().value;
+        assert_eq!(
+            heartbeat,
+            Value::Record(vec![("beat".to_string(), Value::Long(3))])
+        )
+    }
+
     #[test]
     fn display_encode() {
         let sr_settings = SrSettings::new(String::from("http://127.0.0.1:1234"));
         let encoder = AvroEncoder::new(sr_settings);
         assert_eq!(
-            "AvroEncoder { sr_settings: SrSettings { urls: [\"http://127.0.0.1:1234\"], client: Client { accepts: Accepts, proxies: [Proxy(System({}), None)], referer: true, default_headers: {\"accept\": \"*/*\"} }, authorization: None }, direct_cache: {}, cache: {} }"
+            "AvroEncoder { sr_settings: SrSettings { urls: [\"http://127.0.0.1:1234\"], client: Client { accepts: Accepts, proxies: [Proxy(System({}), None)], referer: true, default_headers: {\"accept\": \"*/*\"} }, authorization: None, dialect: Confluent, fetch_deleted: true, normalize: false, headers: {}, usage_listener: None, fallback: None, limits: SchemaLimits { max_schema_bytes: None, max_references: None, max_total_references: None, max_depth: None }, references_fetched: None }, direct_cache: {}, cache: {}, codec_config: AvroCodecConfig { decimal_as_string: false, uuid_as_string: false, temporal_as_string: false }, framing: Confluent, id_strategy: Confluent, compression: None, local_schemas: LocalSchemaProvider { schemas: {} }, field_encryptor: None }"
                 .to_owned(),
             format!("{:?}", encoder)
         )
@@ -1055,6 +1951,84 @@ mod tests {
         assert_eq!(bytes, vec![0, 0, 0, 0, 4, 2, 120])
     }
 
+    #[tokio::test]
+    async fn test_usage_listener() {
+        let mut server = Server::new_async().await;
+        let _m = server.mock("GET", "/schemas/ids/1?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"schema":"{\"type\":\"record\",\"name\":\"Heartbeat\",\"namespace\":\"nl.openweb.data\",\"fields\":[{\"name\":\"beat\",\"type\":\"long\"}]}"}"#)
+            .create();
+        let _m = server.mock("GET", "/subjects/heartbeat-value/versions/latest")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"subject":"heartbeat-value","version":1,"id":3,"schema":"{\"type\":\"record\",\"name\":\"Heartbeat\",\"namespace\":\"nl.openweb.data\",\"fields\":[{\"name\":\"beat\",\"type\":\"long\"}]}"}"#)
+            .create();
+
+        let usages = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = usages.clone();
+        let sr_settings = SrSettings::new_builder(server.url())
+            .set_usage_listener(Arc::new(move |usage: &SchemaUsage| {
+                recorded.lock().unwrap().push(usage.clone())
+            }))
+            .build()
+            .unwrap();
+        let decoder = AvroDecoder::new(sr_settings.clone());
+        let encoder = AvroEncoder::new(sr_settings);
+        for _ in 0..2 {
+            decoder.decode(Some(&[0, 0, 0, 0, 1, 6])).await.unwrap();
+            let strategy = SubjectNameStrategy::TopicNameStrategy(String::from("heartbeat"), false);
+            encoder
+                .encode(vec![("beat", Value::Long(3))], strategy)
+                .await
+                .unwrap();
+        }
+
+        let usage = |kind, id, subject: Option<&str>| SchemaUsage {
+            kind,
+            id,
+            subject: subject.map(String::from),
+        };
+        assert_eq!(
+            *usages.lock().unwrap(),
+            vec![
+                usage(SchemaUsageKind::Fetched, 1, None),
+                usage(SchemaUsageKind::Decoded, 1, None),
+                usage(SchemaUsageKind::Fetched, 3, Some("heartbeat-value")),
+                usage(SchemaUsageKind::Encoded, 3, Some("heartbeat-value")),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_usage_listener_concurrent_decodes() {
+        let mut server = Server::new_async().await;
+        let _m = server.mock("GET", "/schemas/ids/1?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"schema":"{\"type\":\"record\",\"name\":\"Heartbeat\",\"namespace\":\"nl.openweb.data\",\"fields\":[{\"name\":\"beat\",\"type\":\"long\"}]}"}"#)
+            .create();
+
+        let usages = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = usages.clone();
+        let sr_settings = SrSettings::new_builder(server.url())
+            .set_usage_listener(Arc::new(move |usage: &SchemaUsage| {
+                recorded.lock().unwrap().push(usage.kind)
+            }))
+            .build()
+            .unwrap();
+        let decoder = AvroDecoder::new(sr_settings);
+        let decodes = (0..8).map(|_| decoder.decode(Some(&[0, 0, 0, 0, 1, 6])));
+        for result in futures::future::join_all(decodes).await {
+            result.unwrap();
+        }
+
+        assert_eq!(
+            *usages.lock().unwrap(),
+            vec![SchemaUsageKind::Fetched, SchemaUsageKind::Decoded]
+        );
+    }
+
     #[tokio::test]
     async fn test_encode_key_and_value_with_non_static_lifetime() {
         let mut server = Server::new_async().await;
@@ -1120,6 +2094,30 @@ mod tests {
         assert_eq!(bytes, vec![0, 0, 0, 0, 3, 6])
     }
 
+    #[tokio::test]
+    async fn test_record_name_not_matching_schema() {
+        let mut server = Server::new_async().await;
+        let _m = server.mock("GET", "/subjects/nl.openweb.data.Balance/versions/latest")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"subject":"nl.openweb.data.Balance","version":1,"id":3,"schema":"{\"type\":\"record\",\"name\":\"Heartbeat\",\"namespace\":\"nl.openweb.data\",\"fields\":[{\"name\":\"beat\",\"type\":\"long\"}]}"}"#)
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let encoder = AvroEncoder::new(sr_settings);
+        let strategy =
+            SubjectNameStrategy::RecordNameStrategy(String::from("nl.openweb.data.Balance"));
+        let err = encoder
+            .encode(vec![("beat", Value::Long(3))], strategy)
+            .await
+            .unwrap_err();
+
+        assert_eq!(
+            err.error,
+            "Record name nl.openweb.data.Balance of the subject name strategy doesn't match the full name nl.openweb.data.Heartbeat of the schema with id 3"
+        )
+    }
+
     #[tokio::test]
     async fn test_encoder_no_id_in_response() {
         let mut server = Server::new_async().await;
@@ -1187,23 +2185,82 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_encoder_schema_registry_unavailable_with_record() {
-        let sr_settings = SrSettings::new(String::from("http://bogus"));
+    async fn test_encoder_schema_registry_unavailable_with_record() {
+        let sr_settings = SrSettings::new(String::from("http://bogus"));
+        let encoder = AvroEncoder::new(sr_settings);
+        let strategy = SubjectNameStrategy::RecordNameStrategyWithSchema(SuppliedSchema {
+            name: Some(String::from("nl.openweb.data.Balance")),
+            schema_type: SchemaType::Avro,
+            schema: String::from(
+                r#"{"type":"record","name":"Balance","namespace":"nl.openweb.data","fields":[{"name":"beat","type":"long"}]}"#,
+            ),
+            references: vec![],
+        });
+        let err = encoder
+            .encode(vec![("beat", Value::Long(3))], strategy)
+            .await
+            .unwrap_err();
+
+        assert_eq!(err.error, "http call to schema registry failed");
+    }
+
+    #[derive(serde::Serialize)]
+    #[serde(untagged)]
+    enum Beat {
+        Long { beat: i64 },
+        Text { beat: String },
+        Flag { beat: bool },
+    }
+
+    #[tokio::test]
+    async fn test_encode_many() {
+        let mut server = Server::new_async().await;
+        let m = server.mock("GET", "/subjects/nl.openweb.data.Heartbeat/versions/latest")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"subject":"heartbeat-value","version":1,"id":3,"schema":"{\"type\":\"record\",\"name\":\"Heartbeat\",\"namespace\":\"nl.openweb.data\",\"fields\":[{\"name\":\"beat\",\"type\":\"long\"}]}"}"#)
+            .expect(1)
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let encoder = AvroEncoder::new(sr_settings);
+        let strategy =
+            SubjectNameStrategy::RecordNameStrategy(String::from("nl.openweb.data.Heartbeat"));
+
+        let bytes = encoder
+            .encode_many(&[Heartbeat { beat: 3 }, Heartbeat { beat: 5 }], strategy)
+            .await
+            .unwrap();
+
+        assert_eq!(bytes, vec![vec![0, 0, 0, 0, 3, 6], vec![0, 0, 0, 0, 3, 10]]);
+        m.assert();
+    }
+
+    #[tokio::test]
+    async fn test_encode_many_stops_at_first_error() {
+        let mut server = Server::new_async().await;
+        let _m = server.mock("GET", "/subjects/nl.openweb.data.Heartbeat/versions/latest")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"subject":"heartbeat-value","version":1,"id":3,"schema":"{\"type\":\"record\",\"name\":\"Heartbeat\",\"namespace\":\"nl.openweb.data\",\"fields\":[{\"name\":\"beat\",\"type\":\"long\"}]}"}"#)
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
         let encoder = AvroEncoder::new(sr_settings);
-        let strategy = SubjectNameStrategy::RecordNameStrategyWithSchema(SuppliedSchema {
-            name: Some(String::from("nl.openweb.data.Balance")),
-            schema_type: SchemaType::Avro,
-            schema: String::from(
-                r#"{"type":"record","name":"Balance","namespace":"nl.openweb.data","fields":[{"name":"beat","type":"long"}]}"#,
-            ),
-            references: vec![],
-        });
-        let err = encoder
-            .encode(vec![("beat", Value::Long(3))], strategy)
-            .await
-            .unwrap_err();
+        let strategy =
+            SubjectNameStrategy::RecordNameStrategy(String::from("nl.openweb.data.Heartbeat"));
+        let items = [
+            Beat::Long { beat: 3 },
+            Beat::Text {
+                beat: String::from("three"),
+            },
+            Beat::Flag { beat: true },
+        ];
 
-        assert_eq!(err.error, "http call to schema registry failed");
+        let err = encoder.encode_many(&items, &strategy).await.unwrap_err();
+
+        assert_eq!(err.error, "Failed to resolve");
+        assert_eq!(Err(err), encoder.encode_struct(&items[1], &strategy).await);
     }
 
     #[tokio::test]
@@ -1428,17 +2485,11 @@ mod tests {
             .encode(vec![("beat", Value::Long(3))], strategy)
             .await
             .unwrap_err();
-        assert_eq!(
-            error,
-            SRCError::new(
-                "could not parse to RawRegisteredSchema, schema might not exist on this schema registry, the http call failed, cause will give more information",
-                Some(String::from(
-                    "error decoding response body"
-                )),
-                false,
-            )
-            .into_cache()
-        )
+        assert!(error
+            .error
+            .starts_with("schema registry responded with status 501 Not Implemented"));
+        assert!(error.retriable);
+        assert!(error.cached);
     }
 
     #[tokio::test]
@@ -1448,6 +2499,12 @@ mod tests {
             schema_type: SchemaType::Avro,
             schema: String::from(r#"{"type":"record","name":"Name"}"#),
             references: vec![],
+            subject: None,
+            version: None,
+            metadata: None,
+            rule_set: None,
+            guid: None,
+            deleted: false,
         };
         let sr_settings = SrSettings::new(String::from("http://127.0.0.1:1234"));
         let result = to_avro_schema(&sr_settings, registered_schema)
@@ -1468,6 +2525,12 @@ mod tests {
                 r#"syntax = "proto3"; package org.schema_registry_test_app.proto; message Result { string up = 1; string down = 2; }"#,
             ),
             references: vec![],
+            subject: None,
+            version: None,
+            metadata: None,
+            rule_set: None,
+            guid: None,
+            deleted: false,
         };
         let sr_settings = SrSettings::new(String::from("http://127.0.0.1:1234"));
         let err = to_avro_schema(&sr_settings, registered_schema)
@@ -1509,6 +2572,265 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_encode_struct_with_default() {
+        let mut server = Server::new_async().await;
+        let _m = server.mock("GET", "/subjects/heartbeat-value/versions/latest")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"subject":"heartbeat-value","version":1,"id":3,"schema":"{\"type\":\"record\",\"name\":\"Heartbeat\",\"namespace\":\"nl.openweb.data\",\"fields\":[{\"name\":\"source\",\"type\":\"string\",\"default\":\"unknown\"},{\"name\":\"beat\",\"type\":\"long\"}]}"}"#)
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let encoder = AvroEncoder::new(sr_settings);
+        let strategy = SubjectNameStrategy::TopicNameStrategy(String::from("heartbeat"), false);
+        let bytes = encoder
+            .encode_struct(Heartbeat { beat: 3 }, &strategy)
+            .await;
+
+        assert_eq!(
+            bytes,
+            Ok(vec![
+                0, 0, 0, 0, 3, 14, 117, 110, 107, 110, 111, 119, 110, 6
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_encode_struct_into() {
+        let mut server = Server::new_async().await;
+        let _m = server.mock("GET", "/subjects/heartbeat-value/versions/latest")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"subject":"heartbeat-value","version":1,"id":3,"schema":"{\"type\":\"record\",\"name\":\"Heartbeat\",\"namespace\":\"nl.openweb.data\",\"fields\":[{\"name\":\"beat\",\"type\":\"long\"}]}"}"#)
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let encoder = AvroEncoder::new(sr_settings);
+        let strategy = SubjectNameStrategy::TopicNameStrategy(String::from("heartbeat"), false);
+        let mut buf = Vec::new();
+
+        encoder
+            .encode_struct_into(Heartbeat { beat: 3 }, &strategy, &mut buf)
+            .await
+            .unwrap();
+        assert_eq!(buf, vec![0, 0, 0, 0, 3, 6]);
+
+        encoder
+            .encode_into(vec![("beat", Value::Long(5))], &strategy, &mut buf)
+            .await
+            .unwrap();
+        assert_eq!(buf, vec![0, 0, 0, 0, 3, 10]);
+    }
+
+    #[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
+    struct Event {
+        day: String,
+    }
+
+    #[tokio::test]
+    async fn test_codec_config_round_trip() {
+        let mut server = Server::new_async().await;
+        let _m = server.mock("GET", "/subjects/event-value/versions/latest")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"subject":"event-value","version":1,"id":5,"schema":"{\"type\":\"record\",\"name\":\"Event\",\"namespace\":\"nl.openweb.data\",\"fields\":[{\"name\":\"day\",\"type\":{\"type\":\"int\",\"logicalType\":\"date\"}}]}"}"#)
+            .create();
+        let _m = server.mock("GET", "/schemas/ids/5?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"schema":"{\"type\":\"record\",\"name\":\"Event\",\"namespace\":\"nl.openweb.data\",\"fields\":[{\"name\":\"day\",\"type\":{\"type\":\"int\",\"logicalType\":\"date\"}}]}"}"#)
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let config = AvroCodecConfig::all_as_string();
+        let encoder = AvroEncoder::new(sr_settings.clone()).with_codec_config(config);
+        let decoder = AvroDecoder::new(sr_settings).with_codec_config(config);
+        let strategy = SubjectNameStrategy::TopicNameStrategy(String::from("event"), false);
+        let event = Event {
+            day: String::from("1970-01-02"),
+        };
+        let bytes = encoder.encode_struct(&event, &strategy).await.unwrap();
+        assert_eq!(bytes, vec![0, 0, 0, 0, 5, 2]);
+
+        let decoded = decoder.decode_as::<Event>(Some(&bytes)).await.unwrap();
+        assert_eq!(decoded, Some(event));
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct FixedBytes(Vec<u8>);
+
+    impl<'de> serde::Deserialize<'de> for FixedBytes {
+        fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            struct BytesVisitor;
+            impl serde::de::Visitor<'_> for BytesVisitor {
+                type Value = FixedBytes;
+                fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                    f.write_str("bytes")
+                }
+                fn visit_bytes<E: serde::de::Error>(self, v: &[u8]) -> Result<FixedBytes, E> {
+                    Ok(FixedBytes(v.to_vec()))
+                }
+            }
+            deserializer.deserialize_bytes(BytesVisitor)
+        }
+    }
+
+    #[derive(serde::Deserialize, Debug, PartialEq)]
+    struct Payment {
+        code: FixedBytes,
+        amount: String,
+        id: String,
+    }
+
+    fn get_payment_bytes() -> Vec<u8> {
+        let mut bytes = vec![0, 0, 0, 0, 8, 1, 2, 4, 4, 210, 72];
+        bytes.extend_from_slice(b"67e55044-10b1-426f-9247-bb680e5fe0c8");
+        bytes
+    }
+
+    fn get_payment_body() -> &'static str {
+        r#"{"schema":"{\"type\":\"record\",\"name\":\"Payment\",\"namespace\":\"nl.openweb.data\",\"fields\":[{\"name\":\"code\",\"type\":{\"type\":\"fixed\",\"name\":\"Code\",\"size\":2}},{\"name\":\"amount\",\"type\":{\"type\":\"bytes\",\"logicalType\":\"decimal\",\"precision\":10,\"scale\":2}},{\"name\":\"id\",\"type\":{\"type\":\"string\",\"logicalType\":\"uuid\"}}]}"}"#
+    }
+    #[tokio::test]
+    async fn test_decode_as_fixed_decimal_and_uuid() {
+        let mut server = Server::new_async().await;
+        let _m = server
+            .mock("GET", "/schemas/ids/8?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_payment_body())
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let decoder =
+            AvroDecoder::new(sr_settings).with_codec_config(AvroCodecConfig::all_as_string());
+        let payment = decoder
+            .decode_as::<Payment>(Some(&get_payment_bytes()))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            payment,
+            Some(Payment {
+                code: FixedBytes(vec![1, 2]),
+                amount: String::from("12.34"),
+                id: String::from("67e55044-10b1-426f-9247-bb680e5fe0c8"),
+            })
+        )
+    }
+
+    #[tokio::test]
+    async fn test_single_object_round_trip() {
+        let mut server = Server::new_async().await;
+        let _m = server.mock("GET", "/subjects/heartbeat-value/versions/latest")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"subject":"heartbeat-value","version":1,"id":3,"schema":"{\"type\":\"record\",\"name\":\"Heartbeat\",\"namespace\":\"nl.openweb.data\",\"fields\":[{\"name\":\"beat\",\"type\":\"long\"}]}"}"#)
+            .create();
+        let _m = server.mock("GET", "/schemas")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"[{"subject":"other-value","version":1,"id":2,"schemaType":"PROTOBUF","schema":"syntax = \"proto3\";"},{"subject":"heartbeat-value","version":1,"id":3,"schema":"{\"type\":\"record\",\"name\":\"Heartbeat\",\"namespace\":\"nl.openweb.data\",\"fields\":[{\"name\":\"beat\",\"type\":\"long\"}]}"}]"#)
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let encoder = AvroEncoder::new(sr_settings.clone()).with_framing(AvroFraming::SingleObject);
+        let decoder = AvroDecoder::new(sr_settings);
+        let strategy = SubjectNameStrategy::TopicNameStrategy(String::from("heartbeat"), false);
+        let bytes = encoder
+            .encode_struct(Heartbeat { beat: 3 }, &strategy)
+            .await
+            .unwrap();
+
+        assert_eq!(&bytes[..2], &[0xC3, 0x01]);
+        assert_eq!(bytes[10..], [6]);
+        let result = decoder.decode_single_object(Some(&bytes)).await.unwrap();
+        assert_eq!(
+            result.value,
+            Value::Record(vec![("beat".to_string(), Value::Long(3))])
+        );
+        let error = decoder
+            .decode_single_object(Some(&[0xC3, 0x01, 1, 2, 3, 4, 5, 6, 7, 8, 6]))
+            .await
+            .unwrap_err();
+        assert!(error
+            .error
+            .starts_with("Could not find schema with fingerprint"))
+    }
+
+    #[tokio::test]
+    async fn test_single_object_skips_broken_schemas_and_remembers_missing() {
+        let mut server = Server::new_async().await;
+        let all_schemas = server.mock("GET", "/schemas")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"[{"subject":"broken-value","version":1,"id":2},{"subject":"heartbeat-value","version":1,"id":3,"schema":"{\"type\":\"record\",\"name\":\"Heartbeat\",\"namespace\":\"nl.openweb.data\",\"fields\":[{\"name\":\"beat\",\"type\":\"long\"}]}"}]"#)
+            .expect(1)
+            .create();
+
+        let decoder = AvroDecoder::new(SrSettings::new(server.url()));
+        let schema = Schema::parse_str(r#"{"type":"record","name":"Heartbeat","namespace":"nl.openweb.data","fields":[{"name":"beat","type":"long"}]}"#).unwrap();
+        let mut bytes = vec![0xC3, 0x01];
+        bytes.extend_from_slice(&get_fingerprint(&schema).to_le_bytes());
+        bytes.push(6);
+        let result = decoder.decode_single_object(Some(&bytes)).await.unwrap();
+        assert_eq!(
+            result.value,
+            Value::Record(vec![("beat".to_string(), Value::Long(3))])
+        );
+        for _ in 0..2 {
+            let error = decoder
+                .decode_single_object(Some(&[0xC3, 0x01, 1, 2, 3, 4, 5, 6, 7, 8, 6]))
+                .await
+                .unwrap_err();
+            assert!(error
+                .error
+                .starts_with("Could not find schema with fingerprint"))
+        }
+        all_schemas.assert();
+    }
+
+    #[tokio::test]
+    async fn test_local_schemas() {
+        let mut server = Server::new_async().await;
+        let lookup = server.mock("POST", "/subjects/heartbeat-value")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"subject":"heartbeat-value","version":1,"id":3,"schema":"{\"type\":\"record\",\"name\":\"Heartbeat\",\"namespace\":\"nl.openweb.data\",\"fields\":[{\"name\":\"beat\",\"type\":\"long\"}]}"}"#)
+            .expect(1)
+            .create();
+
+        let raw = r#"{"type":"record","name":"Heartbeat","namespace":"nl.openweb.data","fields":[{"name":"beat","type":"long"}]}"#;
+        let local_schemas = LocalSchemaProvider::new();
+        local_schemas.add_schema("heartbeat-value", raw).unwrap();
+        local_schemas
+            .add_schema_with_id("heartbeat-key", raw, 4)
+            .unwrap();
+        let sr_settings = SrSettings::new(server.url());
+        let encoder = AvroEncoder::new(sr_settings).with_local_schemas(local_schemas);
+        let value_strategy =
+            SubjectNameStrategy::TopicNameStrategy(String::from("heartbeat"), false);
+        let key_strategy = SubjectNameStrategy::TopicNameStrategy(String::from("heartbeat"), true);
+
+        for _ in 0..2 {
+            let bytes = encoder
+                .encode(vec![("beat", Value::Long(3))], value_strategy.clone())
+                .await;
+            assert_eq!(bytes, Ok(vec![0, 0, 0, 0, 3, 6]));
+        }
+        let bytes = encoder
+            .encode_struct(Heartbeat { beat: 3 }, &key_strategy)
+            .await;
+        assert_eq!(bytes, Ok(vec![0, 0, 0, 0, 4, 6]));
+        lookup.assert();
+
+        let error = LocalSchemaProvider::new()
+            .add_schema("heartbeat-value", "{")
+            .unwrap_err();
+        assert!(error.error.starts_with("Supplied raw value"))
+    }
+
     #[tokio::test]
     async fn test_primitive_schema_incompatible_strategy() {
         let sr_settings = SrSettings::new(String::from("http://127.0.0.1:1234"));
@@ -1574,4 +2896,194 @@ mod tests {
         };
         assert_eq!(&1i64, counter_value, "counter is 1");
     }
+
+    #[derive(Debug)]
+    struct Reverse;
+
+    impl FieldEncryptor for Reverse {
+        fn encrypt(&self, _rule: &Rule, plaintext: &[u8]) -> Result<Vec<u8>, SRCError> {
+            Ok(plaintext.iter().rev().copied().collect())
+        }
+        fn decrypt(&self, _rule: &Rule, ciphertext: &[u8]) -> Result<Vec<u8>, SRCError> {
+            Ok(ciphertext.iter().rev().copied().collect())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_field_encryption_round_trip() {
+        let mut server = Server::new_async().await;
+        let body = r#"{"subject":"customer-value","version":1,"id":3,"schema":"{\"type\":\"record\",\"name\":\"Customer\",\"namespace\":\"nl.openweb.data\",\"fields\":[{\"name\":\"ssn\",\"type\":\"string\",\"confluent:tags\":[\"PII\"]}]}","ruleSet":{"domainRules":[{"name":"encrypt","kind":"TRANSFORM","mode":"WRITEREAD","type":"ENCRYPT","tags":["PII"],"params":{"encrypt.kek.name":"kek"}}]}}"#;
+        let _m = server
+            .mock("GET", "/subjects/customer-value/versions/latest")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(body)
+            .create();
+        let _n = server
+            .mock("GET", "/schemas/ids/3?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(body)
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let encoder = AvroEncoder::new(sr_settings.clone()).with_field_encryptor(Arc::new(Reverse));
+        let strategy = SubjectNameStrategy::TopicNameStrategy(String::from("customer"), false);
+        let bytes = encoder
+            .encode(vec![("ssn", Value::String(String::from("123")))], strategy)
+            .await
+            .unwrap();
+        assert_eq!(bytes, vec![0, 0, 0, 0, 3, 8, 77, 122, 73, 120]);
+
+        let decoder = AvroDecoder::new(sr_settings.clone());
+        let encrypted = decoder.decode(Some(&bytes)).await.unwrap().value;
+        assert_eq!(
+            encrypted,
+            Value::Record(vec![(
+                String::from("ssn"),
+                Value::String(String::from("MzIx"))
+            )])
+        );
+        let decoder = decoder.with_field_encryptor(Arc::new(Reverse));
+        let decrypted = decoder.decode(Some(&bytes)).await.unwrap().value;
+        assert_eq!(
+            decrypted,
+            Value::Record(vec![(
+                String::from("ssn"),
+                Value::String(String::from("123"))
+            )])
+        );
+    }
+
+    /// Renames the field in the expression of the rule, like `name>full_name`.
+    #[derive(Debug)]
+    struct Rename;
+
+    impl RuleExecutor for Rename {
+        fn transform(
+            &self,
+            rule: &Rule,
+            mut value: value::Value,
+        ) -> Result<value::Value, SRCError> {
+            let (from, to) = rule.expr.as_deref().unwrap().split_once('>').unwrap();
+            let object = value.as_object_mut().unwrap();
+            let v = object.remove(from).unwrap();
+            object.insert(String::from(to), v);
+            Ok(value)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_migration_upgrade() {
+        let mut server = Server::new_async().await;
+        let v1 = r#"{"subject":"customer-value","version":1,"id":1,"schema":"{\"type\":\"record\",\"name\":\"Customer\",\"namespace\":\"nl.openweb.data\",\"fields\":[{\"name\":\"name\",\"type\":\"string\"}]}"}"#;
+        let v2 = r#"{"subject":"customer-value","version":2,"id":2,"schema":"{\"type\":\"record\",\"name\":\"Customer\",\"namespace\":\"nl.openweb.data\",\"fields\":[{\"name\":\"full_name\",\"type\":\"string\"}]}","metadata":{"properties":{"application.version":"2"}},"ruleSet":{"migrationRules":[{"name":"rename","kind":"TRANSFORM","mode":"UPGRADE","type":"JSONATA","expr":"name>full_name"}]}}"#;
+        let latest = server
+            .mock(
+                "GET",
+                "/subjects/customer-value/metadata?key=application.version&value=2",
+            )
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(v2)
+            .expect(2)
+            .create();
+        let _m = server
+            .mock("GET", "/schemas/ids/1?deleted=true")
+            .with_status(200)
+            .with_body(v1)
+            .create();
+        let _m = server
+            .mock("GET", "/schemas/ids/2?deleted=true")
+            .with_status(200)
+            .with_body(v2)
+            .create();
+        let _m = server
+            .mock("GET", "/schemas/ids/1/versions")
+            .with_status(200)
+            .with_body(r#"[{"subject":"customer-value","version":1}]"#)
+            .create();
+        let _m = server
+            .mock("GET", "/subjects/customer-value/versions?deleted=false")
+            .with_status(200)
+            .with_body("[1,2]")
+            .create();
+        let _m = server
+            .mock("GET", "/subjects/customer-value/versions/2")
+            .with_status(200)
+            .with_body(v2)
+            .create();
+
+        let metadata = HashMap::from([(String::from("application.version"), String::from("2"))]);
+        let migration = MigrationConfig::new("customer-value", metadata, Arc::new(Rename));
+        let decoder = AvroDecoder::new(SrSettings::new(server.url())).with_migration(migration);
+        for _ in 0..2 {
+            let result = decoder
+                .decode(Some(&[0, 0, 0, 0, 1, 6, 74, 97, 110]))
+                .await
+                .unwrap();
+            assert_eq!(
+                result.value,
+                Value::Record(vec![(
+                    String::from("full_name"),
+                    Value::String(String::from("Jan"))
+                )])
+            );
+        }
+        let result = decoder
+            .decode_with_schema(Some(&[0, 0, 0, 0, 1, 6, 74, 97, 110]))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(result.schema.id, 2);
+        assert_eq!(result.writer_schema.id, 1);
+
+        decoder.invalidate_subject("customer-value");
+        let result = decoder
+            .decode_with_schema(Some(&[0, 0, 0, 0, 1, 6, 74, 97, 110]))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(result.schema.id, 2);
+        latest.assert();
+    }
+
+    #[tokio::test]
+    async fn test_reader_schema() {
+        let mut server = Server::new_async().await;
+        let _m = server
+            .mock("GET", "/schemas/ids/1?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"schema":"{\"type\":\"record\",\"name\":\"Heartbeat\",\"namespace\":\"nl.openweb.data\",\"fields\":[{\"name\":\"beat\",\"type\":\"long\"},{\"name\":\"source\",\"type\":\"string\"}]}"}"#)
+            .create();
+        let reader_schema = Schema::parse_str(r#"{"type":"record","name":"Heartbeat","namespace":"nl.openweb.data","fields":[{"name":"beat","type":"long"},{"name":"count","type":"int","default":7}]}"#).unwrap();
+        let bytes = [0, 0, 0, 0, 1, 6, 4, 97, 98];
+
+        let decoder = AvroDecoder::new(SrSettings::new(server.url()))
+            .with_reader_schema(reader_schema.clone(), UnknownFieldPolicy::Warn);
+        let result = decoder
+            .decode_with_schema(Some(&bytes))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            result.value,
+            Value::Record(vec![
+                (String::from("beat"), Value::Long(3)),
+                (String::from("count"), Value::Int(7)),
+            ])
+        );
+        assert_eq!(result.schema.id, 1);
+        assert_eq!(result.schema.parsed, reader_schema);
+        assert_ne!(result.writer_schema.parsed, reader_schema);
+
+        let decoder = AvroDecoder::new(SrSettings::new(server.url()))
+            .with_reader_schema(reader_schema, UnknownFieldPolicy::Error);
+        let error = decoder.decode(Some(&bytes)).await.unwrap_err();
+        assert_eq!(
+            error.error,
+            r#"Fields ["source"] of the schema with id 1 are not in the reader schema"#
+        );
+    }
 }
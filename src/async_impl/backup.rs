@@ -0,0 +1,361 @@
+//! Backups of the schema registry. All the versions of all the subjects are written to a
+//! directory, each as the json returned by the schema registry, to
+//! `subjects/{subject}/{version}.json` with the subject percent encoded. This keeps the id,
+//! references, metadata and rule set of each version, so they can be imported into another schema
+//! registry with the same ids and versions.
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use futures::stream::{self, StreamExt, TryStreamExt};
+
+use crate::async_impl::schema_registry::{
+    get_mode, get_subject_versions, import_schema, list_subjects, perform_sr_call,
+    raw_to_registered_schema, set_mode, SrSettings,
+};
+use crate::error::SRCError;
+use crate::schema_registry_common::{
+    percent_encode, Mode, RawRegisteredSchema, RegisteredSchema, SrCall,
+};
+
+/// What was done by `dump_registry`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DumpSummary {
+    pub subjects: usize,
+    pub written: usize,
+    /// The versions which were already written by an earlier dump, and were not fetched again.
+    pub skipped: usize,
+}
+
+/// Writes all the versions of all the subjects to the directory, fetching at most `concurrency`
+/// versions at the same time. Versions already in the directory are skipped, so a dump that failed
+/// halfway can be resumed by calling it again with the same directory, and a later dump only
+/// fetches the new versions. Each version is written to a temporary file first, so an interrupted
+/// dump doesn't leave partial files. Soft deleted subjects and versions are not included.
+pub async fn dump_registry(
+    sr_settings: &SrSettings,
+    path: &Path,
+    concurrency: usize,
+) -> Result<DumpSummary, SRCError> {
+    let subjects = list_subjects(sr_settings, false).await?;
+    let mut summary = DumpSummary {
+        subjects: subjects.len(),
+        ..DumpSummary::default()
+    };
+    let mut missing = Vec::new();
+    for subject in subjects {
+        let versions = get_subject_versions(sr_settings, &subject, false).await?;
+        for version in versions {
+            if version_path(path, &subject, version).exists() {
+                summary.skipped += 1;
+            } else {
+                missing.push((subject.clone(), version));
+            }
+        }
+    }
+    summary.written = missing.len();
+    stream::iter(missing)
+        .map(Ok)
+        .try_for_each_concurrent(concurrency.max(1), |(subject, version)| async move {
+            let raw_schema = perform_sr_call(
+                sr_settings,
+                SrCall::GetBySubjectAndVersion(&subject, version),
+            )
+            .await?;
+            let json = match serde_json::to_string_pretty(&raw_schema) {
+                Ok(v) => v,
+                Err(e) => {
+                    return Err(SRCError::non_retryable_with_cause(
+                        e,
+                        "could not serialize schema to json",
+                    ))
+                }
+            };
+            write_version(&version_path(path, &subject, version), &json)
+        })
+        .await?;
+    Ok(summary)
+}
+
+/// Imports the versions written by `dump_registry` into the schema registry, keeping the ids and
+/// versions, like when moving to another cluster. The schema registry is put in import mode for the
+/// import, and set back to the mode it had afterwards, also when the import fails. Setting import
+/// mode is only allowed when no schema's are registered yet, unless `force` is set. Referenced
+/// versions are imported before the versions referencing them. Gives the number of imported
+/// versions.
+pub async fn import_registry(
+    sr_settings: &SrSettings,
+    path: &Path,
+    force: bool,
+) -> Result<usize, SRCError> {
+    let schemas = in_reference_order(read_dump(path).await?);
+    let previous_mode = get_mode(sr_settings).await?;
+    set_mode(sr_settings, Mode::Import, force).await?;
+    let mut result = Ok(schemas.len());
+    for schema in &schemas {
+        if let Err(e) = import_schema(sr_settings, schema).await {
+            result = Err(e);
+            break;
+        }
+    }
+    set_mode(sr_settings, previous_mode, false).await?;
+    result
+}
+
+/// Reads all the versions written by `dump_registry`.
+async fn read_dump(path: &Path) -> Result<Vec<RegisteredSchema>, SRCError> {
+    let mut files = Vec::new();
+    for subject_dir in read_dir(&path.join("subjects"))? {
+        for file in read_dir(&subject_dir)? {
+            if file.extension().is_some_and(|e| e == "json") {
+                files.push(file);
+            }
+        }
+    }
+    let mut schemas = Vec::with_capacity(files.len());
+    for file in files {
+        let raw_schema = match fs::read_to_string(&file)
+            .map_err(|e| e.to_string())
+            .and_then(|v| {
+                serde_json::from_str::<RawRegisteredSchema>(&v).map_err(|e| e.to_string())
+            }) {
+            Ok(v) => v,
+            Err(e) => {
+                return Err(SRCError::non_retryable_with_cause(
+                    e,
+                    &format!("could not read {}", file.display()),
+                ))
+            }
+        };
+        schemas.push(raw_to_registered_schema(raw_schema, None).await?);
+    }
+    Ok(schemas)
+}
+
+fn read_dir(path: &Path) -> Result<Vec<PathBuf>, SRCError> {
+    match fs::read_dir(path).and_then(|d| d.map(|e| e.map(|e| e.path())).collect()) {
+        Ok(v) => Ok(v),
+        Err(e) => Err(SRCError::non_retryable_with_cause(
+            e,
+            &format!("could not read directory {}", path.display()),
+        )),
+    }
+}
+
+/// Orders the schema's by subject and version, moving the versions they reference before them.
+/// References to versions which are not part of the schema's are expected to already exist.
+fn in_reference_order(mut schemas: Vec<RegisteredSchema>) -> Vec<RegisteredSchema> {
+    schemas.sort_by(|a, b| (&a.subject, a.version).cmp(&(&b.subject, b.version)));
+    let index: HashMap<(String, u32), usize> = schemas
+        .iter()
+        .enumerate()
+        .filter_map(|(i, s)| Some(((s.subject.clone()?, s.version?), i)))
+        .collect();
+    let mut order = Vec::with_capacity(schemas.len());
+    let mut visited = vec![false; schemas.len()];
+    for i in 0..schemas.len() {
+        visit(i, &schemas, &index, &mut visited, &mut order);
+    }
+    let mut schemas: Vec<Option<RegisteredSchema>> = schemas.into_iter().map(Some).collect();
+    order
+        .into_iter()
+        .filter_map(|i| schemas[i].take())
+        .collect()
+}
+
+fn visit(
+    i: usize,
+    schemas: &[RegisteredSchema],
+    index: &HashMap<(String, u32), usize>,
+    visited: &mut [bool],
+    order: &mut Vec<usize>,
+) {
+    if visited[i] {
+        return;
+    }
+    visited[i] = true;
+    for reference in &schemas[i].references {
+        if let Some(j) = index.get(&(reference.subject.clone(), reference.version)) {
+            visit(*j, schemas, index, visited, order);
+        }
+    }
+    order.push(i);
+}
+
+fn version_path(path: &Path, subject: &str, version: u32) -> PathBuf {
+    path.join("subjects")
+        .join(percent_encode(subject))
+        .join(format!("{}.json", version))
+}
+
+fn write_version(path: &Path, json: &str) -> Result<(), SRCError> {
+    let temporary = path.with_extension("json.tmp");
+    let result = match path.parent() {
+        Some(parent) => fs::create_dir_all(parent).and_then(|_| fs::write(&temporary, json)),
+        None => fs::write(&temporary, json),
+    };
+    match result.and_then(|_| fs::rename(&temporary, path)) {
+        Ok(()) => Ok(()),
+        Err(e) => Err(SRCError::retryable_with_cause(
+            e,
+            &format!("could not write {}", path.display()),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use mockito::Server;
+
+    use crate::async_impl::backup::{
+        dump_registry, import_registry, in_reference_order, read_dump, DumpSummary,
+    };
+    use crate::async_impl::schema_registry::SrSettings;
+
+    #[tokio::test]
+    async fn dump_and_resume() {
+        let mut server = Server::new_async().await;
+        let _m = server
+            .mock("GET", "/subjects?deleted=false")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"["heartbeat-value","nl/openweb"]"#)
+            .create();
+        let _m = server
+            .mock("GET", "/subjects/heartbeat-value/versions?deleted=false")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body("[1,2]")
+            .create();
+        let _m = server
+            .mock("GET", "/subjects/nl%2Fopenweb/versions?deleted=false")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body("[1]")
+            .create();
+        let first = server
+            .mock("GET", "/subjects/heartbeat-value/versions/1")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"subject":"heartbeat-value","version":1,"id":3,"schema":"{\"type\":\"record\",\"name\":\"Heartbeat\",\"fields\":[{\"name\":\"beat\",\"type\":\"long\"}]}"}"#)
+            .expect(0)
+            .create();
+        let second = server
+            .mock("GET", "/subjects/heartbeat-value/versions/2")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"subject":"heartbeat-value","version":2,"id":4,"schema":"{\"type\":\"record\",\"name\":\"Heartbeat\",\"fields\":[{\"name\":\"beat\",\"type\":\"long\"},{\"name\":\"source\",\"type\":\"string\",\"default\":\"\"}]}"}"#)
+            .expect(1)
+            .create();
+        let third = server
+            .mock("GET", "/subjects/nl%2Fopenweb/versions/1")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"subject":"nl/openweb","version":1,"id":5,"schemaType":"JSON","schema":"{}","references":[]}"#)
+            .expect(1)
+            .create();
+
+        let path = std::env::temp_dir().join(format!("sr_dump_{}", std::process::id()));
+        let subject_dir = path.join("subjects").join("heartbeat-value");
+        fs::create_dir_all(&subject_dir).unwrap();
+        fs::write(subject_dir.join("1.json"), "{}").unwrap();
+
+        let sr_settings = SrSettings::new(server.url());
+        let summary = dump_registry(&sr_settings, &path, 2).await.unwrap();
+
+        assert_eq!(
+            summary,
+            DumpSummary {
+                subjects: 2,
+                written: 2,
+                skipped: 1,
+            }
+        );
+        first.assert_async().await;
+        second.assert_async().await;
+        third.assert_async().await;
+        let written = fs::read_to_string(path.join("subjects/nl%2Fopenweb/1.json")).unwrap();
+        let written: serde_json::Value = serde_json::from_str(&written).unwrap();
+        assert_eq!(written["id"], 5);
+        assert_eq!(written["schemaType"], "JSON");
+        assert!(!subject_dir.join("2.json.tmp").exists());
+        assert!(subject_dir.join("2.json").exists());
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn import_referenced_versions_first() {
+        let path = std::env::temp_dir().join(format!("sr_import_{}", std::process::id()));
+        let heartbeat_dir = path.join("subjects").join("heartbeat-value");
+        let openweb_dir = path.join("subjects").join("nl%2Fopenweb");
+        fs::create_dir_all(&heartbeat_dir).unwrap();
+        fs::create_dir_all(&openweb_dir).unwrap();
+        fs::write(
+            heartbeat_dir.join("1.json"),
+            r#"{"subject":"heartbeat-value","version":1,"id":7,"schemaType":"JSON","schema":"{\"$ref\":\"openweb.json\"}","references":[{"name":"openweb.json","subject":"nl/openweb","version":2}]}"#,
+        )
+        .unwrap();
+        fs::write(
+            openweb_dir.join("2.json"),
+            r#"{"subject":"nl/openweb","version":2,"id":5,"schemaType":"JSON","schema":"{}"}"#,
+        )
+        .unwrap();
+        fs::write(openweb_dir.join("3.json.tmp"), "{").unwrap();
+
+        let mut server = Server::new_async().await;
+        let get_mode = server
+            .mock("GET", "/mode")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"mode":"READWRITE"}"#)
+            .create();
+        let import_mode = server
+            .mock("PUT", "/mode?force=true")
+            .match_body(r#"{"mode":"IMPORT"}"#)
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"mode":"IMPORT"}"#)
+            .create();
+        let openweb = server
+            .mock("POST", "/subjects/nl%2Fopenweb/versions")
+            .match_body(mockito::Matcher::PartialJsonString(String::from(
+                r#"{"id":5,"version":2,"schemaType":"JSON"}"#,
+            )))
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"id":5}"#)
+            .create();
+        let heartbeat = server
+            .mock("POST", "/subjects/heartbeat-value/versions")
+            .match_body(mockito::Matcher::PartialJsonString(String::from(
+                r#"{"id":7,"version":1,"references":[{"name":"openweb.json","subject":"nl/openweb","version":2}]}"#,
+            )))
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"id":7}"#)
+            .create();
+        let read_write_mode = server
+            .mock("PUT", "/mode?force=false")
+            .match_body(r#"{"mode":"READWRITE"}"#)
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"mode":"READWRITE"}"#)
+            .create();
+
+        let schemas = in_reference_order(read_dump(&path).await.unwrap());
+        let ids: Vec<u32> = schemas.iter().map(|s| s.id).collect();
+        assert_eq!(ids, vec![5, 7]);
+
+        let sr_settings = SrSettings::new(server.url());
+        assert_eq!(import_registry(&sr_settings, &path, true).await, Ok(2));
+
+        get_mode.assert_async().await;
+        import_mode.assert_async().await;
+        openweb.assert_async().await;
+        heartbeat.assert_async().await;
+        read_write_mode.assert_async().await;
+        fs::remove_dir_all(&path).unwrap();
+    }
+}
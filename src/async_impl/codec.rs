@@ -0,0 +1,63 @@
+//! Traits implemented by all the async encoders and decoders, so code can be generic over the
+//! schema type, instead of needing the same glue code for each encoder and decoder.
+
+#[cfg(feature = "avro")]
+use crate::async_impl::avro::AvroEncoder;
+pub use crate::async_impl::decoder_stream::PayloadDecoder;
+#[cfg(feature = "json")]
+use crate::async_impl::json::JsonEncoder;
+#[cfg(feature = "proto_raw")]
+use crate::async_impl::proto_raw::ProtoRawEncoder;
+use crate::async_impl::{SrFuture, SrFutureExt};
+use crate::error::SRCError;
+use crate::schema_registry_common::SubjectNameStrategy;
+
+/// Implemented by the encoders, so they can be used with for example the `SrProducer`.
+pub trait PayloadEncoder {
+    type Input: ?Sized;
+    /// Encodes the value, the same way as the encode function of the encoder.
+    fn encode_payload<'a>(
+        &'a self,
+        value: &'a Self::Input,
+        subject_name_strategy: SubjectNameStrategy,
+    ) -> SrFuture<'a, Result<Vec<u8>, SRCError>>;
+}
+
+#[cfg(feature = "avro")]
+impl PayloadEncoder for AvroEncoder<'_> {
+    type Input = Vec<(&'static str, apache_avro::types::Value)>;
+    fn encode_payload<'a>(
+        &'a self,
+        value: &'a Self::Input,
+        subject_name_strategy: SubjectNameStrategy,
+    ) -> SrFuture<'a, Result<Vec<u8>, SRCError>> {
+        self.encode(value.clone(), subject_name_strategy).sr_boxed()
+    }
+}
+
+#[cfg(feature = "json")]
+impl PayloadEncoder for JsonEncoder<'_> {
+    type Input = serde_json::Value;
+    fn encode_payload<'a>(
+        &'a self,
+        value: &'a serde_json::Value,
+        subject_name_strategy: SubjectNameStrategy,
+    ) -> SrFuture<'a, Result<Vec<u8>, SRCError>> {
+        self.encode(value, subject_name_strategy).sr_boxed()
+    }
+}
+
+/// The input is the full name of the message, together with the encoded protobuf bytes.
+#[cfg(feature = "proto_raw")]
+impl PayloadEncoder for ProtoRawEncoder<'_> {
+    type Input = (String, Vec<u8>);
+    fn encode_payload<'a>(
+        &'a self,
+        value: &'a (String, Vec<u8>),
+        subject_name_strategy: SubjectNameStrategy,
+    ) -> SrFuture<'a, Result<Vec<u8>, SRCError>> {
+        let (full_name, bytes) = value;
+        self.encode(bytes, full_name, subject_name_strategy)
+            .sr_boxed()
+    }
+}
@@ -0,0 +1,156 @@
+//! Makes it possible to decode a stream of payloads, for example created from the rdkafka
+//! StreamConsumer, into a stream of decoded values. Errors are returned as items of the stream,
+//! so one failing payload doesn't end the stream.
+//!
+//! ```no_run
+//! use std::fmt::Debug;
+//! use futures::StreamExt;
+//! use rdkafka::consumer::StreamConsumer;
+//! use rdkafka::message::Message;
+//! use schema_registry_converter::async_impl::decoder_stream::{decode_stream, PayloadDecoder};
+//! async fn print_values<D: PayloadDecoder>(consumer: &StreamConsumer, decoder: &D)
+//! where
+//!     D::Output: Debug,
+//! {
+//!     let payloads = consumer
+//!         .stream()
+//!         .filter_map(|m| async move { m.ok().map(|m| m.payload().map(|p| p.to_vec())) });
+//!     let mut values = Box::pin(decode_stream(decoder, payloads));
+//!     while let Some(result) = values.next().await {
+//!         match result {
+//!             Ok(r) => println!("Decoded value: {:?}", r),
+//!             Err(e) => println!("Error decoding value: {}", e),
+//!         }
+//!     }
+//! }
+//! ```
+use futures::{Stream, StreamExt};
+
+#[cfg(feature = "avro")]
+use crate::async_impl::avro::AvroDecoder;
+#[cfg(feature = "json")]
+use crate::async_impl::json::{DecodeResult as JsonDecodeResult, JsonDecoder};
+#[cfg(feature = "proto_decoder")]
+use crate::async_impl::proto_decoder::{DecodeResultWithContext, ProtoDecoder};
+#[cfg(feature = "proto_raw")]
+use crate::async_impl::proto_raw::{ProtoRawDecoder, RawDecodeResult};
+use crate::async_impl::{SrFuture, SrFutureExt};
+#[cfg(feature = "avro")]
+use crate::avro_common::DecodeResult;
+use crate::error::SRCError;
+
+/// Implemented by the decoders so they can be used with [decode_stream].
+pub trait PayloadDecoder {
+    type Output;
+    /// Decodes the payload, the same way as the decode function of the decoder.
+    fn decode_payload<'a>(
+        &'a self,
+        bytes: Option<&'a [u8]>,
+    ) -> SrFuture<'a, Result<Self::Output, SRCError>>;
+}
+
+#[cfg(feature = "avro")]
+impl PayloadDecoder for AvroDecoder<'_> {
+    type Output = DecodeResult;
+    fn decode_payload<'a>(
+        &'a self,
+        bytes: Option<&'a [u8]>,
+    ) -> SrFuture<'a, Result<DecodeResult, SRCError>> {
+        self.decode(bytes).sr_boxed()
+    }
+}
+
+#[cfg(feature = "json")]
+impl PayloadDecoder for JsonDecoder<'_> {
+    type Output = Option<JsonDecodeResult>;
+    fn decode_payload<'a>(
+        &'a self,
+        bytes: Option<&'a [u8]>,
+    ) -> SrFuture<'a, Result<Option<JsonDecodeResult>, SRCError>> {
+        self.decode(bytes).sr_boxed()
+    }
+}
+
+#[cfg(feature = "proto_decoder")]
+impl PayloadDecoder for ProtoDecoder<'_> {
+    type Output = Option<DecodeResultWithContext>;
+    fn decode_payload<'a>(
+        &'a self,
+        bytes: Option<&'a [u8]>,
+    ) -> SrFuture<'a, Result<Option<DecodeResultWithContext>, SRCError>> {
+        self.decode_with_context(bytes).sr_boxed()
+    }
+}
+
+#[cfg(feature = "proto_raw")]
+impl PayloadDecoder for ProtoRawDecoder<'_> {
+    type Output = Option<RawDecodeResult>;
+    fn decode_payload<'a>(
+        &'a self,
+        bytes: Option<&'a [u8]>,
+    ) -> SrFuture<'a, Result<Option<RawDecodeResult>, SRCError>> {
+        self.decode(bytes).sr_boxed()
+    }
+}
+
+/// Transforms a stream of payloads into a stream of decode results, in the same order. A payload
+/// that can't be decoded gives an error item, after which the next payloads are still decoded.
+pub fn decode_stream<'a, D, S, P>(
+    decoder: &'a D,
+    payloads: S,
+) -> impl Stream<Item = Result<D::Output, SRCError>> + 'a
+where
+    D: PayloadDecoder,
+    S: Stream<Item = Option<P>> + 'a,
+    P: AsRef<[u8]> + 'a,
+{
+    payloads.then(move |payload| async move {
+        decoder
+            .decode_payload(payload.as_ref().map(|p| p.as_ref()))
+            .await
+    })
+}
+
+#[cfg(all(test, feature = "proto_raw"))]
+mod tests {
+    use futures::{stream, StreamExt};
+    use mockito::Server;
+
+    use crate::async_impl::decoder_stream::decode_stream;
+    use crate::async_impl::proto_raw::ProtoRawDecoder;
+    use crate::async_impl::schema_registry::SrSettings;
+    use test_utils::{get_proto_body, get_proto_hb_101, get_proto_hb_schema};
+
+    #[tokio::test]
+    async fn test_decode_stream() {
+        let mut server = Server::new_async().await;
+        let _m = server
+            .mock("GET", "/schemas/ids/7?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_proto_body(get_proto_hb_schema(), 1))
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let decoder = ProtoRawDecoder::new(sr_settings);
+        let payloads = stream::iter(vec![
+            Some(get_proto_hb_101().to_vec()),
+            Some(vec![1, 2, 3]),
+            None,
+            Some(get_proto_hb_101().to_vec()),
+        ]);
+        let results: Vec<_> = decode_stream(&decoder, payloads).collect().await;
+
+        assert_eq!(results.len(), 4);
+        assert_eq!(
+            results[0].as_ref().unwrap().as_ref().unwrap().bytes,
+            vec![8, 101]
+        );
+        assert!(results[1].is_err());
+        assert!(results[2].as_ref().unwrap().is_none());
+        assert_eq!(
+            results[3].as_ref().unwrap().as_ref().unwrap().bytes,
+            vec![8, 101]
+        );
+    }
+}
@@ -44,14 +44,14 @@ impl EasyAvroEncoder {
     pub async fn encode(
         &self,
         values: Vec<(&str, Value)>,
-        subject_name_strategy: SubjectNameStrategy,
+        subject_name_strategy: impl Into<SubjectNameStrategy>,
     ) -> Result<Vec<u8>, SRCError> {
         self.encoder.encode(values, subject_name_strategy).await
     }
     pub async fn encode_struct(
         &self,
         item: impl Serialize,
-        subject_name_strategy: &SubjectNameStrategy,
+        subject_name_strategy: impl Into<SubjectNameStrategy>,
     ) -> Result<Vec<u8>, SRCError> {
         self.encoder
             .encode_struct(item, subject_name_strategy)
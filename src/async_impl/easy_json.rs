@@ -6,6 +6,7 @@ use serde_json::Value;
 use std::sync::Arc;
 
 /// A decoder used to transform bytes to a [DecodeResult], its much like [JsonDecoder] but wrapped with an arc to make it easier.
+#[derive(Clone)]
 pub struct EasyJsonDecoder {
     decoder: Arc<JsonDecoder<'static>>,
 }
@@ -15,9 +16,17 @@ impl EasyJsonDecoder {
         let decoder = Arc::new(JsonDecoder::new(sr_settings));
         EasyJsonDecoder { decoder }
     }
+    /// Creates a decoder with the default settings for the schema registry at the url.
+    pub fn from_url(url: &str) -> EasyJsonDecoder {
+        EasyJsonDecoder::new(SrSettings::new(String::from(url)))
+    }
     pub async fn decode(&self, bytes: Option<&[u8]>) -> Result<Option<DecodeResult>, SRCError> {
         self.decoder.decode(bytes).await
     }
+    /// Same as decode, but only gives back the value, without the schema.
+    pub async fn decode_value(&self, bytes: Option<&[u8]>) -> Result<Option<Value>, SRCError> {
+        Ok(self.decoder.decode(bytes).await?.map(|r| r.value))
+    }
 }
 
 /// An encoder used to transform a [Value] to bytes, its much like [JsonEncoder] but wrapped with an arc to make it easier.
@@ -33,7 +42,7 @@ impl EasyJsonEncoder {
     pub async fn encode(
         &self,
         value: &Value,
-        subject_name_strategy: SubjectNameStrategy,
+        subject_name_strategy: impl Into<SubjectNameStrategy>,
     ) -> Result<Vec<u8>, SRCError> {
         self.encoder.encode(value, subject_name_strategy).await
     }
@@ -97,6 +106,30 @@ mod tests {
         )
     }
 
+    #[tokio::test]
+    async fn test_decode_value_from_url() {
+        let result_value: String = read_to_string("tests/schema/result-example.json")
+            .unwrap()
+            .parse()
+            .unwrap();
+        let mut server = Server::new_async().await;
+        let _m = server
+            .mock("GET", "/schemas/ids/7?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_json_body(json_result_schema(), 7))
+            .create();
+
+        let decoder = EasyJsonDecoder::from_url(&server.url());
+        let value = decoder
+            .decode_value(Some(&*get_payload(7, result_value.into_bytes())))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(value["down"], "string");
+        assert_eq!(value["up"], "STRING")
+    }
+
     #[tokio::test]
     async fn test_encode_default() {
         let mut server = Server::new_async().await;
@@ -1,12 +1,15 @@
 use crate::async_impl::proto_decoder::{DecodeResultWithContext, ProtoDecoder};
 use crate::async_impl::schema_registry::SrSettings;
 use crate::error::SRCError;
+use bytes::Bytes;
 use protofish::decode::Value;
+use serde::de::DeserializeOwned;
 use std::sync::Arc;
 
 /// A decoder used to transform bytes to a [Value], its much like [ProtoDecoder] but wrapped with an arc to make it easier.
 /// The mean use of this way of decoding is if you don't know the format at compile time.
 /// If you do know the format it's better to use the ProtoRawDecoder, and use a different library to deserialize just the proto bytes.
+#[derive(Clone)]
 pub struct EasyProtoDecoder {
     decoder: Arc<ProtoDecoder<'static>>,
 }
@@ -16,6 +19,10 @@ impl EasyProtoDecoder {
         let decoder = Arc::new(ProtoDecoder::new(sr_settings));
         EasyProtoDecoder { decoder }
     }
+    /// Creates a decoder with the default settings for the schema registry at the url.
+    pub fn from_url(url: &str) -> EasyProtoDecoder {
+        EasyProtoDecoder::new(SrSettings::new(String::from(url)))
+    }
     pub async fn decode(&self, bytes: Option<&[u8]>) -> Result<Value, SRCError> {
         self.decoder.decode(bytes).await
     }
@@ -25,6 +32,31 @@ impl EasyProtoDecoder {
     ) -> Result<Option<DecodeResultWithContext>, SRCError> {
         self.decoder.decode_with_context(bytes).await
     }
+    pub async fn decode_bytes(
+        &self,
+        bytes: Option<Bytes>,
+    ) -> Result<Option<DecodeResultWithContext>, SRCError> {
+        self.decoder.decode_bytes(bytes).await
+    }
+    pub async fn decode_json(
+        &self,
+        bytes: Option<&[u8]>,
+    ) -> Result<Option<serde_json::Value>, SRCError> {
+        self.decoder.decode_json(bytes).await
+    }
+    pub async fn decode_as<T: DeserializeOwned>(
+        &self,
+        bytes: Option<&[u8]>,
+    ) -> Result<Option<T>, SRCError> {
+        self.decoder.decode_as(bytes).await
+    }
+    #[cfg(feature = "prost_decoder")]
+    pub async fn decode_prost<T: prost::Message + prost::Name + Default>(
+        &self,
+        bytes: Option<&[u8]>,
+    ) -> Result<Option<T>, SRCError> {
+        self.decoder.decode_prost(bytes).await
+    }
 }
 
 #[cfg(test)]
@@ -34,6 +66,7 @@ mod tests {
     use crate::async_impl::schema_registry::SrSettings;
 
     use protofish::decode::Value;
+    use serde_json::json;
     use test_utils::{get_proto_body, get_proto_hb_101, get_proto_hb_schema};
 
     #[tokio::test]
@@ -80,4 +113,24 @@ mod tests {
 
         assert_eq!(Value::UInt64(101u64), message.fields[0].value)
     }
+
+    #[tokio::test]
+    async fn test_decode_json_from_url() {
+        let mut server = Server::new_async().await;
+        let _m = server
+            .mock("GET", "/schemas/ids/7?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_proto_body(get_proto_hb_schema(), 1))
+            .create();
+
+        let decoder = EasyProtoDecoder::from_url(&server.url());
+        let heartbeat = decoder
+            .clone()
+            .decode_json(Some(get_proto_hb_101()))
+            .await
+            .unwrap();
+
+        assert_eq!(heartbeat, Some(json!({"beat": 101})))
+    }
 }
@@ -1,7 +1,8 @@
 use crate::async_impl::proto_raw::{ProtoRawDecoder, ProtoRawEncoder, RawDecodeResult};
 use crate::async_impl::schema_registry::SrSettings;
 use crate::error::SRCError;
-use crate::schema_registry_common::SubjectNameStrategy;
+use crate::proto_raw_common::ProtoSchema;
+use crate::schema_registry_common::{SubjectNameStrategy, Version};
 use std::sync::Arc;
 
 /// A decoder used to transform bytes to a [RawDecodeResult], its much like [ProtoRawDecoder] but wrapped with an arc to make it easier.
@@ -36,7 +37,7 @@ impl EasyProtoRawEncoder {
         &self,
         bytes: &[u8],
         full_name: &str,
-        subject_name_strategy: SubjectNameStrategy,
+        subject_name_strategy: impl Into<SubjectNameStrategy>,
     ) -> Result<Vec<u8>, SRCError> {
         self.encoder
             .encode(bytes, full_name, subject_name_strategy)
@@ -45,12 +46,44 @@ impl EasyProtoRawEncoder {
     pub async fn encode_single_message(
         &self,
         bytes: &[u8],
-        subject_name_strategy: SubjectNameStrategy,
+        subject_name_strategy: impl Into<SubjectNameStrategy>,
     ) -> Result<Vec<u8>, SRCError> {
         self.encoder
             .encode_single_message(bytes, subject_name_strategy)
             .await
     }
+    pub async fn encode_with_version(
+        &self,
+        bytes: &[u8],
+        full_name: &str,
+        subject_name_strategy: impl Into<SubjectNameStrategy>,
+        version: impl Into<Version>,
+    ) -> Result<Vec<u8>, SRCError> {
+        self.encoder
+            .encode_with_version(bytes, full_name, subject_name_strategy, version)
+            .await
+    }
+    pub async fn encode_with_auto_register(
+        &self,
+        bytes: &[u8],
+        full_name: &str,
+        subject_name_strategy: impl Into<SubjectNameStrategy>,
+        schema: &ProtoSchema,
+    ) -> Result<Vec<u8>, SRCError> {
+        self.encoder
+            .encode_with_auto_register(bytes, full_name, subject_name_strategy, schema)
+            .await
+    }
+    pub async fn encode_with_schema_id(
+        &self,
+        bytes: &[u8],
+        full_name: &str,
+        id: u32,
+    ) -> Result<Vec<u8>, SRCError> {
+        self.encoder
+            .encode_with_schema_id(bytes, full_name, id)
+            .await
+    }
 }
 
 #[cfg(test)]
@@ -58,7 +91,7 @@ mod tests {
     use mockito::Server;
     use crate::async_impl::easy_proto_raw::{EasyProtoRawDecoder, EasyProtoRawEncoder};
     use crate::async_impl::schema_registry::SrSettings;
-    use crate::schema_registry_common::SubjectNameStrategy;
+    use crate::schema_registry_common::{SubjectNameStrategy, Version};
 
     use test_utils::{
         get_proto_body, get_proto_hb_101, get_proto_hb_101_only_data, get_proto_hb_schema,
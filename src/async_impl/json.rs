@@ -1,23 +1,44 @@
-use std::str::FromStr;
+use std::any::type_name;
+use std::borrow::Cow;
 use std::sync::Arc;
 
 use dashmap::mapref::entry::Entry;
 use dashmap::DashMap;
-use futures::future::{BoxFuture, FutureExt, Shared};
+use futures::future::{try_join, FutureExt, Shared};
 use futures::stream::{self, StreamExt};
+use serde::de::DeserializeOwned;
 use serde_json::Value;
 use url::Url;
 use valico::json_schema::schema::ScopedSchema;
 use valico::json_schema::Scope;
 
 use crate::async_impl::schema_registry::{
-    get_referenced_schema, get_schema_by_id_and_type, get_schema_by_subject, SrSettings,
+    get_allowed_schema, get_migration, get_referenced_schema, get_schema_by_subject, SrSettings,
 };
+#[cfg(feature = "json_schemars")]
+use crate::async_impl::schema_registry::{lookup_schema, post_schema};
+use crate::async_impl::{SrFuture, SrFutureExt};
 use crate::error::SRCError;
-use crate::json_common::{fetch_fallback, fetch_id, handle_validation, to_bytes, to_value};
+use crate::field_encryption::{Direction, FieldEncryptor};
+#[cfg(feature = "json_schemars")]
+use crate::json_common::schemars_schema;
+#[cfg(feature = "json_schemars")]
+pub use crate::json_common::BoundJsonEncoder;
+use crate::json_common::{
+    apply_field_encryption, fetch_fallback, fetch_id, handle_validation, reference_url, to_value,
+    trailing_json_bytes, write_bytes,
+};
+pub use crate::json_common::{JsonDraft, JsonValidation};
+use crate::migration::{migrate, Migration, MigrationConfig};
 use crate::schema_registry_common::{
-    get_bytes_result, BytesResult, RegisteredReference, RegisteredSchema, SchemaType,
-    SubjectNameStrategy,
+    check_policy, check_remaining_bytes, to_wire_format_error, BytesResult, DecodeMetadata,
+    DecodeResultWithMetadata, DecodedRecord, DecoderOptions, DeserializationErrorPolicy,
+    IdStrategy, Metadata, RegisteredSchema, RuleSet, SchemaPolicy, SchemaType, SchemaUsageKind,
+    SlowPathWarnings, Strictness, SubjectNameStrategy,
+};
+use crate::telemetry::{
+    decode_start, record_cache_lookup, record_decode_error, warn_registry_fetch, warn_slow_decode,
+    CachedSchemas,
 };
 
 /// Encoder that works by prepending the correct bytes in order to make it valid schema registry
@@ -29,9 +50,12 @@ pub struct JsonEncoder<'a> {
     sr_settings: SrSettings,
     direct_cache: DashMap<String, Arc<JsonSchema>>,
     cache: DashMap<String, SharedFutureSchema<'a>>,
+    validation: JsonValidation,
+    id_strategy: IdStrategy,
+    field_encryptor: Option<Arc<dyn FieldEncryptor>>,
 }
 
-type SharedFutureSchema<'a> = Shared<BoxFuture<'a, Result<Arc<JsonSchema>, SRCError>>>;
+type SharedFutureSchema<'a> = Shared<SrFuture<'a, Result<Arc<JsonSchema>, SRCError>>>;
 
 impl<'a> JsonEncoder<'a> {
     /// Creates a new json encoder
@@ -40,8 +64,31 @@ impl<'a> JsonEncoder<'a> {
             sr_settings,
             direct_cache: DashMap::new(),
             cache: DashMap::new(),
+            validation: JsonValidation::default(),
+            id_strategy: IdStrategy::default(),
+            field_encryptor: None,
         }
     }
+    /// Sets whether, and using which draft, values are validated before encoding. By default they
+    /// are validated, using the draft from the `$schema` of the schema.
+    pub fn with_validation(mut self, validation: JsonValidation) -> JsonEncoder<'a> {
+        self.validation = validation;
+        self
+    }
+    /// Sets how the schema id is added to the json bytes, by default as 4 bytes. With the id in a
+    /// header, only the json bytes are returned.
+    pub fn with_id_strategy(mut self, id_strategy: IdStrategy) -> JsonEncoder<'a> {
+        self.id_strategy = id_strategy;
+        self
+    }
+    /// Sets the encryptor used to encrypt the properties tagged by an ENCRYPT rule of the schema.
+    pub fn with_field_encryptor(
+        mut self,
+        field_encryptor: Arc<dyn FieldEncryptor>,
+    ) -> JsonEncoder<'a> {
+        self.field_encryptor = Some(field_encryptor);
+        self
+    }
     /// Removes errors from the cache, can be usefull to retry failed encodings.
     pub fn remove_errors_from_cache(&self) {
         self.cache.retain(|_, v| match v.peek() {
@@ -54,13 +101,66 @@ impl<'a> JsonEncoder<'a> {
     pub async fn encode(
         &self,
         value: &Value,
-        subject_name_strategy: SubjectNameStrategy,
+        subject_name_strategy: impl Into<SubjectNameStrategy>,
     ) -> Result<Vec<u8>, SRCError> {
+        let subject_name_strategy = subject_name_strategy.into();
         let key = subject_name_strategy.get_subject()?;
-        let schema = &*self.get_schema(key, subject_name_strategy).await?;
-        let id = schema.id;
-        validate(schema.clone(), value)?;
-        to_bytes(id, value)
+        let schema = self.get_schema(key, subject_name_strategy).await?;
+        let mut buf = Vec::new();
+        self.write_value(&schema, value, &mut buf)?;
+        Ok(buf)
+    }
+    /// Like `encode`, but writing the bytes to the buffer, which is cleared first. This way the
+    /// buffer, and its allocation, can be reused for multiple values.
+    pub async fn encode_into(
+        &self,
+        value: &Value,
+        subject_name_strategy: impl Into<SubjectNameStrategy>,
+        buf: &mut Vec<u8>,
+    ) -> Result<(), SRCError> {
+        let subject_name_strategy = subject_name_strategy.into();
+        let key = subject_name_strategy.get_subject()?;
+        let schema = self.get_schema(key, subject_name_strategy).await?;
+        self.write_value(&schema, value, buf)
+    }
+    /// Encodes multiple values for the same subject, getting the schema only once, which saves the
+    /// cache lookups when producing a batch of records. Stops at, and returns, the first error.
+    pub async fn encode_many(
+        &self,
+        values: &[Value],
+        subject_name_strategy: impl Into<SubjectNameStrategy>,
+    ) -> Result<Vec<Vec<u8>>, SRCError> {
+        let subject_name_strategy = subject_name_strategy.into();
+        let key = subject_name_strategy.get_subject()?;
+        let schema = self.get_schema(key, subject_name_strategy).await?;
+        values
+            .iter()
+            .map(|value| {
+                let mut buf = Vec::new();
+                self.write_value(&schema, value, &mut buf)?;
+                Ok(buf)
+            })
+            .collect()
+    }
+
+    fn write_value(
+        &self,
+        schema: &JsonSchema,
+        value: &Value,
+        buf: &mut Vec<u8>,
+    ) -> Result<(), SRCError> {
+        if self.validation.is_enabled() {
+            validate_with(schema.clone(), value, self.validation)?;
+        }
+        let value = apply_field_encryption(
+            self.field_encryptor.as_deref(),
+            schema.rule_set.as_ref(),
+            schema.metadata.as_ref(),
+            &schema.schema,
+            Cow::Borrowed(value),
+            Direction::Write,
+        )?;
+        write_bytes(schema.id, &value, &self.id_strategy, buf)
     }
 
     async fn get_schema(
@@ -73,11 +173,20 @@ impl<'a> JsonEncoder<'a> {
                 let result = self
                     .get_schema_by_shared_future(key.clone(), subject_name_strategy)
                     .await;
-                if result.is_ok() && !self.direct_cache.contains_key(&key) {
-                    self.direct_cache
-                        .insert(key.clone(), result.clone().unwrap());
-                    self.cache.remove(&key);
+                if let Ok(schema) = &result {
+                    if let Entry::Vacant(e) = self.direct_cache.entry(key.clone()) {
+                        e.insert(schema.clone());
+                        self.cache.remove(&key);
+                        self.sr_settings.record_usage(
+                            SchemaUsageKind::Encoded,
+                            schema.id,
+                            Some(&key),
+                        );
+                    }
                 };
+                if matches!(&result, Err(e) if e.is_throttled()) {
+                    self.cache.remove(&key);
+                }
                 result
             }
             Some(result) => Ok(result.value().clone()),
@@ -92,7 +201,7 @@ impl<'a> JsonEncoder<'a> {
         match self.cache.entry(key) {
             Entry::Occupied(e) => e.get().clone(),
             Entry::Vacant(e) => {
-                let sr_settings = self.sr_settings.clone();
+                let sr_settings = self.sr_settings.counting_references();
                 let v = async move {
                     match get_schema_by_subject(&sr_settings, &subject_name_strategy).await {
                         Ok(schema) => match to_json_schema(&sr_settings, None, schema).await {
@@ -102,7 +211,7 @@ impl<'a> JsonEncoder<'a> {
                         Err(e) => Err(e.into_cache()),
                     }
                 }
-                .boxed()
+                .sr_boxed()
                 .shared();
                 e.insert(v).value().clone()
             }
@@ -110,11 +219,44 @@ impl<'a> JsonEncoder<'a> {
     }
 }
 
-fn add_refs_to_scope(scope: &mut Scope, schema: JsonSchema) -> Result<ScopedSchema<'_>, SRCError> {
+/// Generates the json schema for the type using schemars, and registers it for the subject. When
+/// the same schema was already registered, the existing id is used. The returned encoder is bound
+/// to the id of the schema.
+#[cfg(feature = "json_schemars")]
+pub async fn register_schemars<T: schemars::JsonSchema + serde::Serialize>(
+    sr_settings: &SrSettings,
+    subject: &str,
+) -> Result<BoundJsonEncoder<T>, SRCError> {
+    let schema = schemars_schema::<T>()?;
+    let registered = post_schema(sr_settings, String::from(subject), schema).await?;
+    Ok(BoundJsonEncoder::new(registered.id))
+}
+
+/// Generates the json schema for the type using schemars, and verifies it's already registered for
+/// the subject, without registering it. The returned encoder is bound to the id of the schema.
+#[cfg(feature = "json_schemars")]
+pub async fn verify_schemars<T: schemars::JsonSchema + serde::Serialize>(
+    sr_settings: &SrSettings,
+    subject: &str,
+) -> Result<BoundJsonEncoder<T>, SRCError> {
+    let schema = schemars_schema::<T>()?;
+    let registered = lookup_schema(sr_settings, subject, &SchemaType::Json, &schema.schema).await?;
+    Ok(BoundJsonEncoder::new(registered.id))
+}
+
+fn add_refs_to_scope(
+    scope: &mut Scope,
+    schema: JsonSchema,
+    validation: JsonValidation,
+) -> Result<ScopedSchema<'_>, SRCError> {
     for reference in schema.references.into_iter() {
-        add_refs_to_scope(scope, reference)?;
+        // the same schema might be referenced more than once, but can only be compiled once
+        if scope.resolve(&reference.url).is_none() {
+            add_refs_to_scope(scope, reference, validation)?;
+        }
     }
-    match scope.compile_and_return_with_id(&schema.url, schema.schema, false) {
+    let def = validation.prepare(schema.schema);
+    match scope.compile_and_return_with_id(&schema.url, def, false) {
         Ok(v) => Ok(v),
         Err(e) => Err(SRCError::non_retryable_with_cause(
             e,
@@ -124,8 +266,18 @@ fn add_refs_to_scope(scope: &mut Scope, schema: JsonSchema) -> Result<ScopedSche
 }
 
 pub fn validate(schema: JsonSchema, value: &Value) -> Result<(), SRCError> {
+    validate_with(schema, value, JsonValidation::Detect)
+}
+
+/// Validates the value using the draft from the validation. When the value is not valid, the
+/// violations are set on the error.
+pub fn validate_with(
+    schema: JsonSchema,
+    value: &Value,
+    validation: JsonValidation,
+) -> Result<(), SRCError> {
     let mut scope = Scope::new();
-    let schema = add_refs_to_scope(&mut scope, schema)?;
+    let schema = add_refs_to_scope(&mut scope, schema, validation)?;
     let validation = schema.validate(value);
     handle_validation(validation, value)
 }
@@ -138,13 +290,27 @@ pub struct JsonSchema {
     pub url: Url,
     pub schema: Value,
     pub references: Vec<JsonSchema>,
+    /// The rules of the registered schema, used for field level encryption.
+    pub rule_set: Option<RuleSet>,
+    pub metadata: Option<Metadata>,
 }
 
-#[derive(Debug)]
+/// Cloning the decoder is cheap, and the clones share the cache.
+#[derive(Debug, Clone)]
 pub struct JsonDecoder<'a> {
     sr_settings: SrSettings,
-    direct_cache: DashMap<u32, Arc<JsonSchema>>,
-    cache: DashMap<u32, SharedFutureSchema<'a>>,
+    direct_cache: Arc<DashMap<u32, Arc<JsonSchema>>>,
+    cache: Arc<DashMap<u32, SharedFutureSchema<'a>>>,
+    validation: JsonValidation,
+    id_strategy: IdStrategy,
+    strictness: Strictness,
+    error_policy: DeserializationErrorPolicy,
+    field_encryptor: Option<Arc<dyn FieldEncryptor>>,
+    migration: Option<MigrationConfig>,
+    migrations: Arc<DashMap<u32, Arc<Migration>>>,
+    slow_path: SlowPathWarnings,
+    policy: Option<Arc<dyn SchemaPolicy>>,
+    cached_schemas: Arc<CachedSchemas>,
 }
 
 impl<'a> JsonDecoder<'a> {
@@ -156,10 +322,85 @@ impl<'a> JsonDecoder<'a> {
     pub fn new(sr_settings: SrSettings) -> JsonDecoder<'a> {
         JsonDecoder {
             sr_settings,
-            direct_cache: DashMap::new(),
-            cache: DashMap::new(),
+            direct_cache: Arc::new(DashMap::new()),
+            cache: Arc::new(DashMap::new()),
+            validation: JsonValidation::None,
+            id_strategy: IdStrategy::default(),
+            strictness: Strictness::Lenient,
+            error_policy: DeserializationErrorPolicy::default(),
+            field_encryptor: None,
+            migration: None,
+            migrations: Arc::new(DashMap::new()),
+            slow_path: SlowPathWarnings::default(),
+            policy: None,
+            cached_schemas: Arc::new(CachedSchemas::new("json")),
         }
     }
+    /// Sets whether, and using which draft, decoded values are validated against the schema of the
+    /// id in the payload. By default they are not validated. When a value is not valid, the error
+    /// contains the violations, so the message can be put aside.
+    pub fn with_validation(mut self, validation: JsonValidation) -> JsonDecoder<'a> {
+        self.validation = validation;
+        self
+    }
+    /// Sets how the schema id is read, which needs to be the same as used by the encoder. With
+    /// the id in a header, use decode_with_headers.
+    pub fn with_id_strategy(mut self, id_strategy: IdStrategy) -> JsonDecoder<'a> {
+        self.id_strategy = id_strategy;
+        self
+    }
+    /// Sets how payloads that don't follow the wire format are handled. Payloads without the magic
+    /// byte and id always give an error with the [WireFormatError](crate::error::WireFormatError).
+    /// When strict, bytes left after decoding the json value give an error with it too.
+    pub fn with_strictness(mut self, strictness: Strictness) -> JsonDecoder<'a> {
+        self.strictness = strictness;
+        self
+    }
+    /// Sets what decode_or_skip does with payloads that can't be decoded.
+    pub fn with_error_policy(
+        mut self,
+        error_policy: DeserializationErrorPolicy,
+    ) -> JsonDecoder<'a> {
+        self.error_policy = error_policy;
+        self
+    }
+    /// Sets the encryptor used to decrypt the properties tagged by an ENCRYPT rule of the schema.
+    pub fn with_field_encryptor(
+        mut self,
+        field_encryptor: Arc<dyn FieldEncryptor>,
+    ) -> JsonDecoder<'a> {
+        self.field_encryptor = Some(field_encryptor);
+        self
+    }
+    /// Reads the values as the latest version of the subject with the metadata of the migration,
+    /// applying the migration rules of the versions in between when a value was written with
+    /// another version. Validation is done with the schema the value is read as.
+    pub fn with_migration(mut self, migration: MigrationConfig) -> JsonDecoder<'a> {
+        self.migration = Some(migration);
+        self
+    }
+    /// Sets which slow paths of decode are logged as a warning, like a decode taking longer than a
+    /// threshold, or a decode needing to fetch the schema.
+    pub fn with_slow_path_warnings(mut self, slow_path: SlowPathWarnings) -> JsonDecoder<'a> {
+        self.slow_path = slow_path;
+        self
+    }
+    /// Sets the policy restricting which schema's are fetched and decoded. Payloads with a schema
+    /// id that's not allowed give an error with a `PolicyViolation`, which is not cached, so a
+    /// change of the policy is applied directly.
+    pub fn with_policy(mut self, policy: Arc<dyn SchemaPolicy>) -> JsonDecoder<'a> {
+        self.policy = Some(policy);
+        self
+    }
+    /// Sets the options shared by all the decoders, replacing the id strategy, strictness, slow
+    /// path warnings and policy set before.
+    pub fn with_options(mut self, options: DecoderOptions) -> JsonDecoder<'a> {
+        self.id_strategy = options.id_strategy;
+        self.strictness = options.strictness;
+        self.slow_path = options.slow_path;
+        self.policy = options.policy;
+        self
+    }
     /// Remove al the errors from the cache, you might need to/want to run this when a recoverable
     /// error is met. Errors are also cashed to prevent trying to get schema's that either don't
     /// exist or can't be parsed.
@@ -169,44 +410,202 @@ impl<'a> JsonDecoder<'a> {
             None => true,
         });
     }
+    /// Removes the migrations to the latest version of the subject of the migration from the cache,
+    /// so a new latest version with the metadata is picked up on the next decode. Long-lived
+    /// consumers can call this when they are signaled a new version was registered. The schema's
+    /// for the id's don't change, so they are kept.
+    pub fn invalidate_subject(&self, subject: &str) {
+        if matches!(&self.migration, Some(config) if config.subject == subject) {
+            self.migrations.clear();
+        }
+    }
+    /// Decodes the bytes, applying the error policy when they can't be decoded. None is returned
+    /// both for null bytes and for skipped bytes.
+    pub async fn decode_or_skip(
+        &self,
+        bytes: Option<&[u8]>,
+    ) -> Result<Option<DecodeResult>, SRCError> {
+        let result = self.decode(bytes).await;
+        Ok(self.error_policy.apply(bytes, result)?.flatten())
+    }
+    /// Decodes both the key and the payload of a record, concurrently, the same way as decode.
+    pub async fn decode_message(
+        &self,
+        key: Option<&[u8]>,
+        payload: Option<&[u8]>,
+    ) -> Result<DecodedRecord<Option<DecodeResult>>, SRCError> {
+        let (key, value) = try_join(self.decode(key), self.decode(payload)).await?;
+        Ok(DecodedRecord { key, value })
+    }
     /// Reads the bytes to get the name, and gives back the data bytes.
     pub async fn decode(&self, bytes: Option<&[u8]>) -> Result<Option<DecodeResult>, SRCError> {
-        match get_bytes_result(bytes) {
+        self.decode_with_headers(bytes, &[]).await
+    }
+    /// Decodes bytes, using the headers of the record to get the schema id when the id strategy
+    /// has the id in a header.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "decode", level = "debug", skip_all, err)
+    )]
+    pub async fn decode_with_headers(
+        &self,
+        bytes: Option<&[u8]>,
+        headers: &[(&str, &[u8])],
+    ) -> Result<Option<DecodeResult>, SRCError> {
+        let result = match self.id_strategy.get_bytes_result(bytes, headers) {
             BytesResult::Null => Ok(None),
-            BytesResult::Valid(id, bytes) => Ok(Some(self.deserialize(id, &bytes).await?)),
-            BytesResult::Invalid(i) => Err(SRCError::non_retryable_without_cause(&format!(
-                "Invalid bytes: {:?}",
-                i
-            ))),
+            BytesResult::Valid(id, bytes) => {
+                let start = decode_start(&self.slow_path);
+                let result = self.deserialize(id, &bytes).await;
+                warn_slow_decode(&self.slow_path, start, id, || None);
+                Ok(Some(result?))
+            }
+            BytesResult::Invalid(i) => Err(SRCError::from(to_wire_format_error(&i))),
+        };
+        if result.is_err() {
+            record_decode_error("json");
         }
+        result
+    }
+    /// Decodes bytes into a value, together with the id of the schema, so messages can be routed
+    /// without another lookup.
+    pub async fn decode_with_metadata(
+        &self,
+        bytes: Option<&[u8]>,
+    ) -> Result<Option<DecodeResultWithMetadata<Value>>, SRCError> {
+        Ok(self.decode(bytes).await?.map(|r| DecodeResultWithMetadata {
+            metadata: DecodeMetadata {
+                schema_id: r.schema.id,
+                full_name: None,
+            },
+            value: r.value,
+        }))
+    }
+    /// Decodes bytes directly into any struct implementing Deserialize. Without validation, a field
+    /// encryptor or a migration the bytes are deserialized without a Value in between, and the
+    /// schema is not retrieved. When the value doesn't match the struct, the error contains the id
+    /// of the schema.
+    pub async fn decode_as<T: DeserializeOwned>(
+        &self,
+        bytes: Option<&[u8]>,
+    ) -> Result<Option<T>, SRCError> {
+        match self.id_strategy.get_bytes_result(bytes, &[]) {
+            BytesResult::Null => Ok(None),
+            BytesResult::Valid(id, bytes) => {
+                let result = if self.needs_value() {
+                    serde_json::from_value(self.deserialize(id, &bytes).await?.value)
+                } else {
+                    serde_json::from_slice(&bytes)
+                };
+                match result {
+                    Ok(v) => Ok(Some(v)),
+                    Err(e) => {
+                        check_remaining_bytes(trailing_json_bytes(&bytes), self.strictness)?;
+                        Err(SRCError::non_retryable_with_cause(
+                            e,
+                            &format!(
+                                "could not deserialize value with schema id {} into {}",
+                                id,
+                                type_name::<T>()
+                            ),
+                        ))
+                    }
+                }
+            }
+            BytesResult::Invalid(i) => Err(SRCError::from(to_wire_format_error(&i))),
+        }
+    }
+    /// Whether the bytes need to be decoded to a value first, before deserializing into a struct.
+    fn needs_value(&self) -> bool {
+        self.validation.is_enabled() || self.field_encryptor.is_some() || self.migration.is_some()
     }
     /// The actual deserialization trying to get the id from the bytes to retrieve the schema, and
     /// using a reader transforms the bytes to a value.
     async fn deserialize(&self, id: u32, bytes: &[u8]) -> Result<DecodeResult, SRCError> {
-        let schema = &*self.get_schema(id).await?;
-        match serde_json::from_slice(bytes) {
-            Ok(value) => Ok(DecodeResult {
-                schema: schema.clone(),
-                value,
-            }),
-            Err(e) => Err(SRCError::non_retryable_with_cause(
-                e,
-                "could not create value from bytes",
-            )),
+        let schema = self.get_schema(id).await?;
+        let value = match serde_json::from_slice(bytes) {
+            Ok(v) => v,
+            Err(e) => {
+                check_remaining_bytes(trailing_json_bytes(bytes), self.strictness)?;
+                return Err(SRCError::non_retryable_with_cause(
+                    e,
+                    "could not create value from bytes",
+                ));
+            }
+        };
+        let value = apply_field_encryption(
+            self.field_encryptor.as_deref(),
+            schema.rule_set.as_ref(),
+            schema.metadata.as_ref(),
+            &schema.schema,
+            Cow::Owned(value),
+            Direction::Read,
+        )?
+        .into_owned();
+        let (schema, value) = self.migrate(id, schema, value).await?;
+        if self.validation.is_enabled() {
+            validate_with((*schema).clone(), &value, self.validation)?;
         }
+        Ok(DecodeResult {
+            schema: (*schema).clone(),
+            value,
+        })
+    }
+
+    /// Gives the schema to read the value as, with the value migrated to it when a migration is
+    /// set.
+    async fn migrate(
+        &self,
+        id: u32,
+        schema: Arc<JsonSchema>,
+        value: Value,
+    ) -> Result<(Arc<JsonSchema>, Value), SRCError> {
+        let config = match &self.migration {
+            Some(v) => v,
+            None => return Ok((schema, value)),
+        };
+        let cached = self.migrations.get(&id).map(|m| m.value().clone());
+        let migration = match cached {
+            Some(v) => v,
+            None => {
+                let migration = Arc::new(get_migration(&self.sr_settings, config, id).await?);
+                self.migrations.insert(id, migration.clone());
+                migration
+            }
+        };
+        if migration.reader_id == id {
+            return Ok((schema, value));
+        }
+        let reader_schema = self.get_schema(migration.reader_id).await?;
+        let value = migrate(&*config.executor, &migration.rules, value)?;
+        Ok((reader_schema, value))
     }
 
     async fn get_schema(&self, id: u32) -> Result<Arc<JsonSchema>, SRCError> {
+        check_policy(self.policy.as_deref(), id)?;
         match self.direct_cache.get(&id) {
             None => {
+                record_cache_lookup(id, false);
+                warn_registry_fetch(&self.slow_path, id);
                 let result = self.get_schema_by_shared_future(id).await;
-                if result.is_ok() && !self.direct_cache.contains_key(&id) {
-                    self.direct_cache.insert(id, result.clone().unwrap());
-                    self.cache.remove(&id);
+                if let Ok(schema) = &result {
+                    if let Entry::Vacant(e) = self.direct_cache.entry(id) {
+                        e.insert(schema.clone());
+                        self.cache.remove(&id);
+                        self.cached_schemas.added();
+                        self.sr_settings
+                            .record_usage(SchemaUsageKind::Decoded, id, None);
+                    }
                 };
+                if matches!(&result, Err(e) if e.is_throttled()) {
+                    self.cache.remove(&id);
+                }
                 result
             }
-            Some(result) => Ok(result.value().clone()),
+            Some(result) => {
+                record_cache_lookup(id, true);
+                Ok(result.value().clone())
+            }
         }
     }
 
@@ -216,9 +615,12 @@ impl<'a> JsonDecoder<'a> {
         match self.cache.entry(id) {
             Entry::Occupied(e) => e.get().clone(),
             Entry::Vacant(e) => {
-                let sr_settings = self.sr_settings.clone();
+                let sr_settings = self.sr_settings.counting_references();
+                let policy = self.policy.clone();
                 let v = async move {
-                    match get_schema_by_id_and_type(id, &sr_settings, SchemaType::Json).await {
+                    match get_allowed_schema(id, &sr_settings, SchemaType::Json, policy.as_deref())
+                        .await
+                    {
                         Ok(schema) => match to_json_schema(&sr_settings, None, schema).await {
                             Ok(v) => Ok(Arc::new(v)),
                             Err(e) => Err(e),
@@ -226,7 +628,7 @@ impl<'a> JsonDecoder<'a> {
                         Err(e) => Err(e.into_cache()),
                     }
                 }
-                .boxed()
+                .sr_boxed()
                 .shared();
                 e.insert(v).value().clone()
             }
@@ -234,13 +636,6 @@ impl<'a> JsonDecoder<'a> {
     }
 }
 
-fn reference_url(rr: &RegisteredReference) -> Result<Url, SRCError> {
-    match Url::from_str(&rr.name) {
-        Ok(v) => Ok(v),
-        Err(e) => Err(SRCError::non_retryable_with_cause(e, &format!("reference schema with subject {} and version {} has invalid id {}, it has to be a fully qualified url", rr.subject, rr.version, rr.name)))
-    }
-}
-
 fn main_url(schema: &Value, sr_settings: &SrSettings, id: u32) -> Url {
     match fetch_id(schema) {
         Some(url) => url,
@@ -252,11 +647,17 @@ fn to_json_schema(
     sr_settings: &SrSettings,
     optional_url: Option<Url>,
     registered_schema: RegisteredSchema,
-) -> BoxFuture<Result<JsonSchema, SRCError>> {
+) -> SrFuture<Result<JsonSchema, SRCError>> {
     async move {
+        let schema: Value = to_value(&registered_schema.schema)?;
+        let url = match optional_url {
+            Some(v) => v,
+            None => main_url(&schema, sr_settings, registered_schema.id),
+        };
+        let base = &url;
         let refs: Result<Vec<JsonSchema>, SRCError> = stream::iter(registered_schema.references)
             .then(|rr| async move {
-                let url = reference_url(&rr)?;
+                let url = reference_url(base, &rr)?;
                 let rs = get_referenced_schema(sr_settings, &rr).await?;
                 to_json_schema(sr_settings, Some(url), rs).await
             })
@@ -265,19 +666,16 @@ fn to_json_schema(
             .into_iter()
             .collect();
         let references = refs?;
-        let schema: Value = to_value(&registered_schema.schema)?;
-        let url = match optional_url {
-            Some(v) => v,
-            None => main_url(&schema, sr_settings, registered_schema.id),
-        };
         Ok(JsonSchema {
             id: registered_schema.id,
             url,
             schema,
             references,
+            rule_set: registered_schema.rule_set,
+            metadata: registered_schema.metadata,
         })
     }
-    .boxed()
+    .sr_boxed()
 }
 
 /// This decode result is not validated yet, if you want to validate you need to call the validate
@@ -293,11 +691,13 @@ mod tests {
     use std::fs::{read_to_string, File};
     use mockito::Server;
 
-    use serde_json::Value;
+    use serde::Deserialize;
+    use serde_json::{json, Value};
 
-    use crate::async_impl::json::{validate, JsonDecoder, JsonEncoder};
+    use crate::async_impl::json::{validate, JsonDecoder, JsonEncoder, JsonValidation};
     use crate::async_impl::schema_registry::SrSettings;
-    use crate::schema_registry_common::{get_payload, SubjectNameStrategy};
+    use crate::error::WireFormatError;
+    use crate::schema_registry_common::{get_payload, Strictness, SubjectNameStrategy};
     use test_utils::{
         get_json_body, get_json_body_with_reference, json_get_result_references,
         json_incorrect_bytes, json_result_java_bytes, json_result_schema,
@@ -348,6 +748,124 @@ mod tests {
         assert_eq!(encoded_data, json_result_java_bytes())
     }
 
+    #[tokio::test]
+    async fn test_encode_many() {
+        let mut server = Server::new_async().await;
+        let m = server
+            .mock("GET", "/subjects/testresult-value/versions/latest")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_json_body(json_result_schema(), 10))
+            .expect(1)
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let encoder = JsonEncoder::new(sr_settings);
+        let result_example: Value =
+            serde_json::from_reader(File::open("tests/schema/result-example.json").unwrap())
+                .unwrap();
+
+        let encoded_data = encoder
+            .encode_many(
+                &[result_example.clone(), result_example],
+                ("testresult", false),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            encoded_data,
+            vec![json_result_java_bytes(), json_result_java_bytes()]
+        );
+        m.assert();
+    }
+
+    #[tokio::test]
+    async fn test_encode_many_stops_at_first_error() {
+        let mut server = Server::new_async().await;
+        let _m = server
+            .mock("GET", "/subjects/testresult-value/versions/latest")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_json_body(json_result_schema(), 10))
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let encoder = JsonEncoder::new(sr_settings);
+        let values = [
+            json!({"up": "STRING", "down": "string"}),
+            json!({"up": "STRING", "down": 3}),
+            json!({"up": 3, "down": "string"}),
+        ];
+
+        let error = encoder
+            .encode_many(&values, ("testresult", false))
+            .await
+            .unwrap_err();
+
+        assert_eq!(error.violations().len(), 1);
+        assert_eq!(error.violations()[0].path, "/down");
+    }
+
+    #[tokio::test]
+    async fn test_encode_validation() {
+        let mut server = Server::new_async().await;
+        let _m = server
+            .mock("GET", "/subjects/testresult-value/versions/latest")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_json_body(json_result_schema(), 10))
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let strategy = SubjectNameStrategy::TopicNameStrategy(String::from("testresult"), false);
+        let invalid: Value = serde_json::from_str(r#"{"up":"STRING","down":3}"#).unwrap();
+
+        let encoder = JsonEncoder::new(sr_settings.clone());
+        let error = encoder
+            .encode(&invalid, strategy.clone())
+            .await
+            .unwrap_err();
+        assert_eq!(error.violations().len(), 1);
+        assert_eq!(error.violations()[0].path, "/down");
+
+        let encoder = JsonEncoder::new(sr_settings).with_validation(JsonValidation::None);
+        let encoded_data = encoder.encode(&invalid, strategy).await.unwrap();
+        assert_eq!(&encoded_data[..5], &[0, 0, 0, 0, 10]);
+    }
+
+    #[tokio::test]
+    async fn test_encode_relative_reference() {
+        let mut server = Server::new_async().await;
+        let _m = server
+            .mock("GET", "/subjects/wrapper-value/versions/latest")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_json_body_with_reference(
+                r#"{\"type\":\"object\",\"properties\":{\"result\":{\"$ref\":\"result.schema.json\"}},\"required\":[\"result\"]}"#,
+                12,
+                r#"{"name": "result.schema.json", "subject": "result", "version": 1}"#,
+            ))
+            .create();
+        let _r = server
+            .mock("GET", "/subjects/result/versions/1")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_json_body(json_result_schema(), 11))
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let encoder = JsonEncoder::new(sr_settings);
+        let strategy = SubjectNameStrategy::TopicNameStrategy(String::from("wrapper"), false);
+        let valid: Value =
+            serde_json::from_str(r#"{"result":{"up":"STRING","down":"string"}}"#).unwrap();
+        let invalid: Value = serde_json::from_str(r#"{"result":{"up":"STRING"}}"#).unwrap();
+
+        assert!(encoder.encode(&valid, strategy.clone()).await.is_ok());
+        let error = encoder.encode(&invalid, strategy).await.unwrap_err();
+        assert_eq!(error.violations()[0].code, "required");
+    }
+
     #[tokio::test]
     async fn test_encode_clean_cache() {
         let mut server = Server::new_async().await;
@@ -424,6 +942,51 @@ mod tests {
         )
     }
 
+    #[tokio::test]
+    async fn test_decode_as() {
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct TestResult {
+            up: String,
+            down: String,
+        }
+
+        let mut server = Server::new_async().await;
+        let _m = server
+            .mock("GET", "/schemas/ids/7?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_json_body(json_result_schema(), 7))
+            .expect(1)
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let valid = get_payload(7, br#"{"up":"STRING","down":"string"}"#.to_vec());
+        let invalid = get_payload(7, br#"{"up":"STRING"}"#.to_vec());
+        let expected = TestResult {
+            up: String::from("STRING"),
+            down: String::from("string"),
+        };
+
+        let decoder = JsonDecoder::new(sr_settings.clone());
+        let result = decoder.decode_as::<TestResult>(Some(&valid)).await;
+        assert_eq!(result, Ok(Some(expected)));
+        let error = decoder
+            .decode_as::<TestResult>(Some(&invalid))
+            .await
+            .unwrap_err();
+        assert!(error
+            .error
+            .starts_with("could not deserialize value with schema id 7"));
+
+        let decoder = JsonDecoder::new(sr_settings).with_validation(JsonValidation::Detect);
+        let error = decoder
+            .decode_as::<TestResult>(Some(&invalid))
+            .await
+            .unwrap_err();
+        assert_eq!(error.violations()[0].code, "required");
+        _m.assert();
+    }
+
     #[tokio::test]
     async fn test_decoder_clean_cache() {
         let mut server = Server::new_async().await;
@@ -755,11 +1318,77 @@ mod tests {
             .ends_with("was not valid because of missing references"))
     }
 
+    #[cfg(feature = "json_schemars")]
+    #[tokio::test]
+    async fn test_schemars() {
+        use crate::async_impl::json::{register_schemars, verify_schemars};
+
+        #[derive(serde::Serialize, schemars::JsonSchema)]
+        struct Heartbeat {
+            beat: i64,
+        }
+
+        let mut server = Server::new_async().await;
+        let register = server
+            .mock("POST", "/subjects/heartbeat-value/versions")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"id":12}"#)
+            .create();
+        let lookup = server
+            .mock("POST", "/subjects/heartbeat-value")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"subject":"heartbeat-value","version":1,"id":12,"schemaType":"JSON","schema":"{}"}"#)
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let encoder = register_schemars::<Heartbeat>(&sr_settings, "heartbeat-value")
+            .await
+            .unwrap();
+        assert_eq!(encoder.id(), 12);
+        let bytes = encoder.encode(&Heartbeat { beat: 3 }).unwrap();
+        assert_eq!(bytes, get_payload(12, br#"{"beat":3}"#.to_vec()));
+
+        let encoder = verify_schemars::<Heartbeat>(&sr_settings, "heartbeat-value")
+            .await
+            .unwrap();
+        assert_eq!(encoder.id(), 12);
+
+        register.assert();
+        lookup.assert();
+    }
+
     #[tokio::test]
     async fn decode_invalid_bytes() {
         let sr_settings = SrSettings::new(String::from("http://127.0.0.1:1234"));
         let decoder = JsonDecoder::new(sr_settings);
         let result = decoder.decode(Some(&[1, 0])).await.unwrap_err();
-        assert_eq!(String::from("Invalid bytes: [1, 0]"), result.error)
+        assert_eq!(
+            result.wire_format(),
+            Some(&WireFormatError::InvalidMagicByte(1))
+        );
+        assert_eq!(
+            String::from("Invalid magic byte 1, expected 0"),
+            result.error
+        )
+    }
+
+    #[tokio::test]
+    async fn decode_strict_trailing_bytes() {
+        let sr_settings = SrSettings::new(String::from("http://127.0.0.1:1234"));
+        let lenient = JsonDecoder::new(sr_settings.clone());
+        let strict = JsonDecoder::new(sr_settings).with_strictness(Strictness::Strict);
+        let bytes: &[u8] = b"\0\0\0\0\x07{} x";
+
+        let error = lenient.decode_as::<Value>(Some(bytes)).await.unwrap_err();
+        assert_eq!(error.wire_format(), None);
+        let error = strict.decode_as::<Value>(Some(bytes)).await.unwrap_err();
+        assert_eq!(
+            error.wire_format(),
+            Some(&WireFormatError::TrailingBytes(2))
+        );
+        let value = strict.decode_as::<Value>(Some(&bytes[..7])).await.unwrap();
+        assert_eq!(value, Some(json!({})));
     }
 }
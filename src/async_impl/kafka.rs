@@ -0,0 +1,130 @@
+//! Wrappers around the rdkafka StreamConsumer and FutureProducer, decoding the payloads of consumed
+//! messages, and encoding the values of produced messages, with one of the decoders or encoders.
+//!
+//! ```no_run
+//! use rdkafka::config::ClientConfig;
+//! use rdkafka::consumer::Consumer;
+//! use schema_registry_converter::async_impl::avro::AvroDecoder;
+//! use schema_registry_converter::async_impl::kafka::SrConsumer;
+//! use schema_registry_converter::async_impl::schema_registry::SrSettings;
+//! async fn print_values() {
+//!     let consumer = ClientConfig::new()
+//!         .set("group.id", "example")
+//!         .set("bootstrap.servers", "localhost:9092")
+//!         .create()
+//!         .unwrap();
+//!     let decoder = AvroDecoder::new(SrSettings::new(String::from("http://localhost:8081")));
+//!     let consumer = SrConsumer::new(consumer, decoder);
+//!     consumer.consumer().subscribe(&["heartbeat"]).unwrap();
+//!     loop {
+//!         match consumer.recv_decoded().await {
+//!             Ok(m) => println!("Decoded value: {:?}", m.value),
+//!             Err(e) => println!("Error receiving value: {}", e),
+//!         }
+//!     }
+//! }
+//! ```
+use rdkafka::consumer::StreamConsumer;
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use rdkafka::util::Timeout;
+use rdkafka::Message;
+
+use crate::async_impl::codec::{PayloadDecoder, PayloadEncoder};
+use crate::error::SRCError;
+use crate::schema_registry_common::SubjectNameStrategy;
+
+/// A consumed message, with the decoded payload.
+#[derive(Debug)]
+pub struct DecodedMessage<T> {
+    pub topic: String,
+    pub partition: i32,
+    pub offset: i64,
+    pub key: Option<Vec<u8>>,
+    pub value: T,
+}
+
+/// Wraps a StreamConsumer, decoding the payload of each received message with the decoder.
+pub struct SrConsumer<D> {
+    consumer: StreamConsumer,
+    decoder: D,
+}
+
+impl<D: PayloadDecoder> SrConsumer<D> {
+    pub fn new(consumer: StreamConsumer, decoder: D) -> SrConsumer<D> {
+        SrConsumer { consumer, decoder }
+    }
+
+    /// The wrapped consumer, to subscribe, or commit offsets.
+    pub fn consumer(&self) -> &StreamConsumer {
+        &self.consumer
+    }
+
+    /// Receives the next message, and decodes its payload. Errors from Kafka are retryable, while
+    /// errors from decoding are the same as from the decoder.
+    pub async fn recv_decoded(&self) -> Result<DecodedMessage<D::Output>, SRCError> {
+        let message = self.consumer.recv().await.map_err(|e| {
+            SRCError::retryable_with_cause(e, "could not receive message from kafka")
+        })?;
+        let value = self.decoder.decode_payload(message.payload()).await?;
+        Ok(DecodedMessage {
+            topic: String::from(message.topic()),
+            partition: message.partition(),
+            offset: message.offset(),
+            key: message.key().map(|k| k.to_vec()),
+            value,
+        })
+    }
+}
+
+/// Wraps a FutureProducer, encoding the value of each sent message with the encoder.
+pub struct SrProducer<E> {
+    producer: FutureProducer,
+    encoder: E,
+}
+
+impl<E: PayloadEncoder> SrProducer<E> {
+    pub fn new(producer: FutureProducer, encoder: E) -> SrProducer<E> {
+        SrProducer { producer, encoder }
+    }
+
+    /// The wrapped producer, to flush, or send messages without encoding.
+    pub fn producer(&self) -> &FutureProducer {
+        &self.producer
+    }
+
+    /// Encodes the value using the TopicNameStrategy, and sends it with the key. Returns the
+    /// partition and offset of the message.
+    pub async fn send_encoded(
+        &self,
+        topic: &str,
+        key: Option<&[u8]>,
+        value: &E::Input,
+    ) -> Result<(i32, i64), SRCError> {
+        let strategy = SubjectNameStrategy::TopicNameStrategy(String::from(topic), false);
+        self.send_encoded_with_strategy(topic, key, value, strategy)
+            .await
+    }
+
+    /// Encodes the value using the given strategy, and sends it with the key. Returns the
+    /// partition and offset of the message.
+    pub async fn send_encoded_with_strategy(
+        &self,
+        topic: &str,
+        key: Option<&[u8]>,
+        value: &E::Input,
+        subject_name_strategy: impl Into<SubjectNameStrategy>,
+    ) -> Result<(i32, i64), SRCError> {
+        let payload = self
+            .encoder
+            .encode_payload(value, subject_name_strategy.into())
+            .await?;
+        let mut record: FutureRecord<[u8], Vec<u8>> = FutureRecord::to(topic).payload(&payload);
+        if let Some(key) = key {
+            record = record.key(key);
+        }
+        self.producer
+            .send(record, Timeout::Never)
+            .await
+            .map_err(|(e, _)| SRCError::retryable_with_cause(e, "could not send message to kafka"))
+    }
+}
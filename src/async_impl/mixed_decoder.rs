@@ -0,0 +1,86 @@
+//! Decodes the key and the payload of a record with different decoders, for topics where for
+//! example the key is a protobuf message, and the value is an avro record.
+use futures::future::try_join;
+
+use crate::async_impl::decoder_stream::PayloadDecoder;
+use crate::error::SRCError;
+use crate::schema_registry_common::DecodedRecord;
+
+/// Holds a decoder for the key, and one for the payload, which may be of different schema types.
+#[derive(Debug)]
+pub struct MixedDecoder<K, V> {
+    key_decoder: K,
+    value_decoder: V,
+}
+
+impl<K: PayloadDecoder, V: PayloadDecoder> MixedDecoder<K, V> {
+    pub fn new(key_decoder: K, value_decoder: V) -> MixedDecoder<K, V> {
+        MixedDecoder {
+            key_decoder,
+            value_decoder,
+        }
+    }
+
+    /// Decodes both the key and the payload of a record, concurrently, each the same way as the
+    /// decode function of its decoder.
+    pub async fn decode_message(
+        &self,
+        key: Option<&[u8]>,
+        payload: Option<&[u8]>,
+    ) -> Result<DecodedRecord<K::Output, V::Output>, SRCError> {
+        let (key, value) = try_join(
+            self.key_decoder.decode_payload(key),
+            self.value_decoder.decode_payload(payload),
+        )
+        .await?;
+        Ok(DecodedRecord { key, value })
+    }
+}
+
+#[cfg(all(test, feature = "avro", feature = "proto_raw"))]
+mod tests {
+    use apache_avro::types::Value;
+    use mockito::Server;
+
+    use crate::async_impl::avro::AvroDecoder;
+    use crate::async_impl::mixed_decoder::MixedDecoder;
+    use crate::async_impl::proto_raw::ProtoRawDecoder;
+    use crate::async_impl::schema_registry::SrSettings;
+    use test_utils::{get_proto_body, get_proto_hb_101, get_proto_hb_schema};
+
+    #[tokio::test]
+    async fn test_mixed_key_and_value() {
+        let mut server = Server::new_async().await;
+        let _p = server
+            .mock("GET", "/schemas/ids/7?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_proto_body(get_proto_hb_schema(), 1))
+            .create();
+        let _a = server.mock("GET", "/schemas/ids/1?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"schema":"{\"type\":\"record\",\"name\":\"Heartbeat\",\"namespace\":\"nl.openweb.data\",\"fields\":[{\"name\":\"beat\",\"type\":\"long\"}]}"}"#)
+            .create();
+
+        let decoder = MixedDecoder::new(
+            ProtoRawDecoder::new(SrSettings::new(server.url())),
+            AvroDecoder::new(SrSettings::new(server.url())),
+        );
+        let record = decoder
+            .decode_message(Some(get_proto_hb_101()), Some(&[0, 0, 0, 0, 1, 6]))
+            .await
+            .unwrap();
+
+        assert_eq!(record.key.unwrap().bytes, vec![8, 101]);
+        assert_eq!(
+            record.value.value,
+            Value::Record(vec![(String::from("beat"), Value::Long(3))])
+        );
+
+        let record = decoder
+            .decode_message(None, Some(&[0, 0, 0, 0, 1, 6]))
+            .await;
+        assert!(record.unwrap().key.is_none());
+    }
+}
@@ -1,5 +1,9 @@
 #[cfg(feature = "avro")]
 pub mod avro;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod backup;
+pub mod codec;
+pub mod decoder_stream;
 #[cfg(all(feature = "easy", feature = "avro"))]
 pub mod easy_avro;
 #[cfg(all(feature = "easy", feature = "json"))]
@@ -10,8 +14,44 @@ pub mod easy_proto_decoder;
 pub mod easy_proto_raw;
 #[cfg(feature = "json")]
 pub mod json;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+pub mod mixed_decoder;
+pub mod multi_format;
 #[cfg(feature = "proto_decoder")]
 pub mod proto_decoder;
 #[cfg(feature = "proto_raw")]
 pub mod proto_raw;
+#[cfg(feature = "proto_reflect")]
+pub mod proto_reflect;
 pub mod schema_registry;
+#[cfg(feature = "sync_bridge")]
+pub mod sync_bridge;
+#[cfg(feature = "watch")]
+pub mod watch;
+
+/// The boxed future used by the async encoders and decoders. On wasm32 the futures of reqwest, using
+/// fetch, are not Send, so there the future doesn't need to be Send either.
+#[cfg(not(target_arch = "wasm32"))]
+pub type SrFuture<'a, T> = futures::future::BoxFuture<'a, T>;
+#[cfg(target_arch = "wasm32")]
+pub type SrFuture<'a, T> = futures::future::LocalBoxFuture<'a, T>;
+
+/// Boxes a future into a [SrFuture], needing Send except on wasm32.
+pub(crate) trait SrFutureExt<'a, T> {
+    fn sr_boxed(self) -> SrFuture<'a, T>;
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<'a, T, F: std::future::Future<Output = T> + Send + 'a> SrFutureExt<'a, T> for F {
+    fn sr_boxed(self) -> SrFuture<'a, T> {
+        Box::pin(self)
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl<'a, T, F: std::future::Future<Output = T> + 'a> SrFutureExt<'a, T> for F {
+    fn sr_boxed(self) -> SrFuture<'a, T> {
+        Box::pin(self)
+    }
+}
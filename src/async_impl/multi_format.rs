@@ -0,0 +1,167 @@
+//! A decoder for payloads of any schema type, for generic tooling like topic browsers, where the
+//! schema type isn't known up front. The schema type is fetched from the schema registry using the
+//! id in the payload, after which the decoder for that type is used. Only the schema types of
+//! enabled features can be decoded.
+use dashmap::DashMap;
+
+#[cfg(feature = "avro")]
+use crate::async_impl::avro::AvroDecoder;
+#[cfg(feature = "json")]
+use crate::async_impl::json::{DecodeResult as JsonDecodeResult, JsonDecoder};
+#[cfg(feature = "proto_decoder")]
+use crate::async_impl::proto_decoder::{DecodeResultWithContext, ProtoDecoder};
+use crate::async_impl::schema_registry::{get_schema_by_id, SrSettings};
+#[cfg(feature = "avro")]
+use crate::avro_common::DecodeResult as AvroDecodeResult;
+use crate::error::SRCError;
+use crate::schema_registry_common::{BytesResult, DecoderOptions, IdStrategy, SchemaType};
+
+/// The decoded value, as given by the decoder for the schema type.
+#[derive(Debug)]
+pub enum DecodedValue {
+    /// The payload was empty.
+    Null,
+    #[cfg(feature = "avro")]
+    Avro(AvroDecodeResult),
+    #[cfg(feature = "json")]
+    Json(JsonDecodeResult),
+    #[cfg(feature = "proto_decoder")]
+    Protobuf(DecodeResultWithContext),
+}
+
+/// Decodes payloads of any schema type, using the schema type of the id in the payload. The schema
+/// types of the id's are cached.
+#[derive(Debug)]
+pub struct MultiFormatDecoder<'a> {
+    sr_settings: SrSettings,
+    schema_types: DashMap<u32, SchemaType>,
+    id_strategy: IdStrategy,
+    #[cfg(feature = "avro")]
+    avro_decoder: AvroDecoder<'a>,
+    #[cfg(feature = "json")]
+    json_decoder: JsonDecoder<'a>,
+    #[cfg(feature = "proto_decoder")]
+    proto_decoder: ProtoDecoder<'a>,
+    #[cfg(not(any(feature = "avro", feature = "json", feature = "proto_decoder")))]
+    phantom: std::marker::PhantomData<&'a ()>,
+}
+
+impl<'a> MultiFormatDecoder<'a> {
+    pub fn new(sr_settings: SrSettings) -> MultiFormatDecoder<'a> {
+        MultiFormatDecoder {
+            #[cfg(feature = "avro")]
+            avro_decoder: AvroDecoder::new(sr_settings.clone()),
+            #[cfg(feature = "json")]
+            json_decoder: JsonDecoder::new(sr_settings.clone()),
+            #[cfg(feature = "proto_decoder")]
+            proto_decoder: ProtoDecoder::new(sr_settings.clone()),
+            #[cfg(not(any(feature = "avro", feature = "json", feature = "proto_decoder")))]
+            phantom: std::marker::PhantomData,
+            sr_settings,
+            schema_types: DashMap::new(),
+            id_strategy: IdStrategy::default(),
+        }
+    }
+
+    /// Sets the options of the decoders for each schema type. The id strategy is also used to get
+    /// the schema type.
+    pub fn with_options(mut self, options: DecoderOptions) -> MultiFormatDecoder<'a> {
+        #[cfg(feature = "avro")]
+        {
+            self.avro_decoder = self.avro_decoder.with_options(options.clone());
+        }
+        #[cfg(feature = "json")]
+        {
+            self.json_decoder = self.json_decoder.with_options(options.clone());
+        }
+        #[cfg(feature = "proto_decoder")]
+        {
+            self.proto_decoder = self.proto_decoder.with_options(options.clone());
+        }
+        self.id_strategy = options.id_strategy;
+        self
+    }
+
+    /// Decodes the bytes with the decoder for the schema type of the id in the bytes.
+    pub async fn decode(&self, bytes: Option<&[u8]>) -> Result<DecodedValue, SRCError> {
+        let id = match self.id_strategy.get_bytes_result(bytes, &[]) {
+            BytesResult::Null => return Ok(DecodedValue::Null),
+            BytesResult::Valid(id, _) => id,
+            BytesResult::Invalid(i) => {
+                return Err(SRCError::non_retryable_without_cause(&format!(
+                    "Invalid bytes {:?}",
+                    i
+                )))
+            }
+        };
+        match self.get_schema_type(id).await? {
+            #[cfg(feature = "avro")]
+            SchemaType::Avro => Ok(DecodedValue::Avro(self.avro_decoder.decode(bytes).await?)),
+            #[cfg(feature = "json")]
+            SchemaType::Json => match self.json_decoder.decode(bytes).await? {
+                Some(r) => Ok(DecodedValue::Json(r)),
+                None => Ok(DecodedValue::Null),
+            },
+            #[cfg(feature = "proto_decoder")]
+            SchemaType::Protobuf => match self.proto_decoder.decode_with_context(bytes).await? {
+                Some(r) => Ok(DecodedValue::Protobuf(r)),
+                None => Ok(DecodedValue::Null),
+            },
+            schema_type => Err(SRCError::non_retryable_without_cause(&format!(
+                "No decoder available for schema type {:?}, of schema with id {}",
+                schema_type, id
+            ))),
+        }
+    }
+
+    /// Gets the schema type of the id, from the cache or else from the schema registry.
+    pub async fn get_schema_type(&self, id: u32) -> Result<SchemaType, SRCError> {
+        if let Some(schema_type) = self.schema_types.get(&id) {
+            return Ok(schema_type.clone());
+        }
+        let schema_type = get_schema_by_id(id, &self.sr_settings).await?.schema_type;
+        self.schema_types.insert(id, schema_type.clone());
+        Ok(schema_type)
+    }
+}
+
+#[cfg(all(test, feature = "avro"))]
+mod tests {
+    use apache_avro::types::Value;
+    use mockito::Server;
+
+    use crate::async_impl::multi_format::{DecodedValue, MultiFormatDecoder};
+    use crate::async_impl::schema_registry::SrSettings;
+    use crate::schema_registry_common::SchemaType;
+
+    #[tokio::test]
+    async fn test_decode_by_schema_type() {
+        let mut server = Server::new_async().await;
+        let _a = server.mock("GET", "/schemas/ids/1?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"schema":"{\"type\":\"record\",\"name\":\"Heartbeat\",\"namespace\":\"nl.openweb.data\",\"fields\":[{\"name\":\"beat\",\"type\":\"long\"}]}"}"#)
+            .create();
+        let _x = server
+            .mock("GET", "/schemas/ids/2?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"schema":"<heartbeat/>","schemaType":"XML"}"#)
+            .create();
+
+        let decoder = MultiFormatDecoder::new(SrSettings::new(server.url()));
+        match decoder.decode(Some(&[0, 0, 0, 0, 1, 6])).await.unwrap() {
+            DecodedValue::Avro(r) => assert_eq!(
+                r.value,
+                Value::Record(vec![(String::from("beat"), Value::Long(3))])
+            ),
+            v => panic!("Expected an avro value, got {:?}", v),
+        }
+        assert_eq!(decoder.get_schema_type(1).await.unwrap(), SchemaType::Avro);
+        assert!(matches!(
+            decoder.decode(None).await.unwrap(),
+            DecodedValue::Null
+        ));
+        assert!(decoder.decode(Some(&[0, 0, 0, 0, 2, 6])).await.is_err());
+    }
+}
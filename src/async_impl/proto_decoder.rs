@@ -2,27 +2,54 @@ use std::collections::HashSet;
 use bytes::Bytes;
 use dashmap::mapref::entry::Entry;
 use dashmap::DashMap;
-use futures::future::{BoxFuture, Shared};
+use futures::future::{join_all, try_join, Shared};
 use futures::FutureExt;
 use std::sync::Arc;
 
-use crate::async_impl::schema_registry::{
-    get_referenced_schema, get_schema_by_id_and_type, SrSettings,
-};
+use crate::async_impl::schema_registry::{get_allowed_schema, get_referenced_schema, SrSettings};
+use crate::async_impl::{SrFuture, SrFutureExt};
 use crate::error::SRCError;
+use crate::field_encryption::FieldEncryptor;
 use crate::proto_common_types::add_common_files;
-use crate::proto_resolver::{resolve_name, to_index_and_data, MessageResolver};
-use crate::schema_registry_common::{get_bytes_result, BytesResult, RegisteredSchema, SchemaType};
+#[cfg(feature = "proto_descriptor")]
+use crate::proto_decoder_common::to_local_decode_context;
+use crate::proto_decoder_common::{
+    check_trailing_bytes, decode_message, decode_with_context, from_proto_value,
+    invalid_bytes_error, proto_value_to_json, to_decode_context,
+};
+pub use crate::proto_decoder_common::{DecodeContext, DecodeResultWithContext, MessageSelector};
+use crate::proto_resolver::find_imports;
+#[cfg(feature = "prost_decoder")]
+use crate::proto_resolver::to_prost;
+use crate::schema_registry_common::{
+    check_policy, BytesResult, DecodeMetadata, DecodeResultWithMetadata, DecodedRecord,
+    DecoderOptions, IdStrategy, RegisteredSchema, SchemaPolicy, SchemaType, SchemaUsageKind,
+    SlowPathWarnings, Strictness,
+};
+use crate::telemetry::{
+    decode_start, record_cache_lookup, record_decode_error, warn_batch_fetch_failed,
+    warn_registry_fetch, warn_slow_decode, CachedSchemas,
+};
 use protofish::context::Context;
 use protofish::decode::{MessageValue, Value};
+use serde::de::DeserializeOwned;
 
-type SharedFutureSchema<'a> = Shared<BoxFuture<'a, Result<Arc<Vec<String>>, SRCError>>>;
+type SharedFutureContext<'a> = Shared<SrFuture<'a, Result<Arc<DecodeContext>, SRCError>>>;
 
-#[derive(Debug)]
+/// Cloning the decoder is cheap, and the clones share the cache.
+#[derive(Debug, Clone)]
 pub struct ProtoDecoder<'a> {
     sr_settings: SrSettings,
-    direct_cache: DashMap<u32, Arc<Vec<String>>>,
-    cache: DashMap<u32, SharedFutureSchema<'a>>,
+    direct_cache: Arc<DashMap<u32, Arc<DecodeContext>>>,
+    cache: Arc<DashMap<u32, SharedFutureContext<'a>>>,
+    fallback: Arc<DashMap<u32, Arc<DecodeContext>>>,
+    message_selector: Option<MessageSelector>,
+    strictness: Strictness,
+    id_strategy: IdStrategy,
+    field_encryptor: Option<Arc<dyn FieldEncryptor>>,
+    slow_path: SlowPathWarnings,
+    policy: Option<Arc<dyn SchemaPolicy>>,
+    cached_schemas: Arc<CachedSchemas>,
 }
 
 impl<'a> ProtoDecoder<'a> {
@@ -34,10 +61,102 @@ impl<'a> ProtoDecoder<'a> {
     pub fn new(sr_settings: SrSettings) -> ProtoDecoder<'a> {
         ProtoDecoder {
             sr_settings,
-            direct_cache: DashMap::new(),
-            cache: DashMap::new(),
+            direct_cache: Arc::new(DashMap::new()),
+            cache: Arc::new(DashMap::new()),
+            fallback: Arc::new(DashMap::new()),
+            message_selector: None,
+            strictness: Strictness::Lenient,
+            id_strategy: IdStrategy::default(),
+            field_encryptor: None,
+            slow_path: SlowPathWarnings::default(),
+            policy: None,
+            cached_schemas: Arc::new(CachedSchemas::new("protobuf")),
         }
     }
+    /// Sets how payloads that don't follow the wire format are handled. When lenient, decode gives
+    /// back the payload as bytes, and the other functions give an error. When strict, all functions
+    /// give an error with the [WireFormatError](crate::error::WireFormatError), also when bytes
+    /// are left after decoding the message.
+    pub fn with_strictness(mut self, strictness: Strictness) -> Self {
+        self.strictness = strictness;
+        self
+    }
+    /// Sets how the schema id is read, which needs to be the same as used by the encoder. With
+    /// the id in a header, use decode_with_headers, the other functions need the id in the payload.
+    pub fn with_id_strategy(mut self, id_strategy: IdStrategy) -> ProtoDecoder<'a> {
+        self.id_strategy = id_strategy;
+        self
+    }
+    /// Sets the encryptor used to decrypt the fields tagged by an ENCRYPT rule of the schema. The
+    /// fields are tagged in the metadata of the schema, by the full name of the message followed
+    /// by the name of the field.
+    pub fn with_field_encryptor(
+        mut self,
+        field_encryptor: Arc<dyn FieldEncryptor>,
+    ) -> ProtoDecoder<'a> {
+        self.field_encryptor = Some(field_encryptor);
+        self
+    }
+    /// Sets which slow paths of decode are logged as a warning, like a decode taking longer than a
+    /// threshold, or a decode needing to fetch the schema.
+    pub fn with_slow_path_warnings(mut self, slow_path: SlowPathWarnings) -> ProtoDecoder<'a> {
+        self.slow_path = slow_path;
+        self
+    }
+    /// Sets the policy restricting which schema's are fetched and decoded. Payloads with a schema
+    /// id that's not allowed give an error with a `PolicyViolation`, which is not cached, so a
+    /// change of the policy is applied directly.
+    pub fn with_policy(mut self, policy: Arc<dyn SchemaPolicy>) -> ProtoDecoder<'a> {
+        self.policy = Some(policy);
+        self
+    }
+    /// Sets the options shared by all the decoders, replacing the id strategy, strictness, slow
+    /// path warnings and policy set before.
+    pub fn with_options(mut self, options: DecoderOptions) -> ProtoDecoder<'a> {
+        self.id_strategy = options.id_strategy;
+        self.strictness = options.strictness;
+        self.slow_path = options.slow_path;
+        self.policy = options.policy;
+        self
+    }
+    /// Sets the message selector, used to decode payloads without the message index. When the
+    /// selector gives a full name for the schema of the payload, the payload is expected to not
+    /// have the message index, and is decoded as that message.
+    pub fn with_message_selector(mut self, message_selector: MessageSelector) -> ProtoDecoder<'a> {
+        self.message_selector = Some(message_selector);
+        self
+    }
+    /// Creates a new decoder which uses the message selector to decode payloads without the message
+    /// index, the same as using new with with_message_selector.
+    pub fn new_with_message_selector(
+        sr_settings: SrSettings,
+        message_selector: MessageSelector,
+    ) -> ProtoDecoder<'a> {
+        ProtoDecoder::new(sr_settings).with_message_selector(message_selector)
+    }
+    /// Uses the schema from the serialized FileDescriptorSet for the id, instead of getting it from
+    /// the schema registry, so it's possible to decode without access to the schema registry. The
+    /// last file of the set is used as the schema, and should be the same as the registered one,
+    /// the other files are used as the imports.
+    #[cfg(feature = "proto_descriptor")]
+    pub fn add_schema_override(&self, id: u32, descriptor_set: &[u8]) -> Result<(), SRCError> {
+        let context = Arc::new(to_local_decode_context(id, descriptor_set)?);
+        if self.direct_cache.insert(id, context).is_none() {
+            self.cached_schemas.added();
+        }
+        self.cache.remove(&id);
+        Ok(())
+    }
+    /// Like add_schema_override, but the schema registry is tried first, and the schema from the
+    /// FileDescriptorSet is only used when getting the schema from the schema registry fails.
+    #[cfg(feature = "proto_descriptor")]
+    pub fn add_schema_fallback(&self, id: u32, descriptor_set: &[u8]) -> Result<(), SRCError> {
+        let context = Arc::new(to_local_decode_context(id, descriptor_set)?);
+        self.fallback.insert(id, context);
+        self.cache
+            .remove_if(&id, |_, v| matches!(v.peek(), Some(Err(_))));
+        Ok(())
+    }
     /// Remove all the errors from the cache, you might need to/want to run this when a recoverable
     /// error is met. Errors are also cashed to prevent trying to get schema's that either don't
     /// exist or can't be parsed.
@@ -47,171 +166,389 @@ impl<'a> ProtoDecoder<'a> {
             None => true,
         });
     }
+    /// Decodes both the key and the payload of a record, concurrently, the same way as decode.
+    pub async fn decode_message(
+        &self,
+        key: Option<&[u8]>,
+        payload: Option<&[u8]>,
+    ) -> Result<DecodedRecord<Value>, SRCError> {
+        let (key, value) = try_join(self.decode(key), self.decode(payload)).await?;
+        Ok(DecodedRecord { key, value })
+    }
     /// Decodes bytes into a value.
     /// The choice to use Option<&[u8]> as type us made so it plays nice with the BorrowedMessage
     /// struct from rdkafka, for example if we have m: &'a BorrowedMessage and decoder: &'a
     /// Decoder we can use decoder.decode(m.payload()) to decode the payload or
     /// decoder.decode(m.key()) to get the decoded key.
     pub async fn decode(&self, bytes: Option<&[u8]>) -> Result<Value, SRCError> {
-        match get_bytes_result(bytes) {
+        self.decode_with_headers(bytes, &[]).await
+    }
+    /// Decodes bytes into a value, using the headers of the record to get the schema id when the
+    /// id strategy has the id in a header.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "decode", level = "debug", skip_all, err)
+    )]
+    pub async fn decode_with_headers(
+        &self,
+        bytes: Option<&[u8]>,
+        headers: &[(&str, &[u8])],
+    ) -> Result<Value, SRCError> {
+        let result = match self.id_strategy.get_bytes_result(bytes, headers) {
             BytesResult::Null => Ok(Value::Bytes(Bytes::new())),
-            BytesResult::Valid(id, bytes) => Ok(Value::Message(Box::from(
-                self.deserialize(id, &bytes).await?,
-            ))),
-            BytesResult::Invalid(i) => Ok(Value::Bytes(Bytes::from(i))),
+            BytesResult::Valid(id, bytes) => self
+                .deserialize(id, bytes)
+                .await
+                .map(|v| Value::Message(Box::from(v))),
+            BytesResult::Invalid(i) => match self.strictness {
+                Strictness::Lenient => Ok(Value::Bytes(Bytes::from(i))),
+                Strictness::Strict => Err(invalid_bytes_error(&i, self.strictness)),
+            },
+        };
+        if result.is_err() {
+            record_decode_error("protobuf");
         }
+        result
     }
     /// The actual deserialization trying to get the id from the bytes to retrieve the schema, and
     /// using a reader transforms the bytes to a value.
-    async fn deserialize(&self, id: u32, bytes: &[u8]) -> Result<MessageValue, SRCError> {
-        let vec_of_schemas = self.get_vec_of_schemas(id).await?;
-        let context = into_decode_context(vec_of_schemas.to_vec())?;
-        let (index, data) = to_index_and_data(bytes);
-        let full_name = resolve_name(&context.resolver, &index)?;
-        let message_info = context.context.get_message(&full_name).unwrap();
-        Ok(message_info.decode(&data, &context.context))
+    async fn deserialize(&self, id: u32, bytes: Vec<u8>) -> Result<MessageValue, SRCError> {
+        Ok(self
+            .deserialize_with_context(id, Bytes::from(bytes))
+            .await?
+            .value)
     }
     /// Decodes bytes into a value.
     /// The choice to use Option<&[u8]> as type us made so it plays nice with the BorrowedMessage
     /// struct from rdkafka, for example if we have m: &'a BorrowedMessage and decoder: &'a
     /// Decoder we can use decoder.decode(m.payload()) to decode the payload or
     /// decoder.decode(m.key()) to get the decoded key.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "decode", level = "debug", skip_all, err)
+    )]
     pub async fn decode_with_context(
         &self,
         bytes: Option<&[u8]>,
     ) -> Result<Option<DecodeResultWithContext>, SRCError> {
-        match get_bytes_result(bytes) {
+        let result = match self.id_strategy.get_bytes_result(bytes, &[]) {
             BytesResult::Null => Ok(None),
-            BytesResult::Valid(id, bytes) => {
-                match self.deserialize_with_context(id, &bytes).await {
-                    Ok(v) => Ok(Some(v)),
-                    Err(e) => Err(e),
-                }
-            }
-            BytesResult::Invalid(_) => {
-                Err(SRCError::new("no protobuf compatible bytes", None, false))
+            BytesResult::Valid(id, bytes) => self
+                .deserialize_with_context(id, Bytes::from(bytes))
+                .await
+                .map(Some),
+            BytesResult::Invalid(i) => Err(invalid_bytes_error(&i, self.strictness)),
+        };
+        if result.is_err() {
+            record_decode_error("protobuf");
+        }
+        result
+    }
+    /// Decodes multiple payloads, for example all the records from one poll. The schema's for the
+    /// distinct id's are fetched concurrently first, so each schema is only fetched once, after
+    /// which the payloads are decoded concurrently. The results have the same order as the payloads.
+    /// Failing to get a schema is logged as a warning, and gives an error for the payloads with its
+    /// id. Everything runs concurrently on the task calling this, not in parallel, so for large
+    /// batches that take a lot of cpu to decode, spread them over multiple tasks.
+    pub async fn decode_batch(
+        &self,
+        payloads: &[Option<&[u8]>],
+    ) -> Vec<Result<Option<DecodeResultWithContext>, SRCError>> {
+        let ids = self.id_strategy.get_schema_ids(payloads);
+        let fetched = join_all(ids.iter().map(|id| self.get_context(*id))).await;
+        for (id, result) in ids.into_iter().zip(fetched) {
+            if let Err(err) = result {
+                warn_batch_fetch_failed(id, &err);
             }
         }
+        join_all(payloads.iter().map(|p| self.decode_with_context(*p))).await
+    }
+    /// Same as decode_with_context, but the data bytes of the result share the memory of the
+    /// supplied bytes, instead of being copied.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "decode", level = "debug", skip_all, err)
+    )]
+    pub async fn decode_bytes(
+        &self,
+        bytes: Option<Bytes>,
+    ) -> Result<Option<DecodeResultWithContext>, SRCError> {
+        let result = match bytes {
+            None => Ok(None),
+            Some(b) => match self.id_strategy.get_id_and_offset(&b, &[]) {
+                Some((id, offset)) => self
+                    .deserialize_with_context(id, b.slice(offset..))
+                    .await
+                    .map(Some),
+                None => Err(invalid_bytes_error(&b, self.strictness)),
+            },
+        };
+        if result.is_err() {
+            record_decode_error("protobuf");
+        }
+        result
+    }
+    /// Decodes bytes into a value, together with the id of the schema and the full name of the
+    /// message, so messages can be routed by type without another lookup.
+    pub async fn decode_with_metadata(
+        &self,
+        bytes: Option<&[u8]>,
+    ) -> Result<Option<DecodeResultWithMetadata<MessageValue>>, SRCError> {
+        Ok(self
+            .decode_with_context(bytes)
+            .await?
+            .map(|r| DecodeResultWithMetadata {
+                metadata: DecodeMetadata {
+                    schema_id: r.context.registered_schema.id,
+                    full_name: Some(String::from(&*r.full_name)),
+                },
+                value: r.value,
+            }))
     }
     /// The actual deserialization trying to get the id from the bytes to retrieve the schema, and
     /// using a reader transforms the bytes to a value.
     async fn deserialize_with_context(
         &self,
         id: u32,
-        bytes: &[u8],
+        bytes: Bytes,
+    ) -> Result<DecodeResultWithContext, SRCError> {
+        let start = decode_start(&self.slow_path);
+        let context = self.get_context(id).await?;
+        let result = decode_with_context(
+            context.clone(),
+            bytes,
+            self.message_selector.as_ref(),
+            self.field_encryptor.as_deref(),
+        )
+        .and_then(|result| check_trailing_bytes(result, self.strictness));
+        warn_slow_decode(&self.slow_path, start, id, || {
+            context.registered_schema.subject.clone()
+        });
+        result
+    }
+    /// Decodes a payload without the message index, as the message with the full name. This can be
+    /// used for payloads produced by non Confluent clients, for example by keeping the full name of
+    /// the message per topic.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "decode", level = "debug", skip_all, err)
+    )]
+    pub async fn decode_with_message_name(
+        &self,
+        bytes: Option<&[u8]>,
+        full_name: &str,
+    ) -> Result<Option<DecodeResultWithContext>, SRCError> {
+        let result = match self.id_strategy.get_bytes_result(bytes, &[]) {
+            BytesResult::Null => Ok(None),
+            BytesResult::Valid(id, bytes) => self
+                .deserialize_message(id, full_name, Bytes::from(bytes))
+                .await
+                .map(Some),
+            BytesResult::Invalid(i) => Err(invalid_bytes_error(&i, self.strictness)),
+        };
+        if result.is_err() {
+            record_decode_error("protobuf");
+        }
+        result
+    }
+    /// Decodes the bytes as the message with the full name, without reading the message index.
+    async fn deserialize_message(
+        &self,
+        id: u32,
+        full_name: &str,
+        bytes: Bytes,
     ) -> Result<DecodeResultWithContext, SRCError> {
-        let vec_of_schemas = self.get_vec_of_schemas(id).await?;
-        let context = into_decode_context(vec_of_schemas.to_vec())?;
-        let (index, data_bytes) = to_index_and_data(bytes);
-        let full_name = resolve_name(&context.resolver, &index)?;
-        let message_info = context.context.get_message(&full_name).unwrap();
-        let value = message_info.decode(&data_bytes, &context.context);
-        Ok(DecodeResultWithContext {
-            value,
-            context,
-            full_name,
-            data_bytes,
-        })
-    }
-    /// Gets the vector of schema's directly of via a shared future. The direct cache main function
-    /// is for performance.
-    async fn get_vec_of_schemas(&self, id: u32) -> Result<Arc<Vec<String>>, SRCError> {
+        let start = decode_start(&self.slow_path);
+        let context = self.get_context(id).await?;
+        let result = decode_message(
+            context.clone(),
+            Arc::new(String::from(full_name)),
+            bytes,
+            self.field_encryptor.as_deref(),
+        )
+        .and_then(|result| check_trailing_bytes(result, self.strictness));
+        warn_slow_decode(&self.slow_path, start, id, || {
+            context.registered_schema.subject.clone()
+        });
+        result
+    }
+    /// Decodes the bytes into the prost struct, with the message index checked against the name of
+    /// the struct.
+    #[cfg(feature = "prost_decoder")]
+    async fn deserialize_prost<T: prost::Message + prost::Name + Default>(
+        &self,
+        id: u32,
+        bytes: &[u8],
+    ) -> Result<T, SRCError> {
+        let start = decode_start(&self.slow_path);
+        let context = self.get_context(id).await?;
+        let result = to_prost(&context.resolver, bytes);
+        warn_slow_decode(&self.slow_path, start, id, || {
+            context.registered_schema.subject.clone()
+        });
+        result
+    }
+    /// Decodes bytes into a json value, using the names from the schema for the fields and enums.
+    /// Well known types, like Timestamp, are transformed like the Java JsonFormat does.
+    pub async fn decode_json(
+        &self,
+        bytes: Option<&[u8]>,
+    ) -> Result<Option<serde_json::Value>, SRCError> {
+        match self.decode_with_context(bytes).await? {
+            None => Ok(None),
+            Some(r) => Ok(Some(proto_value_to_json(&r.value, &r.context.context))),
+        }
+    }
+    /// Decodes bytes into any struct implementing Deserialize. The names of the fields in the proto
+    /// schema are used, and enums are deserialized from the name of the value.
+    pub async fn decode_as<T: DeserializeOwned>(
+        &self,
+        bytes: Option<&[u8]>,
+    ) -> Result<Option<T>, SRCError> {
+        match self.decode_with_context(bytes).await? {
+            None => Ok(None),
+            Some(r) => Ok(Some(from_proto_value(&r.value, &r.context.context)?)),
+        }
+    }
+    /// Decodes bytes directly into a struct generated by prost. The message referenced in the bytes
+    /// is checked against the full name of the struct, so bytes from another message will give an
+    /// error instead of a struct with wrong values.
+    #[cfg(feature = "prost_decoder")]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "decode", level = "debug", skip_all, err)
+    )]
+    pub async fn decode_prost<T: prost::Message + prost::Name + Default>(
+        &self,
+        bytes: Option<&[u8]>,
+    ) -> Result<Option<T>, SRCError> {
+        let result = match self.id_strategy.get_bytes_result(bytes, &[]) {
+            BytesResult::Null => Ok(None),
+            BytesResult::Valid(id, bytes) => self.deserialize_prost(id, &bytes).await.map(Some),
+            BytesResult::Invalid(i) => Err(invalid_bytes_error(&i, self.strictness)),
+        };
+        if result.is_err() {
+            record_decode_error("protobuf");
+        }
+        result
+    }
+    /// Gets the decode context directly or via a shared future. The direct cache main function is
+    /// for performance.
+    async fn get_context(&self, id: u32) -> Result<Arc<DecodeContext>, SRCError> {
+        check_policy(self.policy.as_deref(), id)?;
         match self.direct_cache.get(&id) {
             None => {
-                let result = self.get_vec_of_schemas_by_shared_future(id).await;
-                if result.is_ok() && !self.direct_cache.contains_key(&id) {
-                    self.direct_cache.insert(id, result.clone().unwrap());
-                    self.cache.remove(&id);
+                record_cache_lookup(id, false);
+                warn_registry_fetch(&self.slow_path, id);
+                let result = self.get_context_by_shared_future(id).await;
+                if let Ok(context) = &result {
+                    if let Entry::Vacant(e) = self.direct_cache.entry(id) {
+                        e.insert(context.clone());
+                        self.cache.remove(&id);
+                        self.cached_schemas.added();
+                        self.sr_settings
+                            .record_usage(SchemaUsageKind::Decoded, id, None);
+                    }
                 };
+                if matches!(&result, Err(e) if e.is_throttled()) {
+                    self.cache.remove(&id);
+                }
                 result
             }
-            Some(result) => Ok(result.value().clone()),
+            Some(result) => {
+                record_cache_lookup(id, true);
+                Ok(result.value().clone())
+            }
         }
     }
-    /// Gets the vector of schema's by a shared future, to prevent multiple of the same calls to
+    /// Gets the decode context by a shared future, to prevent multiple of the same calls to
     /// schema registry, either from the cache, or from the schema registry and then putting
     /// it into the cache.
-    fn get_vec_of_schemas_by_shared_future(&self, id: u32) -> SharedFutureSchema<'a> {
+    fn get_context_by_shared_future(&self, id: u32) -> SharedFutureContext<'a> {
         match self.cache.entry(id) {
             Entry::Occupied(e) => e.get().clone(),
             Entry::Vacant(e) => {
                 let sr_settings = self.sr_settings.clone();
+                let fallback = self.fallback.clone();
+                let policy = self.policy.clone();
                 let v = async move {
-                    match get_schema_by_id_and_type(id, &sr_settings, SchemaType::Protobuf).await {
-                        Ok(v) => to_vec_of_schemas(&sr_settings, v).await,
-                        Err(e) => Err(e.into_cache()),
+                    match to_resolve_context(&sr_settings, id, policy.as_deref()).await {
+                        Ok(v) => Ok(Arc::new(v)),
+                        Err(e) => match fallback.get(&id) {
+                            Some(context) if e.policy_violation().is_none() => {
+                                Ok(context.value().clone())
+                            }
+                            _ => Err(e.into_cache()),
+                        },
                     }
                 }
-                    .boxed()
-                    .shared();
+                .sr_boxed()
+                .shared();
                 e.insert(v).value().clone()
             }
         }
     }
 }
 
-#[derive(Debug)]
-pub struct DecodeResultWithContext {
-    pub value: MessageValue,
-    pub context: DecodeContext,
-    pub full_name: Arc<String>,
-    pub data_bytes: Vec<u8>,
-}
-
 fn add_files<'a>(
     sr_settings: &'a SrSettings,
-    registered_schema: RegisteredSchema,
-    files: &'a mut Vec<String>,
-) -> BoxFuture<'a, Result<(), SRCError>> {
+    registered_schema: &'a RegisteredSchema,
+    files: &'a mut HashSet<String>,
+) -> SrFuture<'a, Result<(), SRCError>> {
     async move {
-        for r in registered_schema.references {
-            let child_schema = get_referenced_schema(sr_settings, &r).await?;
-            add_files(sr_settings, child_schema, files).await?;
+        for r in &registered_schema.references {
+            let child_schema = get_referenced_schema(sr_settings, r).await?;
+            add_files(sr_settings, &child_schema, files).await?;
         }
-        files.push(registered_schema.schema);
+        add_common_files(&find_imports(&registered_schema.schema), files);
+        files.insert(registered_schema.schema.clone());
         Ok(())
     }
-        .boxed()
-}
-
-#[derive(Debug)]
-pub struct DecodeContext {
-    pub resolver: MessageResolver,
-    pub context: Context,
+    .sr_boxed()
 }
 
-fn into_decode_context(vec_of_schemas: Vec<String>) -> Result<DecodeContext, SRCError> {
-    let resolver = MessageResolver::new(vec_of_schemas.last().unwrap());
-    let mut files: HashSet<String> = HashSet::new();
-    add_common_files(resolver.imports(), &mut files);
-    for s in vec_of_schemas {
-        files.insert(s);
-    }
-
-    match Context::parse(files) {
-        Ok(context) => Ok(DecodeContext { resolver, context }),
-        Err(e) => Err(SRCError::non_retryable_with_cause(
-            e,
-            "Error creating proto context",
-        )),
-    }
+async fn to_resolve_context(
+    sr_settings: &SrSettings,
+    id: u32,
+    policy: Option<&dyn SchemaPolicy>,
+) -> Result<DecodeContext, SRCError> {
+    let registered_schema =
+        get_allowed_schema(id, sr_settings, SchemaType::Protobuf, policy).await?;
+    let mut files = HashSet::new();
+    let sr_settings = sr_settings.counting_references();
+    add_files(&sr_settings, &registered_schema, &mut files).await?;
+    to_decode_context(registered_schema, files)
 }
 
-async fn to_vec_of_schemas(
+/// Creates the proto context for the schema, including all the referenced schema's.
+pub(crate) async fn to_context(
     sr_settings: &SrSettings,
     registered_schema: RegisteredSchema,
-) -> Result<Arc<Vec<String>>, SRCError> {
-    let mut vec_of_schemas = Vec::new();
-    add_files(sr_settings, registered_schema, &mut vec_of_schemas).await?;
-    Ok(Arc::new(vec_of_schemas))
+) -> Result<Context, SRCError> {
+    let mut files = HashSet::new();
+    let sr_settings = sr_settings.counting_references();
+    add_files(&sr_settings, &registered_schema, &mut files).await?;
+    Ok(to_decode_context(registered_schema, files)?.context)
 }
 
 #[cfg(test)]
 mod tests {
     use mockito::Server;
-    use crate::async_impl::proto_decoder::ProtoDecoder;
+    use crate::async_impl::proto_decoder::{MessageSelector, ProtoDecoder};
     use crate::async_impl::schema_registry::SrSettings;
+    use crate::error::{PolicyViolation, WireFormatError};
+    use crate::proto_decoder_common::UnknownField;
+    use crate::schema_registry_common::{
+        IdStrategy, SchemaIdPolicy, SchemaSubjectPolicy, SchemaUsage, SchemaUsageKind, Strictness,
+    };
+    use bytes::Bytes;
     use protofish::prelude::Value;
+    use serde::Deserialize;
+    use serde_json::json;
+    use std::collections::HashSet;
+    use std::sync::Arc;
+    #[cfg(feature = "proto_descriptor")]
+    use test_utils::get_proto_hb_descriptor_set;
     use test_utils::{
         get_proto_complex, get_proto_complex_proto_test_message, get_proto_complex_references,
         get_proto_hb_101, get_proto_hb_schema, get_proto_result,
@@ -277,6 +614,182 @@ mod tests {
         assert_eq!(Value::UInt64(101u64), message.fields[0].value)
     }
 
+    #[tokio::test]
+    async fn test_decode_with_context_unknown_fields() {
+        let mut server = Server::new_async().await;
+        let _m = server
+            .mock("GET", "/schemas/ids/7?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_proto_body(get_proto_hb_schema(), 1))
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let decoder = ProtoDecoder::new(sr_settings);
+        let heartbeat = decoder
+            .decode_with_context(Some(&[0, 0, 0, 0, 7, 0, 8, 101, 16, 1]))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(
+            heartbeat.unknown_fields(),
+            vec![UnknownField {
+                message: String::from("nl.openweb.data.Heartbeat"),
+                number: 2,
+                wire_type: 0,
+                bytes: vec![1],
+            }]
+        )
+    }
+
+    #[tokio::test]
+    async fn test_decode_bytes() {
+        let mut server = Server::new_async().await;
+        let _m = server
+            .mock("GET", "/schemas/ids/7?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_proto_body(get_proto_hb_schema(), 1))
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let decoder = ProtoDecoder::new(sr_settings);
+        let bytes = Bytes::from_static(get_proto_hb_101());
+        let heartbeat = decoder
+            .decode_bytes(Some(bytes.clone()))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(Value::UInt64(101u64), heartbeat.value.fields[0].value);
+        assert_eq!(heartbeat.data_bytes, bytes.slice(6..));
+        assert_eq!(heartbeat.data_bytes.as_ptr(), bytes[6..].as_ptr())
+    }
+
+    #[tokio::test]
+    async fn test_decode_long_id() {
+        let mut server = Server::new_async().await;
+        let _m = server
+            .mock("GET", "/schemas/ids/7?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_proto_body(get_proto_hb_schema(), 1))
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let decoder = ProtoDecoder::new(sr_settings).with_id_strategy(IdStrategy::Long);
+        let bytes = Bytes::from_static(&[0, 0, 0, 0, 0, 0, 0, 0, 7, 0, 8, 101]);
+        let heartbeat = decoder
+            .decode_with_context(Some(&bytes))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(Value::UInt64(101u64), heartbeat.value.fields[0].value);
+
+        let heartbeat = decoder
+            .decode_bytes(Some(bytes.clone()))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(heartbeat.data_bytes, bytes.slice(10..));
+        assert!(decoder
+            .decode_bytes(Some(Bytes::from_static(get_proto_hb_101())))
+            .await
+            .is_err())
+    }
+
+    #[tokio::test]
+    async fn test_decode_batch() {
+        let mut server = Server::new_async().await;
+        let _m = server
+            .mock("GET", "/schemas/ids/7?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_proto_body(get_proto_hb_schema(), 1))
+            .expect(1)
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let decoder = ProtoDecoder::new(sr_settings);
+        let payloads = [Some(get_proto_hb_101()), None, Some(get_proto_hb_101())];
+        let results = decoder.decode_batch(&payloads).await;
+
+        _m.assert();
+        assert_eq!(results.len(), 3);
+        assert!(results[1].as_ref().unwrap().is_none());
+        for i in [0, 2] {
+            let heartbeat = results[i].as_ref().unwrap().as_ref().unwrap();
+            assert_eq!(Value::UInt64(101u64), heartbeat.value.fields[0].value)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cloned_decoder_shares_cache() {
+        let mut server = Server::new_async().await;
+        let _m = server
+            .mock("GET", "/schemas/ids/7?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_proto_body(get_proto_hb_schema(), 1))
+            .expect(1)
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let decoder = ProtoDecoder::new(sr_settings);
+        let cloned = decoder.clone();
+        decoder.decode(Some(get_proto_hb_101())).await.unwrap();
+        cloned.decode(Some(get_proto_hb_101())).await.unwrap();
+
+        _m.assert();
+    }
+
+    #[tokio::test]
+    async fn test_decode_json() {
+        let mut server = Server::new_async().await;
+        let _m = server
+            .mock("GET", "/schemas/ids/7?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_proto_body(get_proto_hb_schema(), 1))
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let decoder = ProtoDecoder::new(sr_settings);
+        let heartbeat = decoder.decode_json(Some(get_proto_hb_101())).await.unwrap();
+
+        assert_eq!(heartbeat, Some(json!({"beat": 101})))
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Beat {
+        beat: u64,
+    }
+
+    #[tokio::test]
+    async fn test_decode_as() {
+        let mut server = Server::new_async().await;
+        let _m = server
+            .mock("GET", "/schemas/ids/7?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_proto_body(get_proto_hb_schema(), 1))
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let decoder = ProtoDecoder::new(sr_settings);
+        let heartbeat: Option<Beat> = decoder.decode_as(Some(get_proto_hb_101())).await.unwrap();
+        assert_eq!(heartbeat, Some(Beat { beat: 101 }));
+
+        let result = decoder
+            .decode_with_context(Some(get_proto_hb_101()))
+            .await
+            .unwrap()
+            .unwrap();
+        let heartbeat = Beat::deserialize(&result).unwrap();
+        assert_eq!(heartbeat, Beat { beat: 101 })
+    }
+
     #[tokio::test]
     async fn test_decoder_cache() {
         let mut server = Server::new_async().await;
@@ -342,6 +855,215 @@ mod tests {
         assert_eq!(message.fields[1].value, Value::Int64(1))
     }
 
+    #[cfg(feature = "prost_decoder")]
+    #[derive(Clone, PartialEq, prost::Message)]
+    struct Heartbeat {
+        #[prost(uint64, tag = "1")]
+        beat: u64,
+    }
+
+    #[cfg(feature = "prost_decoder")]
+    impl prost::Name for Heartbeat {
+        const NAME: &'static str = "Heartbeat";
+        const PACKAGE: &'static str = "nl.openweb.data";
+    }
+
+    #[cfg(feature = "prost_decoder")]
+    #[derive(Clone, PartialEq, prost::Message)]
+    struct ProtoResult {
+        #[prost(string, tag = "1")]
+        up: String,
+    }
+
+    #[cfg(feature = "prost_decoder")]
+    impl prost::Name for ProtoResult {
+        const NAME: &'static str = "Result";
+        const PACKAGE: &'static str = "org.schema_registry_test_app.proto";
+    }
+
+    #[cfg(feature = "prost_decoder")]
+    #[tokio::test]
+    async fn test_decode_prost() {
+        let mut server = Server::new_async().await;
+        let _m = server
+            .mock("GET", "/schemas/ids/7?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_proto_body(get_proto_hb_schema(), 1))
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let decoder = ProtoDecoder::new(sr_settings);
+        let heartbeat: Heartbeat = decoder
+            .decode_prost(Some(get_proto_hb_101()))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(heartbeat, Heartbeat { beat: 101 })
+    }
+
+    #[cfg(feature = "prost_decoder")]
+    #[tokio::test]
+    async fn test_decode_prost_wrong_message() {
+        let mut server = Server::new_async().await;
+        let _m = server
+            .mock("GET", "/schemas/ids/7?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_proto_body(get_proto_hb_schema(), 1))
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let decoder = ProtoDecoder::new(sr_settings);
+        let error = decoder
+            .decode_prost::<ProtoResult>(Some(get_proto_hb_101()))
+            .await
+            .unwrap_err();
+
+        assert_eq!(
+            error.error,
+            "Message nl.openweb.data.Heartbeat in the bytes doesn't match expected message org.schema_registry_test_app.proto.Result"
+        )
+    }
+
+    #[cfg(feature = "proto_descriptor")]
+    #[tokio::test]
+    async fn test_decode_with_schema_override() {
+        let sr_settings = SrSettings::new(String::from("http://127.0.0.1:1234"));
+        let decoder = ProtoDecoder::new(sr_settings);
+        decoder
+            .add_schema_override(7, get_proto_hb_descriptor_set())
+            .unwrap();
+        let result = decoder.decode_json(Some(get_proto_hb_101())).await.unwrap();
+
+        assert_eq!(result, Some(json!({"beat": 101})))
+    }
+
+    #[cfg(feature = "proto_descriptor")]
+    #[tokio::test]
+    async fn test_decode_with_schema_fallback() {
+        let mut server = Server::new_async().await;
+        let _m = server
+            .mock("GET", "/schemas/ids/7?deleted=true")
+            .with_status(404)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"error_code":40403,"message":"Schema not found"}"#)
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let decoder = ProtoDecoder::new(sr_settings);
+        assert!(decoder.decode_json(Some(get_proto_hb_101())).await.is_err());
+
+        decoder
+            .add_schema_fallback(7, get_proto_hb_descriptor_set())
+            .unwrap();
+        let result = decoder.decode_json(Some(get_proto_hb_101())).await.unwrap();
+
+        assert_eq!(result, Some(json!({"beat": 101})))
+    }
+
+    #[tokio::test]
+    async fn test_decode_with_metadata() {
+        let mut server = Server::new_async().await;
+        let _m = server
+            .mock("GET", "/schemas/ids/7?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_proto_body(get_proto_hb_schema(), 1))
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let decoder = ProtoDecoder::new(sr_settings);
+        let result = decoder
+            .decode_with_metadata(Some(get_proto_hb_101()))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(result.metadata.schema_id, 7);
+        assert_eq!(
+            result.metadata.full_name,
+            Some(String::from("nl.openweb.data.Heartbeat"))
+        );
+        assert_eq!(Value::UInt64(101u64), result.value.fields[0].value)
+    }
+
+    #[tokio::test]
+    async fn test_decode_with_message_selector() {
+        let mut server = Server::new_async().await;
+        let _m = server
+            .mock("GET", "/schemas/ids/7?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_proto_body(get_proto_hb_schema(), 1))
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let selector = MessageSelector::new(|_| Some(String::from("nl.openweb.data.Heartbeat")));
+        let decoder = ProtoDecoder::new(sr_settings).with_message_selector(selector);
+        let result = decoder
+            .decode_json(Some(&[0, 0, 0, 0, 7, 8, 101]))
+            .await
+            .unwrap();
+
+        assert_eq!(result, Some(json!({"beat": 101})))
+    }
+
+    #[tokio::test]
+    async fn test_decode_with_message_name() {
+        let mut server = Server::new_async().await;
+        let _m = server
+            .mock("GET", "/schemas/ids/7?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_proto_body(get_proto_hb_schema(), 1))
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let decoder = ProtoDecoder::new(sr_settings);
+        let result = decoder
+            .decode_with_message_name(Some(&[0, 0, 0, 0, 7, 8, 101]), "nl.openweb.data.Heartbeat")
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(*result.full_name, "nl.openweb.data.Heartbeat");
+        assert_eq!(Value::UInt64(101u64), result.value.fields[0].value);
+
+        let error = decoder
+            .decode_with_message_name(Some(&[0, 0, 0, 0, 7, 8, 101]), "nl.openweb.data.Other")
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.error,
+            "Could not find message nl.openweb.data.Other in the schema with id 7"
+        )
+    }
+
+    #[tokio::test]
+    async fn test_decode_strict() {
+        let sr_settings = SrSettings::new(String::from("http://127.0.0.1:1234"));
+        let lenient = ProtoDecoder::new(sr_settings);
+        let strict = lenient.clone().with_strictness(Strictness::Strict);
+
+        let value = lenient.decode(Some(&[1, 0, 0, 0, 7])).await.unwrap();
+        assert_eq!(value, Value::Bytes(Bytes::from(vec![1, 0, 0, 0, 7])));
+
+        let error = strict.decode(Some(&[1, 0, 0, 0, 7])).await.unwrap_err();
+        assert_eq!(error.error, "Invalid magic byte 1, expected 0");
+        assert_eq!(
+            error.wire_format(),
+            Some(&WireFormatError::InvalidMagicByte(1))
+        );
+
+        let error = strict
+            .decode_with_context(Some(&[0, 0, 0]))
+            .await
+            .unwrap_err();
+        assert_eq!(error.wire_format(), Some(&WireFormatError::TooShort(3)))
+    }
+
     #[test]
     fn display_decoder() {
         let sr_settings = SrSettings::new(String::from("http://127.0.0.1:1234"));
@@ -350,4 +1072,78 @@ mod tests {
             format!("{:?}", decoder).starts_with("ProtoDecoder { sr_settings: SrSettings { urls: [\"http://127.0.0.1:1234\"], client: Client {")
         )
     }
+
+    #[tokio::test]
+    async fn test_usage_listener() {
+        let mut server = Server::new_async().await;
+        let _m = server
+            .mock("GET", "/schemas/ids/7?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_proto_body(get_proto_hb_schema(), 7))
+            .create();
+
+        let usages = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = usages.clone();
+        let sr_settings = SrSettings::new_builder(server.url())
+            .set_usage_listener(Arc::new(move |usage: &SchemaUsage| {
+                recorded.lock().unwrap().push(usage.clone())
+            }))
+            .build()
+            .unwrap();
+        let decoder = ProtoDecoder::new(sr_settings);
+        for _ in 0..2 {
+            decoder.decode(Some(get_proto_hb_101())).await.unwrap();
+        }
+
+        let usage = |kind| SchemaUsage {
+            kind,
+            id: 7,
+            subject: None,
+        };
+        assert_eq!(
+            *usages.lock().unwrap(),
+            vec![
+                usage(SchemaUsageKind::Fetched),
+                usage(SchemaUsageKind::Decoded)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_decoder_policy() {
+        let mut server = Server::new_async().await;
+        let _m = server
+            .mock("GET", "/schemas/ids/7?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_proto_body(get_proto_hb_schema(), 7))
+            .expect(1)
+            .create();
+        let _n = server
+            .mock("GET", "/schemas/ids/7/versions")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"[{"subject":"nl.openweb.data.Heartbeat","version":1}]"#)
+            .create();
+        let sr_settings = SrSettings::new(server.url());
+
+        let decoder = ProtoDecoder::new(sr_settings.clone())
+            .with_policy(Arc::new(SchemaIdPolicy::Deny(HashSet::from([7]))));
+        let error = decoder.decode(Some(get_proto_hb_101())).await.unwrap_err();
+        assert_eq!(error.policy_violation(), Some(&PolicyViolation { id: 7 }));
+        assert!(!error.cached);
+
+        let denied = HashSet::from([String::from("nl.openweb.data.Heartbeat")]);
+        let decoder = ProtoDecoder::new(sr_settings.clone())
+            .with_policy(Arc::new(SchemaSubjectPolicy::Deny(denied)));
+        let error = decoder.decode(Some(get_proto_hb_101())).await.unwrap_err();
+        assert_eq!(error.policy_violation(), Some(&PolicyViolation { id: 7 }));
+
+        let allowed = HashSet::from([String::from("nl.openweb.data.Heartbeat")]);
+        let decoder = ProtoDecoder::new(sr_settings)
+            .with_policy(Arc::new(SchemaSubjectPolicy::Allow(allowed)));
+        assert!(decoder.decode(Some(get_proto_hb_101())).await.is_ok());
+        _m.assert();
+    }
 }
@@ -0,0 +1,311 @@
+//! A protobuf decoder using prost-reflect instead of protofish. The schema and the referenced
+//! schema's are compiled with protox into a `DescriptorPool`, and the bytes are decoded into a
+//! `DynamicMessage`, which supports the json mapping, `Any` and editions better.
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+use futures::future::Shared;
+use futures::FutureExt;
+use prost_reflect::{DescriptorPool, DynamicMessage};
+use protox::file::{File, FileResolver};
+use protox::Compiler;
+
+use crate::async_impl::schema_registry::{
+    get_referenced_schema, get_schema_by_id_and_type, SrSettings,
+};
+use crate::async_impl::{SrFuture, SrFutureExt};
+use crate::error::SRCError;
+use crate::proto_common_types::get_common_file;
+use crate::proto_resolver::{resolve_name, to_index_and_offset, MessageResolver};
+use crate::schema_registry_common::{
+    get_bytes_result, to_wire_format_error, BytesResult, RegisteredSchema, SchemaType,
+};
+
+/// Everything needed to decode bytes for one schema id, with the pool containing the schema and
+/// the referenced schema's.
+#[derive(Debug)]
+pub struct ReflectContext {
+    pub pool: DescriptorPool,
+    pub resolver: MessageResolver,
+    pub registered_schema: RegisteredSchema,
+}
+
+#[derive(Debug)]
+pub struct ReflectDecodeResult {
+    pub message: DynamicMessage,
+    pub context: Arc<ReflectContext>,
+}
+
+type SharedFutureContext<'a> = Shared<SrFuture<'a, Result<Arc<ReflectContext>, SRCError>>>;
+
+/// Cloning the decoder is cheap, and the clones share the cache.
+#[derive(Debug, Clone)]
+pub struct ProtoReflectDecoder<'a> {
+    sr_settings: SrSettings,
+    direct_cache: Arc<DashMap<u32, Arc<ReflectContext>>>,
+    cache: Arc<DashMap<u32, SharedFutureContext<'a>>>,
+}
+
+impl<'a> ProtoReflectDecoder<'a> {
+    /// Creates a new decoder which will use the supplied url used in creating the sr settings to
+    /// fetch the schema's. It's possible for recoverable errors to stay in the cache, when a result
+    /// comes back as an error you can use remove_errors_from_cache to clean the cache, keeping the
+    /// correctly fetched schema's
+    pub fn new(sr_settings: SrSettings) -> ProtoReflectDecoder<'a> {
+        ProtoReflectDecoder {
+            sr_settings,
+            direct_cache: Arc::new(DashMap::new()),
+            cache: Arc::new(DashMap::new()),
+        }
+    }
+    /// Remove all the errors from the cache, you might need to/want to run this when a recoverable
+    /// error is met.
+    pub fn remove_errors_from_cache(&self) {
+        self.cache.retain(|_, v| match v.peek() {
+            Some(r) => r.is_ok(),
+            None => true,
+        });
+    }
+    /// Decodes bytes into a dynamic message, of the message referenced by the message index in the
+    /// bytes.
+    pub async fn decode(
+        &self,
+        bytes: Option<&[u8]>,
+    ) -> Result<Option<ReflectDecodeResult>, SRCError> {
+        match get_bytes_result(bytes) {
+            BytesResult::Null => Ok(None),
+            BytesResult::Valid(id, bytes) => Ok(Some(self.deserialize(id, &bytes).await?)),
+            BytesResult::Invalid(i) => Err(SRCError::from(to_wire_format_error(&i))),
+        }
+    }
+    /// Decodes bytes into a json value, following the json mapping of protobuf, which for example
+    /// uses the json names of the fields, and strings for 64 bit integers.
+    pub async fn decode_json(
+        &self,
+        bytes: Option<&[u8]>,
+    ) -> Result<Option<serde_json::Value>, SRCError> {
+        match self.decode(bytes).await? {
+            None => Ok(None),
+            Some(r) => match serde_json::to_value(&r.message) {
+                Ok(v) => Ok(Some(v)),
+                Err(e) => Err(SRCError::non_retryable_with_cause(
+                    e,
+                    "Could not map the message to json",
+                )),
+            },
+        }
+    }
+    async fn deserialize(&self, id: u32, bytes: &[u8]) -> Result<ReflectDecodeResult, SRCError> {
+        let context = self.get_context(id).await?;
+        let (index, offset) = to_index_and_offset(bytes)?;
+        let full_name = resolve_name(&context.resolver, &index)?;
+        let descriptor = match context.pool.get_message_by_name(&full_name) {
+            Some(d) => d,
+            None => {
+                return Err(SRCError::non_retryable_without_cause(&format!(
+                    "Could not find message {} in the schema with id {}",
+                    full_name, id
+                )))
+            }
+        };
+        match DynamicMessage::decode(descriptor, &bytes[offset..]) {
+            Ok(message) => Ok(ReflectDecodeResult { message, context }),
+            Err(e) => Err(SRCError::non_retryable_with_cause(
+                e,
+                "Could not decode bytes with prost-reflect",
+            )),
+        }
+    }
+    /// Gets the context directly or via a shared future. The direct cache main function is for
+    /// performance.
+    async fn get_context(&self, id: u32) -> Result<Arc<ReflectContext>, SRCError> {
+        match self.direct_cache.get(&id) {
+            None => {
+                let result = self.get_context_by_shared_future(id).await;
+                if let Ok(context) = &result {
+                    if let Entry::Vacant(e) = self.direct_cache.entry(id) {
+                        e.insert(context.clone());
+                        self.cache.remove(&id);
+                    }
+                };
+                if matches!(&result, Err(e) if e.is_throttled()) {
+                    self.cache.remove(&id);
+                }
+                result
+            }
+            Some(result) => Ok(result.value().clone()),
+        }
+    }
+    /// Gets the context by a shared future, to prevent multiple of the same calls to schema
+    /// registry, either from the cache, or from the schema registry and then putting it into the
+    /// cache.
+    fn get_context_by_shared_future(&self, id: u32) -> SharedFutureContext<'a> {
+        match self.cache.entry(id) {
+            Entry::Occupied(e) => e.get().clone(),
+            Entry::Vacant(e) => {
+                let sr_settings = self.sr_settings.clone();
+                let v = async move {
+                    match to_reflect_context(&sr_settings, id).await {
+                        Ok(v) => Ok(Arc::new(v)),
+                        Err(e) => Err(e.into_cache()),
+                    }
+                }
+                .sr_boxed()
+                .shared();
+                e.insert(v).value().clone()
+            }
+        }
+    }
+}
+
+/// Resolves the imports by the names of the references, or as one of the well known types.
+struct RegistryFiles(HashMap<String, String>);
+
+impl FileResolver for RegistryFiles {
+    fn resolve_path(&self, path: &Path) -> Option<String> {
+        path.to_str().map(String::from)
+    }
+
+    fn open_file(&self, name: &str) -> Result<File, protox::Error> {
+        match self.0.get(name).map(String::as_str) {
+            Some(source) => File::from_source(name, source),
+            None => match get_common_file(name) {
+                Some(source) => File::from_source(name, source),
+                None => Err(protox::Error::file_not_found(name)),
+            },
+        }
+    }
+}
+
+fn add_files<'a>(
+    sr_settings: &'a SrSettings,
+    registered_schema: &'a RegisteredSchema,
+    files: &'a mut HashMap<String, String>,
+) -> SrFuture<'a, Result<(), SRCError>> {
+    async move {
+        for r in &registered_schema.references {
+            let child_schema = get_referenced_schema(sr_settings, r).await?;
+            add_files(sr_settings, &child_schema, files).await?;
+            files.insert(r.name.clone(), child_schema.schema);
+        }
+        Ok(())
+    }
+    .sr_boxed()
+}
+
+async fn to_reflect_context(sr_settings: &SrSettings, id: u32) -> Result<ReflectContext, SRCError> {
+    let registered_schema =
+        get_schema_by_id_and_type(id, sr_settings, SchemaType::Protobuf).await?;
+    let mut files = HashMap::new();
+    let sr_settings = sr_settings.counting_references();
+    add_files(&sr_settings, &registered_schema, &mut files).await?;
+    let name = format!("schema_registry/{}.proto", id);
+    files.insert(name.clone(), registered_schema.schema.clone());
+    let mut compiler = Compiler::with_file_resolver(RegistryFiles(files));
+    compiler.include_imports(true);
+    if let Err(e) = compiler.open_file(&name) {
+        return Err(SRCError::non_retryable_with_cause(
+            e,
+            "Could not compile the proto schema with protox",
+        ));
+    }
+    Ok(ReflectContext {
+        pool: compiler.descriptor_pool(),
+        resolver: MessageResolver::new(&registered_schema.schema),
+        registered_schema,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use mockito::Server;
+    use prost_reflect::Value;
+    use serde_json::json;
+    use test_utils::{
+        get_proto_complex, get_proto_complex_proto_test_message, get_proto_complex_references,
+        get_proto_hb_101, get_proto_hb_schema, get_proto_result,
+    };
+
+    use crate::async_impl::proto_reflect::ProtoReflectDecoder;
+    use crate::async_impl::schema_registry::SrSettings;
+
+    fn get_proto_body(schema: &str, id: u32) -> String {
+        format!(
+            "{{\"schema\":\"{}\", \"schemaType\":\"PROTOBUF\", \"id\":{}}}",
+            schema, id
+        )
+    }
+
+    fn get_proto_body_with_reference(schema: &str, id: u32, reference: &str) -> String {
+        format!(
+            "{{\"schema\":\"{}\", \"schemaType\":\"PROTOBUF\", \"id\":{}, \"references\":[{}]}}",
+            schema, id, reference
+        )
+    }
+
+    #[tokio::test]
+    async fn test_decode_json() {
+        let mut server = Server::new_async().await;
+        let _m = server
+            .mock("GET", "/schemas/ids/7?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_proto_body(get_proto_hb_schema(), 1))
+            .create();
+
+        let decoder = ProtoReflectDecoder::new(SrSettings::new(server.url()));
+        let heartbeat = decoder.decode_json(Some(get_proto_hb_101())).await.unwrap();
+
+        assert_eq!(heartbeat, Some(json!({"beat": "101"})))
+    }
+
+    #[tokio::test]
+    async fn test_decode_complex() {
+        let mut server = Server::new_async().await;
+        let _m = server
+            .mock("GET", "/schemas/ids/6?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_proto_body_with_reference(
+                get_proto_complex(),
+                2,
+                get_proto_complex_references(),
+            ))
+            .create();
+
+        let _m = server
+            .mock("GET", "/subjects/result.proto/versions/1")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_proto_body(get_proto_result(), 1))
+            .create();
+
+        let decoder = ProtoReflectDecoder::new(SrSettings::new(server.url()));
+        let result = decoder
+            .decode(Some(get_proto_complex_proto_test_message()))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(
+            result.message.descriptor().full_name(),
+            "org.schema_registry_test_app.proto.ProtoTest"
+        );
+        assert_eq!(
+            *result.message.get_field_by_name("counter").unwrap(),
+            Value::I64(1)
+        );
+        assert_eq!(result.context.registered_schema.id, 2);
+    }
+
+    #[tokio::test]
+    async fn test_decode_invalid_bytes() {
+        let server = Server::new_async().await;
+        let decoder = ProtoReflectDecoder::new(SrSettings::new(server.url()));
+        assert!(decoder.decode(Some(&[1, 0, 0, 0, 7])).await.is_err());
+        assert!(decoder.decode(None).await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,228 @@
+//! A blocking facade over the async encoders and decoders, for applications that are mostly sync
+//! but want to share the async client, and its cache, with the async parts. The bridge either owns
+//! a runtime, or uses the handle of a multi thread runtime running elsewhere. When called from
+//! within a multi thread runtime the worker is marked as blocking first, and from within a current
+//! thread runtime an error is returned instead of panicking or dead locking.
+//!
+//! The connections of the reqwest client in the SrSettings belong to the runtime they were made
+//! in. So with a bridge owning its runtime, the SrSettings should not be shared with encoders or
+//! decoders used in another runtime, including the one of another bridge. To share them with the
+//! async parts, create the bridge with the handle of the runtime running those.
+use std::future::Future;
+use std::sync::Arc;
+
+use tokio::runtime::{Builder, Handle, Runtime, RuntimeFlavor};
+use tokio::task::block_in_place;
+
+use crate::async_impl::codec::{PayloadDecoder, PayloadEncoder};
+use crate::error::SRCError;
+use crate::schema_registry_common::SubjectNameStrategy;
+
+#[derive(Debug, Clone)]
+enum BridgeRuntime {
+    Owned(Arc<Runtime>),
+    Handle(Handle),
+}
+
+/// Wraps an async encoder or decoder, exposing blocking calls. Cloning the bridge is cheap when
+/// cloning the wrapped encoder or decoder is.
+/// ```
+/// use apache_avro::types::Value;
+/// use mockito::Server;
+/// use schema_registry_converter::async_impl::avro::AvroDecoder;
+/// use schema_registry_converter::async_impl::schema_registry::SrSettings;
+/// use schema_registry_converter::async_impl::sync_bridge::SyncBridge;
+///
+/// let mut server = Server::new();
+/// let _m = server.mock("GET", "/schemas/ids/1?deleted=true")
+///     .with_status(200)
+///     .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+///     .with_body(r#"{"schema":"{\"type\":\"record\",\"name\":\"Heartbeat\",\"namespace\":\"nl.openweb.data\",\"fields\":[{\"name\":\"beat\",\"type\":\"long\"}]}"}"#)
+///     .create();
+///
+/// let bridge = SyncBridge::new(AvroDecoder::new(SrSettings::new(server.url()))).unwrap();
+/// let heartbeat = bridge.decode(Some(&[0, 0, 0, 0, 1, 6])).unwrap().value;
+///
+/// assert_eq!(heartbeat, Value::Record(vec![("beat".to_string(), Value::Long(3))]));
+/// ```
+#[derive(Debug, Clone)]
+pub struct SyncBridge<T> {
+    inner: T,
+    runtime: BridgeRuntime,
+}
+
+impl<T> SyncBridge<T> {
+    /// Creates a bridge with its own current thread runtime, which is only driven while blocking.
+    /// Like any runtime, it can't be dropped from within an async context. The SrSettings of the
+    /// wrapped encoder or decoder should only be used by this bridge, and its clones, as the
+    /// connections of its client are only driven by this runtime.
+    pub fn new(inner: T) -> Result<SyncBridge<T>, SRCError> {
+        match Builder::new_current_thread().enable_all().build() {
+            Ok(runtime) => Ok(SyncBridge {
+                inner,
+                runtime: BridgeRuntime::Owned(Arc::new(runtime)),
+            }),
+            Err(e) => Err(SRCError::non_retryable_with_cause(
+                e,
+                "Could not create a runtime for the sync bridge",
+            )),
+        }
+    }
+    /// Creates a bridge using the handle of a runtime running elsewhere. This should be a multi
+    /// thread runtime, as a current thread runtime is only driven by its own block_on.
+    pub fn with_handle(inner: T, handle: Handle) -> SyncBridge<T> {
+        SyncBridge {
+            inner,
+            runtime: BridgeRuntime::Handle(handle),
+        }
+    }
+    /// The wrapped encoder or decoder, to use with block_on for the calls without a blocking
+    /// counterpart on the bridge.
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+    /// Blocks until the future is done. Within a multi thread runtime, the worker is marked as
+    /// blocking, so the other tasks are moved to another worker. Within a current thread runtime
+    /// blocking would stop the runtime, so an error is returned instead.
+    pub fn block_on<F: Future>(&self, future: F) -> Result<F::Output, SRCError> {
+        match Handle::try_current() {
+            Ok(current) if current.runtime_flavor() == RuntimeFlavor::CurrentThread => {
+                Err(SRCError::non_retryable_without_cause(
+                    "Can't block within a current thread runtime, use the async api instead",
+                ))
+            }
+            Ok(_) => Ok(block_in_place(|| self.run(future))),
+            Err(_) => Ok(self.run(future)),
+        }
+    }
+    fn run<F: Future>(&self, future: F) -> F::Output {
+        match &self.runtime {
+            BridgeRuntime::Owned(runtime) => runtime.block_on(future),
+            BridgeRuntime::Handle(handle) => handle.block_on(future),
+        }
+    }
+}
+
+impl<T: PayloadDecoder> SyncBridge<T> {
+    /// Decodes the bytes, the same way as the decode function of the decoder.
+    pub fn decode(&self, bytes: Option<&[u8]>) -> Result<T::Output, SRCError> {
+        self.block_on(self.inner.decode_payload(bytes))?
+    }
+}
+
+impl<T: PayloadEncoder> SyncBridge<T> {
+    /// Encodes the value, the same way as the encode function of the encoder.
+    pub fn encode(
+        &self,
+        value: &T::Input,
+        subject_name_strategy: impl Into<SubjectNameStrategy>,
+    ) -> Result<Vec<u8>, SRCError> {
+        self.block_on(
+            self.inner
+                .encode_payload(value, subject_name_strategy.into()),
+        )?
+    }
+}
+
+#[cfg(all(test, feature = "avro"))]
+mod tests {
+    use std::thread;
+
+    use apache_avro::types::Value;
+    use mockito::{Mock, Server, ServerGuard};
+
+    use crate::async_impl::avro::{AvroDecoder, AvroEncoder};
+    use crate::async_impl::schema_registry::SrSettings;
+    use crate::async_impl::sync_bridge::SyncBridge;
+    use crate::schema_registry_common::SubjectNameStrategy;
+
+    const SCHEMA: &str = r#"{\"type\":\"record\",\"name\":\"Heartbeat\",\"namespace\":\"nl.openweb.data\",\"fields\":[{\"name\":\"beat\",\"type\":\"long\"}]}"#;
+
+    fn mock_heartbeat(server: &mut ServerGuard) -> Vec<Mock> {
+        vec![
+            server
+                .mock("GET", "/schemas/ids/1?deleted=true")
+                .with_status(200)
+                .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+                .with_body(format!(r#"{{"schema":"{}"}}"#, SCHEMA))
+                .create(),
+            server
+                .mock("GET", "/subjects/heartbeat-value/versions/latest")
+                .with_status(200)
+                .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+                .with_body(format!(
+                    r#"{{"subject":"heartbeat-value","version":1,"id":1,"schema":"{}"}}"#,
+                    SCHEMA
+                ))
+                .create(),
+        ]
+    }
+
+    #[test]
+    fn test_encode_and_decode_outside_runtime() {
+        let mut server = Server::new();
+        let _m = mock_heartbeat(&mut server);
+        let encoder = SyncBridge::new(AvroEncoder::new(SrSettings::new(server.url()))).unwrap();
+        let decoder = SyncBridge::new(AvroDecoder::new(SrSettings::new(server.url()))).unwrap();
+
+        let strategy = SubjectNameStrategy::TopicNameStrategy(String::from("heartbeat"), false);
+        let bytes = encoder
+            .encode(&vec![("beat", Value::Long(3))], strategy)
+            .unwrap();
+        assert_eq!(bytes, vec![0, 0, 0, 0, 1, 6]);
+
+        let heartbeat = decoder.decode(Some(&bytes)).unwrap().value;
+        assert_eq!(
+            heartbeat,
+            Value::Record(vec![(String::from("beat"), Value::Long(3))])
+        );
+    }
+
+    #[test]
+    fn test_decode_from_other_thread() {
+        let mut server = Server::new();
+        let _m = mock_heartbeat(&mut server);
+        let decoder = SyncBridge::new(AvroDecoder::new(SrSettings::new(server.url()))).unwrap();
+
+        let threads: Vec<_> = (0..2)
+            .map(|_| {
+                let decoder = decoder.clone();
+                thread::spawn(move || decoder.decode(Some(&[0, 0, 0, 0, 1, 6])).unwrap().value)
+            })
+            .collect();
+        for thread in threads {
+            assert_eq!(
+                thread.join().unwrap(),
+                Value::Record(vec![(String::from("beat"), Value::Long(3))])
+            );
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_decode_within_multi_thread_runtime() {
+        let mut server = Server::new_async().await;
+        let _m = mock_heartbeat(&mut server);
+        let decoder = SyncBridge::with_handle(
+            AvroDecoder::new(SrSettings::new(server.url())),
+            tokio::runtime::Handle::current(),
+        );
+
+        let heartbeat = decoder.decode(Some(&[0, 0, 0, 0, 1, 6])).unwrap().value;
+        assert_eq!(
+            heartbeat,
+            Value::Record(vec![(String::from("beat"), Value::Long(3))])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_error_within_current_thread_runtime() {
+        let server = Server::new_async().await;
+        let decoder = SyncBridge::with_handle(
+            AvroDecoder::new(SrSettings::new(server.url())),
+            tokio::runtime::Handle::current(),
+        );
+
+        let error = decoder.decode(Some(&[0, 0, 0, 0, 1, 6])).unwrap_err();
+        assert!(error.error.contains("current thread runtime"));
+    }
+}
@@ -0,0 +1,247 @@
+//! Polls the schema registry for new versions, so services can reload what depends on the schema's,
+//! like validators, without a restart. Either the latest versions of subjects are watched, or all
+//! the schema's registered after an id.
+//! ```no_run
+//! # async fn watch() {
+//! use std::time::Duration;
+//! use schema_registry_converter::async_impl::schema_registry::SrSettings;
+//! use schema_registry_converter::async_impl::watch::SchemaWatcher;
+//! use schema_registry_converter::schema_registry_common::RegisteredSchema;
+//!
+//! let watcher = SchemaWatcher::new(SrSettings::new(String::from("http://localhost:8081")))
+//!     .with_subject("heartbeat-value")
+//!     .with_interval(Duration::from_secs(30))
+//!     .on_new_version(|schema: &RegisteredSchema| println!("new version {:?}", schema.version));
+//! let mut latest = watcher.subscribe();
+//! tokio::spawn(watcher.run());
+//! while latest.changed().await.is_ok() {
+//!     let heartbeat = latest.borrow_and_update().get("heartbeat-value").cloned();
+//! }
+//! # }
+//! ```
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::warn;
+use tokio::sync::watch;
+
+use crate::async_impl::schema_registry::{
+    get_all_schemas, perform_sr_call, raw_to_registered_schema, SrSettings,
+};
+use crate::error::SRCError;
+use crate::schema_registry_common::{RegisteredSchema, SrCall};
+
+/// Receives the new versions found by a `SchemaWatcher`. Implemented for closures with the same
+/// arguments.
+pub trait SchemaListener: Send + Sync {
+    fn on_new_version(&self, schema: &RegisteredSchema);
+}
+
+impl<F> SchemaListener for F
+where
+    F: Fn(&RegisteredSchema) + Send + Sync,
+{
+    fn on_new_version(&self, schema: &RegisteredSchema) {
+        self(schema)
+    }
+}
+
+impl fmt::Debug for dyn SchemaListener {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SchemaListener")
+    }
+}
+
+/// Polls the schema registry for new versions of the subjects, or for schema's with a higher id
+/// when `with_schemas_after` is used. New versions are given to the listeners, and the latest
+/// version of each subject is sent on the watch channel from `subscribe`. The first poll gives the
+/// versions which are the latest at that moment.
+#[derive(Debug)]
+pub struct SchemaWatcher {
+    sr_settings: SrSettings,
+    subjects: Vec<String>,
+    after_id: Option<u32>,
+    interval: Duration,
+    listeners: Vec<Arc<dyn SchemaListener>>,
+    latest: watch::Sender<HashMap<String, RegisteredSchema>>,
+}
+
+impl SchemaWatcher {
+    /// Creates a watcher without subjects, polling every minute.
+    pub fn new(sr_settings: SrSettings) -> SchemaWatcher {
+        SchemaWatcher {
+            sr_settings,
+            subjects: Vec::new(),
+            after_id: None,
+            interval: Duration::from_secs(60),
+            listeners: Vec::new(),
+            latest: watch::Sender::new(HashMap::new()),
+        }
+    }
+
+    pub fn with_subject(mut self, subject: &str) -> Self {
+        self.subjects.push(String::from(subject));
+        self
+    }
+
+    /// Watches all the schema's instead of the subjects, for which the id is higher than the
+    /// given id. Since all schema's are fetched with each poll, the interval should not be too
+    /// short for a big schema registry.
+    pub fn with_schemas_after(mut self, id: u32) -> Self {
+        self.after_id = Some(id);
+        self
+    }
+
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    pub fn on_new_version(mut self, listener: impl SchemaListener + 'static) -> Self {
+        self.listeners.push(Arc::new(listener));
+        self
+    }
+
+    /// Gives a receiver of the latest version of each subject, by subject, which is changed when a
+    /// poll found new versions.
+    pub fn subscribe(&self) -> watch::Receiver<HashMap<String, RegisteredSchema>> {
+        self.latest.subscribe()
+    }
+
+    /// Polls the schema registry once, giving the new versions, after giving them to the listeners
+    /// and updating the latest versions.
+    pub async fn poll(&mut self) -> Result<Vec<RegisteredSchema>, SRCError> {
+        let new_versions = match self.after_id {
+            Some(after_id) => self.poll_schemas(after_id).await?,
+            None => self.poll_subjects().await?,
+        };
+        for schema in &new_versions {
+            for listener in &self.listeners {
+                listener.on_new_version(schema);
+            }
+        }
+        if !new_versions.is_empty() {
+            self.latest.send_modify(|latest| {
+                for schema in &new_versions {
+                    if let Some(subject) = &schema.subject {
+                        latest.insert(subject.clone(), schema.clone());
+                    }
+                }
+            });
+        }
+        Ok(new_versions)
+    }
+
+    /// Keeps polling with the interval. Failed polls are logged, and tried again after the
+    /// interval, so this only ends when the future is dropped, for example by aborting the task.
+    pub async fn run(mut self) {
+        loop {
+            if let Err(e) = self.poll().await {
+                warn!("Polling the schema registry for new versions failed: {}", e);
+            }
+            tokio::time::sleep(self.interval).await;
+        }
+    }
+
+    async fn poll_subjects(&self) -> Result<Vec<RegisteredSchema>, SRCError> {
+        let mut new_versions = Vec::new();
+        for subject in &self.subjects {
+            let raw_schema = perform_sr_call(&self.sr_settings, SrCall::GetLatest(subject)).await?;
+            let schema = raw_to_registered_schema(raw_schema, None).await?;
+            let known_version = self.latest.borrow().get(subject).and_then(|s| s.version);
+            if schema.version > known_version {
+                new_versions.push(RegisteredSchema {
+                    subject: Some(subject.clone()),
+                    ..schema
+                });
+            }
+        }
+        Ok(new_versions)
+    }
+
+    async fn poll_schemas(&mut self, after_id: u32) -> Result<Vec<RegisteredSchema>, SRCError> {
+        let mut new_versions: Vec<RegisteredSchema> = get_all_schemas(&self.sr_settings)
+            .await?
+            .into_iter()
+            .filter(|s| s.id > after_id)
+            .collect();
+        new_versions.sort_by_key(|s| (s.id, s.version));
+        if let Some(last) = new_versions.last() {
+            self.after_id = Some(last.id);
+        }
+        Ok(new_versions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use mockito::Server;
+
+    use crate::async_impl::schema_registry::SrSettings;
+    use crate::async_impl::watch::SchemaWatcher;
+    use crate::schema_registry_common::RegisteredSchema;
+
+    #[tokio::test]
+    async fn notify_new_versions_of_subjects() {
+        let mut server = Server::new_async().await;
+        let first = server
+            .mock("GET", "/subjects/heartbeat-value/versions/latest")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"subject":"heartbeat-value","version":1,"id":3,"schema":"{\"type\":\"record\",\"name\":\"Heartbeat\",\"fields\":[{\"name\":\"beat\",\"type\":\"long\"}]}"}"#)
+            .expect(2)
+            .create();
+
+        let notified = Arc::new(Mutex::new(Vec::new()));
+        let listener_notified = notified.clone();
+        let mut watcher = SchemaWatcher::new(SrSettings::new(server.url()))
+            .with_subject("heartbeat-value")
+            .on_new_version(move |schema: &RegisteredSchema| {
+                listener_notified.lock().unwrap().push(schema.id)
+            });
+        let mut latest = watcher.subscribe();
+
+        assert_eq!(watcher.poll().await.unwrap().len(), 1);
+        assert!(latest.has_changed().unwrap());
+        assert_eq!(latest.borrow_and_update()["heartbeat-value"].id, 3);
+        assert!(watcher.poll().await.unwrap().is_empty());
+        assert!(!latest.has_changed().unwrap());
+        first.assert_async().await;
+        first.remove_async().await;
+
+        let _m = server
+            .mock("GET", "/subjects/heartbeat-value/versions/latest")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"subject":"heartbeat-value","version":2,"id":4,"schema":"{\"type\":\"record\",\"name\":\"Heartbeat\",\"fields\":[{\"name\":\"beat\",\"type\":\"long\"},{\"name\":\"source\",\"type\":\"string\",\"default\":\"\"}]}"}"#)
+            .create();
+
+        let new_versions = watcher.poll().await.unwrap();
+        assert_eq!(new_versions[0].version, Some(2));
+        assert_eq!(latest.borrow_and_update()["heartbeat-value"].id, 4);
+        assert_eq!(*notified.lock().unwrap(), vec![3, 4]);
+    }
+
+    #[tokio::test]
+    async fn notify_schemas_after_id() {
+        let mut server = Server::new_async().await;
+        let _m = server
+            .mock("GET", "/schemas")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"[{"subject":"a-value","version":1,"id":3,"schema":"\"long\""},{"subject":"b-value","version":1,"id":5,"schemaType":"JSON","schema":"{}"},{"subject":"a-value","version":2,"id":4,"schema":"\"string\""}]"#)
+            .create();
+
+        let mut watcher = SchemaWatcher::new(SrSettings::new(server.url())).with_schemas_after(3);
+
+        let ids: Vec<u32> = watcher.poll().await.unwrap().iter().map(|s| s.id).collect();
+        assert_eq!(ids, vec![4, 5]);
+        assert!(watcher.poll().await.unwrap().is_empty());
+        let latest = watcher.subscribe();
+        assert_eq!(latest.borrow()["a-value"].version, Some(2));
+    }
+}
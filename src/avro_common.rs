@@ -1,13 +1,25 @@
+use apache_avro::rabin::Rabin;
 use apache_avro::schema::{Name, Schema};
 use apache_avro::types::{Record, Value};
-use apache_avro::{to_avro_datum, to_value};
+use apache_avro::{from_avro_datum, from_value, to_avro_datum, to_value};
 use dashmap::DashMap;
+use log::warn;
+use serde::de::DeserializeOwned;
 use serde::ser::Serialize;
 use serde_json::{value, Map};
+use std::borrow::Cow;
+use std::fmt;
+use std::io::Cursor;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use crate::avro_logical::{collect_names, from_serde_value, resolve_ref, AvroCodecConfig, Names};
 use crate::error::SRCError;
-use crate::schema_registry_common::{get_payload, SchemaType, SuppliedSchema};
+use crate::field_encryption::{Direction, FieldEncryption, FieldEncryptor};
+use crate::migration::{migrate, RuleExecutor};
+use crate::schema_registry_common::{
+    IdStrategy, Metadata, Rule, RuleSet, SchemaType, SubjectNameStrategy, SuppliedSchema,
+};
 
 /// Because we need both the resulting schema, as have a way of posting the schema as json, we use
 /// this struct so we keep them both together.
@@ -16,6 +28,12 @@ pub struct AvroSchema {
     pub id: u32,
     pub raw: String,
     pub parsed: Schema,
+    /// The rules of the registered schema, used for field level encryption.
+    pub rule_set: Option<RuleSet>,
+    pub metadata: Option<Metadata>,
+    /// The subject, only known when the schema was retrieved by subject, since the schema registry
+    /// doesn't return it when getting a schema by id.
+    pub subject: Option<String>,
 }
 
 #[derive(Debug, PartialEq)]
@@ -29,17 +47,24 @@ pub struct DecodeResultWithSchema {
     pub name: Option<Name>,
     pub value: Value,
     pub schema: Arc<AvroSchema>,
+    /// The schema the value was written with, which is another schema than the schema of the value
+    /// when the value was migrated.
+    pub writer_schema: Arc<AvroSchema>,
 }
 
 fn might_replace(
     val: value::Value,
     child: &value::Value,
     replace_values: &DashMap<String, String>,
+    replaced: &mut bool,
 ) -> value::Value {
     match val {
-        value::Value::Object(v) => replace_in_map(v, child, replace_values),
-        value::Value::Array(v) => replace_in_array(&v, child, replace_values),
-        value::Value::String(s) if replace_values.contains_key(&*s) => child.clone(),
+        value::Value::Object(v) => replace_in_map(v, child, replace_values, replaced),
+        value::Value::Array(v) => replace_in_array(&v, child, replace_values, replaced),
+        value::Value::String(s) if !*replaced && replace_values.contains_key(&*s) => {
+            *replaced = true;
+            child.clone()
+        }
         p => p,
     }
 }
@@ -48,11 +73,12 @@ fn replace_in_array(
     parent_array: &[value::Value],
     child: &value::Value,
     replace_values: &DashMap<String, String>,
+    replaced: &mut bool,
 ) -> value::Value {
     value::Value::Array(
         parent_array
             .iter()
-            .map(|v| might_replace(v.clone(), child, replace_values))
+            .map(|v| might_replace(v.clone(), child, replace_values, replaced))
             .collect(),
     )
 }
@@ -61,6 +87,7 @@ fn replace_in_map(
     parent_map: Map<String, value::Value>,
     child: &value::Value,
     replace_values: &DashMap<String, String>,
+    replaced: &mut bool,
 ) -> value::Value {
     value::Value::Object(
         parent_map
@@ -68,13 +95,15 @@ fn replace_in_map(
             .map(|e| {
                 (
                     e.0.clone(),
-                    might_replace(e.1.clone(), child, replace_values),
+                    might_replace(e.1.clone(), child, replace_values, replaced),
                 )
             })
             .collect(),
     )
 }
 
+/// Replaces the first use of the name of the child in the parent by the definition of the child.
+/// Avro doesn't allow a named type to be defined twice, so later uses keep referring to the name.
 pub(crate) fn replace_reference(parent: value::Value, child: value::Value) -> value::Value {
     let (name, namespace) = match &child {
         value::Value::Object(v) => (v["name"].as_str(), v["namespace"].as_str()),
@@ -84,8 +113,9 @@ pub(crate) fn replace_reference(parent: value::Value, child: value::Value) -> va
     match name {
         Some(v) => match namespace {
             Some(u) => {
-                let key = format!(".{}.{}", u, v);
-                replace_values.insert(key.clone(), key);
+                let full_name = format!("{}.{}", u, v);
+                replace_values.insert(format!(".{}", full_name), full_name.clone());
+                replace_values.insert(full_name.clone(), full_name);
                 if parent["namespace"].as_str() == namespace {
                     replace_values.insert(String::from(v), String::from(v));
                 }
@@ -96,16 +126,246 @@ pub(crate) fn replace_reference(parent: value::Value, child: value::Value) -> va
         },
         None => return parent,
     };
+    let mut replaced = false;
     match parent {
-        value::Value::Object(v) => replace_in_map(v, &child, &replace_values),
-        value::Value::Array(v) => replace_in_array(&v, &child, &replace_values),
+        value::Value::Object(v) => replace_in_map(v, &child, &replace_values, &mut replaced),
+        value::Value::Array(v) => replace_in_array(&v, &child, &replace_values, &mut replaced),
         p => p,
     }
 }
 
-fn to_bytes(avro_schema: &AvroSchema, record: Value) -> Result<Vec<u8>, SRCError> {
-    match to_avro_datum(&avro_schema.parsed, record) {
-        Ok(v) => Ok(get_payload(avro_schema.id, v)),
+/// Whether the schema already contains the definition of the named type, for example because it
+/// was added as part of another reference, in which case the reference can be skipped.
+pub(crate) fn contains_definition(schema: &value::Value, full_name: &str) -> bool {
+    match schema {
+        value::Value::Object(v) => {
+            let defines = match (v.get("name"), v.get("type")) {
+                (Some(value::Value::String(n)), Some(value::Value::String(t)))
+                    if matches!(t.as_str(), "record" | "enum" | "fixed") =>
+                {
+                    match v.get("namespace").and_then(|ns| ns.as_str()) {
+                        Some(ns) if !n.contains('.') => format!("{}.{}", ns, n) == full_name,
+                        _ => n == full_name,
+                    }
+                }
+                _ => false,
+            };
+            defines || v.values().any(|c| contains_definition(c, full_name))
+        }
+        value::Value::Array(v) => v.iter().any(|c| contains_definition(c, full_name)),
+        _ => false,
+    }
+}
+
+/// Marker at the start of the avro single object encoding, followed by the little endian
+/// CRC-64-AVRO fingerprint of the schema.
+const SINGLE_OBJECT_MARKER: [u8; 2] = [0xC3, 0x01];
+
+/// How the avro bytes are framed, so a consumer knows the schema to use.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AvroFraming {
+    /// The magic byte followed by the schema id, as used by the Confluent clients.
+    #[default]
+    Confluent,
+    /// The avro single object encoding, with the fingerprint of the schema instead of the id.
+    SingleObject,
+}
+
+/// Gets the CRC-64-AVRO fingerprint of the parsing canonical form of the schema, as used by the
+/// avro single object encoding.
+pub fn get_fingerprint(schema: &Schema) -> u64 {
+    let fingerprint = schema.fingerprint::<Rabin>();
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&fingerprint.bytes[..8]);
+    u64::from_le_bytes(bytes)
+}
+
+/// How long a fingerprint that could not be found in the schema registry is remembered, so not
+/// every payload with an unknown fingerprint causes all the schema's to be fetched again.
+pub(crate) const MISSING_FINGERPRINT_TTL: Duration = Duration::from_secs(60);
+
+/// Keeps track of the fingerprints that could not be found, together with the lock making sure
+/// only one lookup of all the schema's is done at the same time.
+pub(crate) struct FingerprintLookups<L> {
+    missing: DashMap<u64, Instant>,
+    pub(crate) lock: L,
+}
+
+impl<L> FingerprintLookups<L> {
+    pub(crate) fn new(lock: L) -> FingerprintLookups<L> {
+        FingerprintLookups {
+            missing: DashMap::new(),
+            lock,
+        }
+    }
+
+    /// Whether the fingerprint was recently looked up without finding a schema for it.
+    pub(crate) fn is_missing(&self, fingerprint: u64) -> bool {
+        match self.missing.get(&fingerprint) {
+            Some(since) => since.elapsed() < MISSING_FINGERPRINT_TTL,
+            None => false,
+        }
+    }
+
+    /// Remembers the fingerprint could not be found. There is no clock on wasm32, so there the
+    /// missing fingerprints are never remembered, and each lookup fetches all the schema's again.
+    pub(crate) fn set_missing(&self, fingerprint: u64) {
+        if cfg!(not(target_arch = "wasm32")) {
+            self.missing.insert(fingerprint, Instant::now());
+        }
+    }
+}
+
+pub(crate) fn missing_fingerprint_error(fingerprint: u64) -> SRCError {
+    SRCError::non_retryable_without_cause(&format!(
+        "Could not find schema with fingerprint {}",
+        fingerprint
+    ))
+}
+
+impl<L> fmt::Debug for FingerprintLookups<L> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FingerprintLookups")
+            .field("missing", &self.missing)
+            .finish()
+    }
+}
+
+/// Writes the header of the avro single object encoding to the buffer.
+pub(crate) fn write_single_object_header(fingerprint: u64, buf: &mut Vec<u8>) {
+    buf.extend_from_slice(&SINGLE_OBJECT_MARKER);
+    buf.extend_from_slice(&fingerprint.to_le_bytes());
+}
+
+/// Gets the fingerprint and the data bytes from a payload using the avro single object encoding.
+pub(crate) fn get_single_object_bytes(bytes: &[u8]) -> Result<(u64, &[u8]), SRCError> {
+    match bytes {
+        [0xC3, 0x01, rest @ ..] if rest.len() >= 8 => {
+            let (fingerprint, data) = rest.split_at(8);
+            let mut buf = [0u8; 8];
+            buf.copy_from_slice(fingerprint);
+            Ok((u64::from_le_bytes(buf), data))
+        }
+        _ => Err(SRCError::non_retryable_without_cause(&format!(
+            "Invalid single object encoding bytes {:?}",
+            bytes
+        ))),
+    }
+}
+
+/// Compression of the avro bytes after the header. Both the encoder and the decoder need to use the
+/// same compression, as it's not part of the payload. Deflate is without zlib header, and snappy
+/// without checksum.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AvroCompression {
+    #[default]
+    None,
+    #[cfg(feature = "compression")]
+    Deflate,
+    #[cfg(feature = "compression")]
+    Snappy,
+    #[cfg(feature = "compression")]
+    Zstd,
+}
+
+pub(crate) fn compress(bytes: Vec<u8>, compression: AvroCompression) -> Result<Vec<u8>, SRCError> {
+    match compression {
+        AvroCompression::None => Ok(bytes),
+        #[cfg(feature = "compression")]
+        c => compress_with(&bytes, c)
+            .map_err(|e| SRCError::non_retryable_with_cause(e, "Could not compress avro bytes")),
+    }
+}
+
+pub(crate) fn decompress(
+    bytes: &[u8],
+    compression: AvroCompression,
+) -> Result<Cow<[u8]>, SRCError> {
+    match compression {
+        AvroCompression::None => Ok(Cow::Borrowed(bytes)),
+        #[cfg(feature = "compression")]
+        c => decompress_with(bytes, c)
+            .map(Cow::Owned)
+            .map_err(|e| SRCError::non_retryable_with_cause(e, "Could not decompress avro bytes")),
+    }
+}
+
+#[cfg(feature = "compression")]
+fn compress_with(bytes: &[u8], compression: AvroCompression) -> std::io::Result<Vec<u8>> {
+    match compression {
+        AvroCompression::None => Ok(bytes.to_vec()),
+        AvroCompression::Deflate => {
+            use std::io::Write;
+            let mut encoder =
+                flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(bytes)?;
+            encoder.finish()
+        }
+        AvroCompression::Snappy => Ok(snap::raw::Encoder::new().compress_vec(bytes)?),
+        AvroCompression::Zstd => zstd::encode_all(bytes, 0),
+    }
+}
+
+#[cfg(feature = "compression")]
+fn decompress_with(bytes: &[u8], compression: AvroCompression) -> std::io::Result<Vec<u8>> {
+    match compression {
+        AvroCompression::None => Ok(bytes.to_vec()),
+        AvroCompression::Deflate => {
+            use std::io::Read;
+            let mut decompressed = Vec::new();
+            flate2::read::DeflateDecoder::new(bytes).read_to_end(&mut decompressed)?;
+            Ok(decompressed)
+        }
+        AvroCompression::Snappy => Ok(snap::raw::Decoder::new().decompress_vec(bytes)?),
+        AvroCompression::Zstd => zstd::decode_all(bytes),
+    }
+}
+
+fn to_bytes(
+    schema: &Schema,
+    id: u32,
+    record: Value,
+    framing: AvroFraming,
+    id_strategy: &IdStrategy,
+    compression: AvroCompression,
+) -> Result<Vec<u8>, SRCError> {
+    let mut buf = Vec::new();
+    write_bytes(
+        schema,
+        id,
+        record,
+        framing,
+        id_strategy,
+        compression,
+        &mut buf,
+    )?;
+    Ok(buf)
+}
+
+/// Like `to_bytes`, but writing the payload to the buffer, which is cleared first. This way the
+/// header is written directly in the buffer, and the buffer can be reused for multiple records.
+pub(crate) fn write_bytes(
+    schema: &Schema,
+    id: u32,
+    record: Value,
+    framing: AvroFraming,
+    id_strategy: &IdStrategy,
+    compression: AvroCompression,
+    buf: &mut Vec<u8>,
+) -> Result<(), SRCError> {
+    match to_avro_datum(schema, record) {
+        Ok(v) => {
+            let v = compress(v, compression)?;
+            buf.clear();
+            match framing {
+                AvroFraming::Confluent => id_strategy.write_header(id, buf),
+                AvroFraming::SingleObject => {
+                    write_single_object_header(get_fingerprint(schema), buf)
+                }
+            }
+            buf.extend_from_slice(&v);
+            Ok(())
+        }
         Err(e) => Err(SRCError::non_retryable_with_cause(
             e,
             "Could not get Avro bytes",
@@ -113,13 +373,187 @@ fn to_bytes(avro_schema: &AvroSchema, record: Value) -> Result<Vec<u8>, SRCError
     }
 }
 
+fn record_field_schema<'s>(schema: &'s Schema, name: &str) -> Option<&'s Schema> {
+    match schema {
+        Schema::Record(r) => r.lookup.get(name).map(|i| &r.fields[*i].schema),
+        _ => None,
+    }
+}
+
+/// How well a value matches a branch of a union.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum BranchMatch {
+    None,
+    Widening,
+    Exact,
+}
+
+fn branch_match(value: &Value, schema: &Schema, names: &Names) -> BranchMatch {
+    match (value, resolve_ref(schema, names)) {
+        (Value::Null, Schema::Null)
+        | (Value::Boolean(_), Schema::Boolean)
+        | (Value::Int(_), Schema::Int)
+        | (Value::Long(_), Schema::Long)
+        | (Value::Float(_), Schema::Float)
+        | (Value::Double(_), Schema::Double)
+        | (Value::Bytes(_), Schema::Bytes)
+        | (Value::String(_), Schema::String)
+        | (Value::Array(_), Schema::Array(_))
+        | (Value::Map(_), Schema::Map(_))
+        | (Value::Decimal(_), Schema::Decimal(_))
+        | (Value::Uuid(_), Schema::Uuid)
+        | (Value::Date(_), Schema::Date)
+        | (Value::TimeMillis(_), Schema::TimeMillis)
+        | (Value::TimeMicros(_), Schema::TimeMicros)
+        | (Value::TimestampMillis(_), Schema::TimestampMillis)
+        | (Value::TimestampMicros(_), Schema::TimestampMicros)
+        | (Value::LocalTimestampMillis(_), Schema::LocalTimestampMillis)
+        | (Value::LocalTimestampMicros(_), Schema::LocalTimestampMicros) => BranchMatch::Exact,
+        (Value::Int(_), Schema::Long | Schema::Float | Schema::Double)
+        | (Value::Long(_), Schema::Float | Schema::Double)
+        | (Value::Float(_), Schema::Double) => BranchMatch::Widening,
+        (Value::Fixed(size, _), Schema::Fixed(f)) if *size == f.size => BranchMatch::Exact,
+        (Value::Enum(_, symbol), Schema::Enum(e)) if e.symbols.contains(symbol) => {
+            BranchMatch::Exact
+        }
+        (Value::Record(fields), Schema::Record(r))
+            if fields.iter().all(|(name, _)| r.lookup.contains_key(name)) =>
+        {
+            BranchMatch::Exact
+        }
+        _ => BranchMatch::None,
+    }
+}
+
+fn widen(value: Value, schema: &Schema) -> Value {
+    match (value, schema) {
+        (Value::Int(i), Schema::Long) => Value::Long(i64::from(i)),
+        (Value::Int(i), Schema::Float) => Value::Float(i as f32),
+        (Value::Int(i), Schema::Double) => Value::Double(f64::from(i)),
+        (Value::Long(l), Schema::Float) => Value::Float(l as f32),
+        (Value::Long(l), Schema::Double) => Value::Double(l as f64),
+        (Value::Float(f), Schema::Double) => Value::Double(f64::from(f)),
+        (v, _) => v,
+    }
+}
+
+/// Wraps values for union fields in the matching branch of the union, so values can be supplied
+/// without knowing the index of the branch. An exact match of the type is preferred over widening a
+/// number, and when multiple branches match equally well an error is returned. Values already
+/// wrapped in a union are kept as is.
+pub(crate) fn resolve_unions(
+    value: Value,
+    schema: &Schema,
+    names: &Names,
+) -> Result<Value, SRCError> {
+    let schema = resolve_ref(schema, names);
+    Ok(match (value, schema) {
+        (v @ Value::Union(_, _), _) => v,
+        (v, Schema::Union(u)) => {
+            let matches: Vec<(usize, BranchMatch)> = u
+                .variants()
+                .iter()
+                .map(|s| branch_match(&v, s, names))
+                .enumerate()
+                .filter(|(_, m)| *m != BranchMatch::None)
+                .collect();
+            let best = match matches.iter().map(|(_, m)| *m).max() {
+                Some(m) => m,
+                None => {
+                    return Err(SRCError::non_retryable_without_cause(&format!(
+                        "No branch of union {:?} matches value {:?}",
+                        u.variants(),
+                        v
+                    )))
+                }
+            };
+            let best_branches: Vec<usize> = matches
+                .iter()
+                .filter(|(_, m)| *m == best)
+                .map(|(i, _)| *i)
+                .collect();
+            if best_branches.len() > 1 {
+                return Err(SRCError::non_retryable_without_cause(&format!(
+                    "Value {:?} is ambiguous for union {:?}, wrap it in a Value::Union",
+                    v,
+                    u.variants()
+                )));
+            }
+            let index = best_branches[0];
+            let branch = resolve_ref(&u.variants()[index], names);
+            let v = resolve_unions(widen(v, branch), branch, names)?;
+            Value::Union(index as u32, Box::new(v))
+        }
+        (Value::Record(fields), Schema::Record(r)) => Value::Record(
+            fields
+                .into_iter()
+                .map(|(name, v)| match r.lookup.get(&name) {
+                    Some(i) => Ok((name, resolve_unions(v, &r.fields[*i].schema, names)?)),
+                    None => Ok((name, v)),
+                })
+                .collect::<Result<_, SRCError>>()?,
+        ),
+        (Value::Array(items), Schema::Array(inner)) => Value::Array(
+            items
+                .into_iter()
+                .map(|v| resolve_unions(v, inner, names))
+                .collect::<Result<_, SRCError>>()?,
+        ),
+        (Value::Map(items), Schema::Map(inner)) => Value::Map(
+            items
+                .into_iter()
+                .map(|(k, v)| Ok((k, resolve_unions(v, inner, names)?)))
+                .collect::<Result<_, SRCError>>()?,
+        ),
+        (v, _) => v,
+    })
+}
+
 /// Using the schema with a vector of values the values will be correctly deserialized according to
 /// the avro specification.
 pub(crate) fn values_to_bytes(
     avro_schema: &AvroSchema,
     values: Vec<(&str, Value)>,
+    encryptor: Option<&dyn FieldEncryptor>,
+    framing: AvroFraming,
+    id_strategy: &IdStrategy,
+    compression: AvroCompression,
 ) -> Result<Vec<u8>, SRCError> {
-    let mut record = match Record::new(&avro_schema.parsed) {
+    let record = values_to_encrypted_record(avro_schema, values, encryptor)?;
+    to_bytes(
+        &avro_schema.parsed,
+        avro_schema.id,
+        record,
+        framing,
+        id_strategy,
+        compression,
+    )
+}
+
+/// Creates a record from the values, with the tagged fields encrypted, ready to be written.
+pub(crate) fn values_to_encrypted_record(
+    avro_schema: &AvroSchema,
+    values: Vec<(&str, Value)>,
+    encryptor: Option<&dyn FieldEncryptor>,
+) -> Result<Value, SRCError> {
+    let record = values_to_record(&avro_schema.parsed, values)?;
+    apply_field_encryption(avro_schema, encryptor, record, Direction::Write)
+}
+
+pub(crate) fn values_to_bytes_with_schema(
+    schema: &Schema,
+    id: u32,
+    values: Vec<(&str, Value)>,
+    framing: AvroFraming,
+    id_strategy: &IdStrategy,
+    compression: AvroCompression,
+) -> Result<Vec<u8>, SRCError> {
+    let record = values_to_record(schema, values)?;
+    to_bytes(schema, id, record, framing, id_strategy, compression)
+}
+
+fn values_to_record(schema: &Schema, values: Vec<(&str, Value)>) -> Result<Value, SRCError> {
+    let mut record = match Record::new(schema) {
         Some(v) => v,
         None => {
             return Err(SRCError::new(
@@ -129,10 +563,35 @@ pub(crate) fn values_to_bytes(
             ));
         }
     };
-    for value in values {
-        record.put(value.0, value.1)
+    let mut names = Names::new();
+    collect_names(schema, &mut names);
+    for (name, value) in values {
+        let value = match record_field_schema(schema, name) {
+            Some(schema) => resolve_unions(value, schema, &names)?,
+            None => value,
+        };
+        record.put(name, value)
+    }
+    Ok(Value::from(record))
+}
+
+/// Encrypts or decrypts the tagged fields of the value, when there is an encryptor and the schema
+/// has encrypt rules for the direction.
+pub(crate) fn apply_field_encryption(
+    avro_schema: &AvroSchema,
+    encryptor: Option<&dyn FieldEncryptor>,
+    value: Value,
+    direction: Direction,
+) -> Result<Value, SRCError> {
+    match FieldEncryption::new(
+        encryptor,
+        avro_schema.rule_set.as_ref(),
+        avro_schema.metadata.as_ref(),
+        direction,
+    ) {
+        Some(encryption) => encryption.avro_value(&avro_schema.parsed, value),
+        None => Ok(value),
     }
-    to_bytes(avro_schema, Value::from(record))
 }
 
 /// Using the schema with an item implementing serialize the item will be correctly deserialized
@@ -140,19 +599,137 @@ pub(crate) fn values_to_bytes(
 pub(crate) fn item_to_bytes(
     avro_schema: &AvroSchema,
     item: impl Serialize,
+    codec_config: &AvroCodecConfig,
+    encryptor: Option<&dyn FieldEncryptor>,
+    framing: AvroFraming,
+    id_strategy: &IdStrategy,
+    compression: AvroCompression,
 ) -> Result<Vec<u8>, SRCError> {
+    let v = item_to_value(avro_schema, item, codec_config, encryptor)?;
+    to_bytes(
+        &avro_schema.parsed,
+        avro_schema.id,
+        v,
+        framing,
+        id_strategy,
+        compression,
+    )
+}
+
+/// Transforms the item to an avro value resolved to the schema, with the tagged fields encrypted,
+/// ready to be written.
+pub(crate) fn item_to_value(
+    avro_schema: &AvroSchema,
+    item: impl Serialize,
+    codec_config: &AvroCodecConfig,
+    encryptor: Option<&dyn FieldEncryptor>,
+) -> Result<Value, SRCError> {
     match to_value(item)
         .map_err(|e| {
             SRCError::non_retryable_with_cause(e, "Could not transform to apache_avro value")
         })
+        .and_then(|r| from_serde_value(r, &avro_schema.parsed, codec_config))
         .map(|r| r.resolve(&avro_schema.parsed))
     {
-        Ok(Ok(v)) => to_bytes(avro_schema, v),
+        Ok(Ok(v)) => apply_field_encryption(avro_schema, encryptor, v, Direction::Write),
         Ok(Err(e)) => Err(SRCError::non_retryable_with_cause(e, "Failed to resolve")),
         Err(e) => Err(e),
     }
 }
 
+/// Decodes the bytes, without any header, written with the schema.
+pub(crate) fn decode_datum(
+    avro_schema: &AvroSchema,
+    bytes: &[u8],
+    encryptor: Option<&dyn FieldEncryptor>,
+) -> Result<DecodeResult, SRCError> {
+    let mut reader = Cursor::new(bytes);
+    match from_avro_datum(&avro_schema.parsed, &mut reader, None) {
+        Ok(value) => Ok(DecodeResult {
+            name: get_name(&avro_schema.parsed),
+            value: apply_field_encryption(avro_schema, encryptor, value, Direction::Read)?,
+        }),
+        Err(e) => Err(SRCError::non_retryable_with_cause(
+            e,
+            "Could not transform bytes using schema",
+        )),
+    }
+}
+
+/// Decodes the bytes written with the writer schema, resolving the value to the reader schema.
+pub(crate) fn decode_with_reader_schema(
+    writer_schema: &AvroSchema,
+    bytes: &[u8],
+    reader_schema: &Schema,
+    encryptor: Option<&dyn FieldEncryptor>,
+) -> Result<DecodeResult, SRCError> {
+    let result = match encryptor {
+        // The tags are part of the writer schema, so decrypt before resolving to the reader schema.
+        Some(_) if writer_schema.rule_set.is_some() => {
+            decode_datum(writer_schema, bytes, encryptor)?
+                .value
+                .resolve(reader_schema)
+        }
+        _ => from_avro_datum(
+            &writer_schema.parsed,
+            &mut Cursor::new(bytes),
+            Some(reader_schema),
+        ),
+    };
+    match result {
+        Ok(value) => Ok(DecodeResult {
+            name: get_name(reader_schema),
+            value,
+        }),
+        Err(e) => Err(SRCError::non_retryable_with_cause(
+            e,
+            "Could not transform bytes using writer and reader schema",
+        )),
+    }
+}
+
+/// Reads the value, written with another version of the schema, as the reader schema. When there
+/// are migration rules, they transform the value as json first.
+pub(crate) fn migrate_value(
+    value: Value,
+    reader_schema: &AvroSchema,
+    rules: &[Rule],
+    executor: &dyn RuleExecutor,
+) -> Result<Value, SRCError> {
+    let value = if rules.is_empty() {
+        value
+    } else {
+        let json = match serde_json::Value::try_from(value) {
+            Ok(v) => v,
+            Err(e) => {
+                return Err(SRCError::non_retryable_with_cause(
+                    e,
+                    "Could not transform the avro value to json",
+                ))
+            }
+        };
+        Value::from(migrate(executor, rules, json)?)
+    };
+    match value.resolve(&reader_schema.parsed) {
+        Ok(v) => Ok(v),
+        Err(e) => Err(SRCError::non_retryable_with_cause(
+            e,
+            "Could not resolve the migrated value to the reader schema",
+        )),
+    }
+}
+
+/// Deserializes the decoded value into the struct.
+pub(crate) fn from_avro_value<T: DeserializeOwned>(value: &Value) -> Result<T, SRCError> {
+    match from_value::<T>(value) {
+        Ok(v) => Ok(v),
+        Err(e) => Err(SRCError::non_retryable_with_cause(
+            e,
+            "Could not deserialize the avro value",
+        )),
+    }
+}
+
 pub(crate) fn get_name(schema: &Schema) -> Option<Name> {
     match schema {
         Schema::Record(schema) => Some(schema.name.clone()),
@@ -160,6 +737,193 @@ pub(crate) fn get_name(schema: &Schema) -> Option<Name> {
     }
 }
 
+/// What the decoders do with the fields of the writer schema which are not in the reader schema.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum UnknownFieldPolicy {
+    /// Skips the fields.
+    #[default]
+    Ignore,
+    /// Skips the fields, logging a warning with the names of the fields once for each writer schema.
+    Warn,
+    /// Gives an error when decoding a value written with a schema with fields not in the reader
+    /// schema.
+    Error,
+}
+
+/// The schema the decoders read all the values as, with the fields missing in the writer schema
+/// filled with the default of the reader schema, so consumers don't need to be upgraded in lock-step
+/// with the producers.
+#[derive(Clone, Debug)]
+pub(crate) struct ReaderSchema {
+    schema: Arc<Schema>,
+    unknown_fields: UnknownFieldPolicy,
+    /// The reader schema with the id and subject of the writer schema, by the id of the writer.
+    by_writer: Arc<DashMap<u32, Arc<AvroSchema>>>,
+}
+
+impl ReaderSchema {
+    pub(crate) fn new(schema: Schema, unknown_fields: UnknownFieldPolicy) -> ReaderSchema {
+        ReaderSchema {
+            schema: Arc::new(schema),
+            unknown_fields,
+            by_writer: Arc::new(DashMap::new()),
+        }
+    }
+
+    /// Resolves the value written with the writer schema to the reader schema.
+    pub(crate) fn read(
+        &self,
+        writer_schema: &AvroSchema,
+        value: Value,
+    ) -> Result<(Arc<AvroSchema>, Value), SRCError> {
+        let cached = self
+            .by_writer
+            .get(&writer_schema.id)
+            .map(|s| s.value().clone());
+        let reader_schema = match cached {
+            Some(v) => v,
+            None => {
+                self.check_unknown_fields(writer_schema)?;
+                let reader_schema = Arc::new(AvroSchema {
+                    id: writer_schema.id,
+                    raw: self.schema.canonical_form(),
+                    parsed: (*self.schema).clone(),
+                    rule_set: None,
+                    metadata: None,
+                    subject: writer_schema.subject.clone(),
+                });
+                self.by_writer
+                    .insert(writer_schema.id, reader_schema.clone());
+                reader_schema
+            }
+        };
+        match value.resolve(&reader_schema.parsed) {
+            Ok(v) => Ok((reader_schema, v)),
+            Err(e) => Err(SRCError::non_retryable_with_cause(
+                e,
+                &format!(
+                    "Could not read the value written with schema id {} as the reader schema",
+                    writer_schema.id
+                ),
+            )),
+        }
+    }
+
+    fn check_unknown_fields(&self, writer_schema: &AvroSchema) -> Result<(), SRCError> {
+        let unknown = match (&writer_schema.parsed, &*self.schema) {
+            (Schema::Record(writer), Schema::Record(reader)) => writer
+                .fields
+                .iter()
+                .filter(|w| !reader.lookup.contains_key(&w.name))
+                .map(|w| w.name.as_str())
+                .collect::<Vec<&str>>(),
+            _ => return Ok(()),
+        };
+        if unknown.is_empty() {
+            return Ok(());
+        }
+        match self.unknown_fields {
+            UnknownFieldPolicy::Ignore => Ok(()),
+            UnknownFieldPolicy::Warn => {
+                warn!(
+                    "Fields {:?} of the schema with id {} are not in the reader schema, and are skipped",
+                    unknown, writer_schema.id
+                );
+                Ok(())
+            }
+            UnknownFieldPolicy::Error => Err(SRCError::non_retryable_without_cause(&format!(
+                "Fields {:?} of the schema with id {} are not in the reader schema",
+                unknown, writer_schema.id
+            ))),
+        }
+    }
+}
+
+/// Avro schema's available locally by subject, for example from .avsc files embedded at build
+/// time. When used by the encoder, the schema registry is not needed when the id is known, and only
+/// called once to look up the id when it's not.
+#[derive(Debug, Default)]
+pub struct LocalSchemaProvider {
+    schemas: DashMap<String, LocalSchema>,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) enum LocalSchema {
+    /// The id is known, so the schema can be used directly.
+    WithId(Arc<AvroSchema>),
+    /// The raw schema, for which the id still needs to be looked up.
+    WithoutId(String),
+}
+
+impl LocalSchemaProvider {
+    pub fn new() -> LocalSchemaProvider {
+        LocalSchemaProvider::default()
+    }
+    /// Adds the schema for the subject, together with the id it's registered with.
+    pub fn add_schema_with_id(&self, subject: &str, raw: &str, id: u32) -> Result<(), SRCError> {
+        let parsed = parse_local_schema(raw)?;
+        let avro_schema = AvroSchema {
+            id,
+            raw: String::from(raw),
+            parsed,
+            rule_set: None,
+            metadata: None,
+            subject: None,
+        };
+        self.schemas.insert(
+            String::from(subject),
+            LocalSchema::WithId(Arc::new(avro_schema)),
+        );
+        Ok(())
+    }
+    /// Adds the schema for the subject, the id is looked up at the schema registry on first use,
+    /// which also verifies the schema is registered for the subject.
+    pub fn add_schema(&self, subject: &str, raw: &str) -> Result<(), SRCError> {
+        parse_local_schema(raw)?;
+        self.schemas.insert(
+            String::from(subject),
+            LocalSchema::WithoutId(String::from(raw)),
+        );
+        Ok(())
+    }
+    pub(crate) fn get(&self, subject: &str) -> Option<LocalSchema> {
+        self.schemas.get(subject).map(|s| s.value().clone())
+    }
+}
+
+pub(crate) fn parse_local_schema(raw: &str) -> Result<Schema, SRCError> {
+    Schema::parse_str(raw).map_err(|e| {
+        SRCError::non_retryable_with_cause(
+            e,
+            &format!("Supplied raw value {:?} cant be turned into a Schema", raw),
+        )
+    })
+}
+
+/// Checks the full name of the schema, including the namespace, is the record name used for the
+/// subject, when the subject name strategy uses a record name. Like with the Java client, this
+/// prevents values from being encoded with the schema registered for another record.
+pub(crate) fn check_record_name(
+    avro_schema: Arc<AvroSchema>,
+    subject_name_strategy: &SubjectNameStrategy,
+) -> Result<Arc<AvroSchema>, SRCError> {
+    let record_name = match subject_name_strategy.get_record_name() {
+        Some(v) => v,
+        None => return Ok(avro_schema),
+    };
+    match avro_schema.parsed.name().map(|n| n.fullname(None)) {
+        Some(full_name) if full_name == record_name => Ok(avro_schema),
+        Some(full_name) => Err(SRCError::non_retryable_without_cause(&format!(
+            "Record name {} of the subject name strategy doesn't match the full name {} of the schema with id {}",
+            record_name, full_name, avro_schema.id
+        ))),
+        None => Err(SRCError::non_retryable_without_cause(&format!(
+            "Record name {} of the subject name strategy can't be used for the schema with id {}, since it has no name",
+            record_name, avro_schema.id
+        ))),
+    }
+}
+
 pub fn get_supplied_schema(schema: &Schema) -> SuppliedSchema {
     let name = match get_name(schema) {
         None => None,
@@ -183,8 +947,15 @@ mod tests {
 
     use test_utils::{Atype, ConfirmAccountCreation, Heartbeat};
 
-    use crate::avro_common::{values_to_bytes, AvroSchema};
+    use crate::avro_common::{contains_definition, replace_reference};
+    use crate::avro_common::{
+        get_fingerprint, get_single_object_bytes, values_to_bytes, write_single_object_header,
+        AvroCompression, AvroFraming, AvroSchema, FingerprintLookups,
+    };
+    use crate::avro_common::{record_field_schema, resolve_unions};
+    use crate::avro_logical::{AvroCodecConfig, Names};
     use crate::error::SRCError;
+    use crate::schema_registry_common::IdStrategy;
 
     #[test]
     fn to_bytes_no_record() {
@@ -192,8 +963,18 @@ mod tests {
             id: 5,
             raw: "".to_string(),
             parsed: Schema::Boolean,
+            rule_set: None,
+            metadata: None,
+            subject: None,
         };
-        let result = values_to_bytes(&schema, vec![("beat", Value::Long(3))]);
+        let result = values_to_bytes(
+            &schema,
+            vec![("beat", Value::Long(3))],
+            None,
+            AvroFraming::Confluent,
+            &IdStrategy::Confluent,
+            AvroCompression::None,
+        );
         assert_eq!(
             result,
             Err(SRCError::new(
@@ -210,8 +991,19 @@ mod tests {
             id: 5,
             raw: String::from(r#"{"type":"record","name":"Name","namespace":"nl.openweb.data","fields":[{"name":"name","type":"string","avro.java.string":"String"}]}"#),
             parsed: Schema::parse_str(r#"{"type":"record","name":"Name","namespace":"nl.openweb.data","fields":[{"name":"name","type":"string","avro.java.string":"String"}]}"#).unwrap(),
+            rule_set: None,
+            metadata: None,
+            subject: None,
         };
-        let err = values_to_bytes(&schema, vec![("beat", Value::Long(3))]).unwrap_err();
+        let err = values_to_bytes(
+            &schema,
+            vec![("beat", Value::Long(3))],
+            None,
+            AvroFraming::Confluent,
+            &IdStrategy::Confluent,
+            AvroCompression::None,
+        )
+        .unwrap_err();
         assert_eq!(err.error, "Could not get Avro bytes")
     }
 
@@ -225,8 +1017,20 @@ mod tests {
             parsed: Schema::parse_str(
                 r#"{"type":"record","name":"Name","namespace":"nl.openweb.data","fields":[{"name":"name","type":"string","avro.java.string":"String"}]}"#,
             ).unwrap(),
+            rule_set: None,
+            metadata: None,
+            subject: None,
         };
-        let err = crate::avro_common::item_to_bytes(&schema, Heartbeat { beat: 3 }).unwrap_err();
+        let err = crate::avro_common::item_to_bytes(
+            &schema,
+            Heartbeat { beat: 3 },
+            &AvroCodecConfig::default(),
+            None,
+            AvroFraming::Confluent,
+            &IdStrategy::Confluent,
+            AvroCompression::None,
+        )
+        .unwrap_err();
         assert_eq!(err.error, "Failed to resolve")
     }
 
@@ -240,6 +1044,9 @@ mod tests {
             parsed: Schema::parse_str(
                 r#"{"type":"record","name":"ConfirmAccountCreation","namespace":"nl.openweb.data","fields":[{"name":"id","type":{"type":"fixed","name":"Uuid","size":16}},{"name":"a_type","type":{"type":"enum","name":"Atype","symbols":["AUTO","MANUAL"]}}]}"#,
             ).unwrap(),
+            rule_set: None,
+            metadata: None,
+            subject: None,
         };
         let item = ConfirmAccountCreation {
             id: [
@@ -247,7 +1054,131 @@ mod tests {
             ],
             a_type: Atype::Manual,
         };
-        let err = crate::avro_common::item_to_bytes(&schema, item).unwrap_err();
+        let err = crate::avro_common::item_to_bytes(
+            &schema,
+            item,
+            &AvroCodecConfig::default(),
+            None,
+            AvroFraming::Confluent,
+            &IdStrategy::Confluent,
+            AvroCompression::None,
+        )
+        .unwrap_err();
         assert_eq!(err.error, "Failed to resolve")
     }
+
+    #[test]
+    fn single_object_round_trip() {
+        let mut payload = Vec::new();
+        write_single_object_header(0x0102_0304_0506_0708, &mut payload);
+        payload.push(6);
+        assert_eq!(payload, vec![0xC3, 0x01, 8, 7, 6, 5, 4, 3, 2, 1, 6]);
+        let (fingerprint, data) = get_single_object_bytes(&payload).unwrap();
+        assert_eq!(fingerprint, 0x0102_0304_0506_0708);
+        assert_eq!(data, &[6]);
+        assert!(get_single_object_bytes(&[0, 0, 0, 0, 1, 6]).is_err());
+    }
+
+    #[test]
+    fn fingerprint_of_primitives() {
+        assert_eq!(get_fingerprint(&Schema::Null), 7_195_948_357_588_979_594);
+        assert_eq!(
+            get_fingerprint(&Schema::Long),
+            -3_434_872_931_120_570_953i64 as u64
+        );
+    }
+
+    #[test]
+    fn missing_fingerprints() {
+        let lookups = FingerprintLookups::new(());
+        assert!(!lookups.is_missing(5));
+        lookups.set_missing(5);
+        assert!(lookups.is_missing(5));
+        assert!(!lookups.is_missing(6));
+        assert_eq!(
+            format!("{:?}", FingerprintLookups::new(())),
+            "FingerprintLookups { missing: {} }"
+        );
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn compression_round_trip() {
+        use crate::avro_common::{compress, decompress};
+        let bytes = vec![6u8; 100];
+        for compression in [
+            AvroCompression::Deflate,
+            AvroCompression::Snappy,
+            AvroCompression::Zstd,
+        ] {
+            let compressed = compress(bytes.clone(), compression).unwrap();
+            assert!(compressed.len() < bytes.len());
+            assert_eq!(decompress(&compressed, compression).unwrap(), bytes);
+        }
+    }
+
+    #[test]
+    fn resolve_union_branches() {
+        let schema = Schema::parse_str(
+            r#"{"type":"record","name":"Heartbeat","namespace":"nl.openweb.data","fields":[
+                {"name":"beat","type":["null","long"]},
+                {"name":"ratio","type":["null","float","double"]}
+            ]}"#,
+        )
+        .unwrap();
+        let names = Names::new();
+        let beat = |v| resolve_unions(v, record_field_schema(&schema, "beat").unwrap(), &names);
+        assert_eq!(
+            beat(Value::Null).unwrap(),
+            Value::Union(0, Box::new(Value::Null))
+        );
+        assert_eq!(
+            beat(Value::Long(3)).unwrap(),
+            Value::Union(1, Box::new(Value::Long(3)))
+        );
+        assert_eq!(
+            beat(Value::Int(3)).unwrap(),
+            Value::Union(1, Box::new(Value::Long(3)))
+        );
+        assert!(beat(Value::String(String::from("3"))).is_err());
+
+        let ratio = |v| resolve_unions(v, record_field_schema(&schema, "ratio").unwrap(), &names);
+        assert_eq!(
+            ratio(Value::Float(0.5)).unwrap(),
+            Value::Union(1, Box::new(Value::Float(0.5)))
+        );
+        let error = ratio(Value::Int(1)).unwrap_err();
+        assert!(error.error.starts_with("Value Int(1) is ambiguous"));
+    }
+
+    #[test]
+    fn replace_reference_used_twice() {
+        let parent = serde_json::json!({
+            "type": "record",
+            "name": "Match",
+            "namespace": "org.example",
+            "fields": [
+                {"name": "home", "type": "org.example.Result"},
+                {"name": "away", "type": "Result"}
+            ]
+        });
+        let child = serde_json::json!({
+            "type": "record",
+            "name": "Result",
+            "namespace": "org.example",
+            "fields": [{"name": "goals", "type": "int"}]
+        });
+        assert!(!contains_definition(&parent, "org.example.Result"));
+
+        let replaced = replace_reference(parent, child.clone());
+
+        assert!(contains_definition(&replaced, "org.example.Result"));
+        assert_eq!(replaced["fields"][0]["type"], child);
+        assert_eq!(replaced["fields"][1]["type"], "Result");
+        let schema = Schema::parse(&replaced).unwrap();
+        assert_eq!(
+            schema.canonical_form(),
+            r#"{"name":"org.example.Match","type":"record","fields":[{"name":"home","type":{"name":"org.example.Result","type":"record","fields":[{"name":"goals","type":"int"}]}},{"name":"away","type":"org.example.Result"}]}"#
+        );
+    }
 }
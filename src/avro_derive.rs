@@ -0,0 +1,134 @@
+//! Support for the `SrAvroSerialize` derive macro, which takes away the need to build the values
+//! for the avro encoder by hand. The schema is read from an .avsc file at compile time, and the
+//! subject is set using attributes on the struct.
+//!
+//! ```ignore
+//! use schema_registry_converter::avro_derive::SrAvroSerialize;
+//!
+//! #[derive(SrAvroSerialize)]
+//! #[sr_avro(schema = "schemas/heartbeat.avsc", topic = "heartbeat")]
+//! struct Heartbeat {
+//!     beat: i64,
+//! }
+//!
+//! let bytes = encoder.encode_derived(&Heartbeat { beat: 3 }).await;
+//! ```
+pub use apache_avro::types::Value;
+use apache_avro::{to_value, Schema};
+pub use schema_registry_converter_derive::SrAvroSerialize;
+use serde::Serialize;
+
+use crate::avro_common::get_name;
+use crate::error::SRCError;
+use crate::schema_registry_common::{SchemaType, SubjectNameStrategy, SuppliedSchema};
+
+/// Implemented by the derive macro, gives the schema, the subject and the values in the form used
+/// by the encode function of the `AvroEncoder`.
+pub trait SrAvroSerialize {
+    /// The content of the .avsc file.
+    const AVRO_SCHEMA: &'static str;
+    /// The strategy to use, which always includes the schema, so it's registered when missing.
+    fn subject_name_strategy() -> Result<SubjectNameStrategy, SRCError>;
+    /// The values of the fields, by the name in the schema.
+    fn to_avro_values(&self) -> Result<Vec<(&'static str, Value)>, SRCError>;
+}
+
+/// Used by the derive macro to get the value of a field.
+#[doc(hidden)]
+pub fn to_field_value<T: Serialize + ?Sized>(field: &T) -> Result<Value, SRCError> {
+    to_value(field)
+        .map_err(|e| SRCError::non_retryable_with_cause(e, "Could not transform to avro value"))
+}
+
+/// Used by the derive macro to get the strategy from the attributes. When `record_name` is set,
+/// the full name of the record is taken from the schema, which is only parsed in that case.
+#[doc(hidden)]
+pub fn get_subject_name_strategy(
+    raw_schema: &str,
+    topic: Option<&str>,
+    is_key: bool,
+    record_name: bool,
+) -> Result<SubjectNameStrategy, SRCError> {
+    let name = if record_name {
+        match Schema::parse_str(raw_schema) {
+            Ok(parsed) => get_name(&parsed).map(|n| n.fullname(None)),
+            Err(e) => {
+                return Err(SRCError::non_retryable_with_cause(
+                    e,
+                    &format!(
+                        "Supplied raw value {:?} cant be turned into a Schema",
+                        raw_schema
+                    ),
+                ))
+            }
+        }
+    } else {
+        None
+    };
+    let supplied_schema = SuppliedSchema {
+        name,
+        schema_type: SchemaType::Avro,
+        schema: String::from(raw_schema),
+        references: vec![],
+    };
+    match (topic, record_name) {
+        (Some(t), false) => Ok(SubjectNameStrategy::TopicNameStrategyWithSchema(
+            String::from(t),
+            is_key,
+            supplied_schema,
+        )),
+        (Some(t), true) => Ok(SubjectNameStrategy::TopicRecordNameStrategyWithSchema(
+            String::from(t),
+            supplied_schema,
+        )),
+        (None, true) => Ok(SubjectNameStrategy::RecordNameStrategyWithSchema(
+            supplied_schema,
+        )),
+        (None, false) => Err(SRCError::non_retryable_without_cause(
+            "Either a topic or record_name is needed to get the subject",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::avro_derive::{get_subject_name_strategy, to_field_value, Value};
+    use crate::schema_registry_common::SubjectNameStrategy;
+
+    const HEARTBEAT: &str = r#"{"type":"record","name":"Heartbeat","namespace":"nl.openweb.data","fields":[{"name":"beat","type":"long"}]}"#;
+
+    #[test]
+    fn subject_from_attributes() {
+        let strategy =
+            get_subject_name_strategy(HEARTBEAT, Some("heartbeat"), true, false).unwrap();
+        assert_eq!(strategy.get_subject().unwrap(), "heartbeat-key");
+
+        let strategy =
+            get_subject_name_strategy(HEARTBEAT, Some("heartbeat"), false, true).unwrap();
+        assert_eq!(
+            strategy.get_subject().unwrap(),
+            "heartbeat-nl.openweb.data.Heartbeat"
+        );
+
+        let strategy = get_subject_name_strategy(HEARTBEAT, None, false, true).unwrap();
+        match strategy {
+            SubjectNameStrategy::RecordNameStrategyWithSchema(s) => {
+                assert_eq!(s.name, Some(String::from("nl.openweb.data.Heartbeat")));
+                assert_eq!(s.schema, HEARTBEAT);
+            }
+            s => panic!("Unexpected strategy {:?}", s),
+        }
+
+        assert!(get_subject_name_strategy(HEARTBEAT, None, false, false).is_err());
+        assert!(get_subject_name_strategy("{", Some("heartbeat"), false, true).is_err());
+    }
+
+    #[test]
+    fn field_values() {
+        assert_eq!(to_field_value(&3i64).unwrap(), Value::Long(3));
+        assert_eq!(
+            to_field_value("text").unwrap(),
+            Value::String(String::from("text"))
+        );
+    }
+}
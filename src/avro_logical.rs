@@ -0,0 +1,524 @@
+//! Conversions between the avro logical types and the string representations used by the serde
+//! implementations of rust_decimal, bigdecimal, uuid and chrono. By default apache_avro gives the
+//! raw bytes or numbers of the logical types when deserializing, and expects them when serializing.
+//! With an [AvroCodecConfig] the decoders and encoders convert them, so for example a
+//! `chrono::NaiveDate` can be used for a date, and a `rust_decimal::Decimal` for a decimal.
+use std::collections::HashMap;
+
+use apache_avro::schema::Schema;
+use apache_avro::types::Value;
+use apache_avro::Decimal;
+use chrono::{DateTime, Datelike, NaiveDate, NaiveDateTime, NaiveTime, SecondsFormat, Timelike};
+
+use crate::error::SRCError;
+
+/// Days from the first day of the common era until the unix epoch.
+const UNIX_EPOCH_DAYS_FROM_CE: i32 = 719_163;
+
+/// Configures which logical types are converted by `decode_as` and `encode_struct`. The default
+/// converts nothing, keeping the apache_avro behaviour.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AvroCodecConfig {
+    /// Decimals as a plain string like "-12.30", which is what rust_decimal and bigdecimal use.
+    pub decimal_as_string: bool,
+    /// Uuid's as a hyphenated string, which is what uuid uses.
+    pub uuid_as_string: bool,
+    /// Dates, times and timestamps as the strings chrono uses. A date like "2024-01-31" for
+    /// `NaiveDate`, a time like "12:30:00.123" for `NaiveTime`, a timestamp like
+    /// "2024-01-31T12:30:00.123Z" for `DateTime<Utc>`, and a local timestamp like
+    /// "2024-01-31T12:30:00.123" for `NaiveDateTime`.
+    pub temporal_as_string: bool,
+}
+
+impl AvroCodecConfig {
+    /// Converts all the supported logical types.
+    pub fn all_as_string() -> AvroCodecConfig {
+        AvroCodecConfig {
+            decimal_as_string: true,
+            uuid_as_string: true,
+            temporal_as_string: true,
+        }
+    }
+    fn converts_nothing(&self) -> bool {
+        !(self.decimal_as_string || self.uuid_as_string || self.temporal_as_string)
+    }
+}
+
+/// Replaces the logical types in a decoded value by strings, so it can be deserialized into the
+/// ergonomic rust types.
+pub(crate) fn to_serde_value(
+    value: Value,
+    schema: &Schema,
+    config: &AvroCodecConfig,
+) -> Result<Value, SRCError> {
+    if config.converts_nothing() {
+        return Ok(value);
+    }
+    let mut names = HashMap::new();
+    collect_names(schema, &mut names);
+    to_serde(value, schema, &names, config)
+}
+
+/// Replaces the strings of serialized ergonomic rust types by the logical types of the schema,
+/// so the value can be resolved against the schema.
+pub(crate) fn from_serde_value(
+    value: Value,
+    schema: &Schema,
+    config: &AvroCodecConfig,
+) -> Result<Value, SRCError> {
+    if config.converts_nothing() {
+        return Ok(value);
+    }
+    let mut names = HashMap::new();
+    collect_names(schema, &mut names);
+    from_serde(value, schema, &names, config)
+}
+
+pub(crate) type Names<'s> = HashMap<String, &'s Schema>;
+
+pub(crate) fn collect_names<'s>(schema: &'s Schema, names: &mut Names<'s>) {
+    match schema {
+        Schema::Record(r) => {
+            names.insert(r.name.fullname(None), schema);
+            for field in &r.fields {
+                collect_names(&field.schema, names)
+            }
+        }
+        Schema::Enum(e) => {
+            names.insert(e.name.fullname(None), schema);
+        }
+        Schema::Fixed(f) => {
+            names.insert(f.name.fullname(None), schema);
+        }
+        Schema::Array(inner) | Schema::Map(inner) => collect_names(inner, names),
+        Schema::Union(u) => {
+            for variant in u.variants() {
+                collect_names(variant, names)
+            }
+        }
+        _ => (),
+    }
+}
+
+pub(crate) fn resolve_ref<'s>(schema: &'s Schema, names: &Names<'s>) -> &'s Schema {
+    match schema {
+        Schema::Ref { name } => names.get(&name.fullname(None)).copied().unwrap_or(schema),
+        s => s,
+    }
+}
+
+fn to_serde(
+    value: Value,
+    schema: &Schema,
+    names: &Names,
+    config: &AvroCodecConfig,
+) -> Result<Value, SRCError> {
+    let schema = resolve_ref(schema, names);
+    Ok(match (value, schema) {
+        (Value::Record(fields), Schema::Record(r)) => Value::Record(
+            fields
+                .into_iter()
+                .map(|(name, v)| match r.lookup.get(&name) {
+                    Some(i) => Ok((name, to_serde(v, &r.fields[*i].schema, names, config)?)),
+                    None => Ok((name, v)),
+                })
+                .collect::<Result<_, SRCError>>()?,
+        ),
+        (Value::Array(items), Schema::Array(inner)) => Value::Array(
+            items
+                .into_iter()
+                .map(|v| to_serde(v, inner, names, config))
+                .collect::<Result<_, SRCError>>()?,
+        ),
+        (Value::Map(items), Schema::Map(inner)) => Value::Map(
+            items
+                .into_iter()
+                .map(|(k, v)| Ok((k, to_serde(v, inner, names, config)?)))
+                .collect::<Result<_, SRCError>>()?,
+        ),
+        (Value::Union(i, v), Schema::Union(u)) => match u.variants().get(i as usize) {
+            Some(variant) => Value::Union(i, Box::new(to_serde(*v, variant, names, config)?)),
+            None => Value::Union(i, v),
+        },
+        (Value::Decimal(d), Schema::Decimal(ds)) if config.decimal_as_string => {
+            Value::String(decimal_to_string(&d, ds.scale)?)
+        }
+        (Value::Uuid(u), _) if config.uuid_as_string => Value::String(u.to_string()),
+        (v, _) if config.temporal_as_string => match temporal_to_string(&v) {
+            Some(s) => Value::String(s),
+            None => v,
+        },
+        (v, _) => v,
+    })
+}
+
+fn from_serde(
+    value: Value,
+    schema: &Schema,
+    names: &Names,
+    config: &AvroCodecConfig,
+) -> Result<Value, SRCError> {
+    let schema = resolve_ref(schema, names);
+    Ok(match (value, schema) {
+        (Value::Record(fields), Schema::Record(r)) => Value::Record(
+            fields
+                .into_iter()
+                .map(|(name, v)| match r.lookup.get(&name) {
+                    Some(i) => Ok((name, from_serde(v, &r.fields[*i].schema, names, config)?)),
+                    None => Ok((name, v)),
+                })
+                .collect::<Result<_, SRCError>>()?,
+        ),
+        (Value::Array(items), Schema::Array(inner)) => Value::Array(
+            items
+                .into_iter()
+                .map(|v| from_serde(v, inner, names, config))
+                .collect::<Result<_, SRCError>>()?,
+        ),
+        (Value::Map(items), Schema::Map(inner)) => Value::Map(
+            items
+                .into_iter()
+                .map(|(k, v)| Ok((k, from_serde(v, inner, names, config)?)))
+                .collect::<Result<_, SRCError>>()?,
+        ),
+        (Value::Union(i, v), Schema::Union(u)) => Value::Union(
+            i,
+            Box::new(from_serde_union(*v, u.variants(), names, config)?),
+        ),
+        (v, Schema::Union(u)) => from_serde_union(v, u.variants(), names, config)?,
+        (Value::String(s), Schema::Decimal(ds)) if config.decimal_as_string => {
+            match decimal_from_string(&s, ds.scale) {
+                Some(bytes) => Value::Decimal(Decimal::from(bytes)),
+                None => return Err(conversion_error(&s, "decimal")),
+            }
+        }
+        (Value::String(s), schema) if config.temporal_as_string && is_temporal(schema) => {
+            match temporal_from_string(&s, schema) {
+                Some(v) => v,
+                None => return Err(conversion_error(&s, "date, time or timestamp")),
+            }
+        }
+        (v, _) => v,
+    })
+}
+
+/// The serialized value doesn't know the union variant, so the first variant the value can be
+/// converted to is used.
+fn from_serde_union(
+    value: Value,
+    variants: &[Schema],
+    names: &Names,
+    config: &AvroCodecConfig,
+) -> Result<Value, SRCError> {
+    if value == Value::Null {
+        return Ok(value);
+    }
+    for variant in variants.iter().filter(|v| **v != Schema::Null) {
+        if let Ok(v) = from_serde(value.clone(), variant, names, config) {
+            return Ok(v);
+        }
+    }
+    Ok(value)
+}
+
+fn conversion_error(s: &str, logical_type: &str) -> SRCError {
+    SRCError::non_retryable_without_cause(&format!(
+        "Could not convert string {} to {}",
+        s, logical_type
+    ))
+}
+
+fn is_temporal(schema: &Schema) -> bool {
+    matches!(
+        schema,
+        Schema::Date
+            | Schema::TimeMillis
+            | Schema::TimeMicros
+            | Schema::TimestampMillis
+            | Schema::TimestampMicros
+            | Schema::LocalTimestampMillis
+            | Schema::LocalTimestampMicros
+    )
+}
+
+fn temporal_to_string(value: &Value) -> Option<String> {
+    match value {
+        Value::Date(days) => {
+            NaiveDate::from_num_days_from_ce_opt(days.checked_add(UNIX_EPOCH_DAYS_FROM_CE)?)
+                .map(|d| d.to_string())
+        }
+        Value::TimeMillis(millis) => to_time(i64::from(*millis) * 1_000).map(|t| t.to_string()),
+        Value::TimeMicros(micros) => to_time(*micros).map(|t| t.to_string()),
+        Value::TimestampMillis(millis) => DateTime::from_timestamp_millis(*millis)
+            .map(|t| t.to_rfc3339_opts(SecondsFormat::AutoSi, true)),
+        Value::TimestampMicros(micros) => DateTime::from_timestamp_micros(*micros)
+            .map(|t| t.to_rfc3339_opts(SecondsFormat::AutoSi, true)),
+        Value::LocalTimestampMillis(millis) => DateTime::from_timestamp_millis(*millis)
+            .map(|t| t.naive_utc().format("%Y-%m-%dT%H:%M:%S%.f").to_string()),
+        Value::LocalTimestampMicros(micros) => DateTime::from_timestamp_micros(*micros)
+            .map(|t| t.naive_utc().format("%Y-%m-%dT%H:%M:%S%.f").to_string()),
+        _ => None,
+    }
+}
+
+fn to_time(micros: i64) -> Option<NaiveTime> {
+    NaiveTime::from_num_seconds_from_midnight_opt(
+        u32::try_from(micros / 1_000_000).ok()?,
+        u32::try_from(micros % 1_000_000).ok()? * 1_000,
+    )
+}
+
+fn temporal_from_string(s: &str, schema: &Schema) -> Option<Value> {
+    match schema {
+        Schema::Date => {
+            let date = s.parse::<NaiveDate>().ok()?;
+            Some(Value::Date(
+                date.num_days_from_ce() - UNIX_EPOCH_DAYS_FROM_CE,
+            ))
+        }
+        Schema::TimeMillis => {
+            let micros = time_micros(s.parse::<NaiveTime>().ok()?);
+            Some(Value::TimeMillis(i32::try_from(micros / 1_000).ok()?))
+        }
+        Schema::TimeMicros => Some(Value::TimeMicros(time_micros(s.parse().ok()?))),
+        Schema::TimestampMillis => Some(Value::TimestampMillis(
+            DateTime::parse_from_rfc3339(s).ok()?.timestamp_millis(),
+        )),
+        Schema::TimestampMicros => Some(Value::TimestampMicros(
+            DateTime::parse_from_rfc3339(s).ok()?.timestamp_micros(),
+        )),
+        Schema::LocalTimestampMillis => Some(Value::LocalTimestampMillis(
+            s.parse::<NaiveDateTime>()
+                .ok()?
+                .and_utc()
+                .timestamp_millis(),
+        )),
+        Schema::LocalTimestampMicros => Some(Value::LocalTimestampMicros(
+            s.parse::<NaiveDateTime>()
+                .ok()?
+                .and_utc()
+                .timestamp_micros(),
+        )),
+        _ => None,
+    }
+}
+
+fn time_micros(time: NaiveTime) -> i64 {
+    i64::from(time.num_seconds_from_midnight()) * 1_000_000 + i64::from(time.nanosecond() / 1_000)
+}
+
+/// The bytes of an avro decimal are the unscaled value, as big endian two's complement.
+fn decimal_to_string(decimal: &Decimal, scale: usize) -> Result<String, SRCError> {
+    let bytes = match Vec::<u8>::try_from(decimal) {
+        Ok(v) => v,
+        Err(e) => {
+            return Err(SRCError::non_retryable_with_cause(
+                e,
+                "Could not get bytes from decimal",
+            ))
+        }
+    };
+    let negative = bytes.first().map(|b| b & 0x80 != 0).unwrap_or(false);
+    let mut magnitude = if negative { negate(bytes) } else { bytes };
+    let mut digits = Vec::new();
+    while magnitude.iter().any(|b| *b != 0) {
+        let mut remainder = 0u32;
+        for byte in magnitude.iter_mut() {
+            let current = (remainder << 8) | u32::from(*byte);
+            *byte = (current / 10) as u8;
+            remainder = current % 10;
+        }
+        digits.push(b'0' + remainder as u8);
+    }
+    while digits.len() <= scale {
+        digits.push(b'0');
+    }
+    digits.reverse();
+    let (integer, fraction) = digits.split_at(digits.len() - scale);
+    let mut result = String::new();
+    if negative {
+        result.push('-');
+    }
+    result.push_str(&String::from_utf8_lossy(integer));
+    if scale > 0 {
+        result.push('.');
+        result.push_str(&String::from_utf8_lossy(fraction));
+    }
+    Ok(result)
+}
+
+/// Parses a plain decimal string to the bytes of the unscaled value. Gives none when the string is
+/// not a plain decimal, or has more significant fraction digits than the scale.
+fn decimal_from_string(s: &str, scale: usize) -> Option<Vec<u8>> {
+    let (negative, unsigned) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s.strip_prefix('+').unwrap_or(s)),
+    };
+    let (integer, fraction) = unsigned.split_once('.').unwrap_or((unsigned, ""));
+    if integer.is_empty() && fraction.is_empty() {
+        return None;
+    }
+    let fraction = if fraction.len() > scale {
+        let (kept, dropped) = fraction.split_at(scale);
+        if dropped.chars().any(|c| c != '0') {
+            return None;
+        }
+        kept
+    } else {
+        fraction
+    };
+    let digits = format!(
+        "{}{}{}",
+        integer,
+        fraction,
+        "0".repeat(scale - fraction.len())
+    );
+    let mut magnitude: Vec<u8> = Vec::new();
+    for digit in digits.bytes() {
+        if !digit.is_ascii_digit() {
+            return None;
+        }
+        let mut carry = u32::from(digit - b'0');
+        for byte in magnitude.iter_mut().rev() {
+            let current = u32::from(*byte) * 10 + carry;
+            *byte = current as u8;
+            carry = current >> 8;
+        }
+        while carry > 0 {
+            magnitude.insert(0, carry as u8);
+            carry >>= 8;
+        }
+    }
+    if magnitude.first().map(|b| b & 0x80 != 0).unwrap_or(true) {
+        magnitude.insert(0, 0);
+    }
+    Some(if negative {
+        negate(magnitude)
+    } else {
+        magnitude
+    })
+}
+
+/// Two's complement negation of big endian bytes.
+fn negate(mut bytes: Vec<u8>) -> Vec<u8> {
+    let mut carry = true;
+    for byte in bytes.iter_mut().rev() {
+        let (inverted, overflow) = (!*byte).overflowing_add(u8::from(carry));
+        *byte = inverted;
+        carry = carry && overflow;
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use apache_avro::types::Value;
+    use apache_avro::{Decimal, Schema};
+
+    use crate::avro_logical::{
+        decimal_from_string, decimal_to_string, from_serde_value, to_serde_value, AvroCodecConfig,
+    };
+
+    fn get_schema() -> Schema {
+        Schema::parse_str(
+            r#"{"type":"record","name":"Payment","namespace":"nl.openweb.data","fields":[
+                {"name":"amount","type":{"type":"bytes","logicalType":"decimal","precision":10,"scale":2}},
+                {"name":"id","type":{"type":"string","logicalType":"uuid"}},
+                {"name":"day","type":{"type":"int","logicalType":"date"}},
+                {"name":"at","type":["null",{"type":"long","logicalType":"timestamp-millis"}]}
+            ]}"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn decimal_round_trip() {
+        for (s, bytes) in [
+            ("12.34", vec![4, 210]),
+            ("-12.34", vec![251, 46]),
+            ("1.28", vec![0, 128]),
+            ("-1.28", vec![255, 128]),
+            ("0.00", vec![0]),
+        ] {
+            assert_eq!(decimal_from_string(s, 2), Some(bytes.clone()));
+            assert_eq!(decimal_to_string(&Decimal::from(bytes), 2).unwrap(), s);
+        }
+        assert_eq!(decimal_from_string("7", 2), Some(vec![2, 188]));
+        assert_eq!(decimal_from_string("1.2340", 2), None);
+        assert_eq!(decimal_from_string("1e3", 2), None);
+    }
+
+    #[test]
+    fn logical_types_to_and_from_strings() {
+        let schema = get_schema();
+        let decoded = Value::Record(vec![
+            (
+                String::from("amount"),
+                Value::Decimal(Decimal::from(vec![4, 210])),
+            ),
+            (
+                String::from("id"),
+                Value::Uuid("67e55044-10b1-426f-9247-bb680e5fe0c8".parse().unwrap()),
+            ),
+            (String::from("day"), Value::Date(19_753)),
+            (
+                String::from("at"),
+                Value::Union(1, Box::new(Value::TimestampMillis(1_706_704_200_123))),
+            ),
+        ]);
+        let converted =
+            to_serde_value(decoded.clone(), &schema, &AvroCodecConfig::all_as_string()).unwrap();
+        assert_eq!(
+            converted,
+            Value::Record(vec![
+                (String::from("amount"), Value::String(String::from("12.34"))),
+                (
+                    String::from("id"),
+                    Value::String(String::from("67e55044-10b1-426f-9247-bb680e5fe0c8"))
+                ),
+                (
+                    String::from("day"),
+                    Value::String(String::from("2024-01-31"))
+                ),
+                (
+                    String::from("at"),
+                    Value::Union(
+                        1,
+                        Box::new(Value::String(String::from("2024-01-31T12:30:00.123Z")))
+                    )
+                ),
+            ])
+        );
+
+        let back = from_serde_value(converted, &schema, &AvroCodecConfig::all_as_string())
+            .unwrap()
+            .resolve(&schema)
+            .unwrap();
+        assert_eq!(back, decoded);
+    }
+
+    #[test]
+    fn default_config_converts_nothing() {
+        let schema = get_schema();
+        let value = Value::Record(vec![(String::from("day"), Value::Date(1))]);
+        assert_eq!(
+            to_serde_value(value.clone(), &schema, &AvroCodecConfig::default()).unwrap(),
+            value
+        );
+    }
+
+    #[test]
+    fn invalid_date_string() {
+        let schema = get_schema();
+        let value = Value::Record(vec![(
+            String::from("day"),
+            Value::String(String::from("yesterday")),
+        )]);
+        let error =
+            from_serde_value(value, &schema, &AvroCodecConfig::all_as_string()).unwrap_err();
+        assert_eq!(
+            error.error,
+            "Could not convert string yesterday to date, time or timestamp"
+        )
+    }
+}
@@ -0,0 +1,42 @@
+//! Generates Rust types for subjects in the schema registry, and writes them to a file. Protobuf
+//! subjects are written as proto files next to it. Subjects can have a version after a colon.
+//!
+//! `sr_codegen http://localhost:8081 src/schemas.rs orders-value customers-value:3`
+use std::path::Path;
+use std::process::ExitCode;
+
+use schema_registry_converter::blocking::schema_registry::SrSettings;
+use schema_registry_converter::codegen::Codegen;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.len() < 3 {
+        eprintln!("usage: sr_codegen <registry url> <output file> <subject[:version]>...");
+        return ExitCode::FAILURE;
+    }
+    let mut codegen = Codegen::new(SrSettings::new(args[0].clone()));
+    for subject in &args[2..] {
+        codegen = match subject.rsplit_once(':').map(|(s, v)| (s, v.parse::<u32>())) {
+            Some((s, Ok(version))) => codegen.with_subject_version(s, version),
+            _ => codegen.with_subject(subject),
+        };
+    }
+    let output = Path::new(&args[1]);
+    let proto_dir = output.parent().unwrap_or_else(|| Path::new("."));
+    let result = codegen
+        .write_to(output)
+        .and_then(|_| codegen.write_proto_files(proto_dir));
+    match result {
+        Ok(proto_files) => {
+            println!("generated {}", output.display());
+            for file in proto_files {
+                println!("wrote {}", file.display());
+            }
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,295 @@
+//! Decodes Confluent framed payloads, from files or stdin, and prints them as json lines with the
+//! schema id and type, for example to inspect poison messages. With `--to` avro and json payloads are
+//! re-encoded with a version of a subject instead, and written in the same format as the input.
+//!
+//! `sr-convert http://localhost:8081 --format hex dump.txt`
+//! `sr-convert http://localhost:8081 --to orders-value:3 < payload.bin > converted.bin`
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::process::ExitCode;
+
+use apache_avro::{to_avro_datum, Schema};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use schema_registry_converter::avro_logical::AvroCodecConfig;
+use schema_registry_converter::blocking::avro::AvroDecoder;
+use schema_registry_converter::blocking::json::JsonDecoder;
+use schema_registry_converter::blocking::proto_decoder::ProtoDecoder;
+use schema_registry_converter::blocking::schema_registry::{
+    get_schema_by_id, get_schema_by_subject_with_version, SrSettings,
+};
+use schema_registry_converter::error::SRCError;
+use schema_registry_converter::proto_decoder_common::proto_value_to_json;
+use schema_registry_converter::schema_registry_common::{
+    RegisteredSchema, SchemaType, SubjectNameStrategy, Version,
+};
+use serde_json::{json, Value};
+
+const USAGE: &str = "usage: sr-convert <registry url> [--format raw|hex|base64] [--to <subject>[:<version>]] [file]...";
+
+/// How the payloads are read and written. A raw file is a single payload, with hex and base64
+/// there is a payload on each line.
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Raw,
+    Hex,
+    Base64,
+}
+
+struct Converter {
+    sr_settings: SrSettings,
+    schema_types: HashMap<u32, SchemaType>,
+    avro_decoder: AvroDecoder,
+    json_decoder: JsonDecoder,
+    proto_decoder: ProtoDecoder,
+}
+
+impl Converter {
+    fn schema_type(&mut self, id: u32) -> Result<SchemaType, SRCError> {
+        if let Some(schema_type) = self.schema_types.get(&id) {
+            return Ok(schema_type.clone());
+        }
+        let schema_type = get_schema_by_id(id, &self.sr_settings)?.schema_type;
+        self.schema_types.insert(id, schema_type.clone());
+        Ok(schema_type)
+    }
+
+    fn to_json(&mut self, payload: &[u8]) -> Result<Value, SRCError> {
+        let id = schema_id(payload)?;
+        let schema_type = self.schema_type(id)?;
+        let value = match &schema_type {
+            SchemaType::Avro => self.avro_decoder.decode_as::<Value>(Some(payload))?,
+            SchemaType::Json => self.json_decoder.decode_as::<Value>(Some(payload))?,
+            SchemaType::Protobuf => self
+                .proto_decoder
+                .decode_with_context(Some(payload))?
+                .map(|r| proto_value_to_json(&r.value, &r.context.context)),
+            SchemaType::Other(t) => return Err(unsupported(t)),
+        };
+        Ok(json!({
+            "schema_id": id,
+            "schema_type": format!("{:?}", schema_type),
+            "value": value,
+        }))
+    }
+
+    fn convert(&mut self, payload: &[u8], target: &RegisteredSchema) -> Result<Vec<u8>, SRCError> {
+        let id = schema_id(payload)?;
+        let data = match (self.schema_type(id)?, &target.schema_type) {
+            (SchemaType::Avro, SchemaType::Avro) => {
+                let schema = match Schema::parse_str(&target.schema) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        return Err(SRCError::non_retryable_with_cause(
+                            e,
+                            "Could not parse the target schema, references are not supported",
+                        ))
+                    }
+                };
+                let result = self
+                    .avro_decoder
+                    .decode_with_reader_schema(Some(payload), &schema)?;
+                match to_avro_datum(&schema, result.value) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        return Err(SRCError::non_retryable_with_cause(
+                            e,
+                            "Could not encode with the target schema",
+                        ))
+                    }
+                }
+            }
+            (SchemaType::Json, SchemaType::Json) => payload[5..].to_vec(),
+            (source, target) => {
+                return Err(unsupported(&format!(
+                    "converting {:?} to {:?}",
+                    source, target
+                )))
+            }
+        };
+        let mut bytes = vec![0];
+        bytes.extend_from_slice(&target.id.to_be_bytes());
+        bytes.extend(data);
+        Ok(bytes)
+    }
+
+    fn handle(
+        &mut self,
+        payload: &[u8],
+        format: Format,
+        target: Option<&RegisteredSchema>,
+    ) -> Result<Vec<u8>, SRCError> {
+        match target {
+            Some(target) => Ok(write_payload(&self.convert(payload, target)?, format)),
+            None => {
+                let mut line = self.to_json(payload)?.to_string().into_bytes();
+                line.push(b'\n');
+                Ok(line)
+            }
+        }
+    }
+}
+
+fn unsupported(what: &str) -> SRCError {
+    SRCError::non_retryable_without_cause(&format!("sr-convert doesn't support {}", what))
+}
+
+fn schema_id(payload: &[u8]) -> Result<u32, SRCError> {
+    match payload {
+        [0, a, b, c, d, ..] => Ok(u32::from_be_bytes([*a, *b, *c, *d])),
+        _ => Err(SRCError::non_retryable_without_cause(
+            "Payload doesn't start with the magic byte and schema id",
+        )),
+    }
+}
+
+fn read_payloads(input: Vec<u8>, format: Format) -> Result<Vec<Vec<u8>>, String> {
+    if format == Format::Raw {
+        return Ok(vec![input]);
+    }
+    let text = String::from_utf8_lossy(&input);
+    text.lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .map(|line| match format {
+            Format::Base64 => STANDARD.decode(line).map_err(|e| e.to_string()),
+            _ => from_hex(line),
+        })
+        .collect()
+}
+
+fn from_hex(line: &str) -> Result<Vec<u8>, String> {
+    let line = line.trim_start_matches("0x");
+    if !line.is_ascii() || line.len() % 2 != 0 {
+        return Err(format!("invalid hex {}", line));
+    }
+    (0..line.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&line[i..i + 2], 16).map_err(|e| e.to_string()))
+        .collect()
+}
+
+fn write_payload(payload: &[u8], format: Format) -> Vec<u8> {
+    match format {
+        Format::Raw => payload.to_vec(),
+        Format::Hex => {
+            let mut line: String = payload.iter().map(|b| format!("{:02x}", b)).collect();
+            line.push('\n');
+            line.into_bytes()
+        }
+        Format::Base64 => format!("{}\n", STANDARD.encode(payload)).into_bytes(),
+    }
+}
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let url = match args.next() {
+        Some(u) if !u.starts_with("--") => u,
+        _ => {
+            eprintln!("{}", USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut format = Format::Raw;
+    let mut to = None;
+    let mut files = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => {
+                format = match args.next().as_deref() {
+                    Some("raw") => Format::Raw,
+                    Some("hex") => Format::Hex,
+                    Some("base64") => Format::Base64,
+                    _ => {
+                        eprintln!("{}", USAGE);
+                        return ExitCode::FAILURE;
+                    }
+                }
+            }
+            "--to" => to = args.next(),
+            _ => files.push(arg),
+        }
+    }
+
+    let sr_settings = SrSettings::new(url);
+    let target = match to {
+        None => None,
+        Some(subject) => {
+            let (subject, version) =
+                match subject.rsplit_once(':').map(|(s, v)| (s, v.parse::<u32>())) {
+                    Some((s, Ok(v))) => (String::from(s), Version::Number(v)),
+                    _ => (subject, Version::Latest),
+                };
+            let strategy = SubjectNameStrategy::RecordNameStrategy(subject);
+            match get_schema_by_subject_with_version(&sr_settings, &strategy, &version) {
+                Ok(v) => Some(v),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return ExitCode::FAILURE;
+                }
+            }
+        }
+    };
+    let mut converter = Converter {
+        avro_decoder: AvroDecoder::new(sr_settings.clone())
+            .with_codec_config(AvroCodecConfig::all_as_string()),
+        json_decoder: JsonDecoder::new(sr_settings.clone()),
+        proto_decoder: ProtoDecoder::new(sr_settings.clone()),
+        sr_settings,
+        schema_types: HashMap::new(),
+    };
+
+    let mut inputs = Vec::new();
+    if files.is_empty() {
+        let mut input = Vec::new();
+        match io::stdin().read_to_end(&mut input) {
+            Ok(_) => inputs.push((String::from("stdin"), input)),
+            Err(e) => {
+                eprintln!("could not read stdin: {}", e);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    for file in files {
+        match fs::read(&file) {
+            Ok(input) => inputs.push((file, input)),
+            Err(e) => {
+                eprintln!("could not read {}: {}", file, e);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    let mut failed = false;
+    let mut stdout = io::stdout().lock();
+    for (name, input) in inputs {
+        let payloads = match read_payloads(input, format) {
+            Ok(v) => v,
+            Err(e) => {
+                eprintln!("{}: {}", name, e);
+                failed = true;
+                continue;
+            }
+        };
+        for (i, payload) in payloads.iter().enumerate() {
+            match converter.handle(payload, format, target.as_ref()) {
+                Ok(output) => {
+                    if let Err(e) = stdout.write_all(&output) {
+                        eprintln!("could not write output: {}", e);
+                        return ExitCode::FAILURE;
+                    }
+                }
+                Err(e) => {
+                    eprintln!("{} payload {}: {}", name, i + 1, e);
+                    failed = true;
+                }
+            }
+        }
+    }
+    if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
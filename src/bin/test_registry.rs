@@ -0,0 +1,16 @@
+//! Runs the in memory test registry, on the address given as argument, or on localhost:8081.
+use schema_registry_converter::test_registry::serve;
+use tokio::net::TcpListener;
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> std::io::Result<()> {
+    let address = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| String::from("127.0.0.1:8081"));
+    let listener = TcpListener::bind(&address).await?;
+    println!(
+        "test registry listening on http://{}",
+        listener.local_addr()?
+    );
+    serve(listener).await
+}
@@ -22,27 +22,46 @@
 //!
 //! [avro-rs]: https://crates.io/crates/avro-rs
 
+use std::collections::HashSet;
 use std::io::Cursor;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, PoisonError};
 
 use apache_avro::types::Value;
 use apache_avro::{from_avro_datum, Schema};
 use dashmap::mapref::entry::Entry;
 use dashmap::DashMap;
+use serde::de::DeserializeOwned;
 use serde::ser::Serialize;
 use serde_json::Value as JsonValue;
 
 use crate::avro_common::{
-    get_name, item_to_bytes, replace_reference, values_to_bytes, AvroSchema, DecodeResult,
-    DecodeResultWithSchema,
+    apply_field_encryption, check_record_name, contains_definition, decode_datum,
+    decode_with_reader_schema, decompress, from_avro_value, get_fingerprint, get_name,
+    get_single_object_bytes, item_to_bytes, item_to_value, migrate_value,
+    missing_fingerprint_error, parse_local_schema, replace_reference, values_to_bytes,
+    values_to_bytes_with_schema, values_to_encrypted_record, write_bytes, AvroCompression,
+    AvroFraming, AvroSchema, DecodeResult, DecodeResultWithSchema, FingerprintLookups, LocalSchema,
+    LocalSchemaProvider, ReaderSchema, UnknownFieldPolicy,
 };
+#[cfg(feature = "derive")]
+use crate::avro_derive::SrAvroSerialize;
+use crate::avro_logical::{to_serde_value, AvroCodecConfig};
 use crate::blocking::schema_registry::{
-    get_referenced_schema, get_schema_by_id_and_type, get_schema_by_subject, SrSettings,
+    get_all_schemas, get_allowed_schema, get_migration, get_referenced_schema,
+    get_schema_by_subject, lookup_schema, SrSettings,
 };
 use crate::error::SRCError;
+use crate::field_encryption::{Direction, FieldEncryptor};
+use crate::migration::{Migration, MigrationConfig};
 use crate::schema_registry_common::{
-    get_bytes_result, BytesResult, RegisteredReference, RegisteredSchema, SchemaType,
-    SubjectNameStrategy,
+    check_policy, check_remaining_bytes, to_wire_format_error, BytesResult, DecodeMetadata,
+    DecodeResultWithMetadata, DecodedRecord, DecoderOptions, DeserializationErrorPolicy,
+    IdStrategy, RegisteredReference, RegisteredSchema, SchemaPolicy, SchemaType, SchemaUsageKind,
+    SlowPathWarnings, Strictness, SubjectNameStrategy,
+};
+use crate::telemetry::{
+    decode_start, record_cache_lookup, record_decode_error, warn_registry_fetch, warn_slow_decode,
+    CachedSchemas,
 };
 
 /// A decoder used to transform bytes to a Value object
@@ -57,6 +76,8 @@ use crate::schema_registry_common::{
 /// both. But only using the SubjectNameStrategy::TopicNameStrategy it has to be made explicit
 /// whether it's actual used as key or value.
 ///
+/// Cloning the decoder is cheap, and the clones share the cache.
+///
 /// ```
 
 /// use apache_avro::types::Value;
@@ -76,10 +97,24 @@ use crate::schema_registry_common::{
 ///
 /// assert_eq!(heartbeat, Value::Record(vec![("beat".to_string(), Value::Long(3))]))
 /// ```
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct AvroDecoder {
     sr_settings: SrSettings,
-    cache: DashMap<u32, Result<Arc<AvroSchema>, SRCError>>,
+    cache: Arc<DashMap<u32, Result<Arc<AvroSchema>, SRCError>>>,
+    fingerprints: Arc<DashMap<u64, Arc<AvroSchema>>>,
+    fingerprint_lookups: Arc<FingerprintLookups<Mutex<()>>>,
+    codec_config: AvroCodecConfig,
+    compression: AvroCompression,
+    id_strategy: IdStrategy,
+    strictness: Strictness,
+    error_policy: DeserializationErrorPolicy,
+    field_encryptor: Option<Arc<dyn FieldEncryptor>>,
+    migration: Option<MigrationConfig>,
+    migrations: Arc<DashMap<u32, Arc<Migration>>>,
+    reader_schema: Option<ReaderSchema>,
+    slow_path: SlowPathWarnings,
+    policy: Option<Arc<dyn SchemaPolicy>>,
+    cached_schemas: Arc<CachedSchemas>,
 }
 
 impl AvroDecoder {
@@ -91,9 +126,98 @@ impl AvroDecoder {
     pub fn new(sr_settings: SrSettings) -> AvroDecoder {
         AvroDecoder {
             sr_settings,
-            cache: DashMap::new(),
+            cache: Arc::new(DashMap::new()),
+            fingerprints: Arc::new(DashMap::new()),
+            fingerprint_lookups: Arc::new(FingerprintLookups::new(Mutex::new(()))),
+            codec_config: AvroCodecConfig::default(),
+            compression: AvroCompression::default(),
+            id_strategy: IdStrategy::default(),
+            strictness: Strictness::Lenient,
+            error_policy: DeserializationErrorPolicy::default(),
+            field_encryptor: None,
+            migration: None,
+            migrations: Arc::new(DashMap::new()),
+            reader_schema: None,
+            slow_path: SlowPathWarnings::default(),
+            policy: None,
+            cached_schemas: Arc::new(CachedSchemas::new("avro")),
         }
     }
+    /// Sets how logical types are converted by decode_as.
+    pub fn with_codec_config(mut self, codec_config: AvroCodecConfig) -> AvroDecoder {
+        self.codec_config = codec_config;
+        self
+    }
+    /// Sets the compression of the avro bytes, which needs to be the same as used by the encoder.
+    pub fn with_compression(mut self, compression: AvroCompression) -> AvroDecoder {
+        self.compression = compression;
+        self
+    }
+    /// Sets how the schema id is read, which needs to be the same as used by the encoder. With
+    /// the id in a header, use decode_with_headers.
+    pub fn with_id_strategy(mut self, id_strategy: IdStrategy) -> AvroDecoder {
+        self.id_strategy = id_strategy;
+        self
+    }
+    /// Sets how payloads that don't follow the wire format are handled. Payloads without the magic
+    /// byte and id always give an error with the [WireFormatError](crate::error::WireFormatError).
+    /// When strict, bytes left after decoding the data give an error with it too.
+    pub fn with_strictness(mut self, strictness: Strictness) -> AvroDecoder {
+        self.strictness = strictness;
+        self
+    }
+    /// Sets what decode_or_skip does with payloads that can't be decoded.
+    pub fn with_error_policy(mut self, error_policy: DeserializationErrorPolicy) -> AvroDecoder {
+        self.error_policy = error_policy;
+        self
+    }
+    /// Sets the encryptor used to decrypt the fields tagged by an ENCRYPT rule of the schema.
+    pub fn with_field_encryptor(mut self, field_encryptor: Arc<dyn FieldEncryptor>) -> AvroDecoder {
+        self.field_encryptor = Some(field_encryptor);
+        self
+    }
+    /// Reads the values as the latest version of the subject with the metadata of the migration,
+    /// applying the migration rules of the versions in between when a value was written with
+    /// another version. Used by decode, decode_with_schema, decode_with_metadata and decode_as.
+    pub fn with_migration(mut self, migration: MigrationConfig) -> AvroDecoder {
+        self.migration = Some(migration);
+        self
+    }
+    /// Reads the values as the reader schema, filling the fields missing in the schema the value was
+    /// written with, with the default of the reader schema. The policy sets what happens with fields
+    /// only in the writer schema. When a migration is also set, the migrated values are read as the
+    /// reader schema. The schema of the results is the reader schema, with the id and subject of the
+    /// writer schema. Used by decode, decode_with_schema, decode_with_metadata and decode_as.
+    pub fn with_reader_schema(
+        mut self,
+        reader_schema: Schema,
+        unknown_fields: UnknownFieldPolicy,
+    ) -> AvroDecoder {
+        self.reader_schema = Some(ReaderSchema::new(reader_schema, unknown_fields));
+        self
+    }
+    /// Sets which slow paths of decode are logged as a warning, like a decode taking longer than a
+    /// threshold, or a decode needing to fetch the schema.
+    pub fn with_slow_path_warnings(mut self, slow_path: SlowPathWarnings) -> AvroDecoder {
+        self.slow_path = slow_path;
+        self
+    }
+    /// Sets the policy restricting which schema's are fetched and decoded. Payloads with a schema
+    /// id that's not allowed give an error with a `PolicyViolation`, which is not cached, so a
+    /// change of the policy is applied directly.
+    pub fn with_policy(mut self, policy: Arc<dyn SchemaPolicy>) -> AvroDecoder {
+        self.policy = Some(policy);
+        self
+    }
+    /// Sets the options shared by all the decoders, replacing the id strategy, strictness, slow
+    /// path warnings and policy set before.
+    pub fn with_options(mut self, options: DecoderOptions) -> AvroDecoder {
+        self.id_strategy = options.id_strategy;
+        self.strictness = options.strictness;
+        self.slow_path = options.slow_path;
+        self.policy = options.policy;
+        self
+    }
     /// Remove al the errors from the cache, you might need to/want to run this when a recoverable
     /// error is met. Errors are also cashed to prevent trying to get schema's that either don't
     /// exist or can't be parsed.
@@ -136,6 +260,32 @@ impl AvroDecoder {
     pub fn remove_errors_from_cache(&self) {
         self.cache.retain(|_, v| v.is_ok());
     }
+    /// Removes the migrations to the latest version of the subject of the migration from the cache,
+    /// so a new latest version with the metadata is picked up on the next decode. Long-lived
+    /// consumers can call this when they are signaled a new version was registered, like with
+    /// invalidate_subject of the encoder. The schema's for the id's don't change, so they are kept.
+    pub fn invalidate_subject(&self, subject: &str) {
+        if matches!(&self.migration, Some(config) if config.subject == subject) {
+            self.migrations.clear();
+        }
+    }
+    /// Decodes the bytes, applying the error policy when they can't be decoded. None is returned
+    /// when the bytes are skipped.
+    pub fn decode_or_skip(&self, bytes: Option<&[u8]>) -> Result<Option<DecodeResult>, SRCError> {
+        let result = self.decode(bytes);
+        self.error_policy.apply(bytes, result)
+    }
+    /// Decodes both the key and the payload of a record, the same way as decode.
+    pub fn decode_message(
+        &self,
+        key: Option<&[u8]>,
+        payload: Option<&[u8]>,
+    ) -> Result<DecodedRecord<DecodeResult>, SRCError> {
+        Ok(DecodedRecord {
+            key: self.decode(key)?,
+            value: self.decode(payload)?,
+        })
+    }
     /// Decodes bytes into a value.
     /// The choice to use Option<&[u8]> as type us made so it plays nice with the BorrowedMessage
     /// struct from rdkafka, for example if we have m: &'a BorrowedMessage and decoder: &'a
@@ -157,36 +307,66 @@ impl AvroDecoder {
     /// }
     /// ```
     pub fn decode(&self, bytes: Option<&[u8]>) -> Result<DecodeResult, SRCError> {
-        match get_bytes_result(bytes) {
+        self.decode_with_headers(bytes, &[])
+    }
+    /// Decodes bytes, using the headers of the record to get the schema id when the id strategy
+    /// has the id in a header.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "decode", level = "debug", skip_all, err)
+    )]
+    pub fn decode_with_headers(
+        &self,
+        bytes: Option<&[u8]>,
+        headers: &[(&str, &[u8])],
+    ) -> Result<DecodeResult, SRCError> {
+        let result = match self.id_strategy.get_bytes_result(bytes, headers) {
             BytesResult::Null => Ok(DecodeResult {
                 name: None,
                 value: Value::Null,
             }),
-            BytesResult::Valid(id, bytes) => self.deserialize(id, &bytes),
-            BytesResult::Invalid(bytes) => Err(SRCError::non_retryable_without_cause(&format!(
-                "Invalid bytes {:?}",
-                bytes
-            ))),
+            BytesResult::Valid(id, bytes) => {
+                let start = decode_start(&self.slow_path);
+                let result = self.deserialize(id, &bytes);
+                warn_slow_decode(&self.slow_path, start, id, || {
+                    let schema = self.cache.get(&id)?;
+                    schema.value().as_ref().ok()?.subject.clone()
+                });
+                result
+            }
+            BytesResult::Invalid(bytes) => Err(SRCError::from(to_wire_format_error(&bytes))),
+        };
+        if result.is_err() {
+            record_decode_error("avro");
         }
+        result
+    }
+    /// Decodes bytes using the avro single object encoding, instead of the Confluent wire format.
+    /// The schema is found by the fingerprint in the bytes. When the fingerprint is not known yet,
+    /// all the schema's are fetched from the schema registry to find it.
+    pub fn decode_single_object(&self, bytes: Option<&[u8]>) -> Result<DecodeResult, SRCError> {
+        let bytes = match bytes {
+            None => {
+                return Ok(DecodeResult {
+                    name: None,
+                    value: Value::Null,
+                })
+            }
+            Some(v) => v,
+        };
+        let (fingerprint, data) = get_single_object_bytes(bytes)?;
+        let schema = self.schema_by_fingerprint(fingerprint)?;
+        let bytes = decompress(data, self.compression)?;
+        decode_datum(&schema, &bytes, self.field_encryptor.as_deref())
     }
     /// The actual deserialization trying to get the id from the bytes to retrieve the schema, and
     /// using a reader transforms the bytes to a value.
     fn deserialize(&self, id: u32, bytes: &[u8]) -> Result<DecodeResult, SRCError> {
-        let schema = self.schema(id);
-        let mut reader = Cursor::new(bytes);
-        match schema {
-            Ok(s) => match from_avro_datum(&s.parsed, &mut reader, None) {
-                Ok(v) => Ok(DecodeResult {
-                    name: get_name(&s.parsed),
-                    value: v,
-                }),
-                Err(e) => Err(SRCError::non_retryable_with_cause(
-                    e,
-                    "Could not transform bytes using schema",
-                )),
-            },
-            Err(e) => Err(e),
-        }
+        let result = self.deserialize_with_schema(id, bytes)?;
+        Ok(DecodeResult {
+            name: result.name,
+            value: result.value,
+        })
     }
     /// Decodes bytes into a value.
     /// The choice to use Option<&[u8]> as type us made so it plays nice with the BorrowedMessage
@@ -197,16 +377,74 @@ impl AvroDecoder {
         &self,
         bytes: Option<&[u8]>,
     ) -> Result<Option<DecodeResultWithSchema>, SRCError> {
-        match get_bytes_result(bytes) {
+        match self.id_strategy.get_bytes_result(bytes, &[]) {
             BytesResult::Null => Ok(None),
             BytesResult::Valid(id, bytes) => match self.deserialize_with_schema(id, &bytes) {
                 Ok(v) => Ok(Some(v)),
                 Err(e) => Err(e),
             },
-            BytesResult::Invalid(bytes) => Err(SRCError::non_retryable_without_cause(&format!(
-                "Invalid bytes {:?}",
-                bytes
-            ))),
+            BytesResult::Invalid(bytes) => Err(SRCError::from(to_wire_format_error(&bytes))),
+        }
+    }
+    /// Decodes bytes into a value, together with the id of the schema and the full name of the
+    /// record, so messages can be routed by type without another lookup.
+    pub fn decode_with_metadata(
+        &self,
+        bytes: Option<&[u8]>,
+    ) -> Result<Option<DecodeResultWithMetadata<Value>>, SRCError> {
+        Ok(self
+            .decode_with_schema(bytes)?
+            .map(|r| DecodeResultWithMetadata {
+                metadata: DecodeMetadata {
+                    schema_id: r.schema.id,
+                    full_name: r.name.map(|n| n.fullname(None)),
+                },
+                value: r.value,
+            }))
+    }
+    /// Decodes bytes into a value, resolving the schema the bytes were written with, from the
+    /// schema registry, to the supplied reader schema. Fields not in the reader schema are skipped,
+    /// and fields only in the reader schema get their default value, so the value can be projected
+    /// onto a struct that only knows the reader schema. An error is returned when the schema's are
+    /// not compatible.
+    pub fn decode_with_reader_schema(
+        &self,
+        bytes: Option<&[u8]>,
+        reader_schema: &Schema,
+    ) -> Result<DecodeResult, SRCError> {
+        match self.id_strategy.get_bytes_result(bytes, &[]) {
+            BytesResult::Null => Ok(DecodeResult {
+                name: None,
+                value: Value::Null,
+            }),
+            BytesResult::Valid(id, bytes) => {
+                let bytes = decompress(&bytes, self.compression)?;
+                decode_with_reader_schema(
+                    &*self.schema(id)?,
+                    &bytes,
+                    reader_schema,
+                    self.field_encryptor.as_deref(),
+                )
+            }
+            BytesResult::Invalid(bytes) => Err(SRCError::from(to_wire_format_error(&bytes))),
+        }
+    }
+    /// Decodes bytes directly into any struct implementing Deserialize, like the structs generated
+    /// from avro schema's. Fixed values are deserialized as bytes, and by default dates and times as
+    /// the number from the logical type. Set an [AvroCodecConfig] with `with_codec_config` to
+    /// deserialize decimals, uuid's, dates and times from strings, into rust_decimal, uuid and
+    /// chrono types.
+    pub fn decode_as<T: DeserializeOwned>(
+        &self,
+        bytes: Option<&[u8]>,
+    ) -> Result<Option<T>, SRCError> {
+        match self.decode_with_schema(bytes)? {
+            None => Ok(None),
+            Some(result) => {
+                let value =
+                    to_serde_value(result.value, &result.schema.parsed, &self.codec_config)?;
+                Ok(Some(from_avro_value(&value)?))
+            }
         }
     }
     /// The actual deserialization trying to get the id from the bytes to retrieve the schema, and
@@ -216,33 +454,136 @@ impl AvroDecoder {
         id: u32,
         bytes: &[u8],
     ) -> Result<DecodeResultWithSchema, SRCError> {
-        let optional_schema = self.schema(id);
+        let bytes: &[u8] = &decompress(bytes, self.compression)?;
+        let schema = self.schema(id)?;
         let mut reader = Cursor::new(bytes);
-        match optional_schema {
-            Ok(schema) => match from_avro_datum(&schema.parsed, &mut reader, None) {
-                Ok(value) => Ok(DecodeResultWithSchema {
-                    name: get_name(&schema.parsed),
-                    value,
-                    schema,
-                }),
-                Err(e) => Err(SRCError::non_retryable_with_cause(
+        let value = match from_avro_datum(&schema.parsed, &mut reader, None) {
+            Ok(value) => {
+                check_remaining_bytes(bytes.len() - reader.position() as usize, self.strictness)?;
+                self.decrypt(&schema, value)?
+            }
+            Err(e) => {
+                return Err(SRCError::non_retryable_with_cause(
                     e,
                     "Could not transform bytes using schema",
-                )),
-            },
-            Err(e) => Err(e),
+                ))
+            }
+        };
+        let writer_schema = schema.clone();
+        let (schema, value) = self.migrate(id, schema, value)?;
+        let (schema, value) = match &self.reader_schema {
+            Some(reader_schema) => reader_schema.read(&schema, value)?,
+            None => (schema, value),
+        };
+        Ok(DecodeResultWithSchema {
+            name: get_name(&schema.parsed),
+            value,
+            schema,
+            writer_schema,
+        })
+    }
+
+    /// Gives the schema to read the value as, with the value migrated to it when a migration is
+    /// set.
+    fn migrate(
+        &self,
+        id: u32,
+        schema: Arc<AvroSchema>,
+        value: Value,
+    ) -> Result<(Arc<AvroSchema>, Value), SRCError> {
+        let config = match &self.migration {
+            Some(v) => v,
+            None => return Ok((schema, value)),
+        };
+        let cached = self.migrations.get(&id).map(|m| m.value().clone());
+        let migration = match cached {
+            Some(v) => v,
+            None => {
+                let migration = Arc::new(get_migration(&self.sr_settings, config, id)?);
+                self.migrations.insert(id, migration.clone());
+                migration
+            }
+        };
+        if migration.reader_id == id {
+            return Ok((schema, value));
+        }
+        let reader_schema = self.schema(migration.reader_id)?;
+        let value = migrate_value(value, &reader_schema, &migration.rules, &*config.executor)?;
+        Ok((reader_schema, value))
+    }
+
+    fn decrypt(&self, schema: &AvroSchema, value: Value) -> Result<Value, SRCError> {
+        apply_field_encryption(
+            schema,
+            self.field_encryptor.as_deref(),
+            value,
+            Direction::Read,
+        )
+    }
+
+    fn schema_by_fingerprint(&self, fingerprint: u64) -> Result<Arc<AvroSchema>, SRCError> {
+        if let Some(schema) = self.fingerprints.get(&fingerprint) {
+            return Ok(schema.value().clone());
+        }
+        if self.fingerprint_lookups.is_missing(fingerprint) {
+            return Err(missing_fingerprint_error(fingerprint));
+        }
+        // Only one lookup of all the schema's at a time, the others wait for it and use its result.
+        let _lookup = self
+            .fingerprint_lookups
+            .lock
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some(schema) = self.fingerprints.get(&fingerprint) {
+            return Ok(schema.value().clone());
+        }
+        if self.fingerprint_lookups.is_missing(fingerprint) {
+            return Err(missing_fingerprint_error(fingerprint));
+        }
+        let mut ids = HashSet::new();
+        for registered_schema in get_all_schemas(&self.sr_settings)? {
+            if registered_schema.schema_type != SchemaType::Avro
+                || !ids.insert(registered_schema.id)
+            {
+                continue;
+            }
+            if let Ok(schema) = to_avro_schema(&self.sr_settings, registered_schema) {
+                self.fingerprints
+                    .insert(get_fingerprint(&schema.parsed), schema);
+            }
+        }
+        match self.fingerprints.get(&fingerprint) {
+            Some(schema) => Ok(schema.value().clone()),
+            None => {
+                self.fingerprint_lookups.set_missing(fingerprint);
+                Err(missing_fingerprint_error(fingerprint))
+            }
         }
     }
 
     fn schema(&self, id: u32) -> Result<Arc<AvroSchema>, SRCError> {
+        check_policy(self.policy.as_deref(), id)?;
         let sr_settings = &self.sr_settings;
         match self.cache.entry(id) {
-            Entry::Occupied(e) => e.get().clone(),
+            Entry::Occupied(e) => {
+                record_cache_lookup(id, true);
+                e.get().clone()
+            }
             Entry::Vacant(e) => {
-                let v = match get_schema_by_id_and_type(id, sr_settings, SchemaType::Avro) {
+                record_cache_lookup(id, false);
+                warn_registry_fetch(&self.slow_path, id);
+                let policy = self.policy.as_deref();
+                let v = match get_allowed_schema(id, sr_settings, SchemaType::Avro, policy) {
                     Ok(registered_schema) => to_avro_schema(sr_settings, registered_schema),
                     Err(e) => Err(e.into_cache()),
                 };
+                if v.is_ok() {
+                    self.cached_schemas.added();
+                    sr_settings.record_usage(SchemaUsageKind::Decoded, id, None);
+                }
+                if matches!(&v, Err(err) if err.is_throttled()) {
+                    return v;
+                }
                 e.insert(v).value().clone()
             }
         }
@@ -298,6 +639,12 @@ impl AvroDecoder {
 pub struct AvroEncoder {
     sr_settings: SrSettings,
     cache: DashMap<String, Result<Arc<AvroSchema>, SRCError>>,
+    codec_config: AvroCodecConfig,
+    framing: AvroFraming,
+    id_strategy: IdStrategy,
+    compression: AvroCompression,
+    local_schemas: LocalSchemaProvider,
+    field_encryptor: Option<Arc<dyn FieldEncryptor>>,
 }
 
 impl AvroEncoder {
@@ -338,8 +685,50 @@ impl AvroEncoder {
         AvroEncoder {
             sr_settings,
             cache: DashMap::new(),
+            codec_config: AvroCodecConfig::default(),
+            framing: AvroFraming::default(),
+            id_strategy: IdStrategy::default(),
+            compression: AvroCompression::default(),
+            local_schemas: LocalSchemaProvider::default(),
+            field_encryptor: None,
         }
     }
+    /// Sets how logical types are converted by encode_struct.
+    pub fn with_codec_config(mut self, codec_config: AvroCodecConfig) -> AvroEncoder {
+        self.codec_config = codec_config;
+        self
+    }
+    /// Sets the framing of the encoded bytes, by default the Confluent wire format is used. With
+    /// the single object encoding the bytes can be decoded without the schema id, for example by
+    /// systems not using the schema registry.
+    pub fn with_framing(mut self, framing: AvroFraming) -> AvroEncoder {
+        self.framing = framing;
+        self
+    }
+    /// Sets how the schema id is added with the Confluent framing, by default as 4 bytes. With the
+    /// id in a header, only the avro bytes are returned, and the id can be found with
+    /// get_schema_by_subject from the schema registry module.
+    pub fn with_id_strategy(mut self, id_strategy: IdStrategy) -> AvroEncoder {
+        self.id_strategy = id_strategy;
+        self
+    }
+    /// Sets the compression of the avro bytes, for large values. Consumers need to use the same
+    /// compression to decode the bytes.
+    pub fn with_compression(mut self, compression: AvroCompression) -> AvroEncoder {
+        self.compression = compression;
+        self
+    }
+    /// Sets the schema's available locally, which are used instead of the schema from the schema
+    /// registry when the subject matches.
+    pub fn with_local_schemas(mut self, local_schemas: LocalSchemaProvider) -> AvroEncoder {
+        self.local_schemas = local_schemas;
+        self
+    }
+    /// Sets the encryptor used to encrypt the fields tagged by an ENCRYPT rule of the schema.
+    pub fn with_field_encryptor(mut self, field_encryptor: Arc<dyn FieldEncryptor>) -> AvroEncoder {
+        self.field_encryptor = Some(field_encryptor);
+        self
+    }
     /// Remove al the errors from the cache, you might need to/want to run this when a recoverable
     /// error is met. Errors are also cashed to prevent trying to get schema's that either don't
     /// exist or can't be parsed.
@@ -382,6 +771,12 @@ impl AvroEncoder {
     pub fn remove_errors_from_cache(&self) {
         self.cache.retain(|_, v| v.is_ok());
     }
+    /// Removes the schema of the subject from the cache, so the latest version is fetched again on
+    /// the next encode. Long-lived producers can call this when they are signaled a new version was
+    /// registered, to pick it up without a restart.
+    pub fn invalidate_subject(&self, subject: &str) {
+        self.cache.remove(subject);
+    }
     /// Encodes a vector of values to bytes. The correct values of the 'keys' depend on the schema
     /// being fetched at runtime, or the one supplied with the SubjectNameStrategy.
     ///
@@ -390,7 +785,6 @@ impl AvroEncoder {
     /// ```
     /// use apache_avro::types::Value;
     /// use schema_registry_converter::blocking::avro::AvroEncoder;
-    /// use schema_registry_converter::schema_registry_common::SubjectNameStrategy;
     /// use schema_registry_converter::blocking::schema_registry::SrSettings;
     ///
     /// let mut server = mockito::Server::new();
@@ -402,26 +796,34 @@ impl AvroEncoder {
     ///
     /// let sr_settings = SrSettings::new(server.url());
     /// let encoder = AvroEncoder::new(sr_settings);
-    /// let strategy = SubjectNameStrategy::TopicRecordNameStrategy(String::from("heartbeat"), String::from("nl.openweb.data.Heartbeat"));
-    /// let bytes = encoder.encode(vec![("beat", Value::Long(3))], &strategy);
+    /// let bytes = encoder.encode(vec![("beat", Value::Long(3))], ("heartbeat", "nl.openweb.data.Heartbeat"));
     ///
     /// assert_eq!(bytes, Ok(vec![0,0,0,0,3,6]))
     /// ```
     pub fn encode(
         &self,
         values: Vec<(&str, Value)>,
-        subject_name_strategy: &SubjectNameStrategy,
+        subject_name_strategy: impl Into<SubjectNameStrategy>,
     ) -> Result<Vec<u8>, SRCError> {
+        let subject_name_strategy = subject_name_strategy.into();
         let key = subject_name_strategy.get_subject()?;
-        match self.get_schema_and_id(key, subject_name_strategy) {
-            Ok(avro_schema) => values_to_bytes(&avro_schema, values),
+        match self.get_schema_and_id(key, &subject_name_strategy) {
+            Ok(avro_schema) => values_to_bytes(
+                &avro_schema,
+                values,
+                self.field_encryptor.as_deref(),
+                self.framing,
+                &self.id_strategy,
+                self.compression,
+            ),
             Err(e) => Err(e),
         }
     }
 
     /// Encodes a struct or a primitive value to bytes. The schema used for the encoding will be
     /// retrieved from the schema registry, or it will use the one supplied with the
-    /// SubjectNameStrategy.
+    /// SubjectNameStrategy. The fields of the struct are matched to the schema by name, and fields
+    /// missing from the struct get the default from the schema.
     ///
     /// The function get_supplied_schema might be used to easily provide the schema in the correct
     /// form.
@@ -467,15 +869,140 @@ impl AvroEncoder {
     pub fn encode_struct(
         &self,
         item: impl Serialize,
-        subject_name_strategy: &SubjectNameStrategy,
+        subject_name_strategy: impl Into<SubjectNameStrategy>,
     ) -> Result<Vec<u8>, SRCError> {
+        let subject_name_strategy = subject_name_strategy.into();
         let key = subject_name_strategy.get_subject()?;
-        match self.get_schema_and_id(key, subject_name_strategy) {
-            Ok(avro_schema) => item_to_bytes(&avro_schema, item),
+        match self.get_schema_and_id(key, &subject_name_strategy) {
+            Ok(avro_schema) => item_to_bytes(
+                &avro_schema,
+                item,
+                &self.codec_config,
+                self.field_encryptor.as_deref(),
+                self.framing,
+                &self.id_strategy,
+                self.compression,
+            ),
             Err(e) => Err(e),
         }
     }
 
+    /// Encodes multiple structs for the same subject, getting the schema only once, which saves the
+    /// cache lookups when producing a batch of records. Stops at, and returns, the first error.
+    pub fn encode_many<T: Serialize>(
+        &self,
+        items: &[T],
+        subject_name_strategy: impl Into<SubjectNameStrategy>,
+    ) -> Result<Vec<Vec<u8>>, SRCError> {
+        let subject_name_strategy = subject_name_strategy.into();
+        let key = subject_name_strategy.get_subject()?;
+        let avro_schema = self.get_schema_and_id(key, &subject_name_strategy)?;
+        items
+            .iter()
+            .map(|item| {
+                item_to_bytes(
+                    &avro_schema,
+                    item,
+                    &self.codec_config,
+                    self.field_encryptor.as_deref(),
+                    self.framing,
+                    &self.id_strategy,
+                    self.compression,
+                )
+            })
+            .collect()
+    }
+
+    /// Like `encode`, but writing the bytes to the buffer, which is cleared first. This way the
+    /// buffer, and its allocation, can be reused for multiple records.
+    pub fn encode_into(
+        &self,
+        values: Vec<(&str, Value)>,
+        subject_name_strategy: impl Into<SubjectNameStrategy>,
+        buf: &mut Vec<u8>,
+    ) -> Result<(), SRCError> {
+        let subject_name_strategy = subject_name_strategy.into();
+        let key = subject_name_strategy.get_subject()?;
+        let schema = self.get_schema_and_id(key, &subject_name_strategy)?;
+        let record = values_to_encrypted_record(&schema, values, self.field_encryptor.as_deref())?;
+        write_bytes(
+            &schema.parsed,
+            schema.id,
+            record,
+            self.framing,
+            &self.id_strategy,
+            self.compression,
+            buf,
+        )
+    }
+
+    /// Like `encode_struct`, but writing the bytes to the buffer, which is cleared first. This way
+    /// the buffer, and its allocation, can be reused for multiple records.
+    pub fn encode_struct_into(
+        &self,
+        item: impl Serialize,
+        subject_name_strategy: impl Into<SubjectNameStrategy>,
+        buf: &mut Vec<u8>,
+    ) -> Result<(), SRCError> {
+        let subject_name_strategy = subject_name_strategy.into();
+        let key = subject_name_strategy.get_subject()?;
+        let schema = self.get_schema_and_id(key, &subject_name_strategy)?;
+        let value = item_to_value(
+            &schema,
+            item,
+            &self.codec_config,
+            self.field_encryptor.as_deref(),
+        )?;
+        write_bytes(
+            &schema.parsed,
+            schema.id,
+            value,
+            self.framing,
+            &self.id_strategy,
+            self.compression,
+            buf,
+        )
+    }
+
+    /// Encodes a struct deriving `SrAvroSerialize`, using the schema from the .avsc file and the
+    /// subject set with the attributes of the struct.
+    #[cfg(feature = "derive")]
+    pub fn encode_derived<T: SrAvroSerialize>(&self, item: &T) -> Result<Vec<u8>, SRCError> {
+        let values = item.to_avro_values()?;
+        self.encode(values, &T::subject_name_strategy()?)
+    }
+
+    /// Encodes a vector of values to bytes using a schema known locally, together with the id it's
+    /// registered with, for example from an .avsc file embedded at build time. The schema registry
+    /// is not called, so it's up to the caller to make sure the id is correct.
+    /// ```
+    /// use apache_avro::types::Value;
+    /// use apache_avro::Schema;
+    /// use schema_registry_converter::blocking::avro::AvroEncoder;
+    /// use schema_registry_converter::blocking::schema_registry::SrSettings;
+    ///
+    /// let schema = Schema::parse_str(r#"{"type":"record","name":"Heartbeat","namespace":"nl.openweb.data","fields":[{"name":"beat","type":"long"}]}"#).unwrap();
+    /// let encoder = AvroEncoder::new(SrSettings::new(String::from("http://127.0.0.1:1234")));
+    /// let bytes = encoder.encode_with_local_schema(vec![("beat", Value::Long(3))], &schema, 3);
+    ///
+    /// assert_eq!(bytes, Ok(vec![0, 0, 0, 0, 3, 6]));
+    /// ```
+    pub fn encode_with_local_schema(
+        &self,
+        values: Vec<(&str, Value)>,
+        schema: &Schema,
+        id: u32,
+    ) -> Result<Vec<u8>, SRCError> {
+        values_to_bytes_with_schema(
+            schema,
+            id,
+            values,
+            self.framing,
+            &self.id_strategy,
+            self.compression,
+        )
+    }
+
     fn get_schema_and_id(
         &self,
         key: String,
@@ -485,10 +1012,23 @@ impl AvroEncoder {
         match self.cache.entry(key) {
             Entry::Occupied(e) => e.get().clone(),
             Entry::Vacant(e) => {
-                let v = match get_schema_by_subject(sr_settings, subject_name_strategy) {
-                    Ok(registered_schema) => to_avro_schema(sr_settings, registered_schema),
-                    Err(e) => Err(e.into_cache()),
+                let v = match self.local_schemas.get(e.key()) {
+                    Some(LocalSchema::WithId(avro_schema)) => Ok(avro_schema),
+                    Some(LocalSchema::WithoutId(raw)) => {
+                        lookup_local_schema(sr_settings, e.key(), raw)
+                    }
+                    None => match get_schema_by_subject(sr_settings, subject_name_strategy) {
+                        Ok(registered_schema) => to_avro_schema(sr_settings, registered_schema)
+                            .and_then(|s| check_record_name(s, subject_name_strategy)),
+                        Err(e) => Err(e.into_cache()),
+                    },
                 };
+                if let Ok(schema) = &v {
+                    sr_settings.record_usage(SchemaUsageKind::Encoded, schema.id, Some(e.key()));
+                }
+                if matches!(&v, Err(err) if err.is_throttled()) {
+                    return v;
+                }
                 e.insert(v).value().clone()
             }
         }
@@ -502,8 +1042,12 @@ fn add_references(
 ) -> Result<JsonValue, SRCError> {
     let mut new_value = json_value;
     for r in references.iter() {
+        if contains_definition(&new_value, &r.name) {
+            continue;
+        }
         let registered_schema = match get_referenced_schema(sr_settings, r) {
             Ok(v) => v,
+            Err(e) if e.is_throttled() => return Err(e),
             Err(e) => {
                 return Err(SRCError::non_retryable_with_cause(
                     e,
@@ -529,6 +1073,25 @@ fn add_references(
     Ok(new_value)
 }
 
+fn lookup_local_schema(
+    sr_settings: &SrSettings,
+    subject: &str,
+    raw: String,
+) -> Result<Arc<AvroSchema>, SRCError> {
+    let parsed = parse_local_schema(&raw)?;
+    match lookup_schema(sr_settings, subject, &SchemaType::Avro, &raw) {
+        Ok(registered_schema) => Ok(Arc::new(AvroSchema {
+            id: registered_schema.id,
+            raw,
+            parsed,
+            rule_set: registered_schema.rule_set,
+            metadata: registered_schema.metadata,
+            subject: Some(String::from(subject)),
+        })),
+        Err(e) => Err(e.into_cache()),
+    }
+}
+
 fn to_avro_schema(
     sr_settings: &SrSettings,
     registered_schema: RegisteredSchema,
@@ -543,10 +1106,13 @@ fn to_avro_schema(
         }
     }
     let main_schema = match serde_json::from_str(&registered_schema.schema) {
-        Ok(v) => match add_references(sr_settings, v, registered_schema.references.as_slice()) {
-            Ok(u) => u,
-            Err(e) => return Err(e),
-        },
+        Ok(v) => {
+            let sr_settings = sr_settings.counting_references();
+            match add_references(&sr_settings, v, registered_schema.references.as_slice()) {
+                Ok(u) => u,
+                Err(e) => return Err(e),
+            }
+        }
         Err(e) => {
             return Err(SRCError::non_retryable_with_cause(
                 e,
@@ -559,6 +1125,9 @@ fn to_avro_schema(
             id: registered_schema.id,
             raw: registered_schema.schema,
             parsed,
+            rule_set: registered_schema.rule_set,
+            metadata: registered_schema.metadata,
+            subject: registered_schema.subject,
         })),
         Err(e) => Err(SRCError::non_retryable_with_cause(
             e,
@@ -575,6 +1144,7 @@ mod tests {
     use apache_avro::from_value;
 
     use crate::avro_common::get_supplied_schema;
+    use crate::error::WireFormatError;
     use crate::schema_registry_common::SuppliedSchema;
 
     use super::*;
@@ -585,7 +1155,7 @@ mod tests {
         let sr_settings = SrSettings::new(String::from("http://127.0.0.1:1234"));
         let decoder = AvroDecoder::new(sr_settings);
         assert_eq!(
-            "AvroDecoder { sr_settings: SrSettings { urls: [\"http://127.0.0.1:1234\"], client: Client, authorization: None }, cache: {} }"
+            "AvroDecoder { sr_settings: SrSettings { urls: [\"http://127.0.0.1:1234\"], client: Client, authorization: None, dialect: Confluent, fetch_deleted: true, normalize: false, headers: {}, usage_listener: None, fallback: None, limits: SchemaLimits { max_schema_bytes: None, max_references: None, max_total_references: None, max_depth: None }, references_fetched: None }, cache: {}, fingerprints: {}, fingerprint_lookups: FingerprintLookups { missing: {} }, codec_config: AvroCodecConfig { decimal_as_string: false, uuid_as_string: false, temporal_as_string: false }, compression: None, id_strategy: Confluent, strictness: Lenient, error_policy: FailFast, field_encryptor: None, migration: None, migrations: {}, reader_schema: None, slow_path: SlowPathWarnings { decode_threshold: None, registry_fetch: false }, policy: None, cached_schemas: CachedSchemas { schema_type: \"avro\", count: 0 } }"
                 .to_owned(),
             format!("{:?}", decoder)
         )
@@ -627,11 +1197,12 @@ mod tests {
 
         let sr_settings = SrSettings::new(server.url());
         let decoder = AvroDecoder::new(sr_settings);
-        let heartbeat = decoder
+        let result = decoder
             .decode_with_schema(Some(&[0, 0, 0, 0, 1, 6]))
             .unwrap()
-            .unwrap()
-            .value;
+            .unwrap();
+        assert_eq!(result.writer_schema, result.schema);
+        let heartbeat = result.value;
 
         assert_eq!(
             heartbeat,
@@ -669,6 +1240,63 @@ mod tests {
         assert_eq!(item.beat, 3i64);
     }
 
+    #[test]
+    fn test_decode_with_reader_schema() {
+        let mut server = mockito::Server::new();
+        let _m = server.mock("GET", "/schemas/ids/1?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"schema":"{\"type\":\"record\",\"name\":\"Heartbeat\",\"namespace\":\"nl.openweb.data\",\"fields\":[{\"name\":\"beat\",\"type\":\"long\"}]}"}"#)
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let decoder = AvroDecoder::new(sr_settings);
+        let reader_schema = Schema::parse_str(r#"{"type":"record","name":"Heartbeat","namespace":"nl.openweb.data","fields":[{"name":"beat","type":"long"},{"name":"source","type":"string","default":"unknown"}]}"#).unwrap();
+        let result = decoder
+            .decode_with_reader_schema(Some(&[0, 0, 0, 0, 1, 6]), &reader_schema)
+            .unwrap();
+
+        assert_eq!(result.name.unwrap().name.as_str(), "Heartbeat");
+        assert_eq!(
+            result.value,
+            Value::Record(vec![
+                (String::from("beat"), Value::Long(3)),
+                (
+                    String::from("source"),
+                    Value::String(String::from("unknown"))
+                ),
+            ])
+        );
+
+        let incompatible = Schema::parse_str(r#"{"type":"record","name":"Heartbeat","namespace":"nl.openweb.data","fields":[{"name":"source","type":"string"}]}"#).unwrap();
+        let error = decoder
+            .decode_with_reader_schema(Some(&[0, 0, 0, 0, 1, 6]), &incompatible)
+            .unwrap_err();
+        assert_eq!(
+            error.error,
+            "Could not transform bytes using writer and reader schema"
+        )
+    }
+
+    #[test]
+    fn test_decode_as() {
+        let mut server = mockito::Server::new();
+        let _m = server.mock("GET", "/schemas/ids/1?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"schema":"{\"type\":\"record\",\"name\":\"Heartbeat\",\"namespace\":\"nl.openweb.data\",\"fields\":[{\"name\":\"beat\",\"type\":\"long\"}]}"}"#)
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let decoder = AvroDecoder::new(sr_settings);
+        let heartbeat = decoder
+            .decode_as::<Heartbeat>(Some(&[0, 0, 0, 0, 1, 6]))
+            .unwrap();
+
+        assert_eq!(heartbeat.unwrap().beat, 3i64);
+        assert!(decoder.decode_as::<Heartbeat>(None).unwrap().is_none())
+    }
+
     #[test]
     fn test_decoder_no_bytes() {
         let sr_settings = SrSettings::new(String::from("http://127.0.0.1:1234"));
@@ -701,9 +1329,7 @@ mod tests {
 
         assert_eq!(
             result,
-            Err(SRCError::non_retryable_without_cause(
-                "Invalid bytes [1, 0, 0, 0, 1, 6]"
-            ))
+            Err(SRCError::from(WireFormatError::InvalidMagicByte(1)))
         )
     }
 
@@ -715,9 +1341,7 @@ mod tests {
 
         assert_eq!(
             result,
-            Err(SRCError::non_retryable_without_cause(
-                "Invalid bytes [1, 0, 0, 0, 1, 6]"
-            ))
+            Err(SRCError::from(WireFormatError::InvalidMagicByte(1)))
         )
     }
 
@@ -727,12 +1351,30 @@ mod tests {
         let decoder = AvroDecoder::new(sr_settings);
         let result = decoder.decode(Some(&[0, 0, 0, 0]));
 
+        assert_eq!(result, Err(SRCError::from(WireFormatError::TooShort(4))))
+    }
+
+    #[test]
+    fn test_decoder_strict_trailing_bytes() {
+        let mut server = mockito::Server::new();
+        let _m = server.mock("GET", "/schemas/ids/1?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"schema":"{\"type\":\"record\",\"name\":\"Heartbeat\",\"namespace\":\"nl.openweb.data\",\"fields\":[{\"name\":\"beat\",\"type\":\"long\"}]}"}"#)
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let lenient = AvroDecoder::new(sr_settings);
+        let strict = lenient.clone().with_strictness(Strictness::Strict);
+        let bytes: &[u8] = &[0, 0, 0, 0, 1, 6, 7];
+
+        assert!(lenient.decode(Some(bytes)).is_ok());
+        let error = strict.decode(Some(bytes)).unwrap_err();
         assert_eq!(
-            result,
-            Err(SRCError::non_retryable_without_cause(
-                "Invalid bytes [0, 0, 0, 0]"
-            ))
-        )
+            error.wire_format(),
+            Some(&WireFormatError::TrailingBytes(1))
+        );
+        assert!(strict.decode(Some(&bytes[..6])).is_ok());
     }
 
     #[test]
@@ -950,7 +1592,7 @@ mod tests {
         let sr_settings = SrSettings::new(String::from("http://127.0.0.1:1234"));
         let encoder = AvroEncoder::new(sr_settings);
         assert_eq!(
-            "AvroEncoder { sr_settings: SrSettings { urls: [\"http://127.0.0.1:1234\"], client: Client, authorization: None }, cache: {} }"
+            "AvroEncoder { sr_settings: SrSettings { urls: [\"http://127.0.0.1:1234\"], client: Client, authorization: None, dialect: Confluent, fetch_deleted: true, normalize: false, headers: {}, usage_listener: None, fallback: None, limits: SchemaLimits { max_schema_bytes: None, max_references: None, max_total_references: None, max_depth: None }, references_fetched: None }, cache: {}, codec_config: AvroCodecConfig { decimal_as_string: false, uuid_as_string: false, temporal_as_string: false }, framing: Confluent, id_strategy: Confluent, compression: None, local_schemas: LocalSchemaProvider { schemas: {} }, field_encryptor: None }"
                 .to_owned(),
             format!("{:?}", encoder)
         )
@@ -1054,6 +1696,31 @@ mod tests {
         assert_eq!(bytes, Ok(vec![0, 0, 0, 0, 3, 6]))
     }
 
+    #[test]
+    fn test_record_name_not_matching_schema() {
+        let mut server = mockito::Server::new();
+        let _m = server.mock("GET", "/subjects/heartbeat-Heartbeat/versions/latest")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"subject":"heartbeat-Heartbeat","version":1,"id":3,"schema":"{\"type\":\"record\",\"name\":\"Heartbeat\",\"namespace\":\"nl.openweb.data\",\"fields\":[{\"name\":\"beat\",\"type\":\"long\"}]}"}"#)
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let encoder = AvroEncoder::new(sr_settings);
+        let strategy = SubjectNameStrategy::TopicRecordNameStrategy(
+            String::from("heartbeat"),
+            String::from("Heartbeat"),
+        );
+        let err = encoder
+            .encode(vec![("beat", Value::Long(3))], &strategy)
+            .unwrap_err();
+
+        assert_eq!(
+            err.error,
+            "Record name Heartbeat of the subject name strategy doesn't match the full name nl.openweb.data.Heartbeat of the schema with id 3"
+        )
+    }
+
     #[test]
     fn test_encoder_no_id_in_response() {
         let mut server = mockito::Server::new();
@@ -1134,6 +1801,64 @@ mod tests {
         }
     }
 
+    #[derive(serde::Serialize)]
+    #[serde(untagged)]
+    enum Beat {
+        Long { beat: i64 },
+        Text { beat: String },
+        Flag { beat: bool },
+    }
+
+    #[test]
+    fn test_encode_many() {
+        let mut server = mockito::Server::new();
+        let m = server.mock("GET", "/subjects/nl.openweb.data.Heartbeat/versions/latest")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"subject":"heartbeat-value","version":1,"id":3,"schema":"{\"type\":\"record\",\"name\":\"Heartbeat\",\"namespace\":\"nl.openweb.data\",\"fields\":[{\"name\":\"beat\",\"type\":\"long\"}]}"}"#)
+            .expect(1)
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let encoder = AvroEncoder::new(sr_settings);
+        let strategy =
+            SubjectNameStrategy::RecordNameStrategy(String::from("nl.openweb.data.Heartbeat"));
+
+        let bytes = encoder
+            .encode_many(&[Heartbeat { beat: 3 }, Heartbeat { beat: 5 }], strategy)
+            .unwrap();
+
+        assert_eq!(bytes, vec![vec![0, 0, 0, 0, 3, 6], vec![0, 0, 0, 0, 3, 10]]);
+        m.assert();
+    }
+
+    #[test]
+    fn test_encode_many_stops_at_first_error() {
+        let mut server = mockito::Server::new();
+        let _m = server.mock("GET", "/subjects/nl.openweb.data.Heartbeat/versions/latest")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"subject":"heartbeat-value","version":1,"id":3,"schema":"{\"type\":\"record\",\"name\":\"Heartbeat\",\"namespace\":\"nl.openweb.data\",\"fields\":[{\"name\":\"beat\",\"type\":\"long\"}]}"}"#)
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let encoder = AvroEncoder::new(sr_settings);
+        let strategy =
+            SubjectNameStrategy::RecordNameStrategy(String::from("nl.openweb.data.Heartbeat"));
+        let items = [
+            Beat::Long { beat: 3 },
+            Beat::Text {
+                beat: String::from("three"),
+            },
+            Beat::Flag { beat: true },
+        ];
+
+        let err = encoder.encode_many(&items, &strategy).unwrap_err();
+
+        assert_eq!(err.error, "Failed to resolve");
+        assert_eq!(Err(err), encoder.encode_struct(&items[1], &strategy));
+    }
+
     #[test]
     fn test_encode_cache() {
         let mut server = mockito::Server::new();
@@ -1335,18 +2060,14 @@ mod tests {
                 references: vec![],
             },
         );
-        let error = encoder.encode(vec![("beat", Value::Long(3))], &strategy);
-        assert_eq!(
-            error,
-            Err(SRCError::new(
-                "could not parse to RawRegisteredSchema, schema might not exist on this schema registry, the http call failed, cause will give more information",
-                Some(String::from(
-                    "error decoding response body"
-                )),
-                false,
-            )
-            .into_cache())
-        )
+        let error = encoder
+            .encode(vec![("beat", Value::Long(3))], &strategy)
+            .unwrap_err();
+        assert!(error
+            .error
+            .starts_with("schema registry responded with status 501 Not Implemented"));
+        assert!(error.retriable);
+        assert!(error.cached);
     }
 
     #[test]
@@ -1356,6 +2077,12 @@ mod tests {
             schema_type: SchemaType::Avro,
             schema: String::from(r#"{"type":"record","name":"Name"}"#),
             references: vec![],
+            subject: None,
+            version: None,
+            metadata: None,
+            rule_set: None,
+            guid: None,
+            deleted: false,
         };
         let sr_settings = SrSettings::new(String::from("http://127.0.0.1:1234"));
         let err = to_avro_schema(&sr_settings, registered_schema).unwrap_err();
@@ -1374,6 +2101,12 @@ mod tests {
                 r#"syntax = "proto3"; package org.schema_registry_test_app.proto; message Result { string up = 1; string down = 2; }"#,
             ),
             references: vec![],
+            subject: None,
+            version: None,
+            metadata: None,
+            rule_set: None,
+            guid: None,
+            deleted: false,
         };
         let sr_settings = SrSettings::new(String::from("http://127.0.0.1:1234"));
         let result = match to_avro_schema(&sr_settings, registered_schema) {
@@ -1477,4 +2210,98 @@ mod tests {
         };
         assert_eq!(&1i64, counter_value, "counter is 1");
     }
+
+    #[derive(Debug, PartialEq)]
+    struct FixedBytes(Vec<u8>);
+
+    impl<'de> serde::Deserialize<'de> for FixedBytes {
+        fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            struct BytesVisitor;
+            impl serde::de::Visitor<'_> for BytesVisitor {
+                type Value = FixedBytes;
+                fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                    f.write_str("bytes")
+                }
+                fn visit_bytes<E: serde::de::Error>(self, v: &[u8]) -> Result<FixedBytes, E> {
+                    Ok(FixedBytes(v.to_vec()))
+                }
+            }
+            deserializer.deserialize_bytes(BytesVisitor)
+        }
+    }
+
+    #[derive(serde::Deserialize, Debug, PartialEq)]
+    struct Payment {
+        code: FixedBytes,
+        amount: String,
+        id: String,
+    }
+
+    fn get_payment_bytes() -> Vec<u8> {
+        let mut bytes = vec![0, 0, 0, 0, 8, 1, 2, 4, 4, 210, 72];
+        bytes.extend_from_slice(b"67e55044-10b1-426f-9247-bb680e5fe0c8");
+        bytes
+    }
+
+    fn get_payment_body() -> &'static str {
+        r#"{"schema":"{\"type\":\"record\",\"name\":\"Payment\",\"namespace\":\"nl.openweb.data\",\"fields\":[{\"name\":\"code\",\"type\":{\"type\":\"fixed\",\"name\":\"Code\",\"size\":2}},{\"name\":\"amount\",\"type\":{\"type\":\"bytes\",\"logicalType\":\"decimal\",\"precision\":10,\"scale\":2}},{\"name\":\"id\",\"type\":{\"type\":\"string\",\"logicalType\":\"uuid\"}}]}"}"#
+    }
+
+    #[test]
+    fn test_decode_as_fixed_decimal_and_uuid() {
+        let mut server = mockito::Server::new();
+        let _m = server
+            .mock("GET", "/schemas/ids/8?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_payment_body())
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let decoder =
+            AvroDecoder::new(sr_settings).with_codec_config(AvroCodecConfig::all_as_string());
+        let payment = decoder
+            .decode_as::<Payment>(Some(&get_payment_bytes()))
+            .unwrap();
+
+        assert_eq!(
+            payment,
+            Some(Payment {
+                code: FixedBytes(vec![1, 2]),
+                amount: String::from("12.34"),
+                id: String::from("67e55044-10b1-426f-9247-bb680e5fe0c8"),
+            })
+        )
+    }
+
+    #[test]
+    fn test_single_object_skips_broken_schemas_and_remembers_missing() {
+        let mut server = mockito::Server::new();
+        let all_schemas = server.mock("GET", "/schemas")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"[{"subject":"broken-value","version":1,"id":2},{"subject":"heartbeat-value","version":1,"id":3,"schema":"{\"type\":\"record\",\"name\":\"Heartbeat\",\"namespace\":\"nl.openweb.data\",\"fields\":[{\"name\":\"beat\",\"type\":\"long\"}]}"}]"#)
+            .expect(1)
+            .create();
+
+        let decoder = AvroDecoder::new(SrSettings::new(server.url()));
+        let schema = Schema::parse_str(r#"{"type":"record","name":"Heartbeat","namespace":"nl.openweb.data","fields":[{"name":"beat","type":"long"}]}"#).unwrap();
+        let mut bytes = vec![0xC3, 0x01];
+        bytes.extend_from_slice(&get_fingerprint(&schema).to_le_bytes());
+        bytes.push(6);
+        let result = decoder.decode_single_object(Some(&bytes)).unwrap();
+        assert_eq!(
+            result.value,
+            Value::Record(vec![("beat".to_string(), Value::Long(3))])
+        );
+        for _ in 0..2 {
+            let error = decoder
+                .decode_single_object(Some(&[0xC3, 0x01, 1, 2, 3, 4, 5, 6, 7, 8, 6]))
+                .unwrap_err();
+            assert!(error
+                .error
+                .starts_with("Could not find schema with fingerprint"))
+        }
+        all_schemas.assert();
+    }
 }
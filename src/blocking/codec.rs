@@ -0,0 +1,173 @@
+//! Traits implemented by all the blocking encoders and decoders, so code can be generic over the
+//! schema type, instead of needing the same glue code for each encoder and decoder. Some of the
+//! blocking encoders and decoders need to be mutable, so the traits take them as mutable.
+#[cfg(feature = "avro")]
+use crate::avro_common::DecodeResult;
+#[cfg(feature = "avro")]
+use crate::blocking::avro::{AvroDecoder, AvroEncoder};
+#[cfg(feature = "json")]
+use crate::blocking::json::{DecodeResult as JsonDecodeResult, JsonDecoder, JsonEncoder};
+#[cfg(feature = "proto_decoder")]
+use crate::blocking::proto_decoder::{DecodeResultWithContext, ProtoDecoder};
+#[cfg(feature = "proto_raw")]
+use crate::blocking::proto_raw::{ProtoRawDecoder, ProtoRawEncoder, RawDecodeResult};
+use crate::error::SRCError;
+use crate::schema_registry_common::SubjectNameStrategy;
+
+/// Implemented by the encoders, to encode a value without knowing the schema type.
+pub trait PayloadEncoder {
+    type Input: ?Sized;
+    /// Encodes the value, the same way as the encode function of the encoder.
+    fn encode_payload(
+        &mut self,
+        value: &Self::Input,
+        subject_name_strategy: &SubjectNameStrategy,
+    ) -> Result<Vec<u8>, SRCError>;
+}
+
+/// Implemented by the decoders, to decode a payload without knowing the schema type. The output
+/// may borrow from the decoder.
+pub trait PayloadDecoder {
+    type Output<'a>
+    where
+        Self: 'a;
+    /// Decodes the payload, the same way as the decode function of the decoder.
+    fn decode_payload(&mut self, bytes: Option<&[u8]>) -> Result<Self::Output<'_>, SRCError>;
+}
+
+#[cfg(feature = "avro")]
+impl PayloadEncoder for AvroEncoder {
+    type Input = Vec<(&'static str, apache_avro::types::Value)>;
+    fn encode_payload(
+        &mut self,
+        value: &Self::Input,
+        subject_name_strategy: &SubjectNameStrategy,
+    ) -> Result<Vec<u8>, SRCError> {
+        self.encode(value.clone(), subject_name_strategy)
+    }
+}
+
+#[cfg(feature = "avro")]
+impl PayloadDecoder for AvroDecoder {
+    type Output<'a> = DecodeResult;
+    fn decode_payload(&mut self, bytes: Option<&[u8]>) -> Result<DecodeResult, SRCError> {
+        self.decode(bytes)
+    }
+}
+
+#[cfg(feature = "json")]
+impl PayloadEncoder for JsonEncoder {
+    type Input = serde_json::Value;
+    fn encode_payload(
+        &mut self,
+        value: &serde_json::Value,
+        subject_name_strategy: &SubjectNameStrategy,
+    ) -> Result<Vec<u8>, SRCError> {
+        self.encode(value, subject_name_strategy)
+    }
+}
+
+#[cfg(feature = "json")]
+impl PayloadDecoder for JsonDecoder {
+    type Output<'a> = Option<JsonDecodeResult<'a>>;
+    fn decode_payload(
+        &mut self,
+        bytes: Option<&[u8]>,
+    ) -> Result<Option<JsonDecodeResult<'_>>, SRCError> {
+        self.decode(bytes)
+    }
+}
+
+/// The input is the full name of the message, together with the encoded protobuf bytes.
+#[cfg(feature = "proto_raw")]
+impl PayloadEncoder for ProtoRawEncoder {
+    type Input = (String, Vec<u8>);
+    fn encode_payload(
+        &mut self,
+        value: &(String, Vec<u8>),
+        subject_name_strategy: &SubjectNameStrategy,
+    ) -> Result<Vec<u8>, SRCError> {
+        let (full_name, bytes) = value;
+        self.encode(bytes, full_name, subject_name_strategy)
+    }
+}
+
+#[cfg(feature = "proto_raw")]
+impl PayloadDecoder for ProtoRawDecoder {
+    type Output<'a> = Option<RawDecodeResult>;
+    fn decode_payload(
+        &mut self,
+        bytes: Option<&[u8]>,
+    ) -> Result<Option<RawDecodeResult>, SRCError> {
+        self.decode(bytes)
+    }
+}
+
+#[cfg(feature = "proto_decoder")]
+impl PayloadDecoder for ProtoDecoder {
+    type Output<'a> = Option<DecodeResultWithContext>;
+    fn decode_payload(
+        &mut self,
+        bytes: Option<&[u8]>,
+    ) -> Result<Option<DecodeResultWithContext>, SRCError> {
+        self.decode_with_context(bytes)
+    }
+}
+
+#[cfg(all(test, feature = "avro"))]
+mod tests {
+    use apache_avro::types::Value;
+    use mockito::Server;
+
+    use crate::blocking::avro::{AvroDecoder, AvroEncoder};
+    use crate::blocking::codec::{PayloadDecoder, PayloadEncoder};
+    use crate::blocking::schema_registry::SrSettings;
+    use crate::error::SRCError;
+    use crate::schema_registry_common::{SchemaType, SubjectNameStrategy, SuppliedSchema};
+
+    fn round_trip<E: PayloadEncoder, D: PayloadDecoder>(
+        encoder: &mut E,
+        decoder: &mut D,
+        value: &E::Input,
+        subject_name_strategy: &SubjectNameStrategy,
+    ) -> Result<Vec<u8>, SRCError> {
+        let bytes = encoder.encode_payload(value, subject_name_strategy)?;
+        decoder.decode_payload(Some(&bytes))?;
+        Ok(bytes)
+    }
+
+    #[test]
+    fn generic_round_trip() {
+        let mut server = Server::new();
+        let _p = server
+            .mock("POST", "/subjects/heartbeat-value/versions")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"id":1}"#)
+            .create();
+        let _g = server.mock("GET", "/schemas/ids/1?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"schema":"{\"type\":\"record\",\"name\":\"Heartbeat\",\"namespace\":\"nl.openweb.data\",\"fields\":[{\"name\":\"beat\",\"type\":\"long\"}]}"}"#)
+            .create();
+
+        let mut encoder = AvroEncoder::new(SrSettings::new(server.url()));
+        let mut decoder = AvroDecoder::new(SrSettings::new(server.url()));
+        let strategy = SubjectNameStrategy::TopicNameStrategyWithSchema(
+            String::from("heartbeat"),
+            false,
+            SuppliedSchema {
+                name: Some(String::from("nl.openweb.data.Heartbeat")),
+                schema_type: SchemaType::Avro,
+                schema: String::from(
+                    r#"{"type":"record","name":"Heartbeat","namespace":"nl.openweb.data","fields":[{"name":"beat","type":"long"}]}"#,
+                ),
+                references: vec![],
+            },
+        );
+        let value = vec![("beat", Value::Long(3))];
+
+        let bytes = round_trip(&mut encoder, &mut decoder, &value, &strategy).unwrap();
+        assert_eq!(bytes, vec![0, 0, 0, 0, 1, 6]);
+    }
+}
@@ -0,0 +1,258 @@
+//! The http client used for the blocking calls to the schema registry. By default this is the
+//! blocking client of reqwest, which runs a tokio runtime internally. With the `blocking_native`
+//! feature a fully synchronous client based on ureq is used instead, with the same api as far as
+//! it's used by this crate. This way no async runtime is needed, and the blocking calls can't panic
+//! when done from within a runtime.
+#[cfg(not(feature = "blocking_native"))]
+pub(crate) use reqwest::blocking::{Client, ClientBuilder, RequestBuilder, Response};
+#[cfg(not(feature = "blocking_native"))]
+pub(crate) use reqwest::{header, Error, Method, Proxy, StatusCode};
+
+#[cfg(feature = "blocking_native")]
+pub(crate) use http::{header, Method, StatusCode};
+#[cfg(feature = "blocking_native")]
+pub(crate) use native::{Client, ClientBuilder, Error, Proxy, RequestBuilder, Response};
+
+#[cfg(feature = "blocking_native")]
+mod native {
+    use std::fmt;
+    use std::io::{self, Read};
+    use std::time::Duration;
+
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use http::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION};
+    use http::{Method, StatusCode};
+    use serde::de::DeserializeOwned;
+
+    /// Error for a call that could not be done, or a response that could not be read.
+    #[derive(Debug)]
+    pub(crate) struct Error(String);
+
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str(&self.0)
+        }
+    }
+
+    pub(crate) struct Proxy(ureq::Proxy);
+
+    impl Proxy {
+        pub(crate) fn all(proxy_url: &str) -> Result<Proxy, Error> {
+            match ureq::Proxy::new(proxy_url) {
+                Ok(p) => Ok(Proxy(p)),
+                Err(e) => Err(Error(e.to_string())),
+            }
+        }
+    }
+
+    #[derive(Default)]
+    pub(crate) struct ClientBuilder {
+        headers: HeaderMap,
+        user_agent: Option<String>,
+        proxy: Option<Proxy>,
+        timeout: Option<Duration>,
+    }
+
+    impl ClientBuilder {
+        pub(crate) fn default_headers(mut self, headers: HeaderMap) -> ClientBuilder {
+            self.headers = headers;
+            self
+        }
+
+        pub(crate) fn user_agent(mut self, user_agent: &str) -> ClientBuilder {
+            self.user_agent = Some(String::from(user_agent));
+            self
+        }
+
+        pub(crate) fn proxy(mut self, proxy: Proxy) -> ClientBuilder {
+            self.proxy = Some(proxy);
+            self
+        }
+
+        pub(crate) fn timeout(mut self, timeout: Duration) -> ClientBuilder {
+            self.timeout = Some(timeout);
+            self
+        }
+
+        pub(crate) fn build(self) -> Result<Client, Error> {
+            let mut builder = ureq::AgentBuilder::new();
+            if let Some(timeout) = self.timeout {
+                builder = builder.timeout(timeout);
+            }
+            if let Some(user_agent) = self.user_agent {
+                builder = builder.user_agent(&user_agent);
+            }
+            if let Some(Proxy(proxy)) = self.proxy {
+                builder = builder.proxy(proxy);
+            }
+            Ok(Client {
+                agent: builder.build(),
+                headers: self.headers,
+            })
+        }
+    }
+
+    /// The ureq agent, together with the headers added to every request.
+    #[derive(Clone)]
+    pub(crate) struct Client {
+        agent: ureq::Agent,
+        headers: HeaderMap,
+    }
+
+    impl fmt::Debug for Client {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("Client").finish()
+        }
+    }
+
+    impl Client {
+        pub(crate) fn new() -> Client {
+            Client {
+                agent: ureq::Agent::new(),
+                headers: HeaderMap::new(),
+            }
+        }
+
+        pub(crate) fn builder() -> ClientBuilder {
+            ClientBuilder::default()
+        }
+
+        pub(crate) fn get<U: AsRef<str>>(&self, url: U) -> RequestBuilder {
+            self.request(Method::GET, url)
+        }
+
+        pub(crate) fn post<U: AsRef<str>>(&self, url: U) -> RequestBuilder {
+            self.request(Method::POST, url)
+        }
+
+        pub(crate) fn request<U: AsRef<str>>(&self, method: Method, url: U) -> RequestBuilder {
+            let mut request = self.agent.request(method.as_str(), url.as_ref());
+            for (name, value) in &self.headers {
+                if let Ok(value) = value.to_str() {
+                    request = request.set(name.as_str(), value);
+                }
+            }
+            RequestBuilder {
+                request,
+                body: None,
+            }
+        }
+    }
+
+    pub(crate) struct RequestBuilder {
+        request: ureq::Request,
+        body: Option<String>,
+    }
+
+    impl RequestBuilder {
+        pub(crate) fn header(mut self, name: HeaderName, value: &str) -> RequestBuilder {
+            self.request = self.request.set(name.as_str(), value);
+            self
+        }
+
+        pub(crate) fn headers(mut self, headers: HeaderMap) -> RequestBuilder {
+            for (name, value) in &headers {
+                if let Ok(value) = value.to_str() {
+                    self.request = self.request.set(name.as_str(), value);
+                }
+            }
+            self
+        }
+
+        pub(crate) fn body(mut self, body: String) -> RequestBuilder {
+            self.body = Some(body);
+            self
+        }
+
+        pub(crate) fn bearer_auth<T: fmt::Display>(self, token: T) -> RequestBuilder {
+            let value = format!("Bearer {}", token);
+            self.header(AUTHORIZATION, &value)
+        }
+
+        pub(crate) fn basic_auth<U: fmt::Display, P: fmt::Display>(
+            self,
+            username: U,
+            password: Option<P>,
+        ) -> RequestBuilder {
+            let credentials = match password {
+                Some(p) => format!("{}:{}", username, p),
+                None => format!("{}:", username),
+            };
+            let value = format!("Basic {}", STANDARD.encode(credentials));
+            self.header(AUTHORIZATION, &value)
+        }
+
+        /// Sends the request. Like with reqwest, a response with an error status is not an error.
+        pub(crate) fn send(self) -> Result<Response, Error> {
+            let result = match self.body {
+                Some(body) => self.request.send_string(&body),
+                None => self.request.call(),
+            };
+            let response = match result {
+                Ok(r) | Err(ureq::Error::Status(_, r)) => r,
+                Err(e) => return Err(Error(e.to_string())),
+            };
+            let status = match StatusCode::from_u16(response.status()) {
+                Ok(s) => s,
+                Err(e) => return Err(Error(e.to_string())),
+            };
+            let mut headers = HeaderMap::new();
+            for name in response.headers_names() {
+                if let (Ok(n), Some(Ok(v))) = (
+                    HeaderName::from_bytes(name.as_bytes()),
+                    response.header(&name).map(HeaderValue::from_str),
+                ) {
+                    headers.append(n, v);
+                }
+            }
+            Ok(Response {
+                status,
+                headers,
+                body: response.into_reader(),
+            })
+        }
+    }
+
+    /// The maximum length of a body read as text or json, the same as the one of ureq.
+    const MAX_TEXT_BYTES: u64 = 10 * 1024 * 1024;
+
+    /// The response, with the body not read yet. Like with reqwest, the body can be read directly
+    /// using `Read`.
+    pub(crate) struct Response {
+        status: StatusCode,
+        headers: HeaderMap,
+        body: Box<dyn Read + Send + Sync + 'static>,
+    }
+
+    impl Read for Response {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.body.read(buf)
+        }
+    }
+
+    impl Response {
+        pub(crate) fn status(&self) -> StatusCode {
+            self.status
+        }
+
+        pub(crate) fn headers(&self) -> &HeaderMap {
+            &self.headers
+        }
+
+        pub(crate) fn json<T: DeserializeOwned>(self) -> Result<T, Error> {
+            match serde_json::from_str(&self.text()?) {
+                Ok(v) => Ok(v),
+                Err(e) => Err(Error(format!("error decoding response body: {}", e))),
+            }
+        }
+
+        pub(crate) fn text(self) -> Result<String, Error> {
+            let mut body = String::new();
+            match self.take(MAX_TEXT_BYTES).read_to_string(&mut body) {
+                Ok(_) => Ok(body),
+                Err(e) => Err(Error(format!("error reading response body: {}", e))),
+            }
+        }
+    }
+}
@@ -1,20 +1,43 @@
-use std::collections::hash_map::{Entry, RandomState};
+use std::any::type_name;
+use std::borrow::Cow;
+use std::collections::hash_map::{self, RandomState};
 use std::collections::HashMap;
-use std::str::FromStr;
+use std::sync::Arc;
 
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+use serde::de::DeserializeOwned;
 use serde_json::Value;
 use url::Url;
 use valico::json_schema::schema::ScopedSchema;
 use valico::json_schema::{Scope, ValidationState};
 
 use crate::blocking::schema_registry::{
-    get_referenced_schema, get_schema_by_id_and_type, get_schema_by_subject, SrSettings,
+    get_allowed_schema, get_migration, get_referenced_schema, get_schema_by_subject, SrSettings,
 };
+#[cfg(feature = "json_schemars")]
+use crate::blocking::schema_registry::{lookup_schema, post_schema};
 use crate::error::SRCError;
-use crate::json_common::{fetch_fallback, fetch_id, handle_validation, to_bytes, to_value};
+use crate::field_encryption::{Direction, FieldEncryptor};
+#[cfg(feature = "json_schemars")]
+use crate::json_common::schemars_schema;
+#[cfg(feature = "json_schemars")]
+pub use crate::json_common::BoundJsonEncoder;
+use crate::json_common::{
+    apply_field_encryption, fetch_fallback, fetch_id, handle_validation, reference_url, to_value,
+    trailing_json_bytes, write_bytes,
+};
+pub use crate::json_common::{JsonDraft, JsonValidation};
+use crate::migration::{migrate, Migration, MigrationConfig};
 use crate::schema_registry_common::{
-    get_bytes_result, BytesResult, RegisteredReference, RegisteredSchema, SchemaType,
-    SubjectNameStrategy,
+    check_policy, check_remaining_bytes, to_wire_format_error, BytesResult, DecodeMetadata,
+    DecodeResultWithMetadata, DecodedRecord, DecoderOptions, DeserializationErrorPolicy,
+    IdStrategy, Metadata, RegisteredReference, RegisteredSchema, RuleSet, SchemaPolicy, SchemaType,
+    SchemaUsageKind, SlowPathWarnings, Strictness, SubjectNameStrategy,
+};
+use crate::telemetry::{
+    decode_start, record_cache_lookup, record_decode_error, warn_registry_fetch, warn_slow_decode,
+    CachedSchemas,
 };
 
 /// Encoder that works by prepending the correct bytes in order to make it valid schema registry
@@ -24,8 +47,11 @@ use crate::schema_registry_common::{
 #[derive(Debug)]
 pub struct JsonEncoder {
     sr_settings: SrSettings,
-    cache: HashMap<String, Result<EncodeContext, SRCError>, RandomState>,
+    cache: HashMap<String, Result<SchemaContext, SRCError>, RandomState>,
     scope: Scope,
+    validation: JsonValidation,
+    id_strategy: IdStrategy,
+    field_encryptor: Option<Arc<dyn FieldEncryptor>>,
 }
 
 impl JsonEncoder {
@@ -35,8 +61,28 @@ impl JsonEncoder {
             sr_settings,
             cache: HashMap::new(),
             scope: Scope::new(),
+            validation: JsonValidation::default(),
+            id_strategy: IdStrategy::default(),
+            field_encryptor: None,
         }
     }
+    /// Sets whether, and using which draft, values are validated before encoding. By default they
+    /// are validated, using the draft from the `$schema` of the schema.
+    pub fn with_validation(mut self, validation: JsonValidation) -> JsonEncoder {
+        self.validation = validation;
+        self
+    }
+    /// Sets how the schema id is added to the json bytes, by default as 4 bytes. With the id in a
+    /// header, only the json bytes are returned.
+    pub fn with_id_strategy(mut self, id_strategy: IdStrategy) -> JsonEncoder {
+        self.id_strategy = id_strategy;
+        self
+    }
+    /// Sets the encryptor used to encrypt the properties tagged by an ENCRYPT rule of the schema.
+    pub fn with_field_encryptor(mut self, field_encryptor: Arc<dyn FieldEncryptor>) -> JsonEncoder {
+        self.field_encryptor = Some(field_encryptor);
+        self
+    }
     /// Removes errors from the cache, can be useful to retry failed encodings.
     pub fn remove_errors_from_cache(&mut self) {
         self.cache.retain(|_, v| v.is_ok());
@@ -46,12 +92,65 @@ impl JsonEncoder {
     pub fn encode(
         &mut self,
         value: &Value,
-        subject_name_strategy: &SubjectNameStrategy,
+        subject_name_strategy: impl Into<SubjectNameStrategy>,
     ) -> Result<Vec<u8>, SRCError> {
+        let mut buf = Vec::new();
+        self.encode_into(value, subject_name_strategy, &mut buf)?;
+        Ok(buf)
+    }
+
+    /// Like `encode`, but writing the bytes to the buffer, which is cleared first. This way the
+    /// buffer, and its allocation, can be reused for multiple values.
+    pub fn encode_into(
+        &mut self,
+        value: &Value,
+        subject_name_strategy: impl Into<SubjectNameStrategy>,
+        buf: &mut Vec<u8>,
+    ) -> Result<(), SRCError> {
+        let subject_name_strategy = subject_name_strategy.into();
         let key = subject_name_strategy.get_subject()?;
-        let (validation, id) = self.validate(key, subject_name_strategy, value)?;
-        handle_validation(validation, value)?;
-        to_bytes(id, value)
+        self.write_value(key, &subject_name_strategy, value, buf)
+    }
+
+    /// Encodes multiple values for the same subject. The schema is only fetched once, as it's
+    /// cached for the subject. Stops at, and returns, the first error.
+    pub fn encode_many(
+        &mut self,
+        values: &[Value],
+        subject_name_strategy: impl Into<SubjectNameStrategy>,
+    ) -> Result<Vec<Vec<u8>>, SRCError> {
+        let subject_name_strategy = subject_name_strategy.into();
+        let key = subject_name_strategy.get_subject()?;
+        values
+            .iter()
+            .map(|value| {
+                let mut buf = Vec::new();
+                self.write_value(key.clone(), &subject_name_strategy, value, &mut buf)?;
+                Ok(buf)
+            })
+            .collect()
+    }
+
+    fn write_value(
+        &mut self,
+        key: String,
+        subject_name_strategy: &SubjectNameStrategy,
+        value: &Value,
+        buf: &mut Vec<u8>,
+    ) -> Result<(), SRCError> {
+        let (validation, id) = self.validate(key.clone(), subject_name_strategy, value)?;
+        if self.validation.is_enabled() {
+            handle_validation(validation, value)?;
+        }
+        let value = match self.cache.get(&key) {
+            Some(Ok(context)) => context.apply_field_encryption(
+                self.field_encryptor.as_deref(),
+                Cow::Borrowed(value),
+                Direction::Write,
+            )?,
+            _ => Cow::Borrowed(value),
+        };
+        write_bytes(id, &value, &self.id_strategy, buf)
     }
 
     /// Validates the value as being correct according to the schema
@@ -62,22 +161,32 @@ impl JsonEncoder {
         value: &Value,
     ) -> Result<(ValidationState, u32), SRCError> {
         let cached_context = match self.cache.entry(key) {
-            Entry::Occupied(e) => e.into_mut().as_ref(),
-            Entry::Vacant(e) => {
+            hash_map::Entry::Occupied(e) => e.into_mut().as_ref(),
+            hash_map::Entry::Vacant(e) => {
                 let v = match get_schema_by_subject(&self.sr_settings, subject_name_strategy) {
                     Ok(registered_schema) => match set_scoped_schema(
                         &mut self.scope,
                         &self.sr_settings,
                         &registered_schema,
+                        self.validation,
                     ) {
-                        Ok(url) => Ok(EncodeContext {
-                            id: registered_schema.id,
-                            url,
-                        }),
+                        Ok(context) => Ok(context),
                         Err(e) => Err(e.into_cache()),
                     },
                     Err(e) => Err(e.into_cache()),
                 };
+                if let Ok(context) = &v {
+                    self.sr_settings.record_usage(
+                        SchemaUsageKind::Encoded,
+                        context.id,
+                        Some(e.key()),
+                    );
+                }
+                if let Err(err) = &v {
+                    if err.is_throttled() {
+                        return Err(err.clone());
+                    }
+                }
                 e.insert(v).as_ref()
             }
         };
@@ -94,16 +203,98 @@ impl JsonEncoder {
 }
 
 #[derive(Debug)]
-struct EncodeContext {
+struct SchemaContext {
     id: u32,
     url: Url,
+    schema: Value,
+    rule_set: Option<RuleSet>,
+    metadata: Option<Metadata>,
+    /// The referenced schemas, in the order they need to be compiled, so the schema can be
+    /// compiled in another scope without fetching the references again.
+    references: Vec<(Url, Value)>,
+}
+
+impl SchemaContext {
+    /// Compiles the referenced schemas and the schema into the scope, when they are not yet part
+    /// of the scope.
+    fn compile(&self, scope: &mut Scope, validation: JsonValidation) -> Result<(), SRCError> {
+        for (url, def) in self.references.iter() {
+            if scope.resolve(url).is_some() {
+                continue;
+            }
+            if let Err(e) = scope.compile_with_id(url, validation.prepare(def.clone()), false) {
+                return Err(SRCError::non_retryable_with_cause(
+                    e,
+                    &format!("could not compile referenced schema {}", url),
+                ));
+            }
+        }
+        if scope.resolve(&self.url).is_some() {
+            return Ok(());
+        }
+        match scope.compile_with_id(&self.url, validation.prepare(self.schema.clone()), false) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(SRCError::non_retryable_with_cause(
+                e,
+                &format!("could not compile schema with id {}", self.id),
+            )),
+        }
+    }
+
+    fn apply_field_encryption<'v>(
+        &self,
+        encryptor: Option<&dyn FieldEncryptor>,
+        value: Cow<'v, Value>,
+        direction: Direction,
+    ) -> Result<Cow<'v, Value>, SRCError> {
+        apply_field_encryption(
+            encryptor,
+            self.rule_set.as_ref(),
+            self.metadata.as_ref(),
+            &self.schema,
+            value,
+            direction,
+        )
+    }
 }
 
+/// Cloning the decoder is cheap, and the clones share the cache. Each clone compiles the schemas
+/// it uses into its own scope.
 #[derive(Debug)]
 pub struct JsonDecoder {
     sr_settings: SrSettings,
-    cache: HashMap<u32, Result<Url, SRCError>, RandomState>,
+    cache: Arc<DashMap<u32, Result<Arc<SchemaContext>, SRCError>>>,
     scope: Scope,
+    validation: JsonValidation,
+    id_strategy: IdStrategy,
+    strictness: Strictness,
+    error_policy: DeserializationErrorPolicy,
+    field_encryptor: Option<Arc<dyn FieldEncryptor>>,
+    migration: Option<MigrationConfig>,
+    migrations: Arc<DashMap<u32, Arc<Migration>>>,
+    slow_path: SlowPathWarnings,
+    policy: Option<Arc<dyn SchemaPolicy>>,
+    cached_schemas: Arc<CachedSchemas>,
+}
+
+impl Clone for JsonDecoder {
+    fn clone(&self) -> Self {
+        JsonDecoder {
+            sr_settings: self.sr_settings.clone(),
+            cache: self.cache.clone(),
+            scope: Scope::new(),
+            validation: self.validation,
+            id_strategy: self.id_strategy.clone(),
+            strictness: self.strictness,
+            error_policy: self.error_policy.clone(),
+            field_encryptor: self.field_encryptor.clone(),
+            migration: self.migration.clone(),
+            migrations: self.migrations.clone(),
+            slow_path: self.slow_path,
+            policy: self.policy.clone(),
+            cached_schemas: self.cached_schemas.clone(),
+        }
+    }
 }
 
 impl JsonDecoder {
@@ -115,85 +306,336 @@ impl JsonDecoder {
     pub fn new(sr_settings: SrSettings) -> JsonDecoder {
         JsonDecoder {
             sr_settings,
-            cache: HashMap::new(),
+            cache: Arc::new(DashMap::new()),
             scope: Scope::new(),
+            validation: JsonValidation::None,
+            id_strategy: IdStrategy::default(),
+            strictness: Strictness::Lenient,
+            error_policy: DeserializationErrorPolicy::default(),
+            field_encryptor: None,
+            migration: None,
+            migrations: Arc::new(DashMap::new()),
+            slow_path: SlowPathWarnings::default(),
+            policy: None,
+            cached_schemas: Arc::new(CachedSchemas::new("json")),
         }
     }
+    /// Sets whether, and using which draft, decoded values are validated against the schema of the
+    /// id in the payload. By default they are not validated. When a value is not valid, the error
+    /// contains the violations, so the message can be put aside.
+    pub fn with_validation(mut self, validation: JsonValidation) -> JsonDecoder {
+        self.validation = validation;
+        self
+    }
+    /// Sets how the schema id is read, which needs to be the same as used by the encoder. With
+    /// the id in a header, use decode_with_headers.
+    pub fn with_id_strategy(mut self, id_strategy: IdStrategy) -> JsonDecoder {
+        self.id_strategy = id_strategy;
+        self
+    }
+    /// Sets how payloads that don't follow the wire format are handled. Payloads without the magic
+    /// byte and id always give an error with the [WireFormatError](crate::error::WireFormatError).
+    /// When strict, bytes left after decoding the json value give an error with it too.
+    pub fn with_strictness(mut self, strictness: Strictness) -> JsonDecoder {
+        self.strictness = strictness;
+        self
+    }
+    /// Sets what decode_or_skip does with payloads that can't be decoded.
+    pub fn with_error_policy(mut self, error_policy: DeserializationErrorPolicy) -> JsonDecoder {
+        self.error_policy = error_policy;
+        self
+    }
+    /// Sets the encryptor used to decrypt the properties tagged by an ENCRYPT rule of the schema.
+    pub fn with_field_encryptor(mut self, field_encryptor: Arc<dyn FieldEncryptor>) -> JsonDecoder {
+        self.field_encryptor = Some(field_encryptor);
+        self
+    }
+    /// Reads the values as the latest version of the subject with the metadata of the migration,
+    /// applying the migration rules of the versions in between when a value was written with
+    /// another version. Validation is done with the schema the value is read as.
+    pub fn with_migration(mut self, migration: MigrationConfig) -> JsonDecoder {
+        self.migration = Some(migration);
+        self
+    }
+    /// Sets which slow paths of decode are logged as a warning, like a decode taking longer than a
+    /// threshold, or a decode needing to fetch the schema.
+    pub fn with_slow_path_warnings(mut self, slow_path: SlowPathWarnings) -> JsonDecoder {
+        self.slow_path = slow_path;
+        self
+    }
+    /// Sets the policy restricting which schema's are fetched and decoded. Payloads with a schema
+    /// id that's not allowed give an error with a `PolicyViolation`, which is not cached, so a
+    /// change of the policy is applied directly.
+    pub fn with_policy(mut self, policy: Arc<dyn SchemaPolicy>) -> JsonDecoder {
+        self.policy = Some(policy);
+        self
+    }
+    /// Sets the options shared by all the decoders, replacing the id strategy, strictness, slow
+    /// path warnings and policy set before.
+    pub fn with_options(mut self, options: DecoderOptions) -> JsonDecoder {
+        self.id_strategy = options.id_strategy;
+        self.strictness = options.strictness;
+        self.slow_path = options.slow_path;
+        self.policy = options.policy;
+        self
+    }
     /// Remove al the errors from the cache, you might need to/want to run this when a recoverable
     /// error is met. Errors are also cashed to prevent trying to get schema's that either don't
     /// exist or can't be parsed.
     pub fn remove_errors_from_cache(&mut self) {
         self.cache.retain(|_, v| v.is_ok());
     }
+    /// Removes the migrations to the latest version of the subject of the migration from the cache,
+    /// so a new latest version with the metadata is picked up on the next decode. Long-lived
+    /// consumers can call this when they are signaled a new version was registered. The schema's
+    /// for the id's don't change, so they are kept.
+    pub fn invalidate_subject(&self, subject: &str) {
+        if matches!(&self.migration, Some(config) if config.subject == subject) {
+            self.migrations.clear();
+        }
+    }
+    /// Decodes the bytes, applying the error policy when they can't be decoded. None is returned
+    /// both for null bytes and for skipped bytes.
+    pub fn decode_or_skip(
+        &mut self,
+        bytes: Option<&[u8]>,
+    ) -> Result<Option<DecodeResult>, SRCError> {
+        let result = self.decode(bytes);
+        Ok(self.error_policy.apply(bytes, result)?.flatten())
+    }
+    /// Decodes both the key and the payload of a record, the same way as decode.
+    pub fn decode_message(
+        &mut self,
+        key: Option<&[u8]>,
+        payload: Option<&[u8]>,
+    ) -> Result<DecodedRecord<Option<DecodeResult>>, SRCError> {
+        Ok(DecodedRecord {
+            key: self.decode(key)?,
+            value: self.decode(payload)?,
+        })
+    }
     /// Reads the bytes to get the name, and gives back the data bytes.
     pub fn decode(&mut self, bytes: Option<&[u8]>) -> Result<Option<DecodeResult>, SRCError> {
-        match get_bytes_result(bytes) {
+        self.decode_with_headers(bytes, &[])
+    }
+    /// Decodes bytes, using the headers of the record to get the schema id when the id strategy
+    /// has the id in a header.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "decode", level = "debug", skip_all, err)
+    )]
+    pub fn decode_with_headers(
+        &mut self,
+        bytes: Option<&[u8]>,
+        headers: &[(&str, &[u8])],
+    ) -> Result<Option<DecodeResult>, SRCError> {
+        let result = match self.id_strategy.get_bytes_result(bytes, headers) {
             BytesResult::Null => Ok(None),
-            BytesResult::Valid(id, bytes) => Ok(Some(self.deserialize(id, &bytes)?)),
-            BytesResult::Invalid(i) => Err(SRCError::non_retryable_without_cause(&format!(
-                "Invalid bytes: {:?}",
-                i
-            ))),
+            BytesResult::Valid(id, bytes) => {
+                let start = decode_start(&self.slow_path);
+                let result = self.deserialize(id, &bytes);
+                warn_slow_decode(&self.slow_path, start, id, || None);
+                Ok(Some(result?))
+            }
+            BytesResult::Invalid(i) => Err(SRCError::from(to_wire_format_error(&i))),
+        };
+        if result.is_err() {
+            record_decode_error("json");
+        }
+        result
+    }
+    /// Decodes bytes into a value, together with the id of the schema, so messages can be routed
+    /// without another lookup.
+    pub fn decode_with_metadata(
+        &mut self,
+        bytes: Option<&[u8]>,
+    ) -> Result<Option<DecodeResultWithMetadata<Value>>, SRCError> {
+        match self.id_strategy.get_bytes_result(bytes, &[]) {
+            BytesResult::Null => Ok(None),
+            BytesResult::Valid(id, bytes) => Ok(Some(DecodeResultWithMetadata {
+                metadata: DecodeMetadata {
+                    schema_id: id,
+                    full_name: None,
+                },
+                value: self.deserialize(id, &bytes)?.value,
+            })),
+            BytesResult::Invalid(i) => Err(SRCError::from(to_wire_format_error(&i))),
+        }
+    }
+    /// Decodes bytes directly into any struct implementing Deserialize. Without validation, a field
+    /// encryptor or a migration the bytes are deserialized without a Value in between, and the
+    /// schema is not retrieved. When the value doesn't match the struct, the error contains the id
+    /// of the schema.
+    pub fn decode_as<T: DeserializeOwned>(
+        &mut self,
+        bytes: Option<&[u8]>,
+    ) -> Result<Option<T>, SRCError> {
+        match self.id_strategy.get_bytes_result(bytes, &[]) {
+            BytesResult::Null => Ok(None),
+            BytesResult::Valid(id, bytes) => {
+                let result = if self.needs_value() {
+                    serde_json::from_value(self.deserialize(id, &bytes)?.value)
+                } else {
+                    serde_json::from_slice(&bytes)
+                };
+                match result {
+                    Ok(v) => Ok(Some(v)),
+                    Err(e) => {
+                        check_remaining_bytes(trailing_json_bytes(&bytes), self.strictness)?;
+                        Err(SRCError::non_retryable_with_cause(
+                            e,
+                            &format!(
+                                "could not deserialize value with schema id {} into {}",
+                                id,
+                                type_name::<T>()
+                            ),
+                        ))
+                    }
+                }
+            }
+            BytesResult::Invalid(i) => Err(SRCError::from(to_wire_format_error(&i))),
         }
     }
     /// The actual deserialization trying to get the id from the bytes to retrieve the schema, and
     /// using a reader transforms the bytes to a value.
     fn deserialize(&mut self, id: u32, bytes: &[u8]) -> Result<DecodeResult, SRCError> {
-        let schema = self.schema(id)?;
-        match serde_json::from_slice(bytes) {
-            Ok(value) => Ok(DecodeResult { schema, value }),
-            Err(e) => Err(SRCError::non_retryable_with_cause(
-                e,
-                "could not create value from bytes",
-            )),
+        let validation = self.validation;
+        let encryptor = self.field_encryptor.clone();
+        let context = self.context(id)?;
+        let value = match serde_json::from_slice(bytes) {
+            Ok(v) => v,
+            Err(e) => {
+                check_remaining_bytes(trailing_json_bytes(bytes), self.strictness)?;
+                return Err(SRCError::non_retryable_with_cause(
+                    e,
+                    "could not create value from bytes",
+                ));
+            }
+        };
+        let value = context
+            .apply_field_encryption(encryptor.as_deref(), Cow::Owned(value), Direction::Read)?
+            .into_owned();
+        let (reader_id, value) = self.migrate(id, value)?;
+        let schema = self.schema(reader_id)?;
+        if validation.is_enabled() {
+            handle_validation(schema.validate(&value), &value)?;
         }
+        Ok(DecodeResult { schema, value })
+    }
+    /// Whether the bytes need to be decoded to a value first, before deserializing into a struct.
+    fn needs_value(&self) -> bool {
+        self.validation.is_enabled() || self.field_encryptor.is_some() || self.migration.is_some()
+    }
+    /// Gives the id of the schema to read the value as, with the value migrated to it when a
+    /// migration is set.
+    fn migrate(&mut self, id: u32, value: Value) -> Result<(u32, Value), SRCError> {
+        let config = match &self.migration {
+            Some(v) => v,
+            None => return Ok((id, value)),
+        };
+        let migration = match self.migrations.entry(id) {
+            Entry::Occupied(e) => e.get().clone(),
+            Entry::Vacant(e) => e
+                .insert(Arc::new(get_migration(&self.sr_settings, config, id)?))
+                .clone(),
+        };
+        let value = migrate(&*config.executor, &migration.rules, value)?;
+        Ok((migration.reader_id, value))
     }
     /// Gets the Context object, either from the cache, or from the schema registry and then putting
     /// it into the cache.
-    fn schema(&mut self, id: u32) -> Result<ScopedSchema, SRCError> {
-        let url = match self.cache.entry(id) {
-            Entry::Occupied(e) => &*e.into_mut(),
+    fn context(&mut self, id: u32) -> Result<Arc<SchemaContext>, SRCError> {
+        check_policy(self.policy.as_deref(), id)?;
+        match self.cache.entry(id) {
+            Entry::Occupied(e) => {
+                record_cache_lookup(id, true);
+                e.get().clone()
+            }
             Entry::Vacant(e) => {
-                let v = match get_schema_by_id_and_type(id, &self.sr_settings, SchemaType::Json) {
-                    Ok(r) => match set_scoped_schema(&mut self.scope, &self.sr_settings, &r) {
-                        Ok(schema) => Ok(schema),
+                record_cache_lookup(id, false);
+                warn_registry_fetch(&self.slow_path, id);
+                let policy = self.policy.as_deref();
+                let v = match get_allowed_schema(id, &self.sr_settings, SchemaType::Json, policy) {
+                    Ok(r) => match set_scoped_schema(
+                        &mut self.scope,
+                        &self.sr_settings,
+                        &r,
+                        self.validation,
+                    ) {
+                        Ok(context) => Ok(Arc::new(context)),
                         Err(e) => Err(e.into_cache()),
                     },
                     Err(e) => Err(e.into_cache()),
                 };
-                &*e.insert(v)
+                if v.is_ok() {
+                    self.cached_schemas.added();
+                    self.sr_settings
+                        .record_usage(SchemaUsageKind::Decoded, id, None);
+                }
+                if let Err(err) = &v {
+                    if err.is_throttled() {
+                        return Err(err.clone());
+                    }
+                }
+                e.insert(v).clone()
             }
-        };
-        match url {
-            Ok(id) => match self.scope.resolve(id) {
-                Some(schema) => Ok(schema),
-                None => Err(SRCError::non_retryable_without_cause(
-                    "could not get schema from scope",
-                )),
-            },
-            Err(e) => Err(e.clone()),
+        }
+    }
+
+    /// Gives the compiled schema, compiling it into the scope of this decoder first when the
+    /// context was added to the cache by a clone.
+    fn schema(&mut self, id: u32) -> Result<ScopedSchema, SRCError> {
+        let context = self.context(id)?;
+        context.compile(&mut self.scope, self.validation)?;
+        match self.scope.resolve(&context.url) {
+            Some(schema) => Ok(schema),
+            None => Err(SRCError::non_retryable_without_cause(
+                "could not get schema from scope",
+            )),
         }
     }
 }
 
-fn add_refs_to_scope(
-    scope: &mut Scope,
+/// Generates the json schema for the type using schemars, and registers it for the subject. When
+/// the same schema was already registered, the existing id is used. The returned encoder is bound
+/// to the id of the schema.
+#[cfg(feature = "json_schemars")]
+pub fn register_schemars<T: schemars::JsonSchema + serde::Serialize>(
     sr_settings: &SrSettings,
+    subject: &str,
+) -> Result<BoundJsonEncoder<T>, SRCError> {
+    let schema = schemars_schema::<T>()?;
+    let registered = post_schema(sr_settings, String::from(subject), schema)?;
+    Ok(BoundJsonEncoder::new(registered.id))
+}
+
+/// Generates the json schema for the type using schemars, and verifies it's already registered for
+/// the subject, without registering it. The returned encoder is bound to the id of the schema.
+#[cfg(feature = "json_schemars")]
+pub fn verify_schemars<T: schemars::JsonSchema + serde::Serialize>(
+    sr_settings: &SrSettings,
+    subject: &str,
+) -> Result<BoundJsonEncoder<T>, SRCError> {
+    let schema = schemars_schema::<T>()?;
+    let registered = lookup_schema(sr_settings, subject, &SchemaType::Json, &schema.schema)?;
+    Ok(BoundJsonEncoder::new(registered.id))
+}
+
+/// Fetches the referenced schemas, with their own references before them.
+fn fetch_references(
+    sr_settings: &SrSettings,
+    base: &Url,
     refs: &[RegisteredReference],
+    references: &mut Vec<(Url, Value)>,
 ) -> Result<(), SRCError> {
     for rr in refs.iter() {
-        let rs = get_referenced_schema(sr_settings, rr)?;
-        let id = match Url::from_str(&rr.name) {
-            Ok(v) => v,
-            Err(e) => return Err(SRCError::non_retryable_with_cause(e, &format!("reference schema with subject {} and version {} has invalid id {}, it has to be a fully qualified url", rr.subject, rr.version, rr.name)))
-        };
-        // if it's already part of the scope, it's assumed any references are also already part of the scope.
-        if scope.resolve(&id).is_some() {
-            return Ok(());
+        let id = reference_url(base, rr)?;
+        if references.iter().any(|(url, _)| url == &id) {
+            continue;
         }
-        add_refs_to_scope(scope, sr_settings, &rs.references)?;
-        let def: Value = to_value(&rs.schema)?;
-        scope.compile_with_id(&id, def, false).unwrap();
+        let rs = get_referenced_schema(sr_settings, rr)?;
+        fetch_references(sr_settings, &id, &rs.references, references)?;
+        references.push((id, to_value(&rs.schema)?));
     }
     Ok(())
 }
@@ -202,8 +644,8 @@ fn set_scoped_schema(
     scope: &mut Scope,
     sr_settings: &SrSettings,
     registered_schema: &RegisteredSchema,
-) -> Result<Url, SRCError> {
-    add_refs_to_scope(scope, sr_settings, &registered_schema.references)?;
+    validation: JsonValidation,
+) -> Result<SchemaContext, SRCError> {
     let def: Value = match serde_json::from_str(&registered_schema.schema) {
         Ok(v) => v,
         Err(e) => {
@@ -220,16 +662,23 @@ fn set_scoped_schema(
         Some(url) => url,
         None => fetch_fallback(sr_settings.url(), registered_schema.id),
     };
-    match scope.compile_with_id(&id, def, false) {
-        Ok(_) => (),
-        Err(e) => {
-            return Err(SRCError::non_retryable_with_cause(
-                e,
-                &format!("could not compile schema with id {}", registered_schema.id),
-            ))
-        }
+    let mut references = Vec::new();
+    fetch_references(
+        &sr_settings.counting_references(),
+        &id,
+        &registered_schema.references,
+        &mut references,
+    )?;
+    let context = SchemaContext {
+        id: registered_schema.id,
+        url: id,
+        schema: def,
+        rule_set: registered_schema.rule_set.clone(),
+        metadata: registered_schema.metadata.clone(),
+        references,
     };
-    Ok(id)
+    context.compile(scope, validation)?;
+    Ok(context)
 }
 
 #[derive(Debug)]
@@ -242,12 +691,13 @@ pub struct DecodeResult<'a> {
 mod tests {
     use std::fs::{read_to_string, File};
 
-    use serde_json::{from_str, to_string_pretty, Value};
+    use serde_json::{from_str, json, to_string_pretty, Value};
     use valico::json_dsl;
 
-    use crate::blocking::json::{JsonDecoder, JsonEncoder};
+    use crate::blocking::json::{JsonDecoder, JsonEncoder, JsonValidation};
     use crate::blocking::schema_registry::SrSettings;
-    use crate::schema_registry_common::{get_payload, SubjectNameStrategy};
+    use crate::error::WireFormatError;
+    use crate::schema_registry_common::{get_payload, Strictness, SubjectNameStrategy};
     use test_utils::{
         get_json_body, get_json_body_with_reference, json_get_result_references,
         json_incorrect_bytes, json_result_java_bytes, json_result_schema,
@@ -290,7 +740,7 @@ mod tests {
             serde_json::from_reader(File::open("tests/schema/result-example.json").unwrap())
                 .unwrap();
 
-        let encoded_data = encoder.encode(&result_example, &strategy).unwrap();
+        let encoded_data = encoder.encode(&result_example, strategy).unwrap();
 
         assert_eq!(encoded_data, json_result_java_bytes())
     }
@@ -317,6 +767,63 @@ mod tests {
         assert_eq!(encoded_data, json_result_java_bytes())
     }
 
+    #[test]
+    fn test_encode_many() {
+        let mut server = mockito::Server::new();
+        let m = server
+            .mock("GET", "/subjects/testresult-value/versions/latest")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_json_body(json_result_schema(), 10))
+            .expect(1)
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let mut encoder = JsonEncoder::new(sr_settings);
+        let result_example: Value =
+            serde_json::from_reader(File::open("tests/schema/result-example.json").unwrap())
+                .unwrap();
+
+        let encoded_data = encoder
+            .encode_many(
+                &[result_example.clone(), result_example],
+                ("testresult", false),
+            )
+            .unwrap();
+
+        assert_eq!(
+            encoded_data,
+            vec![json_result_java_bytes(), json_result_java_bytes()]
+        );
+        m.assert();
+    }
+
+    #[test]
+    fn test_encode_many_stops_at_first_error() {
+        let mut server = mockito::Server::new();
+        let _m = server
+            .mock("GET", "/subjects/testresult-value/versions/latest")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_json_body(json_result_schema(), 10))
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let mut encoder = JsonEncoder::new(sr_settings);
+        let values = [
+            json!({"up": "STRING", "down": "string"}),
+            json!({"up": "STRING", "down": 3}),
+            json!({"up": 3, "down": "string"}),
+        ];
+
+        let error = encoder
+            .encode_many(&values, ("testresult", false))
+            .unwrap_err();
+
+        assert_eq!(error.violations().len(), 1);
+        assert_eq!(error.violations()[0].path, "/down");
+    }
+
     #[test]
     fn test_encode_clean_cache() {
         let mut server = mockito::Server::new();
@@ -347,6 +854,27 @@ mod tests {
         assert!(encoded_data.is_ok());
     }
 
+    #[test]
+    fn test_decoder_validation() {
+        let mut server = mockito::Server::new();
+        let _m = server
+            .mock("GET", "/schemas/ids/7?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_json_body(json_result_schema(), 7))
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let mut decoder = JsonDecoder::new(sr_settings).with_validation(JsonValidation::Detect);
+        let valid = get_payload(7, br#"{"up":"STRING","down":"string"}"#.to_vec());
+        let invalid = get_payload(7, br#"{"up":"STRING"}"#.to_vec());
+
+        assert!(decoder.decode(Some(&valid)).unwrap().is_some());
+        let error = decoder.decode(Some(&invalid)).unwrap_err();
+        assert_eq!(error.violations().len(), 1);
+        assert_eq!(error.violations()[0].code, "required");
+    }
+
     #[test]
     fn test_decoder_default() {
         let result_value: String = read_to_string("tests/schema/result-example.json")
@@ -397,6 +925,32 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_decoder_clone_shares_cache() {
+        let result_value: String = read_to_string("tests/schema/result-example.json")
+            .unwrap()
+            .parse()
+            .unwrap();
+        let bytes = get_payload(7, result_value.into_bytes());
+        let mut server = mockito::Server::new();
+        let _m = server
+            .mock("GET", "/schemas/ids/7?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_json_body(json_result_schema(), 7))
+            .expect(1)
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let mut decoder = JsonDecoder::new(sr_settings);
+        decoder.decode(Some(&*bytes)).unwrap();
+        let mut clone = decoder.clone();
+        let message = clone.decode(Some(&*bytes)).unwrap().unwrap();
+
+        assert!(message.schema.validate(&message.value).is_strictly_valid());
+        _m.assert();
+    }
+
     #[test]
     fn test_decoder_clean_cache() {
         let mut server = mockito::Server::new();
@@ -670,7 +1224,7 @@ mod tests {
         let sr_settings = SrSettings::new(String::from("http://127.0.0.1:1234"));
         let encoder = JsonEncoder::new(sr_settings);
         assert!(
-            format!("{:?}", encoder).starts_with("JsonEncoder { sr_settings: SrSettings { urls: [\"http://127.0.0.1:1234\"], client: Client, authorization: None }, cache: {}, scope: Scope {")
+            format!("{:?}", encoder).starts_with("JsonEncoder { sr_settings: SrSettings { urls: [\"http://127.0.0.1:1234\"], client: Client, authorization: None, dialect: Confluent, fetch_deleted: true, normalize: false, headers: {}, usage_listener: None, fallback: None, limits: SchemaLimits { max_schema_bytes: None, max_references: None, max_total_references: None, max_depth: None }, references_fetched: None }, cache: {}, scope: Scope {")
         )
     }
 
@@ -679,7 +1233,7 @@ mod tests {
         let sr_settings = SrSettings::new(String::from("http://127.0.0.1:1234"));
         let decoder = JsonDecoder::new(sr_settings);
         assert!(
-                   format!("{:?}", decoder).starts_with("JsonDecoder { sr_settings: SrSettings { urls: [\"http://127.0.0.1:1234\"], client: Client, authorization: None }, cache: {}, scope: Scope {")
+                   format!("{:?}", decoder).starts_with("JsonDecoder { sr_settings: SrSettings { urls: [\"http://127.0.0.1:1234\"], client: Client, authorization: None, dialect: Confluent, fetch_deleted: true, normalize: false, headers: {}, usage_listener: None, fallback: None, limits: SchemaLimits { max_schema_bytes: None, max_references: None, max_total_references: None, max_depth: None }, references_fetched: None }, cache: {}, scope: Scope {")
         )
     }
 
@@ -737,6 +1291,31 @@ mod tests {
         let sr_settings = SrSettings::new(String::from("http://127.0.0.1:1234"));
         let mut decoder = JsonDecoder::new(sr_settings);
         let result = decoder.decode(Some(&[1, 0])).unwrap_err();
-        assert_eq!(String::from("Invalid bytes: [1, 0]"), result.error)
+        assert_eq!(
+            result.wire_format(),
+            Some(&WireFormatError::InvalidMagicByte(1))
+        );
+        assert_eq!(
+            String::from("Invalid magic byte 1, expected 0"),
+            result.error
+        )
+    }
+
+    #[test]
+    fn decode_strict_trailing_bytes() {
+        let sr_settings = SrSettings::new(String::from("http://127.0.0.1:1234"));
+        let mut lenient = JsonDecoder::new(sr_settings.clone());
+        let mut strict = JsonDecoder::new(sr_settings).with_strictness(Strictness::Strict);
+        let bytes: &[u8] = b"\0\0\0\0\x07{} x";
+
+        let error = lenient.decode_as::<Value>(Some(bytes)).unwrap_err();
+        assert_eq!(error.wire_format(), None);
+        let error = strict.decode_as::<Value>(Some(bytes)).unwrap_err();
+        assert_eq!(
+            error.wire_format(),
+            Some(&WireFormatError::TrailingBytes(2))
+        );
+        let value = strict.decode_as::<Value>(Some(&bytes[..7])).unwrap();
+        assert_eq!(value, Some(json!({})));
     }
 }
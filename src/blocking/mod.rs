@@ -1,5 +1,7 @@
 #[cfg(feature = "avro")]
 pub mod avro;
+pub mod codec;
+mod http;
 #[cfg(feature = "json")]
 pub mod json;
 #[cfg(feature = "proto_decoder")]
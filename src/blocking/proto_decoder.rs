@@ -4,20 +4,46 @@ use dashmap::mapref::entry::Entry;
 use dashmap::DashMap;
 use std::sync::Arc;
 
-use crate::blocking::schema_registry::{
-    get_referenced_schema, get_schema_by_id_and_type, SrSettings,
-};
+use crate::blocking::schema_registry::{get_allowed_schema, get_referenced_schema, SrSettings};
 use crate::error::SRCError;
+use crate::field_encryption::FieldEncryptor;
 use crate::proto_common_types::add_common_files;
-use crate::proto_resolver::{resolve_name, to_index_and_data, MessageResolver};
-use crate::schema_registry_common::{get_bytes_result, BytesResult, RegisteredSchema, SchemaType};
+#[cfg(feature = "proto_descriptor")]
+use crate::proto_decoder_common::to_local_decode_context;
+use crate::proto_decoder_common::{
+    check_trailing_bytes, decode_message, decode_with_context, from_proto_value,
+    invalid_bytes_error, proto_value_to_json, to_decode_context,
+};
+pub use crate::proto_decoder_common::{DecodeContext, DecodeResultWithContext, MessageSelector};
+use crate::proto_resolver::find_imports;
+#[cfg(feature = "prost_decoder")]
+use crate::proto_resolver::to_prost;
+use crate::schema_registry_common::{
+    check_policy, BytesResult, DecodeMetadata, DecodeResultWithMetadata, DecodedRecord,
+    DecoderOptions, IdStrategy, RegisteredSchema, SchemaPolicy, SchemaType, SchemaUsageKind,
+    SlowPathWarnings, Strictness,
+};
+use crate::telemetry::{
+    decode_start, record_cache_lookup, record_decode_error, warn_registry_fetch, warn_slow_decode,
+    CachedSchemas,
+};
 use protofish::context::Context;
 use protofish::decode::{MessageValue, Value};
+use serde::de::DeserializeOwned;
 
-#[derive(Debug)]
+/// Cloning the decoder is cheap, and the clones share the cache.
+#[derive(Debug, Clone)]
 pub struct ProtoDecoder {
     sr_settings: SrSettings,
-    cache: DashMap<u32, Result<Arc<DecodeContext>, SRCError>>,
+    cache: Arc<DashMap<u32, Result<Arc<DecodeContext>, SRCError>>>,
+    fallback: Arc<DashMap<u32, Arc<DecodeContext>>>,
+    message_selector: Option<MessageSelector>,
+    strictness: Strictness,
+    id_strategy: IdStrategy,
+    field_encryptor: Option<Arc<dyn FieldEncryptor>>,
+    slow_path: SlowPathWarnings,
+    policy: Option<Arc<dyn SchemaPolicy>>,
+    cached_schemas: Arc<CachedSchemas>,
 }
 
 impl ProtoDecoder {
@@ -29,8 +55,98 @@ impl ProtoDecoder {
     pub fn new(sr_settings: SrSettings) -> ProtoDecoder {
         ProtoDecoder {
             sr_settings,
-            cache: DashMap::new(),
+            cache: Arc::new(DashMap::new()),
+            fallback: Arc::new(DashMap::new()),
+            message_selector: None,
+            strictness: Strictness::Lenient,
+            id_strategy: IdStrategy::default(),
+            field_encryptor: None,
+            slow_path: SlowPathWarnings::default(),
+            policy: None,
+            cached_schemas: Arc::new(CachedSchemas::new("protobuf")),
+        }
+    }
+    /// Sets how payloads that don't follow the wire format are handled. When lenient, decode gives
+    /// back the payload as bytes, and the other functions give an error. When strict, all functions
+    /// give an error with the [WireFormatError](crate::error::WireFormatError), also when bytes
+    /// are left after decoding the message.
+    pub fn with_strictness(mut self, strictness: Strictness) -> Self {
+        self.strictness = strictness;
+        self
+    }
+    /// Sets how the schema id is read, which needs to be the same as used by the encoder. With
+    /// the id in a header, use decode_with_headers, the other functions need the id in the payload.
+    pub fn with_id_strategy(mut self, id_strategy: IdStrategy) -> ProtoDecoder {
+        self.id_strategy = id_strategy;
+        self
+    }
+    /// Sets the encryptor used to decrypt the fields tagged by an ENCRYPT rule of the schema. The
+    /// fields are tagged in the metadata of the schema, by the full name of the message followed
+    /// by the name of the field.
+    pub fn with_field_encryptor(
+        mut self,
+        field_encryptor: Arc<dyn FieldEncryptor>,
+    ) -> ProtoDecoder {
+        self.field_encryptor = Some(field_encryptor);
+        self
+    }
+    /// Sets which slow paths of decode are logged as a warning, like a decode taking longer than a
+    /// threshold, or a decode needing to fetch the schema.
+    pub fn with_slow_path_warnings(mut self, slow_path: SlowPathWarnings) -> ProtoDecoder {
+        self.slow_path = slow_path;
+        self
+    }
+    /// Sets the policy restricting which schema's are fetched and decoded. Payloads with a schema
+    /// id that's not allowed give an error with a `PolicyViolation`, which is not cached, so a
+    /// change of the policy is applied directly.
+    pub fn with_policy(mut self, policy: Arc<dyn SchemaPolicy>) -> ProtoDecoder {
+        self.policy = Some(policy);
+        self
+    }
+    /// Sets the options shared by all the decoders, replacing the id strategy, strictness, slow
+    /// path warnings and policy set before.
+    pub fn with_options(mut self, options: DecoderOptions) -> ProtoDecoder {
+        self.id_strategy = options.id_strategy;
+        self.strictness = options.strictness;
+        self.slow_path = options.slow_path;
+        self.policy = options.policy;
+        self
+    }
+    /// Sets the message selector, used to decode payloads without the message index. When the
+    /// selector gives a full name for the schema of the payload, the payload is expected to not
+    /// have the message index, and is decoded as that message.
+    pub fn with_message_selector(mut self, message_selector: MessageSelector) -> ProtoDecoder {
+        self.message_selector = Some(message_selector);
+        self
+    }
+    /// Creates a new decoder which uses the message selector to decode payloads without the message
+    /// index, the same as using new with with_message_selector.
+    pub fn new_with_message_selector(
+        sr_settings: SrSettings,
+        message_selector: MessageSelector,
+    ) -> ProtoDecoder {
+        ProtoDecoder::new(sr_settings).with_message_selector(message_selector)
+    }
+    /// Uses the schema from the serialized FileDescriptorSet for the id, instead of getting it from
+    /// the schema registry, so it's possible to decode without access to the schema registry. The
+    /// last file of the set is used as the schema, and should be the same as the registered one,
+    /// the other files are used as the imports.
+    #[cfg(feature = "proto_descriptor")]
+    pub fn add_schema_override(&self, id: u32, descriptor_set: &[u8]) -> Result<(), SRCError> {
+        let context = Arc::new(to_local_decode_context(id, descriptor_set)?);
+        if !matches!(self.cache.insert(id, Ok(context)), Some(Ok(_))) {
+            self.cached_schemas.added();
         }
+        Ok(())
+    }
+    /// Like add_schema_override, but the schema registry is tried first, and the schema from the
+    /// FileDescriptorSet is only used when getting the schema from the schema registry fails.
+    #[cfg(feature = "proto_descriptor")]
+    pub fn add_schema_fallback(&self, id: u32, descriptor_set: &[u8]) -> Result<(), SRCError> {
+        let context = Arc::new(to_local_decode_context(id, descriptor_set)?);
+        self.fallback.insert(id, context);
+        self.cache.remove_if(&id, |_, v| v.is_err());
+        Ok(())
     }
     /// Remove al the errors from the cache, you might need to/want to run this when a recoverable
     /// error is met. Errors are also cashed to prevent trying to get schema's that either don't
@@ -38,147 +154,343 @@ impl ProtoDecoder {
     pub fn remove_errors_from_cache(&self) {
         self.cache.retain(|_, v| v.is_ok());
     }
+    /// Decodes both the key and the payload of a record, the same way as decode.
+    pub fn decode_message(
+        &self,
+        key: Option<&[u8]>,
+        payload: Option<&[u8]>,
+    ) -> Result<DecodedRecord<Value>, SRCError> {
+        Ok(DecodedRecord {
+            key: self.decode(key)?,
+            value: self.decode(payload)?,
+        })
+    }
     /// Decodes bytes into a value.
     /// The choice to use Option<&[u8]> as type us made so it plays nice with the BorrowedMessage
     /// struct from rdkafka, for example if we have m: &'a BorrowedMessage and decoder: &'a
     /// Decoder we can use decoder.decode(m.payload()) to decode the payload or
     /// decoder.decode(m.key()) to get the decoded key.
     pub fn decode(&self, bytes: Option<&[u8]>) -> Result<Value, SRCError> {
-        match get_bytes_result(bytes) {
+        self.decode_with_headers(bytes, &[])
+    }
+    /// Decodes bytes into a value, using the headers of the record to get the schema id when the
+    /// id strategy has the id in a header.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "decode", level = "debug", skip_all, err)
+    )]
+    pub fn decode_with_headers(
+        &self,
+        bytes: Option<&[u8]>,
+        headers: &[(&str, &[u8])],
+    ) -> Result<Value, SRCError> {
+        let result = match self.id_strategy.get_bytes_result(bytes, headers) {
             BytesResult::Null => Ok(Value::Bytes(Bytes::new())),
-            BytesResult::Valid(id, bytes) => {
-                Ok(Value::Message(Box::from(self.deserialize(id, &bytes)?)))
-            }
-            BytesResult::Invalid(i) => Ok(Value::Bytes(Bytes::from(i))),
+            BytesResult::Valid(id, bytes) => self
+                .deserialize(id, bytes)
+                .map(|v| Value::Message(Box::from(v))),
+            BytesResult::Invalid(i) => match self.strictness {
+                Strictness::Lenient => Ok(Value::Bytes(Bytes::from(i))),
+                Strictness::Strict => Err(invalid_bytes_error(&i, self.strictness)),
+            },
+        };
+        if result.is_err() {
+            record_decode_error("protobuf");
         }
+        result
     }
     /// The actual deserialization trying to get the id from the bytes to retrieve the schema, and
     /// using a reader transforms the bytes to a value.
-    fn deserialize(&self, id: u32, bytes: &[u8]) -> Result<MessageValue, SRCError> {
-        match self.context(id) {
-            Ok(s) => {
-                let (index, data) = to_index_and_data(bytes);
-                let full_name = resolve_name(&s.resolver, &index)?;
-                let message_info = s.context.get_message(&full_name).unwrap();
-                Ok(message_info.decode(&data, &s.context))
-            }
-            Err(e) => Err(e),
-        }
+    fn deserialize(&self, id: u32, bytes: Vec<u8>) -> Result<MessageValue, SRCError> {
+        Ok(self.deserialize_with_context(id, Bytes::from(bytes))?.value)
     }
     /// Decodes bytes into a decode result.
     /// The choice to use Option<&[u8]> as type us made so it plays nice with the BorrowedMessage
     /// struct from rdkafka, for example if we have m: &'a BorrowedMessage and decoder: &'a
     /// Decoder we can use decoder.decode(m.payload()) to decode the payload or
     /// decoder.decode(m.key()) to get the decoded key.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "decode", level = "debug", skip_all, err)
+    )]
     pub fn decode_with_context(
         &self,
         bytes: Option<&[u8]>,
     ) -> Result<Option<DecodeResultWithContext>, SRCError> {
-        match get_bytes_result(bytes) {
+        let result = match self.id_strategy.get_bytes_result(bytes, &[]) {
             BytesResult::Null => Ok(None),
-            BytesResult::Valid(id, bytes) => match self.deserialize_with_context(id, &bytes) {
-                Ok(v) => Ok(Some(v)),
-                Err(e) => Err(e),
+            BytesResult::Valid(id, bytes) => self
+                .deserialize_with_context(id, Bytes::from(bytes))
+                .map(Some),
+            BytesResult::Invalid(i) => Err(invalid_bytes_error(&i, self.strictness)),
+        };
+        if result.is_err() {
+            record_decode_error("protobuf");
+        }
+        result
+    }
+    /// Same as decode_with_context, but the data bytes of the result share the memory of the
+    /// supplied bytes, instead of being copied.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "decode", level = "debug", skip_all, err)
+    )]
+    pub fn decode_bytes(
+        &self,
+        bytes: Option<Bytes>,
+    ) -> Result<Option<DecodeResultWithContext>, SRCError> {
+        let result = match bytes {
+            None => Ok(None),
+            Some(b) => match self.id_strategy.get_id_and_offset(&b, &[]) {
+                Some((id, offset)) => self
+                    .deserialize_with_context(id, b.slice(offset..))
+                    .map(Some),
+                None => Err(invalid_bytes_error(&b, self.strictness)),
             },
-            BytesResult::Invalid(_) => {
-                Err(SRCError::new("no protobuf compatible bytes", None, false))
-            }
+        };
+        if result.is_err() {
+            record_decode_error("protobuf");
         }
+        result
+    }
+    /// Decodes multiple payloads, for example all the records from one poll. Each schema is only
+    /// fetched once, the results have the same order as the payloads.
+    pub fn decode_batch(
+        &self,
+        payloads: &[Option<&[u8]>],
+    ) -> Vec<Result<Option<DecodeResultWithContext>, SRCError>> {
+        payloads
+            .iter()
+            .map(|p| self.decode_with_context(*p))
+            .collect()
+    }
+    /// Decodes bytes into a value, together with the id of the schema and the full name of the
+    /// message, so messages can be routed by type without another lookup.
+    pub fn decode_with_metadata(
+        &self,
+        bytes: Option<&[u8]>,
+    ) -> Result<Option<DecodeResultWithMetadata<MessageValue>>, SRCError> {
+        Ok(self
+            .decode_with_context(bytes)?
+            .map(|r| DecodeResultWithMetadata {
+                metadata: DecodeMetadata {
+                    schema_id: r.context.registered_schema.id,
+                    full_name: Some(String::from(&*r.full_name)),
+                },
+                value: r.value,
+            }))
     }
     /// The actual deserialization trying to get the id from the bytes to retrieve the schema, and
     /// using a reader transforms the bytes to a value.
     fn deserialize_with_context(
         &self,
         id: u32,
-        bytes: &[u8],
+        bytes: Bytes,
     ) -> Result<DecodeResultWithContext, SRCError> {
-        match self.context(id) {
-            Ok(s) => {
-                let (index, data_bytes) = to_index_and_data(bytes);
-                let full_name = resolve_name(&s.resolver, &index)?;
-                let message_info = s.context.get_message(&full_name).unwrap();
-                let value = message_info.decode(&data_bytes, &s.context);
-                Ok(DecodeResultWithContext {
-                    value,
-                    context: s.clone(),
-                    full_name,
-                    data_bytes,
-                })
-            }
-            Err(e) => Err(e),
+        let start = decode_start(&self.slow_path);
+        let context = self.context(id)?;
+        let result = decode_with_context(
+            context.clone(),
+            bytes,
+            self.message_selector.as_ref(),
+            self.field_encryptor.as_deref(),
+        )
+        .and_then(|result| check_trailing_bytes(result, self.strictness));
+        warn_slow_decode(&self.slow_path, start, id, || {
+            context.registered_schema.subject.clone()
+        });
+        result
+    }
+    /// Decodes a payload without the message index, as the message with the full name. This can be
+    /// used for payloads produced by non Confluent clients, for example by keeping the full name of
+    /// the message per topic.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "decode", level = "debug", skip_all, err)
+    )]
+    pub fn decode_with_message_name(
+        &self,
+        bytes: Option<&[u8]>,
+        full_name: &str,
+    ) -> Result<Option<DecodeResultWithContext>, SRCError> {
+        let result = match self.id_strategy.get_bytes_result(bytes, &[]) {
+            BytesResult::Null => Ok(None),
+            BytesResult::Valid(id, bytes) => self
+                .deserialize_message(id, full_name, Bytes::from(bytes))
+                .map(Some),
+            BytesResult::Invalid(i) => Err(invalid_bytes_error(&i, self.strictness)),
+        };
+        if result.is_err() {
+            record_decode_error("protobuf");
+        }
+        result
+    }
+    /// Decodes the bytes as the message with the full name, without reading the message index.
+    fn deserialize_message(
+        &self,
+        id: u32,
+        full_name: &str,
+        bytes: Bytes,
+    ) -> Result<DecodeResultWithContext, SRCError> {
+        let start = decode_start(&self.slow_path);
+        let context = self.context(id)?;
+        let result = decode_message(
+            context.clone(),
+            Arc::new(String::from(full_name)),
+            bytes,
+            self.field_encryptor.as_deref(),
+        )
+        .and_then(|result| check_trailing_bytes(result, self.strictness));
+        warn_slow_decode(&self.slow_path, start, id, || {
+            context.registered_schema.subject.clone()
+        });
+        result
+    }
+    /// Decodes the bytes into the prost struct, with the message index checked against the name of
+    /// the struct.
+    #[cfg(feature = "prost_decoder")]
+    fn deserialize_prost<T: prost::Message + prost::Name + Default>(
+        &self,
+        id: u32,
+        bytes: &[u8],
+    ) -> Result<T, SRCError> {
+        let start = decode_start(&self.slow_path);
+        let context = self.context(id)?;
+        let result = to_prost(&context.resolver, bytes);
+        warn_slow_decode(&self.slow_path, start, id, || {
+            context.registered_schema.subject.clone()
+        });
+        result
+    }
+    /// Decodes bytes into a json value, using the names from the schema for the fields and enums.
+    /// Well known types, like Timestamp, are transformed like the Java JsonFormat does.
+    pub fn decode_json(&self, bytes: Option<&[u8]>) -> Result<Option<serde_json::Value>, SRCError> {
+        match self.decode_with_context(bytes)? {
+            None => Ok(None),
+            Some(r) => Ok(Some(proto_value_to_json(&r.value, &r.context.context))),
+        }
+    }
+    /// Decodes bytes into any struct implementing Deserialize. The names of the fields in the proto
+    /// schema are used, and enums are deserialized from the name of the value.
+    pub fn decode_as<T: DeserializeOwned>(
+        &self,
+        bytes: Option<&[u8]>,
+    ) -> Result<Option<T>, SRCError> {
+        match self.decode_with_context(bytes)? {
+            None => Ok(None),
+            Some(r) => Ok(Some(from_proto_value(&r.value, &r.context.context)?)),
+        }
+    }
+    /// Decodes bytes directly into a struct generated by prost. The message referenced in the bytes
+    /// is checked against the full name of the struct, so bytes from another message will give an
+    /// error instead of a struct with wrong values.
+    #[cfg(feature = "prost_decoder")]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "decode", level = "debug", skip_all, err)
+    )]
+    pub fn decode_prost<T: prost::Message + prost::Name + Default>(
+        &self,
+        bytes: Option<&[u8]>,
+    ) -> Result<Option<T>, SRCError> {
+        let result = match self.id_strategy.get_bytes_result(bytes, &[]) {
+            BytesResult::Null => Ok(None),
+            BytesResult::Valid(id, bytes) => self.deserialize_prost(id, &bytes).map(Some),
+            BytesResult::Invalid(i) => Err(invalid_bytes_error(&i, self.strictness)),
+        };
+        if result.is_err() {
+            record_decode_error("protobuf");
         }
+        result
     }
     /// Gets the Context object, either from the cache, or from the schema registry and then putting
     /// it into the cache.
     fn context(&self, id: u32) -> Result<Arc<DecodeContext>, SRCError> {
+        check_policy(self.policy.as_deref(), id)?;
         match self.cache.entry(id) {
-            Entry::Occupied(e) => e.get().clone(),
+            Entry::Occupied(e) => {
+                record_cache_lookup(id, true);
+                e.get().clone()
+            }
             Entry::Vacant(e) => {
-                let v = match get_schema_by_id_and_type(id, &self.sr_settings, SchemaType::Protobuf)
+                record_cache_lookup(id, false);
+                warn_registry_fetch(&self.slow_path, id);
+                let (sr_settings, policy) = (&self.sr_settings, self.policy.as_deref());
+                let v = match get_allowed_schema(id, sr_settings, SchemaType::Protobuf, policy)
+                    .and_then(|v| to_resolve_context(sr_settings, v))
                 {
-                    Ok(v) => to_resolve_context(&self.sr_settings, v),
-                    Err(e) => Err(e.into_cache()),
+                    Ok(v) => Ok(v),
+                    Err(e) => match self.fallback.get(&id) {
+                        Some(context) if e.policy_violation().is_none() => {
+                            Ok(context.value().clone())
+                        }
+                        _ => Err(e.into_cache()),
+                    },
                 };
+                if v.is_ok() {
+                    self.cached_schemas.added();
+                    self.sr_settings
+                        .record_usage(SchemaUsageKind::Decoded, id, None);
+                }
+                if matches!(&v, Err(err) if err.is_throttled()) {
+                    return v;
+                }
                 e.insert(v).value().clone()
             }
         }
     }
 }
 
-#[derive(Debug)]
-pub struct DecodeResultWithContext {
-    pub value: MessageValue,
-    pub context: Arc<DecodeContext>,
-    pub full_name: Arc<String>,
-    pub data_bytes: Vec<u8>,
-}
-
 fn add_files(
     sr_settings: &SrSettings,
-    registered_schema: RegisteredSchema,
+    registered_schema: &RegisteredSchema,
     files: &mut HashSet<String>,
 ) -> Result<(), SRCError> {
-    for r in registered_schema.references {
-        let child_schema = get_referenced_schema(sr_settings, &r)?;
-        add_files(sr_settings, child_schema, files)?;
+    for r in &registered_schema.references {
+        let child_schema = get_referenced_schema(sr_settings, r)?;
+        add_files(sr_settings, &child_schema, files)?;
     }
-    files.insert(registered_schema.schema);
+    add_common_files(&find_imports(&registered_schema.schema), files);
+    files.insert(registered_schema.schema.clone());
     Ok(())
 }
 
-#[derive(Debug)]
-pub struct DecodeContext {
-    pub resolver: MessageResolver,
-    pub context: Context,
-    pub registered_schema: RegisteredSchema,
-}
-
 fn to_resolve_context(
     sr_settings: &SrSettings,
     registered_schema: RegisteredSchema,
 ) -> Result<Arc<DecodeContext>, SRCError> {
-    let resolver = MessageResolver::new(&registered_schema.schema);
     let mut files = HashSet::new();
-    add_common_files(resolver.imports(), &mut files);
-    add_files(sr_settings, registered_schema.clone(), &mut files)?;
-    match Context::parse(&files) {
-        Ok(context) => Ok(Arc::new(DecodeContext {
-            resolver,
-            context,
-            registered_schema,
-        })),
-        Err(e) => Err(SRCError::non_retryable_with_cause(
-            e,
-            "Error creating proto context",
-        )),
-    }
+    let sr_settings = sr_settings.counting_references();
+    add_files(&sr_settings, &registered_schema, &mut files)?;
+    Ok(Arc::new(to_decode_context(registered_schema, files)?))
+}
+
+/// Creates the proto context for the schema, including all the referenced schema's.
+pub(crate) fn to_context(
+    sr_settings: &SrSettings,
+    registered_schema: RegisteredSchema,
+) -> Result<Context, SRCError> {
+    let mut files = HashSet::new();
+    let sr_settings = sr_settings.counting_references();
+    add_files(&sr_settings, &registered_schema, &mut files)?;
+    Ok(to_decode_context(registered_schema, files)?.context)
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::blocking::proto_decoder::ProtoDecoder;
+    use crate::blocking::proto_decoder::{MessageSelector, ProtoDecoder};
     use crate::blocking::schema_registry::SrSettings;
+    use crate::error::{PolicyViolation, WireFormatError};
+    use crate::schema_registry_common::{
+        IdStrategy, SchemaIdPolicy, SchemaSubjectPolicy, SchemaUsage, SchemaUsageKind, Strictness,
+    };
+    use bytes::Bytes;
     use protofish::decode::Value;
+    use serde_json::json;
+    use std::collections::HashSet;
+    use std::sync::Arc;
+    #[cfg(feature = "proto_descriptor")]
+    use test_utils::get_proto_hb_descriptor_set;
     use test_utils::{
         get_proto_body, get_proto_body_with_reference, get_proto_complex,
         get_proto_complex_proto_test_message, get_proto_complex_references, get_proto_hb_101,
@@ -232,6 +544,89 @@ mod tests {
         assert_eq!(Value::UInt64(101u64), message.fields[0].value)
     }
 
+    #[test]
+    fn test_decode_bytes() {
+        let mut server = mockito::Server::new();
+        let _m = server
+            .mock("GET", "/schemas/ids/7?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_proto_body(get_proto_hb_schema(), 1))
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let decoder = ProtoDecoder::new(sr_settings);
+        let bytes = Bytes::from_static(get_proto_hb_101());
+        let heartbeat = decoder.decode_bytes(Some(bytes.clone())).unwrap().unwrap();
+
+        assert_eq!(Value::UInt64(101u64), heartbeat.value.fields[0].value);
+        assert_eq!(heartbeat.data_bytes, bytes.slice(6..));
+        assert_eq!(heartbeat.data_bytes.as_ptr(), bytes[6..].as_ptr())
+    }
+
+    #[test]
+    fn test_decode_long_id() {
+        let mut server = mockito::Server::new();
+        let _m = server
+            .mock("GET", "/schemas/ids/7?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_proto_body(get_proto_hb_schema(), 1))
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let decoder = ProtoDecoder::new(sr_settings).with_id_strategy(IdStrategy::Long);
+        let bytes = Bytes::from_static(&[0, 0, 0, 0, 0, 0, 0, 0, 7, 0, 8, 101]);
+        let heartbeat = decoder.decode(Some(&bytes)).unwrap();
+        assert!(matches!(heartbeat, Value::Message(_)));
+
+        let heartbeat = decoder.decode_bytes(Some(bytes.clone())).unwrap().unwrap();
+        assert_eq!(Value::UInt64(101u64), heartbeat.value.fields[0].value);
+        assert_eq!(heartbeat.data_bytes, bytes.slice(10..));
+    }
+
+    #[test]
+    fn test_decode_batch() {
+        let mut server = mockito::Server::new();
+        let _m = server
+            .mock("GET", "/schemas/ids/7?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_proto_body(get_proto_hb_schema(), 1))
+            .expect(1)
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let decoder = ProtoDecoder::new(sr_settings);
+        let payloads = [Some(get_proto_hb_101()), None, Some(get_proto_hb_101())];
+        let results = decoder.decode_batch(&payloads);
+
+        _m.assert();
+        assert_eq!(results.len(), 3);
+        assert!(results[1].as_ref().unwrap().is_none());
+        for i in [0, 2] {
+            let heartbeat = results[i].as_ref().unwrap().as_ref().unwrap();
+            assert_eq!(Value::UInt64(101u64), heartbeat.value.fields[0].value)
+        }
+    }
+
+    #[test]
+    fn test_decode_json() {
+        let mut server = mockito::Server::new();
+        let _m = server
+            .mock("GET", "/schemas/ids/7?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_proto_body(get_proto_hb_schema(), 1))
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let decoder = ProtoDecoder::new(sr_settings);
+        let heartbeat = decoder.decode_json(Some(get_proto_hb_101())).unwrap();
+
+        assert_eq!(heartbeat, Some(json!({"beat": 101})))
+    }
+
     #[test]
     fn test_decoder_cache() {
         let mut server = mockito::Server::new();
@@ -297,14 +692,167 @@ mod tests {
         assert_eq!(message.fields[1].value, Value::Int64(1))
     }
 
+    #[cfg(feature = "prost_decoder")]
+    #[derive(Clone, PartialEq, prost::Message)]
+    struct Heartbeat {
+        #[prost(uint64, tag = "1")]
+        beat: u64,
+    }
+
+    #[cfg(feature = "prost_decoder")]
+    impl prost::Name for Heartbeat {
+        const NAME: &'static str = "Heartbeat";
+        const PACKAGE: &'static str = "nl.openweb.data";
+    }
+
+    #[cfg(feature = "prost_decoder")]
+    #[test]
+    fn test_decode_prost() {
+        let mut server = mockito::Server::new();
+        let _m = server
+            .mock("GET", "/schemas/ids/7?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_proto_body(get_proto_hb_schema(), 1))
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let decoder = ProtoDecoder::new(sr_settings);
+        let heartbeat: Heartbeat = decoder
+            .decode_prost(Some(get_proto_hb_101()))
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(heartbeat, Heartbeat { beat: 101 })
+    }
+
+    #[cfg(feature = "proto_descriptor")]
+    #[test]
+    fn test_decode_with_schema_override() {
+        let sr_settings = SrSettings::new(String::from("http://127.0.0.1:1234"));
+        let decoder = ProtoDecoder::new(sr_settings);
+        decoder
+            .add_schema_override(7, get_proto_hb_descriptor_set())
+            .unwrap();
+        let result = decoder.decode_json(Some(get_proto_hb_101())).unwrap();
+
+        assert_eq!(result, Some(json!({"beat": 101})))
+    }
+
+    #[test]
+    fn test_decode_with_message_selector() {
+        let mut server = mockito::Server::new();
+        let _m = server
+            .mock("GET", "/schemas/ids/7?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_proto_body(get_proto_hb_schema(), 1))
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let selector = MessageSelector::new(|_| Some(String::from("nl.openweb.data.Heartbeat")));
+        let decoder = ProtoDecoder::new_with_message_selector(sr_settings, selector);
+        let result = decoder.decode_json(Some(&[0, 0, 0, 0, 7, 8, 101])).unwrap();
+
+        assert_eq!(result, Some(json!({"beat": 101})))
+    }
+
+    #[test]
+    fn test_decode_strict_invalid_magic_byte() {
+        let sr_settings = SrSettings::new(String::from("http://127.0.0.1:1234"));
+        let decoder = ProtoDecoder::new(sr_settings).with_strictness(Strictness::Strict);
+        let error = decoder
+            .decode(Some(&[1, 0, 0, 0, 7, 0, 8, 101]))
+            .unwrap_err();
+
+        assert_eq!(
+            error.wire_format(),
+            Some(&WireFormatError::InvalidMagicByte(1))
+        )
+    }
+
     #[test]
     fn display_decoder() {
         let sr_settings = SrSettings::new("http://127.0.0.1:1234".to_string());
         let decoder = ProtoDecoder::new(sr_settings);
         assert_eq!(
-            "ProtoDecoder { sr_settings: SrSettings { urls: [\"http://127.0.0.1:1234\"], client: Client, authorization: None }, cache: {} }"
+            "ProtoDecoder { sr_settings: SrSettings { urls: [\"http://127.0.0.1:1234\"], client: Client, authorization: None, dialect: Confluent, fetch_deleted: true, normalize: false, headers: {}, usage_listener: None, fallback: None, limits: SchemaLimits { max_schema_bytes: None, max_references: None, max_total_references: None, max_depth: None }, references_fetched: None }, cache: {}, fallback: {}, message_selector: None, strictness: Lenient, id_strategy: Confluent, field_encryptor: None, slow_path: SlowPathWarnings { decode_threshold: None, registry_fetch: false }, policy: None, cached_schemas: CachedSchemas { schema_type: \"protobuf\", count: 0 } }"
                 .to_owned(),
             format!("{:?}", decoder)
         )
     }
+
+    #[test]
+    fn test_usage_listener() {
+        let mut server = mockito::Server::new();
+        let _m = server
+            .mock("GET", "/schemas/ids/7?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_proto_body(get_proto_hb_schema(), 7))
+            .create();
+
+        let usages = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = usages.clone();
+        let sr_settings = SrSettings::new_builder(server.url())
+            .set_usage_listener(Arc::new(move |usage: &SchemaUsage| {
+                recorded.lock().unwrap().push(usage.clone())
+            }))
+            .build()
+            .unwrap();
+        let decoder = ProtoDecoder::new(sr_settings);
+        for _ in 0..2 {
+            decoder.decode(Some(get_proto_hb_101())).unwrap();
+        }
+
+        let usage = |kind| SchemaUsage {
+            kind,
+            id: 7,
+            subject: None,
+        };
+        assert_eq!(
+            *usages.lock().unwrap(),
+            vec![
+                usage(SchemaUsageKind::Fetched),
+                usage(SchemaUsageKind::Decoded)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_decoder_policy() {
+        let mut server = mockito::Server::new();
+        let _m = server
+            .mock("GET", "/schemas/ids/7?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_proto_body(get_proto_hb_schema(), 7))
+            .expect(1)
+            .create();
+        let _n = server
+            .mock("GET", "/schemas/ids/7/versions")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"[{"subject":"nl.openweb.data.Heartbeat","version":1}]"#)
+            .create();
+        let sr_settings = SrSettings::new(server.url());
+
+        let decoder = ProtoDecoder::new(sr_settings.clone())
+            .with_policy(Arc::new(SchemaIdPolicy::Deny(HashSet::from([7]))));
+        let error = decoder.decode(Some(get_proto_hb_101())).unwrap_err();
+        assert_eq!(error.policy_violation(), Some(&PolicyViolation { id: 7 }));
+        assert!(!error.cached);
+
+        let denied = HashSet::from([String::from("nl.openweb.data.Heartbeat")]);
+        let decoder = ProtoDecoder::new(sr_settings.clone())
+            .with_policy(Arc::new(SchemaSubjectPolicy::Deny(denied)));
+        let error = decoder.decode(Some(get_proto_hb_101())).unwrap_err();
+        assert_eq!(error.policy_violation(), Some(&PolicyViolation { id: 7 }));
+
+        let allowed = HashSet::from([String::from("nl.openweb.data.Heartbeat")]);
+        let decoder = ProtoDecoder::new(sr_settings)
+            .with_policy(Arc::new(SchemaSubjectPolicy::Allow(allowed)));
+        assert!(decoder.decode(Some(get_proto_hb_101())).is_ok());
+        _m.assert();
+    }
 }
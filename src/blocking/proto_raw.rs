@@ -2,16 +2,30 @@ use dashmap::mapref::entry::Entry;
 use dashmap::DashMap;
 use std::sync::Arc;
 
+#[cfg(feature = "proto_decoder")]
+use crate::blocking::proto_decoder::to_context;
 use crate::blocking::schema_registry::{
-    get_schema_by_id_and_type, get_schema_by_subject, SrSettings,
+    get_allowed_schema, get_schema_by_id_and_type, get_schema_by_subject,
+    get_schema_by_subject_with_version, SrSettings,
 };
 use crate::error::SRCError;
+#[cfg(feature = "proto_decoder")]
+use crate::field_encryption::FieldEncryptor;
+#[cfg(feature = "proto_decoder")]
+use crate::proto_raw_common::to_encryption;
 use crate::proto_raw_common::{
-    to_bytes, to_bytes_single_message, to_decode_context, DecodeContext, EncodeContext,
+    to_auto_register_strategy, to_bytes, to_bytes_single_message, to_decode_context, write_bytes,
+    DecodeContext, EncodeContext, EncodeOptions, ProtoSchema, SchemaSelector,
 };
 use crate::proto_resolver::{resolve_name, to_index_and_data, IndexResolver};
 use crate::schema_registry_common::{
-    get_bytes_result, BytesResult, RegisteredSchema, SchemaType, SubjectNameStrategy,
+    check_policy, to_wire_format_error, BytesResult, DecodeMetadata, DecodeResultWithMetadata,
+    DecodedRecord, DecoderOptions, IdStrategy, RegisteredSchema, SchemaPolicy, SchemaType,
+    SchemaUsageKind, SlowPathWarnings, Strictness, SubjectNameStrategy, Version,
+};
+use crate::telemetry::{
+    decode_start, record_cache_lookup, record_decode_error, record_encode_error,
+    warn_registry_fetch, warn_slow_decode, CachedSchemas,
 };
 
 /// Encoder that works by prepending the correct bytes in order to make it valid schema registry
@@ -21,6 +35,7 @@ use crate::schema_registry_common::{
 #[derive(Debug)]
 pub struct ProtoRawEncoder {
     sr_settings: SrSettings,
+    options: EncodeOptions,
     cache: DashMap<String, Result<Arc<EncodeContext>, SRCError>>,
 }
 
@@ -29,65 +44,304 @@ impl ProtoRawEncoder {
     pub fn new(sr_settings: SrSettings) -> ProtoRawEncoder {
         ProtoRawEncoder {
             sr_settings,
+            options: EncodeOptions::default(),
             cache: DashMap::new(),
         }
     }
+    /// Creates a new encoder which, before adding the header, decodes the bytes with the schema
+    /// that is used. When a field is not part of the message, has the wrong type, or the bytes
+    /// can't be fully read, an error is returned instead.
+    #[cfg(feature = "proto_decoder")]
+    pub fn new_with_validation(sr_settings: SrSettings) -> ProtoRawEncoder {
+        ProtoRawEncoder::new(sr_settings).with_validation(true)
+    }
+    /// Sets whether the bytes are decoded with the schema before adding the header, as described
+    /// with new_with_validation. By default they are not validated.
+    #[cfg(feature = "proto_decoder")]
+    pub fn with_validation(mut self, validate: bool) -> ProtoRawEncoder {
+        self.options.validate = validate;
+        self
+    }
+    /// Sets the encryptor used to encrypt the fields tagged by an ENCRYPT rule of the schema. The
+    /// fields are tagged in the metadata of the schema, by the full name of the message followed
+    /// by the name of the field.
+    #[cfg(feature = "proto_decoder")]
+    pub fn with_field_encryptor(
+        mut self,
+        field_encryptor: Arc<dyn FieldEncryptor>,
+    ) -> ProtoRawEncoder {
+        self.options.field_encryptor = Some(field_encryptor);
+        self
+    }
+    /// Sets how the schema id is written, by default the Confluent wire format is used. With the id
+    /// in a header, only the message index and the bytes are in the payload, and the id needs to be
+    /// set in the header by the producer.
+    pub fn with_id_strategy(mut self, id_strategy: IdStrategy) -> ProtoRawEncoder {
+        self.options.id_strategy = id_strategy;
+        self
+    }
     /// Removes errors from the cache, can be useful to retry failed encodings.
     pub fn remove_errors_from_cache(&self) {
         self.cache.retain(|_, v| v.is_ok());
     }
+    /// Removes the schema's of the subject of the strategy from the cache, so they are fetched again
+    /// on the next encode. Long-lived producers can call this when they are signaled a new version
+    /// was registered, to pick it up without a restart.
+    pub fn refresh(&self, subject_name_strategy: &SubjectNameStrategy) -> Result<(), SRCError> {
+        let subject = subject_name_strategy.get_subject()?;
+        let prefix = format!("{}/", subject);
+        self.cache
+            .retain(|k, _| *k != subject && !k.starts_with(&prefix));
+        Ok(())
+    }
     /// Encodes the bytes by adding a few bytes to the message with additional information. The full
     /// names is the optional package followed with the message name, and optionally inner messages.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "encode", level = "debug", skip_all, err)
+    )]
     pub fn encode(
         &self,
         bytes: &[u8],
         full_name: &str,
-        subject_name_strategy: &SubjectNameStrategy,
+        subject_name_strategy: impl Into<SubjectNameStrategy>,
     ) -> Result<Vec<u8>, SRCError> {
-        let key = subject_name_strategy.get_subject()?;
-        match self.encoding_context(key, subject_name_strategy) {
+        let selector = SchemaSelector::Strategy(subject_name_strategy.into());
+        let result = match self.encoding_context(selector) {
             Ok(encode_context) => to_bytes(&encode_context, bytes, full_name),
             Err(e) => Err(e),
+        };
+        if result.is_err() {
+            record_encode_error("protobuf");
         }
+        result
     }
 
+    /// Like `encode`, but writing the bytes to the buffer, which is cleared first. This way the
+    /// buffer, and its allocation, can be reused for multiple messages.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "encode", level = "debug", skip_all, err)
+    )]
+    pub fn encode_into(
+        &self,
+        bytes: &[u8],
+        full_name: &str,
+        subject_name_strategy: impl Into<SubjectNameStrategy>,
+        buf: &mut Vec<u8>,
+    ) -> Result<(), SRCError> {
+        let selector = SchemaSelector::Strategy(subject_name_strategy.into());
+        let result = match self.encoding_context(selector) {
+            Ok(encode_context) => write_bytes(&encode_context, bytes, full_name, buf),
+            Err(e) => Err(e),
+        };
+        if result.is_err() {
+            record_encode_error("protobuf");
+        }
+        result
+    }
+
+    /// Encodes multiple messages of the same type for the same subject, getting the encoding
+    /// context only once. Stops at, and returns, the first error.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "encode", level = "debug", skip_all, err)
+    )]
+    pub fn encode_many(
+        &self,
+        messages: &[&[u8]],
+        full_name: &str,
+        subject_name_strategy: impl Into<SubjectNameStrategy>,
+    ) -> Result<Vec<Vec<u8>>, SRCError> {
+        let selector = SchemaSelector::Strategy(subject_name_strategy.into());
+        let result = match self.encoding_context(selector) {
+            Ok(encode_context) => messages
+                .iter()
+                .map(|bytes| to_bytes(&encode_context, bytes, full_name))
+                .collect(),
+            Err(e) => Err(e),
+        };
+        if result.is_err() {
+            record_encode_error("protobuf");
+        }
+        result
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "encode", level = "debug", skip_all, err)
+    )]
     pub fn encode_single_message(
         &self,
         bytes: &[u8],
-        subject_name_strategy: &SubjectNameStrategy,
+        subject_name_strategy: impl Into<SubjectNameStrategy>,
     ) -> Result<Vec<u8>, SRCError> {
-        let key = subject_name_strategy.get_subject()?;
-        match self.encoding_context(key, subject_name_strategy) {
+        let selector = SchemaSelector::Strategy(subject_name_strategy.into());
+        let result = match self.encoding_context(selector) {
             Ok(encode_context) => to_bytes_single_message(&encode_context, bytes),
             Err(e) => Err(e),
+        };
+        if result.is_err() {
+            record_encode_error("protobuf");
+        }
+        result
+    }
+
+    /// Encodes the bytes using a specific version of the subject, instead of the latest. This way
+    /// producers don't pick up new schema's that might break consumers. The version can also be the
+    /// latest with a metadata key and value, which needs to be present in the schema registry. A
+    /// schema supplied with the strategy is ignored.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "encode", level = "debug", skip_all, err)
+    )]
+    pub fn encode_with_version(
+        &self,
+        bytes: &[u8],
+        full_name: &str,
+        subject_name_strategy: impl Into<SubjectNameStrategy>,
+        version: impl Into<Version>,
+    ) -> Result<Vec<u8>, SRCError> {
+        let selector = SchemaSelector::Version(subject_name_strategy.into(), version.into());
+        let result = match self.encoding_context(selector) {
+            Ok(encode_context) => to_bytes(&encode_context, bytes, full_name),
+            Err(e) => Err(e),
+        };
+        if result.is_err() {
+            record_encode_error("protobuf");
         }
+        result
     }
 
-    fn encoding_context(
+    /// Encodes the bytes using the supplied schema, which is registered under the subject of the
+    /// strategy, together with its imports as references, when it's not already present. This is
+    /// similar to the auto.register.schemas setting of the Java serializer. The resulting id is
+    /// cached, so the schema is only posted once. The strategy should not contain a schema.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "encode", level = "debug", skip_all, err)
+    )]
+    pub fn encode_with_auto_register(
         &self,
-        key: String,
-        subject_name_strategy: &SubjectNameStrategy,
-    ) -> Result<Arc<EncodeContext>, SRCError> {
-        match self.cache.entry(key) {
+        bytes: &[u8],
+        full_name: &str,
+        subject_name_strategy: impl Into<SubjectNameStrategy>,
+        schema: &ProtoSchema,
+    ) -> Result<Vec<u8>, SRCError> {
+        let selector = SchemaSelector::AutoRegister(subject_name_strategy.into(), schema.clone());
+        let result = match self.encoding_context(selector) {
+            Ok(encode_context) => to_bytes(&encode_context, bytes, full_name),
+            Err(e) => Err(e),
+        };
+        if result.is_err() {
+            record_encode_error("protobuf");
+        }
+        result
+    }
+
+    /// Encodes the bytes using the schema with the id, the schema needs to be a protobuf schema.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "encode", level = "debug", skip_all, err)
+    )]
+    pub fn encode_with_schema_id(
+        &self,
+        bytes: &[u8],
+        full_name: &str,
+        id: u32,
+    ) -> Result<Vec<u8>, SRCError> {
+        let result = match self.encoding_context(SchemaSelector::Id(id)) {
+            Ok(encode_context) => to_bytes(&encode_context, bytes, full_name),
+            Err(e) => Err(e),
+        };
+        if result.is_err() {
+            record_encode_error("protobuf");
+        }
+        result
+    }
+
+    fn encoding_context(&self, selector: SchemaSelector) -> Result<Arc<EncodeContext>, SRCError> {
+        match self.cache.entry(selector.key()?) {
             Entry::Occupied(e) => e.get().clone(),
             Entry::Vacant(e) => {
-                let v = match get_schema_by_subject(&self.sr_settings, subject_name_strategy) {
-                    Ok(registered_schema) => Ok(Arc::new(EncodeContext {
-                        id: registered_schema.id,
-                        resolver: IndexResolver::new(&registered_schema.schema),
-                    })),
+                let v = match to_encode_context(&self.sr_settings, &selector, &self.options) {
+                    Ok(encode_context) => Ok(Arc::new(encode_context)),
                     Err(e) => Err(e.into_cache()),
                 };
+                if let Ok(context) = &v {
+                    self.sr_settings.record_usage(
+                        SchemaUsageKind::Encoded,
+                        context.id,
+                        context.subject.as_deref(),
+                    );
+                }
+                if matches!(&v, Err(err) if err.is_throttled()) {
+                    return v;
+                }
                 e.insert(v).value().clone()
             }
         }
     }
 }
 
+fn get_registered_schema(
+    sr_settings: &SrSettings,
+    selector: &SchemaSelector,
+) -> Result<RegisteredSchema, SRCError> {
+    match selector {
+        SchemaSelector::Strategy(s) => get_schema_by_subject(sr_settings, s),
+        SchemaSelector::AutoRegister(s, schema) => {
+            get_schema_by_subject(sr_settings, &to_auto_register_strategy(s, schema)?)
+        }
+        SchemaSelector::Version(s, v) => get_schema_by_subject_with_version(sr_settings, s, v),
+        SchemaSelector::Id(id) => get_schema_by_id_and_type(*id, sr_settings, SchemaType::Protobuf),
+    }
+}
+
+#[cfg_attr(not(feature = "proto_decoder"), allow(unused_variables))]
+fn to_encode_context(
+    sr_settings: &SrSettings,
+    selector: &SchemaSelector,
+    options: &EncodeOptions,
+) -> Result<EncodeContext, SRCError> {
+    let registered_schema = get_registered_schema(sr_settings, selector)?;
+    let resolver = IndexResolver::new(&registered_schema.schema);
+    #[cfg(feature = "proto_decoder")]
+    let encryption = to_encryption(options.field_encryptor.clone(), &registered_schema);
+    #[cfg(feature = "proto_decoder")]
+    let context = if options.validate || encryption.is_some() {
+        Some(Arc::new(to_context(
+            sr_settings,
+            registered_schema.clone(),
+        )?))
+    } else {
+        None
+    };
+    Ok(EncodeContext {
+        id: registered_schema.id,
+        subject: selector
+            .subject()
+            .or_else(|| registered_schema.subject.clone()),
+        id_strategy: options.id_strategy.clone(),
+        resolver,
+        #[cfg(feature = "proto_decoder")]
+        context,
+        #[cfg(feature = "proto_decoder")]
+        validate: options.validate,
+        #[cfg(feature = "proto_decoder")]
+        encryption,
+    })
+}
+
 #[derive(Debug)]
 pub struct ProtoRawDecoder {
     sr_settings: SrSettings,
     cache: DashMap<u32, Result<Arc<DecodeContext>, SRCError>>,
+    id_strategy: IdStrategy,
+    strictness: Strictness,
+    slow_path: SlowPathWarnings,
+    policy: Option<Arc<dyn SchemaPolicy>>,
+    cached_schemas: CachedSchemas,
 }
 
 impl ProtoRawDecoder {
@@ -100,24 +354,114 @@ impl ProtoRawDecoder {
         ProtoRawDecoder {
             sr_settings,
             cache: DashMap::new(),
+            id_strategy: IdStrategy::default(),
+            strictness: Strictness::Lenient,
+            slow_path: SlowPathWarnings::default(),
+            policy: None,
+            cached_schemas: CachedSchemas::new("protobuf"),
         }
     }
+    /// Sets how the schema id is read, which needs to be the same as used by the encoder. With
+    /// the id in a header, use decode_with_headers.
+    pub fn with_id_strategy(mut self, id_strategy: IdStrategy) -> ProtoRawDecoder {
+        self.id_strategy = id_strategy;
+        self
+    }
+    /// Sets how payloads that don't follow the wire format are handled. They always give an error,
+    /// when strict it's the [WireFormatError](crate::error::WireFormatError).
+    pub fn with_strictness(mut self, strictness: Strictness) -> ProtoRawDecoder {
+        self.strictness = strictness;
+        self
+    }
+    /// Sets which slow paths of decode are logged as a warning, like a decode taking longer than a
+    /// threshold, or a decode needing to fetch the schema.
+    pub fn with_slow_path_warnings(mut self, slow_path: SlowPathWarnings) -> ProtoRawDecoder {
+        self.slow_path = slow_path;
+        self
+    }
+    /// Sets the policy restricting which schema's are fetched and decoded. Payloads with a schema
+    /// id that's not allowed give an error with a `PolicyViolation`, which is not cached, so a
+    /// change of the policy is applied directly.
+    pub fn with_policy(mut self, policy: Arc<dyn SchemaPolicy>) -> ProtoRawDecoder {
+        self.policy = Some(policy);
+        self
+    }
+    /// Sets the options shared by all the decoders, replacing the id strategy, strictness, slow
+    /// path warnings and policy set before.
+    pub fn with_options(mut self, options: DecoderOptions) -> ProtoRawDecoder {
+        self.id_strategy = options.id_strategy;
+        self.strictness = options.strictness;
+        self.slow_path = options.slow_path;
+        self.policy = options.policy;
+        self
+    }
     /// Remove al the errors from the cache, you might need to/want to run this when a recoverable
     /// error is met. Errors are also cashed to prevent trying to get schema's that either don't
     /// exist or can't be parsed.
     pub fn remove_errors_from_cache(&self) {
         self.cache.retain(|_, v| v.is_ok());
     }
+    /// Decodes both the key and the payload of a record, the same way as decode.
+    pub fn decode_message(
+        &self,
+        key: Option<&[u8]>,
+        payload: Option<&[u8]>,
+    ) -> Result<DecodedRecord<Option<RawDecodeResult>>, SRCError> {
+        Ok(DecodedRecord {
+            key: self.decode(key)?,
+            value: self.decode(payload)?,
+        })
+    }
     /// Reads the bytes to get the name, and gives back the data bytes.
     pub fn decode(&self, bytes: Option<&[u8]>) -> Result<Option<RawDecodeResult>, SRCError> {
-        match get_bytes_result(bytes) {
+        self.decode_with_headers(bytes, &[])
+    }
+    /// Like decode, using the headers of the record to get the schema id when the id strategy has
+    /// the id in a header.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "decode", level = "debug", skip_all, err)
+    )]
+    pub fn decode_with_headers(
+        &self,
+        bytes: Option<&[u8]>,
+        headers: &[(&str, &[u8])],
+    ) -> Result<Option<RawDecodeResult>, SRCError> {
+        let result = match self.id_strategy.get_bytes_result(bytes, headers) {
             BytesResult::Null => Ok(None),
-            BytesResult::Valid(id, bytes) => Ok(Some(self.deserialize(id, &bytes)?)),
-            BytesResult::Invalid(i) => Err(SRCError::non_retryable_without_cause(&format!(
-                "Invalid bytes {:?}",
-                i
-            ))),
+            BytesResult::Valid(id, bytes) => {
+                let start = decode_start(&self.slow_path);
+                let result = self.deserialize(id, &bytes);
+                warn_slow_decode(&self.slow_path, start, id, || {
+                    result.as_ref().ok().and_then(|r| r.schema.subject.clone())
+                });
+                result.map(Some)
+            }
+            BytesResult::Invalid(i) => Err(match self.strictness {
+                Strictness::Lenient => {
+                    SRCError::non_retryable_without_cause(&format!("Invalid bytes {:?}", i))
+                }
+                Strictness::Strict => SRCError::from(to_wire_format_error(&i)),
+            }),
+        };
+        if result.is_err() {
+            record_decode_error("protobuf");
         }
+        result
+    }
+    /// Gives the data bytes, together with the id of the schema and the full name of the message, so
+    /// messages can be routed by type without another lookup.
+    pub fn decode_with_metadata(
+        &self,
+        bytes: Option<&[u8]>,
+    ) -> Result<Option<DecodeResultWithMetadata<Vec<u8>>>, SRCError> {
+        Ok(self.decode(bytes)?.map(|r| DecodeResultWithMetadata {
+            metadata: DecodeMetadata {
+                schema_id: r.schema.id,
+                full_name: Some(String::from(&*r.full_name)),
+            },
+            value: r.bytes,
+        }))
     }
     /// The actual deserialization trying to get the id from the bytes to retrieve the schema, and
     /// using a reader transforms the bytes to a value.
@@ -125,7 +469,7 @@ impl ProtoRawDecoder {
         match self.context(id) {
             Ok(s) => {
                 let schema = &s.schema;
-                let (index, data) = to_index_and_data(bytes);
+                let (index, data) = to_index_and_data(bytes)?;
                 let full_name = resolve_name(&s.resolver, &index)?;
                 Ok(RawDecodeResult {
                     schema: schema.clone(),
@@ -139,14 +483,28 @@ impl ProtoRawDecoder {
     /// Gets the Context object, either from the cache, or from the schema registry and then putting
     /// it into the cache.
     fn context(&self, id: u32) -> Result<Arc<DecodeContext>, SRCError> {
+        check_policy(self.policy.as_deref(), id)?;
         match self.cache.entry(id) {
-            Entry::Occupied(e) => e.get().clone(),
+            Entry::Occupied(e) => {
+                record_cache_lookup(id, true);
+                e.get().clone()
+            }
             Entry::Vacant(e) => {
-                let v = match get_schema_by_id_and_type(id, &self.sr_settings, SchemaType::Protobuf)
-                {
+                record_cache_lookup(id, false);
+                warn_registry_fetch(&self.slow_path, id);
+                let (sr_settings, policy) = (&self.sr_settings, self.policy.as_deref());
+                let v = match get_allowed_schema(id, sr_settings, SchemaType::Protobuf, policy) {
                     Ok(r) => Ok(Arc::new(to_decode_context(r))),
                     Err(e) => Err(e.into_cache()),
                 };
+                if v.is_ok() {
+                    self.cached_schemas.added();
+                    self.sr_settings
+                        .record_usage(SchemaUsageKind::Decoded, id, None);
+                }
+                if matches!(&v, Err(err) if err.is_throttled()) {
+                    return v;
+                }
                 e.insert(v).value().clone()
             }
         }
@@ -165,14 +523,25 @@ mod tests {
 
     use crate::blocking::proto_raw::{ProtoRawDecoder, ProtoRawEncoder};
     use crate::blocking::schema_registry::SrSettings;
+    #[cfg(feature = "proto_decoder")]
+    use crate::error::SRCError;
+    use crate::error::{PolicyViolation, WireFormatError};
+    #[cfg(feature = "proto_decoder")]
+    use crate::field_encryption::FieldEncryptor;
+    use crate::proto_raw_common::ProtoSchema;
+    #[cfg(feature = "proto_decoder")]
+    use crate::schema_registry_common::Rule;
     use crate::schema_registry_common::{
-        SchemaType, SubjectNameStrategy, SuppliedReference, SuppliedSchema,
+        DecoderOptions, SchemaIdPolicy, SchemaSubjectPolicy, SchemaType, SchemaUsage,
+        SchemaUsageKind, Strictness, SubjectNameStrategy, SuppliedReference, SuppliedSchema,
     };
+    use std::collections::HashSet;
+    use std::sync::Arc;
     use test_utils::{
         get_proto_body, get_proto_body_with_reference, get_proto_complex,
         get_proto_complex_only_data, get_proto_complex_proto_test_message,
-        get_proto_complex_references, get_proto_hb_101, get_proto_hb_101_only_data,
-        get_proto_hb_schema, get_proto_result,
+        get_proto_complex_references, get_proto_complex_source, get_proto_hb_101,
+        get_proto_hb_101_only_data, get_proto_hb_schema, get_proto_result, get_proto_result_source,
     };
 
     #[test]
@@ -201,6 +570,125 @@ mod tests {
         assert_eq!(encoded_data, get_proto_hb_101())
     }
 
+    #[test]
+    fn test_encode_many() {
+        let mut server = mockito::Server::new();
+        let m = server
+            .mock("GET", "/subjects/nl.openweb.data.Heartbeat/versions/latest")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_proto_body(get_proto_hb_schema(), 7))
+            .expect(1)
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let encoder = ProtoRawEncoder::new(sr_settings);
+        let strategy =
+            SubjectNameStrategy::RecordNameStrategy(String::from("nl.openweb.data.Heartbeat"));
+
+        let encoded_data = encoder
+            .encode_many(
+                &[get_proto_hb_101_only_data(), get_proto_hb_101_only_data()],
+                "nl.openweb.data.Heartbeat",
+                strategy,
+            )
+            .unwrap();
+
+        assert_eq!(encoded_data, vec![get_proto_hb_101(), get_proto_hb_101()]);
+        m.assert();
+    }
+
+    #[cfg(feature = "proto_decoder")]
+    #[test]
+    fn test_encode_many_stops_at_first_error() {
+        let mut server = mockito::Server::new();
+        let _m = server
+            .mock("GET", "/subjects/nl.openweb.data.Heartbeat/versions/latest")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_proto_body(get_proto_hb_schema(), 7))
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let encoder = ProtoRawEncoder::new_with_validation(sr_settings);
+        let strategy =
+            SubjectNameStrategy::RecordNameStrategy(String::from("nl.openweb.data.Heartbeat"));
+
+        let error = encoder
+            .encode_many(
+                &[
+                    get_proto_hb_101_only_data(),
+                    &[8, 101, 24, 1],
+                    &[8, 101, 32, 1],
+                ],
+                "nl.openweb.data.Heartbeat",
+                strategy,
+            )
+            .unwrap_err();
+
+        assert_eq!(
+            error.error,
+            "Field 3 in message nl.openweb.data.Heartbeat is not part of the schema, or has the wrong type"
+        )
+    }
+
+    #[cfg(feature = "proto_decoder")]
+    #[test]
+    fn test_encode_single_message_with_validation() {
+        let mut server = mockito::Server::new();
+        let _m = server
+            .mock("GET", "/subjects/nl.openweb.data.Heartbeat/versions/latest")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_proto_body(get_proto_hb_schema(), 7))
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let encoder = ProtoRawEncoder::new_with_validation(sr_settings);
+        let strategy =
+            SubjectNameStrategy::RecordNameStrategy(String::from("nl.openweb.data.Heartbeat"));
+
+        let encoded_data = encoder
+            .encode_single_message(get_proto_hb_101_only_data(), &strategy)
+            .unwrap();
+        assert_eq!(encoded_data, get_proto_hb_101());
+
+        let error = encoder
+            .encode_single_message(&[8, 101, 24, 1], &strategy)
+            .unwrap_err();
+        assert_eq!(
+            error.error,
+            "Field 3 in message nl.openweb.data.Heartbeat is not part of the schema, or has the wrong type"
+        )
+    }
+
+    #[test]
+    fn test_encode_with_version() {
+        let mut server = mockito::Server::new();
+        let _m = server
+            .mock("GET", "/subjects/nl.openweb.data.Heartbeat/versions/2")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_proto_body(get_proto_hb_schema(), 7))
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let encoder = ProtoRawEncoder::new(sr_settings);
+        let strategy =
+            SubjectNameStrategy::RecordNameStrategy(String::from("nl.openweb.data.Heartbeat"));
+
+        let encoded_data = encoder
+            .encode_with_version(
+                get_proto_hb_101_only_data(),
+                "nl.openweb.data.Heartbeat",
+                &strategy,
+                2,
+            )
+            .unwrap();
+
+        assert_eq!(encoded_data, get_proto_hb_101())
+    }
+
     #[test]
     fn test_encode_single_message() {
         let mut server = mockito::Server::new();
@@ -317,13 +805,13 @@ mod tests {
         let result_reference = SuppliedReference {
             name: String::from("result.proto"),
             subject: String::from("result.proto"),
-            schema: String::from(get_proto_result()),
+            schema: String::from(get_proto_result_source()),
             references: vec![],
         };
         let supplied_schema = SuppliedSchema {
             name: Some(String::from("test.proto")),
             schema_type: SchemaType::Protobuf,
-            schema: String::from(get_proto_complex()),
+            schema: String::from(get_proto_complex_source()),
             references: vec![result_reference],
         };
         let strategy = SubjectNameStrategy::RecordNameStrategyWithSchema(supplied_schema);
@@ -340,13 +828,89 @@ mod tests {
     }
 
     #[test]
-    fn display_rew_decoder() {
+    fn test_encode_with_auto_register() {
+        let mut server = mockito::Server::new();
+        let _m = server
+            .mock("POST", "/subjects/result.proto/versions")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_proto_body(get_proto_result(), 5))
+            .expect(1)
+            .create();
+
+        let _n = server
+            .mock("POST", "/subjects/result.proto?deleted=false")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body("{\"version\":1}")
+            .create();
+
+        let _o = server
+            .mock("POST", "/subjects/test-value/versions")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_proto_body(get_proto_complex(), 6))
+            .expect(1)
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let encoder = ProtoRawEncoder::new(sr_settings);
+        let schema = ProtoSchema::Files(
+            String::from("test.proto"),
+            vec![
+                (
+                    String::from("result.proto"),
+                    String::from(get_proto_result_source()),
+                ),
+                (
+                    String::from("test.proto"),
+                    String::from(get_proto_complex_source()),
+                ),
+            ],
+        );
+        let strategy = SubjectNameStrategy::TopicNameStrategy(String::from("test"), false);
+
+        for _ in 0..2 {
+            let encoded_data = encoder
+                .encode_with_auto_register(
+                    get_proto_complex_only_data(),
+                    "org.schema_registry_test_app.proto.ProtoTest",
+                    &strategy,
+                    &schema,
+                )
+                .unwrap();
+            assert_eq!(encoded_data, get_proto_complex_proto_test_message())
+        }
+        _m.assert();
+        _o.assert();
+    }
+
+    #[test]
+    fn test_encode_with_auto_register_missing_import() {
+        let sr_settings = SrSettings::new(String::from("http://127.0.0.1:1234"));
+        let encoder = ProtoRawEncoder::new(sr_settings);
+        let schema = ProtoSchema::Source(String::from(get_proto_complex_source()));
+
+        let error = encoder
+            .encode_with_auto_register(
+                get_proto_complex_only_data(),
+                "org.schema_registry_test_app.proto.ProtoTest",
+                ("test", false),
+                &schema,
+            )
+            .unwrap_err();
+        assert_eq!(error.error, "Missing source for import result.proto")
+    }
+
+    #[cfg(feature = "proto_decoder")]
+    #[test]
+    fn display_encoder() {
         let sr_settings = SrSettings::new(String::from("http://127.0.0.1:1234"));
-        let decoder = ProtoRawEncoder::new(sr_settings);
+        let encoder = ProtoRawEncoder::new(sr_settings);
         assert_eq!(
-            "ProtoRawEncoder { sr_settings: SrSettings { urls: [\"http://127.0.0.1:1234\"], client: Client, authorization: None }, cache: {} }"
+            "ProtoRawEncoder { sr_settings: SrSettings { urls: [\"http://127.0.0.1:1234\"], client: Client, authorization: None, dialect: Confluent, fetch_deleted: true, normalize: false, headers: {}, usage_listener: None, fallback: None, limits: SchemaLimits { max_schema_bytes: None, max_references: None, max_total_references: None, max_depth: None }, references_fetched: None }, options: EncodeOptions { validate: false, id_strategy: Confluent, field_encryptor: None }, cache: {} }"
                 .to_owned(),
-            format!("{:?}", decoder)
+            format!("{:?}", encoder)
         )
     }
 
@@ -374,6 +938,31 @@ mod tests {
         assert_eq!(*raw_result.full_name, "nl.openweb.data.Heartbeat")
     }
 
+    #[test]
+    fn test_decode_with_metadata() {
+        let mut server = mockito::Server::new();
+        let _m = server
+            .mock("GET", "/schemas/ids/7?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_proto_body(get_proto_hb_schema(), 7))
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let decoder = ProtoRawDecoder::new(sr_settings);
+        let result = decoder
+            .decode_with_metadata(Some(get_proto_hb_101()))
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(result.metadata.schema_id, 7);
+        assert_eq!(
+            result.metadata.full_name,
+            Some(String::from("nl.openweb.data.Heartbeat"))
+        );
+        assert_eq!(result.value, get_proto_hb_101_only_data())
+    }
+
     #[test]
     fn test_decoder_cache() {
         let mut server = mockito::Server::new();
@@ -435,14 +1024,180 @@ mod tests {
         assert_eq!(raw_result.bytes, get_proto_complex_only_data())
     }
 
+    #[test]
+    fn test_decoder_options() {
+        let sr_settings = SrSettings::new(String::from("http://127.0.0.1:1234"));
+        let lenient = ProtoRawDecoder::new(sr_settings.clone());
+        let strict = ProtoRawDecoder::new(sr_settings).with_options(DecoderOptions {
+            strictness: Strictness::Strict,
+            ..Default::default()
+        });
+
+        let error = lenient.decode(Some(&[1, 0, 0, 0, 7, 0])).unwrap_err();
+        assert_eq!(error.wire_format(), None);
+        let error = strict.decode(Some(&[1, 0, 0, 0, 7, 0])).unwrap_err();
+        assert_eq!(
+            error.wire_format(),
+            Some(&WireFormatError::InvalidMagicByte(1))
+        );
+    }
+
     #[test]
     fn display_decoder() {
         let sr_settings = SrSettings::new(String::from("http://127.0.0.1:1234"));
         let decoder = ProtoRawDecoder::new(sr_settings);
         assert_eq!(
-            "ProtoRawDecoder { sr_settings: SrSettings { urls: [\"http://127.0.0.1:1234\"], client: Client, authorization: None }, cache: {} }"
+            "ProtoRawDecoder { sr_settings: SrSettings { urls: [\"http://127.0.0.1:1234\"], client: Client, authorization: None, dialect: Confluent, fetch_deleted: true, normalize: false, headers: {}, usage_listener: None, fallback: None, limits: SchemaLimits { max_schema_bytes: None, max_references: None, max_total_references: None, max_depth: None }, references_fetched: None }, cache: {}, id_strategy: Confluent, strictness: Lenient, slow_path: SlowPathWarnings { decode_threshold: None, registry_fetch: false }, policy: None, cached_schemas: CachedSchemas { schema_type: \"protobuf\", count: 0 } }"
                 .to_owned(),
             format!("{:?}", decoder)
         )
     }
+
+    #[cfg(feature = "proto_decoder")]
+    #[derive(Debug)]
+    struct Reverse;
+
+    #[cfg(feature = "proto_decoder")]
+    impl FieldEncryptor for Reverse {
+        fn encrypt(&self, _rule: &Rule, plaintext: &[u8]) -> Result<Vec<u8>, SRCError> {
+            Ok(plaintext.iter().rev().copied().collect())
+        }
+        fn decrypt(&self, _rule: &Rule, ciphertext: &[u8]) -> Result<Vec<u8>, SRCError> {
+            Ok(ciphertext.iter().rev().copied().collect())
+        }
+    }
+
+    #[cfg(feature = "proto_decoder")]
+    #[test]
+    fn test_field_encryption_round_trip() {
+        use crate::blocking::proto_decoder::ProtoDecoder;
+
+        let mut server = mockito::Server::new();
+        let body = r#"{"subject":"customer-value","version":1,"id":3,"schemaType":"PROTOBUF","schema":"syntax = \"proto3\";\npackage nl.openweb.data;\n\nmessage Customer {\n  string ssn = 1;\n  int64 id = 2;\n}\n","metadata":{"tags":{"nl.openweb.data.Customer.ssn":["PII"]}},"ruleSet":{"domainRules":[{"name":"encrypt","kind":"TRANSFORM","mode":"WRITEREAD","type":"ENCRYPT","tags":["PII"],"params":{"encrypt.kek.name":"kek"}}]}}"#;
+        let _m = server
+            .mock("GET", "/subjects/customer-value/versions/latest")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(body)
+            .create();
+        let _n = server
+            .mock("GET", "/schemas/ids/3?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(body)
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let encoder =
+            ProtoRawEncoder::new(sr_settings.clone()).with_field_encryptor(Arc::new(Reverse));
+        let strategy = SubjectNameStrategy::TopicNameStrategy(String::from("customer"), false);
+        let bytes = encoder
+            .encode(
+                &[10, 3, 49, 50, 51, 16, 5],
+                "nl.openweb.data.Customer",
+                &strategy,
+            )
+            .unwrap();
+        assert_eq!(
+            bytes,
+            vec![0, 0, 0, 0, 3, 0, 10, 4, 77, 122, 73, 120, 16, 5]
+        );
+
+        let decoder = ProtoDecoder::new(sr_settings).with_field_encryptor(Arc::new(Reverse));
+        let result = decoder.decode_with_context(Some(&bytes)).unwrap().unwrap();
+        assert_eq!(*result.data_bytes, [10, 3, 49, 50, 51, 16, 5]);
+    }
+
+    #[test]
+    fn test_usage_listener() {
+        let mut server = mockito::Server::new();
+        let _m = server
+            .mock("GET", "/subjects/nl.openweb.data.Heartbeat/versions/latest")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_proto_body(get_proto_hb_schema(), 7))
+            .create();
+        let _n = server
+            .mock("GET", "/schemas/ids/7?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_proto_body(get_proto_hb_schema(), 7))
+            .create();
+
+        let usages = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = usages.clone();
+        let sr_settings = SrSettings::new_builder(server.url())
+            .set_usage_listener(Arc::new(move |usage: &SchemaUsage| {
+                recorded.lock().unwrap().push(usage.clone())
+            }))
+            .build()
+            .unwrap();
+        let decoder = ProtoRawDecoder::new(sr_settings.clone());
+        let encoder = ProtoRawEncoder::new(sr_settings);
+        let strategy =
+            SubjectNameStrategy::RecordNameStrategy(String::from("nl.openweb.data.Heartbeat"));
+        for _ in 0..2 {
+            decoder.decode(Some(get_proto_hb_101())).unwrap();
+            encoder
+                .encode(
+                    get_proto_hb_101_only_data(),
+                    "nl.openweb.data.Heartbeat",
+                    &strategy,
+                )
+                .unwrap();
+        }
+
+        let usage = |kind, id, subject: Option<&str>| SchemaUsage {
+            kind,
+            id,
+            subject: subject.map(String::from),
+        };
+        let subject = Some("nl.openweb.data.Heartbeat");
+        assert_eq!(
+            *usages.lock().unwrap(),
+            vec![
+                usage(SchemaUsageKind::Fetched, 7, None),
+                usage(SchemaUsageKind::Decoded, 7, None),
+                usage(SchemaUsageKind::Fetched, 7, subject),
+                usage(SchemaUsageKind::Encoded, 7, subject),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_decoder_policy() {
+        let mut server = mockito::Server::new();
+        let _m = server
+            .mock("GET", "/schemas/ids/7?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(get_proto_body(get_proto_hb_schema(), 7))
+            .expect(1)
+            .create();
+        let _n = server
+            .mock("GET", "/schemas/ids/7/versions")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"[{"subject":"nl.openweb.data.Heartbeat","version":1}]"#)
+            .create();
+        let sr_settings = SrSettings::new(server.url());
+
+        let decoder = ProtoRawDecoder::new(sr_settings.clone())
+            .with_policy(Arc::new(SchemaIdPolicy::Deny(HashSet::from([7]))));
+        let error = decoder.decode(Some(get_proto_hb_101())).unwrap_err();
+        assert_eq!(error.policy_violation(), Some(&PolicyViolation { id: 7 }));
+        assert!(!error.cached);
+
+        let denied = HashSet::from([String::from("nl.openweb.data.Heartbeat")]);
+        let decoder = ProtoRawDecoder::new(sr_settings.clone())
+            .with_policy(Arc::new(SchemaSubjectPolicy::Deny(denied)));
+        let error = decoder.decode(Some(get_proto_hb_101())).unwrap_err();
+        assert_eq!(error.policy_violation(), Some(&PolicyViolation { id: 7 }));
+
+        let allowed = HashSet::from([String::from("nl.openweb.data.Heartbeat")]);
+        let decoder = ProtoRawDecoder::new(sr_settings)
+            .with_policy(Arc::new(SchemaSubjectPolicy::Allow(allowed)));
+        assert!(decoder.decode(Some(get_proto_hb_101())).is_ok());
+        _m.assert();
+    }
 }
@@ -1,19 +1,35 @@
 //! This module contains the code specific for the schema registry.
 
+use std::collections::HashMap;
+use std::io::Read;
 use std::str;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
 
 use dashmap::DashMap;
-use reqwest::blocking::{Client, ClientBuilder, RequestBuilder, Response};
-use reqwest::header;
-use reqwest::header::{HeaderName, ACCEPT, CONTENT_TYPE};
+use log::warn;
+use serde::de::DeserializeOwned;
 use serde_json::{json, Map, Value};
 
-use crate::error::SRCError;
+use crate::blocking::http::header::{HeaderName, ACCEPT, CONTENT_TYPE};
+use crate::blocking::http::{
+    header, Client, ClientBuilder, Error, Method, Proxy, RequestBuilder, Response, StatusCode,
+};
+use crate::error::{PolicyViolation, SRCError, Throttling};
+#[cfg(any(feature = "avro", feature = "json"))]
+use crate::migration::{migration_rules, versions_between, Migration, MigrationConfig};
+#[cfg(feature = "proto_descriptor")]
+use crate::proto_descriptor::to_sorted_proto_files;
 use crate::schema_registry_common::{
-    url_for_call, RawRegisteredSchema, RegisteredReference, RegisteredSchema, SchemaType,
-    SrAuthorization, SrCall, SubjectNameStrategy, SuppliedReference, SuppliedSchema,
+    compatibility_path, config_path, fetched_usage, is_throttling_status, metadata_path, mode_path,
+    normalize_if, parse_retry_after, subject_path, url_for_call, with_normalize,
+    CompatibilityLevel, CompatibilityResult, Mode, RawConfig, RawMode, RawRegisteredSchema,
+    RegisteredReference, RegisteredSchema, SchemaLimits, SchemaPolicy, SchemaType, SchemaUsage,
+    SchemaUsageKind, SchemaUsageListener, SrAuthorization, SrCall, SrDialect, SubjectNameStrategy,
+    SubjectVersion, SuppliedReference, SuppliedSchema, Version,
 };
+use crate::telemetry::{record_request, request_start, trace_headers};
 
 /// Settings used to do the calls to schema registry. For simple cases you can use `SrSettings::new`
 /// or the `SrSettingsBuilder`. But you can also use it directly so you can all the available
@@ -23,6 +39,14 @@ pub struct SrSettings {
     urls: Vec<String>,
     client: Client,
     authorization: SrAuthorization,
+    dialect: SrDialect,
+    fetch_deleted: bool,
+    normalize: bool,
+    headers: header::HeaderMap,
+    usage_listener: Option<Arc<dyn SchemaUsageListener>>,
+    fallback: Option<Arc<SrSettings>>,
+    limits: SchemaLimits,
+    references_fetched: Option<Arc<AtomicUsize>>,
 }
 
 /// Struct to create an SrSettings when used with multiple url's, authorization, custom headers, or
@@ -30,7 +54,14 @@ pub struct SrSettings {
 pub struct SrSettingsBuilder {
     urls: Vec<String>,
     authorization: SrAuthorization,
+    dialect: SrDialect,
+    fetch_deleted: bool,
+    normalize: bool,
     headers: DashMap<String, String>,
+    usage_listener: Option<Arc<dyn SchemaUsageListener>>,
+    fallback: Option<Arc<SrSettings>>,
+    limits: SchemaLimits,
+    user_agent: Option<String>,
     proxy: Option<String>,
     timeout: Duration,
 }
@@ -48,6 +79,14 @@ impl SrSettings {
             urls: vec![url],
             client: Client::new(),
             authorization: SrAuthorization::None,
+            dialect: SrDialect::default(),
+            fetch_deleted: true,
+            normalize: false,
+            headers: header::HeaderMap::new(),
+            usage_listener: None,
+            fallback: None,
+            limits: SchemaLimits::default(),
+            references_fetched: None,
         }
     }
 
@@ -57,7 +96,14 @@ impl SrSettings {
         SrSettingsBuilder {
             urls: vec![url],
             authorization: SrAuthorization::None,
+            dialect: SrDialect::default(),
+            fetch_deleted: true,
+            normalize: false,
             headers: DashMap::new(),
+            usage_listener: None,
+            fallback: None,
+            limits: SchemaLimits::default(),
+            user_agent: None,
             proxy: None,
             timeout: Duration::from_secs(30),
         }
@@ -66,6 +112,77 @@ impl SrSettings {
     pub(crate) fn url(&self) -> &str {
         &self.urls[0]
     }
+
+    /// Gives a copy of the settings adding the headers to every call done with it, on top of the
+    /// headers set with the builder. For headers that differ per call, like a tenant id, or the
+    /// user agent of a specific team. The copy shares the http client with the original.
+    /// ```
+    /// use schema_registry_converter::blocking::schema_registry::SrSettings;
+    /// let sr_settings = SrSettings::new(String::from("http://localhost:8081"));
+    /// let tenant_settings = sr_settings
+    ///     .with_headers(&[("x-tenant-id", "tenant-a"), ("user-agent", "billing/1.2")])
+    ///     .unwrap();
+    /// ```
+    pub fn with_headers(&self, headers: &[(&str, &str)]) -> Result<SrSettings, SRCError> {
+        let mut settings = self.clone();
+        for (key, value) in headers {
+            let (name, value) = to_header(key, value)?;
+            settings.headers.insert(name, value);
+        }
+        Ok(settings)
+    }
+
+    /// The urls to try in order for a call, with the settings to do the call with. For reads the
+    /// urls of the fallback are tried after the urls of these settings.
+    fn urls_for(&self, read: bool) -> Vec<(&str, &SrSettings)> {
+        let mut urls: Vec<(&str, &SrSettings)> =
+            self.urls.iter().map(|url| (url.as_str(), self)).collect();
+        if let (true, Some(fallback)) = (read, &self.fallback) {
+            urls.extend(fallback.urls.iter().map(|url| (url.as_str(), &**fallback)));
+        }
+        urls
+    }
+
+    /// Gives a copy of the settings counting the referenced schema's fetched with it, to limit the
+    /// total references of a single schema. Only counts when the total is limited.
+    pub(crate) fn counting_references(&self) -> SrSettings {
+        let mut settings = self.clone();
+        settings.references_fetched = self
+            .limits
+            .max_total_references
+            .map(|_| Arc::new(AtomicUsize::new(0)));
+        settings
+    }
+
+    /// Gives the usage of a schema to the listener, when set.
+    pub(crate) fn record_usage(&self, kind: SchemaUsageKind, id: u32, subject: Option<&str>) {
+        if let Some(listener) = &self.usage_listener {
+            listener.on_usage(&SchemaUsage {
+                kind,
+                id,
+                subject: subject.map(String::from),
+            });
+        }
+    }
+}
+
+fn to_header(key: &str, value: &str) -> Result<(HeaderName, header::HeaderValue), SRCError> {
+    let name = match HeaderName::from_bytes(key.as_bytes()) {
+        Ok(h) => h,
+        Err(e) => {
+            return Err(SRCError::non_retryable_with_cause(
+                e,
+                &format!("could not create HeaderName from {}", key),
+            ));
+        }
+    };
+    match header::HeaderValue::from_str(value) {
+        Ok(v) => Ok((name, v)),
+        Err(e) => Err(SRCError::non_retryable_with_cause(
+            e,
+            &format!("could not create HeaderValue for {}", key),
+        )),
+    }
 }
 
 /// Builder for SrSettings
@@ -76,6 +193,7 @@ impl SrSettings {
 ///     .add_url(String::from("http://localhost:8082"))
 ///     .set_token_authorization("some_json_web_token_for_example")
 ///     .add_header("foo", "bar")
+///     .set_user_agent("my-service/1.0")
 ///     .set_proxy("http://localhost:8888")
 ///     .set_timeout(Duration::from_secs(5))
 ///     .build().unwrap();
@@ -108,12 +226,71 @@ impl SrSettingsBuilder {
         self
     }
 
+    /// Sets the implementation of the schema registry, to tolerate the differences of Karapace and
+    /// Redpanda.
+    pub fn set_dialect(&mut self, dialect: SrDialect) -> &mut SrSettingsBuilder {
+        self.dialect = dialect;
+        self
+    }
+
+    /// Sets whether schema's are fetched by id including the deleted schema's, which is the default.
+    /// Older schema registries don't support this, for which it's turned off automatically when they
+    /// respond with bad request.
+    pub fn set_fetch_deleted(&mut self, fetch_deleted: bool) -> &mut SrSettingsBuilder {
+        self.fetch_deleted = fetch_deleted;
+        self
+    }
+
+    /// Sets whether schema's are normalized before they are registered, looked up or checked for
+    /// compatibility, so schema's only differing in formatting don't get registered as new versions.
+    /// The schema is normalized with `normalize_schema`, and the normalize parameter is added to the
+    /// call, so the schema registry normalizes it too. Off by default.
+    pub fn set_normalize(&mut self, normalize: bool) -> &mut SrSettingsBuilder {
+        self.normalize = normalize;
+        self
+    }
+
     /// Adds a custom header that will be added to every call.
     pub fn add_header(&mut self, key: &str, value: &str) -> &mut SrSettingsBuilder {
         self.headers.insert(String::from(key), String::from(value));
         self
     }
 
+    /// Sets the User-Agent used for every call, so the traffic on the schema registry can be
+    /// attributed to the application. Can be overridden for a specific call with
+    /// `SrSettings::with_headers`.
+    pub fn set_user_agent(&mut self, user_agent: &str) -> &mut SrSettingsBuilder {
+        self.user_agent = Some(String::from(user_agent));
+        self
+    }
+
+    /// Sets the listener receiving every schema fetched from the schema registry, and the first use
+    /// of a schema by the avro and json encoders and decoders using the settings.
+    pub fn set_usage_listener(
+        &mut self,
+        usage_listener: Arc<dyn SchemaUsageListener>,
+    ) -> &mut SrSettingsBuilder {
+        self.usage_listener = Some(usage_listener);
+        self
+    }
+
+    /// Sets the settings of a secondary schema registry, like a follower or mirror of the primary
+    /// one, used for reads when the call failed for all the urls, so consumers can keep decoding
+    /// during an outage. The fallback is never written to, and has its own urls, credentials and
+    /// client. The fallback of the fallback is not used.
+    pub fn set_fallback(&mut self, fallback: SrSettings) -> &mut SrSettingsBuilder {
+        self.fallback = Some(Arc::new(fallback));
+        self
+    }
+
+    /// Sets the limits of the schema's fetched from the schema registry, so a malicious or buggy
+    /// producer can't make a consumer run out of memory with a pathological schema. The limits are
+    /// checked for each schema fetched, including the referenced schema's.
+    pub fn set_schema_limits(&mut self, limits: SchemaLimits) -> &mut SrSettingsBuilder {
+        self.limits = limits;
+        self
+    }
+
     /// Sets a proxy that will be used for every call.
     pub fn set_proxy(&mut self, proxy_url: &str) -> &mut SrSettingsBuilder {
         self.proxy = Some(String::from(proxy_url));
@@ -134,7 +311,19 @@ impl SrSettingsBuilder {
     /// and they all have higher precedence than your own builder's configuration.
     /// This means that if you set a proxy both with this builde rand your
     /// client's builder, this builder will overwrite the client's builder.
+    #[cfg(not(feature = "blocking_native"))]
     pub fn build_with(&mut self, client: ClientBuilder) -> Result<SrSettings, SRCError> {
+        self.build_settings(client)
+    }
+
+    /// Build the settings.
+    ///
+    /// If you need your own client, see `build_with`.
+    pub fn build(&mut self) -> Result<SrSettings, SRCError> {
+        self.build_settings(Client::builder())
+    }
+
+    fn build_settings(&mut self, client: ClientBuilder) -> Result<SrSettings, SRCError> {
         let client = self.build_client(client)?;
         let urls = self.urls.clone();
         let authorization = self.authorization.clone();
@@ -142,35 +331,31 @@ impl SrSettingsBuilder {
             urls,
             client,
             authorization,
+            dialect: self.dialect,
+            fetch_deleted: self.fetch_deleted,
+            normalize: self.normalize,
+            headers: header::HeaderMap::new(),
+            usage_listener: self.usage_listener.clone(),
+            fallback: self.fallback.clone(),
+            limits: self.limits,
+            references_fetched: None,
         })
     }
 
-    /// Build the settings.
-    ///
-    /// If you need your own client, see `build_with`.
-    pub fn build(&mut self) -> Result<SrSettings, SRCError> {
-        self.build_with(Client::builder())
-    }
-
     fn build_client(&mut self, mut builder: ClientBuilder) -> Result<Client, SRCError> {
         if !self.headers.is_empty() {
             let mut header_map = header::HeaderMap::new();
             for entry in self.headers.iter() {
-                let header_name = match HeaderName::from_bytes(entry.key().as_bytes()) {
-                    Ok(h) => h,
-                    Err(e) => {
-                        return Err(SRCError::non_retryable_with_cause(
-                            e,
-                            &format!("could not create headername from {}", entry.key()),
-                        ));
-                    }
-                };
-                header_map.insert(header_name, entry.value().parse().unwrap());
+                let (name, value) = to_header(entry.key(), entry.value())?;
+                header_map.insert(name, value);
             }
             builder = builder.default_headers(header_map);
         }
+        if let Some(user_agent) = &self.user_agent {
+            builder = builder.user_agent(user_agent.as_str());
+        }
         if self.proxy.is_some() {
-            match reqwest::Proxy::all(self.proxy.as_ref().unwrap()) {
+            match Proxy::all(self.proxy.as_ref().unwrap()) {
                 Ok(v) => builder = builder.proxy(v),
                 Err(e) => return Err(SRCError::non_retryable_with_cause(e, "invalid proxy value")),
             };
@@ -208,6 +393,27 @@ pub fn get_schema_by_id_and_type(
     }
 }
 
+/// Gets the schema like get_schema_by_id_and_type, used by the decoders. When the policy checks
+/// the subjects, the subjects using the schema are fetched first, giving an error with a
+/// `PolicyViolation` when they are not allowed.
+pub(crate) fn get_allowed_schema(
+    id: u32,
+    sr_settings: &SrSettings,
+    schema_type: SchemaType,
+    policy: Option<&dyn SchemaPolicy>,
+) -> Result<RegisteredSchema, SRCError> {
+    if let Some(p) = policy.filter(|p| p.checks_subjects()) {
+        let subjects: Vec<String> = schema_usages(sr_settings, id)?
+            .into_iter()
+            .map(|v| v.subject)
+            .collect();
+        if !p.allows_subjects(id, &subjects) {
+            return Err(PolicyViolation { id }.into());
+        }
+    }
+    get_schema_by_id_and_type(id, sr_settings, schema_type)
+}
+
 /// Gets the registered schema by supplying a SubjectNameStrategy. This is used to as part of the
 /// encoding so we get the correct schema and id, and possible references.
 pub fn get_schema_by_subject(
@@ -224,10 +430,160 @@ pub fn get_schema_by_subject(
     }
 }
 
+/// Gets a specific version of the registered schema for the subject of the SubjectNameStrategy. Any
+/// schema supplied with the strategy is ignored, the version needs to be present already.
+pub fn get_schema_by_subject_and_version(
+    sr_settings: &SrSettings,
+    subject_name_strategy: &SubjectNameStrategy,
+    version: u32,
+) -> Result<RegisteredSchema, SRCError> {
+    let subject = subject_name_strategy.get_subject()?;
+    let raw_schema = perform_sr_call(
+        sr_settings,
+        SrCall::GetBySubjectAndVersion(&subject, version),
+    )?;
+    raw_to_registered_schema(raw_schema, None)
+}
+
+/// Gets the version of the registered schema for the subject of the SubjectNameStrategy, which can
+/// be the latest, a specific version, or the latest with the metadata. Any schema supplied with the
+/// strategy is ignored, the version needs to be present already.
+pub fn get_schema_by_subject_with_version(
+    sr_settings: &SrSettings,
+    subject_name_strategy: &SubjectNameStrategy,
+    version: &Version,
+) -> Result<RegisteredSchema, SRCError> {
+    let subject = subject_name_strategy.get_subject()?;
+    match version {
+        Version::Latest => {
+            let raw_schema = perform_sr_call(sr_settings, SrCall::GetLatest(&subject))?;
+            raw_to_registered_schema(raw_schema, None)
+        }
+        Version::Number(v) => {
+            get_schema_by_subject_and_version(sr_settings, subject_name_strategy, *v)
+        }
+        Version::LatestWithMetadata(key, value) => {
+            let metadata = HashMap::from([(key.clone(), value.clone())]);
+            get_latest_with_metadata(sr_settings, &subject, &metadata)
+        }
+    }
+}
+
+/// Gets the latest version of the subject having all the metadata, like `application.version`,
+/// so a consumer can be pinned to the major version of the schema it was built with.
+pub fn get_latest_with_metadata(
+    sr_settings: &SrSettings,
+    subject: &str,
+    metadata: &HashMap<String, String>,
+) -> Result<RegisteredSchema, SRCError> {
+    let path = metadata_path(subject, metadata);
+    let raw_schema = perform_json_call(sr_settings, Method::GET, &path, None)?;
+    raw_to_registered_schema(raw_schema, None)
+}
+
+/// Gets the migration from the schema with the id to the latest version of the subject with the
+/// metadata, with the migration rules of the versions in between.
+#[cfg(any(feature = "avro", feature = "json"))]
+pub(crate) fn get_migration(
+    sr_settings: &SrSettings,
+    config: &MigrationConfig,
+    writer_id: u32,
+) -> Result<Migration, SRCError> {
+    let reader = get_latest_with_metadata(sr_settings, &config.subject, &config.metadata)?;
+    if reader.id == writer_id {
+        return Ok(Migration {
+            reader_id: reader.id,
+            rules: vec![],
+        });
+    }
+    let reader_version = match reader.version {
+        Some(v) => v,
+        None => {
+            return Err(SRCError::non_retryable_without_cause(
+                "Could not get version from response",
+            ))
+        }
+    };
+    let usages = schema_usages(sr_settings, writer_id)?;
+    let writer_version = match usages.into_iter().find(|u| u.subject == config.subject) {
+        Some(u) => u.version,
+        None => {
+            return Err(SRCError::non_retryable_without_cause(&format!(
+                "schema with id {} is not registered for subject {}",
+                writer_id, config.subject
+            )))
+        }
+    };
+    let versions = get_subject_versions(sr_settings, &config.subject, false)?;
+    let mut schemas = Vec::new();
+    for version in versions_between(&versions, writer_version, reader_version) {
+        let call = SrCall::GetBySubjectAndVersion(&config.subject, version);
+        let raw_schema = perform_sr_call(sr_settings, call)?;
+        schemas.push(raw_to_registered_schema(raw_schema, None)?);
+    }
+    Ok(Migration {
+        reader_id: reader.id,
+        rules: migration_rules(writer_version < reader_version, &schemas),
+    })
+}
+
+/// Looks up the id and version of a schema which should already be registered for the subject,
+/// without registering it. The schema is compared by the schema registry, so it doesn't need to be
+/// exactly the same string. References are not supported, the schema needs to be self contained.
+pub fn lookup_schema(
+    sr_settings: &SrSettings,
+    subject: &str,
+    schema_type: &SchemaType,
+    schema: &str,
+) -> Result<RegisteredSchema, SRCError> {
+    let schema_type = match schema_type {
+        SchemaType::Avro => String::from("AVRO"),
+        SchemaType::Protobuf => String::from("PROTOBUF"),
+        SchemaType::Json => String::from("JSON"),
+        SchemaType::Other(v) => v.clone(),
+    };
+    let body = get_body(sr_settings.normalize, &schema_type, schema, &[])?;
+    let raw_schema = perform_sr_call(sr_settings, SrCall::PostForVersion(subject, &body))?;
+    raw_to_registered_schema(raw_schema, None)
+}
+
+/// Checks whether the exact schema, with the references, is already registered for the subject,
+/// without registering it, giving the schema with the id and version when it is. Unlike
+/// `lookup_schema`, not finding the schema or the subject is not an error.
+pub fn check_schema_registered(
+    sr_settings: &SrSettings,
+    subject: &str,
+    schema_type: &SchemaType,
+    schema: &str,
+    references: &[RegisteredReference],
+) -> Result<Option<RegisteredSchema>, SRCError> {
+    let schema_type = match schema_type {
+        SchemaType::Avro => String::from("AVRO"),
+        SchemaType::Protobuf => String::from("PROTOBUF"),
+        SchemaType::Json => String::from("JSON"),
+        SchemaType::Other(v) => v.clone(),
+    };
+    let body = get_body(sr_settings.normalize, &schema_type, schema, references)?;
+    let path = sr_settings
+        .dialect
+        .with_deleted(subject_path(subject, None), false);
+    let path = with_normalize(path, sr_settings.normalize);
+    match perform_json_call(sr_settings, Method::POST, &path, Some(&body)) {
+        Ok(raw_schema) => Ok(Some(raw_to_registered_schema(raw_schema, None)?)),
+        // subject not found or schema not found
+        Err(e) if sr_settings.dialect.is_not_found(&e) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
 pub fn get_referenced_schema(
     sr_settings: &SrSettings,
     registered_reference: &RegisteredReference,
 ) -> Result<RegisteredSchema, SRCError> {
+    if let Some(fetched) = &sr_settings.references_fetched {
+        let fetched = fetched.fetch_add(1, Ordering::Relaxed) + 1;
+        sr_settings.limits.check_total_references(fetched)?;
+    }
     let raw_schema = perform_sr_call(
         sr_settings,
         SrCall::GetBySubjectAndVersion(&registered_reference.subject, registered_reference.version),
@@ -274,9 +630,49 @@ fn raw_to_registered_schema(
         schema_type,
         schema,
         references,
+        subject: raw_schema.subject,
+        version: raw_schema.version,
+        metadata: raw_schema.metadata,
+        rule_set: raw_schema.rule_set,
+        guid: raw_schema.guid,
+        deleted: raw_schema.deleted.unwrap_or(false),
     })
 }
 
+/// Registers all the files of a serialized FileDescriptorSet, like the one written by prost-build
+/// using `file_descriptor_set_path`. Each file is registered after the files it imports, under the
+/// subject given by `subject_for_file` for the file name, with the imports as references. Gives the
+/// id of the schema for each message, by the full name of the message.
+#[cfg(feature = "proto_descriptor")]
+pub fn register_descriptor_set<F: Fn(&str) -> String>(
+    sr_settings: &SrSettings,
+    descriptor_set: &[u8],
+    subject_for_file: F,
+) -> Result<HashMap<String, u32>, SRCError> {
+    let mut registered: Vec<RegisteredReference> = Vec::new();
+    let mut ids = HashMap::new();
+    for file in to_sorted_proto_files(descriptor_set)? {
+        let references: Vec<RegisteredReference> = file
+            .dependencies
+            .iter()
+            .filter_map(|d| registered.iter().find(|r| &r.name == d).cloned())
+            .collect();
+        let subject = subject_for_file(&file.name);
+        let body = get_body(sr_settings.normalize, "PROTOBUF", &file.schema, &references)?;
+        let id = call_and_get_id(sr_settings, SrCall::PostNew(&subject, &body))?;
+        let version = call_and_get_version(sr_settings, SrCall::PostForVersion(&subject, &body))?;
+        for message in file.messages {
+            ids.insert(message, id);
+        }
+        registered.push(RegisteredReference {
+            name: file.name,
+            subject,
+            version,
+        });
+    }
+    Ok(ids)
+}
+
 /// Handles posting the schema, and getting back the id. When the schema is already in the schema
 /// registry, the matching id is returned. When it's not it depends on the settings of the schema
 /// registry. The default config will check if the schema is backwards compatible. One of the ways
@@ -306,19 +702,36 @@ pub fn post_schema(
             ));
         }
     };
-    let body = get_body(&schema_type, &schema.schema, &references);
+    let body = get_body(
+        sr_settings.normalize,
+        &schema_type,
+        &schema.schema,
+        &references,
+    )?;
     let id = call_and_get_id(sr_settings, SrCall::PostNew(&subject, &body))?;
     Ok(RegisteredSchema {
         id,
         schema_type: schema.schema_type,
         schema: schema.schema,
         references,
+        subject: Some(subject),
+        version: None,
+        metadata: None,
+        rule_set: None,
+        guid: None,
+        deleted: false,
     })
 }
 
-fn get_body(schema_type: &str, schema: &str, references: &[RegisteredReference]) -> String {
+fn get_body(
+    normalize: bool,
+    schema_type: &str,
+    schema: &str,
+    references: &[RegisteredReference],
+) -> Result<String, SRCError> {
     let mut root_element = Map::new();
-    root_element.insert(String::from("schema"), Value::String(String::from(schema)));
+    let schema = normalize_if(normalize, schema_type, schema)?;
+    root_element.insert(String::from("schema"), Value::String(schema));
     root_element.insert(
         String::from("schemaType"),
         Value::String(String::from(schema_type)),
@@ -328,7 +741,7 @@ fn get_body(schema_type: &str, schema: &str, references: &[RegisteredReference])
         root_element.insert(String::from("references"), Value::Array(values));
     }
     let schema_element = Value::Object(root_element);
-    schema_element.to_string()
+    Ok(schema_element.to_string())
 }
 
 fn call_and_get_id(sr_setting: &SrSettings, sr_call: SrCall) -> Result<u32, SRCError> {
@@ -372,7 +785,12 @@ fn post_reference(
             ));
         }
     };
-    let body = get_body(schema_type, &reference.schema, &references);
+    let body = get_body(
+        sr_settings.normalize,
+        schema_type,
+        &reference.schema,
+        &references,
+    )?;
     perform_sr_call(sr_settings, SrCall::PostNew(&reference.subject, &body))?;
     let version = call_and_get_version(
         sr_settings,
@@ -389,26 +807,48 @@ pub fn perform_sr_call(
     sr_settings: &SrSettings,
     sr_call: SrCall,
 ) -> Result<RawRegisteredSchema, SRCError> {
-    let url_count = sr_settings.urls.len();
+    let urls = sr_settings.urls_for(sr_call.is_read());
     let mut n = 0;
-    loop {
-        let result = perform_single_sr_call(
-            &sr_settings.urls[n],
-            &sr_settings.client,
-            &sr_settings.authorization,
-            sr_call,
-        );
-        if result.is_ok() || n + 1 == url_count {
+    let result = loop {
+        let (url, settings) = urls[n];
+        let start = request_start();
+        let result = perform_single_sr_call(url, settings, &sr_settings.limits, sr_call);
+        record_request(start, &result);
+        if !matches!(&result, Err(e) if try_next_url(&urls, n, e)) {
             break result;
         }
         n += 1
+    };
+    let raw = sr_settings.limits.check(result?)?;
+    if let Some(listener) = &sr_settings.usage_listener {
+        if let Some(usage) = fetched_usage(sr_call, &raw) {
+            listener.on_usage(&usage);
+        }
+    }
+    Ok(raw)
+}
+
+/// Adds the headers of the settings, and the headers propagating the trace context, to the call.
+fn add_headers(builder: RequestBuilder, headers: &header::HeaderMap) -> RequestBuilder {
+    let mut headers = headers.clone();
+    for (key, value) in trace_headers() {
+        if let Ok((name, value)) = to_header(&key, &value) {
+            headers.insert(name, value);
+        }
+    }
+    if headers.is_empty() {
+        builder
+    } else {
+        builder.headers(headers)
     }
 }
 
 fn apply_authentication(
     builder: RequestBuilder,
     authentication: &SrAuthorization,
-) -> Result<Response, reqwest::Error> {
+    headers: &header::HeaderMap,
+) -> Result<Response, Error> {
+    let builder = add_headers(builder, headers);
     match authentication {
         SrAuthorization::None => builder.send(),
         SrAuthorization::Token(token) => builder.bearer_auth(token).send(),
@@ -422,32 +862,136 @@ fn apply_authentication(
     }
 }
 
-fn perform_single_sr_call(
-    base_url: &str,
-    client: &Client,
-    authentication: &SrAuthorization,
-    sr_call: SrCall,
-) -> Result<RawRegisteredSchema, SRCError> {
-    let url = url_for_call(&sr_call, base_url);
-    let builder = match sr_call {
+fn sr_request(client: &Client, url: &str, sr_call: SrCall) -> RequestBuilder {
+    match sr_call {
         SrCall::GetById(_) | SrCall::GetLatest(_) | SrCall::GetBySubjectAndVersion(_, _) => {
-            client.get(&url)
+            client.get(url)
         }
         SrCall::PostNew(_, body) | SrCall::PostForVersion(_, body) => client
-            .post(&url)
+            .post(url)
             .body(String::from(body))
             .header(CONTENT_TYPE, "application/vnd.schemaregistry.v1+json")
             .header(ACCEPT, "application/vnd.schemaregistry.v1+json"),
+    }
+}
+
+/// Reads the body of the response, giving an error as soon as it's known to be larger than the
+/// limits allow, from the Content-Length or while reading, so a large response is never read
+/// completely.
+fn read_body(mut response: Response, limits: &SchemaLimits) -> Result<Vec<u8>, SRCError> {
+    let length = response
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    if let Some(length) = length {
+        limits.check_response_bytes(length)?;
+    }
+    let mut body = Vec::new();
+    let read = match limits.max_response_bytes() {
+        Some(max) => response.take(max as u64 + 1).read_to_end(&mut body),
+        None => response.read_to_end(&mut body),
+    };
+    match read {
+        Ok(_) => {
+            limits.check_response_bytes(body.len() as u64)?;
+            Ok(body)
+        }
+        Err(e) => Err(SRCError::retryable_with_cause(
+            e,
+            "could not read the response of the schema registry",
+        )),
+    }
+}
+
+/// Whether a failed call should be tried with the next url. All the urls of the settings are tried,
+/// but the fallback only when the error is retriable, like a connection error, a server error or
+/// throttling, so a schema that doesn't exist isn't looked up in the fallback.
+fn try_next_url(urls: &[(&str, &SrSettings)], n: usize, error: &SRCError) -> bool {
+    match urls.get(n + 1) {
+        None => false,
+        Some((_, next)) => error.retriable || std::ptr::eq(urls[n].1, *next),
+    }
+}
+
+fn is_throttling(status: StatusCode) -> bool {
+    is_throttling_status(status.as_u16())
+}
+
+/// Retriable error for a throttled call, with the time to wait from the Retry-After header. The
+/// call is not tried again after waiting, that's up to the caller, for example by pausing the
+/// consumer for the duration.
+fn throttling_error(response: &Response) -> SRCError {
+    let retry_after = response
+        .headers()
+        .get(header::RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_retry_after);
+    SRCError::from(Throttling {
+        status: response.status().as_u16(),
+        retry_after,
+    })
+}
+
+/// Older schema registries respond with bad request to fetching a schema by id including the
+/// deleted schema's, in which case it's fetched again without.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(
+        name = "schema_registry_call",
+        skip_all,
+        fields(
+            base_url = %base_url,
+            schema.id = sr_call.schema_id(),
+            subject = sr_call.subject()
+        ),
+        err
+    )
+)]
+fn perform_single_sr_call(
+    base_url: &str,
+    settings: &SrSettings,
+    limits: &SchemaLimits,
+    sr_call: SrCall,
+) -> Result<RawRegisteredSchema, SRCError> {
+    let (client, authentication, headers) =
+        (&settings.client, &settings.authorization, &settings.headers);
+    let (dialect, fetch_deleted, normalize) =
+        (settings.dialect, settings.fetch_deleted, settings.normalize);
+    let url = url_for_call(&sr_call, base_url, dialect, fetch_deleted, normalize);
+    let call = apply_authentication(sr_request(client, &url, sr_call), authentication, headers);
+    let deleted_in_url =
+        fetch_deleted && dialect == SrDialect::Confluent && matches!(sr_call, SrCall::GetById(_));
+    let call = match call {
+        Ok(v) if deleted_in_url && v.status() == StatusCode::BAD_REQUEST => {
+            let url = url_for_call(&sr_call, base_url, dialect, false, normalize);
+            apply_authentication(sr_request(client, &url, sr_call), authentication, headers)
+        }
+        call => call,
     };
-    let call = apply_authentication(builder, authentication);
     match call {
-        Ok(v) => match v.json::<RawRegisteredSchema>() {
-            Ok(r) => Ok(r),
-            Err(e) => Err(SRCError::non_retryable_with_cause(
-                e,
-                "could not parse to RawRegisteredSchema, schema might not exist on this schema registry, the http call failed, cause will give more information",
-            )),
-        },
+        Ok(v) if is_throttling(v.status()) => Err(throttling_error(&v)),
+        Ok(v) if v.status().is_server_error() => {
+            let status = v.status();
+            let response = v.text().unwrap_or_default();
+            Err(SRCError::retryable_with_cause(
+                response,
+                &format!(
+                    "schema registry responded with status {} for {}",
+                    status, url
+                ),
+            ))
+        }
+        Ok(v) => {
+            let body = read_body(v, limits)?;
+            match serde_json::from_slice::<RawRegisteredSchema>(&body) {
+                Ok(r) => Ok(r),
+                Err(e) => Err(SRCError::non_retryable_with_cause(
+                    e,
+                    "could not parse to RawRegisteredSchema, schema might not exist on this schema registry, the http call failed, cause will give more information",
+                )),
+            }
+        }
         Err(e) => Err(SRCError::retryable_with_cause(
             e,
             "http call to schema registry failed",
@@ -456,15 +1000,17 @@ fn perform_single_sr_call(
 }
 
 pub fn get_all_subjects(sr_settings: &SrSettings) -> Result<Vec<String>, SRCError> {
-    let url_count = sr_settings.urls.len();
+    let urls = sr_settings.urls_for(true);
     let mut n = 0;
     loop {
+        let (url, settings) = urls[n];
         let result = perform_single_subjects_call(
-            &sr_settings.urls[n],
-            &sr_settings.client,
-            &sr_settings.authorization,
+            url,
+            &settings.client,
+            &settings.authorization,
+            &settings.headers,
         );
-        if result.is_ok() || n + 1 == url_count {
+        if !matches!(&result, Err(e) if try_next_url(&urls, n, e)) {
             break result;
         }
         n += 1
@@ -475,11 +1021,13 @@ fn perform_single_subjects_call(
     base_url: &str,
     client: &Client,
     authentication: &SrAuthorization,
+    headers: &header::HeaderMap,
 ) -> Result<Vec<String>, SRCError> {
     let url = format!("{}/subjects", base_url);
     let builder = client.get(url);
-    let call = apply_authentication(builder, authentication);
+    let call = apply_authentication(builder, authentication, headers);
     match call {
+        Ok(v) if is_throttling(v.status()) => Err(throttling_error(&v)),
         Ok(v) => match v.json::<Vec<String>>() {
             Ok(r) => Ok(r),
             Err(e) => Err(SRCError::non_retryable_with_cause(
@@ -494,17 +1042,80 @@ fn perform_single_subjects_call(
     }
 }
 
+/// Gets all the schema's from the schema registry, for each subject and version. Used to find a
+/// schema by something other than the id, like the fingerprint of the avro single object encoding.
+pub fn get_all_schemas(sr_settings: &SrSettings) -> Result<Vec<RegisteredSchema>, SRCError> {
+    let urls = sr_settings.urls_for(true);
+    let mut n = 0;
+    let raw_schemas = loop {
+        let (url, settings) = urls[n];
+        let result = perform_single_schemas_call(
+            url,
+            &settings.client,
+            &settings.authorization,
+            &settings.headers,
+        );
+        if !matches!(&result, Err(e) if try_next_url(&urls, n, e)) {
+            break result?;
+        }
+        n += 1
+    };
+    let mut schemas = Vec::with_capacity(raw_schemas.len());
+    for raw_schema in raw_schemas {
+        let (subject, id) = (raw_schema.subject.clone(), raw_schema.id);
+        let registered_schema = match sr_settings.limits.check(raw_schema) {
+            Ok(raw_schema) => raw_to_registered_schema(raw_schema, None),
+            Err(e) => Err(e),
+        };
+        match registered_schema {
+            Ok(schema) => schemas.push(schema),
+            Err(e) => warn!(
+                "Skipping schema with id {:?} of subject {:?}: {}",
+                id, subject, e
+            ),
+        }
+    }
+    Ok(schemas)
+}
+
+fn perform_single_schemas_call(
+    base_url: &str,
+    client: &Client,
+    authentication: &SrAuthorization,
+    headers: &header::HeaderMap,
+) -> Result<Vec<RawRegisteredSchema>, SRCError> {
+    let url = format!("{}/schemas", base_url);
+    let builder = client.get(url);
+    let call = apply_authentication(builder, authentication, headers);
+    match call {
+        Ok(v) if is_throttling(v.status()) => Err(throttling_error(&v)),
+        Ok(v) => match v.json::<Vec<RawRegisteredSchema>>() {
+            Ok(r) => Ok(r),
+            Err(e) => Err(SRCError::non_retryable_with_cause(
+                e,
+                "could not parse to list of schema's, the http call failed, cause will give more information",
+            )),
+        },
+        Err(e) => Err(SRCError::retryable_with_cause(
+            e,
+            "http call to schema registry failed",
+        )),
+    }
+}
+
 pub fn get_all_versions(sr_settings: &SrSettings, subject: String) -> Result<Vec<u32>, SRCError> {
-    let url_count = sr_settings.urls.len();
+    let urls = sr_settings.urls_for(true);
     let mut n = 0;
     loop {
+        let (url, settings) = urls[n];
         let result = perform_single_versions_call(
-            &sr_settings.urls[n],
-            &sr_settings.client,
-            &sr_settings.authorization,
+            url,
+            &settings.client,
+            &settings.authorization,
+            &settings.headers,
             &subject,
         );
-        if result.is_ok() || n + 1 == url_count {
+        if !matches!(&result, Err(e) if try_next_url(&urls, n, e)) {
             break result;
         }
         n += 1
@@ -515,12 +1126,14 @@ fn perform_single_versions_call(
     base_url: &str,
     client: &Client,
     authentication: &SrAuthorization,
+    headers: &header::HeaderMap,
     subject: &String,
 ) -> Result<Vec<u32>, SRCError> {
-    let url = format!("{}/subjects/{}/versions", base_url, subject);
+    let url = format!("{}{}/versions", base_url, subject_path(subject, None));
     let builder = client.get(url);
-    let call = apply_authentication(builder, authentication);
+    let call = apply_authentication(builder, authentication, headers);
     match call {
+        Ok(v) if is_throttling(v.status()) => Err(throttling_error(&v)),
         Ok(v) => match v.json::<Vec<u32>>() {
             Ok(r) => Ok(r),
             Err(e) => Err(SRCError::non_retryable_with_cause(
@@ -535,11 +1148,334 @@ fn perform_single_versions_call(
     }
 }
 
+/// Lists the subjects, including the soft deleted subjects when `deleted` is set.
+pub fn list_subjects(sr_settings: &SrSettings, deleted: bool) -> Result<Vec<String>, SRCError> {
+    let path = sr_settings
+        .dialect
+        .with_deleted(String::from("/subjects"), deleted);
+    perform_json_call(sr_settings, Method::GET, &path, None)
+}
+
+/// Lists the versions of the subject, including the soft deleted versions when `deleted` is set.
+pub fn get_subject_versions(
+    sr_settings: &SrSettings,
+    subject: &str,
+    deleted: bool,
+) -> Result<Vec<u32>, SRCError> {
+    let subject = subject_path(subject, None);
+    let path = sr_settings
+        .dialect
+        .with_deleted(format!("{}/versions", subject), deleted);
+    perform_json_call(sr_settings, Method::GET, &path, None)
+}
+
+/// Deletes the subject, giving the versions which were deleted. Without `permanent` it's a soft
+/// delete, and the schema's can still be retrieved by id. A permanent delete also removes the
+/// schema's, but the schema registry only allows it for a subject which was soft deleted before.
+pub fn delete_subject(
+    sr_settings: &SrSettings,
+    subject: &str,
+    permanent: bool,
+) -> Result<Vec<u32>, SRCError> {
+    let path = format!("{}?permanent={}", subject_path(subject, None), permanent);
+    perform_json_call(sr_settings, Method::DELETE, &path, None)
+}
+
+/// Soft deletes the version of the subject, giving the deleted version.
+pub fn delete_version(
+    sr_settings: &SrSettings,
+    subject: &str,
+    version: u32,
+) -> Result<u32, SRCError> {
+    let path = subject_path(subject, Some(version));
+    perform_json_call(sr_settings, Method::DELETE, &path, None)
+}
+
+/// Gets the ids of the schema's referencing the version of the subject, which would break when the
+/// version is deleted.
+pub fn get_referenced_by(
+    sr_settings: &SrSettings,
+    subject: &str,
+    version: u32,
+) -> Result<Vec<u32>, SRCError> {
+    let path = format!("{}/referencedby", subject_path(subject, Some(version)));
+    perform_json_call(sr_settings, Method::GET, &path, None)
+}
+
+/// Gets the versions of subjects using the schema with the id, since the same schema can be
+/// registered for multiple subjects.
+pub fn schema_usages(sr_settings: &SrSettings, id: u32) -> Result<Vec<SubjectVersion>, SRCError> {
+    let path = format!("/schemas/ids/{}/versions", id);
+    perform_json_call(sr_settings, Method::GET, &path, None)
+}
+
+/// Gets the global compatibility level, used for subjects without their own level.
+pub fn get_compatibility_level(sr_settings: &SrSettings) -> Result<CompatibilityLevel, SRCError> {
+    let path = config_path(None);
+    let config: RawConfig = perform_json_call(sr_settings, Method::GET, &path, None)?;
+    Ok(config.compatibility_level)
+}
+
+/// Sets the global compatibility level, used for subjects without their own level.
+pub fn set_compatibility_level(
+    sr_settings: &SrSettings,
+    level: CompatibilityLevel,
+) -> Result<CompatibilityLevel, SRCError> {
+    put_config(sr_settings, None, level)
+}
+
+/// Gets the compatibility level of the subject. When the subject doesn't have its own level this
+/// fails, unless `default_to_global` is set, in which case the global level is returned.
+pub fn get_subject_compatibility_level(
+    sr_settings: &SrSettings,
+    subject: &str,
+    default_to_global: bool,
+) -> Result<CompatibilityLevel, SRCError> {
+    let path = format!(
+        "{}?defaultToGlobal={}",
+        config_path(Some(subject)),
+        default_to_global
+    );
+    let config: RawConfig = perform_json_call(sr_settings, Method::GET, &path, None)?;
+    Ok(config.compatibility_level)
+}
+
+/// Sets the compatibility level of the subject, overriding the global level.
+pub fn set_subject_compatibility_level(
+    sr_settings: &SrSettings,
+    subject: &str,
+    level: CompatibilityLevel,
+) -> Result<CompatibilityLevel, SRCError> {
+    put_config(sr_settings, Some(subject), level)
+}
+
+fn put_config(
+    sr_settings: &SrSettings,
+    subject: Option<&str>,
+    level: CompatibilityLevel,
+) -> Result<CompatibilityLevel, SRCError> {
+    let body = json!({ "compatibility": level }).to_string();
+    let path = config_path(subject);
+    let config: RawConfig = perform_json_call(sr_settings, Method::PUT, &path, Some(&body))?;
+    Ok(config.compatibility_level)
+}
+
+/// Gets the global mode of the schema registry.
+pub fn get_mode(sr_settings: &SrSettings) -> Result<Mode, SRCError> {
+    let path = mode_path(None);
+    let mode: RawMode = perform_json_call(sr_settings, Method::GET, &path, None)?;
+    Ok(mode.mode)
+}
+
+/// Sets the global mode of the schema registry. Setting the import mode is only allowed when no
+/// schema's are registered yet, unless `force` is set.
+pub fn set_mode(sr_settings: &SrSettings, mode: Mode, force: bool) -> Result<Mode, SRCError> {
+    put_mode(sr_settings, None, mode, force)
+}
+
+/// Gets the mode of the subject. When the subject doesn't have its own mode this fails, unless
+/// `default_to_global` is set, in which case the global mode is returned.
+pub fn get_subject_mode(
+    sr_settings: &SrSettings,
+    subject: &str,
+    default_to_global: bool,
+) -> Result<Mode, SRCError> {
+    let path = format!(
+        "{}?defaultToGlobal={}",
+        mode_path(Some(subject)),
+        default_to_global
+    );
+    let mode: RawMode = perform_json_call(sr_settings, Method::GET, &path, None)?;
+    Ok(mode.mode)
+}
+
+/// Sets the mode of the subject, overriding the global mode. Setting the import mode is only
+/// allowed when no schema's are registered for the subject yet, unless `force` is set.
+pub fn set_subject_mode(
+    sr_settings: &SrSettings,
+    subject: &str,
+    mode: Mode,
+    force: bool,
+) -> Result<Mode, SRCError> {
+    put_mode(sr_settings, Some(subject), mode, force)
+}
+
+fn put_mode(
+    sr_settings: &SrSettings,
+    subject: Option<&str>,
+    mode: Mode,
+    force: bool,
+) -> Result<Mode, SRCError> {
+    let path = format!("{}?force={}", mode_path(subject), force);
+    let body = json!({ "mode": mode }).to_string();
+    let mode: RawMode = perform_json_call(sr_settings, Method::PUT, &path, Some(&body))?;
+    Ok(mode.mode)
+}
+
+/// Registers the schema with the id, subject and version it has, which is only allowed when the
+/// subject is in import mode. Used to copy schema's from one schema registry to another, like
+/// those from `get_all_schemas`, keeping the ids the same. Referenced schema's need to be imported
+/// before the schema's referencing them. The metadata and rule set are kept.
+pub fn import_schema(sr_settings: &SrSettings, schema: &RegisteredSchema) -> Result<u32, SRCError> {
+    let (subject, version) = match (&schema.subject, schema.version) {
+        (Some(s), Some(v)) => (s, v),
+        _ => {
+            return Err(SRCError::non_retryable_without_cause(&format!(
+                "subject and version are needed to import schema with id {}",
+                schema.id
+            )))
+        }
+    };
+    let schema_type = match &schema.schema_type {
+        SchemaType::Avro => String::from("AVRO"),
+        SchemaType::Protobuf => String::from("PROTOBUF"),
+        SchemaType::Json => String::from("JSON"),
+        SchemaType::Other(v) => v.clone(),
+    };
+    let mut body = json!({
+        "schema": schema.schema,
+        "schemaType": schema_type,
+        "id": schema.id,
+        "version": version,
+    });
+    if !schema.references.is_empty() {
+        body["references"] = json!(schema.references);
+    }
+    if let Some(metadata) = &schema.metadata {
+        body["metadata"] = json!(metadata);
+    }
+    if let Some(rule_set) = &schema.rule_set {
+        body["ruleSet"] = json!(rule_set);
+    }
+    call_and_get_id(sr_settings, SrCall::PostNew(subject, &body.to_string()))
+}
+
+/// Checks whether the schema would be compatible with the version of the subject, or with the
+/// latest version when no version is given, without registering it. The compatibility level of the
+/// subject is used, and when not compatible the messages explain why. The references should already
+/// be registered.
+pub fn test_compatibility(
+    sr_settings: &SrSettings,
+    subject: &str,
+    version: Option<u32>,
+    schema_type: &SchemaType,
+    schema: &str,
+    references: &[RegisteredReference],
+) -> Result<CompatibilityResult, SRCError> {
+    let schema_type = match schema_type {
+        SchemaType::Avro => String::from("AVRO"),
+        SchemaType::Protobuf => String::from("PROTOBUF"),
+        SchemaType::Json => String::from("JSON"),
+        SchemaType::Other(v) => v.clone(),
+    };
+    let body = get_body(sr_settings.normalize, &schema_type, schema, references)?;
+    perform_json_call(
+        sr_settings,
+        Method::POST,
+        &with_normalize(compatibility_path(subject, version), sr_settings.normalize),
+        Some(&body),
+    )
+}
+
+/// Does a call to the schema registry, for the calls which don't return a schema, trying the next
+/// url when the call fails. When the schema registry responds with an error status, the response
+/// is set as the cause.
+fn perform_json_call<T: DeserializeOwned>(
+    sr_settings: &SrSettings,
+    method: Method,
+    path: &str,
+    body: Option<&str>,
+) -> Result<T, SRCError> {
+    let urls = sr_settings.urls_for(method == Method::GET);
+    let mut n = 0;
+    loop {
+        let (url, settings) = urls[n];
+        let start = request_start();
+        let result = perform_single_json_call(
+            url,
+            &settings.client,
+            &settings.authorization,
+            &settings.headers,
+            method.clone(),
+            path,
+            body,
+        );
+        record_request(start, &result);
+        if !matches!(&result, Err(e) if try_next_url(&urls, n, e)) {
+            break result;
+        }
+        n += 1
+    }
+}
+
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(
+        name = "schema_registry_call",
+        skip_all,
+        fields(base_url = %base_url, method = %method, path = %path),
+        err
+    )
+)]
+fn perform_single_json_call<T: DeserializeOwned>(
+    base_url: &str,
+    client: &Client,
+    authentication: &SrAuthorization,
+    headers: &header::HeaderMap,
+    method: Method,
+    path: &str,
+    body: Option<&str>,
+) -> Result<T, SRCError> {
+    let url = format!("{}{}", base_url, path);
+    let mut builder = client
+        .request(method, &url)
+        .header(ACCEPT, "application/vnd.schemaregistry.v1+json");
+    if let Some(b) = body {
+        builder = builder
+            .body(String::from(b))
+            .header(CONTENT_TYPE, "application/vnd.schemaregistry.v1+json");
+    }
+    match apply_authentication(builder, authentication, headers) {
+        Ok(v) if is_throttling(v.status()) => Err(throttling_error(&v)),
+        Ok(v) if v.status().is_success() => match v.json::<T>() {
+            Ok(r) => Ok(r),
+            Err(e) => Err(SRCError::non_retryable_with_cause(
+                e,
+                &format!("could not parse the response from {}", url),
+            )),
+        },
+        Ok(v) => {
+            let status = v.status();
+            let response = v.text().unwrap_or_default();
+            Err(SRCError::new(
+                &format!(
+                    "schema registry responded with status {} for {}",
+                    status, url
+                ),
+                Some(response),
+                status.is_server_error(),
+            ))
+        }
+        Err(e) => Err(SRCError::retryable_with_cause(
+            e,
+            "http call to schema registry failed",
+        )),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::time::Duration;
 
-    use crate::blocking::schema_registry::{get_schema_by_id, SrSettings};
+    use crate::blocking::schema_registry::{
+        check_schema_registered, get_all_schemas, get_compatibility_level, get_referenced_schema,
+        get_schema_by_id, get_schema_by_subject_and_version, get_subject_compatibility_level,
+        get_subject_versions, list_subjects, set_subject_compatibility_level, SrSettings,
+    };
+    use crate::error::Throttling;
+    use crate::schema_registry_common::{
+        CompatibilityLevel, RegisteredReference, SchemaLimits, SchemaType, SubjectNameStrategy,
+    };
 
     #[test]
     fn put_correct_url_as_second_check_header_set() {
@@ -574,6 +1510,207 @@ mod tests {
         }
     }
 
+    #[test]
+    fn user_agent_and_per_call_headers() {
+        let mut server = mockito::Server::new();
+        let _m = server
+            .mock("GET", "/schemas/ids/1?deleted=true")
+            .match_header("foo", "bar")
+            .match_header("x-tenant-id", "tenant-a")
+            .match_header("user-agent", "my-service/1.0")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"schema":"{\"type\":\"string\"}"}"#)
+            .create();
+        let _other = server
+            .mock("GET", "/schemas/ids/2?deleted=true")
+            .match_header("x-tenant-id", mockito::Matcher::Missing)
+            .match_header("user-agent", "my-service/1.0")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"schema":"{\"type\":\"long\"}"}"#)
+            .create();
+
+        let sr_settings = SrSettings::new_builder(server.url())
+            .add_header("foo", "bar")
+            .set_user_agent("my-service/1.0")
+            .build()
+            .unwrap();
+        let tenant_settings = sr_settings
+            .with_headers(&[("x-tenant-id", "tenant-a")])
+            .unwrap();
+
+        let result = get_schema_by_id(1, &tenant_settings).unwrap();
+        assert_eq!(result.schema, r#"{"type":"string"}"#);
+        let result = get_schema_by_id(2, &sr_settings).unwrap();
+        assert_eq!(result.schema, r#"{"type":"long"}"#);
+        assert!(sr_settings.with_headers(&[("in valid", "a")]).is_err());
+    }
+
+    #[test]
+    fn fallback_for_reads() {
+        let mut server = mockito::Server::new();
+        let _m = server
+            .mock("GET", "/schemas/ids/1?deleted=true")
+            .match_header("authorization", "Bearer token_of_the_mirror")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"schema":"{\"type\":\"string\"}"}"#)
+            .create();
+
+        let fallback = SrSettings::new_builder(server.url())
+            .set_token_authorization("token_of_the_mirror")
+            .build()
+            .unwrap();
+        let sr_settings = SrSettings::new_builder(String::from("bogus://test"))
+            .set_fallback(fallback)
+            .build()
+            .unwrap();
+
+        let result = get_schema_by_id(1, &sr_settings).unwrap();
+        assert_eq!(result.schema, r#"{"type":"string"}"#);
+    }
+
+    #[test]
+    fn fallback_only_for_retriable_errors() {
+        let mut server = mockito::Server::new();
+        let _m = server
+            .mock("GET", "/schemas/ids/1?deleted=true")
+            .with_status(404)
+            .with_body(r#"{"error_code":40403,"message":"Schema 1 not found"}"#)
+            .create();
+        let _m = server
+            .mock("GET", "/schemas/ids/2?deleted=true")
+            .with_status(500)
+            .create();
+        let mut mirror = mockito::Server::new();
+        let not_found = mirror
+            .mock("GET", "/schemas/ids/1?deleted=true")
+            .expect(0)
+            .create();
+        let _m = mirror
+            .mock("GET", "/schemas/ids/2?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"schema":"{\"type\":\"string\"}"}"#)
+            .create();
+
+        let sr_settings = SrSettings::new_builder(server.url())
+            .set_fallback(SrSettings::new(mirror.url()))
+            .build()
+            .unwrap();
+
+        assert!(get_schema_by_id(1, &sr_settings).is_err());
+        not_found.assert();
+        let result = get_schema_by_id(2, &sr_settings).unwrap();
+        assert_eq!(result.schema, r#"{"type":"string"}"#);
+    }
+
+    #[test]
+    fn schema_limits_while_reading() {
+        let mut server = mockito::Server::new();
+        let large = format!(
+            r#"{{"schema":"{\"type\":\"string\",\"doc\":\"{}\"}"}}"#,
+            "a".repeat(70_000)
+        );
+        let _m = server
+            .mock("GET", "/schemas/ids/1?deleted=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(large)
+            .create();
+        let _m = server
+            .mock("GET", "/schemas")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(
+                r#"[{"subject":"a","version":1,"id":1,"schema":"{\"type\":\"record\",\"name\":\"A\",\"fields\":[]}"},{"subject":"b","version":1,"id":2,"schema":"{\"type\":\"string\"}"}]"#,
+            )
+            .create();
+        let referenced = server
+            .mock("GET", "/subjects/b/versions/1")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"subject":"b","version":1,"id":2,"schema":"{\"type\":\"string\"}"}"#)
+            .expect(1)
+            .create();
+
+        let sr_settings = SrSettings::new_builder(server.url())
+            .set_schema_limits(SchemaLimits {
+                max_schema_bytes: Some(20),
+                max_total_references: Some(1),
+                ..SchemaLimits::default()
+            })
+            .build()
+            .unwrap();
+
+        let err = get_schema_by_id(1, &sr_settings).unwrap_err();
+        assert!(err.error.starts_with("Response of more than"));
+        assert!(!err.retriable);
+        let schemas = get_all_schemas(&sr_settings).unwrap();
+        assert_eq!(schemas.len(), 1);
+        assert_eq!(schemas[0].id, 2);
+        let reference = RegisteredReference {
+            name: String::from("b"),
+            subject: String::from("b"),
+            version: 1,
+        };
+        let counting = sr_settings.counting_references();
+        assert!(get_referenced_schema(&counting, &reference).is_ok());
+        assert!(get_referenced_schema(&counting, &reference).is_err());
+        referenced.assert();
+    }
+
+    #[test]
+    fn throttled_by_schema_registry() {
+        let mut server = mockito::Server::new();
+        let _m = server
+            .mock("GET", "/schemas/ids/1?deleted=true")
+            .with_status(429)
+            .with_header("retry-after", "7")
+            .create();
+        let _m = server
+            .mock("GET", "/subjects?deleted=false")
+            .with_status(503)
+            .create();
+        let _m = server
+            .mock("GET", "/schemas/ids/2?deleted=true")
+            .with_status(504)
+            .with_header("retry-after", "Wed, 21 Oct 2015 07:28:00 GMT")
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+
+        let err = get_schema_by_id(1, &sr_settings).unwrap_err();
+        assert!(err.retriable);
+        assert_eq!(
+            err.throttling(),
+            Some(&Throttling {
+                status: 429,
+                retry_after: Some(Duration::from_secs(7)),
+            })
+        );
+        assert!(!err.into_cache().cached);
+
+        let err = list_subjects(&sr_settings, false).unwrap_err();
+        assert_eq!(
+            err.throttling(),
+            Some(&Throttling {
+                status: 503,
+                retry_after: None,
+            })
+        );
+
+        let err = get_schema_by_id(2, &sr_settings).unwrap_err();
+        assert_eq!(
+            err.throttling(),
+            Some(&Throttling {
+                status: 504,
+                retry_after: Some(Duration::ZERO),
+            })
+        );
+    }
+
     #[test]
     fn basic_authorization() {
         let mut server = mockito::Server::new();
@@ -602,4 +1739,184 @@ mod tests {
             _ => panic!(),
         }
     }
+
+    #[test]
+    fn registered_schema_with_subject_and_references() {
+        let mut server = mockito::Server::new();
+
+        let _m = server.mock("GET", "/subjects/heartbeat-value/versions/2")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"subject":"heartbeat-value","version":2,"id":7,"schemaType":"PROTOBUF","schema":"syntax = \"proto3\"; import \"beat.proto\"; message Heartbeat { Beat beat = 1; }","references":[{"name":"beat.proto","subject":"beat","version":1}]}"#)
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let strategy = SubjectNameStrategy::TopicNameStrategy(String::from("heartbeat"), false);
+        let result = get_schema_by_subject_and_version(&sr_settings, &strategy, 2).unwrap();
+
+        assert_eq!(result.id, 7);
+        assert_eq!(result.schema_type, SchemaType::Protobuf);
+        assert_eq!(
+            result.schema,
+            r#"syntax = "proto3"; import "beat.proto"; message Heartbeat { Beat beat = 1; }"#
+        );
+        assert_eq!(
+            result.references,
+            vec![RegisteredReference {
+                name: String::from("beat.proto"),
+                subject: String::from("beat"),
+                version: 1,
+            }]
+        );
+        assert_eq!(result.subject, Some(String::from("heartbeat-value")));
+        assert_eq!(result.version, Some(2));
+    }
+
+    #[cfg(feature = "proto_descriptor")]
+    #[test]
+    fn register_descriptor_set_for_messages() {
+        use crate::blocking::schema_registry::register_descriptor_set;
+        use test_utils::get_proto_hb_descriptor_set;
+
+        let mut server = mockito::Server::new();
+        let register = server
+            .mock("POST", "/subjects/heartbeat-value/versions")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"id":5}"#)
+            .create();
+        let version = server
+            .mock("POST", "/subjects/heartbeat-value")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"subject":"heartbeat-value","version":2,"id":5,"schema":"{}"}"#)
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+        let ids = register_descriptor_set(&sr_settings, get_proto_hb_descriptor_set(), |f| {
+            f.replace(".proto", "-value")
+        })
+        .unwrap();
+
+        assert_eq!(ids.len(), 1);
+        assert_eq!(ids.get("nl.openweb.data.Heartbeat"), Some(&5));
+        register.assert();
+        version.assert();
+    }
+
+    #[test]
+    fn compatibility_levels() {
+        let mut server = mockito::Server::new();
+        let global = server
+            .mock("GET", "/config")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"compatibilityLevel":"BACKWARD"}"#)
+            .create();
+        let set = server
+            .mock("PUT", "/config/heartbeat-value")
+            .match_body(r#"{"compatibility":"FULL_TRANSITIVE"}"#)
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"compatibility":"FULL_TRANSITIVE"}"#)
+            .create();
+        let subject = server
+            .mock("GET", "/config/heartbeat-value?defaultToGlobal=true")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"compatibilityLevel":"FULL_TRANSITIVE"}"#)
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+
+        let level = get_compatibility_level(&sr_settings).unwrap();
+        assert_eq!(level, CompatibilityLevel::Backward);
+        let level = set_subject_compatibility_level(
+            &sr_settings,
+            "heartbeat-value",
+            CompatibilityLevel::FullTransitive,
+        )
+        .unwrap();
+        assert_eq!(level, CompatibilityLevel::FullTransitive);
+        let level = get_subject_compatibility_level(&sr_settings, "heartbeat-value", true).unwrap();
+        assert_eq!(level, CompatibilityLevel::FullTransitive);
+
+        global.assert();
+        set.assert();
+        subject.assert();
+    }
+
+    #[test]
+    fn exotic_subject_names() {
+        let mut server = mockito::Server::new();
+        let versions = server
+            .mock(
+                "GET",
+                "/subjects/my%20topic%2F%C3%B6rd%C3%A8rs-value/versions?deleted=false",
+            )
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body("[1]")
+            .create();
+        let config = server
+            .mock("PUT", "/config/%3A.ctx%3Aa%2Bb%3F-value")
+            .match_body(r#"{"compatibility":"NONE"}"#)
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"compatibility":"NONE"}"#)
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+
+        let result = get_subject_versions(&sr_settings, "my topic/ördèrs-value", false).unwrap();
+        assert_eq!(result, vec![1]);
+        let level = set_subject_compatibility_level(
+            &sr_settings,
+            ":.ctx:a+b?-value",
+            CompatibilityLevel::None,
+        )
+        .unwrap();
+        assert_eq!(level, CompatibilityLevel::None);
+
+        versions.assert();
+        config.assert();
+    }
+
+    #[test]
+    fn check_registered() {
+        let mut server = mockito::Server::new();
+        let found = server
+            .mock("POST", "/subjects/heartbeat-value?deleted=false")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"subject":"heartbeat-value","version":2,"id":5,"schemaType":"JSON","schema":"{}"}"#)
+            .create();
+        let not_found = server
+            .mock("POST", "/subjects/heartbeat-key?deleted=false")
+            .with_status(404)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"error_code":40401,"message":"Subject 'heartbeat-key' not found."}"#)
+            .create();
+
+        let sr_settings = SrSettings::new(server.url());
+
+        let result = check_schema_registered(
+            &sr_settings,
+            "heartbeat-value",
+            &SchemaType::Json,
+            "{}",
+            &[],
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(result.id, 5);
+        assert_eq!(result.version, Some(2));
+        let result =
+            check_schema_registered(&sr_settings, "heartbeat-key", &SchemaType::Json, "{}", &[])
+                .unwrap();
+        assert!(result.is_none());
+
+        found.assert();
+        not_found.assert();
+    }
 }
@@ -0,0 +1,565 @@
+//! Generates Rust types for subjects in the schema registry, so applications get compile time types
+//! matching the schema's used in production. It's meant to be used from a build script, or with the
+//! `sr_codegen` binary. Each subject becomes a module with the subject, version and id of the schema
+//! as constants. Avro and json schema's become structs and enums deriving the serde traits, which
+//! can be used with `decode_as` and `encode_struct`. For protobuf the schema and its references are
+//! written as proto files, to be compiled with prost-build.
+//! ```no_run
+//! use schema_registry_converter::blocking::schema_registry::SrSettings;
+//! use schema_registry_converter::codegen::Codegen;
+//!
+//! let out_dir = std::env::var("OUT_DIR").unwrap();
+//! Codegen::new(SrSettings::new(String::from("http://localhost:8081")))
+//!     .with_subject("orders-value")
+//!     .with_subject_version("customers-value", 3)
+//!     .write_to(format!("{}/schemas.rs", out_dir))
+//!     .unwrap();
+//! ```
+//! The generated file can then be included with
+//! `include!(concat!(env!("OUT_DIR"), "/schemas.rs"));`.
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_json::Value;
+
+use crate::blocking::schema_registry::{
+    get_referenced_schema, get_schema_by_subject_with_version, SrSettings,
+};
+use crate::error::SRCError;
+use crate::schema_registry_common::{RegisteredSchema, SchemaType, SubjectNameStrategy, Version};
+
+const KEYWORDS: &[&str] = &[
+    "as", "async", "await", "box", "break", "const", "continue", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+    "ref", "return", "static", "struct", "trait", "true", "type", "unsafe", "use", "where",
+    "while",
+];
+
+/// Fetches the subjects from the schema registry, and generates the Rust code for them.
+#[derive(Debug)]
+pub struct Codegen {
+    sr_settings: SrSettings,
+    subjects: Vec<(String, Version)>,
+}
+
+/// The types generated for one schema, with the definitions of the structs and enums.
+struct Generated {
+    names: HashSet<String>,
+    definitions: String,
+}
+
+impl Codegen {
+    pub fn new(sr_settings: SrSettings) -> Codegen {
+        Codegen {
+            sr_settings,
+            subjects: Vec::new(),
+        }
+    }
+
+    /// Adds the latest version of the subject.
+    pub fn with_subject(self, subject: &str) -> Codegen {
+        self.with_subject_version(subject, Version::Latest)
+    }
+
+    /// Adds a specific version of the subject, or the latest with metadata.
+    pub fn with_subject_version(mut self, subject: &str, version: impl Into<Version>) -> Codegen {
+        self.subjects.push((String::from(subject), version.into()));
+        self
+    }
+
+    /// Generates the Rust code with a module for each subject.
+    pub fn generate(&self) -> Result<String, SRCError> {
+        let mut code = String::from(
+            "// Generated by schema_registry_converter, changes will be overwritten.\n",
+        );
+        for (subject, version) in &self.subjects {
+            let registered_schema = self.fetch(subject, version)?;
+            code.push('\n');
+            code.push_str(&self.subject_module(subject, &registered_schema)?);
+        }
+        Ok(code)
+    }
+
+    /// Generates the Rust code, and writes it to the file, only when it changed, so cargo doesn't
+    /// need to rebuild.
+    pub fn write_to<P: AsRef<Path>>(&self, path: P) -> Result<(), SRCError> {
+        let code = self.generate()?;
+        if fs::read_to_string(&path).ok().as_deref() == Some(code.as_str()) {
+            return Ok(());
+        }
+        match fs::write(&path, code) {
+            Ok(()) => Ok(()),
+            Err(e) => Err(SRCError::non_retryable_with_cause(
+                e,
+                "Could not write generated code",
+            )),
+        }
+    }
+
+    /// Writes the protobuf subjects, and their references, as proto files to the directory. The
+    /// references use their name as path, so the imports resolve with the directory as include.
+    /// Gives the files of the subjects, to compile with
+    /// `prost_build::compile_protos(&files, &[dir])`.
+    pub fn write_proto_files<P: AsRef<Path>>(&self, dir: P) -> Result<Vec<PathBuf>, SRCError> {
+        let mut files = Vec::new();
+        for (subject, version) in &self.subjects {
+            let registered_schema = self.fetch(subject, version)?;
+            if registered_schema.schema_type != SchemaType::Protobuf {
+                continue;
+            }
+            self.write_references(dir.as_ref(), &registered_schema)?;
+            let path = dir.as_ref().join(proto_file(subject));
+            write_file(&path, &registered_schema.schema)?;
+            files.push(path);
+        }
+        Ok(files)
+    }
+
+    fn fetch(&self, subject: &str, version: &Version) -> Result<RegisteredSchema, SRCError> {
+        let strategy = SubjectNameStrategy::RecordNameStrategy(String::from(subject));
+        get_schema_by_subject_with_version(&self.sr_settings, &strategy, version)
+    }
+
+    fn write_references(
+        &self,
+        dir: &Path,
+        registered_schema: &RegisteredSchema,
+    ) -> Result<(), SRCError> {
+        for reference in &registered_schema.references {
+            let referenced = get_referenced_schema(&self.sr_settings, reference)?;
+            self.write_references(dir, &referenced)?;
+            write_file(&dir.join(&reference.name), &referenced.schema)?;
+        }
+        Ok(())
+    }
+
+    fn subject_module(
+        &self,
+        subject: &str,
+        registered_schema: &RegisteredSchema,
+    ) -> Result<String, SRCError> {
+        let version = registered_schema.version.unwrap_or_default();
+        let mut code = String::new();
+        let _ = writeln!(
+            code,
+            "/// Generated from version {} of subject `{}`, with schema id {}.",
+            version, subject, registered_schema.id
+        );
+        let _ = writeln!(code, "pub mod {} {{", module_name(subject));
+        let _ = writeln!(code, "    pub const SUBJECT: &str = {:?};", subject);
+        let _ = writeln!(code, "    pub const VERSION: u32 = {};", version);
+        let _ = writeln!(
+            code,
+            "    pub const SCHEMA_ID: u32 = {};",
+            registered_schema.id
+        );
+        let generated = match registered_schema.schema_type {
+            SchemaType::Avro => Some(self.avro_types(registered_schema)?),
+            SchemaType::Json => Some(json_types(subject, registered_schema)?),
+            SchemaType::Protobuf => {
+                let _ = writeln!(
+                    code,
+                    "    /// The proto file written by `write_proto_files`, to compile with prost-build."
+                );
+                let _ = writeln!(
+                    code,
+                    "    pub const PROTO_FILE: &str = {:?};",
+                    proto_file(subject)
+                );
+                None
+            }
+            SchemaType::Other(ref t) => {
+                return Err(SRCError::non_retryable_without_cause(&format!(
+                    "Could not generate code for schema type {}",
+                    t
+                )))
+            }
+        };
+        if let Some(generated) = generated {
+            for line in generated.definitions.lines() {
+                if line.is_empty() {
+                    code.push('\n');
+                } else {
+                    let _ = writeln!(code, "    {}", line);
+                }
+            }
+        }
+        code.push_str("}\n");
+        Ok(code)
+    }
+
+    /// The named types of the references are generated first, so they are known when used.
+    fn avro_types(&self, registered_schema: &RegisteredSchema) -> Result<Generated, SRCError> {
+        let mut generated = Generated {
+            names: HashSet::new(),
+            definitions: String::new(),
+        };
+        let mut schemas = Vec::new();
+        self.collect_references(registered_schema, &mut schemas)?;
+        schemas.push(registered_schema.schema.clone());
+        for schema in schemas {
+            let value = to_json(&schema)?;
+            avro_type(&value, &mut generated)?;
+        }
+        Ok(generated)
+    }
+
+    fn collect_references(
+        &self,
+        registered_schema: &RegisteredSchema,
+        schemas: &mut Vec<String>,
+    ) -> Result<(), SRCError> {
+        for reference in &registered_schema.references {
+            let referenced = get_referenced_schema(&self.sr_settings, reference)?;
+            self.collect_references(&referenced, schemas)?;
+            schemas.push(referenced.schema);
+        }
+        Ok(())
+    }
+}
+
+fn to_json(schema: &str) -> Result<Value, SRCError> {
+    match serde_json::from_str(schema) {
+        Ok(v) => Ok(v),
+        Err(e) => Err(SRCError::non_retryable_with_cause(
+            e,
+            "Could not parse schema as json",
+        )),
+    }
+}
+
+fn write_file(path: &Path, content: &str) -> Result<(), SRCError> {
+    let result = match path.parent() {
+        Some(parent) => fs::create_dir_all(parent).and_then(|_| fs::write(path, content)),
+        None => fs::write(path, content),
+    };
+    match result {
+        Ok(()) => Ok(()),
+        Err(e) => Err(SRCError::non_retryable_with_cause(
+            e,
+            &format!("Could not write {}", path.display()),
+        )),
+    }
+}
+
+fn proto_file(subject: &str) -> String {
+    format!("{}.proto", module_name(subject))
+}
+
+fn module_name(subject: &str) -> String {
+    field_name(subject)
+}
+
+/// The name of a struct or enum, from any name, like `com.example.order_line` to `OrderLine`.
+fn type_name(name: &str) -> String {
+    let short = name.rsplit('.').next().unwrap_or(name);
+    let mut result = String::new();
+    for part in short.split(|c: char| !c.is_ascii_alphanumeric()) {
+        let mut chars = part.chars();
+        if let Some(first) = chars.next() {
+            result.push(first.to_ascii_uppercase());
+            result.extend(chars);
+        }
+    }
+    if result.is_empty() || result.starts_with(|c: char| c.is_ascii_digit()) {
+        result.insert(0, '_');
+    }
+    result
+}
+
+/// The name of a field or module, like `orderLine` to `order_line`.
+fn field_name(name: &str) -> String {
+    let mut result = String::new();
+    let mut previous_lower = false;
+    for c in name.chars() {
+        if c.is_ascii_uppercase() {
+            if previous_lower {
+                result.push('_');
+            }
+            result.push(c.to_ascii_lowercase());
+            previous_lower = false;
+        } else if c.is_ascii_alphanumeric() {
+            result.push(c);
+            previous_lower = true;
+        } else {
+            if !result.ends_with('_') {
+                result.push('_');
+            }
+            previous_lower = false;
+        }
+    }
+    if result.is_empty() || result.starts_with(|c: char| c.is_ascii_digit()) {
+        result.insert(0, '_');
+    }
+    if KEYWORDS.contains(&result.as_str()) {
+        result.insert_str(0, "r#");
+    } else if ["crate", "self", "super"].contains(&result.as_str()) {
+        result.push('_');
+    }
+    result
+}
+
+/// The field with the serde rename when the Rust name differs.
+fn field(code: &mut String, name: &str, rust_type: &str, optional: bool) {
+    let rust_name = field_name(name);
+    if rust_name.trim_start_matches("r#") != name {
+        let _ = writeln!(code, "    #[serde(rename = {:?})]", name);
+    }
+    if optional {
+        let _ = writeln!(
+            code,
+            "    #[serde(default, skip_serializing_if = \"Option::is_none\")]"
+        );
+    }
+    let _ = writeln!(code, "    pub {}: {},", rust_name, rust_type);
+}
+
+fn avro_type(schema: &Value, generated: &mut Generated) -> Result<String, SRCError> {
+    Ok(match schema {
+        Value::String(s) => match s.as_str() {
+            "null" => String::from("()"),
+            "boolean" => String::from("bool"),
+            "int" => String::from("i32"),
+            "long" => String::from("i64"),
+            "float" => String::from("f32"),
+            "double" => String::from("f64"),
+            "bytes" => String::from("Vec<u8>"),
+            "string" => String::from("String"),
+            named => type_name(named),
+        },
+        Value::Array(variants) => {
+            let not_null: Vec<&Value> = variants.iter().filter(|v| *v != "null").collect();
+            match not_null.as_slice() {
+                [single] if not_null.len() < variants.len() => {
+                    format!("Option<{}>", avro_type(single, generated)?)
+                }
+                [single] => avro_type(single, generated)?,
+                _ => String::from("serde_json::Value"),
+            }
+        }
+        Value::Object(object) => match object.get("type") {
+            Some(Value::String(t)) if t == "record" => avro_record(schema, generated)?,
+            Some(Value::String(t)) if t == "enum" => avro_enum(schema, generated)?,
+            Some(Value::String(t)) if t == "fixed" => String::from("Vec<u8>"),
+            Some(Value::String(t)) if t == "array" => match object.get("items") {
+                Some(items) => format!("Vec<{}>", avro_type(items, generated)?),
+                None => return Err(invalid("avro array without items")),
+            },
+            Some(Value::String(t)) if t == "map" => match object.get("values") {
+                Some(values) => format!(
+                    "std::collections::HashMap<String, {}>",
+                    avro_type(values, generated)?
+                ),
+                None => return Err(invalid("avro map without values")),
+            },
+            Some(t) => avro_type(t, generated)?,
+            None => return Err(invalid("avro schema without type")),
+        },
+        _ => {
+            return Err(invalid(
+                "avro schema which is not a string, array or object",
+            ))
+        }
+    })
+}
+
+fn avro_record(schema: &Value, generated: &mut Generated) -> Result<String, SRCError> {
+    let name = match schema.get("name").and_then(|n| n.as_str()) {
+        Some(n) => type_name(n),
+        None => return Err(invalid("avro record without name")),
+    };
+    if !generated.names.insert(name.clone()) {
+        return Ok(name);
+    }
+    let mut code = String::new();
+    if let Some(Value::Array(fields)) = schema.get("fields") {
+        for f in fields {
+            let field_schema = f.get("type").unwrap_or(&Value::Null);
+            let rust_type = avro_type(field_schema, generated)?;
+            let field_name = f.get("name").and_then(|n| n.as_str()).unwrap_or_default();
+            field(&mut code, field_name, &rust_type, false);
+        }
+    }
+    let _ = writeln!(
+        generated.definitions,
+        "\n#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]\npub struct {} {{\n{}}}",
+        name, code
+    );
+    Ok(name)
+}
+
+fn avro_enum(schema: &Value, generated: &mut Generated) -> Result<String, SRCError> {
+    let name = match schema.get("name").and_then(|n| n.as_str()) {
+        Some(n) => type_name(n),
+        None => return Err(invalid("avro enum without name")),
+    };
+    if !generated.names.insert(name.clone()) {
+        return Ok(name);
+    }
+    let mut code = String::new();
+    if let Some(Value::Array(symbols)) = schema.get("symbols") {
+        for symbol in symbols.iter().filter_map(|s| s.as_str()) {
+            let variant = type_name(&symbol.to_lowercase());
+            if variant != symbol {
+                let _ = writeln!(code, "    #[serde(rename = {:?})]", symbol);
+            }
+            let _ = writeln!(code, "    {},", variant);
+        }
+    }
+    let _ = writeln!(
+        generated.definitions,
+        "\n#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]\npub enum {} {{\n{}}}",
+        name, code
+    );
+    Ok(name)
+}
+
+fn json_types(subject: &str, registered_schema: &RegisteredSchema) -> Result<Generated, SRCError> {
+    let mut generated = Generated {
+        names: HashSet::new(),
+        definitions: String::new(),
+    };
+    let schema = to_json(&registered_schema.schema)?;
+    let name = match schema.get("title").and_then(|t| t.as_str()) {
+        Some(t) => t,
+        None => subject,
+    };
+    json_type(&schema, name, &mut generated);
+    Ok(generated)
+}
+
+/// Types that can't be mapped, like those using `$ref` or `oneOf`, become a `serde_json::Value`.
+fn json_type(schema: &Value, name: &str, generated: &mut Generated) -> String {
+    let (type_name_value, nullable) = match schema.get("type") {
+        Some(Value::String(t)) => (t.as_str(), false),
+        Some(Value::Array(types)) => {
+            let not_null: Vec<&str> = types
+                .iter()
+                .filter_map(|t| t.as_str())
+                .filter(|t| *t != "null")
+                .collect();
+            match not_null.as_slice() {
+                [single] => (*single, not_null.len() < types.len()),
+                _ => return String::from("serde_json::Value"),
+            }
+        }
+        _ => return String::from("serde_json::Value"),
+    };
+    let rust_type = match type_name_value {
+        "boolean" => String::from("bool"),
+        "integer" => String::from("i64"),
+        "number" => String::from("f64"),
+        "string" => String::from("String"),
+        "array" => match schema.get("items") {
+            Some(items) => format!("Vec<{}>", json_type(items, name, generated)),
+            None => String::from("Vec<serde_json::Value>"),
+        },
+        "object" => match (schema.get("properties"), schema.get("additionalProperties")) {
+            (Some(Value::Object(_)), _) => json_object(schema, name, generated),
+            (_, Some(values @ Value::Object(_))) => format!(
+                "std::collections::HashMap<String, {}>",
+                json_type(values, name, generated)
+            ),
+            _ => String::from("serde_json::Map<String, serde_json::Value>"),
+        },
+        _ => String::from("serde_json::Value"),
+    };
+    if nullable {
+        format!("Option<{}>", rust_type)
+    } else {
+        rust_type
+    }
+}
+
+fn json_object(schema: &Value, name: &str, generated: &mut Generated) -> String {
+    let name = match schema.get("title").and_then(|t| t.as_str()) {
+        Some(t) => type_name(t),
+        None => type_name(name),
+    };
+    if !generated.names.insert(name.clone()) {
+        return name;
+    }
+    let required: Vec<&str> = match schema.get("required") {
+        Some(Value::Array(r)) => r.iter().filter_map(|n| n.as_str()).collect(),
+        _ => Vec::new(),
+    };
+    let mut code = String::new();
+    if let Some(Value::Object(properties)) = schema.get("properties") {
+        for (property, property_schema) in properties {
+            let rust_type = json_type(property_schema, property, generated);
+            if required.contains(&property.as_str()) || rust_type.starts_with("Option<") {
+                field(&mut code, property, &rust_type, false);
+            } else {
+                field(&mut code, property, &format!("Option<{}>", rust_type), true);
+            }
+        }
+    }
+    let _ = writeln!(
+        generated.definitions,
+        "\n#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]\npub struct {} {{\n{}}}",
+        name, code
+    );
+    name
+}
+
+fn invalid(what: &str) -> SRCError {
+    SRCError::non_retryable_without_cause(&format!("Could not generate code for {}", what))
+}
+
+#[cfg(test)]
+mod tests {
+    use mockito::Server;
+
+    use crate::blocking::schema_registry::SrSettings;
+    use crate::codegen::{field_name, type_name, Codegen};
+
+    #[test]
+    fn names() {
+        assert_eq!(type_name("nl.openweb.data.order_line"), "OrderLine");
+        assert_eq!(type_name("Heartbeat"), "Heartbeat");
+        assert_eq!(field_name("orderLine"), "order_line");
+        assert_eq!(field_name("orders-value"), "orders_value");
+        assert_eq!(field_name("type"), "r#type");
+    }
+
+    #[test]
+    fn generate_avro_and_json() {
+        let mut server = Server::new();
+        let _m = server
+            .mock("GET", "/subjects/heartbeat-value/versions/latest")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"subject":"heartbeat-value","version":2,"id":3,"schema":"{\"type\":\"record\",\"name\":\"Heartbeat\",\"namespace\":\"nl.openweb.data\",\"fields\":[{\"name\":\"beat\",\"type\":\"long\"},{\"name\":\"sourceName\",\"type\":[\"null\",\"string\"]},{\"name\":\"status\",\"type\":{\"type\":\"enum\",\"name\":\"Status\",\"symbols\":[\"UP\",\"DOWN\"]}}]}"}"#)
+            .create();
+        let _m = server
+            .mock("GET", "/subjects/order-value/versions/1")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.schemaregistry.v1+json")
+            .with_body(r#"{"subject":"order-value","version":1,"id":5,"schemaType":"JSON","schema":"{\"type\":\"object\",\"title\":\"Order\",\"properties\":{\"id\":{\"type\":\"integer\"},\"lines\":{\"type\":\"array\",\"items\":{\"type\":\"string\"}}},\"required\":[\"id\"]}"}"#)
+            .create();
+
+        let code = Codegen::new(SrSettings::new(server.url()))
+            .with_subject("heartbeat-value")
+            .with_subject_version("order-value", 1)
+            .generate()
+            .unwrap();
+
+        assert!(code.contains("pub mod heartbeat_value {"));
+        assert!(code.contains("    pub const SUBJECT: &str = \"heartbeat-value\";"));
+        assert!(code.contains("    pub const VERSION: u32 = 2;"));
+        assert!(code.contains("    pub const SCHEMA_ID: u32 = 3;"));
+        assert!(code.contains("    pub struct Heartbeat {"));
+        assert!(code.contains("        pub beat: i64,"));
+        assert!(code.contains(
+            "        #[serde(rename = \"sourceName\")]\n        pub source_name: Option<String>,"
+        ));
+        assert!(code.contains("        pub status: Status,"));
+        assert!(code.contains("        #[serde(rename = \"UP\")]\n        Up,"));
+        assert!(code.contains("pub mod order_value {"));
+        assert!(code.contains("    pub struct Order {"));
+        assert!(code.contains("        pub id: i64,"));
+        assert!(code.contains("        pub lines: Option<Vec<String>>,"));
+    }
+}
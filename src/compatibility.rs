@@ -0,0 +1,923 @@
+//! Checks the compatibility of a new schema with the previous versions of a subject without a call
+//! to the schema registry, so pre-commit hooks and tests can validate schema changes. The rules
+//! follow the schema registry, for avro these are the rules of schema resolution from the avro
+//! specification. For protobuf the wire format needs to stay compatible, and removed field numbers
+//! need to be reserved, for json schema the constraints of the reader can't be narrower than those
+//! of the writer.
+//!
+//! With backward compatibility the new schema needs to be able to read data written with the
+//! previous schema, with forward compatibility the previous schema needs to be able to read data
+//! written with the new schema, and full is both. The transitive variants check against all the
+//! previous versions instead of only the latest.
+#[cfg(feature = "avro")]
+use std::collections::HashSet;
+
+#[cfg(feature = "avro")]
+use apache_avro::Schema;
+#[cfg(feature = "json")]
+use serde_json::{Map, Value};
+
+#[cfg(feature = "avro")]
+use crate::avro_logical::{collect_names, resolve_ref, Names};
+#[cfg(any(feature = "proto_decoder", feature = "proto_raw"))]
+use crate::error::SRCError;
+#[cfg(any(feature = "proto_decoder", feature = "proto_raw"))]
+use crate::proto_schema::{short_name, ProtoFile};
+use crate::schema_registry_common::{CompatibilityLevel, CompatibilityResult};
+
+/// Checks if the new avro schema is compatible with the previous versions, ordered from oldest to
+/// latest, with the level.
+/// ```
+/// use apache_avro::Schema;
+/// use schema_registry_converter::compatibility::check_avro_compatibility;
+/// use schema_registry_converter::schema_registry_common::CompatibilityLevel;
+///
+/// let previous = Schema::parse_str(r#"{"type":"record","name":"Heartbeat","fields":[{"name":"beat","type":"long"}]}"#).unwrap();
+/// let new = Schema::parse_str(r#"{"type":"record","name":"Heartbeat","fields":[{"name":"beat","type":"long"},{"name":"source","type":"string"}]}"#).unwrap();
+///
+/// let result = check_avro_compatibility(&new, &[previous], CompatibilityLevel::Backward);
+/// assert!(!result.is_compatible);
+/// assert_eq!(result.messages, vec!["the new schema can't read data written with previous schema 1: /source: field source is missing in the writer schema and has no default"]);
+/// ```
+#[cfg(feature = "avro")]
+pub fn check_avro_compatibility(
+    new_schema: &Schema,
+    previous: &[Schema],
+    level: CompatibilityLevel,
+) -> CompatibilityResult {
+    check_with_level(new_schema, previous, level, avro_can_read, |_, _| {
+        Vec::new()
+    })
+}
+
+/// Checks the new schema against the previous schema's of the level, using a function giving the
+/// reasons the reader can't read data from the writer, the first argument. The evolution function
+/// gives the reasons the new schema, the second argument, can't follow the previous one regardless
+/// of the direction.
+pub(crate) fn check_with_level<S>(
+    new_schema: &S,
+    previous: &[S],
+    level: CompatibilityLevel,
+    can_read: impl Fn(&S, &S) -> Vec<String>,
+    evolution: impl Fn(&S, &S) -> Vec<String>,
+) -> CompatibilityResult {
+    let (backward, forward, transitive) = match level {
+        CompatibilityLevel::Backward => (true, false, false),
+        CompatibilityLevel::BackwardTransitive => (true, false, true),
+        CompatibilityLevel::Forward => (false, true, false),
+        CompatibilityLevel::ForwardTransitive => (false, true, true),
+        CompatibilityLevel::Full => (true, true, false),
+        CompatibilityLevel::FullTransitive => (true, true, true),
+        CompatibilityLevel::None => (false, false, false),
+    };
+    let first = if transitive {
+        0
+    } else {
+        previous.len().saturating_sub(1)
+    };
+    let mut messages = Vec::new();
+    for (i, old) in previous.iter().enumerate().skip(first) {
+        if backward || forward {
+            for message in evolution(old, new_schema) {
+                messages.push(format!(
+                    "the new schema can't follow previous schema {}: {}",
+                    i + 1,
+                    message
+                ));
+            }
+        }
+        if backward {
+            for message in can_read(old, new_schema) {
+                messages.push(format!(
+                    "the new schema can't read data written with previous schema {}: {}",
+                    i + 1,
+                    message
+                ));
+            }
+        }
+        if forward {
+            for message in can_read(new_schema, old) {
+                messages.push(format!(
+                    "previous schema {} can't read data written with the new schema: {}",
+                    i + 1,
+                    message
+                ));
+            }
+        }
+    }
+    CompatibilityResult {
+        is_compatible: messages.is_empty(),
+        messages,
+    }
+}
+
+#[cfg(feature = "avro")]
+fn avro_can_read(writer: &Schema, reader: &Schema) -> Vec<String> {
+    let mut writer_names = Names::new();
+    collect_names(writer, &mut writer_names);
+    let mut reader_names = Names::new();
+    collect_names(reader, &mut reader_names);
+    let mut resolver = AvroResolver {
+        writer_names,
+        reader_names,
+        seen: HashSet::new(),
+        messages: Vec::new(),
+    };
+    resolver.check(writer, reader, "");
+    resolver.messages
+}
+
+/// Keeps the named types of both schema's, and the pairs of records already checked, so recursive
+/// records don't loop.
+#[cfg(feature = "avro")]
+struct AvroResolver<'s> {
+    writer_names: Names<'s>,
+    reader_names: Names<'s>,
+    seen: HashSet<(String, String)>,
+    messages: Vec<String>,
+}
+
+#[cfg(feature = "avro")]
+impl<'s> AvroResolver<'s> {
+    fn check(&mut self, writer: &'s Schema, reader: &'s Schema, path: &str) {
+        let writer = resolve_ref(writer, &self.writer_names);
+        let reader = resolve_ref(reader, &self.reader_names);
+        match (writer, reader) {
+            (Schema::Union(w), _) => {
+                for variant in w.variants() {
+                    self.check(variant, reader, path);
+                }
+            }
+            (_, Schema::Union(r)) => {
+                let (seen, before) = (self.seen.clone(), self.messages.len());
+                let readable = r.variants().iter().any(|variant| {
+                    self.check(writer, variant, path);
+                    let readable = self.messages.len() == before;
+                    self.messages.truncate(before);
+                    self.seen.clone_from(&seen);
+                    readable
+                });
+                if !readable {
+                    self.message(
+                        path,
+                        &format!("the reader union has no type for {}", describe(writer)),
+                    );
+                }
+            }
+            (Schema::Record(w), Schema::Record(r)) => {
+                if w.name.name != r.name.name {
+                    return self.names_differ(path, writer, reader);
+                }
+                if !self
+                    .seen
+                    .insert((w.name.fullname(None), r.name.fullname(None)))
+                {
+                    return;
+                }
+                for reader_field in &r.fields {
+                    let aliases = reader_field.aliases.as_deref().unwrap_or_default();
+                    let field_path = format!("{}/{}", path, reader_field.name);
+                    match w
+                        .fields
+                        .iter()
+                        .find(|f| f.name == reader_field.name || aliases.contains(&f.name))
+                    {
+                        Some(writer_field) => {
+                            self.check(&writer_field.schema, &reader_field.schema, &field_path)
+                        }
+                        None if reader_field.default.is_some() => (),
+                        None => self.message(
+                            &field_path,
+                            &format!(
+                                "field {} is missing in the writer schema and has no default",
+                                reader_field.name
+                            ),
+                        ),
+                    }
+                }
+            }
+            (Schema::Enum(w), Schema::Enum(r)) => {
+                if w.name.name != r.name.name {
+                    return self.names_differ(path, writer, reader);
+                }
+                if r.default.is_none() {
+                    for symbol in w.symbols.iter().filter(|s| !r.symbols.contains(s)) {
+                        self.message(
+                            path,
+                            &format!(
+                                "symbol {} is missing in the reader enum, which has no default",
+                                symbol
+                            ),
+                        );
+                    }
+                }
+            }
+            (Schema::Fixed(w), Schema::Fixed(r)) => {
+                if w.name.name != r.name.name {
+                    return self.names_differ(path, writer, reader);
+                }
+                if w.size != r.size {
+                    self.message(
+                        path,
+                        &format!("the size changed from {} to {}", w.size, r.size),
+                    );
+                }
+            }
+            (Schema::Array(w), Schema::Array(r)) => self.check(w, r, &format!("{}/items", path)),
+            (Schema::Map(w), Schema::Map(r)) => self.check(w, r, &format!("{}/values", path)),
+            (Schema::Decimal(w), Schema::Decimal(r)) => {
+                if w.precision != r.precision || w.scale != r.scale {
+                    self.message(
+                        path,
+                        &format!(
+                            "the decimal changed from precision {} and scale {} to precision {} and scale {}",
+                            w.precision, w.scale, r.precision, r.scale
+                        ),
+                    );
+                }
+            }
+            _ => match (primitive(writer), primitive(reader)) {
+                (Some(w), Some(r)) if w == r || promotes(w, r) => (),
+                _ => self.message(
+                    path,
+                    &format!("{} can't be read as {}", describe(writer), describe(reader)),
+                ),
+            },
+        }
+    }
+
+    fn names_differ(&mut self, path: &str, writer: &Schema, reader: &Schema) {
+        self.message(
+            path,
+            &format!(
+                "the name changed from {} to {}",
+                describe(writer),
+                describe(reader)
+            ),
+        );
+    }
+
+    fn message(&mut self, path: &str, message: &str) {
+        let path = if path.is_empty() { "/" } else { path };
+        self.messages.push(format!("{}: {}", path, message));
+    }
+}
+
+/// The primitive type of the schema, using the underlying type for the logical types.
+#[cfg(feature = "avro")]
+fn primitive(schema: &Schema) -> Option<&'static str> {
+    Some(match schema {
+        Schema::Null => "null",
+        Schema::Boolean => "boolean",
+        Schema::Int | Schema::Date | Schema::TimeMillis => "int",
+        Schema::Long
+        | Schema::TimeMicros
+        | Schema::TimestampMillis
+        | Schema::TimestampMicros
+        | Schema::LocalTimestampMillis
+        | Schema::LocalTimestampMicros => "long",
+        Schema::Float => "float",
+        Schema::Double => "double",
+        Schema::Bytes => "bytes",
+        Schema::String | Schema::Uuid => "string",
+        _ => return None,
+    })
+}
+
+/// The promotions allowed by schema resolution.
+#[cfg(feature = "avro")]
+fn promotes(writer: &str, reader: &str) -> bool {
+    matches!(
+        (writer, reader),
+        ("int", "long" | "float" | "double")
+            | ("long", "float" | "double")
+            | ("float", "double")
+            | ("string", "bytes")
+            | ("bytes", "string")
+    )
+}
+
+#[cfg(feature = "avro")]
+fn describe(schema: &Schema) -> String {
+    match schema {
+        Schema::Record(r) => format!("record {}", r.name.fullname(None)),
+        Schema::Enum(e) => format!("enum {}", e.name.fullname(None)),
+        Schema::Fixed(f) => format!("fixed {}", f.name.fullname(None)),
+        Schema::Array(_) => String::from("array"),
+        Schema::Map(_) => String::from("map"),
+        Schema::Union(_) => String::from("union"),
+        Schema::Decimal(_) => String::from("decimal"),
+        s => match primitive(s) {
+            Some(p) => String::from(p),
+            None => format!("{:?}", s),
+        },
+    }
+}
+
+/// Checks if the new json schema is compatible with the previous versions, ordered from oldest to
+/// latest, with the level. References are not resolved, a changed `$ref` is reported as a change.
+/// ```
+/// use schema_registry_converter::compatibility::check_json_compatibility;
+/// use schema_registry_converter::schema_registry_common::CompatibilityLevel;
+/// use serde_json::json;
+///
+/// let previous = json!({"type": "object", "properties": {"name": {"type": "string"}}});
+/// let new = json!({"type": "object", "properties": {"name": {"type": "string", "maxLength": 20}}});
+///
+/// let result = check_json_compatibility(&new, &[previous], CompatibilityLevel::Backward);
+/// assert_eq!(result.messages, vec!["the new schema can't read data written with previous schema 1: /properties/name: maxLength 20 was added"]);
+/// ```
+#[cfg(feature = "json")]
+pub fn check_json_compatibility(
+    new_schema: &Value,
+    previous: &[Value],
+    level: CompatibilityLevel,
+) -> CompatibilityResult {
+    check_with_level(new_schema, previous, level, json_can_read, |_, _| {
+        Vec::new()
+    })
+}
+
+#[cfg(feature = "json")]
+fn json_can_read(writer: &Value, reader: &Value) -> Vec<String> {
+    let mut messages = Vec::new();
+    json_check(writer, reader, "", &mut messages);
+    messages
+}
+
+/// The upper bounds, which the reader can't lower, and the lower bounds, which the reader can't
+/// raise.
+#[cfg(feature = "json")]
+const JSON_BOUNDS: [(&str, bool); 10] = [
+    ("maximum", true),
+    ("exclusiveMaximum", true),
+    ("maxLength", true),
+    ("maxItems", true),
+    ("maxProperties", true),
+    ("minimum", false),
+    ("exclusiveMinimum", false),
+    ("minLength", false),
+    ("minItems", false),
+    ("minProperties", false),
+];
+
+#[cfg(feature = "json")]
+fn json_check(writer: &Value, reader: &Value, path: &str, messages: &mut Vec<String>) {
+    let empty = Map::new();
+    let (w, r) = match (writer, reader) {
+        (_, Value::Bool(true)) | (Value::Bool(false), _) => return,
+        (_, Value::Bool(false)) => {
+            return json_message(messages, path, "the reader accepts nothing")
+        }
+        (Value::Bool(true), Value::Object(r)) => (&empty, r),
+        (Value::Object(w), Value::Object(r)) => (w, r),
+        _ => return,
+    };
+    match (json_types(w), json_types(r)) {
+        (_, None) => (),
+        (None, Some(r_types)) => json_message(
+            messages,
+            path,
+            &format!("type {} was added", r_types.join(", ")),
+        ),
+        (Some(w_types), Some(r_types)) => {
+            for t in w_types {
+                if !r_types.contains(&t) && !(t == "integer" && r_types.contains(&"number")) {
+                    json_message(messages, path, &format!("type {} was removed", t));
+                }
+            }
+        }
+    }
+    for (key, upper) in JSON_BOUNDS {
+        match (
+            w.get(key).and_then(Value::as_f64),
+            r.get(key).and_then(Value::as_f64),
+        ) {
+            (_, None) => (),
+            (None, Some(r_bound)) => {
+                json_message(messages, path, &format!("{} {} was added", key, r_bound))
+            }
+            (Some(w_bound), Some(r_bound)) => {
+                if (upper && r_bound < w_bound) || (!upper && r_bound > w_bound) {
+                    json_message(
+                        messages,
+                        path,
+                        &format!("{} was narrowed from {} to {}", key, w_bound, r_bound),
+                    )
+                }
+            }
+        }
+    }
+    for key in ["const", "pattern", "format", "multipleOf", "$ref"] {
+        if let Some(r_value) = r.get(key) {
+            if w.get(key) != Some(r_value) {
+                json_message(
+                    messages,
+                    path,
+                    &format!("{} was changed to {}", key, r_value),
+                );
+            }
+        }
+    }
+    if let Some(r_enum) = r.get("enum").and_then(Value::as_array) {
+        match w.get("enum").and_then(Value::as_array) {
+            Some(w_enum) => {
+                for value in w_enum.iter().filter(|v| !r_enum.contains(v)) {
+                    json_message(messages, path, &format!("enum value {} was removed", value));
+                }
+            }
+            None => json_message(messages, path, "enum was added"),
+        }
+    }
+    if let Some(r_required) = r.get("required").and_then(Value::as_array) {
+        let w_required = w.get("required").and_then(Value::as_array);
+        for name in r_required
+            .iter()
+            .filter(|n| !w_required.is_some_and(|w| w.contains(n)))
+        {
+            json_message(
+                messages,
+                path,
+                &format!("property {} is now required", name),
+            );
+        }
+    }
+    json_check_properties(w, r, path, messages);
+    match (w.get("items"), r.get("items")) {
+        (Some(w_items), Some(r_items)) => {
+            json_check(w_items, r_items, &format!("{}/items", path), messages)
+        }
+        (None, Some(r_items)) => json_check(
+            &Value::Bool(true),
+            r_items,
+            &format!("{}/items", path),
+            messages,
+        ),
+        _ => (),
+    }
+    for key in ["anyOf", "oneOf"] {
+        if let Some(r_branches) = r.get(key).and_then(Value::as_array) {
+            let w_branches = match w.get(key).and_then(Value::as_array) {
+                Some(v) => v.iter().collect(),
+                None => vec![writer],
+            };
+            for (i, w_branch) in w_branches.into_iter().enumerate() {
+                let readable = r_branches.iter().any(|r_branch| {
+                    let mut branch_messages = Vec::new();
+                    json_check(w_branch, r_branch, path, &mut branch_messages);
+                    branch_messages.is_empty()
+                });
+                if !readable {
+                    json_message(
+                        messages,
+                        &format!("{}/{}/{}", path, key, i),
+                        "no branch of the reader accepts it",
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Checks the properties, a property the writer doesn't have can only be added when the writer
+/// doesn't allow additional properties, like the schema registry does.
+#[cfg(feature = "json")]
+fn json_check_properties(
+    w: &Map<String, Value>,
+    r: &Map<String, Value>,
+    path: &str,
+    messages: &mut Vec<String>,
+) {
+    let empty = Map::new();
+    let w_properties = w
+        .get("properties")
+        .and_then(Value::as_object)
+        .unwrap_or(&empty);
+    let r_properties = r
+        .get("properties")
+        .and_then(Value::as_object)
+        .unwrap_or(&empty);
+    let open = Value::Bool(true);
+    let w_additional = w.get("additionalProperties").unwrap_or(&open);
+    let r_additional = r.get("additionalProperties").unwrap_or(&open);
+    for (name, w_property) in w_properties {
+        let property_path = format!("{}/properties/{}", path, name);
+        match r_properties.get(name) {
+            Some(r_property) => json_check(w_property, r_property, &property_path, messages),
+            None if r_additional == &Value::Bool(false) => json_message(
+                messages,
+                &property_path,
+                "property was removed from a closed content model",
+            ),
+            None => json_check(w_property, r_additional, &property_path, messages),
+        }
+    }
+    for (name, r_property) in r_properties {
+        if w_properties.contains_key(name) {
+            continue;
+        }
+        let property_path = format!("{}/properties/{}", path, name);
+        match w_additional {
+            Value::Bool(false) => (),
+            Value::Bool(_) => json_message(
+                messages,
+                &property_path,
+                "property was added to an open content model",
+            ),
+            _ => json_check(w_additional, r_property, &property_path, messages),
+        }
+    }
+    if r_additional == &Value::Bool(false) && w_additional != &Value::Bool(false) {
+        json_message(
+            messages,
+            path,
+            "additional properties are no longer allowed",
+        );
+    } else if r.contains_key("additionalProperties") {
+        json_check(
+            w_additional,
+            r_additional,
+            &format!("{}/additionalProperties", path),
+            messages,
+        );
+    }
+}
+
+#[cfg(feature = "json")]
+fn json_types(schema: &Map<String, Value>) -> Option<Vec<&str>> {
+    match schema.get("type")? {
+        Value::String(t) => Some(vec![t.as_str()]),
+        Value::Array(types) => Some(types.iter().filter_map(Value::as_str).collect()),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "json")]
+fn json_message(messages: &mut Vec<String>, path: &str, message: &str) {
+    let path = if path.is_empty() { "/" } else { path };
+    messages.push(format!("{}: {}", path, message));
+}
+
+/// Checks if the new protobuf schema is compatible with the previous versions, ordered from oldest
+/// to latest, with the level. The schema's are parsed without resolving the imports, so only the
+/// messages in the schema itself are checked.
+/// ```
+/// use schema_registry_converter::compatibility::check_protobuf_compatibility;
+/// use schema_registry_converter::schema_registry_common::CompatibilityLevel;
+///
+/// let previous = r#"syntax = "proto3"; message Heartbeat { int64 beat = 1; string source = 2; }"#;
+/// let new = r#"syntax = "proto3"; message Heartbeat { int64 beat = 1; }"#;
+///
+/// let result = check_protobuf_compatibility(new, &[previous], CompatibilityLevel::Backward).unwrap();
+/// assert_eq!(result.messages, vec!["the new schema can't follow previous schema 1: Heartbeat: field source = 2 was removed without reserving the number"]);
+/// ```
+#[cfg(any(feature = "proto_decoder", feature = "proto_raw"))]
+pub fn check_protobuf_compatibility(
+    new_schema: &str,
+    previous: &[&str],
+    level: CompatibilityLevel,
+) -> Result<CompatibilityResult, SRCError> {
+    let new_schema = ProtoFile::parse(new_schema)?;
+    let previous = previous
+        .iter()
+        .map(|s| ProtoFile::parse(s))
+        .collect::<Result<Vec<ProtoFile>, SRCError>>()?;
+    Ok(check_with_level(
+        &new_schema,
+        &previous,
+        level,
+        proto_can_read,
+        proto_evolution,
+    ))
+}
+
+/// The groups of scalar types that can be read as each other, the other types can only be read as
+/// themselves.
+#[cfg(any(feature = "proto_decoder", feature = "proto_raw"))]
+fn proto_wire_group(type_name: &str) -> &str {
+    match type_name {
+        "int32" | "uint32" | "int64" | "uint64" | "bool" => "varint",
+        "sint32" | "sint64" => "zigzag",
+        "fixed32" | "sfixed32" => "fixed32",
+        "fixed64" | "sfixed64" => "fixed64",
+        "string" | "bytes" => "bytes",
+        t => short_name(t),
+    }
+}
+
+#[cfg(any(feature = "proto_decoder", feature = "proto_raw"))]
+fn proto_can_read(writer: &ProtoFile, reader: &ProtoFile) -> Vec<String> {
+    let mut messages = Vec::new();
+    if writer.package != reader.package {
+        messages.push(format!(
+            "the package changed from {} to {}",
+            writer.package, reader.package
+        ));
+    }
+    for w in &writer.messages {
+        let r = match reader.message(&w.name) {
+            Some(r) => r,
+            None => {
+                messages.push(format!("{}: the message was removed", w.name));
+                continue;
+            }
+        };
+        for w_field in &w.fields {
+            let r_field = match r.field(w_field.number) {
+                Some(f) => f,
+                None if w_field.label.as_deref() == Some("required") => {
+                    messages.push(format!(
+                        "{}: required field {} = {} was removed",
+                        w.name, w_field.name, w_field.number
+                    ));
+                    continue;
+                }
+                None => continue,
+            };
+            let field = format!("{}: field {} = {}", w.name, r_field.name, r_field.number);
+            if proto_wire_group(&w_field.type_name) != proto_wire_group(&r_field.type_name) {
+                messages.push(format!(
+                    "{} changed type from {} to {}",
+                    field, w_field.type_name, r_field.type_name
+                ));
+            }
+            if (w_field.label.as_deref() == Some("repeated"))
+                != (r_field.label.as_deref() == Some("repeated"))
+            {
+                messages.push(format!("{} changed between repeated and singular", field));
+            }
+            if let (None, Some(oneof)) = (&w_field.oneof, &r_field.oneof) {
+                if w.fields.iter().any(|f| f.oneof.as_ref() == Some(oneof)) {
+                    messages.push(format!(
+                        "{} was moved to the existing oneof {}",
+                        field, oneof
+                    ));
+                }
+            }
+        }
+        for r_field in &r.fields {
+            if r_field.label.as_deref() == Some("required") && w.field(r_field.number).is_none() {
+                messages.push(format!(
+                    "{}: required field {} = {} was added",
+                    w.name, r_field.name, r_field.number
+                ));
+            }
+        }
+    }
+    messages
+}
+
+/// Field numbers that are removed need to be reserved, so they can't be used for something else
+/// later, and the new fields can't use numbers or names the previous schema reserved.
+#[cfg(any(feature = "proto_decoder", feature = "proto_raw"))]
+fn proto_evolution(old: &ProtoFile, new: &ProtoFile) -> Vec<String> {
+    let mut messages = Vec::new();
+    for old_message in &old.messages {
+        let new_message = match new.message(&old_message.name) {
+            Some(m) => m,
+            None => continue,
+        };
+        for field in &old_message.fields {
+            if new_message.field(field.number).is_none()
+                && !new_message.reserves_number(field.number)
+            {
+                messages.push(format!(
+                    "{}: field {} = {} was removed without reserving the number",
+                    old_message.name, field.name, field.number
+                ));
+            }
+        }
+        for field in &new_message.fields {
+            if old_message.is_reserved(field) {
+                messages.push(format!(
+                    "{}: field {} = {} uses a reserved number or name",
+                    old_message.name, field.name, field.number
+                ));
+            }
+        }
+    }
+    messages
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "avro")]
+    use apache_avro::Schema;
+
+    #[cfg(feature = "json")]
+    use serde_json::json;
+
+    #[cfg(feature = "avro")]
+    use crate::compatibility::check_avro_compatibility;
+    #[cfg(feature = "json")]
+    use crate::compatibility::check_json_compatibility;
+    #[cfg(any(feature = "proto_decoder", feature = "proto_raw"))]
+    use crate::compatibility::check_protobuf_compatibility;
+    use crate::schema_registry_common::CompatibilityLevel;
+
+    #[cfg(feature = "avro")]
+    fn record(fields: &str) -> Schema {
+        Schema::parse_str(&format!(
+            r#"{{"type":"record","name":"Heartbeat","namespace":"nl.openweb.data","fields":[{}]}}"#,
+            fields
+        ))
+        .unwrap()
+    }
+
+    #[cfg(feature = "avro")]
+    #[test]
+    fn avro_added_field_needs_default_for_backward() {
+        let previous = vec![record(r#"{"name":"beat","type":"long"}"#)];
+        let with_default = record(
+            r#"{"name":"beat","type":"long"},{"name":"source","type":"string","default":""}"#,
+        );
+        let without_default =
+            record(r#"{"name":"beat","type":"long"},{"name":"source","type":"string"}"#);
+
+        assert!(
+            check_avro_compatibility(&with_default, &previous, CompatibilityLevel::Full)
+                .is_compatible
+        );
+        let result =
+            check_avro_compatibility(&without_default, &previous, CompatibilityLevel::Forward);
+        assert!(result.is_compatible);
+        let result =
+            check_avro_compatibility(&without_default, &previous, CompatibilityLevel::Backward);
+        assert_eq!(
+            result.messages,
+            vec![String::from("the new schema can't read data written with previous schema 1: /source: field source is missing in the writer schema and has no default")]
+        );
+    }
+
+    #[cfg(feature = "avro")]
+    #[test]
+    fn avro_promotion_is_only_backward() {
+        let previous = vec![record(r#"{"name":"beat","type":"int"}"#)];
+        let new = record(r#"{"name":"beat","type":"long"}"#);
+
+        assert!(
+            check_avro_compatibility(&new, &previous, CompatibilityLevel::Backward).is_compatible
+        );
+        let result = check_avro_compatibility(&new, &previous, CompatibilityLevel::Full);
+        assert_eq!(
+            result.messages,
+            vec![String::from("previous schema 1 can't read data written with the new schema: /beat: long can't be read as int")]
+        );
+    }
+
+    #[cfg(feature = "avro")]
+    #[test]
+    fn avro_transitive_checks_all_versions() {
+        let previous = vec![
+            record(r#"{"name":"beat","type":"long"}"#),
+            record(
+                r#"{"name":"beat","type":"long"},{"name":"source","type":["null","string"],"default":null}"#,
+            ),
+        ];
+        let new =
+            record(r#"{"name":"beat","type":"long"},{"name":"source","type":["null","string"]}"#);
+
+        assert!(
+            check_avro_compatibility(&new, &previous, CompatibilityLevel::Backward).is_compatible
+        );
+        let result =
+            check_avro_compatibility(&new, &previous, CompatibilityLevel::BackwardTransitive);
+        assert!(!result.is_compatible);
+        assert_eq!(result.messages.len(), 1);
+        assert!(check_avro_compatibility(&new, &previous, CompatibilityLevel::None).is_compatible);
+    }
+
+    #[cfg(feature = "avro")]
+    #[test]
+    fn avro_enum_symbols_and_unions() {
+        let previous = vec![Schema::parse_str(
+            r#"{"type":"record","name":"Light","fields":[{"name":"color","type":{"type":"enum","name":"Color","symbols":["RED","GREEN"]}},{"name":"note","type":"string"}]}"#,
+        )
+        .unwrap()];
+        let new = Schema::parse_str(
+            r#"{"type":"record","name":"Light","fields":[{"name":"color","type":{"type":"enum","name":"Color","symbols":["RED","GREEN","BLUE"]}},{"name":"note","type":["null","string"]}]}"#,
+        )
+        .unwrap();
+
+        assert!(
+            check_avro_compatibility(&new, &previous, CompatibilityLevel::Backward).is_compatible
+        );
+        let result = check_avro_compatibility(&new, &previous, CompatibilityLevel::Forward);
+        assert_eq!(
+            result.messages,
+            vec![
+                String::from("previous schema 1 can't read data written with the new schema: /color: symbol BLUE is missing in the reader enum, which has no default"),
+                String::from("previous schema 1 can't read data written with the new schema: /note: null can't be read as string"),
+            ]
+        );
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json_narrowing_is_not_backward() {
+        let previous = vec![json!({
+            "type": "object",
+            "properties": {"id": {"type": "integer"}, "name": {"type": "string"}},
+            "required": ["id"],
+            "additionalProperties": false
+        })];
+        let widened = json!({
+            "type": "object",
+            "properties": {"id": {"type": "number"}, "name": {"type": "string"}, "note": {"type": "string"}},
+            "required": ["id"]
+        });
+        let narrowed = json!({
+            "type": "object",
+            "properties": {"id": {"type": "integer", "minimum": 1}, "name": {"type": "string", "enum": ["a", "b"]}},
+            "required": ["id", "name"],
+            "additionalProperties": false
+        });
+
+        assert!(
+            check_json_compatibility(&widened, &previous, CompatibilityLevel::Backward)
+                .is_compatible
+        );
+        let result = check_json_compatibility(&widened, &previous, CompatibilityLevel::Forward);
+        assert_eq!(
+            result.messages,
+            vec![
+                String::from("previous schema 1 can't read data written with the new schema: /properties/id: type number was removed"),
+                String::from("previous schema 1 can't read data written with the new schema: /properties/note: property was removed from a closed content model"),
+                String::from("previous schema 1 can't read data written with the new schema: /: additional properties are no longer allowed"),
+            ]
+        );
+        let result = check_json_compatibility(&narrowed, &previous, CompatibilityLevel::Backward);
+        assert_eq!(
+            result.messages,
+            vec![
+                String::from("the new schema can't read data written with previous schema 1: /: property \"name\" is now required"),
+                String::from("the new schema can't read data written with previous schema 1: /properties/id: minimum 1 was added"),
+                String::from("the new schema can't read data written with previous schema 1: /properties/name: enum was added"),
+            ]
+        );
+    }
+
+    #[cfg(any(feature = "proto_decoder", feature = "proto_raw"))]
+    #[test]
+    fn protobuf_types_and_reserved_numbers() {
+        let previous = r#"syntax = "proto3";
+package nl.openweb.data;
+
+// a heartbeat
+message Heartbeat {
+  reserved 3, 5 to 7;
+  reserved "old";
+  int64 beat = 1;
+  string source = 2;
+  Nested nested = 4;
+  message Nested {
+    repeated int32 values = 1 [packed = true];
+  }
+}"#;
+        let compatible = r#"syntax = "proto3";
+package nl.openweb.data;
+
+message Heartbeat {
+  reserved 2, 3, 5 to 7;
+  reserved "old";
+  uint64 beat = 1;
+  Nested nested = 4;
+  map<string, Nested> extra = 8;
+  message Nested {
+    repeated int32 values = 1 [packed = true];
+  }
+}"#;
+        let incompatible = r#"syntax = "proto3";
+package nl.openweb.data;
+
+message Heartbeat {
+  sint64 beat = 1;
+  string old = 6;
+  Nested nested = 4;
+  message Nested {
+    int32 values = 1;
+  }
+}"#;
+
+        let result = check_protobuf_compatibility(
+            compatible,
+            &[previous],
+            CompatibilityLevel::FullTransitive,
+        )
+        .unwrap();
+        assert_eq!(result.messages, Vec::<String>::new());
+        let result =
+            check_protobuf_compatibility(incompatible, &[previous], CompatibilityLevel::Backward)
+                .unwrap();
+        assert_eq!(
+            result.messages,
+            vec![
+                String::from("the new schema can't follow previous schema 1: Heartbeat: field source = 2 was removed without reserving the number"),
+                String::from("the new schema can't follow previous schema 1: Heartbeat: field old = 6 uses a reserved number or name"),
+                String::from("the new schema can't read data written with previous schema 1: Heartbeat: field beat = 1 changed type from int64 to sint64"),
+                String::from("the new schema can't read data written with previous schema 1: Heartbeat.Nested: field values = 1 changed between repeated and singular"),
+            ]
+        );
+        assert!(check_protobuf_compatibility("message {", &[], CompatibilityLevel::Full).is_err());
+    }
+}
@@ -1,15 +1,140 @@
 use std::error::Error;
 use std::fmt;
 use std::fmt::Display;
+use std::time::Duration;
 
 /// Error struct which makes it easy to know if the resulting error is also preserved in the cache
-/// or not. And whether trying it again might not cause an error.
+/// or not. And whether trying it again might not cause an error. More specific information about
+/// the error is available using the accessors, like `wire_format` and `violations`, so it can be
+/// extended without breaking changes.
 #[derive(Debug, PartialEq)]
+#[non_exhaustive]
 pub struct SRCError {
     pub error: String,
     pub cause: Option<String>,
     pub retriable: bool,
     pub cached: bool,
+    pub(crate) wire_format: Option<WireFormatError>,
+    pub(crate) violations: Vec<SchemaViolation>,
+    pub(crate) policy_violation: Option<PolicyViolation>,
+    pub(crate) throttling: Option<Throttling>,
+}
+
+/// A single reason a value is not valid according to the schema.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SchemaViolation {
+    /// Json pointer to the part of the value that is not valid.
+    pub path: String,
+    /// Short code of the kind of violation, like `required` or `wrong_type`.
+    pub code: String,
+    pub title: String,
+    pub detail: Option<String>,
+}
+
+/// The ways a payload might not follow the wire format, a magic byte of zero, followed by four
+/// bytes with the schema id, and the encoded data.
+#[derive(Clone, Debug, PartialEq)]
+pub enum WireFormatError {
+    /// The first byte, which should be zero.
+    InvalidMagicByte(u8),
+    /// The length of a payload that is too short to contain the magic byte and schema id.
+    TooShort(usize),
+    /// The number of bytes after the data that could not be decoded.
+    TrailingBytes(usize),
+}
+
+impl Display for WireFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WireFormatError::InvalidMagicByte(b) => {
+                write!(f, "Invalid magic byte {}, expected 0", b)
+            }
+            WireFormatError::TooShort(l) => write!(
+                f,
+                "Payload of {} bytes is too short, at least 5 bytes are needed",
+                l
+            ),
+            WireFormatError::TrailingBytes(l) => {
+                write!(
+                    f,
+                    "Payload has {} trailing bytes that could not be decoded",
+                    l
+                )
+            }
+        }
+    }
+}
+
+impl Error for WireFormatError {}
+
+/// The schema of a payload is not allowed by the `SchemaPolicy` of the decoder, so it was not
+/// fetched or decoded.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PolicyViolation {
+    pub id: u32,
+}
+
+impl Display for PolicyViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Schema id {} is not allowed by the schema policy",
+            self.id
+        )
+    }
+}
+
+impl Error for PolicyViolation {}
+
+impl From<PolicyViolation> for SRCError {
+    fn from(error: PolicyViolation) -> SRCError {
+        SRCError {
+            policy_violation: Some(error.clone()),
+            ..SRCError::non_retryable_without_cause(&error.to_string())
+        }
+    }
+}
+
+impl From<WireFormatError> for SRCError {
+    fn from(error: WireFormatError) -> SRCError {
+        SRCError {
+            wire_format: Some(error.clone()),
+            ..SRCError::non_retryable_without_cause(&error.to_string())
+        }
+    }
+}
+
+/// The schema registry, or a proxy in front of it, responded with status 429, 502, 503 or 504, with
+/// the duration to wait before trying again when the response had a Retry-After header. The call is
+/// not tried again after the duration, that's up to the caller.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Throttling {
+    pub status: u16,
+    pub retry_after: Option<Duration>,
+}
+
+impl Display for Throttling {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.retry_after {
+            Some(d) => write!(
+                f,
+                "Schema registry responded with status {}, retry after {:?}",
+                self.status, d
+            ),
+            None => write!(f, "Schema registry responded with status {}", self.status),
+        }
+    }
+}
+
+impl Error for Throttling {}
+
+impl From<Throttling> for SRCError {
+    fn from(error: Throttling) -> SRCError {
+        SRCError {
+            throttling: Some(error.clone()),
+            ..SRCError::new(&error.to_string(), None, true)
+        }
+    }
 }
 
 /// Implements standard error so error handling can be simplified
@@ -23,6 +148,10 @@ impl Clone for SRCError {
             cause: self.cause.as_ref().cloned(),
             retriable: self.retriable,
             cached: self.cached,
+            wire_format: self.wire_format.clone(),
+            violations: self.violations.clone(),
+            policy_violation: self.policy_violation.clone(),
+            throttling: self.throttling.clone(),
         }
     }
 }
@@ -54,6 +183,10 @@ impl SRCError {
             cause,
             retriable,
             cached: false,
+            wire_format: None,
+            violations: Vec::new(),
+            policy_violation: None,
+            throttling: None,
         }
     }
     pub fn retryable_with_cause<T: Display>(cause: T, error: &str) -> SRCError {
@@ -65,20 +198,62 @@ impl SRCError {
     pub fn non_retryable_without_cause(error: &str) -> SRCError {
         SRCError::new(error, None, false)
     }
-    /// Should be called before putting the error in the cache
+    /// The error code of the schema registry, when the cause is an error response from the schema
+    /// registry, for example 40401 when the subject is not found.
+    pub fn error_code(&self) -> Option<u32> {
+        let cause: serde_json::Value = serde_json::from_str(self.cause.as_ref()?).ok()?;
+        cause.get("error_code")?.as_u64().map(|c| c as u32)
+    }
+    /// Adds the violations, for an error caused by a value not being valid according to the
+    /// schema.
+    pub fn with_violations(mut self, violations: Vec<SchemaViolation>) -> SRCError {
+        self.violations = violations;
+        self
+    }
+    /// Set when the error is caused by a payload not following the wire format.
+    pub fn wire_format(&self) -> Option<&WireFormatError> {
+        self.wire_format.as_ref()
+    }
+    /// The violations when the error is caused by a value not being valid according to the schema.
+    pub fn violations(&self) -> &[SchemaViolation] {
+        &self.violations
+    }
+    /// Set when the error is caused by the schema of a payload not being allowed by the policy of
+    /// the decoder.
+    pub fn policy_violation(&self) -> Option<&PolicyViolation> {
+        self.policy_violation.as_ref()
+    }
+    /// Set when the schema registry responded it's overloaded, in which case the error is
+    /// retriable, and never cached.
+    pub fn throttling(&self) -> Option<&Throttling> {
+        self.throttling.as_ref()
+    }
+    /// Whether the schema registry responded it's overloaded, in which case the error is not kept
+    /// in the caches of the encoders and decoders.
+    pub fn is_throttled(&self) -> bool {
+        self.throttling.is_some()
+    }
+    /// Should be called before putting the error in the cache, errors from throttling are not
+    /// cached.
     pub fn into_cache(self) -> SRCError {
         SRCError {
             error: self.error,
             cause: self.cause,
             retriable: self.retriable,
-            cached: true,
+            cached: self.throttling.is_none(),
+            wire_format: self.wire_format,
+            violations: self.violations,
+            policy_violation: self.policy_violation,
+            throttling: self.throttling,
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::error::SRCError;
+    use std::time::Duration;
+
+    use crate::error::{PolicyViolation, SRCError, SchemaViolation, Throttling, WireFormatError};
 
     #[test]
     fn display_error_no_cause() {
@@ -95,4 +270,55 @@ mod tests {
         );
         assert_eq!(format!("{}", err), "Error: Could not get id from response, was cause by error in response, it\'s retriable: false, it\'s cached: false".to_owned())
     }
+
+    #[test]
+    fn wire_format_error() {
+        let err = SRCError::from(WireFormatError::InvalidMagicByte(3));
+        assert_eq!(err.error, "Invalid magic byte 3, expected 0");
+        assert_eq!(
+            err.wire_format(),
+            Some(&WireFormatError::InvalidMagicByte(3))
+        );
+        assert_eq!(
+            err.into_cache().wire_format(),
+            Some(&WireFormatError::InvalidMagicByte(3))
+        )
+    }
+
+    #[test]
+    fn policy_violation_error() {
+        let err = SRCError::from(PolicyViolation { id: 7 });
+        assert_eq!(err.error, "Schema id 7 is not allowed by the schema policy");
+        assert!(!err.retriable);
+        assert_eq!(err.policy_violation(), Some(&PolicyViolation { id: 7 }));
+    }
+
+    #[test]
+    fn throttling_error() {
+        let err = SRCError::from(Throttling {
+            status: 429,
+            retry_after: Some(Duration::from_secs(5)),
+        });
+        assert_eq!(
+            err.error,
+            "Schema registry responded with status 429, retry after 5s"
+        );
+        assert!(err.retriable);
+        assert!(err.is_throttled());
+        assert!(!err.into_cache().cached);
+    }
+
+    #[test]
+    fn violations_error() {
+        let violation = SchemaViolation {
+            path: String::from("/beat"),
+            code: String::from("required"),
+            title: String::from("This property is required"),
+            detail: None,
+        };
+        let err = SRCError::non_retryable_without_cause("Value was not valid")
+            .with_violations(vec![violation.clone()]);
+        assert_eq!(err.violations(), &[violation]);
+        assert_eq!(err.clone().into_cache().violations().len(), 1);
+    }
 }
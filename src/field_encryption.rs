@@ -0,0 +1,567 @@
+//! Client side field level encryption, following the ENCRYPT rule of the data contracts of the
+//! schema registry. When the rule set of a schema has an enabled ENCRYPT domain rule, the fields
+//! tagged with one of the tags of the rule are encrypted when encoding, and decrypted when decoding.
+//! A field is tagged either in the schema, using a `confluent:tags` array on the avro field or
+//! json property, or in the metadata of the schema, by the path of the field. Protobuf fields can
+//! only be tagged in the metadata, using the full name of the message followed by the field name.
+//!
+//! The actual encryption is done by an implementation of `FieldEncryptor`, typically using a key
+//! encryption key from a KMS, named by the `encrypt.kek.name` param of the rule. Only string and
+//! bytes fields can be encrypted, encrypted strings are base64 encoded. Protobuf fields are
+//! encrypted by the `ProtoRawEncoder` and decrypted by the `ProtoDecoder`, which need the
+//! `proto_decoder` feature.
+use std::fmt::Debug;
+
+#[cfg(feature = "avro")]
+use apache_avro::schema::{RecordField, RecordSchema, Schema};
+#[cfg(feature = "avro")]
+use apache_avro::types::Value;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+#[cfg(feature = "proto_decoder")]
+use integer_encoding::VarInt;
+#[cfg(feature = "proto_decoder")]
+use protofish::context::{Context, MessageField, MessageInfo, ValueType};
+
+#[cfg(feature = "avro")]
+use crate::avro_logical::{collect_names, resolve_ref, Names};
+use crate::error::SRCError;
+use crate::schema_registry_common::{Metadata, Rule, RuleSet};
+
+const TAGS: &str = "confluent:tags";
+
+/// Encrypts and decrypts the values of tagged fields, for the rule that applies to them.
+pub trait FieldEncryptor: Debug + Send + Sync {
+    fn encrypt(&self, rule: &Rule, plaintext: &[u8]) -> Result<Vec<u8>, SRCError>;
+    fn decrypt(&self, rule: &Rule, ciphertext: &[u8]) -> Result<Vec<u8>, SRCError>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Direction {
+    Write,
+    Read,
+}
+
+/// The encrypt rules that apply in one direction, with the encryptor to use.
+pub(crate) struct FieldEncryption<'a> {
+    encryptor: &'a dyn FieldEncryptor,
+    rules: Vec<&'a Rule>,
+    metadata: Option<&'a Metadata>,
+    direction: Direction,
+}
+
+impl<'a> FieldEncryption<'a> {
+    /// Gives nothing when there is no encryptor, or no enabled encrypt rule in the direction, so
+    /// values without encryption are not traversed.
+    pub(crate) fn new(
+        encryptor: Option<&'a dyn FieldEncryptor>,
+        rule_set: Option<&'a RuleSet>,
+        metadata: Option<&'a Metadata>,
+        direction: Direction,
+    ) -> Option<FieldEncryption<'a>> {
+        let encryptor = encryptor?;
+        let rules: Vec<&Rule> = rule_set?
+            .domain_rules
+            .iter()
+            .filter(|r| r.rule_type == "ENCRYPT" && !r.disabled)
+            .filter(|r| match direction {
+                Direction::Write => r.mode == "WRITE" || r.mode == "WRITEREAD",
+                Direction::Read => r.mode == "READ" || r.mode == "WRITEREAD",
+            })
+            .collect();
+        if rules.is_empty() {
+            return None;
+        }
+        Some(FieldEncryption {
+            encryptor,
+            rules,
+            metadata,
+            direction,
+        })
+    }
+
+    /// The first rule with a tag of the field, from the schema or from the metadata.
+    fn rule_for(&self, path: &str, inline: Option<&serde_json::Value>) -> Option<&'a Rule> {
+        let mut tags: Vec<&str> = match inline.and_then(|t| t.as_array()) {
+            Some(t) => t.iter().filter_map(|t| t.as_str()).collect(),
+            None => Vec::new(),
+        };
+        if let Some(t) = self.metadata.and_then(|m| m.tags.get(path)) {
+            tags.extend(t.iter().map(|t| t.as_str()));
+        }
+        self.rules
+            .iter()
+            .find(|r| r.tags.iter().any(|t| tags.contains(&t.as_str())))
+            .copied()
+    }
+
+    fn apply(&self, rule: &Rule, bytes: &[u8]) -> Result<Vec<u8>, SRCError> {
+        match self.direction {
+            Direction::Write => self.encryptor.encrypt(rule, bytes),
+            Direction::Read => self.encryptor.decrypt(rule, bytes),
+        }
+    }
+
+    fn apply_to_string(&self, rule: &Rule, s: &str) -> Result<String, SRCError> {
+        match self.direction {
+            Direction::Write => Ok(STANDARD.encode(self.apply(rule, s.as_bytes())?)),
+            Direction::Read => {
+                let ciphertext = match STANDARD.decode(s) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        return Err(SRCError::non_retryable_with_cause(
+                            e,
+                            "encrypted field is not valid base64",
+                        ))
+                    }
+                };
+                match String::from_utf8(self.apply(rule, &ciphertext)?) {
+                    Ok(v) => Ok(v),
+                    Err(e) => Err(SRCError::non_retryable_with_cause(
+                        e,
+                        "decrypted field is not valid utf-8",
+                    )),
+                }
+            }
+        }
+    }
+
+    /// Encrypts or decrypts the tagged fields of the avro value. The path of a field in the
+    /// metadata is the full name of the record followed by the name of the field.
+    #[cfg(feature = "avro")]
+    pub(crate) fn avro_value(&self, schema: &Schema, value: Value) -> Result<Value, SRCError> {
+        let mut names = Names::new();
+        collect_names(schema, &mut names);
+        self.avro(schema, value, &names)
+    }
+
+    #[cfg(feature = "avro")]
+    fn avro(&self, schema: &Schema, value: Value, names: &Names) -> Result<Value, SRCError> {
+        Ok(match (value, resolve_ref(schema, names)) {
+            (Value::Record(fields), Schema::Record(r)) => Value::Record(
+                fields
+                    .into_iter()
+                    .map(|(name, v)| {
+                        let v = match r.lookup.get(&name) {
+                            Some(i) => self.avro_record_field(r, &r.fields[*i], v, names)?,
+                            None => v,
+                        };
+                        Ok((name, v))
+                    })
+                    .collect::<Result<_, SRCError>>()?,
+            ),
+            (Value::Union(i, v), Schema::Union(u)) => match u.variants().get(i as usize) {
+                Some(branch) => Value::Union(i, Box::new(self.avro(branch, *v, names)?)),
+                None => Value::Union(i, v),
+            },
+            (Value::Array(items), Schema::Array(inner)) => Value::Array(
+                items
+                    .into_iter()
+                    .map(|v| self.avro(inner, v, names))
+                    .collect::<Result<_, SRCError>>()?,
+            ),
+            (Value::Map(items), Schema::Map(inner)) => Value::Map(
+                items
+                    .into_iter()
+                    .map(|(k, v)| Ok((k, self.avro(inner, v, names)?)))
+                    .collect::<Result<_, SRCError>>()?,
+            ),
+            (v, _) => v,
+        })
+    }
+
+    #[cfg(feature = "avro")]
+    fn avro_record_field(
+        &self,
+        record: &RecordSchema,
+        field: &RecordField,
+        value: Value,
+        names: &Names,
+    ) -> Result<Value, SRCError> {
+        let path = format!("{}.{}", record.name.fullname(None), field.name);
+        match self.rule_for(&path, field.custom_attributes.get(TAGS)) {
+            Some(rule) => self.avro_field(rule, &field.name, value),
+            None => self.avro(&field.schema, value, names),
+        }
+    }
+
+    #[cfg(feature = "avro")]
+    fn avro_field(&self, rule: &Rule, name: &str, value: Value) -> Result<Value, SRCError> {
+        match value {
+            Value::Null => Ok(Value::Null),
+            Value::String(s) => Ok(Value::String(self.apply_to_string(rule, &s)?)),
+            Value::Bytes(b) => Ok(Value::Bytes(self.apply(rule, &b)?)),
+            Value::Union(i, v) => Ok(Value::Union(i, Box::new(self.avro_field(rule, name, *v)?))),
+            _ => Err(unsupported(name)),
+        }
+    }
+
+    /// Encrypts or decrypts the tagged properties of the json value. The path of a property in the
+    /// metadata is the names of the properties leading to it, separated with dots. References are
+    /// not followed.
+    #[cfg(feature = "json")]
+    pub(crate) fn json_value(
+        &self,
+        schema: &serde_json::Value,
+        value: &mut serde_json::Value,
+        path: &str,
+    ) -> Result<(), SRCError> {
+        match value {
+            serde_json::Value::Object(object) => {
+                let properties = match schema.get("properties").and_then(|p| p.as_object()) {
+                    Some(p) => p,
+                    None => return Ok(()),
+                };
+                for (name, v) in object.iter_mut() {
+                    let property = match properties.get(name) {
+                        Some(p) => p,
+                        None => continue,
+                    };
+                    let path = if path.is_empty() {
+                        name.clone()
+                    } else {
+                        format!("{}.{}", path, name)
+                    };
+                    match self.rule_for(&path, property.get(TAGS)) {
+                        Some(rule) => match v {
+                            serde_json::Value::Null => (),
+                            serde_json::Value::String(s) => *s = self.apply_to_string(rule, s)?,
+                            _ => return Err(unsupported(name)),
+                        },
+                        None => self.json_value(property, v, &path)?,
+                    }
+                }
+                Ok(())
+            }
+            serde_json::Value::Array(items) => match schema.get("items") {
+                Some(inner) => {
+                    for v in items.iter_mut() {
+                        self.json_value(inner, v, path)?;
+                    }
+                    Ok(())
+                }
+                None => Ok(()),
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// Encrypts or decrypts the tagged string and bytes fields in the protobuf bytes of the
+    /// message, without the message index. Nested messages are traversed, all other fields are
+    /// copied as is.
+    #[cfg(feature = "proto_decoder")]
+    pub(crate) fn proto_bytes(
+        &self,
+        context: &Context,
+        message: &MessageInfo,
+        bytes: &[u8],
+    ) -> Result<Vec<u8>, SRCError> {
+        let mut result = Vec::with_capacity(bytes.len());
+        let mut rest = bytes;
+        while !rest.is_empty() {
+            let (key, key_length) = read_varint(rest)?;
+            let field = message.get_field(key >> 3);
+            let length = match key & 7 {
+                2 => {
+                    let (length, length_length) = read_varint(&rest[key_length..])?;
+                    let start = key_length + length_length;
+                    let end = start + length as usize;
+                    let value = rest.get(start..end).ok_or_else(invalid_proto_bytes)?;
+                    if let Some(v) = self.proto_field(context, message, field, value)? {
+                        result.extend_from_slice(&rest[..key_length]);
+                        result.extend_from_slice(&(v.len() as u64).encode_var_vec());
+                        result.extend_from_slice(&v);
+                        rest = &rest[end..];
+                        continue;
+                    }
+                    end
+                }
+                wire_type => {
+                    if let Some(f) = field.filter(|f| self.proto_rule_for(message, f).is_some()) {
+                        return Err(unsupported(&f.name));
+                    }
+                    key_length
+                        + match wire_type {
+                            0 => read_varint(&rest[key_length..])?.1,
+                            1 => 8,
+                            5 => 4,
+                            _ => return Err(invalid_proto_bytes()),
+                        }
+                }
+            };
+            let bytes = rest.get(..length).ok_or_else(invalid_proto_bytes)?;
+            result.extend_from_slice(bytes);
+            rest = &rest[length..];
+        }
+        Ok(result)
+    }
+
+    /// The new value of a length delimited field, or None when the value can be copied as is.
+    #[cfg(feature = "proto_decoder")]
+    fn proto_field(
+        &self,
+        context: &Context,
+        message: &MessageInfo,
+        field: Option<&MessageField>,
+        value: &[u8],
+    ) -> Result<Option<Vec<u8>>, SRCError> {
+        let field = match field {
+            Some(f) => f,
+            None => return Ok(None),
+        };
+        let rule = self.proto_rule_for(message, field);
+        match (&field.field_type, rule) {
+            (ValueType::Message(msg_ref), None) => {
+                let inner = context.resolve_message(*msg_ref);
+                Ok(Some(self.proto_bytes(context, inner, value)?))
+            }
+            (_, None) => Ok(None),
+            (ValueType::String, Some(rule)) => match std::str::from_utf8(value) {
+                Ok(s) => Ok(Some(self.apply_to_string(rule, s)?.into_bytes())),
+                Err(e) => Err(SRCError::non_retryable_with_cause(
+                    e,
+                    "string field is not valid utf-8",
+                )),
+            },
+            (ValueType::Bytes, Some(rule)) => Ok(Some(self.apply(rule, value)?)),
+            (_, Some(_)) => Err(unsupported(&field.name)),
+        }
+    }
+
+    #[cfg(feature = "proto_decoder")]
+    fn proto_rule_for(&self, message: &MessageInfo, field: &MessageField) -> Option<&'a Rule> {
+        self.rule_for(&format!("{}.{}", message.full_name, field.name), None)
+    }
+}
+
+#[cfg(feature = "proto_decoder")]
+fn read_varint(bytes: &[u8]) -> Result<(u64, usize), SRCError> {
+    u64::decode_var(bytes).ok_or_else(invalid_proto_bytes)
+}
+
+#[cfg(feature = "proto_decoder")]
+fn invalid_proto_bytes() -> SRCError {
+    SRCError::non_retryable_without_cause("could not read the protobuf bytes to encrypt fields")
+}
+
+fn unsupported(name: &str) -> SRCError {
+    SRCError::non_retryable_without_cause(&format!(
+        "field {} is tagged for encryption, but only string and bytes fields can be encrypted",
+        name
+    ))
+}
+
+#[cfg(all(test, feature = "avro", feature = "json"))]
+mod tests {
+    use std::collections::HashMap;
+
+    use apache_avro::types::Value;
+    use apache_avro::Schema;
+    use serde_json::json;
+
+    use crate::error::SRCError;
+    use crate::field_encryption::{Direction, FieldEncryption, FieldEncryptor};
+    use crate::schema_registry_common::{Metadata, Rule, RuleSet};
+
+    /// Reverses the bytes, so it's easy to see what happened.
+    #[derive(Debug)]
+    struct Reverse;
+
+    impl FieldEncryptor for Reverse {
+        fn encrypt(&self, _rule: &Rule, plaintext: &[u8]) -> Result<Vec<u8>, SRCError> {
+            Ok(plaintext.iter().rev().copied().collect())
+        }
+        fn decrypt(&self, _rule: &Rule, ciphertext: &[u8]) -> Result<Vec<u8>, SRCError> {
+            Ok(ciphertext.iter().rev().copied().collect())
+        }
+    }
+
+    fn rule_set() -> RuleSet {
+        RuleSet {
+            migration_rules: vec![],
+            domain_rules: vec![Rule {
+                name: String::from("encrypt"),
+                doc: None,
+                kind: String::from("TRANSFORM"),
+                mode: String::from("WRITEREAD"),
+                rule_type: String::from("ENCRYPT"),
+                tags: vec![String::from("PII")],
+                params: HashMap::new(),
+                expr: None,
+                on_success: None,
+                on_failure: None,
+                disabled: false,
+            }],
+        }
+    }
+
+    #[test]
+    fn avro_round_trip() {
+        let schema = Schema::parse_str(r#"{"type":"record","name":"Customer","namespace":"nl.openweb","fields":[{"name":"id","type":"long"},{"name":"ssn","type":"string","confluent:tags":["PII"]},{"name":"email","type":["null","string"]}]}"#).unwrap();
+        let metadata = Metadata {
+            tags: HashMap::from([(
+                String::from("nl.openweb.Customer.email"),
+                vec![String::from("PII")],
+            )]),
+            ..Default::default()
+        };
+        let rule_set = rule_set();
+        let value = Value::Record(vec![
+            (String::from("id"), Value::Long(1)),
+            (String::from("ssn"), Value::String(String::from("123"))),
+            (
+                String::from("email"),
+                Value::Union(1, Box::new(Value::String(String::from("a@b")))),
+            ),
+        ]);
+
+        let write = FieldEncryption::new(
+            Some(&Reverse),
+            Some(&rule_set),
+            Some(&metadata),
+            Direction::Write,
+        )
+        .unwrap();
+        let encrypted = write.avro_value(&schema, value.clone()).unwrap();
+        assert_eq!(
+            encrypted,
+            Value::Record(vec![
+                (String::from("id"), Value::Long(1)),
+                (String::from("ssn"), Value::String(String::from("MzIx"))),
+                (
+                    String::from("email"),
+                    Value::Union(1, Box::new(Value::String(String::from("YkBh"))))
+                ),
+            ])
+        );
+
+        let read = FieldEncryption::new(
+            Some(&Reverse),
+            Some(&rule_set),
+            Some(&metadata),
+            Direction::Read,
+        )
+        .unwrap();
+        assert_eq!(read.avro_value(&schema, encrypted).unwrap(), value);
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let schema = json!({"type":"object","properties":{
+            "id":{"type":"integer"},
+            "ssn":{"type":"string","confluent:tags":["PII"]},
+            "age":{"type":"integer","confluent:tags":["PII"]}}});
+        let rule_set = rule_set();
+        let original = json!({"id":1,"ssn":"123"});
+        let mut value = original.clone();
+
+        let write =
+            FieldEncryption::new(Some(&Reverse), Some(&rule_set), None, Direction::Write).unwrap();
+        write.json_value(&schema, &mut value, "").unwrap();
+        assert_eq!(value, json!({"id":1,"ssn":"MzIx"}));
+
+        let read =
+            FieldEncryption::new(Some(&Reverse), Some(&rule_set), None, Direction::Read).unwrap();
+        read.json_value(&schema, &mut value, "").unwrap();
+        assert_eq!(value, original);
+
+        let error = write
+            .json_value(&schema, &mut json!({"age":3}), "")
+            .unwrap_err();
+        assert_eq!(
+            error.error,
+            "field age is tagged for encryption, but only string and bytes fields can be encrypted"
+        );
+    }
+
+    #[cfg(feature = "proto_decoder")]
+    #[test]
+    fn proto_round_trip() {
+        use protofish::context::Context;
+
+        let context = Context::parse([r#"syntax = "proto3";
+            package nl.openweb;
+            message Customer {
+              int64 id = 1;
+              string ssn = 2;
+              Address address = 3;
+            }
+            message Address {
+              bytes street = 1;
+            }"#])
+        .unwrap();
+        let message = context.get_message("nl.openweb.Customer").unwrap();
+        let mut metadata = Metadata {
+            tags: HashMap::from([
+                (
+                    String::from("nl.openweb.Customer.ssn"),
+                    vec![String::from("PII")],
+                ),
+                (
+                    String::from("nl.openweb.Address.street"),
+                    vec![String::from("PII")],
+                ),
+            ]),
+            ..Default::default()
+        };
+        let rule_set = rule_set();
+        let bytes = [8, 1, 18, 3, 49, 50, 51, 26, 4, 10, 2, 1, 2];
+
+        let write = FieldEncryption::new(
+            Some(&Reverse),
+            Some(&rule_set),
+            Some(&metadata),
+            Direction::Write,
+        )
+        .unwrap();
+        let encrypted = write.proto_bytes(&context, message, &bytes).unwrap();
+        assert_eq!(
+            encrypted,
+            vec![8, 1, 18, 4, 77, 122, 73, 120, 26, 4, 10, 2, 2, 1]
+        );
+
+        let read = FieldEncryption::new(
+            Some(&Reverse),
+            Some(&rule_set),
+            Some(&metadata),
+            Direction::Read,
+        )
+        .unwrap();
+        assert_eq!(
+            read.proto_bytes(&context, message, &encrypted).unwrap(),
+            bytes
+        );
+
+        metadata.tags.insert(
+            String::from("nl.openweb.Customer.id"),
+            vec![String::from("PII")],
+        );
+        let write = FieldEncryption::new(
+            Some(&Reverse),
+            Some(&rule_set),
+            Some(&metadata),
+            Direction::Write,
+        )
+        .unwrap();
+        let error = write.proto_bytes(&context, message, &bytes).unwrap_err();
+        assert_eq!(
+            error.error,
+            "field id is tagged for encryption, but only string and bytes fields can be encrypted"
+        );
+    }
+
+    #[test]
+    fn only_enabled_encrypt_rules() {
+        let mut rule_set = rule_set();
+        assert!(FieldEncryption::new(None, Some(&rule_set), None, Direction::Write).is_none());
+        assert!(FieldEncryption::new(Some(&Reverse), None, None, Direction::Write).is_none());
+        rule_set.domain_rules[0].mode = String::from("READ");
+        assert!(
+            FieldEncryption::new(Some(&Reverse), Some(&rule_set), None, Direction::Write).is_none()
+        );
+        rule_set.domain_rules[0].disabled = true;
+        assert!(
+            FieldEncryption::new(Some(&Reverse), Some(&rule_set), None, Direction::Read).is_none()
+        );
+    }
+}
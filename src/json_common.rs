@@ -1,9 +1,131 @@
-use serde_json::value::Value;
+use std::borrow::Cow;
+#[cfg(feature = "json_schemars")]
+use std::marker::PhantomData;
+
+use serde::de::IgnoredAny;
+#[cfg(feature = "json_schemars")]
+use serde::Serialize;
+use serde_json::value::{Map, Value};
+use serde_json::Deserializer;
 use url::Url;
 use valico::json_schema::validators::ValidationState;
 
-use crate::error::SRCError;
-use crate::schema_registry_common::get_payload;
+use crate::error::{SRCError, SchemaViolation};
+use crate::field_encryption::{Direction, FieldEncryption, FieldEncryptor};
+#[cfg(feature = "json_schemars")]
+use crate::schema_registry_common::{get_payload, SchemaType, SuppliedSchema};
+use crate::schema_registry_common::{IdStrategy, Metadata, RegisteredReference, RuleSet};
+
+/// The number of bytes after the first json value in the bytes, which is why the bytes could not be
+/// parsed when the value itself is valid. Gives zero when the first value is not valid.
+pub(crate) fn trailing_json_bytes(bytes: &[u8]) -> usize {
+    let mut values = Deserializer::from_slice(bytes).into_iter::<IgnoredAny>();
+    match values.next() {
+        Some(Ok(_)) => bytes.len() - values.byte_offset(),
+        _ => 0,
+    }
+}
+
+/// The drafts of the json schema specification. Validation is done with valico, which supports
+/// the keywords of draft 7 and older. For the newer drafts `$defs` is supported, other keywords
+/// introduced in those drafts are ignored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JsonDraft {
+    Draft4,
+    Draft6,
+    Draft7,
+    Draft201909,
+    Draft202012,
+}
+
+impl JsonDraft {
+    /// Gets the draft from the `$schema` of the schema, if set and known.
+    pub fn from_schema(schema: &Value) -> Option<JsonDraft> {
+        let uri = schema.get("$schema")?.as_str()?;
+        if uri.contains("draft-04") {
+            Some(JsonDraft::Draft4)
+        } else if uri.contains("draft-06") {
+            Some(JsonDraft::Draft6)
+        } else if uri.contains("draft-07") {
+            Some(JsonDraft::Draft7)
+        } else if uri.contains("2019-09") {
+            Some(JsonDraft::Draft201909)
+        } else if uri.contains("2020-12") {
+            Some(JsonDraft::Draft202012)
+        } else {
+            None
+        }
+    }
+}
+
+/// Whether, and using which draft, values are validated against the schema.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum JsonValidation {
+    /// Values are not validated.
+    None,
+    /// Values are validated using the draft from the `$schema` of the schema, or draft 7 when it
+    /// isn't set.
+    #[default]
+    Detect,
+    /// Values are validated using the draft, regardless of the `$schema` of the schema.
+    Draft(JsonDraft),
+}
+
+impl JsonValidation {
+    pub(crate) fn is_enabled(&self) -> bool {
+        *self != JsonValidation::None
+    }
+    /// Prepares the schema to be compiled by valico, depending on the draft used.
+    pub(crate) fn prepare(&self, schema: Value) -> Value {
+        let draft = match self {
+            JsonValidation::Draft(d) => Some(*d),
+            _ => JsonDraft::from_schema(&schema),
+        };
+        match draft {
+            Some(JsonDraft::Draft201909) | Some(JsonDraft::Draft202012) => rename_defs(schema),
+            _ => schema,
+        }
+    }
+}
+
+/// Keywords of which the value is an object with schema's by name, instead of a schema.
+const SCHEMAS_BY_NAME: [&str; 5] = [
+    "properties",
+    "patternProperties",
+    "definitions",
+    "$defs",
+    "dependentSchemas",
+];
+
+/// Moves the `$defs` to `definitions`, and updates the `$ref`'s pointing to them, since valico only
+/// knows `definitions`.
+fn rename_defs(schema: Value) -> Value {
+    match schema {
+        Value::Object(m) => Value::Object(
+            m.into_iter()
+                .map(|(k, v)| {
+                    let v = match v {
+                        Value::Object(c) if SCHEMAS_BY_NAME.contains(&k.as_str()) => Value::Object(
+                            c.into_iter()
+                                .map(|(n, s)| (n, rename_defs(s)))
+                                .collect::<Map<String, Value>>(),
+                        ),
+                        Value::String(r) if k == "$ref" => {
+                            Value::String(r.replace("#/$defs/", "#/definitions/"))
+                        }
+                        v => rename_defs(v),
+                    };
+                    match k.as_str() {
+                        "$defs" => (String::from("definitions"), v),
+                        _ => (k, v),
+                    }
+                })
+                .collect(),
+        ),
+        Value::Array(a) => Value::Array(a.into_iter().map(rename_defs).collect()),
+        v => v,
+    }
+}
 
 pub(crate) fn handle_validation(
     validation: ValidationState,
@@ -17,16 +139,46 @@ pub(crate) fn handle_validation(
             value
         )))
     } else {
+        let violations = validation
+            .errors
+            .iter()
+            .map(|e| SchemaViolation {
+                path: String::from(e.get_path()),
+                code: String::from(e.get_code()),
+                title: String::from(e.get_title()),
+                detail: e.get_detail().map(String::from),
+            })
+            .collect();
         Err(SRCError::non_retryable_without_cause(&format!(
             "Value {} was not valid according to the schema because {:?}",
             value, validation.errors
-        )))
+        ))
+        .with_violations(violations))
     }
 }
 
-pub(crate) fn to_bytes(id: u32, value: &Value) -> Result<Vec<u8>, SRCError> {
-    match serde_json::to_vec(value) {
-        Ok(bytes) => Ok(get_payload(id, bytes)),
+pub(crate) fn to_bytes(
+    id: u32,
+    value: &Value,
+    id_strategy: &IdStrategy,
+) -> Result<Vec<u8>, SRCError> {
+    let mut buf = Vec::new();
+    write_bytes(id, value, id_strategy, &mut buf)?;
+    Ok(buf)
+}
+
+/// Like `to_bytes`, but writing the header and the serialised value to the buffer, which is
+/// cleared first, so the buffer can be reused for multiple values.
+pub(crate) fn write_bytes(
+    id: u32,
+    value: &Value,
+    id_strategy: &IdStrategy,
+    buf: &mut Vec<u8>,
+) -> Result<(), SRCError> {
+    buf.clear();
+    id_strategy.write_header(id, buf);
+    match serde_json::to_writer(&mut *buf, value) {
+        Ok(()) => Ok(()),
         Err(e) => Err(SRCError::non_retryable_with_cause(
             e,
             "error serialising value to bytes",
@@ -34,6 +186,26 @@ pub(crate) fn to_bytes(id: u32, value: &Value) -> Result<Vec<u8>, SRCError> {
     }
 }
 
+/// Encrypts or decrypts the tagged properties of the value, when there is an encryptor and the
+/// schema has encrypt rules for the direction. The value is only copied when it's changed.
+pub(crate) fn apply_field_encryption<'v>(
+    encryptor: Option<&dyn FieldEncryptor>,
+    rule_set: Option<&RuleSet>,
+    metadata: Option<&Metadata>,
+    schema: &Value,
+    value: Cow<'v, Value>,
+    direction: Direction,
+) -> Result<Cow<'v, Value>, SRCError> {
+    match FieldEncryption::new(encryptor, rule_set, metadata, direction) {
+        Some(encryption) => {
+            let mut value = value.into_owned();
+            encryption.json_value(schema, &mut value, "")?;
+            Ok(Cow::Owned(value))
+        }
+        None => Ok(value),
+    }
+}
+
 pub(crate) fn fetch_id(def: &Value) -> Option<Url> {
     let id = match def {
         Value::Object(m) => match m.get("$id") {
@@ -51,6 +223,24 @@ pub(crate) fn fetch_id(def: &Value) -> Option<Url> {
     }
 }
 
+/// Gets the url of the reference, a relative name like `other.schema.json` is resolved against the
+/// url of the schema using the reference, so a `$ref` with the same name can be resolved.
+pub(crate) fn reference_url(base: &Url, rr: &RegisteredReference) -> Result<Url, SRCError> {
+    match Url::parse(&rr.name) {
+        Ok(v) => Ok(v),
+        Err(_) => match base.join(&rr.name) {
+            Ok(v) => Ok(v),
+            Err(e) => Err(SRCError::non_retryable_with_cause(
+                e,
+                &format!(
+                    "reference schema with subject {} and version {} has invalid name {}",
+                    rr.subject, rr.version, rr.name
+                ),
+            )),
+        },
+    }
+}
+
 pub(crate) fn fetch_fallback(url: &str, id: u32) -> Url {
     let id = format!("{}/id/{}.json", url, id);
     Url::parse(&id).unwrap()
@@ -68,3 +258,133 @@ pub(crate) fn to_value(str: &str) -> Result<Value, SRCError> {
     };
     Ok(value)
 }
+
+/// Encoder for a single type, bound to the id of the schema generated for that type with
+/// schemars. Because the schema is derived from the type, values are encoded without validation,
+/// and without calls to the schema registry.
+#[cfg(feature = "json_schemars")]
+#[derive(Debug)]
+pub struct BoundJsonEncoder<T> {
+    id: u32,
+    _type: PhantomData<fn(&T)>,
+}
+
+#[cfg(feature = "json_schemars")]
+impl<T: Serialize> BoundJsonEncoder<T> {
+    pub(crate) fn new(id: u32) -> BoundJsonEncoder<T> {
+        BoundJsonEncoder {
+            id,
+            _type: PhantomData,
+        }
+    }
+    /// The id of the registered schema.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+    /// Encodes the item to json, prepending the bytes with the id.
+    pub fn encode(&self, item: &T) -> Result<Vec<u8>, SRCError> {
+        match serde_json::to_vec(item) {
+            Ok(bytes) => Ok(get_payload(self.id, bytes)),
+            Err(e) => Err(SRCError::non_retryable_with_cause(
+                e,
+                "error serialising value to bytes",
+            )),
+        }
+    }
+}
+
+/// Generates the json schema of the type with schemars, to be registered for a subject.
+#[cfg(feature = "json_schemars")]
+pub(crate) fn schemars_schema<T: schemars::JsonSchema>() -> Result<SuppliedSchema, SRCError> {
+    match serde_json::to_string(&schemars::schema_for!(T)) {
+        Ok(schema) => Ok(SuppliedSchema {
+            name: Some(T::schema_name()),
+            schema_type: SchemaType::Json,
+            schema,
+            references: vec![],
+        }),
+        Err(e) => Err(SRCError::non_retryable_with_cause(
+            e,
+            &format!("could not generate schema for {}", T::schema_name()),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use valico::json_schema::Scope;
+
+    use url::Url;
+
+    use crate::json_common::{handle_validation, reference_url, JsonDraft, JsonValidation};
+    use crate::schema_registry_common::RegisteredReference;
+
+    #[test]
+    fn detect_draft() {
+        let schema = json!({"$schema": "http://json-schema.org/draft-04/schema#"});
+        assert_eq!(JsonDraft::from_schema(&schema), Some(JsonDraft::Draft4));
+        let schema = json!({"$schema": "https://json-schema.org/draft/2020-12/schema"});
+        assert_eq!(
+            JsonDraft::from_schema(&schema),
+            Some(JsonDraft::Draft202012)
+        );
+        assert_eq!(JsonDraft::from_schema(&json!({"type": "object"})), None);
+    }
+
+    #[test]
+    fn validation_with_defs() {
+        let schema = json!({
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "type": "object",
+            "properties": {
+                "$defs": {"type": "string"},
+                "beat": {"$ref": "#/$defs/beat"}
+            },
+            "required": ["beat"],
+            "$defs": {"beat": {"type": "integer"}}
+        });
+        let prepared = JsonValidation::Detect.prepare(schema.clone());
+        assert_eq!(
+            prepared["definitions"],
+            json!({"beat": {"type": "integer"}})
+        );
+        assert_eq!(prepared["properties"]["$defs"], json!({"type": "string"}));
+        assert_eq!(
+            prepared["properties"]["beat"],
+            json!({"$ref": "#/definitions/beat"})
+        );
+        assert_eq!(
+            JsonValidation::Draft(JsonDraft::Draft7).prepare(schema.clone()),
+            schema
+        );
+
+        let mut scope = Scope::new();
+        let compiled = scope.compile_and_return(prepared, false).unwrap();
+        let value = json!({"beat": 3});
+        assert!(handle_validation(compiled.validate(&value), &value).is_ok());
+        let value = json!({"beat": "3"});
+        let error = handle_validation(compiled.validate(&value), &value).unwrap_err();
+        assert_eq!(error.violations().len(), 1);
+        assert_eq!(error.violations()[0].path, "/beat");
+        assert_eq!(error.violations()[0].code, "wrong_type");
+    }
+
+    #[test]
+    fn relative_reference_url() {
+        let base = Url::parse("http://www.example.com/schemas/result.json").unwrap();
+        let reference = |name: &str| RegisteredReference {
+            name: String::from(name),
+            subject: String::from("other"),
+            version: 1,
+        };
+        assert_eq!(
+            reference_url(&base, &reference("other.schema.json")).unwrap(),
+            Url::parse("http://www.example.com/schemas/other.schema.json").unwrap()
+        );
+        assert_eq!(
+            reference_url(&base, &reference("http://other.com/other.json")).unwrap(),
+            Url::parse("http://other.com/other.json").unwrap()
+        );
+    }
+}
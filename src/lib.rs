@@ -21,19 +21,58 @@
 //! automatically does retries.
 //!
 //! [avro-rs]: https://crates.io/crates/avro-rs
+#[cfg(feature = "arrow")]
+pub mod arrow;
 #[cfg(feature = "futures")]
 pub mod async_impl;
 #[cfg(feature = "avro")]
 pub mod avro_common;
-#[cfg(feature = "blocking")]
+#[cfg(feature = "derive")]
+pub mod avro_derive;
+#[cfg(feature = "avro")]
+pub mod avro_logical;
+#[cfg(any(feature = "blocking", feature = "blocking_native"))]
 pub mod blocking;
+#[cfg(feature = "codegen")]
+pub mod codegen;
+#[cfg(any(
+    feature = "avro",
+    feature = "json",
+    feature = "proto_decoder",
+    feature = "proto_raw"
+))]
+pub mod compatibility;
 pub mod error;
+#[cfg(any(feature = "avro", feature = "json", feature = "proto_decoder"))]
+pub mod field_encryption;
 #[cfg(feature = "json")]
 mod json_common;
+#[cfg(any(
+    feature = "avro",
+    feature = "json",
+    feature = "proto_decoder",
+    feature = "proto_raw"
+))]
+pub mod lint;
+#[cfg(any(feature = "avro", feature = "json"))]
+pub mod migration;
 #[cfg(any(feature = "proto_decoder", feature = "proto_raw"))]
 mod proto_common_types;
+#[cfg(feature = "proto_decoder")]
+pub mod proto_decoder_common;
+#[cfg(feature = "proto_descriptor")]
+pub mod proto_descriptor;
+#[cfg(feature = "proto_decoder")]
+mod proto_deserializer;
 #[cfg(feature = "proto_raw")]
 pub mod proto_raw_common;
 #[cfg(any(feature = "proto_decoder", feature = "proto_raw"))]
 pub mod proto_resolver;
+#[cfg(any(feature = "proto_decoder", feature = "proto_raw"))]
+mod proto_schema;
+#[cfg(feature = "avro")]
+pub mod pulsar;
 pub mod schema_registry_common;
+mod telemetry;
+#[cfg(feature = "test_registry")]
+pub mod test_registry;
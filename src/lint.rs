@@ -0,0 +1,757 @@
+//! Checks schema's for conventions the schema registry doesn't enforce, like the naming, docs and
+//! defaults, so they can be checked in CI before the schema's are registered. The schema's are
+//! parsed the same way as they are for the compatibility checks.
+//!
+//! Each rule can be given a severity, or turned off, with the `Linter`.
+//! ```
+//! use schema_registry_converter::lint::{LintRule, Linter, Severity};
+//! use schema_registry_converter::schema_registry_common::SchemaType;
+//!
+//! let linter = Linter::new()
+//!     .with_severity(LintRule::UnsafeDefault, Severity::Error)
+//!     .without_rule(LintRule::MissingDoc);
+//! let schema = r#"{"type":"record","name":"Heartbeat","fields":[{"name":"beat","type":["null","long"]}]}"#;
+//! let findings = linter.lint(&SchemaType::Avro, schema).unwrap();
+//! assert_eq!(findings.len(), 1);
+//! assert_eq!(findings[0].severity, Severity::Error);
+//! assert_eq!(findings[0].message, "optional field beat has no default");
+//! ```
+use std::collections::HashMap;
+
+#[cfg(feature = "avro")]
+use apache_avro::Schema;
+#[cfg(feature = "json")]
+use serde_json::{Map, Value};
+
+use crate::error::SRCError;
+#[cfg(any(feature = "proto_decoder", feature = "proto_raw"))]
+use crate::proto_schema::{short_name, ProtoFile};
+use crate::schema_registry_common::SchemaType;
+
+/// The rules the linter can check.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LintRule {
+    /// Types should be PascalCase, enum symbols UPPER_SNAKE_CASE, and fields should have the
+    /// case set with `with_field_case`.
+    Naming,
+    /// Records, enums and messages, and json schema's of objects and their properties, should have
+    /// a doc. For protobuf this is a comment before the message or enum.
+    MissingDoc,
+    /// Defaults should be valid for their type, and optional avro fields should have a default, so
+    /// they can be removed later.
+    UnsafeDefault,
+    /// The first value of a protobuf enum should be zero, and named like `COLOR_UNSPECIFIED`, since
+    /// it's used when the value is not set.
+    EnumZeroValue,
+    /// Json schema's of objects should set additionalProperties, since whether the content model is
+    /// open or closed determines which changes are compatible.
+    AdditionalProperties,
+}
+
+const ALL_RULES: [LintRule; 5] = [
+    LintRule::Naming,
+    LintRule::MissingDoc,
+    LintRule::UnsafeDefault,
+    LintRule::EnumZeroValue,
+    LintRule::AdditionalProperties,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// The case of the names of fields and properties.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FieldCase {
+    /// Like `event_time`.
+    Snake,
+    /// Like `eventTime`.
+    Camel,
+}
+
+/// A violation of a rule, with the path of the type or field in the schema.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LintFinding {
+    pub rule: LintRule,
+    pub severity: Severity,
+    pub path: String,
+    pub message: String,
+}
+
+/// Checks schema's with the rules that are turned on, by default all rules with severity warning.
+/// Without a field case, protobuf fields should be snake case, and avro fields and json properties
+/// either snake or camel case.
+#[derive(Clone, Debug)]
+pub struct Linter {
+    rules: HashMap<LintRule, Severity>,
+    field_case: Option<FieldCase>,
+}
+
+impl Default for Linter {
+    fn default() -> Self {
+        Linter::new()
+    }
+}
+
+impl Linter {
+    pub fn new() -> Linter {
+        Linter {
+            rules: ALL_RULES.iter().map(|r| (*r, Severity::Warning)).collect(),
+            field_case: None,
+        }
+    }
+
+    /// Sets the severity of the rule, also turning it on when it was turned off.
+    pub fn with_severity(mut self, rule: LintRule, severity: Severity) -> Self {
+        self.rules.insert(rule, severity);
+        self
+    }
+
+    pub fn without_rule(mut self, rule: LintRule) -> Self {
+        self.rules.remove(&rule);
+        self
+    }
+
+    pub fn with_field_case(mut self, field_case: FieldCase) -> Self {
+        self.field_case = Some(field_case);
+        self
+    }
+
+    /// Parses the schema of the type and checks it. Avro schema's with references to other schema's
+    /// can't be parsed on their own, for those `lint_avro` can be used with the parsed schema.
+    pub fn lint(
+        &self,
+        schema_type: &SchemaType,
+        schema: &str,
+    ) -> Result<Vec<LintFinding>, SRCError> {
+        match schema_type {
+            #[cfg(feature = "avro")]
+            SchemaType::Avro => match Schema::parse_str(schema) {
+                Ok(v) => Ok(self.lint_avro(&v)),
+                Err(e) => Err(SRCError::non_retryable_with_cause(
+                    e,
+                    "Could not parse avro schema",
+                )),
+            },
+            #[cfg(feature = "json")]
+            SchemaType::Json => match serde_json::from_str::<Value>(schema) {
+                Ok(v) => Ok(self.lint_json(&v)),
+                Err(e) => Err(SRCError::non_retryable_with_cause(
+                    e,
+                    "Could not parse json schema",
+                )),
+            },
+            #[cfg(any(feature = "proto_decoder", feature = "proto_raw"))]
+            SchemaType::Protobuf => self.lint_protobuf(schema),
+            t => Err(SRCError::non_retryable_without_cause(&format!(
+                "Can't lint schema's of type {:?}",
+                t
+            ))),
+        }
+    }
+
+    #[cfg(feature = "avro")]
+    pub fn lint_avro(&self, schema: &Schema) -> Vec<LintFinding> {
+        let mut findings = Vec::new();
+        self.avro(schema, "", &mut findings);
+        findings
+    }
+
+    #[cfg(feature = "json")]
+    pub fn lint_json(&self, schema: &Value) -> Vec<LintFinding> {
+        let mut findings = Vec::new();
+        self.json(schema, "", &mut findings);
+        findings
+    }
+
+    #[cfg(any(feature = "proto_decoder", feature = "proto_raw"))]
+    pub fn lint_protobuf(&self, schema: &str) -> Result<Vec<LintFinding>, SRCError> {
+        let file = ProtoFile::parse(schema)?;
+        let mut findings = Vec::new();
+        for message in &file.messages {
+            let name = short_name(&message.name);
+            if !is_pascal_case(name) {
+                self.report(
+                    &mut findings,
+                    LintRule::Naming,
+                    &message.name,
+                    format!("message {} is not PascalCase", name),
+                );
+            }
+            if !message.documented {
+                self.report(
+                    &mut findings,
+                    LintRule::MissingDoc,
+                    &message.name,
+                    format!("message {} has no comment", name),
+                );
+            }
+            for field in &message.fields {
+                if !self.field_case_matches(&field.name, FieldCase::Snake) {
+                    self.report(
+                        &mut findings,
+                        LintRule::Naming,
+                        &format!("{}.{}", message.name, field.name),
+                        format!(
+                            "field {} is not {}",
+                            field.name,
+                            self.field_case_name(FieldCase::Snake)
+                        ),
+                    );
+                }
+            }
+        }
+        for enumeration in &file.enums {
+            let name = short_name(&enumeration.name);
+            if !is_pascal_case(name) {
+                self.report(
+                    &mut findings,
+                    LintRule::Naming,
+                    &enumeration.name,
+                    format!("enum {} is not PascalCase", name),
+                );
+            }
+            if !enumeration.documented {
+                self.report(
+                    &mut findings,
+                    LintRule::MissingDoc,
+                    &enumeration.name,
+                    format!("enum {} has no comment", name),
+                );
+            }
+            for (value, _) in enumeration
+                .values
+                .iter()
+                .filter(|(v, _)| !is_upper_snake_case(v))
+            {
+                self.report(
+                    &mut findings,
+                    LintRule::Naming,
+                    &format!("{}.{}", enumeration.name, value),
+                    format!("enum value {} is not UPPER_SNAKE_CASE", value),
+                );
+            }
+            match enumeration.values.first() {
+                Some((value, number)) if *number != 0 => self.report(
+                    &mut findings,
+                    LintRule::EnumZeroValue,
+                    &enumeration.name,
+                    format!(
+                        "the first value {} of enum {} is {} instead of 0",
+                        value, name, number
+                    ),
+                ),
+                Some((value, _))
+                    if !value.ends_with("UNSPECIFIED") && !value.ends_with("UNKNOWN") =>
+                {
+                    self.report(
+                        &mut findings,
+                        LintRule::EnumZeroValue,
+                        &enumeration.name,
+                        format!(
+                            "the zero value {} of enum {} should be named like {}_UNSPECIFIED",
+                            value,
+                            name,
+                            to_upper_snake_case(name)
+                        ),
+                    )
+                }
+                _ => (),
+            }
+        }
+        Ok(findings)
+    }
+
+    #[cfg(feature = "avro")]
+    fn avro(&self, schema: &Schema, path: &str, findings: &mut Vec<LintFinding>) {
+        match schema {
+            Schema::Record(r) => {
+                self.avro_named("record", &r.name.name, r.doc.is_some(), path, findings);
+                for field in &r.fields {
+                    let field_path = format!("{}/{}", path, field.name);
+                    if !self.field_case_matches(&field.name, FieldCase::Camel) {
+                        self.report(
+                            findings,
+                            LintRule::Naming,
+                            &field_path,
+                            format!(
+                                "field {} is not {}",
+                                field.name,
+                                self.field_case_name(FieldCase::Camel)
+                            ),
+                        );
+                    }
+                    let optional = matches!(&field.schema, Schema::Union(u) if u.is_nullable());
+                    match &field.default {
+                        None if optional => self.report(
+                            findings,
+                            LintRule::UnsafeDefault,
+                            &field_path,
+                            format!("optional field {} has no default", field.name),
+                        ),
+                        Some(default) if !avro_default_matches(default, &field.schema) => self
+                            .report(
+                                findings,
+                                LintRule::UnsafeDefault,
+                                &field_path,
+                                format!(
+                                    "the default {} of field {} doesn't match its type",
+                                    default, field.name
+                                ),
+                            ),
+                        _ => (),
+                    }
+                    self.avro(&field.schema, &field_path, findings);
+                }
+            }
+            Schema::Enum(e) => {
+                self.avro_named("enum", &e.name.name, e.doc.is_some(), path, findings);
+                for symbol in e.symbols.iter().filter(|s| !is_upper_snake_case(s)) {
+                    self.report(
+                        findings,
+                        LintRule::Naming,
+                        path,
+                        format!("enum symbol {} is not UPPER_SNAKE_CASE", symbol),
+                    );
+                }
+            }
+            Schema::Fixed(f) => {
+                self.avro_named("fixed", &f.name.name, f.doc.is_some(), path, findings)
+            }
+            Schema::Array(items) => self.avro(items, &format!("{}/items", path), findings),
+            Schema::Map(values) => self.avro(values, &format!("{}/values", path), findings),
+            Schema::Union(u) => {
+                for variant in u.variants() {
+                    self.avro(variant, path, findings);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    #[cfg(feature = "avro")]
+    fn avro_named(
+        &self,
+        kind: &str,
+        name: &str,
+        documented: bool,
+        path: &str,
+        findings: &mut Vec<LintFinding>,
+    ) {
+        if !is_pascal_case(name) {
+            self.report(
+                findings,
+                LintRule::Naming,
+                path,
+                format!("{} {} is not PascalCase", kind, name),
+            );
+        }
+        if !documented {
+            self.report(
+                findings,
+                LintRule::MissingDoc,
+                path,
+                format!("{} {} has no doc", kind, name),
+            );
+        }
+    }
+
+    #[cfg(feature = "json")]
+    fn json(&self, schema: &Value, path: &str, findings: &mut Vec<LintFinding>) {
+        let schema = match schema {
+            Value::Object(o) => o,
+            _ => return,
+        };
+        let is_object =
+            schema.get("type") == Some(&Value::from("object")) || schema.contains_key("properties");
+        if is_object {
+            if !schema.contains_key("description") && !schema.contains_key("title") {
+                self.report(
+                    findings,
+                    LintRule::MissingDoc,
+                    path,
+                    String::from("object has no description or title"),
+                );
+            }
+            if !schema.contains_key("additionalProperties") {
+                self.report(
+                    findings,
+                    LintRule::AdditionalProperties,
+                    path,
+                    String::from("object doesn't set additionalProperties"),
+                );
+            }
+        }
+        if let Some(default) = schema.get("default") {
+            if !json_default_matches(default, schema) {
+                self.report(
+                    findings,
+                    LintRule::UnsafeDefault,
+                    path,
+                    format!("the default {} doesn't match the schema", default),
+                );
+            }
+        }
+        if let Some(properties) = schema.get("properties").and_then(Value::as_object) {
+            for (name, property) in properties {
+                let property_path = format!("{}/properties/{}", path, name);
+                if !self.field_case_matches(name, FieldCase::Camel) {
+                    self.report(
+                        findings,
+                        LintRule::Naming,
+                        &property_path,
+                        format!(
+                            "property {} is not {}",
+                            name,
+                            self.field_case_name(FieldCase::Camel)
+                        ),
+                    );
+                }
+                let documented = property.as_object().map_or(true, |p| {
+                    p.contains_key("description") || p.contains_key("$ref")
+                });
+                if !documented {
+                    self.report(
+                        findings,
+                        LintRule::MissingDoc,
+                        &property_path,
+                        format!("property {} has no description", name),
+                    );
+                }
+                self.json(property, &property_path, findings);
+            }
+        }
+        for key in ["items", "additionalProperties", "not"] {
+            if let Some(sub_schema) = schema.get(key) {
+                self.json(sub_schema, &format!("{}/{}", path, key), findings);
+            }
+        }
+        for key in ["allOf", "anyOf", "oneOf", "prefixItems"] {
+            if let Some(sub_schemas) = schema.get(key).and_then(Value::as_array) {
+                for (i, sub_schema) in sub_schemas.iter().enumerate() {
+                    self.json(sub_schema, &format!("{}/{}/{}", path, key, i), findings);
+                }
+            }
+        }
+        for key in ["definitions", "$defs"] {
+            if let Some(definitions) = schema.get(key).and_then(Value::as_object) {
+                for (name, definition) in definitions {
+                    self.json(definition, &format!("{}/{}/{}", path, key, name), findings);
+                }
+            }
+        }
+    }
+
+    /// Whether the name has the field case, or the default case when not set. For avro and json
+    /// the default accepts both cases.
+    fn field_case_matches(&self, name: &str, default: FieldCase) -> bool {
+        match (self.field_case, default) {
+            (Some(FieldCase::Snake), _) | (None, FieldCase::Snake) => is_snake_case(name),
+            (Some(FieldCase::Camel), _) => is_camel_case(name),
+            (None, FieldCase::Camel) => is_snake_case(name) || is_camel_case(name),
+        }
+    }
+
+    fn field_case_name(&self, default: FieldCase) -> &'static str {
+        match (self.field_case, default) {
+            (Some(FieldCase::Snake), _) | (None, FieldCase::Snake) => "snake_case",
+            (Some(FieldCase::Camel), _) => "camelCase",
+            (None, FieldCase::Camel) => "snake_case or camelCase",
+        }
+    }
+
+    fn report(&self, findings: &mut Vec<LintFinding>, rule: LintRule, path: &str, message: String) {
+        if let Some(severity) = self.rules.get(&rule) {
+            findings.push(LintFinding {
+                rule,
+                severity: *severity,
+                path: if path.is_empty() {
+                    String::from("/")
+                } else {
+                    String::from(path)
+                },
+                message,
+            });
+        }
+    }
+}
+
+/// Whether the default is valid for the schema, for unions the default needs to be valid for the
+/// first type. Named types referenced by name are not resolved, and always match.
+#[cfg(feature = "avro")]
+fn avro_default_matches(default: &serde_json::Value, schema: &Schema) -> bool {
+    use serde_json::Value as Json;
+    match (schema, default) {
+        (Schema::Null, Json::Null) | (Schema::Boolean, Json::Bool(_)) => true,
+        (Schema::Int | Schema::Date | Schema::TimeMillis, Json::Number(n)) => {
+            n.as_i64().is_some_and(|i| i32::try_from(i).is_ok())
+        }
+        (
+            Schema::Long
+            | Schema::TimeMicros
+            | Schema::TimestampMillis
+            | Schema::TimestampMicros
+            | Schema::LocalTimestampMillis
+            | Schema::LocalTimestampMicros,
+            Json::Number(n),
+        ) => n.is_i64(),
+        (Schema::Float | Schema::Double, Json::Number(_)) => true,
+        (
+            Schema::Bytes
+            | Schema::String
+            | Schema::Uuid
+            | Schema::Fixed(_)
+            | Schema::Decimal(_)
+            | Schema::Duration,
+            Json::String(_),
+        ) => true,
+        (Schema::Enum(e), Json::String(s)) => e.symbols.contains(s),
+        (Schema::Array(items), Json::Array(values)) => {
+            values.iter().all(|v| avro_default_matches(v, items))
+        }
+        (Schema::Map(schema), Json::Object(values)) => {
+            values.values().all(|v| avro_default_matches(v, schema))
+        }
+        (Schema::Record(r), Json::Object(values)) => {
+            r.fields.iter().all(|f| match values.get(&f.name) {
+                Some(v) => avro_default_matches(v, &f.schema),
+                None => f.default.is_some(),
+            })
+        }
+        (Schema::Union(u), _) => u
+            .variants()
+            .first()
+            .is_some_and(|first| avro_default_matches(default, first)),
+        (Schema::Ref { .. }, _) => true,
+        _ => false,
+    }
+}
+
+/// Whether the default has one of the types, and is one of the enum values, when set.
+#[cfg(feature = "json")]
+fn json_default_matches(default: &Value, schema: &Map<String, Value>) -> bool {
+    if let Some(values) = schema.get("enum").and_then(Value::as_array) {
+        if !values.contains(default) {
+            return false;
+        }
+    }
+    let types: Vec<&str> = match schema.get("type") {
+        Some(Value::String(t)) => vec![t.as_str()],
+        Some(Value::Array(types)) => types.iter().filter_map(Value::as_str).collect(),
+        _ => return true,
+    };
+    types.into_iter().any(|t| match (t, default) {
+        ("null", Value::Null)
+        | ("boolean", Value::Bool(_))
+        | ("number", Value::Number(_))
+        | ("string", Value::String(_))
+        | ("array", Value::Array(_))
+        | ("object", Value::Object(_)) => true,
+        ("integer", Value::Number(n)) => n.is_i64() || n.is_u64(),
+        _ => false,
+    })
+}
+
+fn is_pascal_case(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_uppercase())
+        && name.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+fn is_camel_case(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_lowercase())
+        && name.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+fn is_snake_case(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_lowercase())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+fn is_upper_snake_case(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_uppercase())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')
+}
+
+#[cfg(any(feature = "proto_decoder", feature = "proto_raw"))]
+fn to_upper_snake_case(name: &str) -> String {
+    let mut out = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_ascii_uppercase() && i > 0 {
+            out.push('_');
+        }
+        out.push(c.to_ascii_uppercase());
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "avro")]
+    use apache_avro::Schema;
+    #[cfg(feature = "json")]
+    use serde_json::json;
+
+    #[cfg(feature = "avro")]
+    use crate::lint::{FieldCase, Severity};
+    use crate::lint::{LintRule, Linter};
+
+    #[cfg(feature = "avro")]
+    #[test]
+    fn lint_avro() {
+        let schema = Schema::parse_str(
+            r#"{"type":"record","name":"heartbeat","doc":"A beat","fields":[
+                {"name":"beat_count","type":"int","default":3000000000},
+                {"name":"source","type":["null","string"]},
+                {"name":"color","type":{"type":"enum","name":"Color","symbols":["red","GREEN"]},"default":"GREEN"}
+            ]}"#,
+        )
+        .unwrap();
+        let findings = Linter::new()
+            .with_severity(LintRule::UnsafeDefault, Severity::Error)
+            .with_field_case(FieldCase::Camel)
+            .lint_avro(&schema);
+        let findings: Vec<(LintRule, Severity, &str, &str)> = findings
+            .iter()
+            .map(|f| (f.rule, f.severity, f.path.as_str(), f.message.as_str()))
+            .collect();
+        assert_eq!(
+            findings,
+            vec![
+                (
+                    LintRule::Naming,
+                    Severity::Warning,
+                    "/",
+                    "record heartbeat is not PascalCase"
+                ),
+                (
+                    LintRule::Naming,
+                    Severity::Warning,
+                    "/beat_count",
+                    "field beat_count is not camelCase"
+                ),
+                (
+                    LintRule::UnsafeDefault,
+                    Severity::Error,
+                    "/beat_count",
+                    "the default 3000000000 of field beat_count doesn't match its type"
+                ),
+                (
+                    LintRule::UnsafeDefault,
+                    Severity::Error,
+                    "/source",
+                    "optional field source has no default"
+                ),
+                (
+                    LintRule::MissingDoc,
+                    Severity::Warning,
+                    "/color",
+                    "enum Color has no doc"
+                ),
+                (
+                    LintRule::Naming,
+                    Severity::Warning,
+                    "/color",
+                    "enum symbol red is not UPPER_SNAKE_CASE"
+                ),
+            ]
+        );
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn lint_json() {
+        let schema = json!({
+            "title": "Heartbeat",
+            "type": "object",
+            "properties": {
+                "beat": {"type": "integer", "description": "The beat", "default": 1.5},
+                "Source": {"type": "string", "enum": ["a", "b"], "default": "a"},
+                "tags": {"type": "array", "items": {"type": "object", "title": "Tag", "additionalProperties": false}}
+            },
+            "additionalProperties": false
+        });
+        let findings = Linter::new()
+            .without_rule(LintRule::MissingDoc)
+            .lint_json(&schema);
+        let findings: Vec<(LintRule, &str)> =
+            findings.iter().map(|f| (f.rule, f.path.as_str())).collect();
+        assert_eq!(
+            findings,
+            vec![
+                (LintRule::Naming, "/properties/Source"),
+                (LintRule::UnsafeDefault, "/properties/beat"),
+            ]
+        );
+    }
+
+    #[cfg(any(feature = "proto_decoder", feature = "proto_raw"))]
+    #[test]
+    fn lint_protobuf() {
+        let schema = r#"syntax = "proto3";
+package nl.openweb.data;
+
+// A heartbeat
+message Heartbeat {
+  int64 beat = 1; // the beat
+  string sourceName = 2;
+  enum Status {
+    STATUS_UNSPECIFIED = 0;
+    up = 1;
+  }
+}
+
+/* The color */
+enum Color {
+  RED = 0;
+  GREEN = 1;
+}
+
+enum Shape {
+  SHAPE_SQUARE = 1;
+}
+"#;
+        let findings = Linter::new().lint_protobuf(schema).unwrap();
+        let findings: Vec<(LintRule, &str, &str)> = findings
+            .iter()
+            .map(|f| (f.rule, f.path.as_str(), f.message.as_str()))
+            .collect();
+        assert_eq!(
+            findings,
+            vec![
+                (
+                    LintRule::Naming,
+                    "Heartbeat.sourceName",
+                    "field sourceName is not snake_case"
+                ),
+                (
+                    LintRule::MissingDoc,
+                    "Heartbeat.Status",
+                    "enum Status has no comment"
+                ),
+                (
+                    LintRule::Naming,
+                    "Heartbeat.Status.up",
+                    "enum value up is not UPPER_SNAKE_CASE"
+                ),
+                (
+                    LintRule::EnumZeroValue,
+                    "Color",
+                    "the zero value RED of enum Color should be named like COLOR_UNSPECIFIED"
+                ),
+                (LintRule::MissingDoc, "Shape", "enum Shape has no comment"),
+                (
+                    LintRule::EnumZeroValue,
+                    "Shape",
+                    "the first value SHAPE_SQUARE of enum Shape is 1 instead of 0"
+                ),
+            ]
+        );
+        assert!(Linter::new().lint_protobuf("message {").is_err());
+    }
+}
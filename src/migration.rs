@@ -0,0 +1,197 @@
+//! Migration rules of data contracts, to read values written with one major version of a schema as
+//! another major version. A consumer is pinned to the latest version of a subject with certain
+//! metadata, like `application.version`. When a value was written with another version, the
+//! migration rules of the versions in between are applied, UPGRADE rules when the value was written
+//! with an older version, and DOWNGRADE rules when it was written with a newer version.
+//!
+//! The rules transform the value as json. The expressions, like JSONata or CEL, are evaluated by an
+//! implementation of `RuleExecutor`.
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use serde_json::Value;
+
+use crate::error::SRCError;
+use crate::schema_registry_common::{RegisteredSchema, Rule};
+
+/// Transforms a value using the expression of a migration rule.
+pub trait RuleExecutor: Debug + Send + Sync {
+    fn transform(&self, rule: &Rule, value: Value) -> Result<Value, SRCError>;
+}
+
+/// The version of the subject values are read as, being the latest version having all the
+/// metadata, together with the executor for the migration rules.
+#[derive(Clone, Debug)]
+pub struct MigrationConfig {
+    pub subject: String,
+    pub metadata: HashMap<String, String>,
+    pub executor: Arc<dyn RuleExecutor>,
+}
+
+impl MigrationConfig {
+    pub fn new(
+        subject: &str,
+        metadata: HashMap<String, String>,
+        executor: Arc<dyn RuleExecutor>,
+    ) -> MigrationConfig {
+        MigrationConfig {
+            subject: String::from(subject),
+            metadata,
+            executor,
+        }
+    }
+}
+
+/// The id of the schema to read the values written with a certain schema as, with the rules to
+/// apply, in order.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Migration {
+    pub(crate) reader_id: u32,
+    pub(crate) rules: Vec<Rule>,
+}
+
+/// The versions with rules to apply, in the order to apply them, ascending for an upgrade and
+/// descending for a downgrade.
+pub(crate) fn versions_between(
+    versions: &[u32],
+    writer_version: u32,
+    reader_version: u32,
+) -> Vec<u32> {
+    let mut between: Vec<u32> = if writer_version < reader_version {
+        versions
+            .iter()
+            .filter(|v| **v > writer_version && **v <= reader_version)
+            .copied()
+            .collect()
+    } else {
+        versions
+            .iter()
+            .filter(|v| **v > reader_version && **v <= writer_version)
+            .copied()
+            .collect()
+    };
+    between.sort_unstable();
+    if writer_version > reader_version {
+        between.reverse();
+    }
+    between
+}
+
+/// The enabled migration rules of the schema's, for either an upgrade or a downgrade.
+pub(crate) fn migration_rules(upgrade: bool, schemas: &[RegisteredSchema]) -> Vec<Rule> {
+    let modes: &[&str] = if upgrade {
+        &["UPGRADE", "UPGRADEDOWNGRADE"]
+    } else {
+        &["DOWNGRADE", "UPGRADEDOWNGRADE"]
+    };
+    schemas
+        .iter()
+        .filter_map(|s| s.rule_set.as_ref())
+        .flat_map(|r| r.migration_rules.iter())
+        .filter(|r| !r.disabled && modes.contains(&r.mode.as_str()))
+        .cloned()
+        .collect()
+}
+
+/// Applies the rules to the value, in order.
+pub(crate) fn migrate(
+    executor: &dyn RuleExecutor,
+    rules: &[Rule],
+    value: Value,
+) -> Result<Value, SRCError> {
+    rules
+        .iter()
+        .try_fold(value, |value, rule| executor.transform(rule, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde_json::{json, Value};
+
+    use crate::error::SRCError;
+    use crate::migration::{migrate, migration_rules, versions_between, RuleExecutor};
+    use crate::schema_registry_common::{RegisteredSchema, Rule, RuleSet, SchemaType};
+
+    /// Renames the field in the expression of the rule, like `name>full_name`.
+    #[derive(Debug)]
+    struct Rename;
+
+    impl RuleExecutor for Rename {
+        fn transform(&self, rule: &Rule, mut value: Value) -> Result<Value, SRCError> {
+            let expr = rule.expr.as_deref().unwrap_or_default();
+            let (from, to) = expr.split_once('>').unwrap();
+            let object = value.as_object_mut().unwrap();
+            let v = object.remove(from).unwrap_or(Value::Null);
+            object.insert(String::from(to), v);
+            Ok(value)
+        }
+    }
+
+    fn rule(mode: &str, expr: &str) -> Rule {
+        Rule {
+            name: String::from(expr),
+            doc: None,
+            kind: String::from("TRANSFORM"),
+            mode: String::from(mode),
+            rule_type: String::from("JSONATA"),
+            tags: vec![],
+            params: HashMap::new(),
+            expr: Some(String::from(expr)),
+            on_success: None,
+            on_failure: None,
+            disabled: false,
+        }
+    }
+
+    fn schema(version: u32, rules: Vec<Rule>) -> RegisteredSchema {
+        RegisteredSchema {
+            id: version + 10,
+            schema_type: SchemaType::Json,
+            schema: String::from("{}"),
+            references: vec![],
+            subject: Some(String::from("customer-value")),
+            version: Some(version),
+            metadata: None,
+            rule_set: Some(RuleSet {
+                migration_rules: rules,
+                domain_rules: vec![],
+            }),
+            guid: None,
+            deleted: false,
+        }
+    }
+
+    #[test]
+    fn versions_to_apply() {
+        let versions = [1, 2, 4, 5];
+        assert_eq!(versions_between(&versions, 1, 4), vec![2, 4]);
+        assert_eq!(versions_between(&versions, 5, 2), vec![5, 4]);
+        assert!(versions_between(&versions, 2, 2).is_empty());
+    }
+
+    #[test]
+    fn upgrade_and_downgrade() {
+        let schemas = [
+            schema(
+                2,
+                vec![
+                    rule("UPGRADE", "name>full_name"),
+                    rule("DOWNGRADE", "full_name>name"),
+                ],
+            ),
+            schema(3, vec![rule("UPGRADEDOWNGRADE", "age>years")]),
+        ];
+        let upgrade = migration_rules(true, &schemas);
+        let exprs: Vec<_> = upgrade.iter().filter_map(|r| r.expr.as_deref()).collect();
+        assert_eq!(exprs, vec!["name>full_name", "age>years"]);
+        let value = migrate(&Rename, &upgrade, json!({"name":"Jan","age":3})).unwrap();
+        assert_eq!(value, json!({"full_name":"Jan","years":3}));
+
+        let downgrade = migration_rules(false, &schemas);
+        assert_eq!(downgrade.len(), 2);
+        assert_eq!(downgrade[0].expr.as_deref(), Some("full_name>name"));
+    }
+}
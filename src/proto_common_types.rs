@@ -4,6 +4,9 @@ use std::collections::HashSet;
 pub(crate) fn add_common_files(imports: &Vec<String>, files: &mut HashSet<String>) {
     for import in imports {
         if let Some(common_schema) = is_common_import(import) {
+            for dependency in get_dependencies(&common_schema) {
+                files.insert(String::from(get_schema(dependency)));
+            }
             files.insert(String::from(get_schema(&common_schema)));
             continue;
         }
@@ -69,6 +72,22 @@ enum CommonSchema {
     Wrappers,
 }
 
+/// Whether the import is one of the well known types, which don't need to be registered as reference.
+pub(crate) fn is_well_known(import: &str) -> bool {
+    is_common_import(import).is_some() || is_common_type_import(import).is_some()
+}
+
+/// Gets the schema of a well known type by the name of the import, without its dependencies, for
+/// when the imports are resolved by name.
+#[cfg(feature = "proto_reflect")]
+pub(crate) fn get_common_file(import: &str) -> Option<&'static str> {
+    match (is_common_import(import), is_common_type_import(import)) {
+        (Some(common_schema), _) => Some(get_schema(&common_schema)),
+        (None, Some(common_type)) => get_schemas(common_type).last().map(get_schema),
+        (None, None) => None,
+    }
+}
+
 fn is_common_import(import: &str) -> Option<CommonSchema> {
     match import {
         "google/protobuf/any.proto" => Some(CommonSchema::Any),
@@ -86,6 +105,14 @@ fn is_common_import(import: &str) -> Option<CommonSchema> {
     }
 }
 
+/// Some of the well known types import other well known types, these need to be added as well.
+fn get_dependencies(common_schema: &CommonSchema) -> &'static [CommonSchema] {
+    match common_schema {
+        CommonSchema::Api => &[CommonSchema::SourceContext, CommonSchema::Type],
+        _ => &[],
+    }
+}
+
 fn is_common_type_import(import: &str) -> Option<CommonType> {
     match import {
         "google/type/calendar_period.proto" => Some(CommonType::CalendarPeriod),
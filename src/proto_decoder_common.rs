@@ -0,0 +1,1160 @@
+//! Contains structs and functions common to the async and blocking implementation of the proto
+//! decoder. Mainly the decoding itself, and to transform the values decoded by protofish into
+//! something easier to work with.
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use bytes::Bytes;
+use chrono::{DateTime, SecondsFormat, Utc};
+use protofish::context::{Context, MessageField, MessageInfo, Multiplicity, ValueType};
+use protofish::decode::{EnumValue, MessageValue, PackedArray, UnknownValue, Value};
+use serde::de::{DeserializeOwned, Visitor};
+use serde::{forward_to_deserialize_any, Deserializer};
+use serde_json::{Map, Number};
+
+use crate::error::{SRCError, WireFormatError};
+use crate::field_encryption::{Direction, FieldEncryption, FieldEncryptor};
+use crate::proto_common_types::add_common_files;
+#[cfg(feature = "proto_descriptor")]
+use crate::proto_descriptor::to_proto_files;
+use crate::proto_deserializer::ProtoDeserializer;
+#[cfg(feature = "proto_descriptor")]
+use crate::proto_resolver::find_imports;
+use crate::proto_resolver::{resolve_name, to_index_and_offset, MessageResolver};
+use crate::proto_schema::{proto2_constructs, to_proto3};
+#[cfg(feature = "proto_descriptor")]
+use crate::schema_registry_common::SchemaType;
+use crate::schema_registry_common::{to_wire_format_error, RegisteredSchema, Strictness};
+
+/// Everything needed to decode bytes for one schema id. The context is parsed from the schema, the
+/// referenced schema's, and the well known types that are imported.
+#[derive(Debug)]
+pub struct DecodeContext {
+    pub resolver: MessageResolver,
+    pub context: Context,
+    pub registered_schema: RegisteredSchema,
+}
+
+/// Lookups in the parsed schema, to convert decoded values without using the protofish context
+/// directly. The messages and enums are given by their full name, which includes the package.
+impl DecodeContext {
+    /// Gives the full name of the message of the decoded value.
+    pub fn message_name(&self, value: &MessageValue) -> &str {
+        &self.context.resolve_message(value.msg_ref).full_name
+    }
+
+    /// Gives the name of the field with the number in the message.
+    pub fn field_name(&self, message: &str, number: u64) -> Option<&str> {
+        self.field(message, number).map(|f| f.name.as_str())
+    }
+
+    /// Gives the name of the enum value, when the enum has the value.
+    pub fn enum_value_name(&self, enum_name: &str, value: i64) -> Option<&str> {
+        self.context
+            .get_enum(enum_name)?
+            .get_field_by_value(value)
+            .map(|f| f.name.as_str())
+    }
+
+    /// Whether the field is repeated, which is also the case for map fields. None when the message
+    /// doesn't have the field.
+    pub fn is_repeated(&self, message: &str, number: u64) -> Option<bool> {
+        self.field(message, number).map(is_repeated)
+    }
+
+    /// Whether the field is a map field, the values of map fields are entry messages with the key
+    /// as field 1 and the value as field 2. None when the message doesn't have the field.
+    pub fn is_map(&self, message: &str, number: u64) -> Option<bool> {
+        self.field(message, number)
+            .map(|f| is_repeated(f) && is_map_entry(f, &self.context))
+    }
+
+    fn field(&self, message: &str, number: u64) -> Option<&MessageField> {
+        self.context.get_message(message)?.get_field(number)
+    }
+}
+
+/// Selects the message used to decode payloads which don't have the message index, as produced by
+/// some non Confluent clients. The function gets the schema of the payload, and returns the full
+/// name of the message to use, or None when the payload does have the message index.
+#[derive(Clone)]
+pub struct MessageSelector(Arc<dyn Fn(&RegisteredSchema) -> Option<String> + Send + Sync>);
+
+impl MessageSelector {
+    pub fn new<F>(selector: F) -> MessageSelector
+    where
+        F: Fn(&RegisteredSchema) -> Option<String> + Send + Sync + 'static,
+    {
+        MessageSelector(Arc::new(selector))
+    }
+    pub(crate) fn select(&self, registered_schema: &RegisteredSchema) -> Option<String> {
+        (self.0)(registered_schema)
+    }
+}
+
+impl Debug for MessageSelector {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "MessageSelector")
+    }
+}
+
+#[derive(Debug)]
+pub struct DecodeResultWithContext {
+    pub value: MessageValue,
+    pub context: Arc<DecodeContext>,
+    pub full_name: Arc<String>,
+    pub data_bytes: Bytes,
+}
+
+impl DecodeResultWithContext {
+    /// Fields in the bytes which are not part of the schema used for decoding, which might mean the
+    /// producer used a newer schema.
+    pub fn unknown_fields(&self) -> Vec<UnknownField> {
+        unknown_fields(&self.value, &self.context.context)
+    }
+
+    fn deserializer(&self) -> ProtoDeserializer<'_> {
+        let context = &self.context.context;
+        ProtoDeserializer::message(
+            context.resolve_message(self.value.msg_ref),
+            Some(&self.value),
+            context,
+        )
+    }
+}
+
+/// Makes it possible to use the result with serde, the values are deserialized with the names from
+/// the proto schema, fields which are not set are deserialized as their default.
+impl<'de> Deserializer<'de> for &'de DecodeResultWithContext {
+    type Error = serde_json::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserializer().deserialize_any(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserializer().deserialize_option(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.deserializer()
+            .deserialize_newtype_struct(name, visitor)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.deserializer()
+            .deserialize_struct(name, fields, visitor)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple
+        tuple_struct map enum identifier ignored_any
+    }
+}
+
+/// Parses the schema, the files should already contain the referenced schema's.
+pub(crate) fn to_decode_context(
+    registered_schema: RegisteredSchema,
+    mut files: HashSet<String>,
+) -> Result<DecodeContext, SRCError> {
+    let resolver = MessageResolver::new(&registered_schema.schema);
+    add_common_files(resolver.imports(), &mut files);
+    files.insert(registered_schema.schema.clone());
+    match Context::parse(files.iter().map(|f| to_proto3(f))) {
+        Ok(context) => Ok(DecodeContext {
+            resolver,
+            context,
+            registered_schema,
+        }),
+        Err(e) => Err(to_context_error(e, &files)),
+    }
+}
+
+/// Names the proto2 constructs used by the schema's when there are any, since those are the most
+/// likely reason the schema's could not be parsed.
+fn to_context_error(cause: impl fmt::Display, files: &HashSet<String>) -> SRCError {
+    let constructs: Vec<String> = files.iter().flat_map(|f| proto2_constructs(f)).collect();
+    if constructs.is_empty() {
+        SRCError::non_retryable_with_cause(cause, "Error creating proto context")
+    } else {
+        SRCError::non_retryable_with_cause(
+            cause,
+            &format!(
+                "Error creating proto context, the proto2 {} might not be supported",
+                constructs.join(", ")
+            ),
+        )
+    }
+}
+
+/// Creates the decode context from a serialized FileDescriptorSet instead of the schema registry.
+/// The last file of the set is used as the schema, the other files as the referenced schema's.
+#[cfg(feature = "proto_descriptor")]
+pub(crate) fn to_local_decode_context(
+    id: u32,
+    descriptor_set: &[u8],
+) -> Result<DecodeContext, SRCError> {
+    let mut proto_files = to_proto_files(descriptor_set)?;
+    let schema = match proto_files.pop() {
+        Some((_, schema)) => schema,
+        None => {
+            return Err(SRCError::non_retryable_without_cause(
+                "FileDescriptorSet doesn't contain any file",
+            ))
+        }
+    };
+    let mut files = HashSet::new();
+    for (_, source) in proto_files {
+        add_common_files(&find_imports(&source), &mut files);
+        files.insert(source);
+    }
+    let registered_schema = RegisteredSchema {
+        id,
+        schema_type: SchemaType::Protobuf,
+        schema,
+        references: vec![],
+        subject: None,
+        version: None,
+        metadata: None,
+        rule_set: None,
+        guid: None,
+        deleted: false,
+    };
+    to_decode_context(registered_schema, files)
+}
+
+/// Decodes the bytes, without the magic byte and schema id, using the context for the schema id.
+/// The data bytes in the result share the memory with the supplied bytes, unless fields were
+/// decrypted.
+pub(crate) fn decode_with_context(
+    context: Arc<DecodeContext>,
+    bytes: Bytes,
+    message_selector: Option<&MessageSelector>,
+    field_encryptor: Option<&dyn FieldEncryptor>,
+) -> Result<DecodeResultWithContext, SRCError> {
+    if let Some(full_name) = message_selector.and_then(|s| s.select(&context.registered_schema)) {
+        return decode_message(context, Arc::new(full_name), bytes, field_encryptor);
+    }
+    let (index, offset) = to_index_and_offset(&bytes)?;
+    let full_name = resolve_name(&context.resolver, &index)?;
+    decode_message(context, full_name, bytes.slice(offset..), field_encryptor)
+}
+
+/// Decodes the data bytes, which don't start with the message index, as the message with the full
+/// name. When there is an encryptor, the fields tagged by an ENCRYPT rule are decrypted first.
+pub(crate) fn decode_message(
+    context: Arc<DecodeContext>,
+    full_name: Arc<String>,
+    data_bytes: Bytes,
+    field_encryptor: Option<&dyn FieldEncryptor>,
+) -> Result<DecodeResultWithContext, SRCError> {
+    let message_info = match context.context.get_message(&full_name) {
+        Some(message_info) => message_info,
+        None => return Err(message_not_found(&full_name, context.registered_schema.id)),
+    };
+    let schema = &context.registered_schema;
+    let data_bytes = match FieldEncryption::new(
+        field_encryptor,
+        schema.rule_set.as_ref(),
+        schema.metadata.as_ref(),
+        Direction::Read,
+    ) {
+        Some(encryption) => {
+            Bytes::from(encryption.proto_bytes(&context.context, message_info, &data_bytes)?)
+        }
+        None => data_bytes,
+    };
+    let value = message_info.decode(&data_bytes, &context.context);
+    Ok(DecodeResultWithContext {
+        value,
+        context,
+        full_name,
+        data_bytes,
+    })
+}
+
+/// Error for a message name, resolved from the message index, that is not part of the schema, which
+/// could happen when the schema registry returned a different schema than the payload was encoded
+/// with.
+pub(crate) fn message_not_found(full_name: &str, id: u32) -> SRCError {
+    SRCError::non_retryable_without_cause(&format!(
+        "Could not find message {} in the schema with id {}",
+        full_name, id
+    ))
+}
+
+/// Error for bytes that don't follow the wire format, typed when strict.
+pub(crate) fn invalid_bytes_error(bytes: &[u8], strictness: Strictness) -> SRCError {
+    match strictness {
+        Strictness::Lenient => SRCError::new("no protobuf compatible bytes", None, false),
+        Strictness::Strict => SRCError::from(to_wire_format_error(bytes)),
+    }
+}
+
+/// When strict, bytes after the message that could not be decoded give an error.
+pub(crate) fn check_trailing_bytes(
+    result: DecodeResultWithContext,
+    strictness: Strictness,
+) -> Result<DecodeResultWithContext, SRCError> {
+    match (strictness, &result.value.garbage) {
+        (Strictness::Strict, Some(garbage)) => Err(SRCError::from(WireFormatError::TrailingBytes(
+            garbage.len(),
+        ))),
+        _ => Ok(result),
+    }
+}
+
+/// Transforms a decoded message to a json value. The proto field names are used as keys, enums are
+/// set as the name of the value, bytes are base64 encoded and maps become json objects. Well known
+/// types like Timestamp, Duration, the wrappers, Struct and Any are transformed the same way as the
+/// Java JsonFormat does. Fields that are not part of the message in the context are left out.
+pub fn proto_value_to_json(value: &MessageValue, context: &Context) -> serde_json::Value {
+    let info = context.resolve_message(value.msg_ref);
+    match well_known_to_json(info, value, context) {
+        Some(v) => v,
+        None => message_to_json(info, value, context),
+    }
+}
+
+/// Checks the bytes can be decoded as the message with the full name, without fields that are not in
+/// the schema, fields with the wrong wire type, incomplete values or trailing bytes.
+pub(crate) fn validate_message(
+    context: &Context,
+    id: u32,
+    full_name: &str,
+    bytes: &[u8],
+) -> Result<(), SRCError> {
+    let info = match context.get_message(full_name) {
+        Some(v) => v,
+        None => return Err(message_not_found(full_name, id)),
+    };
+    validate_value(&info.decode(bytes, context), context, full_name)
+}
+
+fn validate_value(value: &MessageValue, context: &Context, path: &str) -> Result<(), SRCError> {
+    if let Some(garbage) = &value.garbage {
+        return Err(SRCError::non_retryable_without_cause(&format!(
+            "Message {} has {} trailing bytes that could not be decoded",
+            path,
+            garbage.len()
+        )));
+    }
+    let info = context.resolve_message(value.msg_ref);
+    for field_value in &value.fields {
+        let field_name = match info.get_field(field_value.number) {
+            Some(f) => f.name.clone(),
+            None => field_value.number.to_string(),
+        };
+        match &field_value.value {
+            Value::Unknown(_) => {
+                return Err(SRCError::non_retryable_without_cause(&format!(
+                    "Field {} in message {} is not part of the schema, or has the wrong type",
+                    field_name, path
+                )))
+            }
+            Value::Incomplete(_, _) => {
+                return Err(SRCError::non_retryable_without_cause(&format!(
+                    "Field {} in message {} is incomplete",
+                    field_name, path
+                )))
+            }
+            Value::Message(m) => validate_value(m, context, &format!("{}.{}", path, field_name))?,
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+/// Deserializes a decoded message into any struct implementing Deserialize. The field names of the
+/// proto schema are used, so serde attributes like rename can be used when they differ, a rename to
+/// the number of the field also works. Fields which are not set get their proto3 default, or None
+/// for an Option, enums use the name of the value, or the number for an integer, and bytes can be
+/// deserialized into a `Vec<u8>`. A oneof can be deserialized as an enum, with a variant per field.
+pub fn from_proto_value<T: DeserializeOwned>(
+    value: &MessageValue,
+    context: &Context,
+) -> Result<T, SRCError> {
+    let info = context.resolve_message(value.msg_ref);
+    match T::deserialize(ProtoDeserializer::message(info, Some(value), context)) {
+        Ok(v) => Ok(v),
+        Err(e) => Err(SRCError::non_retryable_with_cause(
+            e,
+            "Could not deserialize protobuf value",
+        )),
+    }
+}
+
+fn message_to_json(
+    info: &MessageInfo,
+    value: &MessageValue,
+    context: &Context,
+) -> serde_json::Value {
+    let mut fields: BTreeMap<u64, (&MessageField, Vec<serde_json::Value>)> = BTreeMap::new();
+    for field_value in &value.fields {
+        let field = match info.get_field(field_value.number) {
+            Some(f) => f,
+            None => continue,
+        };
+        let entry = fields
+            .entry(field_value.number)
+            .or_insert_with(|| (field, Vec::new()));
+        match &field_value.value {
+            Value::Packed(p) => entry.1.extend(packed_to_json(p)),
+            v => entry.1.push(to_json(v, context)),
+        }
+    }
+    let mut map = Map::new();
+    for (_, (field, mut values)) in fields {
+        let json = match field.multiplicity {
+            Multiplicity::Repeated | Multiplicity::RepeatedPacked => {
+                if is_map_entry(field, context) {
+                    serde_json::Value::Object(entries_to_map(values))
+                } else {
+                    serde_json::Value::Array(values)
+                }
+            }
+            _ => values.pop().unwrap_or(serde_json::Value::Null),
+        };
+        map.insert(field.name.clone(), json);
+    }
+    serde_json::Value::Object(map)
+}
+
+/// Transforms a single decoded value to json, using the context to find the names of messages and
+/// enums.
+pub(crate) fn to_json(value: &Value, context: &Context) -> serde_json::Value {
+    match value {
+        Value::Double(v) => float_to_json(*v),
+        Value::Float(v) => float_to_json(*v as f64),
+        Value::Int32(v) | Value::SInt32(v) | Value::SFixed32(v) => serde_json::Value::from(*v),
+        Value::Int64(v) | Value::SInt64(v) | Value::SFixed64(v) => serde_json::Value::from(*v),
+        Value::UInt32(v) | Value::Fixed32(v) => serde_json::Value::from(*v),
+        Value::UInt64(v) | Value::Fixed64(v) => serde_json::Value::from(*v),
+        Value::Bool(v) => serde_json::Value::Bool(*v),
+        Value::String(v) => serde_json::Value::String(v.clone()),
+        Value::Bytes(v) => serde_json::Value::String(STANDARD.encode(v)),
+        Value::Message(v) => proto_value_to_json(v, context),
+        Value::Enum(v) => enum_to_json(v, context),
+        Value::Packed(v) => serde_json::Value::Array(packed_to_json(v)),
+        _ => serde_json::Value::Null,
+    }
+}
+
+fn float_to_json(v: f64) -> serde_json::Value {
+    match Number::from_f64(v) {
+        Some(n) => serde_json::Value::Number(n),
+        None => serde_json::Value::String(v.to_string()),
+    }
+}
+
+fn enum_to_json(value: &EnumValue, context: &Context) -> serde_json::Value {
+    let info = context.resolve_enum(value.enum_ref);
+    match info.get_field_by_value(value.value) {
+        Some(f) => serde_json::Value::String(f.name.clone()),
+        None => serde_json::Value::from(value.value),
+    }
+}
+
+fn packed_to_json(packed: &PackedArray) -> Vec<serde_json::Value> {
+    match packed {
+        PackedArray::Double(v) => v.iter().map(|f| float_to_json(*f)).collect(),
+        PackedArray::Float(v) => v.iter().map(|f| float_to_json(*f as f64)).collect(),
+        PackedArray::Int32(v) | PackedArray::SInt32(v) | PackedArray::SFixed32(v) => {
+            v.iter().map(|i| serde_json::Value::from(*i)).collect()
+        }
+        PackedArray::Int64(v) | PackedArray::SInt64(v) | PackedArray::SFixed64(v) => {
+            v.iter().map(|i| serde_json::Value::from(*i)).collect()
+        }
+        PackedArray::UInt32(v) | PackedArray::Fixed32(v) => {
+            v.iter().map(|i| serde_json::Value::from(*i)).collect()
+        }
+        PackedArray::UInt64(v) | PackedArray::Fixed64(v) => {
+            v.iter().map(|i| serde_json::Value::from(*i)).collect()
+        }
+        PackedArray::Bool(v) => v.iter().map(|b| serde_json::Value::Bool(*b)).collect(),
+    }
+}
+
+pub(crate) fn is_repeated(field: &MessageField) -> bool {
+    matches!(
+        field.multiplicity,
+        Multiplicity::Repeated | Multiplicity::RepeatedPacked
+    )
+}
+
+/// Map fields are encoded as repeated messages with a key and value field, protofish doesn't make
+/// the distinction, so we recognize them by the generated entry message.
+pub(crate) fn is_map_entry(field: &MessageField, context: &Context) -> bool {
+    match field.field_type {
+        ValueType::Message(msg_ref) => {
+            let info = context.resolve_message(msg_ref);
+            info.name.ends_with("Entry")
+                && info.get_field(1).map(|f| f.name == "key").unwrap_or(false)
+                && info
+                    .get_field(2)
+                    .map(|f| f.name == "value")
+                    .unwrap_or(false)
+        }
+        _ => false,
+    }
+}
+
+fn entries_to_map(entries: Vec<serde_json::Value>) -> Map<String, serde_json::Value> {
+    let mut map = Map::new();
+    for entry in entries {
+        if let serde_json::Value::Object(mut e) = entry {
+            let key = match e.remove("key") {
+                Some(serde_json::Value::String(s)) => s,
+                Some(v) => v.to_string(),
+                None => String::new(),
+            };
+            let value = e.remove("value").unwrap_or(serde_json::Value::Null);
+            map.insert(key, value);
+        }
+    }
+    map
+}
+
+pub(crate) fn field_value<'v>(value: &'v MessageValue, number: u64) -> Option<&'v Value> {
+    value
+        .fields
+        .iter()
+        .rev()
+        .find(|f| f.number == number)
+        .map(|f| &f.value)
+}
+
+pub(crate) fn int_field(value: &MessageValue, number: u64) -> i64 {
+    match field_value(value, number) {
+        Some(Value::Int64(v)) => *v,
+        Some(Value::Int32(v)) => *v as i64,
+        _ => 0,
+    }
+}
+
+pub(crate) fn well_known_to_json(
+    info: &MessageInfo,
+    value: &MessageValue,
+    context: &Context,
+) -> Option<serde_json::Value> {
+    match info.full_name.as_str() {
+        "google.protobuf.Timestamp" => Some(serde_json::Value::String(format_timestamp(
+            int_field(value, 1),
+            int_field(value, 2) as i32,
+        ))),
+        "google.protobuf.Duration" => Some(serde_json::Value::String(format_duration(
+            int_field(value, 1),
+            int_field(value, 2) as i32,
+        ))),
+        "google.protobuf.DoubleValue"
+        | "google.protobuf.FloatValue"
+        | "google.protobuf.Int64Value"
+        | "google.protobuf.UInt64Value"
+        | "google.protobuf.Int32Value"
+        | "google.protobuf.UInt32Value"
+        | "google.protobuf.BoolValue"
+        | "google.protobuf.StringValue"
+        | "google.protobuf.BytesValue" => Some(match field_value(value, 1) {
+            Some(v) => to_json(v, context),
+            None => wrapper_default(&info.full_name),
+        }),
+        "google.protobuf.Struct" => {
+            Some(match message_to_json(info, value, context).get("fields") {
+                Some(v) => v.clone(),
+                None => serde_json::Value::Object(Map::new()),
+            })
+        }
+        "google.protobuf.Value" => Some(match value.fields.last() {
+            Some(f) if f.number == 1 => serde_json::Value::Null,
+            Some(f) => to_json(&f.value, context),
+            None => serde_json::Value::Null,
+        }),
+        "google.protobuf.ListValue" => {
+            Some(match message_to_json(info, value, context).get("values") {
+                Some(v) => v.clone(),
+                None => serde_json::Value::Array(Vec::new()),
+            })
+        }
+        "google.protobuf.FieldMask" => {
+            Some(match message_to_json(info, value, context).get("paths") {
+                Some(serde_json::Value::Array(paths)) => serde_json::Value::String(
+                    paths
+                        .iter()
+                        .filter_map(|p| p.as_str())
+                        .collect::<Vec<&str>>()
+                        .join(","),
+                ),
+                _ => serde_json::Value::String(String::new()),
+            })
+        }
+        "google.protobuf.Any" => Some(any_to_json(value, context)),
+        _ => None,
+    }
+}
+
+/// Ergonomic representation of the well known types from google.protobuf, which otherwise would be
+/// nested messages. Json is used for the types without a good Rust counterpart.
+#[derive(Clone, Debug, PartialEq)]
+pub enum WellKnownValue {
+    Timestamp(DateTime<Utc>),
+    Duration(chrono::Duration),
+    Double(f64),
+    Float(f32),
+    Int64(i64),
+    UInt64(u64),
+    Int32(i32),
+    UInt32(u32),
+    Bool(bool),
+    String(String),
+    Bytes(Vec<u8>),
+    Struct(Map<String, serde_json::Value>),
+    Value(serde_json::Value),
+    ListValue(Vec<serde_json::Value>),
+    FieldMask(Vec<String>),
+    Any(serde_json::Value),
+    Empty,
+}
+
+/// Transforms the message to a well known value, will return None when the message is not one of
+/// the well known types.
+pub fn to_well_known(value: &MessageValue, context: &Context) -> Option<WellKnownValue> {
+    let info = context.resolve_message(value.msg_ref);
+    let wrapped = field_value(value, 1);
+    match info.full_name.as_str() {
+        "google.protobuf.Timestamp" => Some(WellKnownValue::Timestamp(to_date_time(
+            int_field(value, 1),
+            int_field(value, 2) as i32,
+        )?)),
+        "google.protobuf.Duration" => Some(WellKnownValue::Duration(
+            chrono::Duration::seconds(int_field(value, 1))
+                + chrono::Duration::nanoseconds(int_field(value, 2)),
+        )),
+        "google.protobuf.DoubleValue" => Some(WellKnownValue::Double(match wrapped {
+            Some(Value::Double(v)) => *v,
+            _ => 0.0,
+        })),
+        "google.protobuf.FloatValue" => Some(WellKnownValue::Float(match wrapped {
+            Some(Value::Float(v)) => *v,
+            _ => 0.0,
+        })),
+        "google.protobuf.Int64Value" => Some(WellKnownValue::Int64(match wrapped {
+            Some(Value::Int64(v)) => *v,
+            _ => 0,
+        })),
+        "google.protobuf.UInt64Value" => Some(WellKnownValue::UInt64(match wrapped {
+            Some(Value::UInt64(v)) => *v,
+            _ => 0,
+        })),
+        "google.protobuf.Int32Value" => Some(WellKnownValue::Int32(match wrapped {
+            Some(Value::Int32(v)) => *v,
+            _ => 0,
+        })),
+        "google.protobuf.UInt32Value" => Some(WellKnownValue::UInt32(match wrapped {
+            Some(Value::UInt32(v)) => *v,
+            _ => 0,
+        })),
+        "google.protobuf.BoolValue" => Some(WellKnownValue::Bool(match wrapped {
+            Some(Value::Bool(v)) => *v,
+            _ => false,
+        })),
+        "google.protobuf.StringValue" => Some(WellKnownValue::String(match wrapped {
+            Some(Value::String(v)) => v.clone(),
+            _ => String::new(),
+        })),
+        "google.protobuf.BytesValue" => Some(WellKnownValue::Bytes(match wrapped {
+            Some(Value::Bytes(v)) => v.to_vec(),
+            _ => Vec::new(),
+        })),
+        "google.protobuf.Struct" => match well_known_to_json(info, value, context) {
+            Some(serde_json::Value::Object(map)) => Some(WellKnownValue::Struct(map)),
+            _ => Some(WellKnownValue::Struct(Map::new())),
+        },
+        "google.protobuf.Value" => Some(WellKnownValue::Value(well_known_to_json(
+            info, value, context,
+        )?)),
+        "google.protobuf.ListValue" => match well_known_to_json(info, value, context) {
+            Some(serde_json::Value::Array(values)) => Some(WellKnownValue::ListValue(values)),
+            _ => Some(WellKnownValue::ListValue(Vec::new())),
+        },
+        "google.protobuf.FieldMask" => Some(WellKnownValue::FieldMask(
+            value
+                .fields
+                .iter()
+                .filter_map(|f| match &f.value {
+                    Value::String(s) => Some(s.clone()),
+                    _ => None,
+                })
+                .collect(),
+        )),
+        "google.protobuf.Any" => Some(WellKnownValue::Any(any_to_json(value, context))),
+        "google.protobuf.Empty" => Some(WellKnownValue::Empty),
+        _ => None,
+    }
+}
+
+/// Gets a field of the message by name as well known value. When the field is not set None is
+/// returned, which makes the wrappers behave like an Option.
+pub fn well_known_field(
+    value: &MessageValue,
+    context: &Context,
+    name: &str,
+) -> Option<WellKnownValue> {
+    let info = context.resolve_message(value.msg_ref);
+    let field = info.get_field_by_name(name)?;
+    match field_value(value, field.number) {
+        Some(Value::Message(m)) => to_well_known(m, context),
+        _ => None,
+    }
+}
+
+pub(crate) fn wrapper_default(full_name: &str) -> serde_json::Value {
+    match full_name {
+        "google.protobuf.BoolValue" => serde_json::Value::Bool(false),
+        "google.protobuf.StringValue" | "google.protobuf.BytesValue" => {
+            serde_json::Value::String(String::new())
+        }
+        _ => serde_json::Value::from(0),
+    }
+}
+
+/// An Any contains the type url, and the encoded message. When the type is part of the context the
+/// message is decoded and added next to the `@type` key, otherwise the base64 encoded bytes are
+/// added as `value`.
+fn any_to_json(value: &MessageValue, context: &Context) -> serde_json::Value {
+    let type_url = match field_value(value, 1) {
+        Some(Value::String(s)) => s.clone(),
+        _ => String::new(),
+    };
+    let bytes = match field_value(value, 2) {
+        Some(Value::Bytes(b)) => b.clone(),
+        _ => Default::default(),
+    };
+    let mut map = Map::new();
+    map.insert(
+        String::from("@type"),
+        serde_json::Value::String(type_url.clone()),
+    );
+    let type_name = type_url.rsplit('/').next().unwrap_or("");
+    match context.get_message(type_name) {
+        Some(info) => {
+            let inner = info.decode(&bytes, context);
+            match proto_value_to_json(&inner, context) {
+                serde_json::Value::Object(fields) => map.extend(fields),
+                v => {
+                    map.insert(String::from("value"), v);
+                }
+            }
+        }
+        None => {
+            map.insert(
+                String::from("value"),
+                serde_json::Value::String(STANDARD.encode(&bytes)),
+            );
+        }
+    }
+    serde_json::Value::Object(map)
+}
+
+/// Formats seconds and nanos since the epoch as a RFC 3339 string in UTC.
+pub(crate) fn format_timestamp(seconds: i64, nanos: i32) -> String {
+    match to_date_time(seconds, nanos) {
+        Some(v) => v.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+        None => String::new(),
+    }
+}
+
+fn to_date_time(seconds: i64, nanos: i32) -> Option<DateTime<Utc>> {
+    DateTime::from_timestamp(seconds, u32::try_from(nanos).ok()?)
+}
+
+/// Formats a duration the way the proto3 json mapping does, so seconds with a `s` suffix.
+pub(crate) fn format_duration(seconds: i64, nanos: i32) -> String {
+    let sign = if seconds < 0 || nanos < 0 { "-" } else { "" };
+    format!(
+        "{}{}{}s",
+        sign,
+        seconds.unsigned_abs(),
+        format_nanos(nanos.abs())
+    )
+}
+
+fn format_nanos(nanos: i32) -> String {
+    if nanos == 0 {
+        String::new()
+    } else if nanos % 1_000_000 == 0 {
+        format!(".{:03}", nanos / 1_000_000)
+    } else if nanos % 1_000 == 0 {
+        format!(".{:06}", nanos / 1_000)
+    } else {
+        format!(".{:09}", nanos)
+    }
+}
+
+/// A field in the bytes which is not part of the message in the schema used for decoding, or has a
+/// different type than in the schema. Typically because the producer used a newer schema.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UnknownField {
+    /// Full name of the message the field was found in, might be a nested message.
+    pub message: String,
+    pub number: u64,
+    pub wire_type: u8,
+    /// The bytes of the value, without the tag, and for length delimited values without the length.
+    pub bytes: Vec<u8>,
+}
+
+/// Gets all the unknown fields of the message, including the ones in nested messages.
+pub fn unknown_fields(value: &MessageValue, context: &Context) -> Vec<UnknownField> {
+    let mut result = Vec::new();
+    add_unknown_fields(value, context, &mut result);
+    result
+}
+
+fn add_unknown_fields(value: &MessageValue, context: &Context, result: &mut Vec<UnknownField>) {
+    let info = context.resolve_message(value.msg_ref);
+    for field_value in &value.fields {
+        match &field_value.value {
+            Value::Unknown(unknown) => {
+                let (wire_type, bytes) = unknown_to_bytes(unknown);
+                result.push(UnknownField {
+                    message: info.full_name.clone(),
+                    number: field_value.number,
+                    wire_type,
+                    bytes,
+                })
+            }
+            Value::Message(m) => add_unknown_fields(m, context, result),
+            _ => (),
+        }
+    }
+}
+
+fn unknown_to_bytes(unknown: &UnknownValue) -> (u8, Vec<u8>) {
+    match unknown {
+        UnknownValue::Varint(v) => {
+            let mut bytes = Vec::new();
+            let mut v = *v;
+            while v >= 0x80 {
+                bytes.push((v as u8) | 0x80);
+                v >>= 7;
+            }
+            bytes.push(v as u8);
+            (0, bytes)
+        }
+        UnknownValue::Fixed64(v) => (1, v.to_le_bytes().to_vec()),
+        UnknownValue::VariableLength(v) => (2, v.to_vec()),
+        UnknownValue::Fixed32(v) => (5, v.to_le_bytes().to_vec()),
+        UnknownValue::Invalid(wire_type, v) => (*wire_type, v.to_vec()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::proto_common_types::add_common_files;
+    use crate::proto_decoder_common::{
+        format_duration, format_timestamp, from_proto_value, proto_value_to_json, to_context_error,
+        to_decode_context, unknown_fields, validate_message, well_known_field, UnknownField,
+        WellKnownValue,
+    };
+    use crate::schema_registry_common::{RegisteredSchema, SchemaType};
+    use chrono::DateTime;
+    use protofish::context::Context;
+    use serde::Deserialize;
+    use serde_json::json;
+    use std::collections::HashSet;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    enum Color {
+        #[serde(rename = "RED")]
+        Red,
+        #[serde(rename = "GREEN")]
+        Green,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Person {
+        name: String,
+        numbers: Vec<i32>,
+        color: Color,
+        #[serde(default)]
+        missing: Option<String>,
+    }
+
+    fn get_context() -> Context {
+        Context::parse([r#"syntax = "proto3";
+            package nl.openweb.data;
+            message Person {
+              string name = 1;
+              repeated int32 numbers = 2;
+              Color color = 3;
+              bytes raw = 5;
+              enum Color { RED = 0; GREEN = 1; }
+            }"#])
+        .unwrap()
+    }
+
+    #[test]
+    fn message_to_json() {
+        let context = get_context();
+        let info = context.get_message("nl.openweb.data.Person").unwrap();
+        let bytes = [10, 3, 66, 111, 98, 18, 2, 1, 2, 24, 1, 42, 2, 1, 2];
+        let value = info.decode(&bytes, &context);
+
+        let json = proto_value_to_json(&value, &context);
+
+        assert_eq!(
+            json,
+            json!({"name": "Bob", "numbers": [1, 2], "color": "GREEN", "raw": "AQI="})
+        )
+    }
+
+    #[test]
+    fn message_to_struct() {
+        let context = get_context();
+        let info = context.get_message("nl.openweb.data.Person").unwrap();
+        let bytes = [10, 3, 66, 111, 98, 18, 2, 1, 2, 24, 1, 42, 2, 1, 2];
+        let value = info.decode(&bytes, &context);
+
+        let person: Person = from_proto_value(&value, &context).unwrap();
+
+        assert_eq!(
+            person,
+            Person {
+                name: String::from("Bob"),
+                numbers: vec![1, 2],
+                color: Color::Green,
+                missing: None,
+            }
+        )
+    }
+
+    #[test]
+    fn well_known_types() {
+        let mut files = HashSet::new();
+        add_common_files(
+            &vec![
+                String::from("google/protobuf/timestamp.proto"),
+                String::from("google/protobuf/wrappers.proto"),
+            ],
+            &mut files,
+        );
+        files.insert(String::from(
+            r#"syntax = "proto3";
+            package nl.openweb.data;
+            import "google/protobuf/timestamp.proto";
+            import "google/protobuf/wrappers.proto";
+            message Event {
+              google.protobuf.Timestamp at = 1;
+              google.protobuf.StringValue note = 2;
+              google.protobuf.Int64Value count = 3;
+            }"#,
+        ));
+        let context = Context::parse(files).unwrap();
+        let info = context.get_message("nl.openweb.data.Event").unwrap();
+        let bytes = [10, 3, 8, 232, 7, 18, 4, 10, 2, 104, 105];
+        let value = info.decode(&bytes, &context);
+
+        assert_eq!(
+            well_known_field(&value, &context, "at"),
+            Some(WellKnownValue::Timestamp(
+                DateTime::from_timestamp(1000, 0).unwrap()
+            ))
+        );
+        assert_eq!(
+            well_known_field(&value, &context, "note"),
+            Some(WellKnownValue::String(String::from("hi")))
+        );
+        assert_eq!(well_known_field(&value, &context, "count"), None);
+        assert_eq!(
+            proto_value_to_json(&value, &context),
+            json!({"at": "1970-01-01T00:16:40Z", "note": "hi"})
+        )
+    }
+
+    #[test]
+    fn validate_valid_message() {
+        let context = get_context();
+        let bytes = [10, 3, 66, 111, 98, 18, 2, 1, 2, 24, 1, 42, 2, 1, 2];
+
+        assert!(validate_message(&context, 1, "nl.openweb.data.Person", &bytes).is_ok())
+    }
+
+    #[test]
+    fn validate_invalid_message() {
+        let context = get_context();
+
+        let wrong_type =
+            validate_message(&context, 1, "nl.openweb.data.Person", &[8, 1]).unwrap_err();
+        assert_eq!(
+            wrong_type.error,
+            "Field name in message nl.openweb.data.Person is not part of the schema, or has the wrong type"
+        );
+
+        let unknown_message =
+            validate_message(&context, 1, "nl.openweb.data.Foo", &[]).unwrap_err();
+        assert_eq!(
+            unknown_message.error,
+            "Could not find message nl.openweb.data.Foo in the schema with id 1"
+        );
+    }
+
+    #[test]
+    fn unknown_fields_of_message() {
+        let context = get_context();
+        let info = context.get_message("nl.openweb.data.Person").unwrap();
+        let value = info.decode(&[10, 3, 66, 111, 98, 48, 150, 1, 58, 2, 1, 2], &context);
+
+        assert_eq!(
+            unknown_fields(&value, &context),
+            vec![
+                UnknownField {
+                    message: String::from("nl.openweb.data.Person"),
+                    number: 6,
+                    wire_type: 0,
+                    bytes: vec![150, 1],
+                },
+                UnknownField {
+                    message: String::from("nl.openweb.data.Person"),
+                    number: 7,
+                    wire_type: 2,
+                    bytes: vec![1, 2],
+                },
+            ]
+        )
+    }
+
+    #[test]
+    fn decode_context_of_editions_and_optional() {
+        for syntax in [r#"edition = "2023";"#, r#"syntax = "proto3";"#] {
+            let schema = format!(
+                "{} package nl.openweb.data; message Person {{ string name = 1; {} int32 age = 2; }}",
+                syntax,
+                if syntax.starts_with("edition") { "" } else { "optional" }
+            );
+            let registered_schema = RegisteredSchema {
+                id: 7,
+                schema_type: SchemaType::Protobuf,
+                schema,
+                references: vec![],
+                subject: None,
+                version: None,
+                metadata: None,
+                rule_set: None,
+                guid: None,
+                deleted: false,
+            };
+            let context = to_decode_context(registered_schema, HashSet::new()).unwrap();
+            let info = context
+                .context
+                .get_message("nl.openweb.data.Person")
+                .unwrap();
+            let value = info.decode(&[10, 3, 66, 111, 98, 16, 42], &context.context);
+
+            assert_eq!(
+                proto_value_to_json(&value, &context.context),
+                json!({"name": "Bob", "age": 42})
+            );
+        }
+    }
+
+    #[test]
+    fn context_error_names_proto2_constructs() {
+        let schema = r#"syntax = "proto2";
+            message Search {
+              required string query = 1;
+              optional int32 page = 2 [default = 1];
+              repeated group Result = 3 { required string url = 4; }
+              extensions 100 to 199;
+            }"#;
+        let files = HashSet::from([String::from(schema)]);
+
+        let error = to_context_error("unexpected token", &files);
+        assert_eq!(
+            error.error,
+            "Error creating proto context, the proto2 group Result, extensions 100 might not be supported"
+        );
+        assert_eq!(error.cause, Some(String::from("unexpected token")));
+
+        let files = HashSet::from([String::from("message A { string group = 1; }")]);
+        let error = to_context_error("unexpected token", &files);
+        assert_eq!(error.error, "Error creating proto context");
+    }
+
+    #[test]
+    fn decode_context_lookups() {
+        let schema = r#"syntax = "proto3";
+            package nl.openweb.data;
+            message Person {
+              string name = 1;
+              repeated int32 numbers = 2;
+              Color color = 3;
+              repeated LabelsEntry labels = 4;
+              enum Color { RED = 0; GREEN = 1; }
+              message LabelsEntry { string key = 1; string value = 2; }
+            }"#;
+        let registered_schema = RegisteredSchema {
+            id: 7,
+            schema_type: SchemaType::Protobuf,
+            schema: String::from(schema),
+            references: vec![],
+            subject: None,
+            version: None,
+            metadata: None,
+            rule_set: None,
+            guid: None,
+            deleted: false,
+        };
+        let context = to_decode_context(registered_schema, HashSet::new()).unwrap();
+        let person = "nl.openweb.data.Person";
+
+        let info = context.context.get_message(person).unwrap();
+        let value = info.decode(&[10, 3, 66, 111, 98], &context.context);
+        assert_eq!(context.message_name(&value), person);
+        assert_eq!(context.field_name(person, 3), Some("color"));
+        assert_eq!(context.field_name(person, 5), None);
+        assert_eq!(context.field_name("nl.openweb.data.Animal", 1), None);
+        assert_eq!(
+            context.enum_value_name("nl.openweb.data.Person.Color", 1),
+            Some("GREEN")
+        );
+        assert_eq!(
+            context.enum_value_name("nl.openweb.data.Person.Color", 2),
+            None
+        );
+        assert_eq!(context.is_repeated(person, 1), Some(false));
+        assert_eq!(context.is_repeated(person, 2), Some(true));
+        assert_eq!(context.is_repeated(person, 4), Some(true));
+        assert_eq!(context.is_map(person, 2), Some(false));
+        assert_eq!(context.is_map(person, 4), Some(true));
+        assert_eq!(context.is_map(person, 5), None);
+    }
+
+    #[test]
+    fn timestamp_formatting() {
+        assert_eq!(format_timestamp(0, 0), "1970-01-01T00:00:00Z");
+        assert_eq!(
+            format_timestamp(1_609_459_199, 500_000_000),
+            "2020-12-31T23:59:59.500Z"
+        );
+    }
+
+    #[test]
+    fn duration_formatting() {
+        assert_eq!(format_duration(3, 0), "3s");
+        assert_eq!(format_duration(-1, -500_000_000), "-1.500s");
+        assert_eq!(format_duration(0, 1), "0.000000001s");
+    }
+}
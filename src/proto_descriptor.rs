@@ -0,0 +1,503 @@
+//! Transforms a compiled FileDescriptorSet, for example created with
+//! `protoc --include_imports --descriptor_set_out`, back into proto schema's. The schema registry,
+//! and protofish, only work with the proto source, so this makes it possible to use descriptor sets
+//! for registering and decoding. Options, services and extensions are not part of the result, and
+//! public imports become normal imports.
+use std::collections::HashSet;
+
+use prost::Message;
+use prost_types::field_descriptor_proto::{Label, Type};
+use prost_types::{
+    DescriptorProto, EnumDescriptorProto, FieldDescriptorProto, FileDescriptorProto,
+    FileDescriptorSet,
+};
+
+use crate::error::SRCError;
+
+/// Gives the name and proto schema of each file in the serialized FileDescriptorSet, in the same
+/// order as in the set, so imported files come before the files importing them.
+pub fn to_proto_files(descriptor_set: &[u8]) -> Result<Vec<(String, String)>, SRCError> {
+    decode_set(descriptor_set)?
+        .file
+        .iter()
+        .map(|f| Ok((String::from(f.name()), to_proto(f)?)))
+        .collect()
+}
+
+/// A file of a descriptor set, as it needs to be registered.
+#[derive(Debug)]
+pub struct ProtoFile {
+    pub name: String,
+    pub schema: String,
+    pub dependencies: Vec<String>,
+    /// The full names of the messages in the file, including nested messages.
+    pub messages: Vec<String>,
+}
+
+/// Gives the files of the serialized FileDescriptorSet, sorted so each file comes after the files
+/// it imports. Fails when an import is missing from the set, which happens when the set was created
+/// without including the imports.
+pub fn to_sorted_proto_files(descriptor_set: &[u8]) -> Result<Vec<ProtoFile>, SRCError> {
+    let set = decode_set(descriptor_set)?;
+    let mut sorted = Vec::with_capacity(set.file.len());
+    let mut visiting = HashSet::new();
+    for file in &set.file {
+        visit(file, &set.file, &mut visiting, &mut sorted)?;
+    }
+    sorted
+        .into_iter()
+        .map(|f| {
+            let mut messages = Vec::new();
+            add_message_names(f.package(), &f.message_type, &mut messages);
+            Ok(ProtoFile {
+                name: String::from(f.name()),
+                schema: to_proto(f)?,
+                dependencies: f.dependency.clone(),
+                messages,
+            })
+        })
+        .collect()
+}
+
+fn decode_set(descriptor_set: &[u8]) -> Result<FileDescriptorSet, SRCError> {
+    match FileDescriptorSet::decode(descriptor_set) {
+        Ok(v) => Ok(v),
+        Err(e) => Err(SRCError::non_retryable_with_cause(
+            e,
+            "Could not decode bytes as FileDescriptorSet",
+        )),
+    }
+}
+
+fn visit<'a>(
+    file: &'a FileDescriptorProto,
+    files: &'a [FileDescriptorProto],
+    visiting: &mut HashSet<&'a str>,
+    sorted: &mut Vec<&'a FileDescriptorProto>,
+) -> Result<(), SRCError> {
+    if sorted.iter().any(|f| f.name() == file.name()) {
+        return Ok(());
+    }
+    if !visiting.insert(file.name()) {
+        return Err(SRCError::non_retryable_without_cause(&format!(
+            "Import cycle found for file {}",
+            file.name()
+        )));
+    }
+    for dependency in &file.dependency {
+        match files.iter().find(|f| f.name() == dependency) {
+            Some(d) => visit(d, files, visiting, sorted)?,
+            None => {
+                return Err(SRCError::non_retryable_without_cause(&format!(
+                    "File {} imports {} which is not part of the FileDescriptorSet",
+                    file.name(),
+                    dependency
+                )))
+            }
+        }
+    }
+    visiting.remove(file.name());
+    sorted.push(file);
+    Ok(())
+}
+
+fn add_message_names(prefix: &str, messages: &[DescriptorProto], names: &mut Vec<String>) {
+    for message in messages.iter().filter(|m| !is_map_entry(m)) {
+        let name = if prefix.is_empty() {
+            String::from(message.name())
+        } else {
+            format!("{}.{}", prefix, message.name())
+        };
+        names.push(name.clone());
+        add_message_names(&name, &message.nested_type, names);
+    }
+}
+
+/// Writes the file descriptor as proto schema.
+pub fn to_proto(file: &FileDescriptorProto) -> Result<String, SRCError> {
+    let proto2 = file.syntax() != "proto3";
+    let mut out = String::new();
+    if proto2 {
+        out.push_str("syntax = \"proto2\";\n");
+    } else {
+        out.push_str("syntax = \"proto3\";\n");
+    }
+    if !file.package().is_empty() {
+        out.push_str(&format!("package {};\n", file.package()));
+    }
+    for dependency in &file.dependency {
+        out.push_str(&format!("import \"{}\";\n", dependency));
+    }
+    for message in &file.message_type {
+        out.push('\n');
+        write_message(&mut out, message, 0, proto2)?;
+    }
+    for enum_type in &file.enum_type {
+        out.push('\n');
+        write_enum(&mut out, enum_type, 0);
+    }
+    Ok(out)
+}
+
+fn write_message(
+    out: &mut String,
+    message: &DescriptorProto,
+    indent: usize,
+    proto2: bool,
+) -> Result<(), SRCError> {
+    let pad = "  ".repeat(indent);
+    out.push_str(&format!("{}message {} {{\n", pad, message.name()));
+    for field in message.field.iter().filter(|f| !in_oneof(f)) {
+        write_field(out, field, message, indent + 1, proto2)?;
+    }
+    for (i, oneof) in message.oneof_decl.iter().enumerate() {
+        let fields: Vec<&FieldDescriptorProto> = message
+            .field
+            .iter()
+            .filter(|f| in_oneof(f) && f.oneof_index() == i as i32)
+            .collect();
+        if fields.is_empty() {
+            continue;
+        }
+        out.push_str(&format!("{}  oneof {} {{\n", pad, oneof.name()));
+        for field in fields {
+            out.push_str(&format!(
+                "{}    {} {} = {};\n",
+                pad,
+                field_type(field)?,
+                field.name(),
+                field.number()
+            ));
+        }
+        out.push_str(&format!("{}  }}\n", pad));
+    }
+    for nested in message.nested_type.iter().filter(|n| !is_map_entry(n)) {
+        write_message(out, nested, indent + 1, proto2)?;
+    }
+    for enum_type in &message.enum_type {
+        write_enum(out, enum_type, indent + 1);
+    }
+    out.push_str(&format!("{}}}\n", pad));
+    Ok(())
+}
+
+fn write_field(
+    out: &mut String,
+    field: &FieldDescriptorProto,
+    message: &DescriptorProto,
+    indent: usize,
+    proto2: bool,
+) -> Result<(), SRCError> {
+    let pad = "  ".repeat(indent);
+    if let Some(entry) = map_entry(field, message) {
+        let key = entry.field.iter().find(|f| f.number() == 1);
+        let value = entry.field.iter().find(|f| f.number() == 2);
+        return match (key, value) {
+            (Some(key), Some(value)) => {
+                out.push_str(&format!(
+                    "{}map<{}, {}> {} = {};\n",
+                    pad,
+                    field_type(key)?,
+                    field_type(value)?,
+                    field.name(),
+                    field.number()
+                ));
+                Ok(())
+            }
+            _ => Err(SRCError::non_retryable_without_cause(&format!(
+                "Invalid map entry for field {}",
+                field.name()
+            ))),
+        };
+    }
+    let label = match field.label() {
+        Label::Repeated => "repeated ",
+        Label::Required => "required ",
+        Label::Optional if proto2 || field.proto3_optional() => "optional ",
+        Label::Optional => "",
+    };
+    let default = match &field.default_value {
+        Some(v) if matches!(field.r#type(), Type::String | Type::Bytes) => {
+            format!(" [default = \"{}\"]", v)
+        }
+        Some(v) => format!(" [default = {}]", v),
+        None => String::new(),
+    };
+    out.push_str(&format!(
+        "{}{}{} {} = {}{};\n",
+        pad,
+        label,
+        field_type(field)?,
+        field.name(),
+        field.number(),
+        default
+    ));
+    Ok(())
+}
+
+fn write_enum(out: &mut String, enum_type: &EnumDescriptorProto, indent: usize) {
+    let pad = "  ".repeat(indent);
+    out.push_str(&format!("{}enum {} {{\n", pad, enum_type.name()));
+    for value in &enum_type.value {
+        out.push_str(&format!(
+            "{}  {} = {};\n",
+            pad,
+            value.name(),
+            value.number()
+        ));
+    }
+    out.push_str(&format!("{}}}\n", pad));
+}
+
+/// Fields which are proto3 optional are in a synthetic oneof, which should not be written.
+fn in_oneof(field: &FieldDescriptorProto) -> bool {
+    field.oneof_index.is_some() && !field.proto3_optional()
+}
+
+fn is_map_entry(message: &DescriptorProto) -> bool {
+    message
+        .options
+        .as_ref()
+        .map(|o| o.map_entry())
+        .unwrap_or(false)
+}
+
+fn map_entry<'a>(
+    field: &FieldDescriptorProto,
+    message: &'a DescriptorProto,
+) -> Option<&'a DescriptorProto> {
+    if field.label() != Label::Repeated || field.r#type() != Type::Message {
+        return None;
+    }
+    let name = field.type_name().rsplit('.').next()?;
+    message
+        .nested_type
+        .iter()
+        .find(|n| n.name() == name && is_map_entry(n))
+}
+
+fn field_type(field: &FieldDescriptorProto) -> Result<String, SRCError> {
+    let scalar = match field.r#type() {
+        Type::Double => "double",
+        Type::Float => "float",
+        Type::Int64 => "int64",
+        Type::Uint64 => "uint64",
+        Type::Int32 => "int32",
+        Type::Fixed64 => "fixed64",
+        Type::Fixed32 => "fixed32",
+        Type::Bool => "bool",
+        Type::String => "string",
+        Type::Bytes => "bytes",
+        Type::Uint32 => "uint32",
+        Type::Sfixed32 => "sfixed32",
+        Type::Sfixed64 => "sfixed64",
+        Type::Sint32 => "sint32",
+        Type::Sint64 => "sint64",
+        Type::Message | Type::Enum => {
+            return Ok(String::from(field.type_name().trim_start_matches('.')))
+        }
+        Type::Group => {
+            return Err(SRCError::non_retryable_without_cause(&format!(
+                "Group field {} is not supported",
+                field.name()
+            )))
+        }
+    };
+    Ok(String::from(scalar))
+}
+
+#[cfg(test)]
+mod tests {
+    use prost::Message;
+    use prost_types::field_descriptor_proto::{Label, Type};
+    use prost_types::{
+        DescriptorProto, EnumDescriptorProto, EnumValueDescriptorProto, FieldDescriptorProto,
+        FileDescriptorProto, FileDescriptorSet, MessageOptions, OneofDescriptorProto,
+    };
+
+    use crate::proto_descriptor::{to_proto_files, to_sorted_proto_files};
+
+    fn field(name: &str, number: i32, label: Label, r#type: Type) -> FieldDescriptorProto {
+        FieldDescriptorProto {
+            name: Some(String::from(name)),
+            number: Some(number),
+            label: Some(label as i32),
+            r#type: Some(r#type as i32),
+            ..Default::default()
+        }
+    }
+
+    fn type_field(
+        name: &str,
+        number: i32,
+        label: Label,
+        r#type: Type,
+        type_name: &str,
+    ) -> FieldDescriptorProto {
+        FieldDescriptorProto {
+            type_name: Some(String::from(type_name)),
+            ..field(name, number, label, r#type)
+        }
+    }
+
+    fn get_descriptor_set() -> Vec<u8> {
+        let heartbeat = FileDescriptorProto {
+            name: Some(String::from("heartbeat.proto")),
+            package: Some(String::from("nl.openweb.data")),
+            syntax: Some(String::from("proto3")),
+            message_type: vec![DescriptorProto {
+                name: Some(String::from("Heartbeat")),
+                field: vec![field("beat", 1, Label::Optional, Type::Uint64)],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let result = FileDescriptorProto {
+            name: Some(String::from("result.proto")),
+            package: Some(String::from("nl.openweb.data")),
+            dependency: vec![String::from("heartbeat.proto")],
+            syntax: Some(String::from("proto3")),
+            message_type: vec![DescriptorProto {
+                name: Some(String::from("Result")),
+                field: vec![
+                    type_field(
+                        "beats",
+                        1,
+                        Label::Repeated,
+                        Type::Message,
+                        ".nl.openweb.data.Heartbeat",
+                    ),
+                    type_field(
+                        "counts",
+                        2,
+                        Label::Repeated,
+                        Type::Message,
+                        ".nl.openweb.data.Result.CountsEntry",
+                    ),
+                    type_field(
+                        "state",
+                        3,
+                        Label::Optional,
+                        Type::Enum,
+                        ".nl.openweb.data.Result.State",
+                    ),
+                    FieldDescriptorProto {
+                        oneof_index: Some(0),
+                        ..field("text", 4, Label::Optional, Type::String)
+                    },
+                    FieldDescriptorProto {
+                        oneof_index: Some(0),
+                        ..field("number", 5, Label::Optional, Type::Int32)
+                    },
+                ],
+                nested_type: vec![DescriptorProto {
+                    name: Some(String::from("CountsEntry")),
+                    field: vec![
+                        field("key", 1, Label::Optional, Type::String),
+                        field("value", 2, Label::Optional, Type::Int64),
+                    ],
+                    options: Some(MessageOptions {
+                        map_entry: Some(true),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }],
+                enum_type: vec![EnumDescriptorProto {
+                    name: Some(String::from("State")),
+                    value: vec![
+                        EnumValueDescriptorProto {
+                            name: Some(String::from("UP")),
+                            number: Some(0),
+                            ..Default::default()
+                        },
+                        EnumValueDescriptorProto {
+                            name: Some(String::from("DOWN")),
+                            number: Some(1),
+                            ..Default::default()
+                        },
+                    ],
+                    ..Default::default()
+                }],
+                oneof_decl: vec![OneofDescriptorProto {
+                    name: Some(String::from("extra")),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        FileDescriptorSet {
+            file: vec![heartbeat, result],
+        }
+        .encode_to_vec()
+    }
+
+    #[test]
+    fn descriptor_set_to_proto_files() {
+        let files = to_proto_files(&get_descriptor_set()).unwrap();
+
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].0, "heartbeat.proto");
+        assert_eq!(
+            files[0].1,
+            "syntax = \"proto3\";\npackage nl.openweb.data;\n\nmessage Heartbeat {\n  uint64 beat = 1;\n}\n"
+        );
+        assert_eq!(files[1].0, "result.proto");
+        assert_eq!(
+            files[1].1,
+            r#"syntax = "proto3";
+package nl.openweb.data;
+import "heartbeat.proto";
+
+message Result {
+  repeated nl.openweb.data.Heartbeat beats = 1;
+  map<string, int64> counts = 2;
+  nl.openweb.data.Result.State state = 3;
+  oneof extra {
+    string text = 4;
+    int32 number = 5;
+  }
+  enum State {
+    UP = 0;
+    DOWN = 1;
+  }
+}
+"#
+        );
+    }
+
+    #[test]
+    fn sorted_proto_files() {
+        let set = FileDescriptorSet::decode(&*get_descriptor_set()).unwrap();
+        let reversed = FileDescriptorSet {
+            file: set.file.into_iter().rev().collect(),
+        }
+        .encode_to_vec();
+
+        let files = to_sorted_proto_files(&reversed).unwrap();
+
+        assert_eq!(files[0].name, "heartbeat.proto");
+        assert_eq!(files[0].messages, vec!["nl.openweb.data.Heartbeat"]);
+        assert_eq!(files[1].name, "result.proto");
+        assert_eq!(files[1].dependencies, vec!["heartbeat.proto"]);
+        assert_eq!(files[1].messages, vec!["nl.openweb.data.Result"]);
+    }
+
+    #[test]
+    fn missing_import() {
+        let mut set = FileDescriptorSet::decode(&*get_descriptor_set()).unwrap();
+        set.file.remove(0);
+
+        let error = to_sorted_proto_files(&set.encode_to_vec()).unwrap_err();
+        assert_eq!(
+            error.error,
+            "File result.proto imports heartbeat.proto which is not part of the FileDescriptorSet"
+        )
+    }
+
+    #[test]
+    fn invalid_descriptor_set() {
+        let error = to_proto_files(&[10, 5, 1]).unwrap_err();
+        assert_eq!(error.error, "Could not decode bytes as FileDescriptorSet")
+    }
+}
@@ -0,0 +1,776 @@
+//! A serde Deserializer directly over the values decoded by protofish, using the schema in the
+//! context for the names and defaults of the fields. Fields that are not set are deserialized as
+//! their proto3 default, or as None for an Option. Enums are deserialized from the name of the
+//! value, or from the number when an integer is expected. A oneof is deserialized as an externally
+//! tagged enum when the struct has a field with the name of the oneof, and bytes can be
+//! deserialized as `Vec<u8>`. Well known types, except the wrappers, are deserialized from the
+//! same json as `proto_value_to_json` gives.
+use std::borrow::Cow;
+use std::collections::HashSet;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use protofish::context::{Context, MessageField, MessageInfo, ValueType};
+use protofish::decode::{MessageValue, PackedArray, Value};
+use serde::de::value::{BorrowedStrDeserializer, SeqDeserializer};
+use serde::de::{
+    DeserializeSeed, EnumAccess, Error as _, IntoDeserializer, MapAccess, SeqAccess, VariantAccess,
+    Visitor,
+};
+use serde::{forward_to_deserialize_any, Deserializer};
+
+use crate::proto_decoder_common::{field_value, is_map_entry, is_repeated, well_known_to_json};
+
+type Error = serde_json::Error;
+
+/// A single value of a field, packed values are expanded to one item per value.
+enum Item<'c> {
+    Value(&'c Value),
+    Packed(Packed),
+}
+
+#[derive(Clone, Copy)]
+enum Packed {
+    Double(f64),
+    Float(f32),
+    Int32(i32),
+    Int64(i64),
+    UInt32(u32),
+    UInt64(u64),
+    Bool(bool),
+}
+
+enum Node<'c> {
+    /// A message, when the value is None all the fields have their default.
+    Message(&'c MessageInfo, Option<&'c MessageValue>),
+    /// A value of a type other than message, when the item is None it's the default of the type.
+    Scalar(&'c ValueType, Option<Item<'c>>),
+    Repeated(&'c MessageField, Vec<Item<'c>>),
+    /// The name of the field of the oneof that is set, with its value.
+    Oneof(Option<(&'c str, Box<ProtoDeserializer<'c>>)>),
+    Key(Cow<'c, str>),
+    Json(serde_json::Value),
+}
+
+pub(crate) struct ProtoDeserializer<'c> {
+    node: Node<'c>,
+    /// Whether the value was in the bytes, when it's not, an Option is deserialized as None.
+    present: bool,
+    context: &'c Context,
+}
+
+impl<'c> ProtoDeserializer<'c> {
+    pub(crate) fn message(
+        info: &'c MessageInfo,
+        value: Option<&'c MessageValue>,
+        context: &'c Context,
+    ) -> ProtoDeserializer<'c> {
+        let node = match (is_wrapper(&info.full_name), info.get_field(1), value) {
+            (true, Some(field), _) => Node::Scalar(
+                &field.field_type,
+                value.and_then(|v| field_value(v, 1)).map(Item::Value),
+            ),
+            (false, _, Some(v)) => match well_known_to_json(info, v, context) {
+                Some(json) => Node::Json(json),
+                None => Node::Message(info, value),
+            },
+            _ => Node::Message(info, value),
+        };
+        ProtoDeserializer {
+            node,
+            present: value.is_some(),
+            context,
+        }
+    }
+
+    fn field(
+        field: &'c MessageField,
+        value: Option<&'c MessageValue>,
+        context: &'c Context,
+    ) -> ProtoDeserializer<'c> {
+        let values = value
+            .into_iter()
+            .flat_map(|v| v.fields.iter())
+            .filter(|f| f.number == field.number)
+            .map(|f| &f.value)
+            .filter(|v| !matches!(v, Value::Unknown(_) | Value::Incomplete(_, _)));
+        if is_repeated(field) {
+            let mut items = Vec::new();
+            for v in values {
+                match v {
+                    Value::Packed(p) => items.extend(packed_items(p)),
+                    v => items.push(Item::Value(v)),
+                }
+            }
+            return ProtoDeserializer {
+                present: !items.is_empty(),
+                node: Node::Repeated(field, items),
+                context,
+            };
+        }
+        let last = values.last();
+        match &field.field_type {
+            ValueType::Message(msg_ref) => {
+                let message = match last {
+                    Some(Value::Message(m)) => Some(&**m),
+                    _ => None,
+                };
+                ProtoDeserializer::message(context.resolve_message(*msg_ref), message, context)
+            }
+            field_type => ProtoDeserializer {
+                present: last.is_some(),
+                node: Node::Scalar(field_type, last.map(Item::Value)),
+                context,
+            },
+        }
+    }
+
+    fn item(
+        field_type: &'c ValueType,
+        item: Item<'c>,
+        context: &'c Context,
+    ) -> ProtoDeserializer<'c> {
+        match item {
+            Item::Value(Value::Message(m)) => {
+                ProtoDeserializer::message(context.resolve_message(m.msg_ref), Some(m), context)
+            }
+            item => ProtoDeserializer {
+                node: Node::Scalar(field_type, Some(item)),
+                present: true,
+                context,
+            },
+        }
+    }
+
+    fn key(key: Cow<'c, str>, context: &'c Context) -> ProtoDeserializer<'c> {
+        ProtoDeserializer {
+            node: Node::Key(key),
+            present: true,
+            context,
+        }
+    }
+}
+
+fn is_wrapper(full_name: &str) -> bool {
+    matches!(
+        full_name,
+        "google.protobuf.DoubleValue"
+            | "google.protobuf.FloatValue"
+            | "google.protobuf.Int64Value"
+            | "google.protobuf.UInt64Value"
+            | "google.protobuf.Int32Value"
+            | "google.protobuf.UInt32Value"
+            | "google.protobuf.BoolValue"
+            | "google.protobuf.StringValue"
+            | "google.protobuf.BytesValue"
+    )
+}
+
+fn packed_items<'c>(packed: &PackedArray) -> Vec<Item<'c>> {
+    match packed {
+        PackedArray::Double(v) => v.iter().map(|i| Item::Packed(Packed::Double(*i))).collect(),
+        PackedArray::Float(v) => v.iter().map(|i| Item::Packed(Packed::Float(*i))).collect(),
+        PackedArray::Int32(v) | PackedArray::SInt32(v) | PackedArray::SFixed32(v) => {
+            v.iter().map(|i| Item::Packed(Packed::Int32(*i))).collect()
+        }
+        PackedArray::Int64(v) | PackedArray::SInt64(v) | PackedArray::SFixed64(v) => {
+            v.iter().map(|i| Item::Packed(Packed::Int64(*i))).collect()
+        }
+        PackedArray::UInt32(v) | PackedArray::Fixed32(v) => {
+            v.iter().map(|i| Item::Packed(Packed::UInt32(*i))).collect()
+        }
+        PackedArray::UInt64(v) | PackedArray::Fixed64(v) => {
+            v.iter().map(|i| Item::Packed(Packed::UInt64(*i))).collect()
+        }
+        PackedArray::Bool(v) => v.iter().map(|i| Item::Packed(Packed::Bool(*i))).collect(),
+    }
+}
+
+/// The number and the name of an enum value, None when the type is not an enum. Unset enums have
+/// the value zero, which is the default in proto3.
+fn enum_parts<'c>(
+    field_type: &ValueType,
+    item: Option<&Item<'c>>,
+    context: &'c Context,
+) -> Option<(i64, Option<&'c str>)> {
+    let enum_ref = match field_type {
+        ValueType::Enum(r) => *r,
+        _ => return None,
+    };
+    let number = match item {
+        Some(Item::Value(Value::Enum(e))) => e.value,
+        Some(Item::Value(Value::Int32(v))) | Some(Item::Packed(Packed::Int32(v))) => i64::from(*v),
+        Some(Item::Packed(Packed::Int64(v))) => *v,
+        _ => 0,
+    };
+    let name = context
+        .resolve_enum(enum_ref)
+        .get_field_by_value(number)
+        .map(|f| f.name.as_str());
+    Some((number, name))
+}
+
+fn bytes<'c>(item: &Option<Item<'c>>) -> &'c [u8] {
+    match item {
+        Some(Item::Value(Value::Bytes(b))) => b,
+        _ => &[],
+    }
+}
+
+fn visit_value<'c, V: Visitor<'c>>(
+    value: &'c Value,
+    context: &'c Context,
+    visitor: V,
+) -> Result<V::Value, Error> {
+    match value {
+        Value::Double(v) => visitor.visit_f64(*v),
+        Value::Float(v) => visitor.visit_f32(*v),
+        Value::Int32(v) | Value::SInt32(v) | Value::SFixed32(v) => visitor.visit_i32(*v),
+        Value::Int64(v) | Value::SInt64(v) | Value::SFixed64(v) => visitor.visit_i64(*v),
+        Value::UInt32(v) | Value::Fixed32(v) => visitor.visit_u32(*v),
+        Value::UInt64(v) | Value::Fixed64(v) => visitor.visit_u64(*v),
+        Value::Bool(v) => visitor.visit_bool(*v),
+        Value::String(v) => visitor.visit_borrowed_str(v),
+        Value::Bytes(v) => visitor.visit_borrowed_bytes(v),
+        Value::Enum(v) => match context.resolve_enum(v.enum_ref).get_field_by_value(v.value) {
+            Some(f) => visitor.visit_borrowed_str(&f.name),
+            None => visitor.visit_i64(v.value),
+        },
+        _ => visitor.visit_unit(),
+    }
+}
+
+fn visit_packed<'c, V: Visitor<'c>>(packed: Packed, visitor: V) -> Result<V::Value, Error> {
+    match packed {
+        Packed::Double(v) => visitor.visit_f64(v),
+        Packed::Float(v) => visitor.visit_f32(v),
+        Packed::Int32(v) => visitor.visit_i32(v),
+        Packed::Int64(v) => visitor.visit_i64(v),
+        Packed::UInt32(v) => visitor.visit_u32(v),
+        Packed::UInt64(v) => visitor.visit_u64(v),
+        Packed::Bool(v) => visitor.visit_bool(v),
+    }
+}
+
+fn visit_default<'c, V: Visitor<'c>>(
+    field_type: &'c ValueType,
+    context: &'c Context,
+    visitor: V,
+) -> Result<V::Value, Error> {
+    match field_type {
+        ValueType::Double => visitor.visit_f64(0.0),
+        ValueType::Float => visitor.visit_f32(0.0),
+        ValueType::Int32 | ValueType::SInt32 | ValueType::SFixed32 => visitor.visit_i32(0),
+        ValueType::Int64 | ValueType::SInt64 | ValueType::SFixed64 => visitor.visit_i64(0),
+        ValueType::UInt32 | ValueType::Fixed32 => visitor.visit_u32(0),
+        ValueType::UInt64 | ValueType::Fixed64 => visitor.visit_u64(0),
+        ValueType::Bool => visitor.visit_bool(false),
+        ValueType::String => visitor.visit_borrowed_str(""),
+        ValueType::Bytes => visitor.visit_borrowed_bytes(&[]),
+        ValueType::Message(msg_ref) => {
+            ProtoDeserializer::message(context.resolve_message(*msg_ref), None, context)
+                .deserialize_any(visitor)
+        }
+        _ => visitor.visit_unit(),
+    }
+}
+
+/// The fields of the message as key and value. When the names of the fields of the struct are
+/// known, a field is keyed by its number when the struct only has the number, and the fields of a
+/// oneof are grouped when the struct has the name of the oneof. The fields of a oneof which are not
+/// grouped are left out when not set, like with json.
+fn message_pairs<'c>(
+    info: &'c MessageInfo,
+    value: Option<&'c MessageValue>,
+    names: Option<&[&str]>,
+    context: &'c Context,
+) -> Vec<(ProtoDeserializer<'c>, ProtoDeserializer<'c>)> {
+    let has_name = |name: &str| names.map(|n| n.contains(&name)).unwrap_or(false);
+    let mut pairs = Vec::new();
+    let mut grouped = HashSet::new();
+    for oneof in &info.oneofs {
+        if !has_name(&oneof.name) {
+            continue;
+        }
+        grouped.extend(oneof.fields.iter().copied());
+        let member = value
+            .and_then(|v| {
+                v.fields
+                    .iter()
+                    .rev()
+                    .find(|f| oneof.fields.contains(&f.number))
+            })
+            .and_then(|f| info.get_field(f.number))
+            .map(|field| {
+                (
+                    field.name.as_str(),
+                    Box::new(ProtoDeserializer::field(field, value, context)),
+                )
+            });
+        pairs.push((
+            ProtoDeserializer::key(Cow::Borrowed(&oneof.name), context),
+            ProtoDeserializer {
+                present: member.is_some(),
+                node: Node::Oneof(member),
+                context,
+            },
+        ));
+    }
+    for field in info.iter_fields() {
+        if grouped.contains(&field.number) {
+            continue;
+        }
+        let de = ProtoDeserializer::field(field, value, context);
+        let in_oneof = info.oneofs.iter().any(|o| o.fields.contains(&field.number));
+        if in_oneof && !de.present && !has_name(&field.name) {
+            continue;
+        }
+        let number = field.number.to_string();
+        let key = if !has_name(&field.name) && has_name(&number) {
+            Cow::Owned(number)
+        } else {
+            Cow::Borrowed(field.name.as_str())
+        };
+        pairs.push((ProtoDeserializer::key(key, context), de));
+    }
+    pairs
+}
+
+/// The entries of a map field, which are messages with the key as field 1 and the value as field 2.
+fn entry_pairs<'c>(
+    items: Vec<Item<'c>>,
+    context: &'c Context,
+) -> Vec<(ProtoDeserializer<'c>, ProtoDeserializer<'c>)> {
+    items
+        .into_iter()
+        .filter_map(|item| match item {
+            Item::Value(Value::Message(entry)) => {
+                let info = context.resolve_message(entry.msg_ref);
+                Some((
+                    ProtoDeserializer::field(info.get_field(1)?, Some(entry), context),
+                    ProtoDeserializer::field(info.get_field(2)?, Some(entry), context),
+                ))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+macro_rules! deserialize_number {
+    ($($method:ident)*) => {$(
+        fn $method<V: Visitor<'c>>(self, visitor: V) -> Result<V::Value, Error> {
+            match &self.node {
+                Node::Scalar(field_type, item) => {
+                    match enum_parts(field_type, item.as_ref(), self.context) {
+                        Some((number, _)) => visitor.visit_i64(number),
+                        None => self.deserialize_any(visitor),
+                    }
+                }
+                _ => self.deserialize_any(visitor),
+            }
+        }
+    )*};
+}
+
+impl<'c> Deserializer<'c> for ProtoDeserializer<'c> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'c>>(self, visitor: V) -> Result<V::Value, Error> {
+        let context = self.context;
+        match self.node {
+            Node::Message(info, value) => {
+                visitor.visit_map(Pairs::new(message_pairs(info, value, None, context)))
+            }
+            Node::Scalar(field_type, item) => {
+                match (enum_parts(field_type, item.as_ref(), context), item) {
+                    (Some((_, Some(name))), _) => visitor.visit_borrowed_str(name),
+                    (Some((number, None)), _) => visitor.visit_i64(number),
+                    (None, Some(Item::Value(v))) => visit_value(v, context, visitor),
+                    (None, Some(Item::Packed(p))) => visit_packed(p, visitor),
+                    (None, None) => visit_default(field_type, context, visitor),
+                }
+            }
+            Node::Repeated(field, items) if is_map_entry(field, context) => {
+                visitor.visit_map(Pairs::new(entry_pairs(items, context)))
+            }
+            Node::Repeated(field, items) => visitor.visit_seq(Items {
+                field_type: &field.field_type,
+                items: items.into_iter(),
+                context,
+            }),
+            Node::Oneof(Some((name, de))) => visitor.visit_map(Pairs::new(vec![(
+                ProtoDeserializer::key(Cow::Borrowed(name), context),
+                *de,
+            )])),
+            Node::Oneof(None) => visitor.visit_unit(),
+            Node::Key(Cow::Borrowed(key)) => visitor.visit_borrowed_str(key),
+            Node::Key(Cow::Owned(key)) => visitor.visit_string(key),
+            Node::Json(json) => json.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'c>>(self, visitor: V) -> Result<V::Value, Error> {
+        if self.present {
+            visitor.visit_some(self)
+        } else {
+            visitor.visit_none()
+        }
+    }
+
+    fn deserialize_str<V: Visitor<'c>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.node {
+            Node::Scalar(ValueType::Bytes, item) => {
+                visitor.visit_string(STANDARD.encode(bytes(&item)))
+            }
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_string<V: Visitor<'c>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_seq<V: Visitor<'c>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.node {
+            Node::Scalar(ValueType::Bytes, item) => {
+                let mut seq = SeqDeserializer::<_, Error>::new(bytes(&item).iter().copied());
+                let value = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(value)
+            }
+            Node::Json(json) => json.deserialize_seq(visitor),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'c>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_struct<V: Visitor<'c>>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match self.node {
+            Node::Message(info, value) => visitor.visit_map(Pairs::new(message_pairs(
+                info,
+                value,
+                Some(fields),
+                self.context,
+            ))),
+            Node::Json(json) => json.deserialize_struct(name, fields, visitor),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_enum<V: Visitor<'c>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        let context = self.context;
+        match self.node {
+            Node::Oneof(Some((name, de))) => visitor.visit_enum(Variant { name, de: *de }),
+            Node::Oneof(None) => Err(Error::custom("none of the fields of the oneof is set")),
+            Node::Scalar(field_type, item) => {
+                match enum_parts(field_type, item.as_ref(), context) {
+                    Some((_, Some(name))) => {
+                        visitor.visit_enum(BorrowedStrDeserializer::<Error>::new(name))
+                    }
+                    Some((number, None)) => {
+                        visitor.visit_enum(number.to_string().into_deserializer())
+                    }
+                    None => ProtoDeserializer {
+                        node: Node::Scalar(field_type, item),
+                        present: self.present,
+                        context,
+                    }
+                    .deserialize_any(visitor),
+                }
+            }
+            Node::Json(json) => json.deserialize_enum(name, variants, visitor),
+            node => ProtoDeserializer {
+                node,
+                present: self.present,
+                context,
+            }
+            .deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'c>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    deserialize_number! {
+        deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64
+    }
+
+    forward_to_deserialize_any! {
+        bool i128 u128 f32 f64 char bytes byte_buf unit unit_struct tuple
+        tuple_struct map identifier
+    }
+}
+
+struct Items<'c> {
+    field_type: &'c ValueType,
+    items: std::vec::IntoIter<Item<'c>>,
+    context: &'c Context,
+}
+
+impl<'c> SeqAccess<'c> for Items<'c> {
+    type Error = Error;
+
+    fn next_element_seed<S: DeserializeSeed<'c>>(
+        &mut self,
+        seed: S,
+    ) -> Result<Option<S::Value>, Error> {
+        match self.items.next() {
+            Some(item) => seed
+                .deserialize(ProtoDeserializer::item(self.field_type, item, self.context))
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.items.len())
+    }
+}
+
+struct Pairs<'c> {
+    pairs: std::vec::IntoIter<(ProtoDeserializer<'c>, ProtoDeserializer<'c>)>,
+    value: Option<ProtoDeserializer<'c>>,
+}
+
+impl<'c> Pairs<'c> {
+    fn new(pairs: Vec<(ProtoDeserializer<'c>, ProtoDeserializer<'c>)>) -> Pairs<'c> {
+        Pairs {
+            pairs: pairs.into_iter(),
+            value: None,
+        }
+    }
+}
+
+impl<'c> MapAccess<'c> for Pairs<'c> {
+    type Error = Error;
+
+    fn next_key_seed<S: DeserializeSeed<'c>>(
+        &mut self,
+        seed: S,
+    ) -> Result<Option<S::Value>, Error> {
+        match self.pairs.next() {
+            Some((key, value)) => {
+                self.value = Some(value);
+                seed.deserialize(key).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<S: DeserializeSeed<'c>>(&mut self, seed: S) -> Result<S::Value, Error> {
+        match self.value.take() {
+            Some(value) => seed.deserialize(value),
+            None => Err(Error::custom("value is missing")),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.pairs.len())
+    }
+}
+
+/// The field of a oneof that is set, as variant of an enum.
+struct Variant<'c> {
+    name: &'c str,
+    de: ProtoDeserializer<'c>,
+}
+
+impl<'c> EnumAccess<'c> for Variant<'c> {
+    type Error = Error;
+    type Variant = ProtoDeserializer<'c>;
+
+    fn variant_seed<S: DeserializeSeed<'c>>(
+        self,
+        seed: S,
+    ) -> Result<(S::Value, Self::Variant), Error> {
+        let variant = seed.deserialize(BorrowedStrDeserializer::<Error>::new(self.name))?;
+        Ok((variant, self.de))
+    }
+}
+
+impl<'c> VariantAccess<'c> for ProtoDeserializer<'c> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<S: DeserializeSeed<'c>>(self, seed: S) -> Result<S::Value, Error> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'c>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn struct_variant<V: Visitor<'c>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_struct("", fields, visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use protofish::context::Context;
+    use serde::Deserialize;
+
+    use crate::proto_decoder_common::from_proto_value;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    enum Color {
+        #[serde(rename = "RED")]
+        Red,
+        #[serde(rename = "GREEN")]
+        Green,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    #[serde(rename_all = "snake_case")]
+    enum Contact {
+        Email(String),
+        Phone(i32),
+    }
+
+    #[derive(Debug, Default, Deserialize, PartialEq)]
+    struct Address {
+        city: String,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Person {
+        name: String,
+        numbers: Vec<i32>,
+        color: Color,
+        raw: Vec<u8>,
+        #[serde(rename = "5")]
+        age: i64,
+        address: Option<Address>,
+        scores: HashMap<String, i32>,
+        contact: Option<Contact>,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Defaults {
+        address: Address,
+        color: i32,
+        phone: Option<i32>,
+    }
+
+    fn get_context() -> Context {
+        Context::parse([r#"syntax = "proto3";
+            package nl.openweb.data;
+            message Person {
+              string name = 1;
+              repeated int32 numbers = 2;
+              Color color = 3;
+              bytes raw = 4;
+              int64 age = 5;
+              Address address = 6;
+              repeated ScoresEntry scores = 7;
+              oneof contact {
+                string email = 8;
+                int32 phone = 9;
+              }
+              enum Color { RED = 0; GREEN = 1; }
+              message ScoresEntry { string key = 1; int32 value = 2; }
+            }
+            message Address {
+              string city = 1;
+            }"#])
+        .unwrap()
+    }
+
+    #[test]
+    fn deserialize_all_fields() {
+        let context = get_context();
+        let info = context.get_message("nl.openweb.data.Person").unwrap();
+        let bytes = [
+            10, 3, 66, 111, 98, 18, 2, 1, 2, 24, 1, 34, 2, 1, 2, 58, 5, 10, 1, 97, 16, 3, 72, 7,
+        ];
+        let value = info.decode(&bytes, &context);
+
+        let person: Person = from_proto_value(&value, &context).unwrap();
+
+        assert_eq!(
+            person,
+            Person {
+                name: String::from("Bob"),
+                numbers: vec![1, 2],
+                color: Color::Green,
+                raw: vec![1, 2],
+                age: 0,
+                address: None,
+                scores: HashMap::from([(String::from("a"), 3)]),
+                contact: Some(Contact::Phone(7)),
+            }
+        )
+    }
+
+    #[test]
+    fn deserialize_defaults() {
+        let context = get_context();
+        let info = context.get_message("nl.openweb.data.Person").unwrap();
+        let value = info.decode(&[], &context);
+
+        let person: Person = from_proto_value(&value, &context).unwrap();
+        assert_eq!(
+            person,
+            Person {
+                name: String::new(),
+                numbers: vec![],
+                color: Color::Red,
+                raw: vec![],
+                age: 0,
+                address: None,
+                scores: HashMap::new(),
+                contact: None,
+            }
+        );
+
+        let defaults: Defaults = from_proto_value(&value, &context).unwrap();
+        assert_eq!(
+            defaults,
+            Defaults {
+                address: Address::default(),
+                color: 0,
+                phone: None,
+            }
+        );
+    }
+
+    #[test]
+    fn deserialize_enum_as_number() {
+        let context = get_context();
+        let info = context.get_message("nl.openweb.data.Person").unwrap();
+        let value = info.decode(&[24, 1, 72, 7], &context);
+
+        let defaults: Defaults = from_proto_value(&value, &context).unwrap();
+
+        assert_eq!(defaults.color, 1);
+        assert_eq!(defaults.phone, Some(7));
+    }
+}
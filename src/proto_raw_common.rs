@@ -1,22 +1,46 @@
 use crate::error::SRCError;
-use crate::proto_resolver::{IndexResolver, MessageResolver};
-use crate::schema_registry_common::{get_payload, RegisteredSchema};
-use integer_encoding::VarInt;
+#[cfg(feature = "proto_decoder")]
+use crate::field_encryption::{Direction, FieldEncryption, FieldEncryptor};
+use crate::proto_common_types::is_well_known;
+#[cfg(feature = "proto_decoder")]
+use crate::proto_decoder_common::message_not_found;
+#[cfg(feature = "proto_decoder")]
+use crate::proto_decoder_common::validate_message;
+#[cfg(feature = "proto_descriptor")]
+use crate::proto_descriptor::to_proto_files;
+use crate::proto_resolver::{find_imports, IndexResolver, MessageResolver};
+use crate::schema_registry_common::{
+    IdStrategy, RegisteredSchema, SchemaType, SubjectNameStrategy, SuppliedReference,
+    SuppliedSchema, Version,
+};
+#[cfg(feature = "proto_decoder")]
+use crate::schema_registry_common::{Metadata, RuleSet};
+#[cfg(feature = "proto_decoder")]
+use protofish::context::Context;
+use std::borrow::Cow;
+#[cfg(feature = "proto_decoder")]
+use std::sync::Arc;
 
 pub(crate) fn to_bytes(
     encode_context: &EncodeContext,
     bytes: &[u8],
     full_name: &str,
 ) -> Result<Vec<u8>, SRCError> {
-    let mut index_bytes = match encode_context.resolver.find_index(full_name) {
-        Some(v) if v.len() == 1 && v[0] == 0i32 => vec![0u8],
-        Some(v) => {
-            let mut result = (v.len() as i32).encode_var_vec();
-            for i in &*v {
-                result.append(&mut i.encode_var_vec())
-            }
-            result
-        }
+    let mut buf = Vec::new();
+    write_bytes(encode_context, bytes, full_name, &mut buf)?;
+    Ok(buf)
+}
+
+/// Like `to_bytes`, but writing the header, the cached message index and the bytes to the buffer,
+/// which is cleared first, so the buffer can be reused for multiple messages.
+pub(crate) fn write_bytes(
+    encode_context: &EncodeContext,
+    bytes: &[u8],
+    full_name: &str,
+    buf: &mut Vec<u8>,
+) -> Result<(), SRCError> {
+    let index_bytes = match encode_context.resolver.find_index_bytes(full_name) {
+        Some(v) => v,
         None => {
             return Err(SRCError::non_retryable_without_cause(&format!(
                 "could not find name {} with resolver",
@@ -24,18 +48,30 @@ pub(crate) fn to_bytes(
             )))
         }
     };
-    index_bytes.extend(bytes);
-    Ok(get_payload(encode_context.id, index_bytes))
+    validate(encode_context, bytes, full_name)?;
+    let bytes = encrypt(encode_context, bytes, full_name)?;
+    buf.clear();
+    buf.reserve(9 + index_bytes.len() + bytes.len());
+    encode_context
+        .id_strategy
+        .write_header(encode_context.id, buf);
+    buf.extend_from_slice(index_bytes);
+    buf.extend_from_slice(&bytes);
+    Ok(())
 }
 
 pub(crate) fn to_bytes_single_message(
     encode_context: &EncodeContext,
     bytes: &[u8],
 ) -> Result<Vec<u8>, SRCError> {
-    if encode_context.resolver.is_single_message() {
+    if let Some(full_name) = encode_context.resolver.single_message_name() {
+        validate(encode_context, bytes, &full_name)?;
+        let bytes = encrypt(encode_context, bytes, &full_name)?;
         let mut index_bytes = vec![0u8];
-        index_bytes.extend(bytes);
-        Ok(get_payload(encode_context.id, index_bytes))
+        index_bytes.extend_from_slice(&bytes);
+        Ok(encode_context
+            .id_strategy
+            .get_payload(encode_context.id, index_bytes))
     } else {
         Err(SRCError::new(
             "Schema was no single message schema",
@@ -45,6 +81,75 @@ pub(crate) fn to_bytes_single_message(
     }
 }
 
+/// When the encode context has a proto context, checks the bytes are valid for the message.
+#[cfg(feature = "proto_decoder")]
+fn validate(encode_context: &EncodeContext, bytes: &[u8], full_name: &str) -> Result<(), SRCError> {
+    match &encode_context.context {
+        Some(context) if encode_context.validate => {
+            validate_message(context, encode_context.id, full_name, bytes)
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(not(feature = "proto_decoder"))]
+fn validate(_: &EncodeContext, _: &[u8], _: &str) -> Result<(), SRCError> {
+    Ok(())
+}
+
+/// When the encode context has an encryption, encrypts the fields tagged by an ENCRYPT rule of the
+/// schema.
+#[cfg(feature = "proto_decoder")]
+fn encrypt<'b>(
+    encode_context: &EncodeContext,
+    bytes: &'b [u8],
+    full_name: &str,
+) -> Result<Cow<'b, [u8]>, SRCError> {
+    let (encryption, context) = match (&encode_context.encryption, &encode_context.context) {
+        (Some(encryption), Some(context)) => (encryption, context),
+        _ => return Ok(Cow::Borrowed(bytes)),
+    };
+    let message = match context.get_message(full_name) {
+        Some(message) => message,
+        None => return Err(message_not_found(full_name, encode_context.id)),
+    };
+    match FieldEncryption::new(
+        Some(&*encryption.encryptor),
+        Some(&encryption.rule_set),
+        encryption.metadata.as_ref(),
+        Direction::Write,
+    ) {
+        Some(f) => Ok(Cow::Owned(f.proto_bytes(context, message, bytes)?)),
+        None => Ok(Cow::Borrowed(bytes)),
+    }
+}
+
+#[cfg(not(feature = "proto_decoder"))]
+fn encrypt<'b>(_: &EncodeContext, bytes: &'b [u8], _: &str) -> Result<Cow<'b, [u8]>, SRCError> {
+    Ok(Cow::Borrowed(bytes))
+}
+
+/// Gives the encryption when there is an encryptor, and the schema has an ENCRYPT rule for
+/// writing, so only then the proto context is needed.
+#[cfg(feature = "proto_decoder")]
+pub(crate) fn to_encryption(
+    field_encryptor: Option<Arc<dyn FieldEncryptor>>,
+    registered_schema: &RegisteredSchema,
+) -> Option<Encryption> {
+    let rule_set = registered_schema.rule_set.as_ref()?;
+    FieldEncryption::new(
+        field_encryptor.as_deref(),
+        Some(rule_set),
+        None,
+        Direction::Write,
+    )?;
+    Some(Encryption {
+        encryptor: field_encryptor?,
+        rule_set: rule_set.clone(),
+        metadata: registered_schema.metadata.clone(),
+    })
+}
+
 pub(crate) fn to_decode_context(registered_schema: RegisteredSchema) -> DecodeContext {
     let schema = String::from(&registered_schema.schema);
     DecodeContext {
@@ -53,10 +158,193 @@ pub(crate) fn to_decode_context(registered_schema: RegisteredSchema) -> DecodeCo
     }
 }
 
+/// Schema used to auto register, the proto source of the file to register and the files it
+/// imports. Imports of well known types are not registered as references, the schema registry
+/// already knows them.
+#[derive(Debug, Clone)]
+pub enum ProtoSchema {
+    /// Proto source without imports, other than the well known types.
+    Source(String),
+    /// Name of the main file, and the name and proto source of all the files, including the
+    /// transitive imports.
+    Files(String, Vec<(String, String)>),
+    /// Serialized FileDescriptorSet, which should include the imports. The last file of the set is
+    /// registered, the other files are used as references.
+    #[cfg(feature = "proto_descriptor")]
+    DescriptorSet(Vec<u8>),
+}
+
+/// Combines the strategy with the schema, so the schema will be registered under the subject of
+/// the strategy when it's not already present.
+pub(crate) fn to_auto_register_strategy(
+    subject_name_strategy: &SubjectNameStrategy,
+    schema: &ProtoSchema,
+) -> Result<SubjectNameStrategy, SRCError> {
+    Ok(match subject_name_strategy {
+        SubjectNameStrategy::RecordNameStrategy(r) => {
+            SubjectNameStrategy::RecordNameStrategyWithSchema(to_supplied_schema(
+                schema,
+                Some(r.clone()),
+            )?)
+        }
+        SubjectNameStrategy::TopicNameStrategy(t, is_key) => {
+            SubjectNameStrategy::TopicNameStrategyWithSchema(
+                t.clone(),
+                *is_key,
+                to_supplied_schema(schema, None)?,
+            )
+        }
+        SubjectNameStrategy::TopicRecordNameStrategy(t, r) => {
+            SubjectNameStrategy::TopicRecordNameStrategyWithSchema(
+                t.clone(),
+                to_supplied_schema(schema, Some(r.clone()))?,
+            )
+        }
+        _ => {
+            return Err(SRCError::non_retryable_without_cause(
+                "Auto register can't be used with a strategy that already has a schema",
+            ))
+        }
+    })
+}
+
+fn to_supplied_schema(
+    schema: &ProtoSchema,
+    name: Option<String>,
+) -> Result<SuppliedSchema, SRCError> {
+    let (main, files) = match schema {
+        ProtoSchema::Source(s) => {
+            return Ok(SuppliedSchema {
+                name,
+                schema_type: SchemaType::Protobuf,
+                schema: s.clone(),
+                references: to_references(s, &[])?,
+            })
+        }
+        ProtoSchema::Files(main, files) => (main.clone(), files.clone()),
+        #[cfg(feature = "proto_descriptor")]
+        ProtoSchema::DescriptorSet(bytes) => {
+            let files = to_proto_files(bytes)?;
+            match files.last() {
+                Some((main, _)) => (main.clone(), files),
+                None => {
+                    return Err(SRCError::non_retryable_without_cause(
+                        "FileDescriptorSet doesn't contain any file",
+                    ))
+                }
+            }
+        }
+    };
+    let source = find_source(&main, &files)?;
+    Ok(SuppliedSchema {
+        name,
+        schema_type: SchemaType::Protobuf,
+        schema: source.clone(),
+        references: to_references(source, &files)?,
+    })
+}
+
+fn to_references(
+    source: &str,
+    files: &[(String, String)],
+) -> Result<Vec<SuppliedReference>, SRCError> {
+    find_imports(source)
+        .into_iter()
+        .filter(|import| !is_well_known(import))
+        .map(|import| {
+            let schema = find_source(&import, files)?;
+            Ok(SuppliedReference {
+                name: import.clone(),
+                subject: import,
+                schema: schema.clone(),
+                references: to_references(schema, files)?,
+            })
+        })
+        .collect()
+}
+
+fn find_source<'a>(name: &str, files: &'a [(String, String)]) -> Result<&'a String, SRCError> {
+    match files.iter().find(|(n, _)| n == name) {
+        Some((_, source)) => Ok(source),
+        None => Err(SRCError::non_retryable_without_cause(&format!(
+            "Missing source for import {}",
+            name
+        ))),
+    }
+}
+
+/// The ways the schema used for encoding can be selected, either the latest or supplied schema from
+/// the strategy, a specific version of the subject, a specific id, or the schema to auto register
+/// under the subject of the strategy.
+#[derive(Debug, Clone)]
+pub(crate) enum SchemaSelector {
+    Strategy(SubjectNameStrategy),
+    AutoRegister(SubjectNameStrategy, ProtoSchema),
+    Version(SubjectNameStrategy, Version),
+    Id(u32),
+}
+
+impl SchemaSelector {
+    /// Key used to cache the encode context.
+    pub(crate) fn key(&self) -> Result<String, SRCError> {
+        match self {
+            SchemaSelector::Strategy(s) => s.get_subject(),
+            SchemaSelector::AutoRegister(s, _) => Ok(format!("{}/auto", s.get_subject()?)),
+            SchemaSelector::Version(s, Version::Latest) => {
+                Ok(format!("{}/versions/latest", s.get_subject()?))
+            }
+            SchemaSelector::Version(s, Version::Number(v)) => {
+                Ok(format!("{}/versions/{}", s.get_subject()?, v))
+            }
+            SchemaSelector::Version(s, Version::LatestWithMetadata(key, value)) => {
+                Ok(format!("{}/metadata/{}={}", s.get_subject()?, key, value))
+            }
+            SchemaSelector::Id(id) => Ok(format!("/ids/{}", id)),
+        }
+    }
+
+    /// Subject of the schema, when it's selected by subject.
+    pub(crate) fn subject(&self) -> Option<String> {
+        match self {
+            SchemaSelector::Strategy(s)
+            | SchemaSelector::AutoRegister(s, _)
+            | SchemaSelector::Version(s, _) => s.get_subject().ok(),
+            SchemaSelector::Id(_) => None,
+        }
+    }
+}
+
+/// The settings of the encoder used to create the encode context of each schema.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct EncodeOptions {
+    pub(crate) validate: bool,
+    pub(crate) id_strategy: IdStrategy,
+    #[cfg(feature = "proto_decoder")]
+    pub(crate) field_encryptor: Option<Arc<dyn FieldEncryptor>>,
+}
+
 #[derive(Debug, Clone)]
 pub(crate) struct EncodeContext {
     pub(crate) id: u32,
+    pub(crate) subject: Option<String>,
+    pub(crate) id_strategy: IdStrategy,
     pub(crate) resolver: IndexResolver,
+    /// Only set when the encoder validates the bytes against the schema, or encrypts fields.
+    #[cfg(feature = "proto_decoder")]
+    pub(crate) context: Option<Arc<Context>>,
+    #[cfg(feature = "proto_decoder")]
+    pub(crate) validate: bool,
+    #[cfg(feature = "proto_decoder")]
+    pub(crate) encryption: Option<Encryption>,
+}
+
+/// The encryptor, with the rules and metadata of the schema, to encrypt the tagged fields.
+#[cfg(feature = "proto_decoder")]
+#[derive(Debug, Clone)]
+pub(crate) struct Encryption {
+    encryptor: Arc<dyn FieldEncryptor>,
+    rule_set: RuleSet,
+    metadata: Option<Metadata>,
 }
 
 #[derive(Debug, Clone)]
@@ -1,11 +1,29 @@
-use std::io::BufReader;
+//! Resolves the messages of a protobuf schema to the message index, which is put in front of the
+//! proto bytes to tell which message of the schema was used. The `MessageResolver` can also be used
+//! to inspect a registered schema, for example to build a routing table by message.
+//! ```
+//! use schema_registry_converter::proto_resolver::MessageResolver;
+//!
+//! let resolver = MessageResolver::new(
+//!     r#"syntax = "proto3"; package a; import "b.proto"; message A {message B {}} message C {}"#,
+//! );
+//! let names: Vec<String> = resolver.full_names().iter().map(|n| n.to_string()).collect();
+//! assert_eq!(names, vec!["a.A", "a.A.B", "a.C"]);
+//! assert_eq!(resolver.find_index("a.A.B"), Some(vec![0, 0]));
+//! assert_eq!(*resolver.find_name(&[1]).unwrap(), "a.C");
+//! assert_eq!(resolver.imports(), &vec![String::from("b.proto")]);
+//! ```
 use std::sync::Arc;
 
 use crate::error::SRCError;
 use dashmap::DashMap;
-use integer_encoding::VarIntReader;
+use integer_encoding::{VarInt, VarIntReader};
 use logos::Logos;
 
+/// Gets the full names of the messages in a protobuf schema by their message index, which is the
+/// position of the message in the schema, followed by the position within the parent message for
+/// nested messages. The schema is only scanned for the package, imports and messages, so it doesn't
+/// need to be valid, and the imports are not resolved.
 #[derive(Debug, Clone)]
 pub struct MessageResolver {
     pub map: DashMap<Vec<i32>, Arc<String>>,
@@ -15,6 +33,7 @@ pub struct MessageResolver {
 #[derive(Debug, Clone)]
 pub struct IndexResolver {
     map: DashMap<String, Arc<Vec<i32>>>,
+    bytes: DashMap<String, Arc<Vec<u8>>>,
 }
 
 impl MessageResolver {
@@ -30,9 +49,37 @@ impl MessageResolver {
         }
     }
 
+    /// Gives the full name of the message with the index, the full name is the optional package
+    /// followed by the names of the parent messages and the name of the message.
     pub fn find_name(&self, index: &[i32]) -> Option<Arc<String>> {
         self.map.get(index).map(|e| e.value().clone())
     }
+
+    /// Gives the index of the message with the full name.
+    pub fn find_index(&self, full_name: &str) -> Option<Vec<i32>> {
+        self.map
+            .iter()
+            .find(|e| e.value().as_str() == full_name)
+            .map(|e| e.key().clone())
+    }
+
+    /// Gives the indexes and full names of all the messages, in the order they are in the schema.
+    pub fn messages(&self) -> Vec<(Vec<i32>, Arc<String>)> {
+        let mut messages: Vec<(Vec<i32>, Arc<String>)> = self
+            .map
+            .iter()
+            .map(|e| (e.key().clone(), e.value().clone()))
+            .collect();
+        messages.sort();
+        messages
+    }
+
+    /// Gives the full names of all the messages, in the order they are in the schema.
+    pub fn full_names(&self) -> Vec<Arc<String>> {
+        self.messages().into_iter().map(|(_, name)| name).collect()
+    }
+
+    /// Gives the imports of the schema, as they are in the schema.
     pub fn imports(&self) -> &Vec<String> {
         &self.imports
     }
@@ -42,19 +89,37 @@ impl IndexResolver {
     pub fn new(s: &str) -> IndexResolver {
         let helper = ResolverHelper::new(s);
         let map = DashMap::new();
+        let bytes = DashMap::new();
         for i in &helper.indexes {
-            map.insert(find_name(i, &helper), Arc::new(i.clone()));
+            let name = find_name(i, &helper);
+            bytes.insert(name.clone(), Arc::new(to_index_bytes(i)));
+            map.insert(name, Arc::new(i.clone()));
         }
-        IndexResolver { map }
+        IndexResolver { map, bytes }
     }
 
     pub fn find_index(&self, name: &str) -> Option<Arc<Vec<i32>>> {
         self.map.get(name).map(|e| e.value().clone())
     }
 
+    /// Gives the message index of the message with the full name as it's put in front of the proto
+    /// bytes, computed once when the resolver is created, so it's not encoded again for each message.
+    pub fn find_index_bytes(&self, name: &str) -> Option<Arc<Vec<u8>>> {
+        self.bytes.get(name).map(|e| e.value().clone())
+    }
+
     pub fn is_single_message(&self) -> bool {
         self.map.len() == 1
     }
+
+    /// Gives the full name of the message when the schema only has one message.
+    pub fn single_message_name(&self) -> Option<String> {
+        if self.is_single_message() {
+            self.map.iter().next().map(|e| e.key().clone())
+        } else {
+            None
+        }
+    }
 }
 
 pub struct ResolverHelper {
@@ -81,6 +146,10 @@ enum Token {
     #[token("}", priority = 10)]
     Close,
 
+    #[regex(r"//[^\n]*", logos::skip, priority = 11)]
+    #[regex(r"/\*([^*]|\*+[^*/])*\*+/", logos::skip, priority = 11)]
+    Comment,
+
     #[regex(r"\S", logos::skip, priority = 1)]
     #[regex(r"[\s]+", logos::skip, priority = 1)]
     Ignorable,
@@ -108,7 +177,9 @@ impl ResolverHelper {
                     let message = String::from(slice[8..slice.len()].trim());
                     for i in &indexes {
                         if same_vec(i, &index) {
-                            *index.last_mut().unwrap() += 1;
+                            if let Some(last) = index.last_mut() {
+                                *last += 1;
+                            }
                         }
                     }
                     indexes.push(index.clone());
@@ -123,9 +194,11 @@ impl ResolverHelper {
                     index.push(0);
                 }
                 Ok(Token::Close) => {
-                    index.pop();
+                    if index.len() > 1 {
+                        index.pop();
+                    }
                 }
-                Err(_) | Ok(Token::Ignorable) => (),
+                Err(_) | Ok(Token::Comment) | Ok(Token::Ignorable) => (),
             };
             next = lex.next()
         }
@@ -139,6 +212,12 @@ impl ResolverHelper {
     }
 }
 
+/// Gets the imports of a proto schema, used to add the well known types when they are imported by
+/// one of the referenced schemas, and to find the references of a supplied schema.
+pub(crate) fn find_imports(s: &str) -> Vec<String> {
+    ResolverHelper::new(s).imports
+}
+
 fn find_part<'a>(index: &'a [i32], helper: &'a ResolverHelper) -> &'a str {
     for i in 0..helper.indexes.len() {
         if same_vec(index, &helper.indexes[i]) {
@@ -175,17 +254,61 @@ fn same_vec(first: &[i32], second: &[i32]) -> bool {
     true
 }
 
-pub fn to_index_and_data(bytes: &[u8]) -> (Vec<i32>, Vec<u8>) {
-    if bytes[0] == 0 {
-        (vec![0], bytes[1..].to_vec())
-    } else {
-        let mut reader = BufReader::new(bytes);
-        let count: i32 = reader.read_varint().unwrap();
-        let mut index = Vec::new();
-        for _ in 0..count {
-            index.push(reader.read_varint().unwrap())
+/// Encodes the message index the way it's put in front of the proto bytes, the number of indexes
+/// followed by the indexes, all as zigzag varints. A single 0 is used as optimization when the index
+/// is the first message in the schema.
+pub fn to_index_bytes(index: &[i32]) -> Vec<u8> {
+    if index == [0] {
+        return vec![0];
+    }
+    let mut bytes = (index.len() as i32).encode_var_vec();
+    for i in index {
+        bytes.append(&mut i.encode_var_vec())
+    }
+    bytes
+}
+
+/// Splits the bytes, without the magic byte and schema id, into the message index and the actual
+/// proto bytes. The index can have any depth, a single 0 is used as optimization when the first
+/// message in the schema is used.
+pub fn to_index_and_data(bytes: &[u8]) -> Result<(Vec<i32>, Vec<u8>), SRCError> {
+    let (index, offset) = to_index_and_offset(bytes)?;
+    Ok((index, bytes[offset..].to_vec()))
+}
+
+/// Same as to_index_and_data, but gives the position where the proto bytes start, so they can be
+/// used without copying.
+pub fn to_index_and_offset(bytes: &[u8]) -> Result<(Vec<i32>, usize), SRCError> {
+    match bytes.first() {
+        None => Err(SRCError::non_retryable_without_cause(
+            "Could not get message index from empty bytes",
+        )),
+        Some(0) => Ok((vec![0], 1)),
+        Some(_) => {
+            let mut reader = bytes;
+            let count = read_index_varint(&mut reader)?;
+            if count < 0 || count as usize > reader.len() {
+                return Err(SRCError::non_retryable_without_cause(&format!(
+                    "Invalid message index length {}",
+                    count
+                )));
+            }
+            let mut index = Vec::with_capacity(count as usize);
+            for _ in 0..count {
+                index.push(read_index_varint(&mut reader)?)
+            }
+            Ok((index, bytes.len() - reader.len()))
         }
-        (index, reader.buffer().to_vec())
+    }
+}
+
+fn read_index_varint(reader: &mut &[u8]) -> Result<i32, SRCError> {
+    match reader.read_varint::<i32>() {
+        Ok(v) => Ok(v),
+        Err(e) => Err(SRCError::non_retryable_with_cause(
+            e,
+            "Could not read message index from bytes",
+        )),
     }
 }
 
@@ -199,9 +322,38 @@ pub fn resolve_name(resolver: &MessageResolver, index: &[i32]) -> Result<Arc<Str
     }
 }
 
+/// Decodes the data bytes into a struct generated by prost, after checking the message referenced in
+/// the bytes is the same message as the one the struct was generated from.
+#[cfg(feature = "prost_decoder")]
+pub(crate) fn to_prost<T: prost::Message + prost::Name + Default>(
+    resolver: &MessageResolver,
+    bytes: &[u8],
+) -> Result<T, SRCError> {
+    let (index, data) = to_index_and_data(bytes)?;
+    let full_name = resolve_name(resolver, &index)?;
+    if *full_name != T::full_name() {
+        return Err(SRCError::non_retryable_without_cause(&format!(
+            "Message {} in the bytes doesn't match expected message {}",
+            full_name,
+            T::full_name()
+        )));
+    }
+    match T::decode(data.as_slice()) {
+        Ok(v) => Ok(v),
+        Err(e) => Err(SRCError::non_retryable_with_cause(
+            e,
+            "Could not decode bytes with prost",
+        )),
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::proto_resolver::{IndexResolver, MessageResolver};
+    use crate::proto_resolver::{
+        to_index_and_data, to_index_and_offset, to_index_bytes, IndexResolver, MessageResolver,
+    };
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
     use std::sync::Arc;
 
     fn get_proto_simple() -> &'static str {
@@ -250,6 +402,20 @@ message GoogleTest {
         assert_eq!(resolver.imports.len(), 0)
     }
 
+    #[test]
+    fn test_unbalanced_braces_message_resolver() {
+        let resolver = MessageResolver::new(r#"package a; } message A{} } message B{}"#);
+
+        assert_eq!(
+            resolver.find_name(&[0]),
+            Some(Arc::new(String::from("a.A")))
+        );
+        assert_eq!(
+            resolver.find_name(&[1]),
+            Some(Arc::new(String::from("a.B")))
+        );
+    }
+
     #[test]
     fn test_simple_schema_index_resolver() {
         let resolver = IndexResolver::new(get_proto_simple());
@@ -287,6 +453,32 @@ message GoogleTest {
         assert_eq!(resolver.imports[0], String::from("result.proto"))
     }
 
+    #[test]
+    fn test_complex_schema_messages() {
+        let resolver = MessageResolver::new(get_proto_complex());
+
+        assert_eq!(
+            resolver.full_names(),
+            vec![
+                Arc::new(String::from("org.schema_registry_test_app.proto.A")),
+                Arc::new(String::from("org.schema_registry_test_app.proto.B")),
+                Arc::new(String::from("org.schema_registry_test_app.proto.C")),
+                Arc::new(String::from("org.schema_registry_test_app.proto.C.D")),
+                Arc::new(String::from("org.schema_registry_test_app.proto.ProtoTest")),
+            ]
+        );
+        assert_eq!(resolver.messages()[3].0, vec![2, 0]);
+        assert_eq!(
+            resolver.find_index("org.schema_registry_test_app.proto.C.D"),
+            Some(vec![2, 0])
+        );
+        assert_eq!(
+            resolver.find_index("org.schema_registry_test_app.proto.D"),
+            None
+        );
+        assert_eq!(resolver.imports(), &vec![String::from("result.proto")]);
+    }
+
     #[test]
     fn test_schema_with_three_imports() {
         let resolver = MessageResolver::new(get_three_complex());
@@ -315,5 +507,100 @@ message GoogleTest {
             resolver.find_index("org.schema_registry_test_app.proto.ProtoTest"),
             Some(Arc::new(vec![3]))
         );
+        assert_eq!(
+            resolver.find_index_bytes("org.schema_registry_test_app.proto.A"),
+            Some(Arc::new(vec![0]))
+        );
+        assert_eq!(
+            resolver.find_index_bytes("org.schema_registry_test_app.proto.C.D"),
+            Some(Arc::new(vec![4, 4, 0]))
+        );
+        assert_eq!(
+            resolver.find_index_bytes("org.schema_registry_test_app.proto.D"),
+            None
+        );
+    }
+
+    fn add_nested_messages(
+        rng: &mut StdRng,
+        parent_index: &[i32],
+        parent_name: &str,
+        schema: &mut String,
+        expected: &mut Vec<(Vec<i32>, String)>,
+    ) {
+        let count = if parent_index.is_empty() {
+            rng.gen_range(1..4)
+        } else {
+            rng.gen_range(0..3)
+        };
+        for i in 0..count {
+            let mut index = parent_index.to_vec();
+            index.push(i);
+            let name = format!("M{}", i);
+            let full_name = format!("{}.{}", parent_name, name);
+            schema.push_str(&format!("message {} {{ int32 f = 1; ", name));
+            if rng.gen_bool(0.5) {
+                schema.push_str("enum E { A = 0; } // message NotAMessage {\n");
+            }
+            expected.push((index.clone(), full_name.clone()));
+            if index.len() < 5 {
+                add_nested_messages(rng, &index, &full_name, schema, expected);
+            }
+            schema.push_str("} ");
+        }
+    }
+
+    #[test]
+    fn test_random_deeply_nested_schemas() {
+        let mut rng = StdRng::seed_from_u64(286);
+        for _ in 0..50 {
+            let mut schema = String::from(r#"syntax = "proto3"; package deep.nesting; "#);
+            let mut expected = Vec::new();
+            add_nested_messages(&mut rng, &[], "deep.nesting", &mut schema, &mut expected);
+
+            let message_resolver = MessageResolver::new(&schema);
+            let index_resolver = IndexResolver::new(&schema);
+            for (index, name) in expected {
+                assert_eq!(
+                    message_resolver.find_name(&index),
+                    Some(Arc::new(name.clone()))
+                );
+                assert_eq!(
+                    index_resolver.find_index(&name),
+                    Some(Arc::new(index.clone()))
+                );
+                assert_eq!(
+                    index_resolver.find_index_bytes(&name),
+                    Some(Arc::new(to_index_bytes(&index)))
+                );
+
+                let mut bytes = to_index_bytes(&index);
+                bytes.extend_from_slice(&[8, 1]);
+                assert_eq!(to_index_and_data(&bytes).unwrap(), (index, vec![8, 1]));
+            }
+        }
+    }
+
+    #[test]
+    fn test_index_and_data_large_payload() {
+        let mut bytes = to_index_bytes(&[1, 0, 2]);
+        let data = vec![7u8; 10_000];
+        bytes.extend_from_slice(&data);
+        assert_eq!(to_index_and_data(&bytes).unwrap(), (vec![1, 0, 2], data));
+    }
+
+    #[test]
+    fn test_index_and_data_invalid() {
+        assert!(to_index_and_data(&[]).is_err());
+        assert!(to_index_and_data(&[20, 2]).is_err());
+    }
+
+    #[test]
+    fn test_index_and_offset() {
+        assert_eq!(to_index_and_offset(&[0, 8, 101]).unwrap(), (vec![0], 1));
+        assert_eq!(
+            to_index_and_offset(&[4, 2, 6, 8, 101]).unwrap(),
+            (vec![1, 3], 3)
+        );
     }
 }
@@ -0,0 +1,459 @@
+//! A parser for the parts of proto files the compatibility checks and the linter need, without
+//! resolving the imports, so single schema's from the schema registry can be checked.
+use crate::error::SRCError;
+
+/// The parts of a proto file that matter for compatibility and linting, with the nested messages
+/// and enums flattened. The names of nested messages and enums include the names of the messages
+/// they're nested in, but not the package.
+pub(crate) struct ProtoFile {
+    pub(crate) package: String,
+    pub(crate) messages: Vec<ProtoMessage>,
+    pub(crate) enums: Vec<ProtoEnum>,
+}
+
+pub(crate) struct ProtoMessage {
+    pub(crate) name: String,
+    /// Whether there is a comment before the message.
+    pub(crate) documented: bool,
+    pub(crate) fields: Vec<ProtoField>,
+    pub(crate) reserved_numbers: Vec<(u32, u32)>,
+    pub(crate) reserved_names: Vec<String>,
+}
+
+pub(crate) struct ProtoField {
+    pub(crate) name: String,
+    pub(crate) number: u32,
+    pub(crate) label: Option<String>,
+    pub(crate) type_name: String,
+    pub(crate) oneof: Option<String>,
+}
+
+pub(crate) struct ProtoEnum {
+    pub(crate) name: String,
+    /// Whether there is a comment before the enum.
+    pub(crate) documented: bool,
+    pub(crate) values: Vec<(String, i32)>,
+}
+
+impl ProtoMessage {
+    pub(crate) fn field(&self, number: u32) -> Option<&ProtoField> {
+        self.fields.iter().find(|f| f.number == number)
+    }
+
+    pub(crate) fn reserves_number(&self, number: u32) -> bool {
+        self.reserved_numbers
+            .iter()
+            .any(|(from, to)| (*from..=*to).contains(&number))
+    }
+
+    pub(crate) fn is_reserved(&self, field: &ProtoField) -> bool {
+        self.reserved_names.contains(&field.name) || self.reserves_number(field.number)
+    }
+}
+
+impl ProtoFile {
+    pub(crate) fn parse(schema: &str) -> Result<ProtoFile, SRCError> {
+        let mut parser = ProtoParser {
+            tokens: proto_tokens(schema),
+            position: 0,
+            documented: false,
+        };
+        let mut file = ProtoFile {
+            package: String::new(),
+            messages: Vec::new(),
+            enums: Vec::new(),
+        };
+        while let Some(token) = parser.next() {
+            match token.as_str() {
+                ";" => (),
+                "syntax" | "edition" | "import" | "option" => parser.skip_statement(),
+                "package" => {
+                    file.package = parser.expect_name()?;
+                    parser.expect(";")?;
+                }
+                "message" => {
+                    let documented = parser.documented;
+                    let name = parser.expect_name()?;
+                    parser.message(name, documented, &mut file)?;
+                }
+                "enum" => {
+                    let documented = parser.documented;
+                    let name = parser.expect_name()?;
+                    parser.enumeration(name, documented, &mut file)?;
+                }
+                "service" | "extend" => parser.skip_block()?,
+                _ => return Err(parser.error(&token)),
+            }
+        }
+        Ok(file)
+    }
+
+    pub(crate) fn message(&self, name: &str) -> Option<&ProtoMessage> {
+        self.messages.iter().find(|m| m.name == name)
+    }
+}
+
+/// Parses the tokens, keeping whether there was a comment before the last token.
+struct ProtoParser {
+    tokens: Vec<(String, bool)>,
+    position: usize,
+    documented: bool,
+}
+
+impl ProtoParser {
+    fn next(&mut self) -> Option<String> {
+        let (token, documented) = self.tokens.get(self.position).cloned()?;
+        self.position += 1;
+        self.documented = documented;
+        Some(token)
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.position).map(|(t, _)| t.as_str())
+    }
+
+    fn error(&self, token: &str) -> SRCError {
+        SRCError::non_retryable_without_cause(&format!(
+            "Could not parse the protobuf schema, unexpected {}",
+            token
+        ))
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), SRCError> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            Some(token) => Err(self.error(&token)),
+            None => Err(self.error("end of schema")),
+        }
+    }
+
+    fn expect_name(&mut self) -> Result<String, SRCError> {
+        match self.next() {
+            Some(token)
+                if token.starts_with(|c: char| c.is_alphanumeric() || c == '_' || c == '.') =>
+            {
+                Ok(token)
+            }
+            Some(token) => Err(self.error(&token)),
+            None => Err(self.error("end of schema")),
+        }
+    }
+
+    fn expect_number(&mut self) -> Result<u32, SRCError> {
+        let token = self.expect_name()?;
+        token.parse().map_err(|_| self.error(&token))
+    }
+
+    /// Skips to the end of the statement, including option values between braces.
+    fn skip_statement(&mut self) {
+        let mut depth = 0;
+        while let Some(token) = self.next() {
+            match token.as_str() {
+                "{" => depth += 1,
+                "}" => depth -= 1,
+                ";" if depth == 0 => return,
+                _ => (),
+            }
+        }
+    }
+
+    /// Skips the name and the body of a definition.
+    fn skip_block(&mut self) -> Result<(), SRCError> {
+        while self.peek().is_some_and(|t| t != "{") {
+            self.next();
+        }
+        self.expect("{")?;
+        let mut depth = 1;
+        while depth > 0 {
+            match self.next().as_deref() {
+                Some("{") => depth += 1,
+                Some("}") => depth -= 1,
+                Some(_) => (),
+                None => return Err(self.error("end of schema")),
+            }
+        }
+        Ok(())
+    }
+
+    fn message(
+        &mut self,
+        name: String,
+        documented: bool,
+        file: &mut ProtoFile,
+    ) -> Result<(), SRCError> {
+        self.expect("{")?;
+        let index = file.messages.len();
+        let mut message = ProtoMessage {
+            name,
+            documented,
+            fields: Vec::new(),
+            reserved_numbers: Vec::new(),
+            reserved_names: Vec::new(),
+        };
+        loop {
+            let token = match self.next() {
+                Some(t) => t,
+                None => return Err(self.error("end of schema")),
+            };
+            match token.as_str() {
+                "}" => break,
+                ";" => (),
+                "option" | "extensions" => self.skip_statement(),
+                "extend" => self.skip_block()?,
+                "message" => {
+                    let documented = self.documented;
+                    let nested = format!("{}.{}", message.name, self.expect_name()?);
+                    self.message(nested, documented, file)?;
+                }
+                "enum" => {
+                    let documented = self.documented;
+                    let nested = format!("{}.{}", message.name, self.expect_name()?);
+                    self.enumeration(nested, documented, file)?;
+                }
+                "reserved" => self.reserved(&mut message)?,
+                "oneof" => {
+                    let oneof = self.expect_name()?;
+                    self.expect("{")?;
+                    loop {
+                        match self.next() {
+                            Some(t) if t == "}" => break,
+                            Some(t) if t == "option" => self.skip_statement(),
+                            Some(t) if t == ";" => (),
+                            Some(t) => {
+                                let field = self.field(t, Some(oneof.clone()))?;
+                                message.fields.push(field);
+                            }
+                            None => return Err(self.error("end of schema")),
+                        }
+                    }
+                }
+                _ => {
+                    let field = self.field(token, None)?;
+                    message.fields.push(field);
+                }
+            }
+        }
+        file.messages.insert(index, message);
+        Ok(())
+    }
+
+    fn enumeration(
+        &mut self,
+        name: String,
+        documented: bool,
+        file: &mut ProtoFile,
+    ) -> Result<(), SRCError> {
+        self.expect("{")?;
+        let mut values = Vec::new();
+        loop {
+            let token = match self.next() {
+                Some(t) => t,
+                None => return Err(self.error("end of schema")),
+            };
+            match token.as_str() {
+                "}" => break,
+                ";" => (),
+                "option" | "reserved" => self.skip_statement(),
+                _ => {
+                    self.expect("=")?;
+                    let number = self.expect_name()?;
+                    let number = number.parse().map_err(|_| self.error(&number))?;
+                    values.push((token, number));
+                    self.skip_statement();
+                }
+            }
+        }
+        file.enums.push(ProtoEnum {
+            name,
+            documented,
+            values,
+        });
+        Ok(())
+    }
+
+    fn reserved(&mut self, message: &mut ProtoMessage) -> Result<(), SRCError> {
+        loop {
+            let token = match self.next() {
+                Some(t) => t,
+                None => return Err(self.error("end of schema")),
+            };
+            if token == ";" {
+                return Ok(());
+            }
+            if token == "," {
+                continue;
+            }
+            if token.starts_with('"') || token.starts_with('\'') {
+                message
+                    .reserved_names
+                    .push(token.trim_matches(|c| c == '"' || c == '\'').to_string());
+                continue;
+            }
+            let from: u32 = token.parse().map_err(|_| self.error(&token))?;
+            let to = if self.peek() == Some("to") {
+                self.next();
+                match self.expect_name()?.as_str() {
+                    "max" => u32::MAX,
+                    to => to.parse().map_err(|_| self.error(to))?,
+                }
+            } else {
+                from
+            };
+            message.reserved_numbers.push((from, to));
+        }
+    }
+
+    fn field(&mut self, first: String, oneof: Option<String>) -> Result<ProtoField, SRCError> {
+        let (label, type_name) = match first.as_str() {
+            "optional" | "required" | "repeated" => (Some(first), self.expect_name()?),
+            _ => (None, first),
+        };
+        let type_name = if type_name == "map" {
+            self.expect("<")?;
+            let key = self.expect_name()?;
+            self.expect(",")?;
+            let value = self.expect_name()?;
+            self.expect(">")?;
+            format!("map<{},{}>", key, short_name(&value))
+        } else {
+            type_name
+        };
+        let name = self.expect_name()?;
+        self.expect("=")?;
+        let number = self.expect_number()?;
+        if self.peek() == Some("[") {
+            while self.next().is_some_and(|t| t != "]") {}
+        }
+        self.expect(";")?;
+        Ok(ProtoField {
+            name,
+            number,
+            label,
+            type_name,
+            oneof,
+        })
+    }
+}
+
+/// Splits the schema in names, numbers, strings and single characters, leaving out the comments,
+/// with whether there was a comment on its own line before the token.
+fn proto_tokens(schema: &str) -> Vec<(String, bool)> {
+    let chars: Vec<char> = schema.chars().collect();
+    token_spans(&chars)
+        .into_iter()
+        .map(|(start, end, comment)| (chars[start..end].iter().collect(), comment))
+        .collect()
+}
+
+/// Gives the start and end of each token in the chars, with whether there was a comment on its own
+/// line before the token.
+fn token_spans(chars: &[char]) -> Vec<(usize, usize, bool)> {
+    let mut spans = Vec::new();
+    let mut i = 0;
+    let mut line_start = true;
+    let mut comment = false;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        i += 1;
+        if c.is_whitespace() {
+            line_start |= c == '\n';
+            continue;
+        }
+        if c == '/' && chars.get(i) == Some(&'/') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+            comment |= line_start;
+            continue;
+        }
+        if c == '/' && chars.get(i) == Some(&'*') {
+            i += 2;
+            while i < chars.len() && !(chars[i - 1] == '*' && chars[i] == '/') {
+                i += 1;
+            }
+            i += 1;
+            comment |= line_start;
+            continue;
+        }
+        if c == '"' || c == '\'' {
+            while i < chars.len() && chars[i] != c {
+                i += if chars[i] == '\\' { 2 } else { 1 };
+            }
+            i += 1;
+        } else if c.is_alphanumeric() || c == '_' || c == '.' || c == '-' {
+            while i < chars.len()
+                && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '.')
+            {
+                i += 1;
+            }
+        }
+        spans.push((start, i.min(chars.len()), comment));
+        line_start = false;
+        comment = false;
+    }
+    spans
+}
+
+/// Rewrites the editions syntax to proto3, and removes the optional labels of proto3 fields, which
+/// protofish can't parse. Both keep the wire format the same, only the presence of fields differs,
+/// which doesn't matter for decoding. Other schema's are returned as they are.
+#[cfg(feature = "proto_decoder")]
+pub(crate) fn to_proto3(schema: &str) -> String {
+    let chars: Vec<char> = schema.chars().collect();
+    let spans = token_spans(&chars);
+    let tokens: Vec<String> = spans
+        .iter()
+        .map(|(start, end, _)| chars[*start..*end].iter().collect())
+        .collect();
+    let token = |i: usize| tokens.get(i).map(String::as_str).unwrap_or("");
+    let mut replacements: Vec<(usize, usize, &str)> = Vec::new();
+    match (token(0), token(2)) {
+        ("edition", _) => match tokens.iter().position(|t| t == ";") {
+            Some(end) => replacements.push((spans[0].0, spans[end].1, r#"syntax = "proto3";"#)),
+            None => return String::from(schema),
+        },
+        ("syntax", "\"proto3\"" | "'proto3'") => (),
+        _ => return String::from(schema),
+    }
+    for (i, t) in tokens.iter().enumerate() {
+        let statement_start = i == 0 || matches!(token(i - 1), "{" | "}" | ";");
+        if t == "optional" && statement_start && token(i + 1) != "=" {
+            replacements.push((spans[i].0, spans[i].1, ""));
+        }
+    }
+    let mut result = String::with_capacity(schema.len());
+    let mut position = 0;
+    for (start, end, replacement) in replacements {
+        result.extend(&chars[position..start]);
+        result.push_str(replacement);
+        position = end;
+    }
+    result.extend(&chars[position..]);
+    result
+}
+
+/// Finds the proto2 groups, extensions and extends in the schema, which protofish might not
+/// support, so they can be named when the schema can't be parsed.
+#[cfg(feature = "proto_decoder")]
+pub(crate) fn proto2_constructs(schema: &str) -> Vec<String> {
+    let tokens: Vec<String> = proto_tokens(schema).into_iter().map(|(t, _)| t).collect();
+    let mut constructs = Vec::new();
+    for (i, token) in tokens.iter().enumerate() {
+        let next = tokens.get(i + 1).map(String::as_str).unwrap_or("");
+        let is_construct = match token.as_str() {
+            "group" => tokens.get(i + 2).map(String::as_str) == Some("="),
+            "extend" | "extensions" => next != "=",
+            _ => false,
+        };
+        if is_construct {
+            constructs.push(format!("{} {}", token, next));
+        }
+    }
+    constructs
+}
+
+/// The name without the package, so references with and without the package compare equal.
+pub(crate) fn short_name(type_name: &str) -> &str {
+    type_name.rsplit('.').next().unwrap_or(type_name)
+}
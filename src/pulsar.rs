@@ -0,0 +1,169 @@
+//! Interop with the schema info of Apache Pulsar, so producers for both Kafka and Pulsar can share
+//! the schema's from the schema registry. Pulsar keeps the version of the schema in the metadata
+//! of a message, so the bytes are encoded and decoded without the Confluent framing.
+//!
+//! Pulsar describes json and protobuf values with an avro definition, so only avro schema's can
+//! be converted.
+use std::collections::HashMap;
+use std::io::Cursor;
+
+use apache_avro::types::Value;
+use apache_avro::{from_avro_datum, to_avro_datum, Schema};
+use serde::{Deserialize, Serialize};
+
+use crate::avro_common::get_supplied_schema;
+use crate::error::SRCError;
+use crate::schema_registry_common::{RegisteredSchema, SchemaType, SuppliedSchema};
+
+/// The schema info as used by Pulsar, which can be posted to, and is returned from, the schema
+/// endpoints of the Pulsar admin API.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct SchemaInfo {
+    #[serde(default)]
+    pub name: String,
+    #[serde(rename = "type")]
+    pub schema_type: String,
+    pub schema: String,
+    #[serde(default)]
+    pub properties: HashMap<String, String>,
+}
+
+impl SchemaInfo {
+    /// Creates the schema info for the registered schema, with the id, subject and version from the
+    /// schema registry as properties.
+    pub fn from_registered_schema(
+        name: &str,
+        registered_schema: &RegisteredSchema,
+    ) -> Result<SchemaInfo, SRCError> {
+        if registered_schema.schema_type != SchemaType::Avro {
+            return Err(SRCError::non_retryable_without_cause(&format!(
+                "schema type {:?} can't be used with pulsar, only avro is supported",
+                registered_schema.schema_type
+            )));
+        }
+        let mut properties = HashMap::new();
+        properties.insert(
+            String::from("schema_registry.id"),
+            registered_schema.id.to_string(),
+        );
+        if let Some(subject) = &registered_schema.subject {
+            properties.insert(String::from("schema_registry.subject"), subject.clone());
+        }
+        if let Some(version) = registered_schema.version {
+            properties.insert(String::from("schema_registry.version"), version.to_string());
+        }
+        Ok(SchemaInfo {
+            name: String::from(name),
+            schema_type: String::from("AVRO"),
+            schema: registered_schema.schema.clone(),
+            properties,
+        })
+    }
+
+    /// The id of the schema in the schema registry, when the schema info was created from a
+    /// registered schema.
+    pub fn schema_registry_id(&self) -> Option<u32> {
+        self.properties.get("schema_registry.id")?.parse().ok()
+    }
+
+    /// Gets the schema, to register it with the schema registry.
+    pub fn to_supplied_schema(&self) -> Result<SuppliedSchema, SRCError> {
+        let parsed = self.parse()?;
+        Ok(SuppliedSchema {
+            schema: self.schema.clone(),
+            ..get_supplied_schema(&parsed)
+        })
+    }
+
+    fn parse(&self) -> Result<Schema, SRCError> {
+        if self.schema_type != "AVRO" {
+            return Err(SRCError::non_retryable_without_cause(&format!(
+                "schema type {} can't be used with the schema registry, only AVRO is supported",
+                self.schema_type
+            )));
+        }
+        Schema::parse_str(&self.schema)
+            .map_err(|e| SRCError::non_retryable_with_cause(e, "could not parse the avro schema"))
+    }
+}
+
+/// Encodes and decodes avro values with the schema of a schema info, without any framing.
+#[derive(Clone, Debug)]
+pub struct PulsarAvroCodec {
+    schema: Schema,
+}
+
+impl PulsarAvroCodec {
+    pub fn new(schema_info: &SchemaInfo) -> Result<PulsarAvroCodec, SRCError> {
+        Ok(PulsarAvroCodec {
+            schema: schema_info.parse()?,
+        })
+    }
+
+    /// Encodes the value, which is resolved to the schema first.
+    pub fn encode(&self, value: Value) -> Result<Vec<u8>, SRCError> {
+        let value = value
+            .resolve(&self.schema)
+            .map_err(|e| SRCError::non_retryable_with_cause(e, "Failed to resolve"))?;
+        to_avro_datum(&self.schema, value)
+            .map_err(|e| SRCError::non_retryable_with_cause(e, "Could not get Avro bytes"))
+    }
+
+    pub fn decode(&self, bytes: &[u8]) -> Result<Value, SRCError> {
+        let mut reader = Cursor::new(bytes);
+        from_avro_datum(&self.schema, &mut reader, None).map_err(|e| {
+            SRCError::non_retryable_with_cause(e, "Could not transform bytes using schema")
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use apache_avro::types::Value;
+
+    use crate::pulsar::{PulsarAvroCodec, SchemaInfo};
+    use crate::schema_registry_common::{RegisteredSchema, SchemaType};
+
+    const HEARTBEAT: &str = r#"{"type":"record","name":"Heartbeat","namespace":"nl.openweb.data","fields":[{"name":"beat","type":"long"}]}"#;
+
+    fn registered(schema_type: SchemaType) -> RegisteredSchema {
+        RegisteredSchema {
+            id: 3,
+            schema_type,
+            schema: String::from(HEARTBEAT),
+            references: vec![],
+            subject: Some(String::from("heartbeat-value")),
+            version: Some(2),
+            metadata: None,
+            rule_set: None,
+            guid: None,
+            deleted: false,
+        }
+    }
+
+    #[test]
+    fn schema_info_round_trip() {
+        let info =
+            SchemaInfo::from_registered_schema("heartbeat", &registered(SchemaType::Avro)).unwrap();
+        assert_eq!(info.schema_type, "AVRO");
+        assert_eq!(info.schema_registry_id(), Some(3));
+        let supplied = info.to_supplied_schema().unwrap();
+        assert_eq!(
+            supplied.name,
+            Some(String::from("nl.openweb.data.Heartbeat"))
+        );
+        assert_eq!(supplied.schema, HEARTBEAT);
+
+        let codec = PulsarAvroCodec::new(&info).unwrap();
+        let value = Value::Record(vec![(String::from("beat"), Value::Long(3))]);
+        let bytes = codec.encode(value.clone()).unwrap();
+        assert_eq!(bytes, vec![6]);
+        assert_eq!(codec.decode(&bytes).unwrap(), value);
+    }
+
+    #[test]
+    fn only_avro() {
+        let result = SchemaInfo::from_registered_schema("heartbeat", &registered(SchemaType::Json));
+        assert!(result.is_err());
+    }
+}
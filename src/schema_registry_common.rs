@@ -1,11 +1,15 @@
 //! Contains structs, enums' and functions common to async and blocking implementation of schema
 //! registry. So stuff dealing with the responses from schema registry, determining the subject, etc.
 use core::fmt;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use byteorder::{BigEndian, ByteOrder, ReadBytesExt};
+use log::warn;
 use serde::{Deserialize, Serialize};
 
-use crate::error::SRCError;
+use crate::error::{PolicyViolation, SRCError, WireFormatError};
 
 #[derive(Clone)]
 pub(crate) enum SrAuthorization {
@@ -60,6 +64,138 @@ pub struct RegisteredReference {
     pub version: u32,
 }
 
+/// Result of checking a schema for compatibility with a version of a subject. When it's not
+/// compatible, the messages explain why.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct CompatibilityResult {
+    pub is_compatible: bool,
+    #[serde(default)]
+    pub messages: Vec<String>,
+}
+
+/// The decoded key and value of a record. The key and value can have different types when they're
+/// decoded with a `MixedDecoder`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DecodedRecord<K, V = K> {
+    pub key: K,
+    pub value: V,
+}
+
+/// The compatibility level used by the schema registry when registering a new version for a
+/// subject. The transitive variants check against all the versions instead of only the latest.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum CompatibilityLevel {
+    Backward,
+    BackwardTransitive,
+    Forward,
+    ForwardTransitive,
+    Full,
+    FullTransitive,
+    None,
+}
+
+/// The config as returned by the schema registry, which uses a different name for the level when
+/// it's set.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct RawConfig {
+    #[serde(rename = "compatibilityLevel", alias = "compatibility")]
+    pub(crate) compatibility_level: CompatibilityLevel,
+}
+
+/// The mode of the schema registry, or of a subject. Schema's can only be registered with a given
+/// id and version in import mode.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Mode {
+    ReadWrite,
+    ReadOnly,
+    #[serde(rename = "READONLY_OVERRIDE")]
+    ReadOnlyOverride,
+    Import,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct RawMode {
+    pub(crate) mode: Mode,
+}
+
+/// A version of a subject, as used to tell where a schema is used.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct SubjectVersion {
+    pub subject: String,
+    pub version: u32,
+}
+
+/// The version of a subject to get, either the latest, a specific version, or the latest version
+/// having the metadata key with the value, as used by data contracts.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Version {
+    Latest,
+    Number(u32),
+    LatestWithMetadata(String, String),
+}
+
+impl From<u32> for Version {
+    fn from(version: u32) -> Self {
+        Version::Number(version)
+    }
+}
+
+/// Metadata of a schema, as used by data contracts.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct Metadata {
+    /// The tags, by the path of the field in the schema.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub tags: HashMap<String, Vec<String>>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub properties: HashMap<String, String>,
+    /// The names of the properties containing sensitive information.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sensitive: Vec<String>,
+}
+
+/// The rules of a schema, as used by data contracts. Migration rules apply when reading or writing
+/// with another version of the schema, domain rules apply to each value.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RuleSet {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub migration_rules: Vec<Rule>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub domain_rules: Vec<Rule>,
+}
+
+/// A single rule of a data contract. The values are kept as strings, so rules with a kind, mode or
+/// type unknown to this crate are kept too.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Rule {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub doc: Option<String>,
+    /// Either TRANSFORM or CONDITION.
+    pub kind: String,
+    /// When the rule is applied, like WRITE, READ, WRITEREAD or UPGRADE.
+    pub mode: String,
+    /// The type of the rule, like ENCRYPT or CEL.
+    #[serde(rename = "type")]
+    pub rule_type: String,
+    /// The tags of the fields the rule applies to.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub params: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expr: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_success: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_failure: Option<String>,
+    #[serde(default)]
+    pub disabled: bool,
+}
+
 /// Schema as retrieved from the schema registry. It's close to the json received and doesn't do
 /// type specific transformations.
 #[derive(Clone, Debug)]
@@ -68,6 +204,17 @@ pub struct RegisteredSchema {
     pub schema_type: SchemaType,
     pub schema: String,
     pub references: Vec<RegisteredReference>,
+    /// Only known when retrieved by subject, or when posted, since the schema registry doesn't
+    /// return it when getting a schema by id.
+    pub subject: Option<String>,
+    /// Only known when retrieved by subject, or as part of all the schema's.
+    pub version: Option<u32>,
+    pub metadata: Option<Metadata>,
+    pub rule_set: Option<RuleSet>,
+    /// The globally unique id, only set by newer versions of the schema registry.
+    pub guid: Option<String>,
+    /// Whether the version was soft deleted, only set when soft deleted versions were requested.
+    pub deleted: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -79,6 +226,10 @@ pub struct RawRegisteredSchema {
     pub schema_type: Option<String>,
     pub references: Option<Vec<RegisteredReference>>,
     pub schema: Option<String>,
+    pub metadata: Option<Metadata>,
+    pub rule_set: Option<RuleSet>,
+    pub guid: Option<String>,
+    pub deleted: Option<bool>,
 }
 
 /// Intermediate result to just handle the byte transformation. When used in a decoder just the
@@ -90,6 +241,23 @@ pub enum BytesResult {
     Valid(u32, Vec<u8>),
 }
 
+/// Information about a decoded payload, which makes it possible to route messages without looking
+/// at the value. The payload only contains the schema id, so the subject and version are not known.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DecodeMetadata {
+    pub schema_id: u32,
+    /// For avro the full name of the record, for protobuf the full name of the message, and not
+    /// set for json.
+    pub full_name: Option<String>,
+}
+
+/// The decoded value together with the metadata, returned by the decode_with_metadata functions.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DecodeResultWithMetadata<T> {
+    pub metadata: DecodeMetadata,
+    pub value: T,
+}
+
 /// Strategy similar to the one in the Java client. By default schema's needs to be backwards
 /// compatible. Historically the only available strategy was the TopicNameStrategy. This meant in
 /// practice that a topic could only have one type, or the restriction on backwards compatibility
@@ -98,6 +266,15 @@ pub enum BytesResult {
 /// compatible.
 /// Depending on the strategy, either the topic, whether the value is used as key, the fully
 /// qualified name (only for RecordNameStrategy), or the schema needs to be provided.
+/// With the ContextStrategy the subject of the wrapped strategy is qualified with a schema context,
+/// like `:.mycontext:orders-value`. Since the ids are unique per context, decoders should use an
+/// url including the context, like `http://localhost:8081/contexts/.mycontext`.
+/// For other naming conventions the CustomStrategy uses a SubjectNameResolver, which can also be a
+/// closure, to get the subject from the topic, whether the value is used as key, and the name of
+/// the supplied schema, if any.
+/// The async encoders accept anything that converts into a strategy, like a reference to a stored
+/// strategy, a tuple of the topic and whether it's the key, or a tuple of the topic and the record
+/// name.
 #[derive(Clone, Debug)]
 pub enum SubjectNameStrategy {
     RecordNameStrategy(String),
@@ -106,9 +283,38 @@ pub enum SubjectNameStrategy {
     RecordNameStrategyWithSchema(SuppliedSchema),
     TopicNameStrategyWithSchema(String, bool, SuppliedSchema),
     TopicRecordNameStrategyWithSchema(String, SuppliedSchema),
+    ContextStrategy(String, Box<SubjectNameStrategy>),
+    CustomStrategy(String, bool, Arc<dyn SubjectNameResolver>),
+    CustomStrategyWithSchema(String, bool, SuppliedSchema, Arc<dyn SubjectNameResolver>),
+}
+
+/// Gets the subject from the topic, whether the value is used as key, and the name of the schema
+/// when supplied. Implemented for closures with the same arguments.
+pub trait SubjectNameResolver: Send + Sync {
+    fn resolve(&self, topic: &str, is_key: bool, record_name: Option<&str>) -> String;
+}
+
+impl<F> SubjectNameResolver for F
+where
+    F: Fn(&str, bool, Option<&str>) -> String + Send + Sync,
+{
+    fn resolve(&self, topic: &str, is_key: bool, record_name: Option<&str>) -> String {
+        self(topic, is_key, record_name)
+    }
+}
+
+impl fmt::Debug for dyn SubjectNameResolver {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SubjectNameResolver")
+    }
 }
 
 impl SubjectNameStrategy {
+    /// Wraps the strategy so the subject is qualified with the context.
+    pub fn in_context(self, context: &str) -> SubjectNameStrategy {
+        SubjectNameStrategy::ContextStrategy(String::from(context), Box::new(self))
+    }
+
     /// Helper function to get the schema from the strategy.
     pub(crate) fn get_schema(&self) -> Option<&SuppliedSchema> {
         match self {
@@ -118,6 +324,21 @@ impl SubjectNameStrategy {
             SubjectNameStrategy::RecordNameStrategyWithSchema(s) => Some(s),
             SubjectNameStrategy::TopicNameStrategyWithSchema(_, _, s) => Some(s),
             SubjectNameStrategy::TopicRecordNameStrategyWithSchema(_, s) => Some(s),
+            SubjectNameStrategy::ContextStrategy(_, s) => s.get_schema(),
+            SubjectNameStrategy::CustomStrategy(_, _, _) => None,
+            SubjectNameStrategy::CustomStrategyWithSchema(_, _, s, _) => Some(s),
+        }
+    }
+
+    /// The record name which is part of the subject, for the record name strategies.
+    pub(crate) fn get_record_name(&self) -> Option<&str> {
+        match self {
+            SubjectNameStrategy::RecordNameStrategy(rn) => Some(rn),
+            SubjectNameStrategy::TopicRecordNameStrategy(_, rn) => Some(rn),
+            SubjectNameStrategy::RecordNameStrategyWithSchema(s) => s.name.as_deref(),
+            SubjectNameStrategy::TopicRecordNameStrategyWithSchema(_, s) => s.name.as_deref(),
+            SubjectNameStrategy::ContextStrategy(_, s) => s.get_record_name(),
+            _ => None,
         }
     }
 
@@ -153,10 +374,116 @@ impl SubjectNameStrategy {
             )),
             Some(n) => Ok(format!("{}-{}", t, n)),
         },
+        SubjectNameStrategy::ContextStrategy(c, s) => Ok(qualified_subject(c, &s.get_subject()?)),
+        SubjectNameStrategy::CustomStrategy(t, is_key, r) => Ok(r.resolve(t, *is_key, None)),
+        SubjectNameStrategy::CustomStrategyWithSchema(t, is_key, s, r) => {
+            Ok(r.resolve(t, *is_key, s.name.as_deref()))
+        }
     }
     }
 }
 
+/// Displays the subject, or the reason there is no subject, like a supplied schema without a name.
+impl fmt::Display for SubjectNameStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.get_subject() {
+            Ok(subject) => write!(f, "{}", subject),
+            Err(e) => write!(f, "{}", e.error),
+        }
+    }
+}
+
+impl From<&SubjectNameStrategy> for SubjectNameStrategy {
+    fn from(strategy: &SubjectNameStrategy) -> Self {
+        strategy.clone()
+    }
+}
+
+/// The topic, and whether it's used as key, for the TopicNameStrategy.
+impl From<(&str, bool)> for SubjectNameStrategy {
+    fn from((topic, is_key): (&str, bool)) -> Self {
+        SubjectNameStrategy::TopicNameStrategy(String::from(topic), is_key)
+    }
+}
+
+impl From<(String, bool)> for SubjectNameStrategy {
+    fn from((topic, is_key): (String, bool)) -> Self {
+        SubjectNameStrategy::TopicNameStrategy(topic, is_key)
+    }
+}
+
+/// The topic and the fully qualified record name, for the TopicRecordNameStrategy.
+impl From<(&str, &str)> for SubjectNameStrategy {
+    fn from((topic, record_name): (&str, &str)) -> Self {
+        SubjectNameStrategy::TopicRecordNameStrategy(String::from(topic), String::from(record_name))
+    }
+}
+
+impl From<(String, String)> for SubjectNameStrategy {
+    fn from((topic, record_name): (String, String)) -> Self {
+        SubjectNameStrategy::TopicRecordNameStrategy(topic, record_name)
+    }
+}
+
+/// Qualifies the subject with the context, like `:.mycontext:orders-value`. The leading dot of the
+/// context is optional. Subjects which are already qualified, or the default context, are left as
+/// is.
+pub fn qualified_subject(context: &str, subject: &str) -> String {
+    let context = context.trim_start_matches('.');
+    if context.is_empty() || subject.starts_with(":.") {
+        String::from(subject)
+    } else {
+        format!(":.{}:{}", context, subject)
+    }
+}
+
+/// Percent encodes the value to be used as part of the path or query of a call to schema registry.
+/// Only the unreserved characters are kept as is, so slashes, spaces, the colons of qualified
+/// subjects and unicode characters are all escaped.
+pub(crate) fn percent_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for b in value.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(b as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+/// The implementation of the schema registry. Karapace and Redpanda implement the same API as the
+/// Confluent schema registry, with small differences. For these the deleted parameter is not
+/// added, and any error code for something not being found is treated as not found.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SrDialect {
+    #[default]
+    Confluent,
+    Karapace,
+    Redpanda,
+}
+
+impl SrDialect {
+    /// Adds the deleted parameter to the path, when supported by the schema registry.
+    pub(crate) fn with_deleted(&self, path: String, deleted: bool) -> String {
+        match self {
+            SrDialect::Confluent if path.contains('?') => format!("{}&deleted={}", path, deleted),
+            SrDialect::Confluent => format!("{}?deleted={}", path, deleted),
+            SrDialect::Karapace | SrDialect::Redpanda => path,
+        }
+    }
+
+    /// Whether the error is the response for a subject, version or schema that doesn't exist.
+    pub(crate) fn is_not_found(&self, error: &SRCError) -> bool {
+        match (self, error.error_code()) {
+            (SrDialect::Confluent, Some(code)) => code == 40401 || code == 40403,
+            (_, Some(code)) => (40400..40500).contains(&code),
+            (_, None) => false,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum SrCall<'a> {
     GetById(u32),
@@ -166,28 +493,136 @@ pub enum SrCall<'a> {
     PostForVersion(&'a str, &'a str),
 }
 
-pub(crate) fn url_for_call(call: &SrCall, base_url: &str) -> String {
+impl SrCall<'_> {
+    /// Whether the call only reads from the schema registry, so it can be done with the fallback.
+    /// Looking up the version of a schema is a post, but doesn't register anything.
+    pub(crate) fn is_read(&self) -> bool {
+        !matches!(self, SrCall::PostNew(_, _))
+    }
+
+    /// The id of the schema, when the call is for an id, used as attribute of the span of the call.
+    #[cfg(feature = "tracing")]
+    pub(crate) fn schema_id(&self) -> Option<u32> {
+        match self {
+            SrCall::GetById(id) => Some(*id),
+            _ => None,
+        }
+    }
+
+    /// The subject, when the call is for a subject, used as attribute of the span of the call.
+    #[cfg(feature = "tracing")]
+    pub(crate) fn subject(&self) -> Option<&str> {
+        match self {
+            SrCall::GetById(_) => None,
+            SrCall::GetLatest(s)
+            | SrCall::GetBySubjectAndVersion(s, _)
+            | SrCall::PostNew(s, _)
+            | SrCall::PostForVersion(s, _) => Some(s),
+        }
+    }
+}
+
+/// The url for the call. Schema's by id are fetched including deleted schema's when
+/// `fetch_deleted` is set, so payloads encoded with a schema which was deleted later can still be
+/// decoded. Schema's are posted with the normalize parameter when `normalize` is set.
+pub(crate) fn url_for_call(
+    call: &SrCall,
+    base_url: &str,
+    dialect: SrDialect,
+    fetch_deleted: bool,
+    normalize: bool,
+) -> String {
     match call {
-        SrCall::GetById(id) => format!("{}/schemas/ids/{}?deleted=true", base_url, id),
+        SrCall::GetById(id) if fetch_deleted => {
+            dialect.with_deleted(format!("{}/schemas/ids/{}", base_url, id), true)
+        }
+        SrCall::GetById(id) => format!("{}/schemas/ids/{}", base_url, id),
         SrCall::GetLatest(subject) => {
-            // Use escape sequences instead of slashes in the subject
-            format!("{}/subjects/{}/versions/latest", base_url, subject.replace("/", "%2F"))
+            format!(
+                "{}/subjects/{}/versions/latest",
+                base_url,
+                percent_encode(subject)
+            )
         }
         SrCall::GetBySubjectAndVersion(subject, version) => {
-            // Use escape sequences instead of slashes in the subject
-            format!("{}/subjects/{}/versions/{}", base_url, subject.replace("/", "%2F"), version)
+            format!(
+                "{}/subjects/{}/versions/{}",
+                base_url,
+                percent_encode(subject),
+                version
+            )
         }
         SrCall::PostNew(subject, _) => {
-            // Use escape sequences instead of slashes in the subject
-            format!("{}/subjects/{}/versions", base_url, subject.replace("/", "%2F"))
+            let url = format!("{}/subjects/{}/versions", base_url, percent_encode(subject));
+            with_normalize(url, normalize)
         }
         SrCall::PostForVersion(subject, _) => {
-            // Use escape sequences instead of slashes in the subject
-            format!("{}/subjects/{}?deleted=false", base_url, subject.replace("/", "%2F"))
+            let url = format!("{}/subjects/{}", base_url, percent_encode(subject));
+            with_normalize(dialect.with_deleted(url, false), normalize)
         }
     }
 }
 
+/// Adds the normalize parameter to the path when set, so the schema registry normalizes the schema
+/// before looking it up or registering it.
+pub(crate) fn with_normalize(path: String, normalize: bool) -> String {
+    match normalize {
+        false => path,
+        true if path.contains('?') => format!("{}&normalize=true", path),
+        true => format!("{}?normalize=true", path),
+    }
+}
+
+/// Path for the subject, or for the subject and version when the version is set.
+pub(crate) fn subject_path(subject: &str, version: Option<u32>) -> String {
+    let subject = percent_encode(subject);
+    match version {
+        Some(v) => format!("/subjects/{}/versions/{}", subject, v),
+        None => format!("/subjects/{}", subject),
+    }
+}
+
+/// Path for the latest version of the subject having all the metadata.
+pub(crate) fn metadata_path(subject: &str, metadata: &HashMap<String, String>) -> String {
+    let mut pairs: Vec<(&String, &String)> = metadata.iter().collect();
+    pairs.sort();
+    let query: Vec<String> = pairs
+        .into_iter()
+        .map(|(k, v)| format!("key={}&value={}", percent_encode(k), percent_encode(v)))
+        .collect();
+    let subject = subject_path(subject, None);
+    format!("{}/metadata?{}", subject, query.join("&"))
+}
+
+/// Path for the config of the subject, or the global config when no subject is given.
+pub(crate) fn config_path(subject: Option<&str>) -> String {
+    match subject {
+        Some(s) => format!("/config/{}", percent_encode(s)),
+        None => String::from("/config"),
+    }
+}
+
+/// Path for the mode of the subject, or the global mode when no subject is given.
+pub(crate) fn mode_path(subject: Option<&str>) -> String {
+    match subject {
+        Some(s) => format!("/mode/{}", percent_encode(s)),
+        None => String::from("/mode"),
+    }
+}
+
+/// Path for checking the compatibility against the version, or the latest version when not set.
+pub(crate) fn compatibility_path(subject: &str, version: Option<u32>) -> String {
+    let version = match version {
+        Some(v) => v.to_string(),
+        None => String::from("latest"),
+    };
+    format!(
+        "/compatibility/subjects/{}/versions/{}?verbose=true",
+        percent_encode(subject),
+        version
+    )
+}
+
 /// Creates payload that can be included as a key or value on a kafka record
 pub fn get_payload(id: u32, encoded_bytes: Vec<u8>) -> Vec<u8> {
     let mut payload = vec![0u8];
@@ -214,12 +649,915 @@ pub fn get_bytes_result(bytes: Option<&[u8]>) -> BytesResult {
     }
 }
 
+/// Gets the schema id from the header of the bytes, without copying the rest, for example to route
+/// records by schema id without decoding them. Gives `None` when the bytes don't start with the
+/// magic byte and an id.
+pub fn extract_id(bytes: &[u8]) -> Option<u32> {
+    match bytes {
+        [0, id @ ..] if id.len() >= 4 => Some(BigEndian::read_u32(&id[..4])),
+        _ => None,
+    }
+}
+
+/// Gets the encoded message, without the magic byte and the id. Gives `None` when the bytes don't
+/// start with the magic byte and an id.
+pub fn strip_header(bytes: &[u8]) -> Option<&[u8]> {
+    extract_id(bytes).map(|_| &bytes[5..])
+}
+
+/// Puts the magic byte and the id in front of the encoded message, like [get_payload] but
+/// borrowing the message, for example to frame a message with another id.
+pub fn frame(id: u32, encoded_bytes: &[u8]) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(encoded_bytes.len() + 5);
+    payload.push(0u8);
+    payload.extend_from_slice(&id.to_be_bytes());
+    payload.extend_from_slice(encoded_bytes);
+    payload
+}
+
+/// Gets the schema id from the headers of a kafka record, for when the id is not part of the
+/// payload. Implemented for closures with the same argument.
+pub trait HeaderIdExtractor: Send + Sync {
+    fn get_id(&self, headers: &[(&str, &[u8])]) -> Option<u32>;
+}
+
+impl<F> HeaderIdExtractor for F
+where
+    F: Fn(&[(&str, &[u8])]) -> Option<u32> + Send + Sync,
+{
+    fn get_id(&self, headers: &[(&str, &[u8])]) -> Option<u32> {
+        self(headers)
+    }
+}
+
+impl fmt::Debug for dyn HeaderIdExtractor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "HeaderIdExtractor")
+    }
+}
+
+/// How the id of the schema is stored with the encoded bytes. By default the Confluent wire format
+/// is used. Some serializers, like the ones from Apicurio, use 8 bytes for the id, or put the id in
+/// a header of the kafka record. With a header the payload only contains the encoded bytes, and
+/// the producer needs to set the header itself.
+#[derive(Clone, Debug, Default)]
+pub enum IdStrategy {
+    /// The magic byte followed by the id as 4 bytes.
+    #[default]
+    Confluent,
+    /// The magic byte followed by the id as 8 bytes.
+    Long,
+    /// The id is in a header, found by the extractor.
+    Header(Arc<dyn HeaderIdExtractor>),
+}
+
+impl IdStrategy {
+    /// Creates the payload, including the id when it's part of the payload.
+    pub fn get_payload(&self, id: u32, encoded_bytes: Vec<u8>) -> Vec<u8> {
+        match self {
+            IdStrategy::Header(_) => encoded_bytes,
+            _ => {
+                let mut payload = Vec::with_capacity(encoded_bytes.len() + 9);
+                self.write_header(id, &mut payload);
+                payload.extend_from_slice(encoded_bytes.as_slice());
+                payload
+            }
+        }
+    }
+
+    /// Writes the magic byte and the id to the buffer, when the id is part of the payload, so the
+    /// encoded bytes can be written to the same buffer after it.
+    pub fn write_header(&self, id: u32, buf: &mut Vec<u8>) {
+        match self {
+            IdStrategy::Confluent => {
+                buf.push(0u8);
+                buf.extend_from_slice(&id.to_be_bytes());
+            }
+            IdStrategy::Long => {
+                buf.push(0u8);
+                buf.extend_from_slice(&u64::from(id).to_be_bytes());
+            }
+            IdStrategy::Header(_) => (),
+        }
+    }
+
+    /// Like [get_bytes_result], but getting the id the way of the strategy, which might be from
+    /// the headers.
+    pub fn get_bytes_result(&self, bytes: Option<&[u8]>, headers: &[(&str, &[u8])]) -> BytesResult {
+        match (self, bytes) {
+            (_, None) => BytesResult::Null,
+            (IdStrategy::Confluent, _) => get_bytes_result(bytes),
+            (IdStrategy::Long, Some(p)) if p.len() > 8 && p[0] == 0 => {
+                match u32::try_from(BigEndian::read_u64(&p[1..9])) {
+                    Ok(id) => BytesResult::Valid(id, p[9..].to_owned()),
+                    Err(_) => BytesResult::Invalid(p[..].to_owned()),
+                }
+            }
+            (IdStrategy::Header(extractor), Some(p)) => match extractor.get_id(headers) {
+                Some(id) => BytesResult::Valid(id, p[..].to_owned()),
+                None => BytesResult::Invalid(p[..].to_owned()),
+            },
+            (_, Some(p)) => BytesResult::Invalid(p[..].to_owned()),
+        }
+    }
+
+    /// Gets the id and the offset of the encoded bytes in the payload, so the encoded bytes can be
+    /// used without copying them.
+    pub(crate) fn get_id_and_offset(
+        &self,
+        payload: &[u8],
+        headers: &[(&str, &[u8])],
+    ) -> Option<(u32, usize)> {
+        match self {
+            IdStrategy::Confluent if payload.len() > 4 && payload[0] == 0 => {
+                Some((BigEndian::read_u32(&payload[1..5]), 5))
+            }
+            IdStrategy::Long if payload.len() > 8 && payload[0] == 0 => {
+                u32::try_from(BigEndian::read_u64(&payload[1..9]))
+                    .ok()
+                    .map(|id| (id, 9))
+            }
+            IdStrategy::Header(extractor) => extractor.get_id(headers).map(|id| (id, 0)),
+            _ => None,
+        }
+    }
+
+    /// Like [get_schema_ids], getting the distinct ids from the payloads without headers.
+    pub(crate) fn get_schema_ids(&self, payloads: &[Option<&[u8]>]) -> Vec<u32> {
+        match self {
+            IdStrategy::Confluent => get_schema_ids(payloads),
+            IdStrategy::Long => {
+                let mut ids = Vec::new();
+                let mut seen = HashSet::new();
+                for p in payloads {
+                    if let BytesResult::Valid(id, _) = self.get_bytes_result(*p, &[]) {
+                        if seen.insert(id) {
+                            ids.push(id)
+                        }
+                    }
+                }
+                ids
+            }
+            IdStrategy::Header(_) => Vec::new(),
+        }
+    }
+}
+
+/// How decoders handle payloads that don't follow the wire format. Lenient keeps the behaviour of
+/// the decoder, which for some decoders means returning the payload as bytes, while strict always
+/// gives an error with the [WireFormatError].
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Strictness {
+    #[default]
+    Lenient,
+    Strict,
+}
+
+/// Receives the payloads that could not be decoded, together with the error, for example to send
+/// them to a dead letter topic. Implemented for closures with the same arguments.
+pub trait DeadLetterHandler: Send + Sync {
+    fn handle(&self, bytes: Option<&[u8]>, error: &SRCError);
+}
+
+impl<F> DeadLetterHandler for F
+where
+    F: Fn(Option<&[u8]>, &SRCError) + Send + Sync,
+{
+    fn handle(&self, bytes: Option<&[u8]>, error: &SRCError) {
+        self(bytes, error)
+    }
+}
+
+impl fmt::Debug for dyn DeadLetterHandler {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "DeadLetterHandler")
+    }
+}
+
+/// Restricts which schema's the decoders fetch and decode, for example to block deprecated
+/// schema's. Checked with the schema id of the payload before the schema is fetched, giving an
+/// error with a `PolicyViolation` when not allowed. Implemented for closures with the same
+/// arguments, by `SchemaIdPolicy`, and by `SchemaSubjectPolicy` to restrict the subjects.
+pub trait SchemaPolicy: Send + Sync {
+    fn allows(&self, id: u32) -> bool;
+    /// Whether the subjects using the schema need to be checked with `allows_subjects`, which
+    /// takes an extra call to the schema registry when the schema is fetched.
+    fn checks_subjects(&self) -> bool {
+        false
+    }
+    /// Whether the schema is allowed, given the subjects using it. Only called when
+    /// `checks_subjects` is true, and after `allows` allowed the id.
+    fn allows_subjects(&self, _id: u32, _subjects: &[String]) -> bool {
+        true
+    }
+}
+
+impl<F> SchemaPolicy for F
+where
+    F: Fn(u32) -> bool + Send + Sync,
+{
+    fn allows(&self, id: u32) -> bool {
+        self(id)
+    }
+}
+
+impl fmt::Debug for dyn SchemaPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SchemaPolicy")
+    }
+}
+
+/// A list of schema ids which are either the only ones allowed, or not allowed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SchemaIdPolicy {
+    Allow(HashSet<u32>),
+    Deny(HashSet<u32>),
+}
+
+impl SchemaPolicy for SchemaIdPolicy {
+    fn allows(&self, id: u32) -> bool {
+        match self {
+            SchemaIdPolicy::Allow(ids) => ids.contains(&id),
+            SchemaIdPolicy::Deny(ids) => !ids.contains(&id),
+        }
+    }
+}
+
+/// A list of subjects which are either the only ones allowed, or not allowed. A schema is allowed
+/// when any of the subjects using it is in the allow list, or none is in the deny list. Since the
+/// subjects are fetched together with the schema, an error because of the subjects is cached like
+/// other errors.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SchemaSubjectPolicy {
+    Allow(HashSet<String>),
+    Deny(HashSet<String>),
+}
+
+impl SchemaPolicy for SchemaSubjectPolicy {
+    fn allows(&self, _id: u32) -> bool {
+        true
+    }
+    fn checks_subjects(&self) -> bool {
+        true
+    }
+    fn allows_subjects(&self, _id: u32, subjects: &[String]) -> bool {
+        match self {
+            SchemaSubjectPolicy::Allow(allowed) => subjects.iter().any(|s| allowed.contains(s)),
+            SchemaSubjectPolicy::Deny(denied) => !subjects.iter().any(|s| denied.contains(s)),
+        }
+    }
+}
+
+/// Gives an error when the policy is set, and doesn't allow the schema id.
+pub(crate) fn check_policy(policy: Option<&dyn SchemaPolicy>, id: u32) -> Result<(), SRCError> {
+    match policy {
+        Some(p) if !p.allows(id) => Err(PolicyViolation { id }.into()),
+        _ => Ok(()),
+    }
+}
+
+/// What decode_or_skip does with a poison message, being a payload with a bad magic byte, an
+/// unknown schema id, or bytes not matching the schema. Retriable errors, like the schema registry
+/// not being available, are always returned, as the payload might be fine.
+#[derive(Clone, Debug, Default)]
+pub enum DeserializationErrorPolicy {
+    /// Returns the error.
+    #[default]
+    FailFast,
+    /// Logs the error as a warning, and skips the payload.
+    SkipAndLog,
+    /// Gives the payload and the error to the handler, and skips the payload.
+    Callback(Arc<dyn DeadLetterHandler>),
+}
+
+impl DeserializationErrorPolicy {
+    /// Applies the policy to the result of decoding the bytes, giving None when they are skipped.
+    pub fn apply<T>(
+        &self,
+        bytes: Option<&[u8]>,
+        result: Result<T, SRCError>,
+    ) -> Result<Option<T>, SRCError> {
+        match (self, result) {
+            (_, Ok(v)) => Ok(Some(v)),
+            (_, Err(e)) if e.retriable => Err(e),
+            (DeserializationErrorPolicy::FailFast, Err(e)) => Err(e),
+            (DeserializationErrorPolicy::SkipAndLog, Err(e)) => {
+                warn!("Skipping payload that could not be decoded: {}", e);
+                Ok(None)
+            }
+            (DeserializationErrorPolicy::Callback(handler), Err(e)) => {
+                handler.handle(bytes, &e);
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Which slow paths of decoding are logged as a warning, to help finding the cause of latency
+/// spikes. The warnings contain the schema id, and the subject when it's known. The default warns
+/// about nothing.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SlowPathWarnings {
+    /// Warns when decoding a payload takes longer than the threshold.
+    pub decode_threshold: Option<Duration>,
+    /// Warns when decoding a payload needs to fetch the schema from the schema registry.
+    pub registry_fetch: bool,
+}
+
+/// The options shared by all the decoders, so the avro, json and protobuf decoders of an
+/// application can be configured in one place. Set with `with_options` of the decoder, the `with_`
+/// methods of the decoder can still change an option afterwards.
+#[derive(Clone, Debug, Default)]
+pub struct DecoderOptions {
+    /// How the schema id is read, which needs to be the same as used by the encoder.
+    pub id_strategy: IdStrategy,
+    /// How payloads that don't follow the wire format are handled.
+    pub strictness: Strictness,
+    /// Which slow paths of decoding are logged as a warning.
+    pub slow_path: SlowPathWarnings,
+    /// Restricts which schema's are fetched and decoded.
+    pub policy: Option<Arc<dyn SchemaPolicy>>,
+}
+
+/// How a schema was used, given to the [SchemaUsageListener].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SchemaUsageKind {
+    /// The schema was fetched from the schema registry, by id or by subject.
+    Fetched,
+    /// The schema was used for the first time by a decoder.
+    Decoded,
+    /// The schema was used for the first time by an encoder.
+    Encoded,
+}
+
+/// The usage of a schema, with the subject when it's known. Schema's fetched by id, and used by a
+/// decoder, don't have a subject, since the schema registry doesn't return it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SchemaUsage {
+    pub kind: SchemaUsageKind,
+    pub id: u32,
+    pub subject: Option<String>,
+}
+
+/// Receives every schema fetched from the schema registry, and the first use of a schema by the
+/// avro and json encoders and decoders, for example to keep track of which schema's are produced
+/// and consumed by a service. Implemented for closures with the same arguments. The listener is
+/// called on the path of the call, so it should be quick.
+pub trait SchemaUsageListener: Send + Sync {
+    fn on_usage(&self, usage: &SchemaUsage);
+}
+
+impl<F> SchemaUsageListener for F
+where
+    F: Fn(&SchemaUsage) + Send + Sync,
+{
+    fn on_usage(&self, usage: &SchemaUsage) {
+        self(usage)
+    }
+}
+
+impl fmt::Debug for dyn SchemaUsageListener {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SchemaUsageListener")
+    }
+}
+
+/// The usage of the schema fetched with the call, when the call fetched a schema and the id is
+/// known.
+pub(crate) fn fetched_usage(sr_call: SrCall, raw: &RawRegisteredSchema) -> Option<SchemaUsage> {
+    let id = match sr_call {
+        SrCall::GetById(id) => id,
+        SrCall::GetLatest(_) | SrCall::GetBySubjectAndVersion(_, _) => raw.id?,
+        SrCall::PostNew(_, _) | SrCall::PostForVersion(_, _) => return None,
+    };
+    let subject = match sr_call {
+        SrCall::GetLatest(s) | SrCall::GetBySubjectAndVersion(s, _) => Some(String::from(s)),
+        _ => raw.subject.clone(),
+    };
+    Some(SchemaUsage {
+        kind: SchemaUsageKind::Fetched,
+        id,
+        subject,
+    })
+}
+
+/// Limits of the schema's fetched from the schema registry, checked before the schema is parsed, so
+/// a pathological schema can't make a consumer run out of memory. The default has no limits.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SchemaLimits {
+    /// The maximum length of a schema in bytes. The response with the schema is limited too, using
+    /// the Content-Length and while reading it, to six times this plus 64 KiB, since the schema is
+    /// an escaped json string in the response, next to the references and metadata.
+    pub max_schema_bytes: Option<usize>,
+    /// The maximum number of references of a schema. Since each referenced schema is checked too,
+    /// together with the depth this limits the size of the graph of references.
+    pub max_references: Option<usize>,
+    /// The maximum number of referenced schema's fetched for a single schema, including the
+    /// references of the references, limiting the whole graph of references.
+    pub max_total_references: Option<usize>,
+    /// The maximum nesting depth of the objects and arrays of avro and json schema's, and of the
+    /// blocks of protobuf schema's.
+    pub max_depth: Option<usize>,
+}
+
+impl SchemaLimits {
+    /// Gives the schema back when it's within the limits, and a non retryable error otherwise.
+    pub(crate) fn check(&self, raw: RawRegisteredSchema) -> Result<RawRegisteredSchema, SRCError> {
+        let schema = raw.schema.as_deref().unwrap_or_default();
+        if let Some(max) = self.max_schema_bytes {
+            if schema.len() > max {
+                return Err(SRCError::non_retryable_without_cause(&format!(
+                    "Schema of {} bytes is larger than the maximum of {} bytes",
+                    schema.len(),
+                    max
+                )));
+            }
+        }
+        if let Some(max) = self.max_references {
+            let references = raw.references.as_ref().map_or(0, |r| r.len());
+            if references > max {
+                return Err(SRCError::non_retryable_without_cause(&format!(
+                    "Schema has {} references, which is more than the maximum of {}",
+                    references, max
+                )));
+            }
+        }
+        if let Some(max) = self.max_depth {
+            if nesting_depth(schema) > max {
+                return Err(SRCError::non_retryable_without_cause(&format!(
+                    "Schema is nested deeper than the maximum depth of {}",
+                    max
+                )));
+            }
+        }
+        Ok(raw)
+    }
+
+    /// The maximum length of a response with a schema, when the length of the schema is limited.
+    pub(crate) fn max_response_bytes(&self) -> Option<usize> {
+        self.max_schema_bytes
+            .map(|max| max.saturating_mul(6).saturating_add(64 * 1024))
+    }
+
+    /// Gives a non retryable error when the response is longer than the maximum, which might only
+    /// be known while reading it.
+    pub(crate) fn check_response_bytes(&self, length: u64) -> Result<(), SRCError> {
+        match self.max_response_bytes() {
+            Some(max) if length > max as u64 => {
+                Err(SRCError::non_retryable_without_cause(&format!(
+                    "Response of more than {} bytes is larger than the maximum for a schema",
+                    max
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Gives a non retryable error when more referenced schema's were fetched for a single schema
+    /// than the maximum total.
+    pub(crate) fn check_total_references(&self, fetched: usize) -> Result<(), SRCError> {
+        match self.max_total_references {
+            Some(max) if fetched > max => Err(SRCError::non_retryable_without_cause(&format!(
+                "Schema has more than {} references in total, including the references of references",
+                max
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The maximum nesting of braces and brackets in the schema, ignoring the ones in strings.
+fn nesting_depth(schema: &str) -> usize {
+    let mut depth = 0usize;
+    let mut max = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for b in schema.bytes() {
+        match b {
+            _ if escaped => escaped = false,
+            b'\\' if in_string => escaped = true,
+            b'"' => in_string = !in_string,
+            b'{' | b'[' if !in_string => {
+                depth += 1;
+                max = max.max(depth);
+            }
+            b'}' | b']' if !in_string => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    max
+}
+
+/// Gives the reason the payload, which was found invalid by [get_bytes_result], doesn't follow the
+/// wire format.
+pub fn to_wire_format_error(bytes: &[u8]) -> WireFormatError {
+    match bytes.first() {
+        Some(b) if *b != 0 => WireFormatError::InvalidMagicByte(*b),
+        _ => WireFormatError::TooShort(bytes.len()),
+    }
+}
+
+/// When strict, gives an error with the number of bytes that were left after decoding the data.
+pub(crate) fn check_remaining_bytes(
+    remaining: usize,
+    strictness: Strictness,
+) -> Result<(), SRCError> {
+    match strictness {
+        Strictness::Strict if remaining > 0 => {
+            Err(SRCError::from(WireFormatError::TrailingBytes(remaining)))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Whether the status means the schema registry, or a proxy in front of it, is overloaded or
+/// temporarily unavailable, in which case the call should be tried again later. Besides too many
+/// requests and service unavailable, this includes bad gateway and gateway timeout, which are given
+/// by load balancers when the schema registry doesn't respond in time.
+pub(crate) fn is_throttling_status(status: u16) -> bool {
+    matches!(status, 429 | 502 | 503 | 504)
+}
+
+/// Parses the value of a Retry-After header, which is either the number of seconds to wait, or the
+/// http date after which to try again, as described in RFC 9110. A date in the past gives zero.
+/// There is no clock on wasm32, so there the date form is ignored.
+pub(crate) fn parse_retry_after(value: &str) -> Option<Duration> {
+    match value.trim().parse::<u64>() {
+        Ok(seconds) => Some(Duration::from_secs(seconds)),
+        Err(_) if cfg!(not(target_arch = "wasm32")) => retry_after_date(value, SystemTime::now()),
+        Err(_) => None,
+    }
+}
+
+fn retry_after_date(value: &str, now: SystemTime) -> Option<Duration> {
+    let date = parse_http_date(value)?;
+    Some(date.duration_since(now).unwrap_or(Duration::ZERO))
+}
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Parses the three formats of an http date, the IMF-fixdate `Sun, 06 Nov 1994 08:49:37 GMT`, and
+/// the obsolete `Sunday, 06-Nov-94 08:49:37 GMT` and `Sun Nov  6 08:49:37 1994`. Two digit years
+/// below 70 are taken to be after 2000.
+fn parse_http_date(value: &str) -> Option<SystemTime> {
+    let parts: Vec<&str> = value.split_whitespace().collect();
+    let (day, month, year, time) = match parts.as_slice() {
+        [_, day, month, year, time, "GMT"] => (*day, *month, year.parse::<i64>().ok()?, *time),
+        [_, date, time, "GMT"] => {
+            let mut date = date.split('-');
+            let (day, month) = (date.next()?, date.next()?);
+            let year = match date.next()?.parse::<i64>().ok()? {
+                y if y < 70 => 2000 + y,
+                y if y < 100 => 1900 + y,
+                y => y,
+            };
+            (day, month, year, *time)
+        }
+        [_, month, day, time, year] => (*day, *month, year.parse::<i64>().ok()?, *time),
+        _ => return None,
+    };
+    let day = day.parse::<i64>().ok().filter(|d| (1..=31).contains(d))?;
+    let month = MONTHS.iter().position(|m| *m == month)? as i64 + 1;
+    let mut time = time.split(':').map(|t| t.parse::<i64>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+    if time.next().is_some() || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    let seconds = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second;
+    u64::try_from(seconds)
+        .ok()
+        .map(|s| UNIX_EPOCH + Duration::from_secs(s))
+}
+
+/// The number of days since the unix epoch of a date in the gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Gets the distinct schema id's of the payloads, in the order they first appear. Payloads that are
+/// empty, or don't start with the magic byte, are skipped.
+pub fn get_schema_ids(payloads: &[Option<&[u8]>]) -> Vec<u32> {
+    let mut ids = Vec::new();
+    let mut seen = HashSet::new();
+    for p in payloads.iter().flatten() {
+        if p.len() > 4 && p[0] == 0 {
+            let id = BigEndian::read_u32(&p[1..5]);
+            if seen.insert(id) {
+                ids.push(id)
+            }
+        }
+    }
+    ids
+}
+
+/// Initial value of the CRC-64-AVRO fingerprint, also known as the Rabin fingerprint.
+const CRC_64_AVRO_EMPTY: u64 = 0xc15d_213a_a4d7_a795;
+
+const CRC_64_AVRO_TABLE: [u64; 256] = crc_64_avro_table();
+
+const fn crc_64_avro_table() -> [u64; 256] {
+    let mut table = [0u64; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut fp = i as u64;
+        let mut j = 0;
+        while j < 8 {
+            fp = (fp >> 1) ^ (CRC_64_AVRO_EMPTY & (fp & 1).wrapping_neg());
+            j += 1;
+        }
+        table[i] = fp;
+        i += 1;
+    }
+    table
+}
+
+/// Computes the CRC-64-AVRO fingerprint of the bytes, as described in the avro specification. For
+/// an avro schema these should be the bytes of the parsing canonical form.
+pub fn crc_64_avro(bytes: &[u8]) -> u64 {
+    let mut fp = CRC_64_AVRO_EMPTY;
+    for b in bytes {
+        fp = (fp >> 8) ^ CRC_64_AVRO_TABLE[((fp ^ u64::from(*b)) & 0xff) as usize];
+    }
+    fp
+}
+
+/// The algorithms available to fingerprint the canonical form of a schema.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FingerprintAlgorithm {
+    /// The CRC-64-AVRO fingerprint, as little endian bytes like in the avro single object encoding.
+    Crc64Avro,
+    #[cfg(feature = "fingerprint")]
+    Md5,
+    #[cfg(feature = "fingerprint")]
+    Sha256,
+}
+
+/// Gets the canonical form of the schema, so schema's only differing in formatting are the same.
+/// For avro this is the parsing canonical form from the avro specification. For protobuf comments
+/// are removed and whitespace is normalized. For json the keys are sorted and whitespace is
+/// removed. References are not resolved, so the schema should be compared with the raw schema of a
+/// registered schema.
+pub fn to_canonical_form(schema_type: &SchemaType, schema: &str) -> Result<String, SRCError> {
+    match schema_type {
+        #[cfg(feature = "avro")]
+        SchemaType::Avro => match apache_avro::Schema::parse_str(schema) {
+            Ok(v) => Ok(v.canonical_form()),
+            Err(e) => Err(SRCError::non_retryable_with_cause(
+                e,
+                "Could not parse avro schema",
+            )),
+        },
+        SchemaType::Protobuf => Ok(normalize_proto(schema)),
+        SchemaType::Json => match serde_json::from_str::<serde_json::Value>(schema) {
+            Ok(v) => {
+                let mut out = String::new();
+                write_sorted_json(&v, &mut out);
+                Ok(out)
+            }
+            Err(e) => Err(SRCError::non_retryable_with_cause(
+                e,
+                "Could not parse json schema",
+            )),
+        },
+        t => Err(SRCError::non_retryable_without_cause(&format!(
+            "Can't get the canonical form of type {:?}",
+            t
+        ))),
+    }
+}
+
+/// Normalizes the schema before registering it, so schema's only differing in formatting don't get
+/// registered as new versions. Unlike the canonical form nothing is removed that matters for the
+/// compatibility, like defaults. For avro the schema is minified with the keys sorted, and types
+/// only having a primitive type are replaced by the name of the type. For json the schema is
+/// minified with the keys sorted, and for protobuf comments are removed and whitespace is
+/// normalized. Schema's of other types are returned as is.
+/// ```
+/// use schema_registry_converter::schema_registry_common::{normalize_schema, SchemaType};
+///
+/// let schema = r#"{
+///   "type": "record", "name": "Heartbeat",
+///   "fields": [{"type": {"type": "long"}, "name": "beat"}]
+/// }"#;
+/// assert_eq!(
+///     normalize_schema(&SchemaType::Avro, schema).unwrap(),
+///     r#"{"fields":[{"name":"beat","type":"long"}],"name":"Heartbeat","type":"record"}"#
+/// );
+/// ```
+pub fn normalize_schema(schema_type: &SchemaType, schema: &str) -> Result<String, SRCError> {
+    let parse = |schema: &str| match serde_json::from_str::<serde_json::Value>(schema) {
+        Ok(v) => Ok(v),
+        Err(e) => Err(SRCError::non_retryable_with_cause(
+            e,
+            &format!("Could not parse {:?} schema", schema_type),
+        )),
+    };
+    let value = match schema_type {
+        SchemaType::Avro => collapse_avro_primitives(parse(schema)?),
+        SchemaType::Json => parse(schema)?,
+        SchemaType::Protobuf => return Ok(normalize_proto(schema)),
+        SchemaType::Other(_) => return Ok(String::from(schema)),
+    };
+    let mut out = String::new();
+    write_sorted_json(&value, &mut out);
+    Ok(out)
+}
+
+/// Normalizes the schema, of the type as named in the body of the call, when `normalize` is set.
+pub(crate) fn normalize_if(
+    normalize: bool,
+    schema_type: &str,
+    schema: &str,
+) -> Result<String, SRCError> {
+    if !normalize {
+        return Ok(String::from(schema));
+    }
+    let schema_type = match schema_type {
+        "AVRO" => SchemaType::Avro,
+        "PROTOBUF" => SchemaType::Protobuf,
+        "JSON" => SchemaType::Json,
+        t => SchemaType::Other(String::from(t)),
+    };
+    normalize_schema(&schema_type, schema)
+}
+
+/// Replaces objects only having a primitive type, like `{"type":"long"}`, by the name of the type.
+fn collapse_avro_primitives(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(map) => {
+            if let (1, Some(serde_json::Value::String(t))) = (map.len(), map.get("type")) {
+                if matches!(
+                    t.as_str(),
+                    "null" | "boolean" | "int" | "long" | "float" | "double" | "bytes" | "string"
+                ) {
+                    return serde_json::Value::String(t.clone());
+                }
+            }
+            serde_json::Value::Object(
+                map.into_iter()
+                    .map(|(k, v)| match k.as_str() {
+                        "default" => (k, v),
+                        _ => (k, collapse_avro_primitives(v)),
+                    })
+                    .collect(),
+            )
+        }
+        serde_json::Value::Array(items) => {
+            serde_json::Value::Array(items.into_iter().map(collapse_avro_primitives).collect())
+        }
+        v => v,
+    }
+}
+
+/// Gets the fingerprint of the canonical form of the schema.
+pub fn get_schema_fingerprint(
+    schema_type: &SchemaType,
+    schema: &str,
+    algorithm: FingerprintAlgorithm,
+) -> Result<Vec<u8>, SRCError> {
+    let canonical_form = to_canonical_form(schema_type, schema)?;
+    let bytes = canonical_form.as_bytes();
+    Ok(match algorithm {
+        FingerprintAlgorithm::Crc64Avro => crc_64_avro(bytes).to_le_bytes().to_vec(),
+        #[cfg(feature = "fingerprint")]
+        FingerprintAlgorithm::Md5 => {
+            use md5::Digest;
+            md5::Md5::digest(bytes).to_vec()
+        }
+        #[cfg(feature = "fingerprint")]
+        FingerprintAlgorithm::Sha256 => {
+            use sha2::Digest;
+            sha2::Sha256::digest(bytes).to_vec()
+        }
+    })
+}
+
+/// Checks whether the schema, for example the one used to compile the code, has the same canonical
+/// form as the registered schema, for example retrieved by the id from the bytes.
+pub fn matches_registered_schema(
+    registered_schema: &RegisteredSchema,
+    schema: &str,
+) -> Result<bool, SRCError> {
+    let registered = to_canonical_form(&registered_schema.schema_type, &registered_schema.schema)?;
+    Ok(registered == to_canonical_form(&registered_schema.schema_type, schema)?)
+}
+
+fn is_proto_punctuation(c: char) -> bool {
+    matches!(
+        c,
+        '{' | '}' | '[' | ']' | '(' | ')' | '<' | '>' | ';' | ',' | '=' | '"' | '\''
+    )
+}
+
+/// Removes the comments and makes the whitespace outside string literals a single space, which is
+/// only kept when both sides are not punctuation.
+fn normalize_proto(schema: &str) -> String {
+    let mut out = String::with_capacity(schema.len());
+    let mut chars = schema.chars().peekable();
+    let mut whitespace = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '/' if chars.peek() == Some(&'/') => {
+                for n in chars.by_ref() {
+                    if n == '\n' {
+                        break;
+                    }
+                }
+                whitespace = true;
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut previous = ' ';
+                for n in chars.by_ref() {
+                    if previous == '*' && n == '/' {
+                        break;
+                    }
+                    previous = n;
+                }
+                whitespace = true;
+            }
+            c if c.is_whitespace() => whitespace = true,
+            c => {
+                if whitespace
+                    && !is_proto_punctuation(c)
+                    && !out.is_empty()
+                    && !out.ends_with(is_proto_punctuation)
+                {
+                    out.push(' ');
+                }
+                whitespace = false;
+                out.push(c);
+                if c == '"' || c == '\'' {
+                    while let Some(n) = chars.next() {
+                        out.push(n);
+                        if n == '\\' {
+                            if let Some(escaped) = chars.next() {
+                                out.push(escaped);
+                            }
+                        } else if n == c {
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    out
+}
+
+fn write_sorted_json(value: &serde_json::Value, out: &mut String) {
+    match value {
+        serde_json::Value::Object(map) => {
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            out.push('{');
+            for (i, (k, v)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str(&serde_json::Value::from(k.as_str()).to_string());
+                out.push(':');
+                write_sorted_json(v, out);
+            }
+            out.push('}');
+        }
+        serde_json::Value::Array(items) => {
+            out.push('[');
+            for (i, v) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_sorted_json(v, out);
+            }
+            out.push(']');
+        }
+        v => out.push_str(&v.to_string()),
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use crate::error::SRCError;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use byteorder::{BigEndian, ByteOrder};
+
+    use crate::error::{SRCError, WireFormatError};
     use crate::schema_registry_common::{
-        get_bytes_result, BytesResult, RegisteredSchema, SchemaType, SrAuthorization,
-        SubjectNameStrategy, SuppliedSchema,
+        config_path, crc_64_avro, extract_id, frame, get_bytes_result, get_payload,
+        get_schema_fingerprint, get_schema_ids, matches_registered_schema, normalize_if,
+        normalize_schema, parse_http_date, parse_retry_after, percent_encode, qualified_subject,
+        retry_after_date, strip_header, subject_path, to_canonical_form, to_wire_format_error,
+        url_for_call, with_normalize, BytesResult, DeserializationErrorPolicy,
+        FingerprintAlgorithm, IdStrategy, RawRegisteredSchema, RegisteredSchema, SchemaLimits,
+        SchemaType, SrAuthorization, SrCall, SrDialect, SubjectNameStrategy, SuppliedSchema,
     };
 
     #[test]
@@ -250,6 +1588,29 @@ mod test {
         )
     }
 
+    #[test]
+    fn strategy_from_tuples_and_display() {
+        let strategy = SubjectNameStrategy::from(("heartbeat", true));
+        assert_eq!(strategy.to_string(), "heartbeat-key");
+        let strategy: SubjectNameStrategy = (String::from("heartbeat"), false).into();
+        assert_eq!(strategy.to_string(), "heartbeat-value");
+        let strategy = SubjectNameStrategy::from(("heartbeat", "nl.openweb.data.Heartbeat"));
+        assert_eq!(
+            SubjectNameStrategy::from(&strategy).to_string(),
+            "heartbeat-nl.openweb.data.Heartbeat"
+        );
+        let strategy = SubjectNameStrategy::RecordNameStrategyWithSchema(SuppliedSchema {
+            name: None,
+            schema_type: SchemaType::Avro,
+            schema: String::from(r#""string""#),
+            references: vec![],
+        });
+        assert_eq!(
+            strategy.to_string(),
+            "name is mandatory in SuppliedSchema when used in TopicRecordNameStrategyWithSchema"
+        );
+    }
+
     #[test]
     fn display_authorization_token() {
         let authorization =
@@ -279,17 +1640,46 @@ mod test {
             schema_type: SchemaType::Avro,
             schema: String::from("some schema"),
             references: vec![],
+            subject: None,
+            version: None,
+            metadata: None,
+            rule_set: None,
+            guid: None,
+            deleted: false,
         };
         assert_eq!(0, registered_schema.id);
         assert_eq!(SchemaType::Avro, registered_schema.schema_type);
         assert_eq!("some schema", registered_schema.schema);
         assert!(registered_schema.references.is_empty());
         assert_eq!(
-            r#"RegisteredSchema { id: 0, schema_type: Avro, schema: "some schema", references: [] }"#,
+            r#"RegisteredSchema { id: 0, schema_type: Avro, schema: "some schema", references: [], subject: None, version: None, metadata: None, rule_set: None, guid: None, deleted: false }"#,
             format!("{:?}", registered_schema)
         )
     }
 
+    #[test]
+    fn raw_schema_with_rule_set() {
+        let raw: RawRegisteredSchema = serde_json::from_str(
+            r#"{"id":1,"schema":"{}","guid":"4b7f6c2e","deleted":true,
+            "metadata":{"tags":{"Customer.ssn":["PII"]},"properties":{"owner":"fennel"}},
+            "ruleSet":{"domainRules":[{"name":"encrypt","kind":"TRANSFORM","mode":"WRITEREAD","type":"ENCRYPT","tags":["PII"],"params":{"encrypt.kek.name":"kek"}}]}}"#,
+        )
+        .unwrap();
+
+        let metadata = raw.metadata.unwrap();
+        assert_eq!(metadata.tags["Customer.ssn"], vec!["PII"]);
+        assert_eq!(metadata.properties["owner"], "fennel");
+        let rule_set = raw.rule_set.unwrap();
+        assert!(rule_set.migration_rules.is_empty());
+        let rule = &rule_set.domain_rules[0];
+        assert_eq!(rule.rule_type, "ENCRYPT");
+        assert_eq!(rule.mode, "WRITEREAD");
+        assert_eq!(rule.params["encrypt.kek.name"], "kek");
+        assert!(!rule.disabled);
+        assert_eq!(raw.guid, Some(String::from("4b7f6c2e")));
+        assert_eq!(raw.deleted, Some(true));
+    }
+
     #[test]
     fn display_byte_result_invalid() {
         let byte_result = BytesResult::Invalid(vec![0, 0]);
@@ -324,6 +1714,151 @@ mod test {
         );
     }
 
+    #[test]
+    fn subject_in_context() {
+        let strategy = SubjectNameStrategy::TopicNameStrategy(String::from("orders"), false)
+            .in_context(".mycontext");
+        assert_eq!(
+            strategy.get_subject(),
+            Ok(String::from(":.mycontext:orders-value"))
+        );
+        assert_eq!(qualified_subject("mycontext", "a"), ":.mycontext:a");
+        assert_eq!(qualified_subject(".", "a"), "a");
+        assert_eq!(qualified_subject("other", ":.mycontext:a"), ":.mycontext:a");
+    }
+
+    #[test]
+    fn dialect_without_deleted() {
+        let url = url_for_call(
+            &SrCall::GetById(3),
+            "http://localhost:8081",
+            SrDialect::Redpanda,
+            true,
+            false,
+        );
+        assert_eq!(url, "http://localhost:8081/schemas/ids/3");
+        let url = url_for_call(
+            &SrCall::GetById(3),
+            "http://localhost:8081",
+            SrDialect::Confluent,
+            false,
+            false,
+        );
+        assert_eq!(url, "http://localhost:8081/schemas/ids/3");
+        let path = SrDialect::Confluent.with_deleted(String::from("/subjects?x=y"), true);
+        assert_eq!(path, "/subjects?x=y&deleted=true");
+        let error = SRCError::new(
+            "not found",
+            Some(String::from(r#"{"error_code":40400}"#)),
+            false,
+        );
+        assert!(SrDialect::Karapace.is_not_found(&error));
+        assert!(!SrDialect::Confluent.is_not_found(&error));
+    }
+
+    #[test]
+    fn custom_strategy() {
+        let resolver = Arc::new(|topic: &str, is_key: bool, name: Option<&str>| {
+            let suffix = if is_key { "key" } else { "value" };
+            format!("prod.{}.{}-{}", topic, name.unwrap_or("any"), suffix)
+        });
+        let strategy = SubjectNameStrategy::CustomStrategy(String::from("orders"), false, resolver);
+        assert_eq!(
+            strategy.get_subject(),
+            Ok(String::from("prod.orders.any-value"))
+        );
+        assert_eq!(
+            "CustomStrategy(\"orders\", false, SubjectNameResolver)".to_owned(),
+            format!("{:?}", strategy)
+        );
+    }
+
+    #[test]
+    fn percent_encode_exotic_names() {
+        assert_eq!(percent_encode("orders-value_v1.0~"), "orders-value_v1.0~");
+        assert_eq!(percent_encode("a b/c?d&e=f#g"), "a%20b%2Fc%3Fd%26e%3Df%23g");
+        assert_eq!(percent_encode("ördèrs:€"), "%C3%B6rd%C3%A8rs%3A%E2%82%AC");
+    }
+
+    #[test]
+    fn qualified_subject_in_path() {
+        let subject = ":.mycontext:nl/openweb";
+        assert_eq!(
+            subject_path(subject, Some(2)),
+            "/subjects/%3A.mycontext%3Anl%2Fopenweb/versions/2"
+        );
+        assert_eq!(
+            config_path(Some(subject)),
+            "/config/%3A.mycontext%3Anl%2Fopenweb"
+        );
+        assert_eq!(
+            url_for_call(
+                &SrCall::GetLatest(subject),
+                "http://localhost:8081",
+                SrDialect::Confluent,
+                true,
+                true
+            ),
+            "http://localhost:8081/subjects/%3A.mycontext%3Anl%2Fopenweb/versions/latest"
+        );
+    }
+
+    #[test]
+    fn long_and_header_ids() {
+        let strategy = IdStrategy::Long;
+        let payload = strategy.get_payload(7, vec![6]);
+        assert_eq!(payload, vec![0, 0, 0, 0, 0, 0, 0, 0, 7, 6]);
+        assert_eq!(
+            strategy.get_bytes_result(Some(&payload), &[]),
+            BytesResult::Valid(7, vec![6])
+        );
+        assert_eq!(strategy.get_schema_ids(&[Some(&payload), None]), vec![7]);
+        assert_eq!(strategy.get_id_and_offset(&payload, &[]), Some((7, 9)));
+        assert_eq!(
+            IdStrategy::Confluent.get_id_and_offset(&payload, &[]),
+            Some((0, 5))
+        );
+
+        let extractor = |headers: &[(&str, &[u8])]| {
+            headers
+                .iter()
+                .find(|(k, _)| *k == "apicurio.value.globalId")
+                .map(|(_, v)| BigEndian::read_u64(v) as u32)
+        };
+        let strategy = IdStrategy::Header(Arc::new(extractor));
+        assert_eq!(strategy.get_payload(7, vec![6]), vec![6]);
+        let mut buf = vec![1];
+        strategy.write_header(7, &mut buf);
+        assert_eq!(buf, vec![1]);
+        let id: &[u8] = &[0, 0, 0, 0, 0, 0, 0, 9];
+        let headers = [("apicurio.value.globalId", id)];
+        assert_eq!(
+            strategy.get_bytes_result(Some(&[6]), &headers),
+            BytesResult::Valid(9, vec![6])
+        );
+        assert_eq!(
+            strategy.get_bytes_result(Some(&[6]), &[]),
+            BytesResult::Invalid(vec![6])
+        );
+        assert_eq!(strategy.get_id_and_offset(&[6], &headers), Some((9, 0)));
+        assert_eq!(strategy.get_id_and_offset(&[6], &[]), None);
+    }
+
+    #[test]
+    fn extract_strip_and_frame() {
+        let bytes = frame(7, &[101, 99]);
+        assert_eq!(bytes, vec![0, 0, 0, 0, 7, 101, 99]);
+        assert_eq!(extract_id(&bytes), Some(7));
+        assert_eq!(strip_header(&bytes), Some(&[101u8, 99][..]));
+        assert_eq!(
+            frame(8, strip_header(&bytes).unwrap()),
+            get_payload(8, vec![101, 99])
+        );
+        assert_eq!(extract_id(&[0, 0, 0, 7]), None);
+        assert_eq!(strip_header(&[1, 0, 0, 0, 7, 101]), None);
+        assert_eq!(strip_header(&[0, 0, 0, 0, 7]), Some(&[][..]));
+    }
+
     #[test]
     fn get_bytes_result_null() {
         let result = get_bytes_result(None);
@@ -341,4 +1876,236 @@ mod test {
         let result = get_bytes_result(Some(&[0, 0, 0, 0]));
         assert_eq!(BytesResult::Invalid(vec![0, 0, 0, 0]), result)
     }
+
+    #[test]
+    fn wire_format_error_of_invalid_bytes() {
+        assert_eq!(
+            to_wire_format_error(&[1, 0, 0, 0, 7, 101]),
+            WireFormatError::InvalidMagicByte(1)
+        );
+        assert_eq!(
+            to_wire_format_error(&[0, 0, 0]),
+            WireFormatError::TooShort(3)
+        );
+    }
+
+    #[test]
+    fn get_schema_ids_distinct() {
+        let payloads: [Option<&[u8]>; 5] = [
+            Some(&[0, 0, 0, 0, 7, 101]),
+            None,
+            Some(&[0, 0, 0, 1, 3, 101]),
+            Some(&[1, 0, 0, 0, 5, 101]),
+            Some(&[0, 0, 0, 0, 7, 102]),
+        ];
+        assert_eq!(get_schema_ids(&payloads), vec![7, 259])
+    }
+
+    #[test]
+    fn retry_after_in_seconds_or_http_date() {
+        assert_eq!(parse_retry_after(" 120"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after("soon"), None);
+        let date = Some(UNIX_EPOCH + Duration::from_secs(784_111_777));
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"), date);
+        assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), date);
+        assert_eq!(parse_http_date("Sun Nov  6 08:49:37 1994"), date);
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 24:49:37 GMT"), None);
+        assert_eq!(parse_http_date("Sun, 06 Foo 1994 08:49:37 GMT"), None);
+        let now = UNIX_EPOCH + Duration::from_secs(784_111_700);
+        assert_eq!(
+            retry_after_date("Sun, 06 Nov 1994 08:49:37 GMT", now),
+            Some(Duration::from_secs(77))
+        );
+        assert_eq!(
+            retry_after_date("Sat, 05 Nov 1994 08:49:37 GMT", now),
+            Some(Duration::ZERO)
+        );
+    }
+
+    #[test]
+    fn crc_64_avro_of_primitives() {
+        assert_eq!(crc_64_avro(br#""null""#), 7_195_948_357_588_979_594);
+        assert_eq!(
+            crc_64_avro(br#""long""#),
+            -3_434_872_931_120_570_953i64 as u64
+        );
+    }
+
+    #[test]
+    fn canonical_form_of_proto() {
+        let schema = "syntax = \"proto3\";\n// The beat\nmessage Heartbeat {\n  uint64 beat = 1; /* count */\n  string name = 2 [default = \"a  b\"];\n}\n";
+        assert_eq!(
+            to_canonical_form(&SchemaType::Protobuf, schema).unwrap(),
+            "syntax=\"proto3\";message Heartbeat{uint64 beat=1;string name=2[default=\"a  b\"];}"
+        )
+    }
+
+    #[test]
+    fn canonical_form_of_json() {
+        let schema = r#"{ "type": "object", "properties": { "b": {"type": "string"}, "a": {"type": "integer"} } }"#;
+        assert_eq!(
+            to_canonical_form(&SchemaType::Json, schema).unwrap(),
+            r#"{"properties":{"a":{"type":"integer"},"b":{"type":"string"}},"type":"object"}"#
+        )
+    }
+
+    #[test]
+    fn normalize_keeps_what_matters_for_compatibility() {
+        let schema = r#"{"type": "record", "name": "Beat", "doc": "a beat", "fields": [
+            {"name": "b", "type": ["null", {"type": "string"}], "default": null},
+            {"name": "a", "type": {"type": "long", "logicalType": "timestamp-millis"}},
+            {"name": "c", "type": {"type": "map", "values": "string"}, "default": {"type": "int"}}
+        ]}"#;
+        assert_eq!(
+            normalize_schema(&SchemaType::Avro, schema).unwrap(),
+            r#"{"doc":"a beat","fields":[{"default":null,"name":"b","type":["null","string"]},{"name":"a","type":{"logicalType":"timestamp-millis","type":"long"}},{"default":{"type":"int"},"name":"c","type":{"type":"map","values":"string"}}],"name":"Beat","type":"record"}"#
+        );
+        let other = SchemaType::Other(String::from("XML"));
+        assert_eq!(normalize_schema(&other, " <a/> ").unwrap(), " <a/> ");
+        assert!(normalize_schema(&SchemaType::Json, "{").is_err());
+        assert_eq!(
+            normalize_if(true, "PROTOBUF", "message A {\n  int32 a = 1;\n}").unwrap(),
+            "message A{int32 a=1;}"
+        );
+        assert_eq!(
+            with_normalize(String::from("/subjects/a?deleted=false"), true),
+            "/subjects/a?deleted=false&normalize=true"
+        );
+        assert_eq!(
+            url_for_call(
+                &SrCall::PostNew("a", "{}"),
+                "http://localhost:8081",
+                SrDialect::Confluent,
+                true,
+                true
+            ),
+            "http://localhost:8081/subjects/a/versions?normalize=true"
+        );
+    }
+
+    #[test]
+    fn registered_schema_matches_formatted_schema() {
+        let registered_schema = RegisteredSchema {
+            id: 1,
+            schema_type: SchemaType::Json,
+            schema: String::from(r#"{"type":"object","title":"Heartbeat"}"#),
+            references: vec![],
+            subject: None,
+            version: None,
+            metadata: None,
+            rule_set: None,
+            guid: None,
+            deleted: false,
+        };
+        let schema = "{\n  \"title\": \"Heartbeat\",\n  \"type\": \"object\"\n}";
+        assert!(matches_registered_schema(&registered_schema, schema).unwrap());
+        assert!(!matches_registered_schema(&registered_schema, r#"{"type":"string"}"#).unwrap());
+        assert_eq!(
+            get_schema_fingerprint(&SchemaType::Json, schema, FingerprintAlgorithm::Crc64Avro)
+                .unwrap(),
+            get_schema_fingerprint(
+                &SchemaType::Json,
+                &registered_schema.schema,
+                FingerprintAlgorithm::Crc64Avro
+            )
+            .unwrap()
+        );
+    }
+
+    #[cfg(all(feature = "avro", feature = "fingerprint"))]
+    #[test]
+    fn fingerprints_of_avro() {
+        let schema = r#"{"type": "null"}"#;
+        assert_eq!(
+            to_canonical_form(&SchemaType::Avro, schema).unwrap(),
+            r#""null""#
+        );
+        assert_eq!(
+            get_schema_fingerprint(&SchemaType::Avro, schema, FingerprintAlgorithm::Crc64Avro)
+                .unwrap(),
+            7_195_948_357_588_979_594u64.to_le_bytes().to_vec()
+        );
+        assert_eq!(
+            get_schema_fingerprint(&SchemaType::Avro, schema, FingerprintAlgorithm::Md5).unwrap(),
+            vec![155, 65, 239, 103, 101, 28, 24, 72, 138, 139, 8, 187, 103, 199, 86, 153]
+        );
+        assert_eq!(
+            get_schema_fingerprint(&SchemaType::Avro, schema, FingerprintAlgorithm::Sha256)
+                .unwrap(),
+            vec![
+                240, 114, 203, 236, 59, 248, 132, 24, 113, 212, 40, 66, 48, 197, 233, 131, 220, 33,
+                26, 86, 131, 122, 237, 134, 36, 135, 20, 143, 148, 125, 26, 31
+            ]
+        );
+    }
+
+    #[test]
+    fn error_policies() {
+        let poison = SRCError::non_retryable_without_cause("Invalid magic byte");
+        let bytes: &[u8] = &[1, 2, 3];
+
+        let result =
+            DeserializationErrorPolicy::FailFast.apply::<u8>(Some(bytes), Err(poison.clone()));
+        assert_eq!(result, Err(poison.clone()));
+        let result =
+            DeserializationErrorPolicy::SkipAndLog.apply::<u8>(Some(bytes), Err(poison.clone()));
+        assert_eq!(result, Ok(None));
+
+        let dead_letters = Arc::new(Mutex::new(Vec::new()));
+        let handled = dead_letters.clone();
+        let policy = DeserializationErrorPolicy::Callback(Arc::new(
+            move |b: Option<&[u8]>, e: &SRCError| {
+                handled
+                    .lock()
+                    .unwrap()
+                    .push((b.map(|b| b.to_vec()), e.error.clone()))
+            },
+        ));
+        assert_eq!(policy.apply(Some(bytes), Ok(3)), Ok(Some(3)));
+        assert_eq!(policy.apply::<u8>(Some(bytes), Err(poison)), Ok(None));
+        let retriable = SRCError::retryable_with_cause("timeout", "Schema registry not available");
+        assert!(policy.apply::<u8>(None, Err(retriable)).is_err());
+        assert_eq!(
+            *dead_letters.lock().unwrap(),
+            vec![(Some(vec![1, 2, 3]), String::from("Invalid magic byte"))]
+        );
+    }
+
+    #[test]
+    fn schema_limits() {
+        let raw = || -> RawRegisteredSchema {
+            serde_json::from_str(
+                r#"{"schema":"{\"type\":\"record\",\"name\":\"A\",\"doc\":\"{{{{\",\"fields\":[{\"name\":\"b\",\"type\":\"B\"}]}","references":[{"name":"B","subject":"b","version":1}]}"#,
+            )
+            .unwrap()
+        };
+        assert!(SchemaLimits::default().check(raw()).is_ok());
+        let limits = SchemaLimits {
+            max_schema_bytes: Some(200),
+            max_references: Some(1),
+            max_total_references: Some(1),
+            max_depth: Some(3),
+        };
+        assert!(limits.check(raw()).is_ok());
+        assert!(limits.check_total_references(1).is_ok());
+        assert!(limits.check_total_references(2).is_err());
+        assert!(limits.check_response_bytes(1200 + 64 * 1024).is_ok());
+        assert!(limits.check_response_bytes(1201 + 64 * 1024).is_err());
+
+        let too_large = SchemaLimits {
+            max_schema_bytes: Some(20),
+            ..limits
+        };
+        assert!(!too_large.check(raw()).unwrap_err().retriable);
+        let too_many_references = SchemaLimits {
+            max_references: Some(0),
+            ..limits
+        };
+        assert!(too_many_references.check(raw()).is_err());
+        let too_deep = SchemaLimits {
+            max_depth: Some(2),
+            ..limits
+        };
+        assert!(too_deep.check(raw()).is_err());
+    }
 }
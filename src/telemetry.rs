@@ -0,0 +1,253 @@
+//! Hooks for the behaviour of the caches, the calls to schema registry, and decoding. With the
+//! tracing feature these emit events, and with the metrics feature these record the metrics
+//! `registry_request_duration`, `cache_hits`, `cache_misses`, `schemas_cached`, `decode_errors`
+//! and `encode_errors` using the metrics facade, so they can be exported with any metrics recorder,
+//! like the one for prometheus. Without either feature these do nothing. The slow path warnings
+//! are logged independent of the features. With the opentelemetry feature the context of the
+//! current span is propagated to the schema registry with the headers of the calls.
+#![cfg_attr(
+    not(any(feature = "tracing", feature = "metrics")),
+    allow(unused_variables)
+)]
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use log::warn;
+
+use crate::error::SRCError;
+use crate::schema_registry_common::SlowPathWarnings;
+
+/// Records looking up the schema with the id in the cache of a decoder.
+pub(crate) fn record_cache_lookup(id: u32, hit: bool) {
+    #[cfg(feature = "tracing")]
+    tracing::trace!(schema.id = id, hit, "schema cache lookup");
+    #[cfg(feature = "metrics")]
+    if hit {
+        metrics::counter!("cache_hits").increment(1);
+    } else {
+        metrics::counter!("cache_misses").increment(1);
+    }
+}
+
+/// Counts the schema's in the cache of a decoder for the `schemas_cached` gauge. The decoders only
+/// remove errors from their cache, so the schema's are taken off the gauge when the count is
+/// dropped, which for the decoders sharing their cache between clones is with the last clone.
+#[derive(Debug)]
+#[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+pub(crate) struct CachedSchemas {
+    schema_type: &'static str,
+    count: AtomicUsize,
+}
+
+impl CachedSchemas {
+    pub(crate) fn new(schema_type: &'static str) -> CachedSchemas {
+        CachedSchemas {
+            schema_type,
+            count: AtomicUsize::new(0),
+        }
+    }
+
+    /// Records a schema being added to the cache of the decoder.
+    pub(crate) fn added(&self) {
+        self.count.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        metrics::gauge!("schemas_cached", "schema_type" => self.schema_type).increment(1.0);
+    }
+}
+
+impl Drop for CachedSchemas {
+    fn drop(&mut self) {
+        #[cfg(feature = "metrics")]
+        metrics::gauge!("schemas_cached", "schema_type" => self.schema_type)
+            .decrement(*self.count.get_mut() as f64);
+    }
+}
+
+/// The start of a call to schema registry, only taken when it's recorded. On wasm32 there is no
+/// clock to take it from, so the duration of the calls isn't recorded there.
+pub(crate) fn request_start() -> Option<Instant> {
+    if cfg!(all(feature = "metrics", not(target_arch = "wasm32"))) {
+        Some(Instant::now())
+    } else {
+        None
+    }
+}
+
+/// Records a call to schema registry, started at start, and whether it was successful.
+pub(crate) fn record_request<T, E>(start: Option<Instant>, result: &Result<T, E>) {
+    #[cfg(feature = "metrics")]
+    if let Some(start) = start {
+        metrics::histogram!(
+            "registry_request_duration",
+            "outcome" => if result.is_ok() { "success" } else { "failure" }
+        )
+        .record(start.elapsed().as_secs_f64());
+    }
+}
+
+/// The headers propagating the context of the current span to the schema registry, like the W3C
+/// `traceparent` and `tracestate`, so the calls show up in distributed traces. The headers are
+/// set by the global text map propagator of opentelemetry, which needs to be set by the
+/// application, for example to the `TraceContextPropagator`.
+pub(crate) fn trace_headers() -> Vec<(String, String)> {
+    #[cfg(feature = "opentelemetry")]
+    {
+        use opentelemetry::propagation::Injector;
+        use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+        struct HeaderInjector(Vec<(String, String)>);
+
+        impl Injector for HeaderInjector {
+            fn set(&mut self, key: &str, value: String) {
+                self.0.push((String::from(key), value));
+            }
+        }
+
+        let context = tracing::Span::current().context();
+        let mut injector = HeaderInjector(Vec::new());
+        opentelemetry::global::get_text_map_propagator(|propagator| {
+            propagator.inject_context(&context, &mut injector)
+        });
+        injector.0
+    }
+    #[cfg(not(feature = "opentelemetry"))]
+    Vec::new()
+}
+
+/// Records a payload that could not be decoded.
+pub(crate) fn record_decode_error(schema_type: &'static str) {
+    #[cfg(feature = "metrics")]
+    metrics::counter!("decode_errors", "schema_type" => schema_type).increment(1);
+}
+
+/// Records a payload that could not be encoded.
+pub(crate) fn record_encode_error(schema_type: &'static str) {
+    #[cfg(feature = "metrics")]
+    metrics::counter!("encode_errors", "schema_type" => schema_type).increment(1);
+}
+
+/// The start of a decode, only taken when slow decodes are warned about. Like with the calls to
+/// schema registry, there is no clock to take it from on wasm32.
+pub(crate) fn decode_start(warnings: &SlowPathWarnings) -> Option<Instant> {
+    if warnings.decode_threshold.is_some() && cfg!(not(target_arch = "wasm32")) {
+        Some(Instant::now())
+    } else {
+        None
+    }
+}
+
+/// Warns when the decode, started at start, of a payload with the schema id took longer than the
+/// threshold. The subject is only looked up for the warning.
+pub(crate) fn warn_slow_decode(
+    warnings: &SlowPathWarnings,
+    start: Option<Instant>,
+    id: u32,
+    subject: impl FnOnce() -> Option<String>,
+) {
+    if let (Some(start), Some(threshold)) = (start, warnings.decode_threshold) {
+        let elapsed = start.elapsed();
+        if elapsed > threshold {
+            let subject = match subject() {
+                Some(subject) => format!(" and subject {}", subject),
+                None => String::new(),
+            };
+            warn!(
+                "Decoding a payload with schema id {}{} took {:?}, which is longer than {:?}",
+                id, subject, elapsed, threshold
+            );
+        }
+    }
+}
+
+/// Warns when decoding a payload with the schema id needs to fetch the schema from the schema
+/// registry.
+pub(crate) fn warn_registry_fetch(warnings: &SlowPathWarnings, id: u32) {
+    if warnings.registry_fetch {
+        warn!(
+            "Decoding a payload with schema id {} needs to fetch the schema from the schema registry",
+            id
+        );
+    }
+}
+
+/// Warns when getting the schema with the id, before decoding a batch of payloads, failed. The
+/// payloads with the id are still decoded, giving an error when getting the schema fails again.
+pub(crate) fn warn_batch_fetch_failed(id: u32, err: &SRCError) {
+    warn!(
+        "Getting the schema with id {} for decoding a batch failed: {}",
+        id, err
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
+
+    use crate::schema_registry_common::SlowPathWarnings;
+    use crate::telemetry::{decode_start, warn_slow_decode, CachedSchemas};
+
+    #[test]
+    fn slow_decode_only_timed_with_threshold() {
+        assert!(decode_start(&SlowPathWarnings::default()).is_none());
+
+        let warnings = SlowPathWarnings {
+            decode_threshold: Some(Duration::from_secs(60)),
+            registry_fetch: false,
+        };
+        let start = decode_start(&warnings);
+        assert!(start.is_some());
+        warn_slow_decode(&warnings, start, 7, || {
+            panic!("the subject is only needed for a slow decode")
+        });
+    }
+
+    #[test]
+    fn cached_schemas_counted_for_the_gauge() {
+        let cached_schemas = CachedSchemas::new("avro");
+        cached_schemas.added();
+        cached_schemas.added();
+        assert_eq!(cached_schemas.count.load(Ordering::Relaxed), 2);
+    }
+
+    #[cfg(feature = "opentelemetry")]
+    #[test]
+    fn trace_headers_set_by_global_propagator() {
+        use opentelemetry::propagation::text_map_propagator::FieldIter;
+        use opentelemetry::propagation::{Extractor, Injector, TextMapPropagator};
+        use opentelemetry::Context;
+
+        use crate::telemetry::trace_headers;
+
+        #[derive(Debug)]
+        struct FixedPropagator(Vec<String>);
+
+        impl TextMapPropagator for FixedPropagator {
+            fn inject_context(&self, _: &Context, injector: &mut dyn Injector) {
+                injector.set(
+                    "traceparent",
+                    String::from("00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01"),
+                );
+            }
+
+            fn extract_with_context(&self, cx: &Context, _: &dyn Extractor) -> Context {
+                cx.clone()
+            }
+
+            fn fields(&self) -> FieldIter<'_> {
+                FieldIter::new(&self.0)
+            }
+        }
+
+        opentelemetry::global::set_text_map_propagator(FixedPropagator(vec![String::from(
+            "traceparent",
+        )]));
+        assert_eq!(
+            trace_headers(),
+            vec![(
+                String::from("traceparent"),
+                String::from("00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01")
+            )]
+        );
+    }
+}
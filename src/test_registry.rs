@@ -0,0 +1,312 @@
+//! A schema registry keeping the schema's in memory, implementing the part of the Confluent REST
+//! API used by this crate, being the schemas/ids, subjects and compatibility endpoints. It's meant
+//! for tests, so they don't need a schema registry running in docker. All schema's are compatible,
+//! and deleting isn't supported.
+//!
+//! The registry can be started as part of a test with `TestRegistry::start`, or as a separate
+//! process with the `test_registry` binary.
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::Deserialize;
+use serde_json::json;
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+
+use crate::error::SRCError;
+use crate::schema_registry_common::RegisteredReference;
+
+type SharedRegistry = Arc<Mutex<Registry>>;
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct StoredSchema {
+    #[serde(default = "avro")]
+    schema_type: String,
+    schema: String,
+    #[serde(default)]
+    references: Vec<RegisteredReference>,
+}
+
+fn avro() -> String {
+    String::from("AVRO")
+}
+
+/// The schema's, where the id is the index plus one, and for each subject the ids of the versions,
+/// where the version is the index plus one.
+#[derive(Debug, Default)]
+struct Registry {
+    schemas: Vec<StoredSchema>,
+    subjects: BTreeMap<String, Vec<u32>>,
+}
+
+impl Registry {
+    fn schema(&self, id: u32) -> Option<&StoredSchema> {
+        self.schemas.get(id.checked_sub(1)? as usize)
+    }
+
+    fn register(&mut self, subject: String, schema: StoredSchema) -> u32 {
+        let id = match self.schemas.iter().position(|s| *s == schema) {
+            Some(index) => index as u32 + 1,
+            None => {
+                self.schemas.push(schema);
+                self.schemas.len() as u32
+            }
+        };
+        let versions = self.subjects.entry(subject).or_default();
+        if !versions.contains(&id) {
+            versions.push(id);
+        }
+        id
+    }
+
+    fn version_response(&self, subject: &str, version: usize, id: u32) -> Response {
+        let schema = &self.schemas[id as usize - 1];
+        Json(json!({
+            "subject": subject,
+            "version": version,
+            "id": id,
+            "schemaType": schema.schema_type,
+            "schema": schema.schema,
+            "references": schema.references,
+        }))
+        .into_response()
+    }
+}
+
+fn error_response(status: StatusCode, error_code: u32, message: &str) -> Response {
+    (
+        status,
+        Json(json!({"error_code": error_code, "message": message})),
+    )
+        .into_response()
+}
+
+fn subject_not_found() -> Response {
+    error_response(StatusCode::NOT_FOUND, 40401, "Subject not found.")
+}
+
+fn version_not_found() -> Response {
+    error_response(StatusCode::NOT_FOUND, 40402, "Version not found.")
+}
+
+async fn get_schema_by_id(State(registry): State<SharedRegistry>, Path(id): Path<u32>) -> Response {
+    let registry = registry.lock().unwrap();
+    match registry.schema(id) {
+        Some(schema) => Json(json!({
+            "schemaType": schema.schema_type,
+            "schema": schema.schema,
+            "references": schema.references,
+        }))
+        .into_response(),
+        None => error_response(StatusCode::NOT_FOUND, 40403, "Schema not found"),
+    }
+}
+
+async fn list_subjects(State(registry): State<SharedRegistry>) -> Response {
+    let registry = registry.lock().unwrap();
+    let subjects: Vec<&String> = registry.subjects.keys().collect();
+    Json(subjects).into_response()
+}
+
+async fn list_versions(
+    State(registry): State<SharedRegistry>,
+    Path(subject): Path<String>,
+) -> Response {
+    let registry = registry.lock().unwrap();
+    match registry.subjects.get(&subject) {
+        Some(ids) => Json((1..=ids.len()).collect::<Vec<usize>>()).into_response(),
+        None => subject_not_found(),
+    }
+}
+
+async fn get_version(
+    State(registry): State<SharedRegistry>,
+    Path((subject, version)): Path<(String, String)>,
+) -> Response {
+    let registry = registry.lock().unwrap();
+    let ids = match registry.subjects.get(&subject) {
+        Some(ids) => ids,
+        None => return subject_not_found(),
+    };
+    let version = match version.as_str() {
+        "latest" => ids.len(),
+        v => match v.parse::<usize>() {
+            Ok(v) => v,
+            Err(_) => {
+                return error_response(StatusCode::UNPROCESSABLE_ENTITY, 42202, "Invalid version")
+            }
+        },
+    };
+    match version.checked_sub(1).and_then(|i| ids.get(i)) {
+        Some(id) => registry.version_response(&subject, version, *id),
+        None => version_not_found(),
+    }
+}
+
+async fn register_schema(
+    State(registry): State<SharedRegistry>,
+    Path(subject): Path<String>,
+    Json(schema): Json<StoredSchema>,
+) -> Response {
+    let mut registry = registry.lock().unwrap();
+    let id = registry.register(subject, schema);
+    Json(json!({ "id": id })).into_response()
+}
+
+async fn check_schema(
+    State(registry): State<SharedRegistry>,
+    Path(subject): Path<String>,
+    Json(schema): Json<StoredSchema>,
+) -> Response {
+    let registry = registry.lock().unwrap();
+    let ids = match registry.subjects.get(&subject) {
+        Some(ids) => ids,
+        None => return subject_not_found(),
+    };
+    let found = ids
+        .iter()
+        .enumerate()
+        .find(|(_, id)| registry.schema(**id) == Some(&schema));
+    match found {
+        Some((index, id)) => registry.version_response(&subject, index + 1, *id),
+        None => error_response(StatusCode::NOT_FOUND, 40403, "Schema not found"),
+    }
+}
+
+async fn check_compatibility(
+    State(registry): State<SharedRegistry>,
+    Path((subject, _)): Path<(String, String)>,
+) -> Response {
+    let registry = registry.lock().unwrap();
+    if registry.subjects.contains_key(&subject) {
+        Json(json!({"is_compatible": true})).into_response()
+    } else {
+        subject_not_found()
+    }
+}
+
+fn router() -> Router {
+    Router::new()
+        .route("/schemas/ids/:id", get(get_schema_by_id))
+        .route("/subjects", get(list_subjects))
+        .route("/subjects/:subject", post(check_schema))
+        .route(
+            "/subjects/:subject/versions",
+            get(list_versions).post(register_schema),
+        )
+        .route("/subjects/:subject/versions/:version", get(get_version))
+        .route(
+            "/compatibility/subjects/:subject/versions/:version",
+            post(check_compatibility),
+        )
+        .with_state(SharedRegistry::default())
+}
+
+/// Serves a new, empty, registry on the listener, until the future is dropped.
+pub async fn serve(listener: TcpListener) -> std::io::Result<()> {
+    axum::serve(listener, router()).await
+}
+
+/// A registry running in the background on a random port of localhost, which is stopped when
+/// dropped.
+#[derive(Debug)]
+pub struct TestRegistry {
+    address: SocketAddr,
+    handle: JoinHandle<std::io::Result<()>>,
+}
+
+impl TestRegistry {
+    /// Starts a new, empty, registry. Needs to be called from within a tokio runtime.
+    pub async fn start() -> Result<TestRegistry, SRCError> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.map_err(|e| {
+            SRCError::non_retryable_with_cause(e, "could not bind the test registry")
+        })?;
+        let address = listener.local_addr().map_err(|e| {
+            SRCError::non_retryable_with_cause(e, "could not get the address of the test registry")
+        })?;
+        let handle = tokio::spawn(serve(listener));
+        Ok(TestRegistry { address, handle })
+    }
+
+    /// The url to use in the schema registry settings.
+    pub fn url(&self) -> String {
+        format!("http://{}", self.address)
+    }
+}
+
+impl Drop for TestRegistry {
+    fn drop(&mut self) {
+        self.handle.abort()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::async_impl::schema_registry::{
+        check_schema_registered, get_schema_by_id, get_schema_by_subject, get_subject_versions,
+        list_subjects, post_schema, SrSettings,
+    };
+    use crate::schema_registry_common::{SchemaType, SubjectNameStrategy, SuppliedSchema};
+    use crate::test_registry::TestRegistry;
+
+    const HEARTBEAT: &str = r#"{"type":"record","name":"Heartbeat","namespace":"nl.openweb.data","fields":[{"name":"beat","type":"long"}]}"#;
+
+    fn heartbeat() -> SuppliedSchema {
+        SuppliedSchema {
+            name: Some(String::from("nl.openweb.data.Heartbeat")),
+            schema_type: SchemaType::Avro,
+            schema: String::from(HEARTBEAT),
+            references: vec![],
+        }
+    }
+
+    #[tokio::test]
+    async fn register_and_fetch() {
+        let registry = TestRegistry::start().await.unwrap();
+        let sr_settings = SrSettings::new(registry.url());
+
+        let registered = post_schema(&sr_settings, String::from("a/b-value"), heartbeat())
+            .await
+            .unwrap();
+        assert_eq!(registered.id, 1);
+        let again = post_schema(&sr_settings, String::from("other-value"), heartbeat())
+            .await
+            .unwrap();
+        assert_eq!(again.id, 1);
+
+        let result = get_schema_by_id(1, &sr_settings).await.unwrap();
+        assert_eq!(result.schema, HEARTBEAT);
+        assert!(get_schema_by_id(2, &sr_settings).await.is_err());
+
+        let strategy = SubjectNameStrategy::TopicNameStrategy(String::from("a/b"), false);
+        let latest = get_schema_by_subject(&sr_settings, &strategy)
+            .await
+            .unwrap();
+        assert_eq!(latest.id, 1);
+
+        let subjects = list_subjects(&sr_settings, false).await.unwrap();
+        assert_eq!(subjects, vec!["a/b-value", "other-value"]);
+        let versions = get_subject_versions(&sr_settings, "a/b-value", false)
+            .await
+            .unwrap();
+        assert_eq!(versions, vec![1]);
+
+        let found =
+            check_schema_registered(&sr_settings, "a/b-value", &SchemaType::Avro, HEARTBEAT, &[])
+                .await
+                .unwrap();
+        assert_eq!(found.map(|s| s.id), Some(1));
+        let found =
+            check_schema_registered(&sr_settings, "unknown", &SchemaType::Avro, HEARTBEAT, &[])
+                .await
+                .unwrap();
+        assert!(found.is_none());
+    }
+}
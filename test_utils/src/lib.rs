@@ -26,6 +26,16 @@ pub fn get_proto_complex() -> &'static str {
     r#"syntax = \"proto3\"; import \"result.proto\"; message A {bytes id = 1;} message B {bytes id = 1;} message C {bytes id = 1; D d = 2; message D {int64 counter = 1;}} package org.schema_registry_test_app.proto; message ProtoTest {bytes id = 1; enum Language {Java = 0;Rust = 1;} Language by = 2;int64 counter = 3;string input = 4;repeated A results = 5;}"#
 }
 
+/// The result schema as supplied by the user, without the escaping needed for a json body.
+pub fn get_proto_result_source() -> &'static str {
+    r#"syntax = "proto3"; package org.schema_registry_test_app.proto; message Result { string up = 1; string down = 2; } "#
+}
+
+/// The complex schema as supplied by the user, without the escaping needed for a json body.
+pub fn get_proto_complex_source() -> &'static str {
+    r#"syntax = "proto3"; import "result.proto"; message A {bytes id = 1;} message B {bytes id = 1;} message C {bytes id = 1; D d = 2; message D {int64 counter = 1;}} package org.schema_registry_test_app.proto; message ProtoTest {bytes id = 1; enum Language {Java = 0;Rust = 1;} Language by = 2;int64 counter = 3;string input = 4;repeated A results = 5;}"#
+}
+
 pub fn get_proto_hb_101_only_data() -> &'static [u8] {
     &get_proto_hb_101()[6..]
 }
@@ -34,6 +44,16 @@ pub fn get_proto_hb_101() -> &'static [u8] {
     &[0, 0, 0, 0, 7, 0, 8, 101]
 }
 
+/// FileDescriptorSet with only heartbeat.proto, with the same Heartbeat message as the hb schema.
+pub fn get_proto_hb_descriptor_set() -> &'static [u8] {
+    &[
+        10, 69, 10, 15, 104, 101, 97, 114, 116, 98, 101, 97, 116, 46, 112, 114, 111, 116, 111, 18,
+        15, 110, 108, 46, 111, 112, 101, 110, 119, 101, 98, 46, 100, 97, 116, 97, 34, 25, 10, 9,
+        72, 101, 97, 114, 116, 98, 101, 97, 116, 18, 12, 10, 4, 98, 101, 97, 116, 24, 1, 32, 1, 40,
+        4, 98, 6, 112, 114, 111, 116, 111, 51,
+    ]
+}
+
 pub fn get_proto_complex_only_data() -> &'static [u8] {
     &get_proto_complex_proto_test_message()[7..]
 }
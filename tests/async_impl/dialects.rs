@@ -0,0 +1,66 @@
+use schema_registry_converter::async_impl::schema_registry::{
+    check_schema_registered, get_schema_by_id, get_subject_versions, list_subjects, post_schema,
+    SrSettings,
+};
+use schema_registry_converter::schema_registry_common::{SchemaType, SrDialect, SuppliedSchema};
+
+const SCHEMA: &str = r#"{"type":"record","name":"Heartbeat","namespace":"nl.openweb.data","fields":[{"name":"beat","type":"long"}]}"#;
+
+async fn register_and_fetch(url: &str, dialect: SrDialect) {
+    let sr_settings = SrSettings::new_builder(String::from(url))
+        .set_dialect(dialect)
+        .build()
+        .unwrap();
+    let supplied_schema = SuppliedSchema {
+        name: Some(String::from("nl.openweb.data.Heartbeat")),
+        schema_type: SchemaType::Avro,
+        schema: String::from(SCHEMA),
+        references: vec![],
+    };
+    let registered = post_schema(
+        &sr_settings,
+        String::from("dialect-heartbeat-value"),
+        supplied_schema,
+    )
+    .await
+    .unwrap();
+
+    let result = get_schema_by_id(registered.id, &sr_settings).await.unwrap();
+    assert_eq!(result.schema_type, SchemaType::Avro);
+    let subjects = list_subjects(&sr_settings, false).await.unwrap();
+    assert!(subjects.contains(&String::from("dialect-heartbeat-value")));
+    let versions = get_subject_versions(&sr_settings, "dialect-heartbeat-value", false)
+        .await
+        .unwrap();
+    assert!(!versions.is_empty());
+    let result = check_schema_registered(
+        &sr_settings,
+        "dialect-heartbeat-value",
+        &SchemaType::Avro,
+        SCHEMA,
+        &[],
+    )
+    .await
+    .unwrap();
+    assert_eq!(result.map(|s| s.id), Some(registered.id));
+    let result = check_schema_registered(
+        &sr_settings,
+        "dialect-unknown-value",
+        &SchemaType::Avro,
+        SCHEMA,
+        &[],
+    )
+    .await
+    .unwrap();
+    assert!(result.is_none());
+}
+
+#[tokio::test]
+async fn test_karapace() {
+    register_and_fetch("http://localhost:8083", SrDialect::Karapace).await
+}
+
+#[tokio::test]
+async fn test_redpanda() {
+    register_and_fetch("http://localhost:18081", SrDialect::Redpanda).await
+}
@@ -0,0 +1,75 @@
+use apache_avro::types::Value;
+use rand::Rng;
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::Consumer;
+use schema_registry_converter::async_impl::avro::{AvroDecoder, AvroEncoder};
+use schema_registry_converter::async_impl::kafka::{SrConsumer, SrProducer};
+use schema_registry_converter::async_impl::schema_registry::SrSettings;
+use schema_registry_converter::schema_registry_common::{
+    SchemaType, SubjectNameStrategy, SuppliedSchema,
+};
+
+fn get_schema_registry_url() -> String {
+    String::from("http://localhost:8081")
+}
+
+fn get_brokers() -> &'static str {
+    "127.0.0.1:9092"
+}
+
+fn get_heartbeat_schema() -> SuppliedSchema {
+    SuppliedSchema {
+        name: Some(String::from("nl.openweb.data.Heartbeat")),
+        schema_type: SchemaType::Avro,
+        schema: String::from(
+            r#"{"type":"record","name":"Heartbeat","namespace":"nl.openweb.data","fields":[{"name":"beat","type":"long"}]}"#,
+        ),
+        references: vec![],
+    }
+}
+
+#[tokio::test]
+async fn test_send_and_receive() {
+    let topic = "kafkahelpers";
+    let producer = ClientConfig::new()
+        .set("bootstrap.servers", get_brokers())
+        .set("message.timeout.ms", "60000")
+        .create()
+        .unwrap();
+    let encoder = AvroEncoder::new(SrSettings::new(get_schema_registry_url()));
+    let producer = SrProducer::new(producer, encoder);
+    let consumer = ClientConfig::new()
+        .set("group.id", "kafkahelpers")
+        .set("bootstrap.servers", get_brokers())
+        .set("enable.partition.eof", "false")
+        .set("auto.offset.reset", "earliest")
+        .create()
+        .unwrap();
+    let decoder = AvroDecoder::new(SrSettings::new(get_schema_registry_url()));
+    let consumer = SrConsumer::new(consumer, decoder);
+    consumer.consumer().subscribe(&[topic]).unwrap();
+
+    let beat = rand::thread_rng().gen::<i64>();
+    let strategy = SubjectNameStrategy::TopicNameStrategyWithSchema(
+        String::from(topic),
+        false,
+        get_heartbeat_schema(),
+    );
+    producer
+        .send_encoded_with_strategy(
+            topic,
+            Some(b"key"),
+            &vec![("beat", Value::Long(beat))],
+            strategy,
+        )
+        .await
+        .unwrap();
+
+    loop {
+        let message = consumer.recv_decoded().await.unwrap();
+        if message.value.value == Value::Record(vec![(String::from("beat"), Value::Long(beat))]) {
+            assert_eq!(message.key, Some(b"key".to_vec()));
+            break;
+        }
+    }
+}
@@ -1 +1,4 @@
+mod dialects;
+#[cfg(all(feature = "avro", feature = "kafka"))]
+mod kafka;
 mod schema_registry_calls;